@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+
+use acorn::{Durability, EncryptionKey, IsolationLevel, OpenReport, PageId};
+
+use crate::Error;
+
+/// Options for opening a [`Database`].
+#[derive(Debug, Default, Clone)]
+pub struct DatabaseBuilder(acorn::DatabaseBuilder);
+
+impl DatabaseBuilder {
+	/// Sets the amount of memory used for caching pages, in bytes. A
+	/// [`ByteSize`](crate::ByteSize) can be used to parse it from a string like
+	/// `"64MiB"`.
+	pub fn page_cache_size(self, size: usize) -> Self {
+		Self(self.0.page_cache_size(size))
+	}
+
+	/// Sets how writes to the segment files are made to survive a power loss.
+	/// By default, they are left to the operating system.
+	pub fn durability(self, durability: Durability) -> Self {
+		Self(self.0.durability(durability))
+	}
+
+	/// Encrypts the files of the database with the key, or opens an
+	/// encrypted database with it.
+	pub fn encryption_key(self, key: Option<EncryptionKey>) -> Self {
+		Self(self.0.encryption_key(key))
+	}
+
+	/// Sets whether the database is opened without writing to its folder.
+	/// Its transactions are read-only.
+	pub fn read_only(self, read_only: bool) -> Self {
+		Self(self.0.read_only(read_only))
+	}
+
+	/// Sets whether [`DatabaseBuilder::open`] creates the database if the
+	/// folder doesn't hold one yet, which it does by default. Otherwise,
+	/// opening fails with [`ErrorKind::NotFound`].
+	///
+	/// [`ErrorKind::NotFound`]: crate::ErrorKind::NotFound
+	pub fn create_if_missing(self, create: bool) -> Self {
+		Self(self.0.create_if_missing(create))
+	}
+
+	/// Limits the total size of the segment files to `max_size` bytes, so that
+	/// the database can't fill up the disk. Once the limit is reached, writing
+	/// to a page of another segment fails with [`ErrorKind::Limit`].
+	///
+	/// [`ErrorKind::Limit`]: crate::ErrorKind::Limit
+	pub fn max_size(self, max_size: Option<u64>) -> Self {
+		Self(self.0.max_size(max_size))
+	}
+
+	/// Sets the number of threads that run the background tasks of the
+	/// database, like writing back pages and taking checkpoints. Every
+	/// database has threads of its own, one per CPU by default.
+	pub fn background_threads(self, num_threads: Option<usize>) -> Self {
+		Self(self.0.background_threads(num_threads))
+	}
+
+	/// Opens the database in the folder at `path`.
+	pub fn open(self, path: impl Into<PathBuf>) -> Result<Database, Error> {
+		Ok(Database(self.0.open(path)?))
+	}
+
+	/// Opens the database in the folder at `path`, and reports what was found
+	/// while opening it.
+	pub fn open_with_report(
+		self,
+		path: impl Into<PathBuf>,
+	) -> Result<(Database, OpenReport), Error> {
+		let (db, report) = self.0.open_with_report(path)?;
+		Ok((Database(db), report))
+	}
+
+	/// Opens a new, empty database that is only kept in memory.
+	pub fn open_in_memory(self) -> Result<Database, Error> {
+		Ok(Database(self.0.open_in_memory()?))
+	}
+}
+
+/// An embedded acorn database, stored in a single folder.
+pub struct Database(acorn::Database);
+
+impl Database {
+	/// The number of bytes of each page that can be read and written.
+	pub const PAGE_SIZE: usize = acorn::Database::PAGE_SIZE;
+
+	pub fn builder() -> DatabaseBuilder {
+		DatabaseBuilder::default()
+	}
+
+	/// Opens the database in the folder at `path` with the default options.
+	/// See [`DatabaseBuilder::open`].
+	pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+		Self::builder().open(path)
+	}
+
+	/// Opens the database in the folder at `path` with the default options,
+	/// and reports what was found while opening it. See
+	/// [`DatabaseBuilder::open_with_report`].
+	pub fn open_with_report(path: impl Into<PathBuf>) -> Result<(Self, OpenReport), Error> {
+		Self::builder().open_with_report(path)
+	}
+
+	/// Opens a new, empty in-memory database with the default options. See
+	/// [`DatabaseBuilder::open_in_memory`].
+	pub fn open_in_memory() -> Result<Self, Error> {
+		Self::builder().open_in_memory()
+	}
+
+	pub fn begin_transaction(&self) -> Result<Transaction, Error> {
+		Ok(Transaction(self.0.begin_transaction()?))
+	}
+
+	/// Begins a transaction with the given isolation level. Transactions of
+	/// a read-only database are read-only.
+	pub fn begin_transaction_with(&self, isolation: IsolationLevel) -> Result<Transaction, Error> {
+		Ok(Transaction(self.0.begin_transaction_with(isolation)?))
+	}
+
+	/// Takes a read-only snapshot of the committed state of the database.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot(self.0.snapshot())
+	}
+
+	/// Reads committed data from a page, outside of any transaction.
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		Ok(self.0.read(page_id, offset, buf)?)
+	}
+
+	/// Writes all modified pages to disk, and waits for that to complete.
+	pub fn flush(&self) -> Result<(), Error> {
+		Ok(self.0.flush()?)
+	}
+
+	/// Writes the pages covered by the WAL back to their segments, so that
+	/// the WAL can be truncated.
+	pub fn checkpoint(&self) -> Result<(), Error> {
+		Ok(self.0.checkpoint()?)
+	}
+
+	/// Checkpoints and closes the database. Dropping the database without
+	/// closing it is safe, but requires recovery the next time it is opened.
+	pub fn close(self) -> Result<(), Error> {
+		Ok(self.0.close()?)
+	}
+}
+
+/// A transaction on a [`Database`]. It is aborted if it is dropped without
+/// being committed.
+pub struct Transaction(acorn::Transaction);
+
+impl Transaction {
+	pub fn id(&self) -> u64 {
+		self.0.id()
+	}
+
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		Ok(self.0.read(page_id, offset, buf)?)
+	}
+
+	pub fn write(&mut self, page_id: PageId, offset: usize, buf: &[u8]) -> Result<(), Error> {
+		Ok(self.0.write(page_id, offset, buf)?)
+	}
+
+	pub fn commit(self) -> Result<(), Error> {
+		Ok(self.0.commit()?)
+	}
+
+	pub fn abort(self) -> Result<(), Error> {
+		Ok(self.0.abort()?)
+	}
+}
+
+/// A read-only snapshot of the committed state of a [`Database`], see
+/// [`Database::snapshot`].
+pub struct Snapshot(acorn::Snapshot);
+
+impl Snapshot {
+	/// The sequence number of the last commit that the snapshot sees.
+	pub fn seq(&self) -> u64 {
+		self.0.seq()
+	}
+
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		Ok(self.0.read(page_id, offset, buf)?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use acorn::ErrorKind;
+
+	use super::*;
+
+	#[test]
+	fn commit_and_read_in_memory() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+		let page_id = PageId::new_unwrap(1, 2);
+		let snapshot = db.snapshot();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id, 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+
+		// then
+		let mut buf = [0; 3];
+		db.read(page_id, 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+		snapshot.read(page_id, 0, &mut buf).unwrap();
+		assert_eq!(buf, [0, 0, 0]);
+		assert!(db.snapshot().seq() > snapshot.seq());
+	}
+
+	#[test]
+	fn classify_write_conflicts() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+		let page_id = PageId::new_unwrap(1, 2);
+		let mut isolated = db
+			.begin_transaction_with(IsolationLevel::SnapshotIsolation)
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id, 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		isolated.write(page_id, 0, &[4, 5, 6]).unwrap();
+		let error = isolated.commit().unwrap_err();
+
+		// then
+		assert_eq!(error.kind(), ErrorKind::Conflict);
+		assert_eq!(error.page_id(), Some(page_id));
+	}
+}
@@ -0,0 +1,39 @@
+use std::{error, fmt};
+
+use acorn::{ErrorKind, PageId};
+
+/// An error of a [`Database`](crate::Database) operation.
+#[derive(Debug)]
+pub struct Error(acorn::Error);
+
+impl Error {
+	/// The class of the error, which tells how to handle it.
+	pub fn kind(&self) -> ErrorKind {
+		self.0.kind()
+	}
+
+	/// The page the error is about, if it is about a single page, e.g. the
+	/// page that was found to be corrupted or that a transaction conflicted
+	/// on.
+	pub fn page_id(&self) -> Option<PageId> {
+		self.0.page_id()
+	}
+}
+
+impl From<acorn::Error> for Error {
+	fn from(error: acorn::Error) -> Self {
+		Self(error)
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.0.fmt(f)
+	}
+}
+
+impl error::Error for Error {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		self.0.source()
+	}
+}
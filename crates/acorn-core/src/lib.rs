@@ -0,0 +1,24 @@
+//! The stable subset of the `acorn` storage engine.
+//!
+//! Everything exported here follows semantic versioning: it only changes
+//! incompatibly in a new major version. The rest of `acorn`, such as the
+//! statistics, the WAL stream and the fault injection and fuzzing entry
+//! points, is experimental and may change in any release. Embedders that only
+//! need to open a database and run transactions on it should depend on this
+//! crate instead.
+//!
+//! [`Database`], [`Transaction`], [`Snapshot`], [`DatabaseBuilder`] and
+//! [`Error`] wrap the types of `acorn` with the same names, and only forward
+//! their stable methods, so that new experimental methods of `acorn` don't
+//! become part of this crate's API. The B-trees of `acorn` are not part of
+//! its public API yet, so they are not exported here either.
+
+mod database;
+mod error;
+
+pub use acorn::{
+	ByteSize, Durability, EncryptionKey, ErrorKind, IsolationLevel, OpenReport, OpenWarning,
+	PageId, SyncPrimitive,
+};
+pub use database::{Database, DatabaseBuilder, Snapshot, Transaction};
+pub use error::Error;
@@ -5,8 +5,25 @@ use crate::utils::units::{GIB, KIB};
 pub(crate) const PAGE_SIZE: usize = 32 * KIB;
 pub(crate) const DEFAULT_MAX_NUM_OPEN_SEGMENTS: usize = 512;
 pub(crate) const DEFAULT_MAX_WAL_GENERATION_SIZE: usize = 4 * GIB;
+pub(crate) const DEFAULT_WAL_SIZE_WARNING_THRESHOLD: usize = 16 * GIB;
 pub(crate) const DEFAULT_PAGE_CACHE_SIZE: usize = 2 * GIB;
 pub(crate) const DEFAULT_MAX_DIRTY_PAGES: f32 = 0.2;
+pub(crate) const DEFAULT_MAX_TRANSACTION_PAGES: f32 = 0.5;
 pub(crate) const DEFAULT_NUM_WORKERS: usize = 2;
 pub(crate) const DEFAULT_CHECKPOINT_PERIOD: Duration = Duration::from_mins(1);
-pub(crate) const DEFAULT_FLUSH_PERIOD: Duration = Duration::from_mins(3);
+pub(crate) const DEFAULT_CHECKPOINT_WAL_SIZE: usize = GIB;
+pub(crate) const DEFAULT_CHECKPOINT_DIRTY_RATIO: f32 = 0.5;
+pub(crate) const DEFAULT_CHECKPOINT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+pub(crate) const DEFAULT_IDLE_MAINTENANCE_AFTER: Duration = Duration::from_secs(30);
+pub(crate) const DEFAULT_IDLE_SCRUB_PAGES: usize = 64;
+pub(crate) const DEFAULT_GROUP_COMMIT_DELAY: Duration = Duration::ZERO;
+pub(crate) const DEFAULT_FLUSH_PERIOD: Duration = Duration::from_secs(5);
+pub(crate) const DEFAULT_MAX_DIRTY_AGE: Duration = Duration::from_secs(30);
+pub(crate) const DEFAULT_COALESCE_WINDOW: Duration = Duration::ZERO;
+pub(crate) const DEFAULT_THROTTLE_MAX_WAIT: Duration = Duration::from_secs(10);
+pub(crate) const THROTTLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+pub(crate) const DEFAULT_IO_RETRY_ATTEMPTS: u32 = 3;
+pub(crate) const DEFAULT_IO_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+pub(crate) const DEFAULT_IO_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(1);
+pub(crate) const SHARED_READ_ATTEMPTS: u32 = 3;
+pub(crate) const SHARED_OPEN_ATTEMPTS: u32 = 3;
@@ -0,0 +1,3969 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	fmt, fs, io, mem,
+	ops::RangeInclusive,
+	path::{Path, PathBuf},
+	sync::{Arc, Weak},
+	time::{Duration, Instant},
+};
+
+use futures::executor::ThreadPool;
+use log::{error, info};
+use parking_lot::Mutex;
+use static_assertions::assert_impl_all;
+use tempfile::TempDir;
+
+#[cfg(feature = "async")]
+use crate::tasks::BlockingPool;
+use crate::{
+	consts::{PAGE_SIZE, SHARED_OPEN_ATTEMPTS},
+	files::{
+		crypto::EncryptionKey,
+		exchange_dirs,
+		overlay::OverlayFolder,
+		read_cipher,
+		segment::{SegmentPageSizes, PAGE_BODY_SIZE},
+		upgrade::FormatUpgrades,
+		vfs::{Vfs, VfsFolder},
+		DatabaseFolder, DatabaseFolderApi, Durability, FileError, FolderLock, FolderLockGuard,
+		PageId, WalIndex,
+	},
+	page_store::{
+		self, CancellationToken, Canonicalize, CheckReport, CheckpointStats, CheckpointTrigger,
+		CustomRecord, InMemoryPageStorage, Listeners, LockGraph, MaintenanceStats, MemoryUsage,
+		OpenReport, OpenWarning, PageStorage, PageStorageApi, PageStorageConfig, ReadPage,
+		ScratchPageStorage, SegmentIoStats, SnapshotApi, Stats, StorageError, TransactionApi,
+		TransactionSize, VfsPageStorage, WalPosition, WalRecord, WalRecordHandler,
+		WalRecordHandlers, WalSubscription, WalTransaction, WritePage,
+	},
+	page_type::{decode_page, encode_page, PageType, PageTypeMismatch},
+	tasks::{BackgroundTasks, TaskMonitor, TaskPanic, Timer, TimerHandle},
+	utils::cache::EvictionPolicy,
+};
+
+/// An error of a [`Database`] operation. Its message tells the
+/// [class](ErrorKind) of the error and the page or segment it is about, and its
+/// [source](std::error::Error::source) is the error that caused it.
+#[derive(Debug)]
+pub struct Error(StorageError);
+
+/// The class of failure that caused an [`Error`], so that applications can
+/// handle errors without matching on each of them. The classes and their
+/// [codes](ErrorKind::code) are stable, but more may be added, and errors may
+/// be classified more precisely in later versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+	/// Stored data is corrupted or inconsistent, like a page that fails its
+	/// checksum, or a file that isn't what it should be.
+	Corruption,
+	/// Reading or writing a file failed in the operating system.
+	Io,
+	/// Another transaction or process got in the way; retrying may succeed.
+	Conflict,
+	/// A configured limit was reached, like the quota or the size of a
+	/// transaction.
+	Limit,
+	/// The transaction stopped waiting, because it reached its deadline or
+	/// was cancelled.
+	Interrupted,
+	/// The options don't fit the database, or each other, like a wrong
+	/// encryption key or an invalid setting.
+	Config,
+	/// The database, or a state of it that was asked for, doesn't exist.
+	NotFound,
+	/// The operation isn't allowed on the database or the transaction, like
+	/// writing in a read-only transaction.
+	Usage,
+	/// The database failed internally, like a background task that panicked.
+	Internal,
+}
+
+impl ErrorKind {
+	/// A short, stable name of the class, e.g. for logs and metrics.
+	pub fn code(self) -> &'static str {
+		match self {
+			Self::Corruption => "corruption",
+			Self::Io => "io",
+			Self::Conflict => "conflict",
+			Self::Limit => "limit",
+			Self::Interrupted => "interrupted",
+			Self::Config => "config",
+			Self::NotFound => "not_found",
+			Self::Usage => "usage",
+			Self::Internal => "internal",
+		}
+	}
+}
+
+impl fmt::Display for ErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.code())
+	}
+}
+
+impl Error {
+	/// The class of failure that caused the error.
+	pub fn kind(&self) -> ErrorKind {
+		match &self.0 {
+			StorageError::ChecksumMismatch(..)
+			| StorageError::MissingSegment { .. }
+			| StorageError::RecoveryVerificationFailed(..)
+			| StorageError::IncompleteWalArchive(..)
+			| StorageError::PageTypeMismatch(..) => ErrorKind::Corruption,
+			StorageError::ReadConflict(..)
+			| StorageError::WriteConflict(..)
+			| StorageError::Deadlock { .. }
+			| StorageError::SegmentInUse(..) => ErrorKind::Conflict,
+			StorageError::TransactionLimitReached
+			| StorageError::QuotaExceeded { .. }
+			| StorageError::Busy(..)
+			| StorageError::CustomRecordTooLarge { .. }
+			| StorageError::TransactionTooLarge { .. } => ErrorKind::Limit,
+			StorageError::TimedOut { .. } | StorageError::Cancelled { .. } => {
+				ErrorKind::Interrupted
+			}
+			StorageError::InvalidConfig(..)
+			| StorageError::UnknownWalRecordType(..)
+			| StorageError::MigrationNeedsRecovery => ErrorKind::Config,
+			StorageError::SnapshotUnavailable(..)
+			| StorageError::WalPositionUnavailable(..)
+			| StorageError::RestorePointNotFound(..)
+			| StorageError::UnknownSavepoint(..) => ErrorKind::NotFound,
+			StorageError::PageOutOfBounds { .. }
+			| StorageError::FrozenSegment(..)
+			| StorageError::NoWal
+			| StorageError::NotAFollower
+			| StorageError::ReadOnlyTransaction => ErrorKind::Usage,
+			StorageError::WalNotInitialized
+			| StorageError::TaskPanicked(..)
+			| StorageError::WalRecordHandler { .. } => ErrorKind::Internal,
+			StorageError::File(err) => match err {
+				FileError::MissingMagic
+				| FileError::Corrupted(..)
+				| FileError::WrongFileType(..)
+				| FileError::UnexpectedEof
+				| FileError::ChecksumMismatch
+				| FileError::UnexpectedFile(..) => ErrorKind::Corruption,
+				FileError::ByteOrderMismatch
+				| FileError::IncompatibleVersion(..)
+				| FileError::UnsupportedFeatures(..)
+				| FileError::IncompatiblePageVersion(..)
+				| FileError::PageSizeMismatch { .. }
+				| FileError::MissingKey
+				| FileError::WrongKey
+				| FileError::NotEncrypted => ErrorKind::Config,
+				FileError::Locked | FileError::Stale => ErrorKind::Conflict,
+				FileError::NoDatabase(..) => ErrorKind::NotFound,
+				FileError::Io(err) if is_out_of_space(err) => ErrorKind::Limit,
+				FileError::Io(..) => ErrorKind::Io,
+			},
+		}
+	}
+
+	/// The page the error is about, if it is about a single one, like the
+	/// page that failed its checksum verification, or that another
+	/// transaction modified if a transaction could not commit because of a
+	/// read or write conflict.
+	pub fn page_id(&self) -> Option<PageId> {
+		match &self.0 {
+			StorageError::ChecksumMismatch(page_id)
+			| StorageError::ReadConflict(page_id)
+			| StorageError::WriteConflict(page_id)
+			| StorageError::Deadlock { page_id, .. }
+			| StorageError::TimedOut { page_id, .. }
+			| StorageError::Cancelled { page_id, .. } => Some(*page_id),
+			StorageError::PageTypeMismatch(mismatch) => Some(mismatch.page_id),
+			_ => None,
+		}
+	}
+
+	/// The segment the error is about, if it is about a single one. This
+	/// includes the segment of [`Self::page_id`].
+	pub fn segment_num(&self) -> Option<u32> {
+		match &self.0 {
+			StorageError::FrozenSegment(segment_num)
+			| StorageError::SegmentInUse(segment_num)
+			| StorageError::QuotaExceeded { segment_num, .. }
+			| StorageError::MissingSegment { segment_num, .. } => Some(*segment_num),
+			_ => self.page_id().map(|page_id| page_id.segment_num),
+		}
+	}
+
+	/// Whether the transaction was chosen to resolve a deadlock with other
+	/// transactions. It has to be aborted, after which it can be retried.
+	pub fn is_deadlock(&self) -> bool {
+		matches!(self.0, StorageError::Deadlock { .. })
+	}
+
+	/// Whether a transaction that validates its reads could not commit,
+	/// because another transaction modified a page it read. It has been
+	/// aborted, and can be retried.
+	pub fn is_read_conflict(&self) -> bool {
+		matches!(self.0, StorageError::ReadConflict(..))
+	}
+
+	/// Whether a transaction with [`IsolationLevel::SnapshotIsolation`] could
+	/// not commit, because another transaction committed to a page it
+	/// modified after its snapshot was taken. It has been aborted, and can be
+	/// retried.
+	pub fn is_write_conflict(&self) -> bool {
+		matches!(self.0, StorageError::WriteConflict(..))
+	}
+
+	/// Whether the transaction tried to modify a page of a segment that would
+	/// make the database exceed its quota, see [`DatabaseBuilder::max_size`]
+	/// and [`DatabaseBuilder::max_segments`].
+	pub fn is_quota_exceeded(&self) -> bool {
+		matches!(self.0, StorageError::QuotaExceeded { .. })
+	}
+
+	/// Whether the transaction tried to lock another page while it already
+	/// holds as much as it may, see [`DatabaseBuilder::max_transaction_size`].
+	/// The transaction can still be committed or aborted.
+	pub fn is_transaction_too_large(&self) -> bool {
+		matches!(self.0, StorageError::TransactionTooLarge { .. })
+	}
+
+	/// Whether the transaction tried to modify a page while the WAL or the
+	/// dirty pages were too far behind the writes, and they didn't catch up
+	/// in time, see [`DatabaseBuilder::throttle_wal_size`]. It can be retried
+	/// later.
+	pub fn is_busy(&self) -> bool {
+		matches!(self.0, StorageError::Busy(..))
+	}
+
+	/// Whether the transaction tried to modify a page of a segment that was
+	/// frozen with [`Database::freeze_segment`].
+	pub fn is_frozen_segment(&self) -> bool {
+		matches!(self.0, StorageError::FrozenSegment(..))
+	}
+
+	/// Whether the transaction reached its deadline while waiting for a page,
+	/// see [`Transaction::with_deadline`].
+	pub fn is_timed_out(&self) -> bool {
+		matches!(self.0, StorageError::TimedOut { .. })
+	}
+
+	/// Whether the transaction was cancelled while waiting for a page, see
+	/// [`Transaction::with_cancellation`].
+	pub fn is_cancelled(&self) -> bool {
+		matches!(self.0, StorageError::Cancelled { .. })
+	}
+
+	/// The locks held and waited for by all transactions when a deadlock was
+	/// detected, if the error was caused by one.
+	pub fn lock_graph(&self) -> Option<&LockGraph> {
+		match &self.0 {
+			StorageError::Deadlock { lock_graph, .. } => Some(lock_graph),
+			_ => None,
+		}
+	}
+
+	/// The pages that were found corrupted after recovering the database, if
+	/// it failed to open because of them, see
+	/// [`DatabaseBuilder::verify_after_recovery`].
+	pub fn recovery_problems(&self) -> Option<&CheckReport> {
+		match &self.0 {
+			StorageError::RecoveryVerificationFailed(report) => Some(report),
+			_ => None,
+		}
+	}
+
+	/// Whether the database is encrypted, and was opened without a key or with
+	/// a different key than it was created with.
+	pub fn is_wrong_key(&self) -> bool {
+		matches!(
+			self.0,
+			StorageError::File(FileError::MissingKey | FileError::WrongKey)
+		)
+	}
+
+	/// Whether the database couldn't be opened, because another process has
+	/// it open and locked, see [`DatabaseBuilder::lock`].
+	pub fn is_locked(&self) -> bool {
+		matches!(self.0, StorageError::File(FileError::Locked))
+	}
+
+	/// Whether a read-only database read a page that the process writing to
+	/// it wrote back since it was opened, so that it has to be refreshed, see
+	/// [`Database::refresh`].
+	pub fn is_stale(&self) -> bool {
+		matches!(self.0, StorageError::File(FileError::Stale))
+	}
+
+	/// Whether the database couldn't be opened, because it doesn't exist and
+	/// wasn't supposed to be created, see
+	/// [`DatabaseBuilder::create_if_missing`].
+	pub fn is_not_found(&self) -> bool {
+		matches!(self.0, StorageError::File(FileError::NoDatabase(..)))
+	}
+
+	/// The page and the tags involved, if a page was read as a [`PageType`]
+	/// that it wasn't written as.
+	pub fn page_type_mismatch(&self) -> Option<&PageTypeMismatch> {
+		match &self.0 {
+			StorageError::PageTypeMismatch(mismatch) => Some(mismatch),
+			_ => None,
+		}
+	}
+
+	/// The panic of a background task that made the database stop accepting
+	/// transactions, if the error was caused by one.
+	pub fn task_panic(&self) -> Option<&TaskPanic> {
+		match &self.0 {
+			StorageError::TaskPanicked(panic) => Some(panic),
+			_ => None,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Database error of class {}", self.kind())?;
+		match (self.page_id(), self.segment_num()) {
+			(Some(page_id), _) => write!(f, " on page {page_id}"),
+			(None, Some(segment_num)) => write!(f, " in segment {segment_num}"),
+			(None, None) => Ok(()),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		Some(&self.0)
+	}
+}
+
+impl From<StorageError> for Error {
+	fn from(value: StorageError) -> Self {
+		Self(value)
+	}
+}
+
+impl From<FileError> for Error {
+	fn from(value: FileError) -> Self {
+		Self(value.into())
+	}
+}
+
+/// Whether a file couldn't be written because the disk or the quota of the
+/// user is full, which is a limit rather than a failure of the disk.
+#[cfg(unix)]
+fn is_out_of_space(err: &io::Error) -> bool {
+	matches!(err.raw_os_error(), Some(libc::ENOSPC | libc::EDQUOT))
+}
+
+#[cfg(not(unix))]
+fn is_out_of_space(_err: &io::Error) -> bool {
+	false
+}
+
+enum Storage {
+	Durable(Arc<PageStorage>),
+	Scratch {
+		storage: Arc<ScratchPageStorage>,
+		_dir: TempDir,
+	},
+	InMemory {
+		storage: Arc<InMemoryPageStorage>,
+		folder: Arc<OverlayFolder>,
+		/// The options the database was opened with, to open it again after a
+		/// simulated crash.
+		builder: Box<DatabaseBuilder>,
+	},
+	Vfs(Arc<VfsPageStorage>),
+}
+
+impl Storage {
+	fn checkpoint(&self) -> Result<(), StorageError> {
+		match self {
+			Self::Durable(storage) => storage.checkpoint(),
+			Self::Scratch { .. } => Ok(()),
+			Self::InMemory { storage, .. } => storage.checkpoint(),
+			Self::Vfs(storage) => storage.checkpoint(),
+		}
+	}
+
+	fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
+		match self {
+			Self::Durable(storage) => storage.get_page(page_id)?.read(offset, buf),
+			Self::Scratch { storage, .. } => storage.get_page(page_id)?.read(offset, buf),
+			Self::InMemory { storage, .. } => storage.get_page(page_id)?.read(offset, buf),
+			Self::Vfs(storage) => storage.get_page(page_id)?.read(offset, buf),
+		}
+	}
+
+	fn flush(&self) -> Result<(), StorageError> {
+		match self {
+			Self::Durable(storage) => storage.flush_sync(),
+			Self::Scratch { storage, .. } => storage.flush_sync(),
+			Self::InMemory { storage, .. } => storage.flush_sync(),
+			Self::Vfs(storage) => storage.flush_sync(),
+		}
+	}
+
+	fn auto_checkpoint(&self) -> Result<Option<CheckpointTrigger>, StorageError> {
+		match self {
+			Self::Durable(storage) => storage.auto_checkpoint(),
+			Self::Scratch { .. } => Ok(None),
+			Self::InMemory { storage, .. } => storage.auto_checkpoint(),
+			Self::Vfs(storage) => storage.auto_checkpoint(),
+		}
+	}
+
+	fn idle_maintenance(&self) -> Result<bool, StorageError> {
+		match self {
+			Self::Durable(storage) => storage.idle_maintenance(),
+			Self::Scratch { storage, .. } => storage.idle_maintenance(),
+			Self::InMemory { storage, .. } => storage.idle_maintenance(),
+			Self::Vfs(storage) => storage.idle_maintenance(),
+		}
+	}
+
+	fn listeners(&self) -> &Listeners {
+		match self {
+			Self::Durable(storage) => storage.listeners(),
+			Self::Scratch { storage, .. } => storage.listeners(),
+			Self::InMemory { storage, .. } => storage.listeners(),
+			Self::Vfs(storage) => storage.listeners(),
+		}
+	}
+
+	fn task_panic(&self) -> Option<TaskPanic> {
+		match self {
+			Self::Durable(storage) => storage.task_panic(),
+			Self::Scratch { storage, .. } => storage.task_panic(),
+			Self::InMemory { storage, .. } => storage.task_panic(),
+			Self::Vfs(storage) => storage.task_panic(),
+		}
+	}
+
+	#[cfg(feature = "async")]
+	fn blocking_pool(&self) -> Arc<BlockingPool> {
+		match self {
+			Self::Durable(storage) => Arc::clone(storage.blocking_pool()),
+			Self::Scratch { storage, .. } => Arc::clone(storage.blocking_pool()),
+			Self::InMemory { storage, .. } => Arc::clone(storage.blocking_pool()),
+			Self::Vfs(storage) => Arc::clone(storage.blocking_pool()),
+		}
+	}
+
+	async fn periodic_checkpoint_task(mut timer: Timer, storage: Weak<Self>) {
+		while timer.wait() {
+			let Some(storage) = storage.upgrade() else {
+				break;
+			};
+			if let Err(err) = storage.auto_checkpoint() {
+				error!("A checkpoint failed: {err}");
+			}
+			if let Err(err) = storage.idle_maintenance() {
+				error!("Idle maintenance failed: {err}");
+			}
+		}
+	}
+}
+
+/// How strictly a transaction is isolated from the transactions running
+/// concurrently with it. Stricter levels make more transactions fail to
+/// commit, so that they have to be retried.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+	/// Reads see the latest committed state of each page, waiting for
+	/// transactions that are writing to it.
+	#[default]
+	ReadCommitted,
+
+	/// Like [`IsolationLevel::ReadCommitted`], but once a page was read, it
+	/// reads the same until the transaction modifies it, even if other
+	/// transactions commit to it in the meantime. Different pages may still
+	/// reflect different commits, since each is kept as of when it was first
+	/// read. Every page that was read is kept in memory until the transaction
+	/// completes.
+	RepeatableRead,
+
+	/// Reads see the state as of the last commit before the transaction
+	/// began, without waiting for other transactions. Committing fails if
+	/// another transaction committed to a page the transaction modified in
+	/// the meantime, see [`Error::is_write_conflict`].
+	SnapshotIsolation,
+
+	/// Committing fails if another transaction modified any of the pages the
+	/// transaction read in the meantime, see [`Error::is_read_conflict`].
+	Serializable,
+}
+
+/// Options for opening a [`Database`].
+#[derive(Debug, Default, Clone)]
+pub struct DatabaseBuilder {
+	config: PageStorageConfig,
+	encryption_key: Option<EncryptionKey>,
+	follower: bool,
+	canonicalize: Option<Canonicalize>,
+	read_only: bool,
+	must_exist: bool,
+	lock: bool,
+	allow_readers: bool,
+	background_threads: Option<usize>,
+}
+
+impl DatabaseBuilder {
+	/// Sets the amount of memory used for caching pages, in bytes. A
+	/// [`ByteSize`](crate::ByteSize) can be used to parse it from a string like
+	/// `"64MiB"`.
+	pub fn page_cache_size(mut self, size: usize) -> Self {
+		self.config.page_cache.page_cache_size = size;
+		self
+	}
+
+	/// Sets the fraction of the page cache that may be dirty before the
+	/// oldest dirty pages are written back in the background.
+	pub fn max_dirty_ratio(mut self, ratio: f32) -> Self {
+		self.config.page_cache.max_dirty_pages = ratio;
+		self
+	}
+
+	/// Sets the fraction of the page cache that a single transaction may keep
+	/// locked. Beyond that, the pages it modified are spilled to a temporary
+	/// file, and given back to the cache with their original content until
+	/// the transaction commits, so that a large transaction can't take up the
+	/// whole cache. Half of the cache by default.
+	pub fn max_transaction_pages(mut self, ratio: f32) -> Self {
+		self.config.page_cache.max_transaction_pages = ratio;
+		self
+	}
+
+	/// Sets the number of bytes that a single transaction may hold in the
+	/// page cache, in its write set and spilled to disk together, see
+	/// [`Transaction::size`]. Locking another page beyond that fails, see
+	/// [`Error::is_transaction_too_large`]. Not limited by default.
+	pub fn max_transaction_size(mut self, max_size: Option<usize>) -> Self {
+		self.config.page_cache.max_transaction_size = max_size;
+		self
+	}
+
+	/// Sets the time after which a dirty page is written back in the
+	/// background.
+	pub fn max_dirty_age(mut self, age: Duration) -> Self {
+		self.config.page_cache.max_dirty_age = age;
+		self
+	}
+
+	/// Sets the time for which modified pages are not written back in the
+	/// background after they were last modified, unless the page cache has
+	/// too many dirty pages. This coalesces the writes to pages that are
+	/// modified frequently, at the cost of keeping them dirty for longer.
+	pub fn coalesce_window(mut self, window: Duration) -> Self {
+		self.config.page_cache.coalesce_window = window;
+		self
+	}
+
+	/// Sets the policy by which pages are evicted from the page cache.
+	pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+		self.config.page_cache.eviction_policy = policy;
+		self
+	}
+
+	/// Sets how many evicted pages the [`EvictionPolicy::Adaptive`] policy
+	/// remembers, as a fraction of the number of pages the page cache holds.
+	/// Pages that are loaded again while they are remembered are considered
+	/// frequently used; a larger history detects reuse over longer distances,
+	/// at the cost of memory. Defaults to 1.
+	pub fn eviction_history_ratio(mut self, ratio: f32) -> Self {
+		self.config.page_cache.eviction_tuning.history_ratio = ratio;
+		self
+	}
+
+	/// Sets how often a newly cached page has to be accessed before the
+	/// [`EvictionPolicy::Adaptive`] policy considers it frequently used,
+	/// instead of evicting it. Higher thresholds protect the frequently used
+	/// pages better against scans. Defaults to 1.
+	pub fn eviction_promotion_threshold(mut self, threshold: u32) -> Self {
+		self.config.page_cache.eviction_tuning.promotion_threshold = threshold;
+		self
+	}
+
+	/// Sets the maximum total size in bytes of the page versions that are
+	/// retained after they were replaced, so that [`Database::read_at`] can
+	/// read the state as of past commits. No versions are retained by default.
+	pub fn version_retention_size(mut self, size: usize) -> Self {
+		self.config.version_retention.max_size = size;
+		self
+	}
+
+	/// Sets the time after which a replaced page version is no longer
+	/// retained, or removes this limit if `None`.
+	pub fn version_retention_age(mut self, age: Option<Duration>) -> Self {
+		self.config.version_retention.max_age = age;
+		self
+	}
+
+	/// Sets how long a commit waits for concurrent commits before syncing the
+	/// WAL, so that they can share a single sync. Longer delays increase the
+	/// throughput of concurrent commits, at the cost of commit latency.
+	pub fn group_commit_delay(mut self, delay: Duration) -> Self {
+		self.config.wal.group_commit_delay = delay;
+		self
+	}
+
+	/// Sets the WAL size in bytes at which a checkpoint is taken
+	/// automatically, or disables this trigger if `None`.
+	pub fn checkpoint_wal_size(mut self, size: Option<usize>) -> Self {
+		self.config.checkpoint.wal_size = size;
+		self
+	}
+
+	/// Sets the fraction of the page cache that may be dirty before a
+	/// checkpoint is taken automatically, or disables this trigger if `None`.
+	pub fn checkpoint_dirty_ratio(mut self, ratio: Option<f32>) -> Self {
+		self.config.checkpoint.dirty_ratio = ratio;
+		self
+	}
+
+	/// Sets the time after which a checkpoint is taken automatically, or
+	/// disables this trigger if `None`.
+	pub fn checkpoint_period(mut self, period: Option<Duration>) -> Self {
+		self.config.checkpoint.period = period;
+		self
+	}
+
+	/// Sets how long no transaction must have run before maintenance is done
+	/// in the background: a checkpoint if one is needed, verifying the
+	/// checksums of a few stored pages, and deleting WAL files that are no
+	/// longer needed. It is repeated after each further period of idleness.
+	/// Defaults to 30 seconds; `None` disables idle maintenance. See
+	/// [`Database::maintenance_stats`].
+	pub fn idle_maintenance(mut self, after: Option<Duration>) -> Self {
+		self.config.idle_maintenance.idle_after = after;
+		self
+	}
+
+	/// Sets how many stored pages each run of idle maintenance reads back to
+	/// verify their checksums. Runs continue where the previous one stopped,
+	/// so that all pages are verified eventually. Defaults to 64.
+	pub fn idle_scrub_pages(mut self, num_pages: usize) -> Self {
+		self.config.idle_maintenance.scrub_pages = num_pages;
+		self
+	}
+
+	/// Creates the segments in the range `segments` with pages of `page_size`
+	/// bytes on disk instead of 32 KiB, so that segments of small pages don't
+	/// take up as much disk space and I/O. `page_size` must be a power of two
+	/// of at least 4 KiB. Pages of these segments can only hold
+	/// [`Database::page_size`] bytes, and writing anything but zeroes past that
+	/// fails; they are still cached in full.
+	///
+	/// Each segment stores its page size, and a database has to be opened
+	/// with the page sizes its segments were created with. If ranges overlap,
+	/// the one that was set last applies.
+	pub fn segment_page_size(mut self, segments: RangeInclusive<u32>, page_size: usize) -> Self {
+		self.config.segment_page_sizes.set(segments, page_size);
+		self
+	}
+
+	/// Makes the segments in the range `segments` unlogged: writes to their
+	/// pages are visible once their transaction commits, but they are not
+	/// written to the WAL, so they cost no more than writing back the pages.
+	/// Instead, the segments are cleared every time the database is opened,
+	/// whether it was closed properly or not. This suits data that can be
+	/// rebuilt, like temporary indexes and spill areas of bulk operations.
+	///
+	/// Transactions that write to both unlogged and other segments are only
+	/// atomic for the other segments.
+	pub fn unlogged_segments(mut self, segments: RangeInclusive<u32>) -> Self {
+		self.config.unlogged_segments.add(segments);
+		self
+	}
+
+	/// Sets whether opening the database recreates segment files that are
+	/// missing, even though the WAL has writes to them. Only the pages in the
+	/// WAL can be restored then, so by default, opening fails instead.
+	pub fn recreate_missing_segments(mut self, recreate: bool) -> Self {
+		self.config.recreate_missing_segments = recreate;
+		self
+	}
+
+	/// Sets whether opening the database salvages a WAL that has a corrupted
+	/// item. The WAL is cut off at that item then, and the transactions that
+	/// committed before it are recovered, while those that committed after it
+	/// are lost; pages that were written back to the segments after it may
+	/// still hold their changes. By default, opening fails instead.
+	pub fn salvage_wal(mut self, salvage: bool) -> Self {
+		self.config.wal.salvage = salvage;
+		self
+	}
+
+	/// Sets whether recovering the database when opening it reads back the
+	/// pages it restored from the WAL, and fails if any of them fails its
+	/// checksum, before the database accepts transactions. Unlike
+	/// [`Database::check`], only the recovered pages are read.
+	pub fn verify_after_recovery(mut self, verify: bool) -> Self {
+		self.config.verify_after_recovery = verify;
+		self
+	}
+
+	/// Limits the number of bytes per second that are written back to disk
+	/// in the background, so that background writes don't slow down
+	/// transactions. There is no limit by default. The limit can be changed
+	/// later with [`Database::set_background_io_rate`].
+	pub fn background_io_rate(mut self, rate: Option<usize>) -> Self {
+		self.config.page_cache.background_io_rate = rate;
+		self
+	}
+
+	/// Sets how many segment files are kept open at most, so that databases
+	/// with many segments don't run out of file descriptors. Files that were
+	/// used least recently are closed first, and opened again on demand.
+	/// Defaults to 512.
+	pub fn max_open_segments(mut self, max: usize) -> Self {
+		self.config.physical_storage.max_num_open_segments = max;
+		self
+	}
+
+	/// Limits the total size of the segment files to `max_size` bytes, so that
+	/// the database can't fill up the disk. Every segment counts with its full
+	/// size of 65536 pages, and the WAL isn't counted. Once the limit is
+	/// reached, writing to a page of another segment fails, see
+	/// [`Error::is_quota_exceeded`], while pages of the segments in use can
+	/// still be written to.
+	pub fn max_size(mut self, max_size: Option<u64>) -> Self {
+		self.config.quota.max_size = max_size;
+		self
+	}
+
+	/// Limits the number of segments that have pages, like
+	/// [`Self::max_size`].
+	pub fn max_segments(mut self, max_segments: Option<u32>) -> Self {
+		self.config.quota.max_segments = max_segments;
+		self
+	}
+
+	/// Sets the WAL size in bytes at which writes are throttled, or disables
+	/// this limit if `None`, which it is by default. A transaction that
+	/// starts writing while the WAL is this large waits until a checkpoint
+	/// has shrunk it, so that writing faster than checkpoints can keep up
+	/// doesn't grow the WAL without bound. See [`Self::throttle_max_wait`].
+	///
+	/// Transactions are only throttled before they modify their first page,
+	/// since checkpoints would otherwise wait for the pages they hold.
+	pub fn throttle_wal_size(mut self, size: Option<usize>) -> Self {
+		self.config.write_throttle.wal_size = size;
+		self
+	}
+
+	/// Sets the fraction of the page cache that may be dirty before writes
+	/// are throttled, like [`Self::throttle_wal_size`], or disables this limit
+	/// if `None`. Throttled transactions wait for the dirty pages to be
+	/// written back.
+	pub fn throttle_dirty_ratio(mut self, ratio: Option<f32>) -> Self {
+		self.config.write_throttle.dirty_ratio = ratio;
+		self
+	}
+
+	/// Sets how long a throttled transaction waits for the database to catch
+	/// up, before its write fails with [`Error::is_busy`]. With a duration of
+	/// zero, throttled writes fail right away. Defaults to 10 seconds.
+	pub fn throttle_max_wait(mut self, max_wait: Duration) -> Self {
+		self.config.write_throttle.max_wait = max_wait;
+		self
+	}
+
+	/// Sets how writes to the segment files are made to survive a power loss.
+	/// By default, they are left to the operating system.
+	pub fn durability(mut self, durability: Durability) -> Self {
+		self.config.physical_storage.durability = durability;
+		self
+	}
+
+	/// Sets a folder that the WAL files are stored in, instead of the `wal`
+	/// folder inside the folder of the database, e.g. to put the WAL on a
+	/// faster device. The database has to be opened with the same WAL folder
+	/// every time, or the transactions that were committed since the last
+	/// checkpoint are lost.
+	pub fn wal_path(mut self, path: Option<PathBuf>) -> Self {
+		self.config.wal.path = path;
+		self
+	}
+
+	/// Sets the size in bytes from which on the WAL continues in a new file.
+	/// A checkpoint is taken whenever a file is full, and checkpoints only
+	/// delete whole files, so smaller files keep the WAL smaller on disk, at
+	/// the cost of more frequent checkpoints. Defaults to 4 GiB.
+	pub fn wal_file_size(mut self, size: usize) -> Self {
+		self.config.wal.max_generation_size = size;
+		self
+	}
+
+	/// Sets a folder that WAL files are moved to once a checkpoint no longer
+	/// needs them, instead of deleting them. If archiving is enabled when the
+	/// database is created, the archive can be used to restore any committed
+	/// state with [`Database::restore_to`].
+	pub fn wal_archive(mut self, path: Option<PathBuf>) -> Self {
+		self.config.wal.archive = path;
+		self
+	}
+
+	/// Sets whether large page writes are compressed with zstd before they
+	/// are written to the WAL, which makes it considerably smaller during
+	/// bulk loads. Only WAL files created after this is enabled are
+	/// compressed; existing ones are read either way. Disabled by default.
+	pub fn wal_compression(mut self, compress: bool) -> Self {
+		self.config.wal.compress = compress;
+		self
+	}
+
+	/// Encrypts the pages and the WAL of the database with `key`. A database
+	/// that was created with a key can only be opened with the same key, and
+	/// a database created without one can't be encrypted later. Backups of
+	/// the database are encrypted with the same key.
+	pub fn encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+		self.encryption_key = key;
+		self
+	}
+
+	/// Sets a function that rewrites each page before it is copied into a
+	/// backup, see [`Database::backup_to`]. It is meant to zero the parts of
+	/// pages that their format leaves unused, which may still hold stale
+	/// bytes, so that backups of databases with the same logical content
+	/// consist of the same bytes, and deduplicate and hash the same. The
+	/// pages of the database itself are not changed.
+	pub fn backup_canonicalizer(mut self, canonicalize: Option<fn(PageId, &mut [u8])>) -> Self {
+		self.canonicalize = canonicalize;
+		self
+	}
+
+	/// Registers the handler of the custom WAL records of `record_type`, see
+	/// [`Transaction::log_record`]. Recovery fails if the WAL contains records
+	/// of a type that has no handler, so the handlers have to be registered
+	/// for as long as their records may be in the WAL.
+	pub fn wal_record_handler(
+		mut self,
+		record_type: u16,
+		handler: Arc<dyn WalRecordHandler>,
+	) -> Self {
+		self.config
+			.wal
+			.record_handlers
+			.register(record_type, handler);
+		self
+	}
+
+	/// Sets whether the database is opened without writing to its folder.
+	/// Its transactions are read-only, and if it was not closed properly, it
+	/// is recovered in memory, leaving the WAL and the segment files as they
+	/// are. The database has to exist already, and can't be encrypted.
+	pub fn read_only(mut self, read_only: bool) -> Self {
+		self.read_only = read_only;
+		self
+	}
+
+	/// Sets whether [`DatabaseBuilder::open`] creates the database if the
+	/// folder doesn't hold one yet, which it does by default. Otherwise,
+	/// opening fails, see [`Error::is_not_found`].
+	pub fn create_if_missing(mut self, create: bool) -> Self {
+		self.must_exist = !create;
+		self
+	}
+
+	/// Sets whether the folder of the database is locked while it is open,
+	/// so that other processes that lock it as well can't open it at the same
+	/// time, see [`Error::is_locked`]. Databases opened with
+	/// [`DatabaseBuilder::read_only`] share their lock with each other, but
+	/// not with a process that opens the database for writing. The lock is
+	/// released once the database and all of its transactions are dropped.
+	/// Disabled by default.
+	pub fn lock(mut self, lock: bool) -> Self {
+		self.lock = lock;
+		self
+	}
+
+	/// Sets whether processes that open the database with
+	/// [`DatabaseBuilder::read_only`] can lock it while this process has it
+	/// open for writing with [`DatabaseBuilder::lock`]. Only a single process
+	/// can write to the database either way. Readers see the database as of
+	/// when they opened it; once the writer wrote back pages they didn't read
+	/// yet, reading those fails, see [`Error::is_stale`], and they have to be
+	/// refreshed, see [`Database::refresh`]. Disabled by default.
+	pub fn allow_readers(mut self, allow: bool) -> Self {
+		self.allow_readers = allow;
+		self
+	}
+
+	/// Sets the number of threads that run the background tasks of the
+	/// database, like writing back pages and taking checkpoints. Every
+	/// database has threads of its own, one per CPU by default, so processes
+	/// that open many databases at once may want to keep this low. The
+	/// database has as many threads again for blocking work like prefetching
+	/// pages and the async variants of its operations, which are only started
+	/// once they are needed.
+	pub fn background_threads(mut self, num_threads: Option<usize>) -> Self {
+		self.background_threads = num_threads;
+		self.config.page_cache.blocking_threads = num_threads;
+		self
+	}
+
+	/// Opens the database in the folder at `path`, creating it if it doesn't
+	/// exist yet. If the database was not closed properly, it is recovered
+	/// before this returns. Problems that don't keep the database from being
+	/// opened are logged.
+	pub fn open(self, path: impl Into<PathBuf>) -> Result<Database, Error> {
+		let (database, report) = self.open_with_report(path)?;
+		report.log();
+		Ok(database)
+	}
+
+	/// Opens the database like [`open`](Self::open), but returns what was
+	/// found while opening it, like problems that were worked around, instead
+	/// of logging it.
+	pub fn open_with_report(
+		self,
+		path: impl Into<PathBuf>,
+	) -> Result<(Database, OpenReport), Error> {
+		self.config.validate()?;
+		if self.read_only {
+			return self.open_read_only(path.into());
+		}
+		let path = path.into();
+		if self.must_exist && !self.open_folder(path.clone())?.has_database()? {
+			return Err(FileError::NoDatabase(path).into());
+		}
+		let lock = self.lock_folder(&path)?;
+		let folder = self.folder(path)?;
+		Self::upgrade_format(&folder)?;
+		let thread_pool = Self::thread_pool(self.background_threads)?;
+
+		let closed_cleanly = folder.was_closed_cleanly();
+		let (storage, mut report) = PageStorage::open_or_create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&self.config,
+		)?;
+		if !closed_cleanly {
+			report.warnings.push(OpenWarning::UncleanShutdown);
+		}
+		folder.mark_open()?;
+		let database = self
+			.start(Storage::Durable(storage), &thread_pool)
+			.with_lock(lock)
+			.with_open_marker(folder);
+		Ok((database, report))
+	}
+
+	/// Creates a database in the folder at `path` with the state right after
+	/// the transaction with the given ID committed, by replaying the WAL files
+	/// in the `archive` folder. The archive must contain all WAL files since
+	/// the database was created, and the folder at `path` must not contain a
+	/// database yet.
+	pub fn restore_to(
+		self,
+		path: impl Into<PathBuf>,
+		archive: impl Into<PathBuf>,
+		transaction_id: u64,
+	) -> Result<Database, Error> {
+		self.config.validate()?;
+		let archive = archive.into();
+		if self.config.wal.archive.as_ref() == Some(&archive) {
+			return Err(StorageError::InvalidConfig(
+				"A restored database can't archive its WAL to the archive it is restored from"
+					.to_string(),
+			)
+			.into());
+		}
+		let archive_cipher = read_cipher(&archive, self.encryption_key.as_ref())?;
+		let path = path.into();
+		let lock = self.lock_folder(&path)?;
+		let folder = self.folder(path)?;
+		ensure_no_database(&folder)?;
+		let thread_pool = Self::thread_pool(self.background_threads)?;
+
+		let storage =
+			PageStorage::create(Arc::clone(&folder), Arc::clone(&thread_pool), &self.config)?;
+		page_store::restore(&storage, &archive, transaction_id, archive_cipher.as_ref())?;
+		storage.checkpoint()?;
+		folder.mark_open()?;
+		Ok(self
+			.start(Storage::Durable(storage), &thread_pool)
+			.with_lock(lock)
+			.with_open_marker(folder))
+	}
+
+	/// Opens the database in the folder at `path` like [`open`](Self::open),
+	/// but first migrates it if it was created with a smaller page size than
+	/// the one of this build of acorn.
+	///
+	/// The pages are copied into a new database next to `path`, which then
+	/// replaces the old one; pages keep their IDs, and grow by zeroes at their
+	/// end. This requires as much free disk space as the database takes up.
+	/// The WAL isn't migrated, so the database must have been closed cleanly by
+	/// the version of acorn that created it. Databases with larger pages can't
+	/// be migrated, since their pages wouldn't fit.
+	pub fn migrate_page_size(self, path: impl Into<PathBuf>) -> Result<Database, Error> {
+		self.config.validate()?;
+		if self.config.wal.archive.is_some() || self.config.wal.path.is_some() {
+			return Err(StorageError::InvalidConfig(
+				"The WAL of a database can't be archived or stored elsewhere while its page size is migrated"
+					.to_string(),
+			)
+			.into());
+		}
+		let path = path.into();
+		let mut target_path = path.clone().into_os_string();
+		target_path.push(".migrating");
+		let target_path = PathBuf::from(target_path);
+		// Left over by a migration that was interrupted
+		if target_path.exists() {
+			fs::remove_dir_all(&target_path).map_err(FileError::from)?;
+		}
+
+		let lock = self.lock_folder(&path)?;
+		let folder = self.folder(path.clone())?;
+		Self::upgrade_format(&folder)?;
+		if !folder.needs_page_size_migration()? {
+			mem::drop(lock);
+			return self.open(path);
+		}
+		let thread_pool = Self::thread_pool(self.background_threads)?;
+		let target_folder = self.folder(target_path.clone())?;
+		let target = PageStorage::create(target_folder, Arc::clone(&thread_pool), &self.config)?;
+		let num_pages = page_store::migrate_pages(folder, thread_pool, &self.config.wal, &target)?;
+		target.checkpoint()?;
+		drop(target);
+
+		exchange_dirs(&path, &target_path)?;
+		fs::remove_dir_all(&target_path).map_err(FileError::from)?;
+		info!("Migrated {num_pages} pages to a page size of {PAGE_SIZE} bytes");
+		mem::drop(lock);
+		self.open(path)
+	}
+
+	fn open_read_only(self, path: PathBuf) -> Result<(Database, OpenReport), Error> {
+		if self.encryption_key.is_some() {
+			return Err(StorageError::InvalidConfig(
+				"Encrypted databases can't be opened read-only".to_string(),
+			)
+			.into());
+		}
+		let folder = self.open_folder(path.clone())?;
+		if !folder.has_database()? {
+			return Err(FileError::NoDatabase(path).into());
+		}
+		let lock = self.lock_folder(&path)?;
+		// A writer sharing the folder may write back pages while the WAL is
+		// recovered, until one attempt catches it between two checkpoints.
+		let mut attempt = 1;
+		let (database, report) = loop {
+			let folder = Arc::new(OverlayFolder::new(self.open_folder(path.clone())?)?);
+			match self.clone().start_in_memory(folder) {
+				Err(err) if err.is_stale() && attempt < SHARED_OPEN_ATTEMPTS => attempt += 1,
+				result => break result?,
+			}
+		};
+		Ok((database.with_lock(lock), report))
+	}
+
+	/// Opens a new, empty database that is stored in a temporary folder and
+	/// deleted once it is dropped.
+	///
+	/// Scratch databases have no WAL, so they are cheaper to write to, but
+	/// nothing written to them survives a crash. Transactions can still be
+	/// aborted.
+	pub fn open_scratch(self) -> Result<Database, Error> {
+		self.config.validate()?;
+		let dir = TempDir::new().map_err(FileError::from)?;
+		let folder = Arc::new(
+			DatabaseFolder::open(dir.path().to_path_buf())
+				.with_segment_page_sizes(self.config.segment_page_sizes.clone())
+				.with_encryption(self.encryption_key.as_ref())?,
+		);
+		let storage = PageStorage::create_scratch(
+			folder,
+			Self::thread_pool(self.background_threads)?,
+			&self.config,
+		);
+		Ok(Database::new(Storage::Scratch { storage, _dir: dir })
+			.with_canonicalizer(self.canonicalize)
+			.with_encryption_key(self.encryption_key)
+			.with_follower(self.follower, &self.config.wal.record_handlers))
+	}
+
+	/// Opens a new, empty database that is kept entirely in memory, and lost
+	/// once it is dropped, e.g. for tests and caches.
+	///
+	/// Unlike scratch databases, in-memory databases have a WAL, so they
+	/// behave like databases stored in a folder. This includes recovering
+	/// from a crash, see [`Database::simulate_crash`].
+	pub fn open_in_memory(self) -> Result<Database, Error> {
+		let (database, report) = self.open_in_memory_with_report()?;
+		report.log();
+		Ok(database)
+	}
+
+	/// Opens a new in-memory database like
+	/// [`open_in_memory`](Self::open_in_memory), but returns what was found
+	/// while opening it instead of logging it, like
+	/// [`open_with_report`](Self::open_with_report).
+	pub fn open_in_memory_with_report(self) -> Result<(Database, OpenReport), Error> {
+		self.config.validate()?;
+		if self.encryption_key.is_some() {
+			return Err(StorageError::InvalidConfig(
+				"In-memory databases can't be encrypted".to_string(),
+			)
+			.into());
+		}
+		self.start_in_memory(Arc::new(OverlayFolder::in_memory()))
+	}
+
+	/// Opens the database stored in `vfs`, creating it if it doesn't exist yet,
+	/// like [`DatabaseBuilder::open`] does for a folder. Encryption, WAL
+	/// archiving and WAL paths are not supported.
+	pub fn open_vfs(self, vfs: Arc<dyn Vfs>) -> Result<Database, Error> {
+		let (database, report) = self.open_vfs_with_report(vfs)?;
+		report.log();
+		Ok(database)
+	}
+
+	/// Opens the database stored in `vfs` like [`open_vfs`](Self::open_vfs),
+	/// but returns what was found while opening it instead of logging it, like
+	/// [`open_with_report`](Self::open_with_report).
+	pub fn open_vfs_with_report(self, vfs: Arc<dyn Vfs>) -> Result<(Database, OpenReport), Error> {
+		self.config.validate()?;
+		if self.encryption_key.is_some()
+			|| self.config.wal.archive.is_some()
+			|| self.config.wal.path.is_some()
+		{
+			return Err(StorageError::InvalidConfig(
+				"Databases stored in a VFS can't be encrypted, or archive or move their WAL"
+					.to_string(),
+			)
+			.into());
+		}
+		let folder = Arc::new(
+			VfsFolder::new(vfs)
+				.with_durability(self.config.physical_storage.durability)
+				.with_wal_compression(self.config.wal.compress)
+				.with_segment_page_sizes(self.config.segment_page_sizes.clone()),
+		);
+		let thread_pool = Self::thread_pool(self.background_threads)?;
+
+		let (storage, report) =
+			PageStorage::open_or_create(folder, Arc::clone(&thread_pool), &self.config)?;
+		Ok((self.start(Storage::Vfs(storage), &thread_pool), report))
+	}
+
+	/// Opens an in-memory database on the folder, recovering what was written
+	/// to it before.
+	fn start_in_memory(self, folder: Arc<OverlayFolder>) -> Result<(Database, OpenReport), Error> {
+		let thread_pool = Self::thread_pool(self.background_threads)?;
+		let (storage, report) = PageStorage::open_or_create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&self.config,
+		)?;
+		let database = self.start(
+			Storage::InMemory {
+				storage,
+				folder,
+				builder: Box::new(self.clone()),
+			},
+			&thread_pool,
+		);
+		Ok((database, report))
+	}
+
+	/// Upgrades the files of a database that an older version of acorn wrote
+	/// in place, so that they can be opened.
+	fn upgrade_format(folder: &DatabaseFolder) -> Result<(), Error> {
+		let num_upgraded = folder.upgrade_format(&FormatUpgrades::builtin())?;
+		if num_upgraded != 0 {
+			info!("Upgraded the format of {num_upgraded} database files");
+		}
+		Ok(())
+	}
+
+	/// Locks the folder at `path` if [`DatabaseBuilder::lock`] is set.
+	fn lock_folder(&self, path: &Path) -> Result<Option<Arc<FolderLockGuard>>, Error> {
+		if !self.lock {
+			return Ok(None);
+		}
+		let lock = if self.read_only {
+			FolderLock::Shared
+		} else if self.allow_readers {
+			FolderLock::Writer
+		} else {
+			FolderLock::Exclusive
+		};
+		Ok(Some(Arc::new(lock.acquire(path)?)))
+	}
+
+	fn thread_pool(num_threads: Option<usize>) -> Result<Arc<ThreadPool>, Error> {
+		let mut builder = ThreadPool::builder();
+		builder.name_prefix("acorn-");
+		if let Some(num_threads) = num_threads {
+			if num_threads == 0 {
+				return Err(StorageError::InvalidConfig(
+					"A database needs at least one background thread".to_string(),
+				)
+				.into());
+			}
+			builder.pool_size(num_threads);
+		}
+		Ok(Arc::new(builder.create().map_err(FileError::from)?))
+	}
+
+	fn folder(&self, path: PathBuf) -> Result<Arc<DatabaseFolder>, Error> {
+		Ok(Arc::new(self.open_folder(path)?))
+	}
+
+	fn open_folder(&self, path: PathBuf) -> Result<DatabaseFolder, Error> {
+		Ok(DatabaseFolder::open(path)
+			.with_durability(self.config.physical_storage.durability)
+			.with_wal_path(self.config.wal.path.clone())
+			.with_wal_archive(self.config.wal.archive.clone())
+			.with_wal_compression(self.config.wal.compress)
+			.with_segment_page_sizes(self.config.segment_page_sizes.clone())
+			.with_encryption(self.encryption_key.as_ref())?)
+	}
+
+	/// Sets whether background tasks that write back pages or take
+	/// checkpoints are restarted after they panicked, waiting longer after
+	/// each panic. Either way, the database stops accepting transactions once
+	/// a background task panicked, see [`Database::task_panic`], but
+	/// restarting them keeps writing back the changes that were committed.
+	pub fn restart_panicked_tasks(mut self, restart: bool) -> Self {
+		self.config.page_cache.restart_panicked_tasks = restart;
+		self.config.wal.restart_panicked_tasks = restart;
+		self
+	}
+
+	/// Opens the database as a follower of another one: its transactions are
+	/// read-only, and it is only modified by applying the WAL records that
+	/// the other database streams, see [`Database::apply_wal_record`].
+	pub fn follower(mut self, follower: bool) -> Self {
+		self.follower = follower;
+		self
+	}
+
+	/// Wraps storage with a WAL in a database that takes checkpoints in the
+	/// background.
+	fn start(&self, storage: Storage, thread_pool: &ThreadPool) -> Database {
+		let database = Database::new(storage)
+			.with_canonicalizer(self.canonicalize)
+			.with_encryption_key(self.encryption_key.clone())
+			.with_follower(self.follower, &self.config.wal.record_handlers)
+			.with_read_only(self.read_only);
+
+		let tasks = Arc::new(TaskMonitor::new(self.config.wal.restart_panicked_tasks));
+		let (timer, timer_handle) = Timer::new(self.config.checkpoint.poll_interval);
+		let storage = Arc::downgrade(&database.storage);
+		tasks.spawn_periodic(thread_pool, "checkpoint", timer, move |timer| {
+			Storage::periodic_checkpoint_task(timer, Weak::clone(&storage))
+		});
+		database.with_checkpoint_timer(timer_handle, tasks)
+	}
+}
+
+/// Fails if the folder already contains a database, which creating a new one
+/// would overwrite.
+fn ensure_no_database(folder: &DatabaseFolder) -> Result<(), Error> {
+	if folder.iter_wal_files()?.next().is_some() {
+		return Err(StorageError::InvalidConfig(
+			"The folder already contains a database".to_string(),
+		)
+		.into());
+	}
+	Ok(())
+}
+
+/// An embedded acorn database, stored in a single folder.
+pub struct Database {
+	storage: Arc<Storage>,
+	checkpoint_timer_handle: Option<TimerHandle>,
+	tasks: Arc<TaskMonitor>,
+	encryption_key: Option<EncryptionKey>,
+	follower: Option<Mutex<Follower>>,
+	canonicalize: Option<Canonicalize>,
+	read_only: bool,
+	lock: Option<Arc<FolderLockGuard>>,
+	/// The folder that is marked as open until the database is closed, see
+	/// [`Database::close`].
+	open_marker: Option<Arc<DatabaseFolder>>,
+	/// Runs the async variants of the database's operations, see
+	/// [`DatabaseBuilder::background_threads`].
+	#[cfg(feature = "async")]
+	blocking_pool: Arc<BlockingPool>,
+}
+assert_impl_all!(Database: Send, Sync);
+
+/// The WAL records a follower received, but hasn't applied yet.
+#[derive(Default)]
+struct Follower {
+	/// The writes and custom records of each transaction whose commit wasn't
+	/// received yet.
+	pending: HashMap<u64, Vec<WalRecord>>,
+	/// The position after the last applied commit.
+	position: Option<WalPosition>,
+	/// Redo the custom records once their transactions are applied.
+	record_handlers: WalRecordHandlers,
+}
+
+impl Database {
+	/// The number of bytes of each page that can be read and written.
+	pub const PAGE_SIZE: usize = PAGE_BODY_SIZE;
+
+	pub fn builder() -> DatabaseBuilder {
+		DatabaseBuilder::default()
+	}
+
+	/// The number of bytes of the pages of the segment that can be written.
+	/// This is [`Self::PAGE_SIZE`], unless the segment was given smaller pages
+	/// with [`DatabaseBuilder::segment_page_size`]; the rest of those pages
+	/// always reads as zeroes.
+	pub fn page_size(&self, segment_num: u32) -> usize {
+		self.segment_page_sizes().body_size(segment_num)
+	}
+
+	/// Opens the database in the folder at `path` with the default options.
+	/// See [`DatabaseBuilder::open`].
+	pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+		Self::builder().open(path)
+	}
+
+	/// Opens the database in the folder at `path` with the default options,
+	/// and reports what was found while opening it. See
+	/// [`DatabaseBuilder::open_with_report`].
+	pub fn open_with_report(path: impl Into<PathBuf>) -> Result<(Self, OpenReport), Error> {
+		Self::builder().open_with_report(path)
+	}
+
+	/// Opens a new, empty in-memory database with the default options. See
+	/// [`DatabaseBuilder::open_in_memory`].
+	pub fn open_in_memory() -> Result<Self, Error> {
+		Self::builder().open_in_memory()
+	}
+
+	/// Restores the database from a WAL archive with the default options. See
+	/// [`DatabaseBuilder::restore_to`].
+	pub fn restore_to(
+		path: impl Into<PathBuf>,
+		archive: impl Into<PathBuf>,
+		transaction_id: u64,
+	) -> Result<Self, Error> {
+		Self::builder().restore_to(path, archive, transaction_id)
+	}
+
+	fn new(storage: Storage) -> Self {
+		Self {
+			#[cfg(feature = "async")]
+			blocking_pool: storage.blocking_pool(),
+			storage: Arc::new(storage),
+			checkpoint_timer_handle: None,
+			tasks: Arc::default(),
+			encryption_key: None,
+			follower: None,
+			canonicalize: None,
+			read_only: false,
+			lock: None,
+			open_marker: None,
+		}
+	}
+
+	fn with_checkpoint_timer(mut self, timer_handle: TimerHandle, tasks: Arc<TaskMonitor>) -> Self {
+		self.checkpoint_timer_handle = Some(timer_handle);
+		self.tasks = tasks;
+		self
+	}
+
+	fn with_canonicalizer(mut self, canonicalize: Option<Canonicalize>) -> Self {
+		self.canonicalize = canonicalize;
+		self
+	}
+
+	fn with_encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+		self.encryption_key = key;
+		self
+	}
+
+	fn with_lock(mut self, lock: Option<Arc<FolderLockGuard>>) -> Self {
+		self.lock = lock;
+		self
+	}
+
+	fn with_open_marker(mut self, folder: Arc<DatabaseFolder>) -> Self {
+		self.open_marker = Some(folder);
+		self
+	}
+
+	fn with_read_only(mut self, read_only: bool) -> Self {
+		self.read_only = read_only;
+		self
+	}
+
+	fn with_follower(mut self, follower: bool, record_handlers: &WalRecordHandlers) -> Self {
+		self.follower = follower.then(|| {
+			Mutex::new(Follower {
+				record_handlers: record_handlers.clone(),
+				..Follower::default()
+			})
+		});
+		self
+	}
+
+	pub fn begin_transaction(&self) -> Result<Transaction, Error> {
+		self.begin_transaction_with(IsolationLevel::ReadCommitted)
+	}
+
+	/// Begins a transaction whose reads are all repeatable: committing it
+	/// fails if another transaction modified any of the pages it read in the
+	/// meantime, see [`Error::is_read_conflict`]. This makes committed
+	/// transactions serializable, at the cost of having to retry on conflicts.
+	pub fn begin_validated_transaction(&self) -> Result<Transaction, Error> {
+		self.begin_transaction_with(IsolationLevel::Serializable)
+	}
+
+	/// Begins a transaction with the given isolation level. Transactions of
+	/// a follower or a read-only database are read-only.
+	pub fn begin_transaction_with(&self, isolation: IsolationLevel) -> Result<Transaction, Error> {
+		self.start_transaction(isolation, self.follower.is_some() || self.read_only)
+	}
+
+	fn start_transaction(
+		&self,
+		isolation: IsolationLevel,
+		read_only: bool,
+	) -> Result<Transaction, Error> {
+		if let Some(panic) = self.task_panic() {
+			return Err(StorageError::TaskPanicked(panic).into());
+		}
+		let inner = match &*self.storage {
+			Storage::Durable(storage) => {
+				let transaction = if read_only {
+					storage.read_only_transaction()?
+				} else {
+					storage.transaction()?
+				};
+				InnerTransaction::Durable(match isolation {
+					IsolationLevel::ReadCommitted => transaction,
+					IsolationLevel::RepeatableRead => transaction.with_repeatable_reads(),
+					IsolationLevel::SnapshotIsolation => transaction.with_snapshot_reads(),
+					IsolationLevel::Serializable => transaction.with_read_tracking(),
+				})
+			}
+			Storage::Scratch { storage, .. } => {
+				let transaction = if read_only {
+					storage.read_only_transaction()?
+				} else {
+					storage.transaction()?
+				};
+				InnerTransaction::Scratch(match isolation {
+					IsolationLevel::ReadCommitted => transaction,
+					IsolationLevel::RepeatableRead => transaction.with_repeatable_reads(),
+					IsolationLevel::SnapshotIsolation => transaction.with_snapshot_reads(),
+					IsolationLevel::Serializable => transaction.with_read_tracking(),
+				})
+			}
+			Storage::InMemory { storage, .. } => {
+				let transaction = if read_only {
+					storage.read_only_transaction()?
+				} else {
+					storage.transaction()?
+				};
+				InnerTransaction::InMemory(match isolation {
+					IsolationLevel::ReadCommitted => transaction,
+					IsolationLevel::RepeatableRead => transaction.with_repeatable_reads(),
+					IsolationLevel::SnapshotIsolation => transaction.with_snapshot_reads(),
+					IsolationLevel::Serializable => transaction.with_read_tracking(),
+				})
+			}
+			Storage::Vfs(storage) => {
+				let transaction = if read_only {
+					storage.read_only_transaction()?
+				} else {
+					storage.transaction()?
+				};
+				InnerTransaction::Vfs(match isolation {
+					IsolationLevel::ReadCommitted => transaction,
+					IsolationLevel::RepeatableRead => transaction.with_repeatable_reads(),
+					IsolationLevel::SnapshotIsolation => transaction.with_snapshot_reads(),
+					IsolationLevel::Serializable => transaction.with_read_tracking(),
+				})
+			}
+		};
+		Ok(Transaction {
+			inner,
+			_storage: Arc::clone(&self.storage),
+			_lock: self.lock.clone(),
+			#[cfg(feature = "async")]
+			blocking_pool: Arc::clone(&self.blocking_pool),
+		})
+	}
+
+	/// Takes a read-only snapshot of the committed state of the database.
+	/// Reads from the snapshot are not affected by transactions that commit
+	/// after it was taken, and don't wait for transactions that are writing.
+	pub fn snapshot(&self) -> Snapshot {
+		let inner = match &*self.storage {
+			Storage::Durable(storage) => InnerSnapshot::Durable(storage.snapshot()),
+			Storage::Scratch { storage, .. } => InnerSnapshot::Scratch(storage.snapshot()),
+			Storage::InMemory { storage, .. } => InnerSnapshot::InMemory(storage.snapshot()),
+			Storage::Vfs(storage) => InnerSnapshot::Vfs(storage.snapshot()),
+		};
+		self.wrap_snapshot(inner)
+	}
+
+	/// Takes a snapshot of the state of the database as of a past commit,
+	/// identified by the [`Snapshot::seq`] of an earlier snapshot. This fails
+	/// unless the page versions that were replaced since are still retained,
+	/// see [`DatabaseBuilder::version_retention_size`]. Sequence numbers start
+	/// over whenever the database is opened.
+	pub fn read_at(&self, seq: u64) -> Result<Snapshot, Error> {
+		let inner = match &*self.storage {
+			Storage::Durable(storage) => InnerSnapshot::Durable(storage.snapshot_at(seq)?),
+			Storage::Scratch { storage, .. } => InnerSnapshot::Scratch(storage.snapshot_at(seq)?),
+			Storage::InMemory { storage, .. } => InnerSnapshot::InMemory(storage.snapshot_at(seq)?),
+			Storage::Vfs(storage) => InnerSnapshot::Vfs(storage.snapshot_at(seq)?),
+		};
+		Ok(self.wrap_snapshot(inner))
+	}
+
+	/// The position in the WAL that transactions committed from now on come
+	/// after. A replica that is copied from the database afterwards can be
+	/// kept up to date with [`Database::wal_stream`] from this position.
+	pub fn wal_position(&self) -> Result<WalPosition, Error> {
+		let position = match &*self.storage {
+			Storage::Durable(storage) => storage.wal_position()?,
+			Storage::Scratch { .. } => return Err(StorageError::NoWal.into()),
+			Storage::InMemory { storage, .. } => storage.wal_position()?,
+			Storage::Vfs(storage) => storage.wal_position()?,
+		};
+		Ok(position)
+	}
+
+	/// Streams the writes of the transactions that commit at or after `from`,
+	/// in commit order, as soon as the commits are durable. Rolled back
+	/// transactions are left out. The WAL is kept until the stream has read
+	/// it, so a stream that isn't read should be dropped.
+	///
+	/// This fails if a checkpoint already deleted the WAL at `from`.
+	pub fn wal_stream(&self, from: WalPosition) -> Result<WalStream, Error> {
+		let inner = match &*self.storage {
+			Storage::Durable(storage) => InnerWalStream::Durable(storage.wal_stream(from)?),
+			Storage::Scratch { .. } => return Err(StorageError::NoWal.into()),
+			Storage::InMemory { storage, .. } => {
+				InnerWalStream::InMemory(storage.wal_stream(from)?)
+			}
+			Storage::Vfs(storage) => InnerWalStream::Vfs(storage.wal_stream(from)?),
+		};
+		Ok(WalStream { inner })
+	}
+
+	/// Lists the transactions that have items in the WAL, with their positions,
+	/// the number of writes and whether they were committed, rolled back or
+	/// are still open, e.g. to see what was in flight at the time of a crash.
+	/// The written data itself is not read into memory.
+	pub fn wal_transactions(&self) -> Result<Vec<WalTransaction>, Error> {
+		let transactions = match &*self.storage {
+			Storage::Durable(storage) => storage.wal_transactions()?,
+			Storage::Scratch { .. } => return Err(StorageError::NoWal.into()),
+			Storage::InMemory { storage, .. } => storage.wal_transactions()?,
+			Storage::Vfs(storage) => storage.wal_transactions()?,
+		};
+		Ok(transactions)
+	}
+
+	/// Deletes the parts of the WAL that are no longer needed, such as those
+	/// that were only kept for a [`WalStream`] that was dropped since, without
+	/// waiting for the next checkpoint. Unlike [`Database::checkpoint`], this
+	/// doesn't write back any pages. Returns the number of bytes that were
+	/// deleted.
+	pub fn compact_wal(&self) -> Result<usize, Error> {
+		let num_bytes = match &*self.storage {
+			Storage::Durable(storage) => storage.compact_wal()?,
+			Storage::Scratch { .. } => return Err(StorageError::NoWal.into()),
+			Storage::InMemory { storage, .. } => storage.compact_wal()?,
+			Storage::Vfs(storage) => storage.compact_wal()?,
+		};
+		Ok(num_bytes)
+	}
+
+	fn wrap_snapshot(&self, inner: InnerSnapshot) -> Snapshot {
+		Snapshot {
+			inner,
+			_storage: Arc::clone(&self.storage),
+		}
+	}
+
+	/// Applies a record of the WAL stream of another database to a follower,
+	/// see [`DatabaseBuilder::follower`]. The writes of a transaction become
+	/// visible at once when its commit is applied. Custom records are logged
+	/// on the follower as well, and redone by its handlers once their
+	/// transaction is applied.
+	pub fn apply_wal_record(&self, record: WalRecord) -> Result<(), Error> {
+		let Some(follower) = &self.follower else {
+			return Err(StorageError::NotAFollower.into());
+		};
+		let mut follower = follower.lock();
+		match record {
+			WalRecord::Write { transaction_id, .. } | WalRecord::Custom { transaction_id, .. } => {
+				follower
+					.pending
+					.entry(transaction_id)
+					.or_default()
+					.push(record);
+			}
+			WalRecord::Commit {
+				transaction_id,
+				position,
+			} => {
+				let records = follower.pending.remove(&transaction_id).unwrap_or_default();
+				let mut t = self.start_transaction(IsolationLevel::ReadCommitted, false)?;
+				let mut custom_records = Vec::new();
+				for record in records {
+					match record {
+						WalRecord::Write {
+							page_id,
+							offset,
+							data,
+							..
+						} => t.write(page_id, usize::from(offset), &data)?,
+						WalRecord::Custom {
+							record_type,
+							version,
+							data,
+							..
+						} => {
+							t.log_record(record_type, version, &data)?;
+							custom_records.push(CustomRecord {
+								transaction_id: t.id(),
+								record_type,
+								version,
+								data,
+							});
+						}
+						WalRecord::Commit { .. } => (),
+					}
+				}
+				t.commit()?;
+				for record in &custom_records {
+					follower.record_handlers.redo(record)?;
+				}
+				follower.position = Some(position);
+			}
+		}
+		Ok(())
+	}
+
+	/// The position after the last commit that was applied to a follower,
+	/// from which the WAL stream of the other database can be resumed.
+	pub fn replicated_position(&self) -> Option<WalPosition> {
+		self.follower.as_ref()?.lock().position
+	}
+
+	/// Reads committed data from a page, outside of any transaction.
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		Ok(self.storage.read(page_id, offset, buf)?)
+	}
+
+	/// Writes all modified pages to disk, and waits for that to complete.
+	pub fn flush(&self) -> Result<(), Error> {
+		Ok(self.storage.flush()?)
+	}
+
+	/// Changes the number of bytes per second that are written back to disk
+	/// in the background, or removes the limit if `None`.
+	pub fn set_background_io_rate(&self, rate: Option<usize>) {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.set_background_io_rate(rate),
+			Storage::Scratch { storage, .. } => storage.set_background_io_rate(rate),
+			Storage::InMemory { storage, .. } => storage.set_background_io_rate(rate),
+			Storage::Vfs(storage) => storage.set_background_io_rate(rate),
+		}
+	}
+
+	/// Returns I/O statistics for each segment that was accessed since the
+	/// database was opened, keyed by segment number.
+	pub fn segment_stats(&self) -> BTreeMap<u32, SegmentIoStats> {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.segment_stats(),
+			Storage::Scratch { storage, .. } => storage.segment_stats(),
+			Storage::InMemory { storage, .. } => storage.segment_stats(),
+			Storage::Vfs(storage) => storage.segment_stats(),
+		}
+	}
+
+	/// Returns how much memory the database currently uses, broken down by
+	/// what it is used for. The numbers are estimates, and don't include
+	/// memory used by the allocator itself.
+	pub fn memory_usage(&self) -> MemoryUsage {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.memory_usage(),
+			Storage::Scratch { storage, .. } => storage.memory_usage(),
+			Storage::InMemory { storage, .. } => storage.memory_usage(),
+			Storage::Vfs(storage) => storage.memory_usage(),
+		}
+	}
+
+	/// Writes the IDs of the pages that are currently cached to the file at
+	/// `path`, e.g. right before closing the database, so that a restarted
+	/// database can regain its working set with [`Self::warm_cache_from`].
+	pub fn save_cache_state(&self, path: impl Into<PathBuf>) -> Result<(), Error> {
+		let path = path.into();
+		match &*self.storage {
+			Storage::Durable(storage) => storage.save_cache_state(&path)?,
+			Storage::Scratch { storage, .. } => storage.save_cache_state(&path)?,
+			Storage::InMemory { storage, .. } => storage.save_cache_state(&path)?,
+			Storage::Vfs(storage) => storage.save_cache_state(&path)?,
+		}
+		Ok(())
+	}
+
+	/// Starts reading the pages listed by [`Self::save_cache_state`] into the
+	/// cache in the background, as many as fit, so that they don't have to
+	/// be read from disk on first access. Returns the number of pages that
+	/// are read. If there is no file at `path`, nothing is read.
+	pub fn warm_cache_from(&self, path: impl Into<PathBuf>) -> Result<usize, Error> {
+		let path = path.into();
+		let num_pages = match &*self.storage {
+			Storage::Durable(storage) => storage.warm_cache_from(&path)?,
+			Storage::Scratch { storage, .. } => storage.warm_cache_from(&path)?,
+			Storage::InMemory { storage, .. } => storage.warm_cache_from(&path)?,
+			Storage::Vfs(storage) => storage.warm_cache_from(&path)?,
+		};
+		Ok(num_pages)
+	}
+
+	/// Returns the counters of the cache, the WAL and the transactions since
+	/// the database was opened, along with the number of pages in each
+	/// segment and the current memory usage.
+	pub fn stats(&self) -> Result<Stats, Error> {
+		let stats = match &*self.storage {
+			Storage::Durable(storage) => storage.stats()?,
+			Storage::Scratch { storage, .. } => storage.stats()?,
+			Storage::InMemory { storage, .. } => storage.stats()?,
+			Storage::Vfs(storage) => storage.stats()?,
+		};
+		Ok(stats)
+	}
+
+	/// Reads every page stored in the segment files, and reports those that
+	/// fail their checksum or can't be read at all, e.g. to verify the
+	/// database after a crash. Pages are checked as they are stored on disk,
+	/// so recent writes that are only in the WAL so far are not covered.
+	pub fn check(&self) -> Result<CheckReport, Error> {
+		let report = match &*self.storage {
+			Storage::Durable(storage) => storage.check()?,
+			Storage::Scratch { storage, .. } => storage.check()?,
+			Storage::InMemory { storage, .. } => storage.check()?,
+			Storage::Vfs(storage) => storage.check()?,
+		};
+		Ok(report)
+	}
+
+	/// Makes the pages of a segment read-only, e.g. once reference data that
+	/// is never modified again was loaded into it. Writes to the segment fail
+	/// afterwards, see [`Error::is_frozen_segment`], while reads from it don't
+	/// wait for or conflict with other transactions.
+	///
+	/// The pages are written back and their checksums are verified once while
+	/// freezing, which fails if a transaction is still writing to the segment.
+	/// Segments stay frozen until the database is closed.
+	pub fn freeze_segment(&self, segment_num: u32) -> Result<(), Error> {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.freeze_segment(segment_num)?,
+			Storage::Scratch { storage, .. } => storage.freeze_segment(segment_num)?,
+			Storage::InMemory { storage, .. } => storage.freeze_segment(segment_num)?,
+			Storage::Vfs(storage) => storage.freeze_segment(segment_num)?,
+		}
+		Ok(())
+	}
+
+	/// Returns which transactions currently hold which pages, and which of
+	/// them are waiting for pages held by others.
+	pub fn lock_graph(&self) -> LockGraph {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.lock_graph(),
+			Storage::Scratch { storage, .. } => storage.lock_graph(),
+			Storage::InMemory { storage, .. } => storage.lock_graph(),
+			Storage::Vfs(storage) => storage.lock_graph(),
+		}
+	}
+
+	/// Copies the current state of the database into a new database in the
+	/// folder at `path`, which must not contain a database yet. Transactions
+	/// can continue while the backup is taken; it contains the state as of
+	/// the last commit before it started.
+	///
+	/// Every stored page is read through the page cache, so the backup may
+	/// evict pages that are frequently accessed.
+	pub fn backup_to(&self, path: impl Into<PathBuf>) -> Result<(), Error> {
+		let folder = self.backup_folder(path.into())?;
+		ensure_no_database(&folder)?;
+		let target = PageStorage::create(
+			Arc::clone(&folder),
+			DatabaseBuilder::thread_pool(None)?,
+			&self.backup_config(),
+		)?;
+		self.backup_into(&folder, &target, None)
+	}
+
+	/// Brings a backup that was taken of this database with
+	/// [`Self::backup_to`] up to date, by copying only the pages that were
+	/// modified since it was taken or last updated.
+	pub fn update_backup(&self, path: impl Into<PathBuf>) -> Result<(), Error> {
+		let folder = self.backup_folder(path.into())?;
+		let Some(since) = folder.backup_point()? else {
+			return Err(StorageError::InvalidConfig(
+				"The folder doesn't contain a backup".to_string(),
+			)
+			.into());
+		};
+		let target = PageStorage::open(
+			Arc::clone(&folder),
+			DatabaseBuilder::thread_pool(None)?,
+			&self.backup_config(),
+		)?;
+		self.backup_into(&folder, &target, Some(since))
+	}
+
+	fn backup_folder(&self, path: PathBuf) -> Result<Arc<DatabaseFolder>, Error> {
+		Ok(Arc::new(
+			DatabaseFolder::open(path)
+				.with_segment_page_sizes(self.segment_page_sizes().clone())
+				.with_encryption(self.encryption_key.as_ref())?,
+		))
+	}
+
+	/// Backups have the page sizes of the database, so that they can be
+	/// opened with the same options.
+	fn backup_config(&self) -> PageStorageConfig {
+		PageStorageConfig {
+			segment_page_sizes: self.segment_page_sizes().clone(),
+			..Default::default()
+		}
+	}
+
+	fn segment_page_sizes(&self) -> &SegmentPageSizes {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.segment_page_sizes(),
+			Storage::Scratch { storage, .. } => storage.segment_page_sizes(),
+			Storage::InMemory { storage, .. } => storage.segment_page_sizes(),
+			Storage::Vfs(storage) => storage.segment_page_sizes(),
+		}
+	}
+
+	fn backup_into(
+		&self,
+		folder: &DatabaseFolder,
+		target: &Arc<PageStorage>,
+		since: Option<WalIndex>,
+	) -> Result<(), Error> {
+		let canonicalize = self.canonicalize;
+		let backup_point = match &*self.storage {
+			Storage::Durable(storage) => page_store::backup(storage, target, since, canonicalize)?,
+			Storage::Scratch { storage, .. } => {
+				page_store::backup(storage, target, since, canonicalize)?
+			}
+			Storage::InMemory { storage, .. } => {
+				page_store::backup(storage, target, since, canonicalize)?
+			}
+			Storage::Vfs(storage) => page_store::backup(storage, target, since, canonicalize)?,
+		};
+		target.checkpoint()?;
+		folder.set_backup_point(backup_point)?;
+		Ok(())
+	}
+
+	/// Writes all modified pages to disk, and deletes the parts of the WAL
+	/// that are no longer needed for recovery.
+	///
+	/// This also happens automatically in the background, according to the
+	/// checkpoint options of the [`DatabaseBuilder`].
+	pub fn checkpoint(&self) -> Result<(), Error> {
+		self.storage.checkpoint()?;
+		Ok(())
+	}
+
+	/// Returns how many checkpoints were taken and why, along with the current
+	/// values of the metrics that trigger them.
+	pub fn checkpoint_stats(&self) -> CheckpointStats {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.checkpoint_stats(),
+			Storage::Scratch { storage, .. } => storage.checkpoint_stats(),
+			Storage::InMemory { storage, .. } => storage.checkpoint_stats(),
+			Storage::Vfs(storage) => storage.checkpoint_stats(),
+		}
+	}
+
+	/// Registers a function that is called after each transaction that can
+	/// write committed, with the ID of the transaction and the pages it wrote
+	/// to. By then, the commit is as durable as the
+	/// [`Durability`](crate::Durability) of the database makes it, so the
+	/// function can safely invalidate caches or start processing the changes
+	/// elsewhere.
+	///
+	/// Listeners are called on the thread that committed, before
+	/// [`Transaction::commit`] returns, and stay registered as long as the
+	/// database is open.
+	pub fn on_commit(&self, listener: impl Fn(u64, &BTreeSet<PageId>) + Send + Sync + 'static) {
+		self.storage.listeners().on_commit(listener);
+	}
+
+	/// Registers a function that is called after each transaction that can
+	/// write was aborted or dropped without committing, with the ID of the
+	/// transaction and the pages whose writes were undone. See
+	/// [`Database::on_commit`].
+	pub fn on_rollback(&self, listener: impl Fn(u64, &BTreeSet<PageId>) + Send + Sync + 'static) {
+		self.storage.listeners().on_rollback(listener);
+	}
+
+	/// Registers a function that is called after each checkpoint, with the
+	/// reason it was taken. Checkpoints that are taken in the background call
+	/// it on a background thread. See [`Database::on_commit`].
+	pub fn on_checkpoint(&self, listener: impl Fn(CheckpointTrigger) + Send + Sync + 'static) {
+		self.storage.listeners().on_checkpoint(listener);
+	}
+
+	/// Returns what the maintenance that runs while the database is idle did
+	/// so far, see [`DatabaseBuilder::idle_maintenance`].
+	pub fn maintenance_stats(&self) -> MaintenanceStats {
+		match &*self.storage {
+			Storage::Durable(storage) => storage.maintenance_stats(),
+			Storage::Scratch { storage, .. } => storage.maintenance_stats(),
+			Storage::InMemory { storage, .. } => storage.maintenance_stats(),
+			Storage::Vfs(storage) => storage.maintenance_stats(),
+		}
+	}
+
+	/// Drops an in-memory database as if the process crashed, losing
+	/// everything that wasn't written to its WAL or segment files yet, and
+	/// recovers it from them like opening it again would. This fails for
+	/// databases that are not in memory, and while transactions or snapshots
+	/// of the database still exist.
+	pub fn simulate_crash(self) -> Result<Database, Error> {
+		let (database, report) = self.simulate_crash_with_report()?;
+		report.log();
+		Ok(database)
+	}
+
+	/// Simulates a crash like [`simulate_crash`](Self::simulate_crash), but
+	/// returns what was found while recovering the database instead of
+	/// logging it, which always includes [`OpenWarning::UncleanShutdown`].
+	pub fn simulate_crash_with_report(self) -> Result<(Database, OpenReport), Error> {
+		if Arc::strong_count(&self.storage) > 1 {
+			return Err(StorageError::InvalidConfig(
+				"Can't simulate a crash while transactions or snapshots exist".to_string(),
+			)
+			.into());
+		}
+		let Storage::InMemory {
+			folder, builder, ..
+		} = &*self.storage
+		else {
+			return Err(StorageError::InvalidConfig(
+				"Only in-memory databases can simulate a crash".to_string(),
+			)
+			.into());
+		};
+		let folder = Arc::clone(folder);
+		let builder = DatabaseBuilder::clone(builder);
+		mem::drop(self);
+		let (database, mut report) = builder.start_in_memory(folder)?;
+		report.warnings.push(OpenWarning::UncleanShutdown);
+		Ok((database, report))
+	}
+
+	/// Whether another process wrote to the folder of a read-only database
+	/// since it was opened, so that [`Database::refresh`] would see a newer
+	/// state. Always `false` for databases that aren't read-only.
+	pub fn is_stale(&self) -> Result<bool, Error> {
+		match &*self.storage {
+			Storage::InMemory { folder, .. } if self.read_only => Ok(folder.base_changed()?),
+			_ => Ok(false),
+		}
+	}
+
+	/// Opens a read-only database again, so that it sees what the process
+	/// writing to it committed since it was opened, see
+	/// [`DatabaseBuilder::allow_readers`]. Transactions and snapshots of the
+	/// database keep reading the state it had before.
+	pub fn refresh(self) -> Result<Database, Error> {
+		let (database, report) = self.refresh_with_report()?;
+		report.log();
+		Ok(database)
+	}
+
+	/// Opens a read-only database again like [`refresh`](Self::refresh), but
+	/// returns what was found while opening it instead of logging it.
+	pub fn refresh_with_report(self) -> Result<(Database, OpenReport), Error> {
+		let reopen = match &*self.storage {
+			Storage::InMemory {
+				folder, builder, ..
+			} if self.read_only => folder
+				.base_path()
+				.map(|path| (path.to_path_buf(), DatabaseBuilder::clone(builder))),
+			_ => None,
+		};
+		let Some((path, builder)) = reopen else {
+			return Err(StorageError::InvalidConfig(
+				"Only read-only databases can be refreshed".to_string(),
+			)
+			.into());
+		};
+		mem::drop(self);
+		builder.open_with_report(path)
+	}
+
+	/// The first panic of one of the database's background tasks, if any
+	/// panicked. Once one did, beginning a transaction fails, but the
+	/// database can still be read, checkpointed and closed.
+	pub fn task_panic(&self) -> Option<TaskPanic> {
+		self.tasks.panic().or_else(|| self.storage.task_panic())
+	}
+
+	/// Checkpoints and closes the database. Dropping the database without
+	/// closing it is safe, but requires recovery the next time it is opened,
+	/// which reports [`OpenWarning::UncleanShutdown`]. So does closing it
+	/// while transactions or snapshots of it still exist.
+	pub fn close(self) -> Result<(), Error> {
+		self.checkpoint()?;
+		if let Some(folder) = &self.open_marker {
+			if Arc::strong_count(&self.storage) == 1 {
+				folder.mark_closed()?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(feature = "async")]
+impl Database {
+	/// Reads `len` bytes of committed data from a page, like
+	/// [`Database::read`], but without blocking the caller's executor.
+	pub async fn read_async(
+		&self,
+		page_id: PageId,
+		offset: usize,
+		len: usize,
+	) -> Result<Vec<u8>, Error> {
+		let storage = Arc::clone(&self.storage);
+		self.blocking_pool
+			.spawn_blocking("read", move || {
+				let mut buf = vec![0; len];
+				storage.read(page_id, offset, &mut buf)?;
+				Ok(buf)
+			})
+			.await
+			.map_err(StorageError::TaskPanicked)?
+	}
+
+	/// Like [`Database::flush`], but without blocking the caller's executor.
+	pub async fn flush_async(&self) -> Result<(), Error> {
+		let storage = Arc::clone(&self.storage);
+		self.blocking_pool
+			.spawn_blocking("flush", move || Ok(storage.flush()?))
+			.await
+			.map_err(StorageError::TaskPanicked)?
+	}
+}
+
+enum InnerTransaction {
+	Durable(<Arc<PageStorage> as PageStorageApi>::Transaction<'static>),
+	Scratch(<Arc<ScratchPageStorage> as PageStorageApi>::Transaction<'static>),
+	InMemory(<Arc<InMemoryPageStorage> as PageStorageApi>::Transaction<'static>),
+	Vfs(<Arc<VfsPageStorage> as PageStorageApi>::Transaction<'static>),
+}
+
+/// A transaction on a [`Database`]. Writes only become durable once the
+/// transaction is committed; a transaction that is dropped without being
+/// committed is aborted.
+///
+/// Transactions don't borrow the database they were started on, so they can
+/// be stored alongside it, and outlive the `Database` handle itself.
+pub struct Transaction {
+	inner: InnerTransaction,
+	/// Keeps the rest of the storage alive, such as the directory of scratch
+	/// databases.
+	_storage: Arc<Storage>,
+	/// Keeps other processes from opening the database while the transaction
+	/// can still write to it.
+	_lock: Option<Arc<FolderLockGuard>>,
+	#[cfg(feature = "async")]
+	blocking_pool: Arc<BlockingPool>,
+}
+assert_impl_all!(Transaction: Send);
+
+impl Transaction {
+	pub fn id(&self) -> u64 {
+		match &self.inner {
+			InnerTransaction::Durable(t) => t.id(),
+			InnerTransaction::Scratch(t) => t.id(),
+			InnerTransaction::InMemory(t) => t.id(),
+			InnerTransaction::Vfs(t) => t.id(),
+		}
+	}
+
+	/// What the transaction holds on to until it completes, including the
+	/// pages it spilled to disk, see
+	/// [`DatabaseBuilder::max_transaction_pages`].
+	pub fn size(&self) -> TransactionSize {
+		match &self.inner {
+			InnerTransaction::Durable(t) => t.size(),
+			InnerTransaction::Scratch(t) => t.size(),
+			InnerTransaction::InMemory(t) => t.size(),
+			InnerTransaction::Vfs(t) => t.size(),
+		}
+	}
+
+	/// Makes reads and writes fail instead of waiting past `deadline` for
+	/// pages that other transactions are writing to, for the lock of a cached
+	/// page, or for another thread to read a page from disk, see
+	/// [`Error::is_timed_out`]. The transaction can still be aborted or
+	/// committed afterwards.
+	pub fn with_deadline(mut self, deadline: Instant) -> Self {
+		self.inner = match self.inner {
+			InnerTransaction::Durable(t) => InnerTransaction::Durable(t.with_deadline(deadline)),
+			InnerTransaction::Scratch(t) => InnerTransaction::Scratch(t.with_deadline(deadline)),
+			InnerTransaction::InMemory(t) => InnerTransaction::InMemory(t.with_deadline(deadline)),
+			InnerTransaction::Vfs(t) => InnerTransaction::Vfs(t.with_deadline(deadline)),
+		};
+		self
+	}
+
+	/// Makes reads and writes fail instead of waiting for pages once `token`
+	/// is cancelled, like [`Self::with_deadline`], see
+	/// [`Error::is_cancelled`]. Waits that are in progress are interrupted,
+	/// too.
+	pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+		self.inner = match self.inner {
+			InnerTransaction::Durable(t) => InnerTransaction::Durable(t.with_cancellation(token)),
+			InnerTransaction::Scratch(t) => InnerTransaction::Scratch(t.with_cancellation(token)),
+			InnerTransaction::InMemory(t) => InnerTransaction::InMemory(t.with_cancellation(token)),
+			InnerTransaction::Vfs(t) => InnerTransaction::Vfs(t.with_cancellation(token)),
+		};
+		self
+	}
+
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		match &self.inner {
+			InnerTransaction::Durable(t) => t.get_page(page_id)?.read(offset, buf)?,
+			InnerTransaction::Scratch(t) => t.get_page(page_id)?.read(offset, buf)?,
+			InnerTransaction::InMemory(t) => t.get_page(page_id)?.read(offset, buf)?,
+			InnerTransaction::Vfs(t) => t.get_page(page_id)?.read(offset, buf)?,
+		}
+		Ok(())
+	}
+
+	pub fn write(&mut self, page_id: PageId, offset: usize, buf: &[u8]) -> Result<(), Error> {
+		match &mut self.inner {
+			InnerTransaction::Durable(t) => t.get_page_mut(page_id)?.write(offset, buf)?,
+			InnerTransaction::Scratch(t) => t.get_page_mut(page_id)?.write(offset, buf)?,
+			InnerTransaction::InMemory(t) => t.get_page_mut(page_id)?.write(offset, buf)?,
+			InnerTransaction::Vfs(t) => t.get_page_mut(page_id)?.write(offset, buf)?,
+		}
+		Ok(())
+	}
+
+	/// Reads a whole page as a value of type `T`. Fails if the page doesn't
+	/// start with the tag of `T`, see [`Error::page_type_mismatch`].
+	pub fn read_typed<T: PageType>(&self, page_id: PageId) -> Result<T, Error> {
+		let mut page = vec![0; PAGE_BODY_SIZE];
+		self.read(page_id, 0, &mut page)?;
+		Ok(decode_page(page_id, &page)?)
+	}
+
+	/// Replaces the content of a page with `value`, preceded by the tag of its
+	/// type.
+	pub fn write_typed<T: PageType>(&mut self, page_id: PageId, value: &T) -> Result<(), Error> {
+		self.write(page_id, 0, &encode_page(value))
+	}
+
+	/// Replaces the content of the page `to` with that of the page `from`.
+	pub fn copy_page(&mut self, from: PageId, to: PageId) -> Result<(), Error> {
+		match &mut self.inner {
+			InnerTransaction::Durable(t) => t.copy_page(from, to)?,
+			InnerTransaction::Scratch(t) => t.copy_page(from, to)?,
+			InnerTransaction::InMemory(t) => t.copy_page(from, to)?,
+			InnerTransaction::Vfs(t) => t.copy_page(from, to)?,
+		}
+		Ok(())
+	}
+
+	/// Moves the content of the page `from` to the page `to`, leaving `from`
+	/// empty. Like any other write, the move only takes effect once the
+	/// transaction commits, so a crash can neither lose nor duplicate the
+	/// page.
+	pub fn move_page(&mut self, from: PageId, to: PageId) -> Result<(), Error> {
+		match &mut self.inner {
+			InnerTransaction::Durable(t) => t.move_page(from, to)?,
+			InnerTransaction::Scratch(t) => t.move_page(from, to)?,
+			InnerTransaction::InMemory(t) => t.move_page(from, to)?,
+			InnerTransaction::Vfs(t) => t.move_page(from, to)?,
+		}
+		Ok(())
+	}
+
+	/// Logs a custom record of the type `record_type`, whose data is in the
+	/// format `version`, so that the handler registered for the type with
+	/// [`DatabaseBuilder::wal_record_handler`] redoes or undoes it during
+	/// recovery. The record is logged on commit, together with the writes of
+	/// the transaction. Aborting the transaction instead undoes the record
+	/// right away.
+	///
+	/// Fails if no handler is registered for the type, or if the data is
+	/// larger than a single WAL item can hold.
+	pub fn log_record(&mut self, record_type: u16, version: u16, data: &[u8]) -> Result<(), Error> {
+		match &mut self.inner {
+			InnerTransaction::Durable(t) => t.log_record(record_type, version, data)?,
+			InnerTransaction::Scratch(t) => t.log_record(record_type, version, data)?,
+			InnerTransaction::InMemory(t) => t.log_record(record_type, version, data)?,
+			InnerTransaction::Vfs(t) => t.log_record(record_type, version, data)?,
+		}
+		Ok(())
+	}
+
+	pub fn commit(self) -> Result<(), Error> {
+		match self.inner {
+			InnerTransaction::Durable(t) => t.commit()?,
+			InnerTransaction::Scratch(t) => t.commit()?,
+			InnerTransaction::InMemory(t) => t.commit()?,
+			InnerTransaction::Vfs(t) => t.commit()?,
+		}
+		Ok(())
+	}
+
+	pub fn abort(self) -> Result<(), Error> {
+		match self.inner {
+			InnerTransaction::Durable(t) => t.undo()?,
+			InnerTransaction::Scratch(t) => t.undo()?,
+			InnerTransaction::InMemory(t) => t.undo()?,
+			InnerTransaction::Vfs(t) => t.undo()?,
+		}
+		Ok(())
+	}
+
+	/// Like [`Transaction::commit`], but waits for the WAL to be flushed
+	/// without blocking the caller's executor.
+	#[cfg(feature = "async")]
+	pub async fn commit_async(self) -> Result<(), Error> {
+		let blocking_pool = Arc::clone(&self.blocking_pool);
+		blocking_pool
+			.spawn_blocking("commit", move || self.commit())
+			.await
+			.map_err(StorageError::TaskPanicked)?
+	}
+}
+
+enum InnerSnapshot {
+	Durable(<Arc<PageStorage> as PageStorageApi>::Snapshot<'static>),
+	Scratch(<Arc<ScratchPageStorage> as PageStorageApi>::Snapshot<'static>),
+	InMemory(<Arc<InMemoryPageStorage> as PageStorageApi>::Snapshot<'static>),
+	Vfs(<Arc<VfsPageStorage> as PageStorageApi>::Snapshot<'static>),
+}
+
+/// A read-only snapshot of a [`Database`], see [`Database::snapshot`].
+pub struct Snapshot {
+	inner: InnerSnapshot,
+	/// Keeps the rest of the storage alive, see [`Transaction`].
+	_storage: Arc<Storage>,
+}
+assert_impl_all!(Snapshot: Send, Sync);
+
+impl Snapshot {
+	/// The sequence number of the last commit that the snapshot sees.
+	pub fn seq(&self) -> u64 {
+		match &self.inner {
+			InnerSnapshot::Durable(s) => s.seq(),
+			InnerSnapshot::Scratch(s) => s.seq(),
+			InnerSnapshot::InMemory(s) => s.seq(),
+			InnerSnapshot::Vfs(s) => s.seq(),
+		}
+	}
+
+	pub fn read(&self, page_id: PageId, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+		match &self.inner {
+			InnerSnapshot::Durable(s) => s.get_page(page_id)?.read(offset, buf)?,
+			InnerSnapshot::Scratch(s) => s.get_page(page_id)?.read(offset, buf)?,
+			InnerSnapshot::InMemory(s) => s.get_page(page_id)?.read(offset, buf)?,
+			InnerSnapshot::Vfs(s) => s.get_page(page_id)?.read(offset, buf)?,
+		}
+		Ok(())
+	}
+
+	/// Reads a whole page as a value of type `T`, see
+	/// [`Transaction::read_typed`].
+	pub fn read_typed<T: PageType>(&self, page_id: PageId) -> Result<T, Error> {
+		let mut page = vec![0; PAGE_BODY_SIZE];
+		self.read(page_id, 0, &mut page)?;
+		Ok(decode_page(page_id, &page)?)
+	}
+}
+
+enum InnerWalStream {
+	Durable(WalSubscription<DatabaseFolder>),
+	InMemory(WalSubscription<OverlayFolder>),
+	Vfs(WalSubscription<VfsFolder>),
+}
+
+/// The committed changes to a [`Database`], see [`Database::wal_stream`].
+/// Iterating waits for the next record, and ends once the database is
+/// closed.
+pub struct WalStream {
+	inner: InnerWalStream,
+}
+assert_impl_all!(WalStream: Send);
+
+impl WalStream {
+	/// Returns the next record if it is already durable, without waiting.
+	pub fn try_next(&mut self) -> Result<Option<WalRecord>, Error> {
+		self.next_record(false)
+	}
+
+	fn next_record(&mut self, wait: bool) -> Result<Option<WalRecord>, Error> {
+		let record = match &mut self.inner {
+			InnerWalStream::Durable(s) => s.next_record(wait)?,
+			InnerWalStream::InMemory(s) => s.next_record(wait)?,
+			InnerWalStream::Vfs(s) => s.next_record(wait)?,
+		};
+		Ok(record)
+	}
+}
+
+impl Iterator for WalStream {
+	type Item = Result<WalRecord, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.next_record(true).transpose()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		ffi::OsString,
+		io::{self, Write},
+		iter,
+		num::NonZeroU8,
+		path::Path,
+		sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+		thread,
+	};
+
+	use tempfile::tempdir;
+
+	use crate::{
+		files::{
+			segment,
+			test_helpers::page_id,
+			vfs::{OsVfs, VfsFile},
+		},
+		page_store::{
+			test_helpers::{HandlerCall, RecordingHandler},
+			CheckProblem, OpenWarning, WalTransactionStatus,
+		},
+		utils::units::{ByteSize, KIB},
+	};
+
+	use super::*;
+
+	#[test]
+	fn write_and_reopen() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 10, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 10, &[4, 5, 6]).unwrap();
+		t.abort().unwrap();
+		db.close().unwrap();
+
+		// when
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 10, &mut buf).unwrap();
+
+		// then
+		assert_eq!(buf, [1, 2, 3]);
+	}
+
+	#[test]
+	fn report_problems_found_while_opening() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		db.close().unwrap();
+		let wal_dir = tempdir.path().join(DatabaseFolder::WAL_DIR_NAME);
+		let last_generation: u64 = fs::read_dir(&wal_dir)
+			.unwrap()
+			.map(|entry| {
+				entry
+					.unwrap()
+					.file_name()
+					.to_string_lossy()
+					.parse()
+					.unwrap()
+			})
+			.max()
+			.unwrap();
+		fs::OpenOptions::new()
+			.append(true)
+			.open(wal_dir.join(last_generation.to_string()))
+			.unwrap()
+			.write_all(&[0xff; 5])
+			.unwrap();
+
+		// when
+		let (_db, report) = Database::open_with_report(tempdir.path()).unwrap();
+
+		// then
+		assert_eq!(
+			report.warnings,
+			[OpenWarning::TornWalTail {
+				generation: last_generation,
+				len: 5
+			}]
+		);
+	}
+
+	#[test]
+	fn report_unclean_shutdown() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		mem::drop(db);
+
+		// when
+		let (db, report_after_drop) = Database::open_with_report(tempdir.path()).unwrap();
+		db.close().unwrap();
+		let (_db, report_after_close) = Database::open_with_report(tempdir.path()).unwrap();
+
+		// then
+		assert_eq!(report_after_drop.warnings, [OpenWarning::UncleanShutdown]);
+		assert_eq!(report_after_close.warnings, []);
+	}
+
+	#[test]
+	fn report_unclean_shutdown_after_simulated_crash() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+
+		// when
+		let (_db, report) = db.simulate_crash_with_report().unwrap();
+
+		// then
+		assert_eq!(report.warnings, [OpenWarning::UncleanShutdown]);
+	}
+
+	#[test]
+	fn report_unusual_page_sizes() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder()
+			.segment_page_size(2..=3, PAGE_SIZE / 4)
+			.segment_page_size(5..=5, PAGE_SIZE / 2);
+		let db = builder.clone().open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		for segment_num in 1..=5 {
+			t.write(page_id!(segment_num, 1), 0, &[1]).unwrap();
+		}
+		t.commit().unwrap();
+		db.close().unwrap();
+
+		// when
+		let (_db, report) = builder.open_with_report(tempdir.path()).unwrap();
+
+		// then
+		assert_eq!(
+			report.warnings,
+			[
+				OpenWarning::UnusualPageSize {
+					first: 2,
+					last: 3,
+					page_size: PAGE_SIZE / 4
+				},
+				OpenWarning::UnusualPageSize {
+					first: 5,
+					last: 5,
+					page_size: PAGE_SIZE / 2
+				}
+			]
+		);
+	}
+
+	#[test]
+	fn report_sync_primitive_without_writing_probe_files() {
+		// given
+		let tempdir = tempdir().unwrap();
+		Database::open(tempdir.path()).unwrap().close().unwrap();
+		let list_files = || {
+			let mut files: Vec<_> = fs::read_dir(tempdir.path())
+				.unwrap()
+				.map(|entry| entry.unwrap().file_name())
+				.collect();
+			files.sort();
+			files
+		};
+
+		// when
+		let (db, report) = Database::open_with_report(tempdir.path()).unwrap();
+		db.close().unwrap();
+		let files = list_files();
+		let (_db, read_only_report) = Database::builder()
+			.read_only(true)
+			.open_with_report(tempdir.path())
+			.unwrap();
+
+		// then
+		assert!(report.sync_primitive.is_some());
+		assert!(!files.iter().any(|file| file == ".sync_probe"));
+		assert_eq!(read_only_report.sync_primitive, None);
+		assert_eq!(list_files(), files);
+	}
+
+	#[test]
+	fn transaction_outlives_database() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+
+		// when
+		mem::drop(db);
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+
+		// then
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn commit_on_other_thread() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+
+		// when
+		thread::spawn(move || t.commit().unwrap()).join().unwrap();
+
+		// then
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[4, 5, 6]).unwrap();
+		t.commit().unwrap();
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [4, 5, 6]);
+	}
+
+	#[test]
+	fn scratch_database() {
+		// given
+		let db = Database::builder()
+			.page_cache_size(8 * Database::PAGE_SIZE)
+			.open_scratch()
+			.unwrap();
+
+		// when
+		for page_num in 1..=16 {
+			let mut t = db.begin_transaction().unwrap();
+			t.write(page_id!(0, page_num), 0, &page_num.to_ne_bytes())
+				.unwrap();
+			t.commit().unwrap();
+		}
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(0, 1), 0, &[0xff, 0xff]).unwrap();
+		t.abort().unwrap();
+
+		// then
+		for page_num in 1..=16_u16 {
+			let mut buf = [0; 2];
+			db.read(page_id!(0, page_num), 0, &mut buf).unwrap();
+			assert_eq!(u16::from_ne_bytes(buf), page_num);
+		}
+	}
+
+	#[test]
+	fn detect_deadlock() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t1 = db.begin_transaction().unwrap();
+		t1.write(page_id!(1, 1), 0, &[1]).unwrap();
+		let mut t2 = db.begin_transaction().unwrap();
+		t2.write(page_id!(1, 2), 0, &[2]).unwrap();
+
+		// when
+		let write_or_abort = |mut t: Transaction, page_id: PageId| match t.write(page_id, 0, &[3]) {
+			Ok(()) => {
+				t.commit().unwrap();
+				false
+			}
+			Err(err) => {
+				assert!(err.is_deadlock());
+				t.abort().unwrap();
+				true
+			}
+		};
+		let other = thread::spawn(move || write_or_abort(t2, page_id!(1, 1)));
+		let deadlocked = write_or_abort(t1, page_id!(1, 2));
+		let other_deadlocked = other.join().unwrap();
+
+		// then
+		assert!(deadlocked != other_deadlocked);
+	}
+
+	#[test]
+	fn out_of_bounds_write() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+
+		// when
+		let result = t.write(page_id!(1, 2), Database::PAGE_SIZE - 1, &[1, 2]);
+
+		// then
+		assert!(matches!(
+			result,
+			Err(Error(StorageError::PageOutOfBounds { .. }))
+		));
+		t.abort().unwrap();
+	}
+
+	#[test]
+	fn small_pages_in_some_segments() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder().segment_page_size(2..=3, 4 * KIB);
+		let db = builder.clone().open(tempdir.path()).unwrap();
+		let small_size = db.page_size(2);
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), small_size, &[1]).unwrap();
+		t.write(page_id!(2, 1), small_size - 1, &[2]).unwrap();
+
+		// when
+		let too_large = t.write(page_id!(2, 1), small_size, &[3]);
+		let zeroes = t.write(page_id!(3, 1), 0, &vec![0; Database::PAGE_SIZE]);
+		t.commit().unwrap();
+		db.close().unwrap();
+		let mismatched = Database::open(tempdir.path()).unwrap();
+		let mismatched_read = mismatched.read(page_id!(2, 1), 0, &mut [0]);
+		mismatched.close().unwrap();
+		let db = builder.open(tempdir.path()).unwrap();
+
+		// then
+		assert_eq!(db.page_size(1), Database::PAGE_SIZE);
+		assert_eq!(small_size, 4 * KIB - (PAGE_SIZE - Database::PAGE_SIZE));
+		assert!(matches!(
+			too_large,
+			Err(Error(StorageError::PageOutOfBounds { .. }))
+		));
+		assert!(zeroes.is_ok());
+		assert!(matches!(
+			mismatched_read,
+			Err(Error(StorageError::File(FileError::PageSizeMismatch {
+				found: 4096,
+				expected: PAGE_SIZE
+			})))
+		));
+		let mut buf = [0; 1];
+		db.read(page_id!(2, 1), small_size - 1, &mut buf).unwrap();
+		assert_eq!(buf, [2]);
+		let mut tail = [1; 8];
+		db.read(page_id!(2, 1), Database::PAGE_SIZE - 8, &mut tail)
+			.unwrap();
+		assert_eq!(tail, [0; 8]);
+	}
+
+	#[test]
+	fn snapshot_ignores_concurrent_writes() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		let snapshot = db.snapshot();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[4, 5, 6]).unwrap();
+		let mut during_write = [0; 3];
+		snapshot.read(page_id!(1, 2), 0, &mut during_write).unwrap();
+		t.commit().unwrap();
+		let mut after_commit = [0; 3];
+		snapshot.read(page_id!(1, 2), 0, &mut after_commit).unwrap();
+		let mut new_snapshot = [0; 3];
+		db.snapshot()
+			.read(page_id!(1, 2), 0, &mut new_snapshot)
+			.unwrap();
+
+		// then
+		assert_eq!(during_write, [1, 2, 3]);
+		assert_eq!(after_commit, [1, 2, 3]);
+		assert_eq!(new_snapshot, [4, 5, 6]);
+	}
+
+	#[test]
+	fn validated_transaction_conflict() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut validated = db.begin_validated_transaction().unwrap();
+		let mut buf = [0; 3];
+		validated.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		validated.read(page_id!(1, 3), 0, &mut buf).unwrap();
+		validated.write(page_id!(1, 4), 0, &[1, 2, 3]).unwrap();
+		let unrelated = db.begin_validated_transaction().unwrap();
+		unrelated.read(page_id!(1, 3), 0, &mut buf).unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[4, 5, 6]).unwrap();
+		t.commit().unwrap();
+		let result = validated.commit();
+
+		// then
+		assert!(result.is_err_and(|error| error.is_read_conflict()));
+		unrelated.commit().unwrap();
+		db.read(page_id!(1, 4), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0, 0, 0]);
+	}
+
+	#[test]
+	fn report_page_of_read_conflict() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut validated = db.begin_validated_transaction().unwrap();
+		let mut buf = [0; 3];
+		validated.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		validated.write(page_id!(1, 3), 0, &[1, 2, 3]).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[4, 5, 6]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let error = validated.commit().unwrap_err();
+
+		// then
+		assert!(error.is_read_conflict());
+		assert_eq!(error.page_id(), Some(page_id!(1, 2)));
+		assert_eq!(error.kind(), ErrorKind::Conflict);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn classify_full_disks_as_limits() {
+		// given
+		let errors = [libc::ENOSPC, libc::EDQUOT, libc::EIO]
+			.map(|code| Error::from(FileError::Io(io::Error::from_raw_os_error(code))));
+
+		// when
+		let kinds = errors.each_ref().map(Error::kind);
+
+		// then
+		assert_eq!(kinds, [ErrorKind::Limit, ErrorKind::Limit, ErrorKind::Io]);
+	}
+
+	#[test]
+	fn chain_error_sources() {
+		// given
+		let page_error = Error::from(StorageError::ChecksumMismatch(page_id!(1, 2)));
+		let segment_error = Error::from(StorageError::FrozenSegment(3));
+		let io_error = Error::from(FileError::Io(io::Error::other("disk on fire")));
+
+		// when
+		let messages = [&page_error, &segment_error, &io_error].map(|error| {
+			let source = std::error::Error::source(error).unwrap();
+			(error.to_string(), source.to_string())
+		});
+
+		// then
+		assert_eq!(
+			messages,
+			[
+				(
+					"Database error of class corruption on page 00000001:0002".to_string(),
+					StorageError::ChecksumMismatch(page_id!(1, 2)).to_string()
+				),
+				(
+					"Database error of class usage in segment 3".to_string(),
+					StorageError::FrozenSegment(3).to_string()
+				),
+				(
+					"Database error of class io".to_string(),
+					"disk on fire".to_string()
+				),
+			]
+		);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn async_commit_and_read() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+
+		// when
+		let data = futures::executor::block_on(async {
+			t.commit_async().await?;
+			db.flush_async().await?;
+			db.read_async(page_id!(1, 2), 0, 3).await
+		});
+
+		// then
+		assert_eq!(data.unwrap(), [1, 2, 3]);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn run_async_operations_on_pool_of_database() {
+		// given
+		let first = Database::builder()
+			.background_threads(Some(1))
+			.open_in_memory()
+			.unwrap();
+		let second = Database::builder()
+			.background_threads(Some(2))
+			.open_in_memory()
+			.unwrap();
+
+		// when
+		let t = first.begin_transaction().unwrap();
+
+		// then
+		assert!(Arc::ptr_eq(&t.blocking_pool, &first.blocking_pool));
+		assert!(!Arc::ptr_eq(&first.blocking_pool, &second.blocking_pool));
+		let Storage::InMemory { storage, .. } = &*first.storage else {
+			panic!("Expected in-memory storage");
+		};
+		assert!(Arc::ptr_eq(storage.blocking_pool(), &first.blocking_pool));
+	}
+
+	#[test]
+	fn snapshot_isolation_write_conflict() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut isolated = db
+			.begin_transaction_with(IsolationLevel::SnapshotIsolation)
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut buf = [0; 3];
+		isolated.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		isolated.write(page_id!(1, 2), 0, &[4, 5, 6]).unwrap();
+		let result = isolated.commit();
+
+		// then
+		assert_eq!(buf, [0, 0, 0]);
+		let error = result.unwrap_err();
+		assert!(error.is_write_conflict());
+		assert_eq!(error.kind(), ErrorKind::Conflict);
+		assert_eq!(error.page_id(), Some(page_id!(1, 2)));
+		assert_eq!(error.segment_num(), Some(1));
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[7, 8, 9]).unwrap();
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn repeatable_read_keeps_pages_as_first_read() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut isolated = db
+			.begin_transaction_with(IsolationLevel::RepeatableRead)
+			.unwrap();
+		let mut before = [0; 3];
+		isolated.read(page_id!(1, 2), 0, &mut before).unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.write(page_id!(1, 3), 0, &[4, 5, 6]).unwrap();
+		t.commit().unwrap();
+		let mut repeated = [0; 3];
+		isolated.read(page_id!(1, 2), 0, &mut repeated).unwrap();
+		let mut first_read = [0; 3];
+		isolated.read(page_id!(1, 3), 0, &mut first_read).unwrap();
+		isolated.write(page_id!(1, 2), 0, &[7, 8, 9]).unwrap();
+		let mut after_write = [0; 3];
+		isolated.read(page_id!(1, 2), 0, &mut after_write).unwrap();
+		isolated.commit().unwrap();
+
+		// then
+		assert_eq!(before, [0, 0, 0]);
+		assert_eq!(repeated, [0, 0, 0]);
+		assert_eq!(first_read, [4, 5, 6]);
+		assert_eq!(after_write, [7, 8, 9]);
+	}
+
+	#[test]
+	fn freeze_segment() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		let in_use = db.freeze_segment(1);
+		t.commit().unwrap();
+
+		// when
+		db.freeze_segment(1).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		let frozen_write = t.write(page_id!(1, 2), 0, &[4, 5, 6]);
+		t.write(page_id!(2, 2), 0, &[4, 5, 6]).unwrap();
+		let mut buf = [0; 3];
+		t.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert!(in_use.is_err());
+		assert!(frozen_write.is_err_and(|error| error.is_frozen_segment()));
+		assert_eq!(buf, [1, 2, 3]);
+	}
+
+	#[test]
+	fn recover_in_memory() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let db = db.simulate_crash().unwrap();
+
+		// then
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+		assert!(db.simulate_crash().is_ok());
+	}
+
+	struct CountingVfs {
+		inner: OsVfs,
+		opened: AtomicUsize,
+	}
+
+	impl Vfs for CountingVfs {
+		fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+			self.opened.fetch_add(1, Ordering::Relaxed);
+			self.inner.open(path)
+		}
+
+		fn exists(&self, path: &Path) -> io::Result<bool> {
+			self.inner.exists(path)
+		}
+
+		fn remove(&self, path: &Path) -> io::Result<()> {
+			self.inner.remove(path)
+		}
+
+		fn list(&self, path: &Path) -> io::Result<Vec<OsString>> {
+			self.inner.list(path)
+		}
+	}
+
+	#[test]
+	fn open_custom_vfs() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let vfs = Arc::new(CountingVfs {
+			inner: OsVfs::new(tempdir.path()),
+			opened: AtomicUsize::new(0),
+		});
+
+		// when
+		let db = Database::builder().open_vfs(vfs.clone()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		db.close().unwrap();
+
+		// then
+		assert!(vfs.opened.load(Ordering::Relaxed) > 0);
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+	}
+
+	/// Flips a bit of every write to a segment file while `corrupt` is set.
+	struct CorruptingVfs {
+		inner: OsVfs,
+		corrupt: Arc<AtomicBool>,
+	}
+
+	struct CorruptingFile {
+		inner: Box<dyn VfsFile>,
+		corrupt: Arc<AtomicBool>,
+	}
+
+	impl Vfs for CorruptingVfs {
+		fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+			let file = self.inner.open(path)?;
+			if !path.starts_with("segments") {
+				return Ok(file);
+			}
+			Ok(Box::new(CorruptingFile {
+				inner: file,
+				corrupt: Arc::clone(&self.corrupt),
+			}))
+		}
+
+		fn exists(&self, path: &Path) -> io::Result<bool> {
+			self.inner.exists(path)
+		}
+
+		fn remove(&self, path: &Path) -> io::Result<()> {
+			self.inner.remove(path)
+		}
+
+		fn list(&self, path: &Path) -> io::Result<Vec<OsString>> {
+			self.inner.list(path)
+		}
+	}
+
+	impl VfsFile for CorruptingFile {
+		fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+			self.inner.read_exact_at(buf, offset)
+		}
+
+		fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+			if !self.corrupt.load(Ordering::Relaxed) {
+				return self.inner.write_all_at(buf, offset);
+			}
+			let mut buf = buf.to_vec();
+			if let Some(last) = buf.last_mut() {
+				*last ^= 1;
+			}
+			self.inner.write_all_at(&buf, offset)
+		}
+
+		fn len(&self) -> io::Result<u64> {
+			self.inner.len()
+		}
+
+		fn set_len(&self, len: u64) -> io::Result<()> {
+			self.inner.set_len(len)
+		}
+
+		fn sync_data(&self) -> io::Result<()> {
+			self.inner.sync_data()
+		}
+	}
+
+	#[test]
+	fn verify_pages_after_recovery() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let corrupt = Arc::new(AtomicBool::new(false));
+		let vfs = Arc::new(CorruptingVfs {
+			inner: OsVfs::new(tempdir.path()),
+			corrupt: Arc::clone(&corrupt),
+		});
+		let db = Database::builder().open_vfs(vfs.clone()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		mem::drop(db);
+
+		// when
+		corrupt.store(true, Ordering::Relaxed);
+		let result = Database::builder()
+			.verify_after_recovery(true)
+			.open_vfs(vfs);
+
+		// then
+		let error = result.err().unwrap();
+		let problems = error.recovery_problems().unwrap();
+		assert_eq!(
+			problems.problems,
+			[CheckProblem::ChecksumMismatch(page_id!(1, 2))]
+		);
+	}
+
+	#[test]
+	fn read_at_past_commit() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::builder()
+			.version_retention_size(Database::PAGE_SIZE)
+			.open(tempdir.path())
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1]).unwrap();
+		t.commit().unwrap();
+		let seq = db.snapshot().seq();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[2]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut past = [0];
+		db.read_at(seq)
+			.unwrap()
+			.read(page_id!(1, 2), 0, &mut past)
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[3]).unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert_eq!(past, [1]);
+		assert!(db.read_at(seq).is_err());
+		assert!(db.read_at(seq + 1).is_ok());
+	}
+
+	#[test]
+	fn migrate_page_size() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let path = tempdir.path().join("db");
+		Database::open(&path).unwrap().close().unwrap();
+		std::fs::create_dir_all(path.join("segments")).unwrap();
+		segment::test_helpers::write_resized_segment(
+			&path.join("segments").join("1"),
+			PAGE_SIZE / 2,
+			&[(2, &[1; 64]), (5, &[2; 64])],
+		);
+
+		// when
+		let db = Database::builder().migrate_page_size(&path).unwrap();
+		let mut buf = [0; 64];
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		let mut tail = [1; 64];
+		db.read(page_id!(1, 5), Database::PAGE_SIZE - 64, &mut tail)
+			.unwrap();
+
+		// then
+		assert_eq!(buf, [1; 64]);
+		assert_eq!(tail, [0; 64]);
+		db.read(page_id!(1, 5), 0, &mut buf).unwrap();
+		assert_eq!(buf, [2; 64]);
+		assert!(!tempdir.path().join("db.migrating").exists());
+	}
+
+	#[test]
+	fn store_wal_in_other_folder() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let path = tempdir.path().join("db");
+		let wal_path = tempdir.path().join("log");
+		let builder = Database::builder()
+			.wal_path(Some(wal_path.clone()))
+			.checkpoint_period(None);
+		let db = builder.clone().open(&path).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 10, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		mem::drop(db);
+
+		// when
+		let db = builder.open(&path).unwrap();
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 10, &mut buf).unwrap();
+
+		// then
+		assert_eq!(buf, [1, 2, 3]);
+		assert!(!path.join("wal").exists());
+		assert_ne!(std::fs::read_dir(&wal_path).unwrap().count(), 0);
+	}
+
+	#[test]
+	fn open_read_only() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 10, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		mem::drop(db);
+		let wal_before = std::fs::read(tempdir.path().join("wal").join("0")).unwrap();
+
+		// when
+		let db = Database::builder()
+			.read_only(true)
+			.open(tempdir.path())
+			.unwrap();
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 2), 10, &mut buf).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		let write_result = t.write(page_id!(1, 2), 10, &[4, 5, 6]);
+		mem::drop(t);
+		db.close().unwrap();
+
+		// then
+		assert_eq!(buf, [1, 2, 3]);
+		assert!(write_result.is_err());
+		let wal_after = std::fs::read(tempdir.path().join("wal").join("0")).unwrap();
+		assert_eq!(wal_after, wal_before);
+	}
+
+	#[test]
+	fn fail_to_open_missing_database() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let path = tempdir.path().join("db");
+
+		// when
+		let result = Database::builder().create_if_missing(false).open(&path);
+		let read_only_result = Database::builder().read_only(true).open(&path);
+
+		// then
+		let error = result.err().unwrap();
+		assert!(error.is_not_found());
+		assert_eq!(error.kind(), ErrorKind::NotFound);
+		assert_eq!(error.kind().code(), "not_found");
+		assert!(read_only_result.err().unwrap().is_not_found());
+		assert!(!path.join("wal").exists());
+	}
+
+	#[test]
+	fn lock_database() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder().lock(true);
+		let db = builder.clone().open(tempdir.path()).unwrap();
+
+		// when
+		let second = builder.clone().open(tempdir.path());
+		let read_only = builder.clone().read_only(true).open(tempdir.path());
+		db.close().unwrap();
+		let first_reader = builder.clone().read_only(true).open(tempdir.path());
+		let second_reader = builder.clone().read_only(true).open(tempdir.path());
+		let writer = builder.open(tempdir.path());
+
+		// then
+		assert!(second.err().unwrap().is_locked());
+		assert!(read_only.err().unwrap().is_locked());
+		assert!(first_reader.is_ok());
+		assert!(second_reader.is_ok());
+		assert!(writer.err().unwrap().is_locked());
+	}
+
+	#[test]
+	fn share_database_with_readers() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder().lock(true).allow_readers(true);
+		let writer = builder.clone().open(tempdir.path()).unwrap();
+		let mut t = writer.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.commit().unwrap();
+		writer.checkpoint().unwrap();
+		let reader = builder
+			.clone()
+			.read_only(true)
+			.open(tempdir.path())
+			.unwrap();
+
+		// when
+		let second_writer = builder.clone().open(tempdir.path());
+		let exclusive_writer = Database::builder().lock(true).open(tempdir.path());
+		let mut buf = [0; 1];
+		reader.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		let mut t = writer.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[2]).unwrap();
+		t.write(page_id!(1, 2), 0, &[3]).unwrap();
+		t.commit().unwrap();
+		writer.checkpoint().unwrap();
+
+		// then
+		assert!(second_writer.err().unwrap().is_locked());
+		assert!(exclusive_writer.err().unwrap().is_locked());
+		assert_eq!(buf, [1]);
+		reader.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1]);
+		assert!(reader
+			.read(page_id!(1, 2), 0, &mut buf)
+			.err()
+			.unwrap()
+			.is_stale());
+		assert!(reader.is_stale().unwrap());
+		assert!(!writer.is_stale().unwrap());
+
+		let reader = reader.refresh().unwrap();
+		assert!(!reader.is_stale().unwrap());
+		reader.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [2]);
+		reader.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [3]);
+		assert!(writer.refresh().is_err());
+	}
+
+	#[test]
+	fn open_independent_databases() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let first_path = tempdir.path().join("first");
+		let second_path = tempdir.path().join("second");
+		let first = Database::builder()
+			.lock(true)
+			.background_threads(Some(1))
+			.open(&first_path)
+			.unwrap();
+		let second = Database::builder()
+			.lock(true)
+			.page_cache_size(64 * PAGE_SIZE)
+			.encryption_key(Some(EncryptionKey::new([1; 32])))
+			.background_threads(Some(2))
+			.open(&second_path)
+			.unwrap();
+
+		// when
+		let mut t = first.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.commit().unwrap();
+		let mut t = second.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[2]).unwrap();
+		t.commit().unwrap();
+		first.close().unwrap();
+		let mut t = second.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[3]).unwrap();
+		t.commit().unwrap();
+		second.checkpoint().unwrap();
+
+		// then
+		let mut buf = [0; 1];
+		second.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [2]);
+		assert_eq!(second.stats().unwrap().commits, 2);
+		let first = Database::builder().lock(true).open(&first_path).unwrap();
+		first.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1]);
+		first.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+		assert_eq!(first.stats().unwrap().commits, 0);
+		assert!(Database::builder()
+			.background_threads(Some(0))
+			.open(tempdir.path().join("third"))
+			.is_err());
+	}
+
+	#[test]
+	fn notify_listeners() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let commit_events = Arc::clone(&events);
+		db.on_commit(move |id, pages| {
+			commit_events
+				.lock()
+				.push(("commit", Some(id), pages.iter().copied().collect()))
+		});
+		let rollback_events = Arc::clone(&events);
+		db.on_rollback(move |id, pages| {
+			rollback_events
+				.lock()
+				.push(("rollback", Some(id), pages.iter().copied().collect()))
+		});
+		let checkpoint_events = Arc::clone(&events);
+		db.on_checkpoint(move |trigger| {
+			assert_eq!(trigger, CheckpointTrigger::Manual);
+			checkpoint_events
+				.lock()
+				.push(("checkpoint", None, Vec::new()))
+		});
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		let committed_id = t.id();
+		t.write(page_id!(1, 2), 0, &[1]).unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.commit().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		let aborted_id = t.id();
+		t.write(page_id!(1, 3), 0, &[1]).unwrap();
+		t.abort().unwrap();
+		db.checkpoint().unwrap();
+
+		// then
+		assert_eq!(
+			*events.lock(),
+			[
+				(
+					"commit",
+					Some(committed_id),
+					vec![page_id!(1, 1), page_id!(1, 2)]
+				),
+				("rollback", Some(aborted_id), vec![page_id!(1, 3)]),
+				("checkpoint", None, Vec::new()),
+			]
+		);
+	}
+
+	#[test]
+	fn encrypted_database() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let key = EncryptionKey::new([1; 32]);
+		let data = [0xab; 64];
+		let db = Database::builder()
+			.encryption_key(Some(key.clone()))
+			.open(tempdir.path())
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 10, &data).unwrap();
+		t.commit().unwrap();
+		db.checkpoint().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 3), 10, &data).unwrap();
+		t.commit().unwrap();
+		db.close().unwrap();
+
+		// when
+		let without_key = Database::open(tempdir.path());
+		let wrong_key = Database::builder()
+			.encryption_key(Some(EncryptionKey::new([2; 32])))
+			.open(tempdir.path());
+		let db = Database::builder()
+			.encryption_key(Some(key))
+			.open(tempdir.path())
+			.unwrap();
+		let mut buf = [0; 64];
+		db.read(page_id!(1, 3), 10, &mut buf).unwrap();
+
+		// then
+		assert!(without_key.is_err_and(|err| err.is_wrong_key()));
+		assert!(wrong_key.is_err_and(|err| err.is_wrong_key()));
+		assert_eq!(buf, data);
+		for dir in ["segments", "wal"] {
+			for entry in std::fs::read_dir(tempdir.path().join(dir)).unwrap() {
+				let content = std::fs::read(entry.unwrap().path()).unwrap();
+				assert!(!content.windows(data.len()).any(|window| window == data));
+			}
+		}
+	}
+
+	#[test]
+	fn throttle_writes_while_dirty() {
+		// given
+		let db = Database::builder()
+			.page_cache_size(64 * PAGE_SIZE)
+			.throttle_dirty_ratio(Some(0.01))
+			.throttle_max_wait(Duration::ZERO)
+			.open_scratch()
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.write(page_id!(1, 2), 0, &[2]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		let busy = t.write(page_id!(1, 3), 0, &[3]);
+		t.abort().unwrap();
+		db.flush().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		let caught_up = t.write(page_id!(1, 3), 0, &[3]);
+		t.commit().unwrap();
+
+		// then
+		assert!(busy.is_err_and(|err| err.is_busy()));
+		assert!(caught_up.is_ok());
+		assert_eq!(db.stats().unwrap().throttled_writes, 1);
+	}
+
+	#[test]
+	fn spill_and_limit_large_transactions() {
+		// given
+		let db = Database::builder()
+			.page_cache_size(16 * PAGE_SIZE)
+			.max_transaction_pages(0.2)
+			.max_transaction_size(Some(4 * Database::PAGE_SIZE + 100))
+			.open_in_memory()
+			.unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		for page_num in 1..=4 {
+			t.write(page_id!(1, page_num), 0, &[page_num as u8])
+				.unwrap();
+		}
+		let size = t.size();
+		let too_large = t.write(page_id!(1, 5), 0, &[5]);
+		t.commit().unwrap();
+
+		// then
+		assert!(size.cached <= 3 * Database::PAGE_SIZE);
+		assert!(size.spilled >= Database::PAGE_SIZE);
+		assert!(too_large.err().unwrap().is_transaction_too_large());
+		let mut buf = [0; 1];
+		for page_num in 1..=4 {
+			db.read(page_id!(1, page_num), 0, &mut buf).unwrap();
+			assert_eq!(buf, [page_num as u8]);
+		}
+		db.read(page_id!(1, 5), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+	}
+
+	#[test]
+	fn clear_unlogged_segments_on_open() {
+		// given
+		let db = Database::builder()
+			.unlogged_segments(2..=3)
+			.open_in_memory()
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.write(page_id!(2, 1), 0, &[2]).unwrap();
+		t.commit().unwrap();
+		db.checkpoint().unwrap();
+		let wal_bytes = db.stats().unwrap().wal_bytes_written;
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(3, 1), 0, &[3; 1000]).unwrap();
+		t.commit().unwrap();
+		let unlogged_wal_bytes = db.stats().unwrap().wal_bytes_written - wal_bytes;
+		let mut buf = [0; 1];
+		db.read(page_id!(3, 1), 0, &mut buf).unwrap();
+		let db = db.simulate_crash().unwrap();
+
+		// then
+		assert_eq!(buf, [3]);
+		assert!(unlogged_wal_bytes < 1000);
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1]);
+		db.read(page_id!(2, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+		db.read(page_id!(3, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+	}
+
+	#[test]
+	fn clear_unlogged_segments_on_reopen() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder().unlogged_segments(2..=2);
+		let db = builder.clone().open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.write(page_id!(2, 1), 0, &[2]).unwrap();
+		t.commit().unwrap();
+		db.close().unwrap();
+		// Without WAL files, opening doesn't recover anything.
+		fs::remove_dir_all(tempdir.path().join(DatabaseFolder::WAL_DIR_NAME)).unwrap();
+
+		// when
+		let db = builder.open(tempdir.path()).unwrap();
+
+		// then
+		let mut buf = [0; 1];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1]);
+		db.read(page_id!(2, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+	}
+
+	#[test]
+	fn enforce_quota_across_restarts() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let builder = Database::builder().max_segments(Some(2));
+		let db = builder.clone().open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.write(page_id!(2, 1), 0, &[2]).unwrap();
+		t.commit().unwrap();
+		db.close().unwrap();
+
+		// when
+		let db = builder.open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		let in_quota = t.write(page_id!(1, 2), 0, &[3]);
+		let exceeded = t.write(page_id!(3, 1), 0, &[4]);
+
+		// then
+		assert!(in_quota.is_ok());
+		assert!(exceeded.is_err_and(|err| err.is_quota_exceeded()));
+		t.commit().unwrap();
+		assert!(Database::builder()
+			.max_size(Some(1))
+			.open(tempdir.path())
+			.is_err());
+	}
+
+	#[test]
+	fn report_memory_usage() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let before = db.memory_usage();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[1; 100]).unwrap();
+		let during = db.memory_usage();
+		t.commit().unwrap();
+		let after = db.memory_usage();
+
+		// then
+		assert_eq!(before.write_sets, 0);
+		assert!(during.write_sets >= 100);
+		assert!(during.page_cache >= Database::PAGE_SIZE);
+		assert!(during.page_versions >= Database::PAGE_SIZE);
+		assert!(during.total() > before.total());
+		assert_eq!(after.write_sets, 0);
+		assert!(after.wal_buffers > 0);
+	}
+
+	#[test]
+	fn collect_stats() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1; 4]).unwrap();
+		t.write(page_id!(1, 2), 0, &[1; 4]).unwrap();
+		t.write(page_id!(2, 1), 0, &[1; 4]).unwrap();
+		t.commit().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 3), 0, &[2; 4]).unwrap();
+		t.abort().unwrap();
+		db.read(page_id!(1, 1), 0, &mut [0; 4]).unwrap();
+		let stats = db.stats().unwrap();
+
+		// then
+		assert_eq!(stats.commits, 1);
+		assert_eq!(stats.aborts, 1);
+		assert!(stats.cache.hits > 0);
+		assert_eq!(stats.cache.misses, 4);
+		assert!(stats.cache.peak_dirty_pages >= 3);
+		assert!(stats.wal_bytes_written > 0);
+		assert_eq!(stats.segment_pages[&1], 3);
+		assert_eq!(stats.segment_pages[&2], 1);
+		assert_eq!(stats.memory, db.memory_usage());
+	}
+
+	#[test]
+	fn reject_too_small_page_cache() {
+		// given
+		let size: ByteSize = "1KiB".parse().unwrap();
+
+		// when
+		let result = Database::builder()
+			.page_cache_size(size.into())
+			.open_scratch();
+
+		// then
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn stop_accepting_transactions_after_task_panic() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1; 4]).unwrap();
+
+		// when
+		let pool = ThreadPool::new().unwrap();
+		db.tasks
+			.spawn(&pool, "checkpoint", async { panic!("Injected panic") });
+		while db.task_panic().is_none() {
+			thread::yield_now();
+		}
+		let begin = db.begin_transaction();
+
+		// then
+		let panic = db.task_panic().unwrap();
+		assert_eq!(panic.task(), "checkpoint");
+		assert_eq!(panic.message(), "Injected panic");
+		assert_eq!(begin.err().unwrap().task_panic(), Some(&panic));
+		t.commit().unwrap();
+		let mut buf = [0; 4];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1; 4]);
+		db.close().unwrap();
+	}
+
+	#[test]
+	fn stream_committed_transactions_from_wal() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut stream = db.wal_stream(db.wal_position().unwrap()).unwrap();
+
+		// when
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 10, &[1, 2]).unwrap();
+		t.commit().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 2), 0, &[3]).unwrap();
+		t.abort().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 3), 0, &[4]).unwrap();
+		t.commit().unwrap();
+		let records: Vec<WalRecord> = iter::from_fn(|| stream.try_next().unwrap()).collect();
+
+		// then
+		let [WalRecord::Write {
+			page_id: first_page,
+			offset: 10,
+			data: first_data,
+			..
+		}, WalRecord::Commit {
+			position: first_commit,
+			..
+		}, WalRecord::Write {
+			page_id: second_page,
+			data: second_data,
+			..
+		}, WalRecord::Commit { .. }] = &records[..]
+		else {
+			panic!("Unexpected records: {records:?}");
+		};
+		assert_eq!(*first_page, page_id!(1, 1));
+		assert_eq!(first_data, &[1, 2]);
+		assert_eq!(*second_page, page_id!(1, 3));
+		assert_eq!(second_data, &[4]);
+
+		let resumed = db.wal_stream(*first_commit).unwrap();
+		db.close().unwrap();
+		let resumed: Vec<WalRecord> = resumed.map(Result::unwrap).collect();
+		assert_eq!(resumed, records[2..]);
+	}
+
+	#[test]
+	fn list_wal_transactions() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1, 2]).unwrap();
+		t.write(page_id!(1, 2), 0, &[3]).unwrap();
+		t.commit().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 3), 0, &[4]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let transactions = db.wal_transactions().unwrap();
+		db.close().unwrap();
+
+		// then
+		let summaries: Vec<(usize, usize, WalTransactionStatus)> = transactions
+			.iter()
+			.map(|transaction| {
+				(
+					transaction.num_writes,
+					transaction.num_bytes,
+					transaction.status,
+				)
+			})
+			.collect();
+		assert_eq!(
+			summaries,
+			vec![
+				(2, 3, WalTransactionStatus::Committed),
+				(1, 1, WalTransactionStatus::Committed),
+			]
+		);
+		assert!(transactions[0].last < transactions[1].first);
+	}
+
+	#[test]
+	fn compact_wal_after_stream_is_dropped() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let stream = db.wal_stream(db.wal_position().unwrap()).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+		db.checkpoint().unwrap();
+
+		// when
+		let num_kept = db.compact_wal().unwrap();
+		mem::drop(stream);
+		let num_deleted = db.compact_wal().unwrap();
+		let num_deleted_again = db.compact_wal().unwrap();
+
+		// then
+		assert_eq!(num_kept, 0);
+		assert!(num_deleted > 0);
+		assert_eq!(num_deleted_again, 0);
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+	}
+
+	#[test]
+	fn warm_cache_after_restart() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db_path = tempdir.path().join("db");
+		let state_path = tempdir.path().join("cache");
+		let db = Database::open(&db_path).unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.write(page_id!(1, 2), 0, &[2]).unwrap();
+		t.write(page_id!(2, 1), 0, &[3]).unwrap();
+		t.commit().unwrap();
+		db.save_cache_state(&state_path).unwrap();
+		db.close().unwrap();
+		let db = Database::open(&db_path).unwrap();
+
+		// when
+		let num_missing = db.warm_cache_from(tempdir.path().join("missing")).unwrap();
+		let num_warmed = db.warm_cache_from(&state_path).unwrap();
+
+		// then
+		assert_eq!(num_missing, 0);
+		assert_eq!(num_warmed, 3);
+		let mut buf = [0];
+		db.read(page_id!(2, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [3]);
+	}
+
+	#[test]
+	fn apply_wal_records_to_follower() {
+		// given
+		let leader = Database::open_in_memory().unwrap();
+		let follower = Database::builder().follower(true).open_in_memory().unwrap();
+		let mut stream = leader.wal_stream(leader.wal_position().unwrap()).unwrap();
+		let mut t = leader.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1, 2, 3]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut position = None;
+		while let Some(record) = stream.try_next().unwrap() {
+			if let WalRecord::Commit { position: p, .. } = record {
+				position = Some(p);
+			}
+			follower.apply_wal_record(record).unwrap();
+		}
+
+		// then
+		let mut buf = [0; 3];
+		follower.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [1, 2, 3]);
+		assert_eq!(follower.replicated_position(), position);
+		let mut t = follower.begin_transaction().unwrap();
+		assert!(t.write(page_id!(1, 1), 0, &[4]).is_err());
+		assert!(leader
+			.apply_wal_record(WalRecord::Commit {
+				transaction_id: 0,
+				position: position.unwrap(),
+			})
+			.is_err());
+	}
+
+	#[test]
+	fn redo_custom_records() {
+		// given
+		let handler = Arc::new(RecordingHandler::default());
+		let builder = Database::builder()
+			.wal_record_handler(3, Arc::clone(&handler) as Arc<dyn WalRecordHandler>);
+		let leader = builder.clone().open_in_memory().unwrap();
+		let follower = builder.follower(true).open_in_memory().unwrap();
+		let mut stream = leader.wal_stream(leader.wal_position().unwrap()).unwrap();
+		let mut t = leader.begin_transaction().unwrap();
+		t.log_record(3, 1, b"committed").unwrap();
+		t.commit().unwrap();
+		let mut t = leader.begin_transaction().unwrap();
+		t.log_record(3, 1, b"aborted").unwrap();
+		t.abort().unwrap();
+
+		// when
+		while let Some(record) = stream.try_next().unwrap() {
+			follower.apply_wal_record(record).unwrap();
+		}
+		mem::drop(stream);
+		let leader = leader.simulate_crash().unwrap();
+
+		// then
+		let record = |transaction_id, data: &[u8]| CustomRecord {
+			transaction_id,
+			record_type: 3,
+			version: 1,
+			data: data.to_vec(),
+		};
+		assert_eq!(
+			*handler.calls.lock(),
+			[
+				HandlerCall::Undo(record(1, b"aborted")),
+				HandlerCall::Redo(record(0, b"committed")),
+				HandlerCall::Redo(record(0, b"committed")),
+			]
+		);
+		let mut t = leader.begin_transaction().unwrap();
+		assert!(t.log_record(4, 1, b"unknown").is_err());
+	}
+
+	#[derive(Debug)]
+	struct Counter(u32);
+
+	impl PageType for Counter {
+		const TAG: NonZeroU8 = NonZeroU8::new(1).unwrap();
+
+		fn decode(bytes: &[u8]) -> Self {
+			Self(u32::from_le_bytes(bytes[..4].try_into().unwrap()))
+		}
+
+		fn encode(&self, bytes: &mut [u8]) {
+			bytes[..4].copy_from_slice(&self.0.to_le_bytes());
+		}
+	}
+
+	#[derive(Debug)]
+	struct Label;
+
+	impl PageType for Label {
+		const TAG: NonZeroU8 = NonZeroU8::new(2).unwrap();
+
+		fn decode(_bytes: &[u8]) -> Self {
+			Self
+		}
+
+		fn encode(&self, _bytes: &mut [u8]) {}
+	}
+
+	#[test]
+	fn read_and_write_typed_pages() {
+		// given
+		let db = Database::open_in_memory().unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write_typed(page_id!(1, 1), &Counter(42)).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let t = db.begin_transaction().unwrap();
+		let counter = t.read_typed::<Counter>(page_id!(1, 1)).unwrap();
+		let wrong_type = t.read_typed::<Label>(page_id!(1, 1)).unwrap_err();
+		let unwritten = t.read_typed::<Counter>(page_id!(1, 2)).unwrap_err();
+
+		// then
+		assert_eq!(counter.0, 42);
+		assert_eq!(
+			wrong_type.page_type_mismatch(),
+			Some(&PageTypeMismatch {
+				page_id: page_id!(1, 1),
+				expected: Label::TAG,
+				found: 1,
+			})
+		);
+		assert_eq!(unwritten.page_type_mismatch().unwrap().found, 0);
+		t.abort().unwrap();
+	}
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use crate::page_store::{AccessStats, PageId, PageStorageApi, TransactionApi};
+
+use super::{catalog::Catalog, DatabaseError};
+
+/// Runs an operation on the tree rooted at `root`, and attributes the pages
+/// it accesses to the tree, so that they show up in [`tree_access_stats`].
+///
+/// Operations on other trees that run as part of it are attributed to those
+/// trees instead.
+pub(super) fn tagged<T: TransactionApi, R>(
+	t: &mut T,
+	root: PageId,
+	operation: impl FnOnce(&mut T) -> Result<R, DatabaseError>,
+) -> Result<R, DatabaseError> {
+	t.count_operation(root);
+	let outer = t.tag_accesses(Some(root));
+	let result = operation(t);
+	t.tag_accesses(outer);
+	result
+}
+
+/// Returns how many pages the operations on each tree of the catalog
+/// accessed since the storage was opened, and how many of them had to be
+/// read from storage, by the name of the tree.
+///
+/// Trees with a high read amplification, or that miss the cache often, are
+/// the ones where a different layout, like a clustered tree or an index with
+/// shorter keys, would pay off. The accesses are attributed to the root of a
+/// tree, so those from before a tree was rebuilt under a new root are not
+/// included.
+pub(super) fn tree_access_stats<S: PageStorageApi>(
+	catalog: &Catalog,
+	storage: &S,
+) -> Result<BTreeMap<String, AccessStats>, DatabaseError> {
+	let mut t = storage.read_only_transaction()?;
+	let entries = catalog.entries(&mut t)?;
+	t.commit()?;
+	let mut stats = storage.access_stats();
+	Ok(entries
+		.into_iter()
+		.filter_map(|(name, entry)| Some((name, stats.remove(&entry.root)?)))
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::{
+			b_tree::BTree, catalog::TreeKind, page_alloc::PageAllocator, var_b_tree::KeyOrder,
+			DbPointer,
+		},
+		page_store::test_helpers::{page_id, temp_storage},
+	};
+
+	use super::*;
+
+	#[test]
+	fn attribute_page_accesses_to_trees() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let catalog = Catalog::default();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		catalog.init(&mut t).unwrap();
+		let small = BTree::new(
+			catalog
+				.create_tree(&mut t, "small", TreeKind::BTree, KeyOrder::Bytes)
+				.unwrap(),
+		);
+		let large = BTree::new(
+			catalog
+				.create_tree(&mut t, "large", TreeKind::BTree, KeyOrder::Bytes)
+				.unwrap(),
+		);
+		catalog
+			.create_tree(&mut t, "unused", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		let pointer = DbPointer::new(page_id!(9, 1), 0);
+		small.insert(&mut t, 1, pointer).unwrap();
+		for key in 0..20_000 {
+			large.insert(&mut t, key, pointer).unwrap();
+		}
+		t.commit().unwrap();
+		let before = tree_access_stats(&catalog, &storage).unwrap();
+
+		// when
+		let mut t = storage.read_only_transaction().unwrap();
+		for key in 0..100 {
+			small.search(&mut t, key).unwrap();
+			large.search(&mut t, key).unwrap();
+		}
+		t.commit().unwrap();
+
+		// then
+		let after = tree_access_stats(&catalog, &storage).unwrap();
+		assert_eq!(after.keys().collect::<Vec<_>>(), vec!["large", "small"]);
+		let small_searches = after["small"].operations - before["small"].operations;
+		let small_accesses = after["small"].page_accesses - before["small"].page_accesses;
+		let large_searches = after["large"].operations - before["large"].operations;
+		let large_accesses = after["large"].page_accesses - before["large"].page_accesses;
+		assert_eq!(small_searches, 100);
+		assert_eq!(large_searches, 100);
+		// The small tree is a single leaf, the large one has internal nodes.
+		assert_eq!(small_accesses, 100);
+		assert!(large_accesses >= 200);
+		assert!(after["large"].read_amplification() > after["small"].read_amplification());
+		assert!(after["large"].storage_reads <= after["large"].page_accesses);
+	}
+}
@@ -0,0 +1,1180 @@
+use std::{
+	mem,
+	ops::{Bound, RangeBounds},
+	vec,
+};
+
+use crate::page_store::{PageId, TransactionApi};
+
+use super::{
+	amplification::tagged,
+	page_alloc::PageAllocator,
+	pages::{BTreeNode, BTreePage},
+	scan_token::ScanToken,
+	split_policy::SplitPolicy,
+	DatabaseError, DbPointer, Leaf,
+};
+
+/// The separator key and page of the new right sibling of a split node.
+type Split = (u64, PageId);
+
+/// A B+ tree index that maps keys to [`DbPointer`]s.
+///
+/// The root of the tree always stays on the same page, so that it can be
+/// referenced from elsewhere; splitting or collapsing the root moves its
+/// contents to a different page instead.
+pub(super) struct BTree {
+	root: PageId,
+	id: u64,
+	leaf_capacity: usize,
+	internal_capacity: usize,
+	split_policy: SplitPolicy,
+}
+
+impl BTree {
+	pub fn new(root: PageId) -> Self {
+		Self {
+			root,
+			id: 0,
+			leaf_capacity: BTreePage::<()>::LEAF_CAPACITY,
+			internal_capacity: BTreePage::<()>::INTERNAL_CAPACITY,
+			split_policy: SplitPolicy::default(),
+		}
+	}
+
+	/// Splits overflowing nodes according to `split_policy`. The policy only
+	/// affects inserts; it isn't stored in the tree, so it can differ between
+	/// accesses.
+	pub fn with_split_policy(mut self, split_policy: SplitPolicy) -> Self {
+		self.split_policy = split_policy;
+		self
+	}
+
+	/// Sets the id that the [`ScanToken`]s of range scans over the tree refer
+	/// to it by. Unlike the root, it has to stay the same for as long as
+	/// tokens are kept around. Trees have the id 0 unless one is given.
+	pub fn with_id(mut self, id: u64) -> Self {
+		self.id = id;
+		self
+	}
+
+	/// The number of entries that fit in a leaf.
+	pub fn leaf_capacity(&self) -> usize {
+		self.leaf_capacity
+	}
+
+	pub fn init(&self, t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
+		Self::write_node(t, self.root, &BTreeNode::Leaf(Vec::new()))
+	}
+
+	pub fn search(
+		&self,
+		t: &mut impl TransactionApi,
+		key: u64,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		tagged(t, self.root, |t| {
+			let mut page_id = self.root;
+			loop {
+				match Self::read_node(t, page_id)? {
+					BTreeNode::Leaf(entries) => {
+						let Ok(index) = entries.binary_search_by_key(&key, |(key, _)| *key) else {
+							return Ok(None);
+						};
+						return Ok(Some(entries[index].1));
+					}
+					BTreeNode::Internal { keys, children } => {
+						page_id = children[Self::child_index(&keys, key)];
+					}
+				}
+			}
+		})
+	}
+
+	/// Returns the entries with keys in `range`, in ascending key order.
+	pub fn range<'t, T: TransactionApi>(
+		&self,
+		t: &'t mut T,
+		range: impl RangeBounds<u64>,
+	) -> Range<'t, T> {
+		Range {
+			t,
+			tree: self.root,
+			tree_id: self.id,
+			root: Some(self.root),
+			start: range.start_bound().cloned(),
+			end: range.end_bound().cloned(),
+			stack: Vec::new(),
+			entries: Vec::new().into_iter(),
+			read_ahead: 0,
+			num_prefetched: 0,
+			exhausted: false,
+		}
+	}
+
+	/// Continues the range scan that `token` was taken from, after the last
+	/// entry it returned. If the scan read from a snapshot, `t` has to read
+	/// as of the same commit, see
+	/// [`Transaction::with_snapshot_reads_at`](crate::page_store::Transaction::with_snapshot_reads_at);
+	/// otherwise, the scan continues with the current entries of the tree.
+	pub fn resume<'t, T: TransactionApi>(
+		&self,
+		t: &'t mut T,
+		token: &ScanToken,
+	) -> Result<Range<'t, T>, DatabaseError> {
+		if token.tree != self.id {
+			return Err(DatabaseError::ScanTokenMismatch);
+		}
+		if let Some(expected) = token.seq {
+			let actual = t.snapshot_seq();
+			if actual != Some(expected) {
+				return Err(DatabaseError::ScanSnapshotMismatch { expected, actual });
+			}
+		}
+		Ok(self.range(t, (token.start, token.end)))
+	}
+
+	/// Returns the leaves of the tree in key order, each with its page and
+	/// entries.
+	pub fn leaves(&self, t: &mut impl TransactionApi) -> Result<Vec<Leaf<u64>>, DatabaseError> {
+		let mut leaves = Vec::new();
+		let mut stack = vec![self.root];
+		while let Some(page_id) = stack.pop() {
+			match Self::read_node(t, page_id)? {
+				BTreeNode::Leaf(entries) => leaves.push((page_id, entries)),
+				BTreeNode::Internal { children, .. } => stack.extend(children.into_iter().rev()),
+			}
+		}
+		Ok(leaves)
+	}
+
+	/// Returns every page of the tree, starting with the root.
+	pub fn pages(&self, t: &mut impl TransactionApi) -> Result<Vec<PageId>, DatabaseError> {
+		let mut pages = vec![self.root];
+		let mut index = 0;
+		while let Some(page_id) = pages.get(index).copied() {
+			if let BTreeNode::Internal { children, .. } = Self::read_node(t, page_id)? {
+				pages.extend(children);
+			}
+			index += 1;
+		}
+		Ok(pages)
+	}
+
+	/// Points the parent of the node at `from` to `to` instead, so that the
+	/// node can be moved there, and returns whether the tree has a node at
+	/// `from`. The root can't be moved, since it stays on the same page.
+	pub fn relocate_page(
+		&self,
+		t: &mut impl TransactionApi,
+		from: PageId,
+		to: PageId,
+	) -> Result<bool, DatabaseError> {
+		let mut stack = vec![self.root];
+		while let Some(page_id) = stack.pop() {
+			let BTreeNode::Internal { keys, mut children } = Self::read_node(t, page_id)? else {
+				continue;
+			};
+			if let Some(index) = children.iter().position(|child| *child == from) {
+				children[index] = to;
+				Self::write_node(t, page_id, &BTreeNode::Internal { keys, children })?;
+				return Ok(true);
+			}
+			stack.extend(children);
+		}
+		Ok(false)
+	}
+
+	/// Inserts a value for `key`, and returns the value it replaced, if any.
+	pub fn insert(
+		&self,
+		t: &mut impl TransactionApi,
+		key: u64,
+		value: DbPointer,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		tagged(t, self.root, |t| {
+			let (replaced, split) = self.insert_into(t, self.root, key, value)?;
+			if let Some((separator, right)) = split {
+				// The root keeps its page, so its left half has to move.
+				let left = PageAllocator::alloc(t)?;
+				let left_node = Self::read_node(t, self.root)?;
+				Self::write_node(t, left, &left_node)?;
+				Self::write_node(
+					t,
+					self.root,
+					&BTreeNode::Internal {
+						keys: vec![separator],
+						children: vec![left, right],
+					},
+				)?;
+			}
+			Ok(replaced)
+		})
+	}
+
+	/// Fills the tree, which has to be empty, with `entries` in ascending key
+	/// order. Instead of inserting the entries one by one, the nodes are
+	/// built bottom-up, level by level, and filled evenly, so that every page
+	/// is written only once.
+	pub fn bulk_load(
+		&self,
+		t: &mut impl TransactionApi,
+		entries: impl IntoIterator<Item = (u64, DbPointer)>,
+	) -> Result<(), DatabaseError> {
+		if Self::read_node(t, self.root)? != BTreeNode::Leaf(Vec::new()) {
+			return Err(DatabaseError::TreeNotEmpty);
+		}
+		let entries: Vec<(u64, DbPointer)> = entries.into_iter().collect();
+		if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+			return Err(DatabaseError::UnsortedKeys {
+				prev: pair[0].0,
+				key: pair[1].0,
+			});
+		}
+
+		let mut leaves = Self::even_groups(entries, self.leaf_capacity);
+		if leaves.len() <= 1 {
+			let entries = leaves.pop().unwrap_or_default();
+			return Self::write_node(t, self.root, &BTreeNode::Leaf(entries));
+		}
+		// The first key and the page of each node of the current level.
+		let mut level: Vec<(u64, PageId)> = Vec::with_capacity(leaves.len());
+		for entries in leaves {
+			let page_id = PageAllocator::alloc(t)?;
+			level.push((entries[0].0, page_id));
+			Self::write_node(t, page_id, &BTreeNode::Leaf(entries))?;
+		}
+		loop {
+			let nodes = Self::even_groups(level, self.internal_capacity + 1);
+			let is_root = nodes.len() == 1;
+			level = Vec::with_capacity(nodes.len());
+			for node in nodes {
+				let first_key = node[0].0;
+				let (mut keys, children): (Vec<u64>, Vec<PageId>) = node.into_iter().unzip();
+				keys.remove(0);
+				let page_id = if is_root {
+					self.root
+				} else {
+					PageAllocator::alloc(t)?
+				};
+				Self::write_node(t, page_id, &BTreeNode::Internal { keys, children })?;
+				level.push((first_key, page_id));
+			}
+			if is_root {
+				return Ok(());
+			}
+		}
+	}
+
+	/// Removes `key` from the tree, and returns the value it had, if any.
+	pub fn delete(
+		&self,
+		t: &mut impl TransactionApi,
+		key: u64,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		tagged(t, self.root, |t| {
+			let removed = self.delete_from(t, self.root, key)?;
+			if let BTreeNode::Internal { keys, children } = Self::read_node(t, self.root)? {
+				if keys.is_empty() {
+					let child_node = Self::read_node(t, children[0])?;
+					Self::write_node(t, self.root, &child_node)?;
+					PageAllocator::free(t, children[0])?;
+				}
+			}
+			Ok(removed)
+		})
+	}
+
+	/// Inserts into the subtree at `page_id`. If the node had to be split,
+	/// returns the first key of the new right sibling along with its page.
+	fn insert_into(
+		&self,
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		key: u64,
+		value: DbPointer,
+	) -> Result<(Option<DbPointer>, Option<Split>), DatabaseError> {
+		match Self::read_node(t, page_id)? {
+			BTreeNode::Leaf(mut entries) => {
+				let index = match entries.binary_search_by_key(&key, |(key, _)| *key) {
+					Ok(index) => {
+						let replaced = entries[index].1;
+						entries[index].1 = value;
+						Self::write_node(t, page_id, &BTreeNode::Leaf(entries))?;
+						return Ok((Some(replaced), None));
+					}
+					Err(index) => index,
+				};
+				entries.insert(index, (key, value));
+				if entries.len() <= self.leaf_capacity {
+					Self::write_node(t, page_id, &BTreeNode::Leaf(entries))?;
+					return Ok((None, None));
+				}
+
+				let split_point = self.split_policy.leaf_split_point(entries.len(), index);
+				let right_entries = entries.split_off(split_point);
+				let separator = right_entries[0].0;
+				let right = PageAllocator::alloc(t)?;
+				Self::write_node(t, right, &BTreeNode::Leaf(right_entries))?;
+				Self::write_node(t, page_id, &BTreeNode::Leaf(entries))?;
+				Ok((None, Some((separator, right))))
+			}
+			BTreeNode::Internal {
+				mut keys,
+				mut children,
+			} => {
+				let index = Self::child_index(&keys, key);
+				let (replaced, split) = self.insert_into(t, children[index], key, value)?;
+				let Some((separator, new_child)) = split else {
+					return Ok((replaced, None));
+				};
+				keys.insert(index, separator);
+				children.insert(index + 1, new_child);
+				if keys.len() <= self.internal_capacity {
+					Self::write_node(t, page_id, &BTreeNode::Internal { keys, children })?;
+					return Ok((replaced, None));
+				}
+
+				let mid = self.split_policy.internal_split_point(keys.len(), index);
+				let right_keys = keys.split_off(mid + 1);
+				let separator = keys.pop().unwrap();
+				let right_children = children.split_off(mid + 1);
+				let right = PageAllocator::alloc(t)?;
+				Self::write_node(
+					t,
+					right,
+					&BTreeNode::Internal {
+						keys: right_keys,
+						children: right_children,
+					},
+				)?;
+				Self::write_node(t, page_id, &BTreeNode::Internal { keys, children })?;
+				Ok((replaced, Some((separator, right))))
+			}
+		}
+	}
+
+	fn delete_from(
+		&self,
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		key: u64,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		match Self::read_node(t, page_id)? {
+			BTreeNode::Leaf(mut entries) => {
+				let Ok(index) = entries.binary_search_by_key(&key, |(key, _)| *key) else {
+					return Ok(None);
+				};
+				let (_, removed) = entries.remove(index);
+				Self::write_node(t, page_id, &BTreeNode::Leaf(entries))?;
+				Ok(Some(removed))
+			}
+			BTreeNode::Internal {
+				mut keys,
+				mut children,
+			} => {
+				let index = Self::child_index(&keys, key);
+				let removed = self.delete_from(t, children[index], key)?;
+				if removed.is_some() && self.rebalance(t, &mut keys, &mut children, index)? {
+					Self::write_node(t, page_id, &BTreeNode::Internal { keys, children })?;
+				}
+				Ok(removed)
+			}
+		}
+	}
+
+	/// Merges the child at `index` with a sibling, or moves entries over from
+	/// the sibling, if the child is less than half full. Returns whether the
+	/// parent's keys or children changed.
+	fn rebalance(
+		&self,
+		t: &mut impl TransactionApi,
+		keys: &mut Vec<u64>,
+		children: &mut Vec<PageId>,
+		index: usize,
+	) -> Result<bool, DatabaseError> {
+		let node = Self::read_node(t, children[index])?;
+		if node.len() >= self.min_len(&node) || children.len() < 2 {
+			return Ok(false);
+		}
+
+		let left_index = if index > 0 { index - 1 } else { index };
+		let (left_id, right_id) = (children[left_index], children[left_index + 1]);
+		t.lock_pages(&[left_id, right_id])?;
+		let left = Self::read_node(t, left_id)?;
+		let right = Self::read_node(t, right_id)?;
+
+		match (left, right) {
+			(BTreeNode::Leaf(mut left), BTreeNode::Leaf(right)) => {
+				left.extend(right);
+				if left.len() <= self.leaf_capacity {
+					Self::write_node(t, left_id, &BTreeNode::Leaf(left))?;
+					PageAllocator::free(t, right_id)?;
+					keys.remove(left_index);
+					children.remove(left_index + 1);
+					return Ok(true);
+				}
+				let right = left.split_off(left.len() / 2);
+				keys[left_index] = right[0].0;
+				Self::write_node(t, left_id, &BTreeNode::Leaf(left))?;
+				Self::write_node(t, right_id, &BTreeNode::Leaf(right))?;
+				Ok(true)
+			}
+			(
+				BTreeNode::Internal {
+					keys: mut left_keys,
+					children: mut left_children,
+				},
+				BTreeNode::Internal {
+					keys: right_keys,
+					children: right_children,
+				},
+			) => {
+				left_keys.push(keys[left_index]);
+				left_keys.extend(right_keys);
+				left_children.extend(right_children);
+				if left_keys.len() <= self.internal_capacity {
+					Self::write_node(
+						t,
+						left_id,
+						&BTreeNode::Internal {
+							keys: left_keys,
+							children: left_children,
+						},
+					)?;
+					PageAllocator::free(t, right_id)?;
+					keys.remove(left_index);
+					children.remove(left_index + 1);
+					return Ok(true);
+				}
+				let mid = left_keys.len() / 2;
+				let right_keys = left_keys.split_off(mid + 1);
+				keys[left_index] = left_keys.pop().unwrap();
+				let right_children = left_children.split_off(mid + 1);
+				Self::write_node(
+					t,
+					left_id,
+					&BTreeNode::Internal {
+						keys: left_keys,
+						children: left_children,
+					},
+				)?;
+				Self::write_node(
+					t,
+					right_id,
+					&BTreeNode::Internal {
+						keys: right_keys,
+						children: right_children,
+					},
+				)?;
+				Ok(true)
+			}
+			_ => Err(DatabaseError::PageFormat(format!(
+				"B-tree siblings {left_id} and {right_id} are not on the same level"
+			))),
+		}
+	}
+
+	/// Splits `items` into as few groups of at most `capacity` items as
+	/// possible, whose sizes differ by at most one. Groups of a bulk-loaded
+	/// tree are thus at least half full, like those of any other tree.
+	fn even_groups<I>(items: Vec<I>, capacity: usize) -> Vec<Vec<I>> {
+		let num_groups = items.len().div_ceil(capacity);
+		let mut items = items.into_iter();
+		(0..num_groups)
+			.map(|index| {
+				let len = items.len().div_ceil(num_groups - index);
+				items.by_ref().take(len).collect()
+			})
+			.collect()
+	}
+
+	fn min_len(&self, node: &BTreeNode) -> usize {
+		match node {
+			BTreeNode::Leaf(..) => self.leaf_capacity / 2,
+			BTreeNode::Internal { .. } => self.internal_capacity / 2,
+		}
+	}
+
+	fn child_index(keys: &[u64], key: u64) -> usize {
+		keys.partition_point(|separator| *separator <= key)
+	}
+
+	fn read_node(t: &mut impl TransactionApi, page_id: PageId) -> Result<BTreeNode, DatabaseError> {
+		BTreePage::new(t.get_page(page_id)?)?.read_node()
+	}
+
+	fn write_node(
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		node: &BTreeNode,
+	) -> Result<(), DatabaseError> {
+		BTreePage::new_unchecked(t.get_page_mut(page_id)?).write_node(node)
+	}
+}
+
+/// An iterator over a range of B-tree entries.
+///
+/// Leaves don't link to their siblings, so the iterator keeps the path from
+/// the root to the current leaf, and continues from the closest ancestor
+/// that has children left once a leaf is exhausted.
+///
+/// With [`Range::read_ahead`], the iterator also learns the upcoming leaves
+/// from their parents, and prefetches them before it reaches them.
+pub(super) struct Range<'t, T: TransactionApi> {
+	t: &'t mut T,
+	/// The root of the tree, which the page accesses are attributed to.
+	tree: PageId,
+	/// The id of the tree, for [`Range::token`].
+	tree_id: u64,
+	/// The root, until the first leaf has been reached.
+	root: Option<PageId>,
+	start: Bound<u64>,
+	end: Bound<u64>,
+	/// The keys and children of each ancestor of the current leaf, along with
+	/// the index of the child that is being visited.
+	stack: Vec<(Vec<u64>, Vec<PageId>, usize)>,
+	entries: vec::IntoIter<(u64, DbPointer)>,
+	/// How many leaves ahead of the current one to prefetch.
+	read_ahead: usize,
+	/// How many of the leaves after the current one were prefetched already.
+	num_prefetched: usize,
+	/// Whether the end of the range was reached.
+	exhausted: bool,
+}
+
+impl<T: TransactionApi> Range<'_, T> {
+	/// Makes the iterator keep up to `distance` leaves ahead of the current
+	/// one prefetched, so that large scans don't wait for storage on every
+	/// leaf. The leaves are learned from the parent of the current leaf and
+	/// the next internal node after it, so fewer may be prefetched near the
+	/// end of a parent.
+	pub fn read_ahead(mut self, distance: usize) -> Self {
+		self.read_ahead = distance;
+		self
+	}
+
+	/// Returns a token to continue the scan after the entries returned so
+	/// far with [`BTree::resume`], or `None` if the end of the range was
+	/// reached.
+	pub fn token(&self) -> Option<ScanToken> {
+		if self.exhausted {
+			return None;
+		}
+		Some(ScanToken {
+			tree: self.tree_id,
+			start: self.start,
+			end: self.end,
+			seq: self.t.snapshot_seq(),
+		})
+	}
+
+	/// Descends to the first leaf that may contain keys in the range.
+	fn seek_start(&mut self, root: PageId) -> Result<(), DatabaseError> {
+		let mut page_id = root;
+		loop {
+			match BTree::read_node(self.t, page_id)? {
+				BTreeNode::Leaf(mut entries) => {
+					let first = entries.partition_point(|(key, _)| match self.start {
+						Bound::Included(start) => *key < start,
+						Bound::Excluded(start) => *key <= start,
+						Bound::Unbounded => false,
+					});
+					entries.drain(..first);
+					self.entries = entries.into_iter();
+					return Ok(());
+				}
+				BTreeNode::Internal { keys, children } => {
+					let index = match self.start {
+						Bound::Included(start) | Bound::Excluded(start) => {
+							BTree::child_index(&keys, start)
+						}
+						Bound::Unbounded => 0,
+					};
+					page_id = children[index];
+					self.stack.push((keys, children, index));
+				}
+			}
+		}
+	}
+
+	/// Moves on to the leftmost leaf after the current one. Returns `false` if
+	/// there is none.
+	fn next_leaf(&mut self) -> Result<bool, DatabaseError> {
+		let mut page_id = loop {
+			let Some((_, children, index)) = self.stack.last_mut() else {
+				return Ok(false);
+			};
+			if *index + 1 < children.len() {
+				*index += 1;
+				break children[*index];
+			}
+			self.stack.pop();
+		};
+		loop {
+			match BTree::read_node(self.t, page_id)? {
+				BTreeNode::Leaf(entries) => {
+					self.entries = entries.into_iter();
+					self.num_prefetched = self.num_prefetched.saturating_sub(1);
+					return Ok(true);
+				}
+				BTreeNode::Internal { keys, children } => {
+					page_id = children[0];
+					self.stack.push((keys, children, 0));
+				}
+			}
+		}
+	}
+
+	/// Returns the next entry, attributing the pages that have to be read for
+	/// it to the tree. The whole range counts as one operation on the tree.
+	fn next_entry(&mut self) -> Result<Option<(u64, DbPointer)>, DatabaseError> {
+		if self.root.is_some() {
+			self.t.count_operation(self.tree);
+		}
+		let outer = self.t.tag_accesses(Some(self.tree));
+		let entry = self.read_next_entry();
+		self.t.tag_accesses(outer);
+		entry
+	}
+
+	fn read_next_entry(&mut self) -> Result<Option<(u64, DbPointer)>, DatabaseError> {
+		if let Some(root) = self.root.take() {
+			self.seek_start(root)?;
+			self.prefetch_leaves()?;
+		}
+		let entry = loop {
+			if let Some(entry) = self.entries.next() {
+				break entry;
+			}
+			if !self.next_leaf()? {
+				self.exhausted = true;
+				return Ok(None);
+			}
+			self.prefetch_leaves()?;
+		};
+		if !self.before_end(entry.0) {
+			self.finish();
+			self.exhausted = true;
+			return Ok(None);
+		}
+		// What remains of the range, for `Range::token`.
+		self.start = Bound::Excluded(entry.0);
+		Ok(Some(entry))
+	}
+
+	fn before_end(&self, key: u64) -> bool {
+		match self.end {
+			Bound::Included(end) => key <= end,
+			Bound::Excluded(end) => key < end,
+			Bound::Unbounded => true,
+		}
+	}
+
+	/// Prefetches the upcoming leaves once less than half of the read-ahead
+	/// distance is left prefetched.
+	fn prefetch_leaves(&mut self) -> Result<(), DatabaseError> {
+		if self.read_ahead == 0 || self.num_prefetched > self.read_ahead / 2 {
+			return Ok(());
+		}
+		let leaves = self.upcoming_leaves()?;
+		if leaves.len() > self.num_prefetched {
+			self.t.prefetch(&leaves[self.num_prefetched..])?;
+		}
+		self.num_prefetched = leaves.len();
+		Ok(())
+	}
+
+	/// Returns up to `read_ahead` leaves after the current one that may
+	/// contain keys in the range, from the current parent and the internal
+	/// node after it.
+	fn upcoming_leaves(&mut self) -> Result<Vec<PageId>, DatabaseError> {
+		let Some((keys, children, index)) = self.stack.last() else {
+			return Ok(Vec::new());
+		};
+		// Child `i` only has keys of at least `keys[i - 1]`.
+		let mut leaves: Vec<PageId> = (index + 1..children.len())
+			.take_while(|i| self.before_end(keys[i - 1]))
+			.map(|i| children[i])
+			.take(self.read_ahead)
+			.collect();
+		if leaves.len() == self.read_ahead || leaves.len() + index + 1 < children.len() {
+			return Ok(leaves);
+		}
+
+		let Some((parent_keys, parents, parent_index)) = self.stack.iter().nth_back(1) else {
+			return Ok(leaves);
+		};
+		let Some(next_parent) = parents.get(parent_index + 1).copied() else {
+			return Ok(leaves);
+		};
+		if !self.before_end(parent_keys[*parent_index]) {
+			return Ok(leaves);
+		}
+		if let BTreeNode::Internal { keys, children } = BTree::read_node(self.t, next_parent)? {
+			let remaining = self.read_ahead - leaves.len();
+			leaves.push(children[0]);
+			leaves.extend(
+				(1..children.len())
+					.take_while(|i| self.before_end(keys[i - 1]))
+					.map(|i| children[i])
+					.take(remaining - 1),
+			);
+		}
+		Ok(leaves)
+	}
+
+	fn finish(&mut self) {
+		self.stack.clear();
+		mem::take(&mut self.entries);
+	}
+}
+
+impl<T: TransactionApi> Iterator for Range<'_, T> {
+	type Item = Result<(u64, DbPointer), DatabaseError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let result = self.next_entry();
+		if result.is_err() {
+			self.finish();
+		}
+		result.transpose()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use crate::page_store::{
+		test_helpers::{page_id, temp_storage},
+		PageStorageApi, PageStorageConfig, VersionRetention,
+	};
+
+	use super::*;
+
+	const NUM_KEYS: u64 = 200;
+
+	fn small_tree() -> BTree {
+		BTree {
+			root: page_id!(1, 1),
+			id: 0,
+			leaf_capacity: 4,
+			internal_capacity: 4,
+			split_policy: SplitPolicy::default(),
+		}
+	}
+
+	fn pointer(key: u64) -> DbPointer {
+		DbPointer::new(page_id!(2, 1), key as u16)
+	}
+
+	/// The number of entries in each leaf of `tree`, from left to right.
+	fn leaf_lens(tree: &BTree, t: &mut impl TransactionApi) -> Vec<usize> {
+		let mut lens = Vec::new();
+		let mut stack = vec![tree.root];
+		while let Some(page_id) = stack.pop() {
+			match BTree::read_node(t, page_id).unwrap() {
+				BTreeNode::Leaf(entries) => lens.push(entries.len()),
+				BTreeNode::Internal { children, .. } => stack.extend(children.into_iter().rev()),
+			}
+		}
+		lens
+	}
+
+	/// Visits the keys in an order that is neither ascending nor descending.
+	fn shuffled_keys() -> impl Iterator<Item = u64> {
+		(0..NUM_KEYS).map(|i| (i * 37) % NUM_KEYS)
+	}
+
+	#[test]
+	fn insert_and_search() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+
+		// when
+		for key in shuffled_keys() {
+			assert_eq!(tree.insert(&mut t, key, pointer(key)).unwrap(), None);
+		}
+		let replaced = tree.insert(&mut t, 5, pointer(500)).unwrap();
+
+		// then
+		assert_eq!(replaced, Some(pointer(5)));
+		for key in 0..NUM_KEYS {
+			let expected = if key == 5 { pointer(500) } else { pointer(key) };
+			assert_eq!(tree.search(&mut t, key).unwrap(), Some(expected));
+		}
+		assert_eq!(tree.search(&mut t, NUM_KEYS).unwrap(), None);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn range_scan() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for key in shuffled_keys().filter(|key| key % 2 == 0) {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+
+		// when
+		let all: Vec<u64> = tree
+			.range(&mut t, ..)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		let middle: Vec<(u64, DbPointer)> = tree
+			.range(&mut t, 51..=60)
+			.collect::<Result<_, _>>()
+			.unwrap();
+		let tail: Vec<u64> = tree
+			.range(&mut t, (Bound::Excluded(190), Bound::Unbounded))
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		let empty = tree.range(&mut t, 61..62).count();
+
+		// then
+		assert_eq!(all, (0..NUM_KEYS).step_by(2).collect::<Vec<_>>());
+		assert_eq!(
+			middle,
+			[52, 54, 56, 58, 60].map(|key| (key, pointer(key))).to_vec()
+		);
+		assert_eq!(tail, vec![192, 194, 196, 198]);
+		assert_eq!(empty, 0);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn resume_range_scan_from_token() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree().with_id(7);
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for key in shuffled_keys() {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+		t.commit().unwrap();
+		let mut t = storage.transaction().unwrap();
+		let mut range = tree.range(&mut t, 10..150);
+		let first_page: Vec<u64> = range
+			.by_ref()
+			.take(20)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		let token = range.token().unwrap().to_bytes();
+		t.commit().unwrap();
+
+		// when
+		let token = ScanToken::from_bytes(&token).unwrap();
+		let mut t = storage.transaction().unwrap();
+		tree.delete(&mut t, 40).unwrap();
+		let mismatch = small_tree().with_id(8).resume(&mut t, &token).err();
+		let mut range = tree.resume(&mut t, &token).unwrap();
+		let rest: Vec<u64> = range.by_ref().map(|entry| entry.unwrap().0).collect();
+		let end_token = range.token();
+
+		// then
+		assert_eq!(first_page, (10..30).collect::<Vec<_>>());
+		assert!(matches!(mismatch, Some(DatabaseError::ScanTokenMismatch)));
+		assert_eq!(rest, (30..150).filter(|key| *key != 40).collect::<Vec<_>>());
+		assert_eq!(end_token, None);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn resume_snapshot_range_scan_from_token() {
+		// given
+		let (_tempdir, storage) = temp_storage(&PageStorageConfig {
+			version_retention: VersionRetention {
+				max_size: usize::MAX,
+				max_age: None,
+			},
+			..Default::default()
+		});
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for key in shuffled_keys() {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+		t.commit().unwrap();
+		let mut t = storage
+			.read_only_transaction()
+			.unwrap()
+			.with_snapshot_reads();
+		let mut range = tree.range(&mut t, 10..150);
+		let first_page: Vec<u64> = range
+			.by_ref()
+			.take(20)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		let token = range.token().unwrap().to_bytes();
+		t.commit().unwrap();
+
+		// when
+		let mut t = storage.transaction().unwrap();
+		tree.insert(&mut t, NUM_KEYS / 2, pointer(0)).unwrap();
+		tree.delete(&mut t, NUM_KEYS / 2 + 1).unwrap();
+		t.commit().unwrap();
+		let token = ScanToken::from_bytes(&token).unwrap();
+		let mut t = storage.transaction().unwrap();
+		let mismatch = tree.resume(&mut t, &token).err();
+		let mut t = storage
+			.read_only_transaction()
+			.unwrap()
+			.with_snapshot_reads_at(token.seq.unwrap())
+			.unwrap();
+		let rest: Vec<(u64, DbPointer)> = tree
+			.resume(&mut t, &token)
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+
+		// then
+		assert_eq!(first_page, (10..30).collect::<Vec<_>>());
+		assert!(matches!(
+			mismatch,
+			Some(DatabaseError::ScanSnapshotMismatch { actual: None, .. })
+		));
+		assert_eq!(
+			rest,
+			(30..150).map(|key| (key, pointer(key))).collect::<Vec<_>>()
+		);
+	}
+
+	#[test]
+	fn bulk_load_sorted_entries() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+
+		// when
+		let unsorted = tree.bulk_load(&mut t, [(2, pointer(2)), (1, pointer(1))]);
+		tree.bulk_load(&mut t, (0..NUM_KEYS).map(|key| (key, pointer(key))))
+			.unwrap();
+		let not_empty = tree.bulk_load(&mut t, [(NUM_KEYS, pointer(NUM_KEYS))]);
+
+		// then
+		assert!(matches!(
+			unsorted,
+			Err(DatabaseError::UnsortedKeys { prev: 2, key: 1 })
+		));
+		assert!(matches!(not_empty, Err(DatabaseError::TreeNotEmpty)));
+		// 50 leaves, 10 and 2 internal nodes, and the root.
+		assert_eq!(tree.pages(&mut t).unwrap().len(), 63);
+		let all: Vec<u64> = tree
+			.range(&mut t, ..)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		assert_eq!(all, (0..NUM_KEYS).collect::<Vec<_>>());
+		for key in shuffled_keys().filter(|key| key % 2 == 0) {
+			assert_eq!(tree.delete(&mut t, key).unwrap(), Some(pointer(key)));
+		}
+		tree.insert(&mut t, NUM_KEYS, pointer(NUM_KEYS)).unwrap();
+		for key in 0..=NUM_KEYS {
+			let expected = (key % 2 == 1 || key == NUM_KEYS).then(|| pointer(key));
+			assert_eq!(tree.search(&mut t, key).unwrap(), expected);
+		}
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn delete_and_merge() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for key in 0..NUM_KEYS {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+
+		// when
+		for key in shuffled_keys().filter(|key| key % 2 == 0) {
+			assert_eq!(tree.delete(&mut t, key).unwrap(), Some(pointer(key)));
+		}
+		let missing = tree.delete(&mut t, 0).unwrap();
+
+		// then
+		assert_eq!(missing, None);
+		for key in 0..NUM_KEYS {
+			let expected = (key % 2 == 1).then(|| pointer(key));
+			assert_eq!(tree.search(&mut t, key).unwrap(), expected);
+		}
+		for key in (0..NUM_KEYS).filter(|key| key % 2 == 1) {
+			tree.delete(&mut t, key).unwrap();
+		}
+		assert_eq!(
+			BTree::read_node(&mut t, tree.root).unwrap(),
+			BTreeNode::Leaf(Vec::new())
+		);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn split_ascending_keys_at_insertion_point() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree().with_split_policy(SplitPolicy {
+			append_optimized: true,
+			..Default::default()
+		});
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+
+		// when
+		for key in 0..NUM_KEYS {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+
+		// then
+		assert_eq!(leaf_lens(&tree, &mut t), vec![4; 50]);
+		for key in (0..NUM_KEYS).step_by(3) {
+			assert_eq!(tree.delete(&mut t, key).unwrap(), Some(pointer(key)));
+		}
+		for key in 0..NUM_KEYS {
+			let expected = (key % 3 != 0).then(|| pointer(key));
+			assert_eq!(tree.search(&mut t, key).unwrap(), expected);
+		}
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn split_with_fill_factor() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree().with_split_policy(SplitPolicy {
+			leaf_fill: 0.75,
+			internal_fill: 0.75,
+			append_optimized: false,
+		});
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+
+		// when
+		for key in 0..NUM_KEYS {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+
+		// then
+		let leaf_lens = leaf_lens(&tree, &mut t);
+		let (last, full) = leaf_lens.split_last().unwrap();
+		assert!(full.iter().all(|len| *len == 3));
+		assert!(*last <= 4);
+		for key in 0..NUM_KEYS {
+			assert_eq!(tree.search(&mut t, key).unwrap(), Some(pointer(key)));
+		}
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn range_scan_with_read_ahead() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for key in shuffled_keys() {
+			tree.insert(&mut t, key, pointer(key)).unwrap();
+		}
+
+		// when
+		let all: Vec<u64> = tree
+			.range(&mut t, ..)
+			.read_ahead(5)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+		let middle: Vec<u64> = tree
+			.range(&mut t, 37..120)
+			.read_ahead(2)
+			.map(|entry| entry.unwrap().0)
+			.collect();
+
+		// then
+		assert_eq!(all, (0..NUM_KEYS).collect::<Vec<_>>());
+		assert_eq!(middle, (37..120).collect::<Vec<_>>());
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn child_index_matches_linear_scan() {
+		// given
+		let separators: Vec<Vec<u64>> = (0..6)
+			.map(|len| (1..=len).map(|i| i * 10).collect())
+			.collect();
+
+		// then
+		for keys in &separators {
+			for key in 0..70 {
+				let expected = keys
+					.iter()
+					.take_while(|separator| **separator <= key)
+					.count();
+				assert_eq!(BTree::child_index(keys, key), expected, "{key} in {keys:?}");
+			}
+		}
+	}
+
+	#[test]
+	fn match_model_under_random_operations() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		let mut model: BTreeMap<u64, DbPointer> = BTreeMap::new();
+		let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+
+		// when
+		for step in 0..2000 {
+			// A linear congruential generator, so that failures can be
+			// reproduced.
+			state = state
+				.wrapping_mul(6_364_136_223_846_793_005)
+				.wrapping_add(1_442_695_040_888_963_407);
+			let key = (state >> 33) % 300;
+			match (state >> 20) % 3 {
+				0 => assert_eq!(
+					tree.insert(&mut t, key, pointer(step)).unwrap(),
+					model.insert(key, pointer(step)),
+					"insert {key} at step {step}"
+				),
+				1 => assert_eq!(
+					tree.delete(&mut t, key).unwrap(),
+					model.remove(&key),
+					"delete {key} at step {step}"
+				),
+				_ => assert_eq!(
+					tree.search(&mut t, key).unwrap(),
+					model.get(&key).copied(),
+					"search {key} at step {step}"
+				),
+			}
+		}
+
+		// then
+		for key in 0..300 {
+			assert_eq!(tree.search(&mut t, key).unwrap(), model.get(&key).copied());
+		}
+		let entries: Vec<(u64, DbPointer)> =
+			tree.range(&mut t, ..).collect::<Result<_, _>>().unwrap();
+		assert_eq!(entries, model.into_iter().collect::<Vec<_>>());
+		t.commit().unwrap();
+	}
+}
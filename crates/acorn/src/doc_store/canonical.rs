@@ -0,0 +1,127 @@
+use crate::{
+	files::segment::PAGE_BODY_SIZE,
+	page_store::{ReadPage, StorageError, WritePage},
+};
+
+use super::{
+	pages::{BTreePage, RecordPage, VarBTreePage},
+	DatabaseError, PageKind,
+};
+
+/// Zeroes the parts of a page that its format leaves unused, such as the
+/// space of removed records or entries, so that pages with the same logical
+/// content consist of the same bytes. Pages of kinds that don't leave stale
+/// bytes behind, and pages of unknown kinds, are left unchanged.
+pub(super) fn canonicalize(page: &mut [u8]) -> Result<(), DatabaseError> {
+	debug_assert_eq!(page.len(), PAGE_BODY_SIZE);
+	match PageKind::from(page[0]) {
+		Some(PageKind::Records) => RecordPage::new_unchecked(PageBuf(page)).canonicalize(),
+		Some(PageKind::BTreeNode) => BTreePage::new_unchecked(PageBuf(page)).canonicalize(),
+		Some(PageKind::VarBTreeNode | PageKind::PrefixVarBTreeNode) => {
+			VarBTreePage::new_unchecked(PageBuf(page)).canonicalize()
+		}
+		Some(PageKind::Free) => {
+			page[1..].fill(0);
+			Ok(())
+		}
+		_ => Ok(()),
+	}
+}
+
+struct PageBuf<'a>(&'a mut [u8]);
+
+impl PageBuf<'_> {
+	fn range(&self, offset: usize, len: usize) -> Result<std::ops::Range<usize>, StorageError> {
+		match offset.checked_add(len) {
+			Some(end) if end <= self.0.len() => Ok(offset..end),
+			_ => Err(StorageError::PageOutOfBounds { offset, len }),
+		}
+	}
+}
+
+impl ReadPage for PageBuf<'_> {
+	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
+		let range = self.range(offset, buf.len())?;
+		buf.copy_from_slice(&self.0[range]);
+		Ok(())
+	}
+}
+
+impl WritePage for PageBuf<'_> {
+	fn write(&mut self, offset: usize, buf: &[u8]) -> Result<(), StorageError> {
+		let range = self.range(offset, buf.len())?;
+		self.0[range].copy_from_slice(buf);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::pages::{BTreeNode, RecordKind},
+		page_store::test_helpers::page_id,
+	};
+
+	use super::*;
+
+	fn record_page(records: &[&[u8]], removed: &[u16]) -> Vec<u8> {
+		let mut page = vec![0; PAGE_BODY_SIZE];
+		let mut records_page = RecordPage::new_unchecked(PageBuf(&mut page));
+		records_page.init().unwrap();
+		for record in records {
+			records_page.insert(RecordKind::Inline, record).unwrap();
+		}
+		for index in removed {
+			records_page.remove(*index).unwrap();
+		}
+		page
+	}
+
+	#[test]
+	fn canonicalize_record_pages() {
+		// given
+		let mut modified = record_page(&[b"first", b"second", b"a much longer third"], &[2]);
+		let mut fresh = record_page(&[b"first", b"second"], &[]);
+
+		// when
+		canonicalize(&mut modified).unwrap();
+		canonicalize(&mut fresh).unwrap();
+
+		// then
+		assert_eq!(modified, fresh);
+	}
+
+	#[test]
+	fn canonicalize_b_tree_pages() {
+		// given
+		let mut shrunk = vec![0; PAGE_BODY_SIZE];
+		let mut fresh = vec![0; PAGE_BODY_SIZE];
+		let node = BTreeNode::Internal {
+			keys: vec![10],
+			children: vec![page_id!(1, 1), page_id!(1, 2)],
+		};
+		let mut shrunk_page = BTreePage::new_unchecked(PageBuf(&mut shrunk));
+		shrunk_page
+			.write_node(&BTreeNode::Internal {
+				keys: vec![10, 20, 30],
+				children: vec![
+					page_id!(1, 1),
+					page_id!(1, 2),
+					page_id!(1, 3),
+					page_id!(1, 4),
+				],
+			})
+			.unwrap();
+		shrunk_page.write_node(&node).unwrap();
+		BTreePage::new_unchecked(PageBuf(&mut fresh))
+			.write_node(&node)
+			.unwrap();
+
+		// when
+		canonicalize(&mut shrunk).unwrap();
+		canonicalize(&mut fresh).unwrap();
+
+		// then
+		assert_eq!(shrunk, fresh);
+	}
+}
@@ -0,0 +1,590 @@
+use std::{collections::BTreeMap, mem};
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::{
+	files::utils::CRC32,
+	page_store::{PageId, TransactionApi},
+};
+
+use super::{
+	b_tree::BTree,
+	overflow,
+	page_alloc::PageAllocator,
+	pages::{CatalogRootPage, CatalogVersionPage},
+	var_b_tree::{Comparators, KeyOrder, VarBTree},
+	DatabaseError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(super) enum TreeKind {
+	BTree = 0,
+	VarBTree = 1,
+}
+
+impl TreeKind {
+	pub fn from_repr(kind: u8) -> Option<Self> {
+		match kind {
+			0 => Some(Self::BTree),
+			1 => Some(Self::VarBTree),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CatalogEntry {
+	/// Identifies the tree for as long as it exists, even if its root
+	/// changes. It is the version of the catalog that added the tree, so no
+	/// two trees ever get the same id.
+	pub id: u64,
+	pub kind: TreeKind,
+	pub root: PageId,
+	pub order: KeyOrder,
+}
+
+impl CatalogEntry {
+	/// Opens the tree with fixed-size keys that the entry refers to.
+	pub fn b_tree(&self) -> BTree {
+		debug_assert_eq!(self.kind, TreeKind::BTree);
+		BTree::new(self.root).with_id(self.id)
+	}
+}
+
+#[derive(AsBytes, FromZeroes, FromBytes)]
+#[repr(C, packed)]
+struct CatalogEntryRepr {
+	name_len: u16,
+	id: u64,
+	kind: u8,
+	segment_num: u32,
+	page_num: u16,
+	order: u8,
+	/// The id of the comparator if `order` is custom, and 0 otherwise.
+	custom_order: u8,
+}
+
+/// The catalog of named trees in the database.
+///
+/// The catalog is never modified in place. Every change writes a complete
+/// new version with a checksum, and then switches the root page over to it
+/// in the same transaction, so a crash can never leave a partially updated
+/// catalog. The last few versions are kept, so that past states of the
+/// catalog can be inspected after the fact.
+///
+/// The catalog of a database is rooted at the page that the
+/// [`PageAllocator`] sets aside for it, see [`Catalog::default`].
+pub(super) struct Catalog {
+	root_page: PageId,
+	comparators: Comparators,
+}
+
+impl Default for Catalog {
+	fn default() -> Self {
+		Self::new(PageAllocator::CATALOG_PAGE_ID)
+	}
+}
+
+impl Catalog {
+	const NUM_RETAINED_VERSIONS: usize = 8;
+
+	pub fn new(root_page: PageId) -> Self {
+		Self {
+			root_page,
+			comparators: Comparators::default(),
+		}
+	}
+
+	/// Sets the comparators that trees with a custom key order use.
+	pub fn with_comparators(mut self, comparators: Comparators) -> Self {
+		self.comparators = comparators;
+		self
+	}
+
+	pub fn init(&self, t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
+		let version_page = Self::write_version(t, 1, None, &BTreeMap::new())?;
+		CatalogRootPage::new_unchecked(t.get_page_mut(self.root_page)?).init(version_page)
+	}
+
+	pub fn get(
+		&self,
+		t: &mut impl TransactionApi,
+		name: &str,
+	) -> Result<Option<CatalogEntry>, DatabaseError> {
+		Ok(self.entries(t)?.remove(name))
+	}
+
+	pub fn entries(
+		&self,
+		t: &mut impl TransactionApi,
+	) -> Result<BTreeMap<String, CatalogEntry>, DatabaseError> {
+		let current = self.current_version_page(t)?;
+		Self::read_version(t, current)
+	}
+
+	/// The version numbers of all retained versions, from newest to oldest.
+	pub fn versions(&self, t: &mut impl TransactionApi) -> Result<Vec<u64>, DatabaseError> {
+		let mut versions = Vec::new();
+		let mut next_version_page = Some(self.current_version_page(t)?);
+		while let Some(version_page_id) = next_version_page {
+			let version_page = CatalogVersionPage::new(t.get_page(version_page_id)?)?;
+			versions.push(version_page.get_version()?);
+			next_version_page = version_page.get_previous()?;
+		}
+		Ok(versions)
+	}
+
+	/// Returns the entries of a past version of the catalog, or `None` if it
+	/// is no longer retained.
+	pub fn entries_at(
+		&self,
+		t: &mut impl TransactionApi,
+		version: u64,
+	) -> Result<Option<BTreeMap<String, CatalogEntry>>, DatabaseError> {
+		let mut next_version_page = Some(self.current_version_page(t)?);
+		while let Some(version_page_id) = next_version_page {
+			let version_page = CatalogVersionPage::new(t.get_page(version_page_id)?)?;
+			if version_page.get_version()? == version {
+				mem::drop(version_page);
+				return Self::read_version(t, version_page_id).map(Some);
+			}
+			next_version_page = version_page.get_previous()?;
+		}
+		Ok(None)
+	}
+
+	/// Returns the pages that hold the catalog itself: the root page, and the
+	/// pages of the retained versions.
+	pub fn pages(&self, t: &mut impl TransactionApi) -> Result<Vec<PageId>, DatabaseError> {
+		let mut pages = vec![self.root_page];
+		let mut next_version_page = Some(self.current_version_page(t)?);
+		while let Some(version_page_id) = next_version_page {
+			let version_page = CatalogVersionPage::new(t.get_page(version_page_id)?)?;
+			let entries = version_page.get_entries()?;
+			next_version_page = version_page.get_previous()?;
+			mem::drop(version_page);
+
+			pages.push(version_page_id);
+			pages.extend(overflow::pages(t, entries)?);
+		}
+		Ok(pages)
+	}
+
+	/// Allocates and initializes an empty tree, and adds it to the catalog.
+	/// Only trees with variable-length keys can have a key order other than
+	/// [`KeyOrder::Bytes`].
+	pub fn create_tree(
+		&self,
+		t: &mut impl TransactionApi,
+		name: &str,
+		kind: TreeKind,
+		order: KeyOrder,
+	) -> Result<PageId, DatabaseError> {
+		if name.len() > usize::from(u16::MAX) {
+			return Err(DatabaseError::KeyTooLong {
+				len: name.len(),
+				max: usize::from(u16::MAX),
+			});
+		}
+		if kind == TreeKind::BTree && order != KeyOrder::Bytes {
+			return Err(DatabaseError::UnsupportedKeyOrder(name.to_string()));
+		}
+		order.comparator(&self.comparators)?;
+		let mut entries = self.entries(t)?;
+		if entries.contains_key(name) {
+			return Err(DatabaseError::TreeExists(name.to_string()));
+		}
+		let root = PageAllocator::alloc(t)?;
+		match kind {
+			TreeKind::BTree => BTree::new(root).init(t)?,
+			TreeKind::VarBTree => VarBTree::new(root).init(t)?,
+		}
+		let current = self.current_version_page(t)?;
+		let id = CatalogVersionPage::new(t.get_page(current)?)?.get_version()? + 1;
+		entries.insert(
+			name.to_string(),
+			CatalogEntry {
+				id,
+				kind,
+				root,
+				order,
+			},
+		);
+		self.update(t, &entries)?;
+		Ok(root)
+	}
+
+	/// Opens a tree with variable-length keys, ordered by the key order it was
+	/// created with.
+	pub fn var_b_tree(&self, entry: &CatalogEntry) -> Result<VarBTree, DatabaseError> {
+		debug_assert_eq!(entry.kind, TreeKind::VarBTree);
+		let comparator = entry.order.comparator(&self.comparators)?;
+		Ok(VarBTree::new(entry.root).with_comparator(comparator))
+	}
+
+	/// Removes a tree from the catalog. The pages of the tree are left to
+	/// [`PageAllocator::collect_garbage`], since past versions of the catalog
+	/// may still refer to them.
+	pub fn drop_tree(
+		&self,
+		t: &mut impl TransactionApi,
+		name: &str,
+	) -> Result<CatalogEntry, DatabaseError> {
+		let mut entries = self.entries(t)?;
+		let Some(entry) = entries.remove(name) else {
+			return Err(DatabaseError::TreeNotFound(name.to_string()));
+		};
+		self.update(t, &entries)?;
+		Ok(entry)
+	}
+
+	/// Points the entry of a tree at the root of a different tree of the same
+	/// kind and key order, such as a rebuilt copy of it. Fails if the entry
+	/// doesn't have the root `old_root` anymore.
+	pub fn swap_root(
+		&self,
+		t: &mut impl TransactionApi,
+		name: &str,
+		old_root: PageId,
+		new_root: PageId,
+	) -> Result<(), DatabaseError> {
+		let mut entries = self.entries(t)?;
+		let Some(entry) = entries.get_mut(name) else {
+			return Err(DatabaseError::TreeNotFound(name.to_string()));
+		};
+		if entry.root != old_root {
+			return Err(DatabaseError::TreeReplaced(name.to_string()));
+		}
+		entry.root = new_root;
+		self.update(t, &entries)
+	}
+
+	fn update(
+		&self,
+		t: &mut impl TransactionApi,
+		entries: &BTreeMap<String, CatalogEntry>,
+	) -> Result<(), DatabaseError> {
+		let current = self.current_version_page(t)?;
+		let version = CatalogVersionPage::new(t.get_page(current)?)?.get_version()?;
+		let new_version_page = Self::write_version(t, version + 1, Some(current), entries)?;
+		CatalogRootPage::new(t.get_page_mut(self.root_page)?)?.set_current(new_version_page)?;
+		Self::drop_old_versions(t, new_version_page)
+	}
+
+	fn drop_old_versions(t: &mut impl TransactionApi, newest: PageId) -> Result<(), DatabaseError> {
+		let mut version_page_id = newest;
+		for _ in 1..Self::NUM_RETAINED_VERSIONS {
+			let previous = CatalogVersionPage::new(t.get_page(version_page_id)?)?.get_previous()?;
+			let Some(previous) = previous else {
+				return Ok(());
+			};
+			version_page_id = previous;
+		}
+
+		let mut version_page = CatalogVersionPage::new(t.get_page_mut(version_page_id)?)?;
+		let mut next_dropped = version_page.get_previous()?;
+		version_page.set_previous(None)?;
+		mem::drop(version_page);
+
+		while let Some(dropped) = next_dropped {
+			let version_page = CatalogVersionPage::new(t.get_page(dropped)?)?;
+			let entries = version_page.get_entries()?;
+			next_dropped = version_page.get_previous()?;
+			mem::drop(version_page);
+
+			overflow::free(t, entries)?;
+			PageAllocator::free(t, dropped)?;
+		}
+		Ok(())
+	}
+
+	fn current_version_page(&self, t: &mut impl TransactionApi) -> Result<PageId, DatabaseError> {
+		CatalogRootPage::new(t.get_page(self.root_page)?)?.get_current()
+	}
+
+	fn write_version(
+		t: &mut impl TransactionApi,
+		version: u64,
+		previous: Option<PageId>,
+		entries: &BTreeMap<String, CatalogEntry>,
+	) -> Result<PageId, DatabaseError> {
+		let data = Self::serialize(entries);
+		let checksum = CRC32.checksum(&data);
+		let entries = overflow::write(t, &data)?;
+		let version_page = PageAllocator::alloc(t)?;
+		CatalogVersionPage::new_unchecked(t.get_page_mut(version_page)?)
+			.init(version, previous, checksum, entries)?;
+		Ok(version_page)
+	}
+
+	fn read_version(
+		t: &mut impl TransactionApi,
+		version_page_id: PageId,
+	) -> Result<BTreeMap<String, CatalogEntry>, DatabaseError> {
+		let version_page = CatalogVersionPage::new(t.get_page(version_page_id)?)?;
+		let version = version_page.get_version()?;
+		let checksum = version_page.get_checksum()?;
+		let entries = version_page.get_entries()?;
+		mem::drop(version_page);
+
+		let data = overflow::read(t, entries)?;
+		if CRC32.checksum(&data) != checksum {
+			return Err(DatabaseError::CatalogChecksumMismatch(version));
+		}
+		Self::deserialize(&data)
+	}
+
+	fn serialize(entries: &BTreeMap<String, CatalogEntry>) -> Vec<u8> {
+		let mut data = Vec::new();
+		for (name, entry) in entries {
+			let (order, custom_order) = entry.order.to_repr();
+			let repr = CatalogEntryRepr {
+				name_len: u16::try_from(name.len()).expect("Tree names must fit in 16 bits!"),
+				id: entry.id,
+				kind: entry.kind as u8,
+				segment_num: entry.root.segment_num,
+				page_num: entry.root.page_num.get(),
+				order,
+				custom_order,
+			};
+			data.extend_from_slice(repr.as_bytes());
+			data.extend_from_slice(name.as_bytes());
+		}
+		data
+	}
+
+	fn deserialize(mut data: &[u8]) -> Result<BTreeMap<String, CatalogEntry>, DatabaseError> {
+		let format_error = || DatabaseError::PageFormat("Malformed catalog entry".to_string());
+
+		let mut entries = BTreeMap::new();
+		while !data.is_empty() {
+			let repr = CatalogEntryRepr::read_from_prefix(data).ok_or_else(format_error)?;
+			data = &data[mem::size_of::<CatalogEntryRepr>()..];
+			let name_len = usize::from(repr.name_len);
+			if data.len() < name_len {
+				return Err(format_error());
+			}
+			let name = String::from_utf8(data[..name_len].to_vec())?;
+			data = &data[name_len..];
+
+			let kind = TreeKind::from_repr(repr.kind).ok_or_else(format_error)?;
+			let root = PageId::new(
+				repr.segment_num,
+				repr.page_num.try_into().map_err(|_| format_error())?,
+			);
+			let order =
+				KeyOrder::from_repr(repr.order, repr.custom_order).ok_or_else(format_error)?;
+			entries.insert(
+				name,
+				CatalogEntry {
+					id: repr.id,
+					kind,
+					root,
+					order,
+				},
+			);
+		}
+		Ok(entries)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::DbPointer,
+		page_store::{
+			test_helpers::{page_id, temp_storage},
+			PageStorageApi,
+		},
+	};
+
+	use super::*;
+
+	#[test]
+	fn create_and_drop_trees() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::default();
+		catalog.init(&mut t).unwrap();
+
+		// when
+		let users = catalog
+			.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		catalog
+			.create_tree(&mut t, "names", TreeKind::VarBTree, KeyOrder::Bytes)
+			.unwrap();
+		let duplicate = catalog.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes);
+		catalog.drop_tree(&mut t, "names").unwrap();
+
+		// then
+		assert!(matches!(duplicate, Err(DatabaseError::TreeExists(..))));
+		assert_eq!(
+			catalog.get(&mut t, "users").unwrap(),
+			Some(CatalogEntry {
+				id: 2,
+				kind: TreeKind::BTree,
+				root: users,
+				order: KeyOrder::Bytes,
+			})
+		);
+		assert_eq!(catalog.get(&mut t, "names").unwrap(), None);
+		assert!(matches!(
+			catalog.drop_tree(&mut t, "names"),
+			Err(DatabaseError::TreeNotFound(..))
+		));
+		assert_eq!(catalog.versions(&mut t).unwrap(), vec![4, 3, 2, 1]);
+		let before_drop = catalog.entries_at(&mut t, 3).unwrap().unwrap();
+		assert_eq!(before_drop.len(), 2);
+		assert_eq!(before_drop["names"].kind, TreeKind::VarBTree);
+		assert_eq!(before_drop["names"].id, 3);
+		assert_ne!(users, PageAllocator::CATALOG_PAGE_ID);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn assign_distinct_tree_ids() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::new(page_id!(1, 1));
+		catalog.init(&mut t).unwrap();
+
+		// when
+		catalog
+			.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		let dropped = catalog.drop_tree(&mut t, "users").unwrap();
+		catalog
+			.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		let recreated = catalog.get(&mut t, "users").unwrap().unwrap();
+		let token = recreated.b_tree().range(&mut t, ..).token().unwrap();
+
+		// then
+		assert_ne!(recreated.id, dropped.id);
+		assert_eq!(token.tree, recreated.id);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn retain_recent_versions() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::new(page_id!(1, 1));
+		catalog.init(&mut t).unwrap();
+
+		// when
+		for i in 0..10 {
+			catalog
+				.create_tree(
+					&mut t,
+					&format!("tree {i}"),
+					TreeKind::BTree,
+					KeyOrder::Bytes,
+				)
+				.unwrap();
+		}
+
+		// then
+		assert_eq!(
+			catalog.versions(&mut t).unwrap(),
+			(4..=11).rev().collect::<Vec<u64>>()
+		);
+		assert_eq!(catalog.entries_at(&mut t, 3).unwrap(), None);
+		assert_eq!(catalog.entries_at(&mut t, 4).unwrap().unwrap().len(), 3);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn record_key_orders() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::new(page_id!(1, 1))
+			.with_comparators(Comparators::default().with_custom(7, |a, b| b.cmp(a)));
+		catalog.init(&mut t).unwrap();
+
+		// when
+		catalog
+			.create_tree(&mut t, "numbers", TreeKind::VarBTree, KeyOrder::Numeric)
+			.unwrap();
+		catalog
+			.create_tree(&mut t, "reversed", TreeKind::VarBTree, KeyOrder::Custom(7))
+			.unwrap();
+		let fixed_size =
+			catalog.create_tree(&mut t, "ids", TreeKind::BTree, KeyOrder::CaseInsensitive);
+		let unregistered =
+			catalog.create_tree(&mut t, "unknown", TreeKind::VarBTree, KeyOrder::Custom(8));
+		let numbers = catalog.get(&mut t, "numbers").unwrap().unwrap();
+		let tree = catalog.var_b_tree(&numbers).unwrap();
+		tree.insert(&mut t, b"10", DbPointer::new(page_id!(2, 1), 10))
+			.unwrap();
+		tree.insert(&mut t, b"010", DbPointer::new(page_id!(2, 1), 11))
+			.unwrap();
+		let reversed = catalog.get(&mut t, "reversed").unwrap().unwrap();
+
+		// then
+		assert_eq!(numbers.order, KeyOrder::Numeric);
+		assert_eq!(reversed.order, KeyOrder::Custom(7));
+		assert!(matches!(
+			fixed_size,
+			Err(DatabaseError::UnsupportedKeyOrder(..))
+		));
+		assert!(matches!(
+			unregistered,
+			Err(DatabaseError::UnknownKeyOrder(8))
+		));
+		assert!(matches!(
+			Catalog::new(page_id!(1, 1)).var_b_tree(&reversed),
+			Err(DatabaseError::UnknownKeyOrder(7))
+		));
+		assert_eq!(
+			tree.search(&mut t, b"10").unwrap(),
+			Some(DbPointer::new(page_id!(2, 1), 10))
+		);
+		assert_eq!(
+			tree.search(&mut t, b"010").unwrap(),
+			Some(DbPointer::new(page_id!(2, 1), 11))
+		);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn detect_corrupted_catalog() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::new(page_id!(1, 1));
+		catalog.init(&mut t).unwrap();
+		catalog
+			.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+
+		// when
+		let current = catalog.current_version_page(&mut t).unwrap();
+		let version_page = CatalogVersionPage::new(t.get_page(current).unwrap()).unwrap();
+		let previous = version_page.get_previous().unwrap();
+		let checksum = version_page.get_checksum().unwrap();
+		let entries = version_page.get_entries().unwrap();
+		drop(version_page);
+		CatalogVersionPage::new_unchecked(t.get_page_mut(current).unwrap())
+			.init(2, previous, !checksum, entries)
+			.unwrap();
+
+		// then
+		assert!(matches!(
+			catalog.entries(&mut t),
+			Err(DatabaseError::CatalogChecksumMismatch(2))
+		));
+		t.undo().unwrap();
+	}
+}
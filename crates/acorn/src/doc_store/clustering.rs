@@ -0,0 +1,353 @@
+use std::{
+	collections::{BTreeMap, HashMap, HashSet},
+	hash::Hash,
+};
+
+use crate::page_store::{PageId, PageStorageApi, TransactionApi};
+
+use super::{
+	b_tree::BTree,
+	catalog::Catalog,
+	rebuild::{RetiredTree, TreeRebuild},
+	var_b_tree::VarBTree,
+	DatabaseError, Leaf,
+};
+
+/// A page that holds hot keys, but more cold ones, so that keeping the hot
+/// keys cached keeps the cold ones cached as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct MixedPage {
+	pub page_id: PageId,
+	pub num_hot: usize,
+	pub num_cold: usize,
+}
+
+/// How the hot keys of a tree are spread across a kind of page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct HotSpread {
+	/// The number of pages with at least one hot key.
+	pub num_pages: usize,
+	/// The fewest pages that could hold all hot keys, judging by the fullest
+	/// page.
+	pub min_pages: usize,
+	/// The pages where cold keys outnumber hot ones, ordered by page.
+	pub mixed_pages: Vec<MixedPage>,
+}
+
+impl HotSpread {
+	fn collect<'a>(pages: impl IntoIterator<Item = (PageId, &'a [bool])>) -> Self {
+		let mut spread = Self::default();
+		let mut num_hot_keys = 0;
+		let mut max_keys = 0;
+		for (page_id, is_hot) in pages {
+			let num_hot = is_hot.iter().filter(|is_hot| **is_hot).count();
+			let num_cold = is_hot.len() - num_hot;
+			num_hot_keys += num_hot;
+			max_keys = usize::max(max_keys, is_hot.len());
+			if num_hot == 0 {
+				continue;
+			}
+			spread.num_pages += 1;
+			if num_cold > num_hot {
+				spread.mixed_pages.push(MixedPage {
+					page_id,
+					num_hot,
+					num_cold,
+				});
+			}
+		}
+		spread.min_pages = num_hot_keys.div_ceil(usize::max(max_keys, 1));
+		spread.mixed_pages.sort_by_key(|page| page.page_id);
+		spread
+	}
+
+	/// Whether the hot keys are spread across more than twice as many pages
+	/// as they need.
+	pub fn is_scattered(&self) -> bool {
+		self.num_pages > 2 * self.min_pages
+	}
+}
+
+/// How well the pages of a tree separate its frequently accessed keys from
+/// the rest, see [`ClusteringAdvisor`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct ClusteringReport {
+	pub num_keys: usize,
+	pub num_hot_keys: usize,
+	pub num_leaves: usize,
+	/// The fewest leaves that could hold all keys. For trees with
+	/// variable-length keys, this is judged by the fullest leaf.
+	pub min_leaves: usize,
+	/// How the leaves of the tree hold the hot keys.
+	pub leaves: HotSpread,
+	/// How the records that the hot keys point to are stored.
+	pub records: HotSpread,
+}
+
+impl ClusteringReport {
+	/// Whether rewriting the tree with [`cluster_tree`] would likely make it
+	/// take up fewer cached pages: its leaves are less than two thirds full
+	/// on average, or its hot keys are scattered across its leaves.
+	///
+	/// Clustering only moves the nodes of the tree, so records stay where
+	/// they are; and hot keys that are far apart in key order still end up
+	/// on different leaves.
+	pub fn should_cluster(&self) -> bool {
+		2 * self.num_leaves > 3 * self.min_leaves || self.leaves.is_scattered()
+	}
+}
+
+/// Finds trees whose frequently accessed ("hot") keys are co-located with
+/// rarely accessed ones, or spread across more pages than they need.
+///
+/// The hot keys are the most frequently accessed keys that together account
+/// for a share of all accesses, 80% by default. The access counts come from
+/// the application, since the database doesn't keep statistics per key.
+#[derive(Debug, Clone)]
+pub(super) struct ClusteringAdvisor {
+	hot_share: f64,
+}
+
+impl Default for ClusteringAdvisor {
+	fn default() -> Self {
+		Self { hot_share: 0.8 }
+	}
+}
+
+impl ClusteringAdvisor {
+	/// Sets the share of all accesses that the hot keys account for, between
+	/// 0 and 1.
+	pub fn with_hot_share(mut self, hot_share: f64) -> Self {
+		self.hot_share = hot_share.clamp(0.0, 1.0);
+		self
+	}
+
+	pub fn analyze_b_tree(
+		&self,
+		t: &mut impl TransactionApi,
+		tree: &BTree,
+		accesses: &HashMap<u64, u64>,
+	) -> Result<ClusteringReport, DatabaseError> {
+		Ok(self.analyze(tree.leaves(t)?, Some(tree.leaf_capacity()), accesses))
+	}
+
+	pub fn analyze_var_b_tree(
+		&self,
+		t: &mut impl TransactionApi,
+		tree: &VarBTree,
+		accesses: &HashMap<Vec<u8>, u64>,
+	) -> Result<ClusteringReport, DatabaseError> {
+		Ok(self.analyze(tree.leaves(t)?, None, accesses))
+	}
+
+	/// Analyzes a tree, given its leaves in key order, the number of entries
+	/// that fit in a leaf if it is fixed, and the number of accesses to each
+	/// key. Keys without accesses are cold.
+	fn analyze<K: Hash + Eq>(
+		&self,
+		leaves: Vec<Leaf<K>>,
+		leaf_capacity: Option<usize>,
+		accesses: &HashMap<K, u64>,
+	) -> ClusteringReport {
+		let hot_keys = self.hot_keys(&leaves, accesses);
+		let mut report = ClusteringReport {
+			num_keys: leaves.iter().map(|(_, entries)| entries.len()).sum(),
+			num_hot_keys: hot_keys.len(),
+			num_leaves: leaves.len(),
+			..ClusteringReport::default()
+		};
+		let leaf_capacity = leaf_capacity
+			.or_else(|| leaves.iter().map(|(_, entries)| entries.len()).max())
+			.unwrap_or(1);
+		report.min_leaves = report.num_keys.div_ceil(leaf_capacity.max(1));
+
+		let mut leaf_keys: Vec<(PageId, Vec<bool>)> = Vec::with_capacity(leaves.len());
+		let mut record_keys: BTreeMap<PageId, Vec<bool>> = BTreeMap::new();
+		for (page_id, entries) in &leaves {
+			let is_hot: Vec<bool> = entries
+				.iter()
+				.map(|(key, _)| hot_keys.contains(key))
+				.collect();
+			for ((_, pointer), is_hot) in entries.iter().zip(&is_hot) {
+				record_keys
+					.entry(pointer.page_id())
+					.or_default()
+					.push(*is_hot);
+			}
+			leaf_keys.push((*page_id, is_hot));
+		}
+		report.leaves = HotSpread::collect(
+			leaf_keys
+				.iter()
+				.map(|(page_id, is_hot)| (*page_id, is_hot.as_slice())),
+		);
+		report.records = HotSpread::collect(
+			record_keys
+				.iter()
+				.map(|(page_id, is_hot)| (*page_id, is_hot.as_slice())),
+		);
+		report
+	}
+
+	fn hot_keys<'a, K: Hash + Eq>(
+		&self,
+		leaves: &'a [Leaf<K>],
+		accesses: &HashMap<K, u64>,
+	) -> HashSet<&'a K> {
+		let mut counts: Vec<(&K, u64)> = leaves
+			.iter()
+			.flat_map(|(_, entries)| entries.iter())
+			.filter_map(|(key, _)| Some((key, *accesses.get(key)?)))
+			.filter(|(_, count)| *count != 0)
+			.collect();
+		// Stable, so that keys with the same count are taken in key order
+		counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+		let total: u64 = counts.iter().map(|(_, count)| count).sum();
+		#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+		let hot_total = (total as f64 * self.hot_share).ceil() as u64;
+		let mut hot_keys = HashSet::new();
+		let mut covered = 0;
+		for (key, count) in counts {
+			if covered >= hot_total {
+				break;
+			}
+			hot_keys.insert(key);
+			covered += count;
+		}
+		hot_keys
+	}
+}
+
+/// Rewrites the tree `name` in key order, so that its leaves are filled
+/// evenly and allocated one after another, and returns the pages of the old
+/// tree, which have to be freed afterwards.
+///
+/// The tree is rebuilt like with [`TreeRebuild`], but without a way to
+/// capture concurrent changes, so the tree must not be modified until this
+/// returns; transactions that have to keep modifying it should rebuild it
+/// with [`TreeRebuild`] themselves.
+pub(super) fn cluster_tree<S: PageStorageApi>(
+	catalog: &Catalog,
+	storage: &S,
+	name: &str,
+) -> Result<RetiredTree, DatabaseError> {
+	let rebuild = TreeRebuild::begin(catalog, storage, name)?;
+	rebuild.build(catalog, storage)?;
+	rebuild.swap(catalog, storage)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::{
+			catalog::TreeKind, page_alloc::PageAllocator, var_b_tree::KeyOrder, DbPointer,
+		},
+		page_store::test_helpers::{page_id, temp_storage},
+	};
+
+	use super::*;
+
+	const NUM_KEYS: u64 = 20_000;
+
+	fn pointer(page_num: u16, index: u16) -> DbPointer {
+		DbPointer::new(page_id!(9, page_num), index)
+	}
+
+	#[test]
+	fn report_scattered_and_mixed_hot_keys() {
+		// given
+		let leaves = vec![
+			(
+				page_id!(1, 1),
+				vec![(1, pointer(1, 0)), (2, pointer(1, 1)), (3, pointer(2, 0))],
+			),
+			(
+				page_id!(1, 2),
+				vec![(4, pointer(2, 1)), (5, pointer(2, 2)), (6, pointer(2, 3))],
+			),
+			(page_id!(1, 3), vec![(7, pointer(3, 0)), (8, pointer(3, 1))]),
+		];
+		let accesses = HashMap::from([(1, 50), (4, 30), (7, 15), (8, 5)]);
+
+		// when
+		let report = ClusteringAdvisor::default().analyze(leaves, None, &accesses);
+
+		// then
+		assert_eq!(report.num_keys, 8);
+		assert_eq!(report.num_hot_keys, 2);
+		assert_eq!(report.num_leaves, 3);
+		assert_eq!(report.min_leaves, 3);
+		assert_eq!(
+			report.leaves,
+			HotSpread {
+				num_pages: 2,
+				min_pages: 1,
+				mixed_pages: vec![
+					MixedPage {
+						page_id: page_id!(1, 1),
+						num_hot: 1,
+						num_cold: 2
+					},
+					MixedPage {
+						page_id: page_id!(1, 2),
+						num_hot: 1,
+						num_cold: 2
+					},
+				]
+			}
+		);
+		assert_eq!(report.records.num_pages, 2);
+		assert_eq!(report.records.min_pages, 1);
+		assert_eq!(
+			report.records.mixed_pages,
+			[MixedPage {
+				page_id: page_id!(9, 2),
+				num_hot: 1,
+				num_cold: 3
+			}]
+		);
+		assert!(!report.should_cluster());
+	}
+
+	#[test]
+	fn cluster_sparse_tree() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let catalog = Catalog::default();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		catalog.init(&mut t).unwrap();
+		let root = catalog
+			.create_tree(&mut t, "items", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		let tree = BTree::new(root);
+		// Inserting in descending order leaves every split-off leaf half full.
+		for key in (0..NUM_KEYS).rev() {
+			tree.insert(&mut t, key, pointer(1, 0)).unwrap();
+		}
+		t.commit().unwrap();
+		let accesses: HashMap<u64, u64> = (0..NUM_KEYS).map(|key| (key, 1)).collect();
+		let advisor = ClusteringAdvisor::default();
+		let mut t = storage.read_only_transaction().unwrap();
+		let before = advisor.analyze_b_tree(&mut t, &tree, &accesses).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let retired = cluster_tree(&catalog, &storage, "items").unwrap();
+		retired.free(&storage, 16).unwrap();
+
+		// then
+		let mut t = storage.read_only_transaction().unwrap();
+		let entry = catalog.get(&mut t, "items").unwrap().unwrap();
+		let after = advisor
+			.analyze_b_tree(&mut t, &BTree::new(entry.root), &accesses)
+			.unwrap();
+		t.commit().unwrap();
+		assert!(before.should_cluster());
+		assert!(!after.should_cluster());
+		assert_eq!(after.num_keys, NUM_KEYS as usize);
+		assert!(after.num_leaves < before.num_leaves);
+		assert_eq!(after.num_leaves, after.min_leaves);
+	}
+}
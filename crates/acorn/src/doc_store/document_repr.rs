@@ -8,7 +8,7 @@ use super::{DatabaseError, DbPointer};
 
 #[derive(Debug, AsBytes, FromZeroes, FromBytes)]
 #[repr(C)]
-struct DbPointerRepr {
+pub(super) struct DbPointerRepr {
 	segment_num: u32,
 	page_num: u16,
 	index: u16,
@@ -0,0 +1,293 @@
+use std::io::{Read, Write};
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::{
+	files::{
+		dump::{DumpReader, DumpWriter, EntryKind},
+		FileError,
+	},
+	page_store::{StorageError, TransactionApi},
+};
+
+use super::{
+	b_tree::BTree,
+	catalog::{Catalog, TreeKind},
+	records::RecordManager,
+	var_b_tree::{KeyOrder, VarBTree},
+	DatabaseError,
+};
+
+/// The number of records that are read at once while dumping a tree.
+const BATCH_SIZE: usize = 256;
+
+/// The definition of a tree in a dump, which follows its name.
+#[derive(AsBytes, FromZeroes, FromBytes)]
+#[repr(C, packed)]
+struct TreeDefinitionRepr {
+	kind: u8,
+	order: u8,
+	/// The id of the comparator if `order` is custom, and 0 otherwise.
+	custom_order: u8,
+}
+
+/// A tree that the records of a dump are loaded into.
+enum TargetTree {
+	BTree(BTree),
+	VarBTree(VarBTree),
+}
+
+/// Writes every tree of the catalog to `writer` as a logical dump, and
+/// returns the number of records. Each tree is written as its definition,
+/// followed by its keys in key order, each with the content of the record it
+/// points to. Keys whose record was deleted are left out.
+///
+/// The dump refers to no pages, so it can be loaded with [`load`] into a
+/// database with a different page size or layout.
+pub(super) fn dump(
+	t: &mut impl TransactionApi,
+	catalog: &Catalog,
+	records: &RecordManager,
+	writer: impl Write,
+) -> Result<usize, DatabaseError> {
+	let mut writer = DumpWriter::new(writer).map_err(StorageError::from)?;
+	let mut num_records = 0;
+	for (name, entry) in catalog.entries(t)? {
+		let (order, custom_order) = entry.order.to_repr();
+		let definition = TreeDefinitionRepr {
+			kind: entry.kind as u8,
+			order,
+			custom_order,
+		};
+		writer
+			.write_entry(EntryKind::Tree, name.as_bytes(), definition.as_bytes())
+			.map_err(StorageError::from)?;
+
+		// The entries of trees with variable-length keys are read without
+		// their comparator, since they are already in its order.
+		let entries = match entry.kind {
+			TreeKind::BTree => entry
+				.b_tree()
+				.range(t, ..)
+				.map(|entry| entry.map(|(key, pointer)| (key.to_be_bytes().to_vec(), pointer)))
+				.collect::<Result<Vec<_>, _>>()?,
+			TreeKind::VarBTree => VarBTree::new(entry.root).entries(t)?,
+		};
+		for batch in entries.chunks(BATCH_SIZE) {
+			let pointers: Vec<_> = batch.iter().map(|(_, pointer)| *pointer).collect();
+			let batch_records = records.get_records(t, &pointers)?;
+			for ((key, _), record) in batch.iter().zip(batch_records) {
+				let Some(record) = record else {
+					continue;
+				};
+				writer
+					.write_entry(EntryKind::Record, key, &record)
+					.map_err(StorageError::from)?;
+				num_records += 1;
+			}
+		}
+	}
+	writer.finish().map_err(StorageError::from)?;
+	Ok(num_records)
+}
+
+/// Creates the trees of a dump that was written with [`dump`] in the
+/// catalog, inserts their records into `records`, and returns the number of
+/// records. Fails if one of the trees exists already.
+///
+/// Everything is loaded in the transaction, so a dump that turns out to be
+/// corrupted leaves nothing behind once the transaction is aborted.
+pub(super) fn load(
+	t: &mut impl TransactionApi,
+	catalog: &Catalog,
+	records: &RecordManager,
+	reader: impl Read,
+) -> Result<usize, DatabaseError> {
+	let mut reader = DumpReader::new(reader).map_err(StorageError::from)?;
+	let mut key = Vec::new();
+	let mut data = Vec::new();
+	let mut tree = None;
+	let mut num_records = 0;
+	while let Some(kind) = reader
+		.next_entry(&mut key, &mut data)
+		.map_err(StorageError::from)?
+	{
+		match kind {
+			EntryKind::Tree => {
+				let definition = TreeDefinitionRepr::read_from(data.as_slice())
+					.ok_or_else(|| corrupted("Malformed tree definition"))?;
+				let kind = TreeKind::from_repr(definition.kind)
+					.ok_or_else(|| corrupted("Unknown tree kind"))?;
+				let order = KeyOrder::from_repr(definition.order, definition.custom_order)
+					.ok_or_else(|| corrupted("Unknown key order"))?;
+				let name = String::from_utf8(key.clone())?;
+				catalog.create_tree(t, &name, kind, order)?;
+				let entry = catalog.get(t, &name)?.expect("The tree was just created");
+				tree = Some(match kind {
+					TreeKind::BTree => TargetTree::BTree(entry.b_tree()),
+					TreeKind::VarBTree => TargetTree::VarBTree(catalog.var_b_tree(&entry)?),
+				});
+			}
+			EntryKind::Record => {
+				let Some(tree) = &tree else {
+					return Err(corrupted("A record precedes the first tree"));
+				};
+				let pointer = records.insert_record(t, &data)?;
+				match tree {
+					TargetTree::BTree(tree) => {
+						let key = <[u8; 8]>::try_from(key.as_slice()).map_err(|_| {
+							corrupted("A key of a tree with fixed-size keys isn't 8 bytes long")
+						})?;
+						tree.insert(t, u64::from_be_bytes(key), pointer)?;
+					}
+					TargetTree::VarBTree(tree) => {
+						tree.insert(t, &key, pointer)?;
+					}
+				}
+				num_records += 1;
+			}
+			EntryKind::End => unreachable!("The end of the dump is read as `None`"),
+		}
+	}
+	Ok(num_records)
+}
+
+fn corrupted(reason: &str) -> DatabaseError {
+	StorageError::from(FileError::Corrupted(format!("Invalid dump: {reason}"))).into()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::page_alloc::PageAllocator,
+		page_store::{test_helpers::temp_storage, PageStorageApi},
+	};
+
+	use super::*;
+
+	fn init_store(t: &mut impl TransactionApi) -> (Catalog, RecordManager) {
+		PageAllocator::init(t).unwrap();
+		let catalog = Catalog::default();
+		catalog.init(t).unwrap();
+		let records = RecordManager::new(PageAllocator::alloc(t).unwrap());
+		records.init(t).unwrap();
+		(catalog, records)
+	}
+
+	#[test]
+	fn dump_and_load_trees_into_different_layout() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		let (catalog, records) = init_store(&mut t);
+		catalog
+			.create_tree(&mut t, "ids", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		catalog
+			.create_tree(&mut t, "names", TreeKind::VarBTree, KeyOrder::Numeric)
+			.unwrap();
+		let ids = catalog.get(&mut t, "ids").unwrap().unwrap().b_tree();
+		let names = catalog.get(&mut t, "names").unwrap().unwrap();
+		let names = catalog.var_b_tree(&names).unwrap();
+		for id in 0..300_u64 {
+			let pointer = records
+				.insert_record(&mut t, &id.to_le_bytes().repeat(20))
+				.unwrap();
+			ids.insert(&mut t, id, pointer).unwrap();
+			let pointer = records.insert_record(&mut t, &[1; 5000]).unwrap();
+			names
+				.insert(&mut t, id.to_string().as_bytes(), pointer)
+				.unwrap();
+		}
+		let deleted = ids.search(&mut t, 7).unwrap().unwrap();
+		records.delete_record(&mut t, deleted).unwrap();
+		let (_target_tempdir, target) = temp_storage(&Default::default());
+		let mut target_t = target.transaction().unwrap();
+		let (target_catalog, target_records) = init_store(&mut target_t);
+		for _ in 0..100 {
+			target_records
+				.insert_record(&mut target_t, &[2; 3000])
+				.unwrap();
+		}
+
+		// when
+		let mut dump_data = Vec::new();
+		let num_dumped = dump(&mut t, &catalog, &records, &mut dump_data).unwrap();
+		let num_loaded = load(
+			&mut target_t,
+			&target_catalog,
+			&target_records,
+			dump_data.as_slice(),
+		)
+		.unwrap();
+
+		// then
+		assert_eq!(num_dumped, 599);
+		assert_eq!(num_loaded, 599);
+		let entries = target_catalog.entries(&mut target_t).unwrap();
+		assert_eq!(entries["ids"].kind, TreeKind::BTree);
+		assert_eq!(entries["names"].kind, TreeKind::VarBTree);
+		assert_eq!(entries["names"].order, KeyOrder::Numeric);
+		let target_ids = entries["ids"].b_tree();
+		assert_eq!(target_ids.search(&mut target_t, 7).unwrap(), None);
+		let pointer = target_ids.search(&mut target_t, 42).unwrap().unwrap();
+		assert_ne!(Some(pointer), ids.search(&mut t, 42).unwrap());
+		assert_eq!(
+			target_records.get_record(&mut target_t, pointer).unwrap(),
+			Some(42_u64.to_le_bytes().repeat(20))
+		);
+		let target_names = target_catalog.var_b_tree(&entries["names"]).unwrap();
+		let keys: Vec<Vec<u8>> = target_names
+			.entries(&mut target_t)
+			.unwrap()
+			.into_iter()
+			.map(|(key, _)| key)
+			.collect();
+		let expected_keys: Vec<Vec<u8>> =
+			(0..300_u64).map(|id| id.to_string().into_bytes()).collect();
+		assert_eq!(keys, expected_keys);
+		let pointer = target_names.search(&mut target_t, b"299").unwrap().unwrap();
+		assert_eq!(
+			target_records.get_record(&mut target_t, pointer).unwrap(),
+			Some(vec![1; 5000])
+		);
+		t.commit().unwrap();
+		target_t.commit().unwrap();
+	}
+
+	#[test]
+	fn reject_corrupted_dumps() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		let (catalog, records) = init_store(&mut t);
+		let mut writer = DumpWriter::new(Vec::new()).unwrap();
+		writer
+			.write_entry(EntryKind::Record, b"orphan", &[1])
+			.unwrap();
+		let orphan_record = writer.finish().unwrap();
+		let mut dump_data = Vec::new();
+		catalog
+			.create_tree(&mut t, "ids", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		dump(&mut t, &catalog, &records, &mut dump_data).unwrap();
+
+		// when
+		let orphan_result = load(&mut t, &catalog, &records, orphan_record.as_slice());
+		let existing_result = load(&mut t, &catalog, &records, dump_data.as_slice());
+
+		// then
+		assert!(matches!(
+			orphan_result,
+			Err(DatabaseError::Storage(StorageError::File(
+				FileError::Corrupted(..)
+			)))
+		));
+		assert!(matches!(
+			existing_result,
+			Err(DatabaseError::TreeExists(..))
+		));
+		assert_eq!(catalog.entries(&mut t).unwrap().len(), 1);
+		t.commit().unwrap();
+	}
+}
@@ -0,0 +1,364 @@
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+use std::{
+	io::{BufRead, Write},
+	num::NonZeroU16,
+};
+
+use crate::page_store::{PageId, TransactionApi};
+
+use super::{b_tree::BTree, DatabaseError, DbPointer};
+
+const CSV_HEADER: &str = "key,segment,page,index";
+
+/// Text formats that B-tree entries can be exported to and imported from.
+/// With the `sqlite` feature, they can be exported to SQLite databases as
+/// well, see [`export_sqlite`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ExportFormat {
+	/// Comma-separated values with a `key,segment,page,index` header line.
+	Csv,
+	/// One JSON object per line, with the fields `key`, `segment`, `page`
+	/// and `index`.
+	JsonLines,
+}
+
+/// Writes all entries of the tree in key order, and returns how many there
+/// were.
+pub(super) fn export(
+	t: &mut impl TransactionApi,
+	tree: &BTree,
+	format: ExportFormat,
+	mut writer: impl Write,
+) -> Result<usize, DatabaseError> {
+	if format == ExportFormat::Csv {
+		writeln!(writer, "{CSV_HEADER}")?;
+	}
+	let mut num_entries = 0;
+	for entry in tree.range(t, ..) {
+		let (key, pointer) = entry?;
+		let page_id = pointer.page_id();
+		match format {
+			ExportFormat::Csv => writeln!(
+				writer,
+				"{key},{},{},{}",
+				page_id.segment_num,
+				page_id.page_num,
+				pointer.index()
+			)?,
+			ExportFormat::JsonLines => writeln!(
+				writer,
+				r#"{{"key":{key},"segment":{},"page":{},"index":{}}}"#,
+				page_id.segment_num,
+				page_id.page_num,
+				pointer.index()
+			)?,
+		}
+		num_entries += 1;
+	}
+	writer.flush()?;
+	Ok(num_entries)
+}
+
+/// Inserts the entries read from `reader` into the tree, replacing existing
+/// entries with the same keys, and returns how many there were.
+pub(super) fn import(
+	t: &mut impl TransactionApi,
+	tree: &BTree,
+	format: ExportFormat,
+	reader: impl BufRead,
+) -> Result<usize, DatabaseError> {
+	let mut num_entries = 0;
+	for (index, line) in reader.lines().enumerate() {
+		let line = line?;
+		let line_num = index + 1;
+		if line.trim().is_empty() || (format == ExportFormat::Csv && line.trim() == CSV_HEADER) {
+			continue;
+		}
+		let (key, pointer) = match format {
+			ExportFormat::Csv => parse_csv_entry(&line),
+			ExportFormat::JsonLines => parse_json_entry(&line),
+		}
+		.map_err(|reason| DatabaseError::Import {
+			line: line_num,
+			reason,
+		})?;
+		tree.insert(t, key, pointer)?;
+		num_entries += 1;
+	}
+	Ok(num_entries)
+}
+
+/// Writes all entries of the tree into the table `table` of the SQLite
+/// database at `path`, and returns how many there were. The database is
+/// created if it doesn't exist yet, and so is the table, with the columns
+/// `key`, `segment`, `page` and `index`; entries with the same keys as rows
+/// that are already in it replace those.
+///
+/// SQLite stores integers as signed 64-bit values, so keys above
+/// [`i64::MAX`] can't be exported.
+#[cfg(feature = "sqlite")]
+pub(super) fn export_sqlite(
+	t: &mut impl TransactionApi,
+	tree: &BTree,
+	path: &Path,
+	table: &str,
+) -> Result<usize, DatabaseError> {
+	let mut connection = rusqlite::Connection::open(path)?;
+	let sql_transaction = connection.transaction()?;
+	let table = quote_identifier(table);
+	sql_transaction.execute(
+		&format!(
+			r#"CREATE TABLE IF NOT EXISTS {table} (
+				key INTEGER PRIMARY KEY,
+				segment INTEGER NOT NULL,
+				page INTEGER NOT NULL,
+				"index" INTEGER NOT NULL
+			)"#
+		),
+		(),
+	)?;
+	let mut num_entries = 0;
+	{
+		let mut insert = sql_transaction.prepare(&format!(
+			r#"INSERT OR REPLACE INTO {table} (key, segment, page, "index") VALUES (?1, ?2, ?3, ?4)"#
+		))?;
+		for entry in tree.range(t, ..) {
+			let (key, pointer) = entry?;
+			let page_id = pointer.page_id();
+			insert.execute((
+				key,
+				page_id.segment_num,
+				page_id.page_num.get(),
+				pointer.index(),
+			))?;
+			num_entries += 1;
+		}
+	}
+	sql_transaction.commit()?;
+	Ok(num_entries)
+}
+
+/// Inserts the rows of the table `table` of the SQLite database at `path`
+/// into the tree, like [`import`], and returns how many there were. The
+/// table needs the columns of [`export_sqlite`]; the line numbers of import
+/// errors count its rows in key order.
+#[cfg(feature = "sqlite")]
+pub(super) fn import_sqlite(
+	t: &mut impl TransactionApi,
+	tree: &BTree,
+	path: &Path,
+	table: &str,
+) -> Result<usize, DatabaseError> {
+	let connection =
+		rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+	let mut select = connection.prepare(&format!(
+		r#"SELECT key, segment, page, "index" FROM {} ORDER BY key"#,
+		quote_identifier(table)
+	))?;
+	let mut rows = select.query(())?;
+	let mut num_entries = 0;
+	while let Some(row) = rows.next()? {
+		let key: u64 = row.get(0)?;
+		let segment: u32 = row.get(1)?;
+		let page: u16 = row.get(2)?;
+		let index: u16 = row.get(3)?;
+		let Some(page) = NonZeroU16::new(page) else {
+			return Err(DatabaseError::Import {
+				line: num_entries + 1,
+				reason: "Invalid page number `0`".to_string(),
+			});
+		};
+		tree.insert(t, key, DbPointer::new(PageId::new(segment, page), index))?;
+		num_entries += 1;
+	}
+	Ok(num_entries)
+}
+
+/// Quotes a table name for SQL, so that it can contain any character.
+#[cfg(feature = "sqlite")]
+fn quote_identifier(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn parse_csv_entry(line: &str) -> Result<(u64, DbPointer), String> {
+	let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+	let [key, segment, page, index] = fields.as_slice() else {
+		return Err(format!("Expected 4 fields, but found {}", fields.len()));
+	};
+	entry_from_fields(key, segment, page, index)
+}
+
+/// Parses a flat JSON object with unsigned integer fields. Anything more
+/// complex is rejected, since the export never produces it.
+fn parse_json_entry(line: &str) -> Result<(u64, DbPointer), String> {
+	let Some(body) = line
+		.trim()
+		.strip_prefix('{')
+		.and_then(|line| line.strip_suffix('}'))
+	else {
+		return Err("Expected a JSON object".to_string());
+	};
+
+	let [mut key, mut segment, mut page, mut index] = [None; 4];
+	for field in body.split(',') {
+		let Some((name, value)) = field.split_once(':') else {
+			return Err(format!("Invalid field `{}`", field.trim()));
+		};
+		let slot = match name.trim() {
+			r#""key""# => &mut key,
+			r#""segment""# => &mut segment,
+			r#""page""# => &mut page,
+			r#""index""# => &mut index,
+			other => return Err(format!("Unknown field {other}")),
+		};
+		*slot = Some(value.trim());
+	}
+	entry_from_fields(
+		required_field(key, "key")?,
+		required_field(segment, "segment")?,
+		required_field(page, "page")?,
+		required_field(index, "index")?,
+	)
+}
+
+fn required_field<'a>(value: Option<&'a str>, name: &str) -> Result<&'a str, String> {
+	value.ok_or_else(|| format!("Missing field \"{name}\""))
+}
+
+fn entry_from_fields(
+	key: &str,
+	segment: &str,
+	page: &str,
+	index: &str,
+) -> Result<(u64, DbPointer), String> {
+	let key: u64 = key.parse().map_err(|_| format!("Invalid key `{key}`"))?;
+	let segment: u32 = segment
+		.parse()
+		.map_err(|_| format!("Invalid segment number `{segment}`"))?;
+	let page: NonZeroU16 = page
+		.parse()
+		.map_err(|_| format!("Invalid page number `{page}`"))?;
+	let index: u16 = index
+		.parse()
+		.map_err(|_| format!("Invalid index `{index}`"))?;
+	Ok((key, DbPointer::new(PageId::new(segment, page), index)))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::page_alloc::PageAllocator,
+		page_store::{
+			test_helpers::{page_id, temp_storage},
+			PageStorageApi,
+		},
+	};
+
+	use super::*;
+
+	#[test]
+	fn export_and_import() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let source = BTree::new(page_id!(1, 1));
+		source.init(&mut t).unwrap();
+		source
+			.insert(&mut t, 7, DbPointer::new(page_id!(2, 3), 4))
+			.unwrap();
+		source
+			.insert(&mut t, 1, DbPointer::new(page_id!(5, 6), 0))
+			.unwrap();
+
+		// when
+		let mut csv = Vec::new();
+		export(&mut t, &source, ExportFormat::Csv, &mut csv).unwrap();
+		let mut json = Vec::new();
+		export(&mut t, &source, ExportFormat::JsonLines, &mut json).unwrap();
+		let target = BTree::new(page_id!(1, 2));
+		target.init(&mut t).unwrap();
+		let num_imported =
+			import(&mut t, &target, ExportFormat::JsonLines, json.as_slice()).unwrap();
+
+		// then
+		assert_eq!(
+			String::from_utf8(csv.clone()).unwrap(),
+			"key,segment,page,index\n1,5,6,0\n7,2,3,4\n"
+		);
+		assert_eq!(
+			String::from_utf8(json).unwrap(),
+			"{\"key\":1,\"segment\":5,\"page\":6,\"index\":0}\n\
+			 {\"key\":7,\"segment\":2,\"page\":3,\"index\":4}\n"
+		);
+		assert_eq!(num_imported, 2);
+		let mut reexported = Vec::new();
+		export(&mut t, &target, ExportFormat::Csv, &mut reexported).unwrap();
+		assert_eq!(reexported, csv);
+		t.commit().unwrap();
+	}
+
+	#[cfg(feature = "sqlite")]
+	#[test]
+	fn export_and_import_sqlite() {
+		// given
+		let (tempdir, storage) = temp_storage(&Default::default());
+		let path = tempdir.path().join("export.sqlite");
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let source = BTree::new(page_id!(1, 1));
+		source.init(&mut t).unwrap();
+		source
+			.insert(&mut t, 7, DbPointer::new(page_id!(2, 3), 4))
+			.unwrap();
+		source
+			.insert(&mut t, 1, DbPointer::new(page_id!(5, 6), 0))
+			.unwrap();
+
+		// when
+		let num_exported = export_sqlite(&mut t, &source, &path, "odd \"name\"").unwrap();
+		let target = BTree::new(page_id!(1, 2));
+		target.init(&mut t).unwrap();
+		let num_imported = import_sqlite(&mut t, &target, &path, "odd \"name\"").unwrap();
+
+		// then
+		assert_eq!(num_exported, 2);
+		assert_eq!(num_imported, 2);
+		let mut source_csv = Vec::new();
+		export(&mut t, &source, ExportFormat::Csv, &mut source_csv).unwrap();
+		let mut target_csv = Vec::new();
+		export(&mut t, &target, ExportFormat::Csv, &mut target_csv).unwrap();
+		assert_eq!(target_csv, source_csv);
+		t.commit().unwrap();
+	}
+
+	#[cfg(feature = "sqlite")]
+	#[test]
+	fn reject_keys_out_of_sqlite_range() {
+		// given
+		let (tempdir, storage) = temp_storage(&Default::default());
+		let path = tempdir.path().join("export.sqlite");
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let tree = BTree::new(page_id!(1, 1));
+		tree.init(&mut t).unwrap();
+		tree.insert(&mut t, u64::MAX, DbPointer::new(page_id!(2, 3), 4))
+			.unwrap();
+
+		// when
+		let result = export_sqlite(&mut t, &tree, &path, "entries");
+
+		// then
+		assert!(matches!(result, Err(DatabaseError::Sqlite(_))));
+	}
+
+	#[test]
+	fn reject_invalid_lines() {
+		// expect
+		assert!(parse_csv_entry("1,2,0,3").is_err());
+		assert!(parse_csv_entry("1,2,3").is_err());
+		assert!(parse_json_entry(r#"{"key":1,"segment":2,"page":3}"#).is_err());
+		assert!(parse_json_entry(r#"{"key":"a","segment":2,"page":3,"index":4}"#).is_err());
+	}
+}
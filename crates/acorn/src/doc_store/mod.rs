@@ -1,4 +1,4 @@
-use std::{num::NonZero, string::FromUtf8Error};
+use std::{io, num::NonZero, string::FromUtf8Error};
 
 use document::SchemaError;
 use pages::PageKind;
@@ -6,10 +6,27 @@ use thiserror::Error;
 
 use crate::page_store::{PageId, StorageError};
 
+#[cfg(feature = "fuzzing")]
+pub(crate) use self::pages::fuzz_btree_page;
+
+mod amplification;
+mod b_tree;
+mod canonical;
+mod catalog;
+mod clustering;
 mod document;
 mod document_repr;
+mod dump;
+mod interop;
+mod overflow;
 mod page_alloc;
 mod pages;
+mod provenance;
+mod rebuild;
+mod records;
+mod scan_token;
+mod split_policy;
+mod var_b_tree;
 
 #[derive(Debug, Error)]
 pub(crate) enum DatabaseError {
@@ -28,16 +45,74 @@ pub(crate) enum DatabaseError {
 	#[error("Tried to insert data to a page out of bounds")]
 	PageIndexOutOfBounds,
 
+	#[error("Invalid scan token")]
+	InvalidScanToken,
+
+	#[error("The scan token belongs to a different tree")]
+	ScanTokenMismatch,
+
+	#[error("The scan token was issued as of commit {expected}, but the transaction reads as of {actual:?}")]
+	ScanSnapshotMismatch { expected: u64, actual: Option<u64> },
+
+	#[error("All page IDs of the last possible segment are allocated")]
+	PageIdsExhausted,
+
 	#[error(transparent)]
 	StringEncoding(#[from] FromUtf8Error),
 
 	#[error(transparent)]
 	Schema(#[from] SchemaError),
 
+	#[error("Key of length {len} exceeds the maximum key length {max}")]
+	KeyTooLong { len: usize, max: usize },
+
+	#[error("Bulk-loaded keys must be strictly ascending, but {key} follows {prev}")]
+	UnsortedKeys { prev: u64, key: u64 },
+
+	#[error("Only empty trees can be bulk-loaded")]
+	TreeNotEmpty,
+
+	#[error("There is no record at {0:?}")]
+	RecordNotFound(DbPointer),
+
+	#[error("Record of length {0} exceeds the maximum record length")]
+	RecordTooLarge(usize),
+
+	#[error("A tree named '{0}' already exists")]
+	TreeExists(String),
+
+	#[error("There is no tree named '{0}'")]
+	TreeNotFound(String),
+
+	#[error("The tree '{0}' was replaced while it was being rebuilt")]
+	TreeReplaced(String),
+
+	#[error("No comparator is registered for the custom key order {0}")]
+	UnknownKeyOrder(u8),
+
+	#[error("The tree '{0}' has fixed-size keys, so it can't have a custom key order")]
+	UnsupportedKeyOrder(String),
+
+	#[error("Version {0} of the catalog is corrupted; its checksum does not match its contents")]
+	CatalogChecksumMismatch(u64),
+
+	#[error("Invalid import data on line {line}: {reason}")]
+	Import { line: usize, reason: String },
+
+	#[error(transparent)]
+	Io(#[from] io::Error),
+
+	#[cfg(feature = "sqlite")]
+	#[error(transparent)]
+	Sqlite(#[from] rusqlite::Error),
+
 	#[error(transparent)]
 	Storage(#[from] StorageError),
 }
 
+/// A leaf of a tree, with its page and its entries in key order.
+pub(super) type Leaf<K> = (PageId, Vec<(K, DbPointer)>);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct DbPointer {
 	segment_num: u32,
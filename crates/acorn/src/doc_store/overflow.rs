@@ -0,0 +1,124 @@
+use crate::page_store::{PageId, TransactionApi};
+
+use super::{
+	page_alloc::PageAllocator,
+	pages::{OverflowPage, OverflowRef},
+	DatabaseError,
+};
+
+/// Writes a value to a new chain of overflow pages, and returns a reference to
+/// it. A value may span any number of pages, as long as its length fits in 32
+/// bits.
+pub(super) fn write(
+	t: &mut impl TransactionApi,
+	data: &[u8],
+) -> Result<OverflowRef, DatabaseError> {
+	let Ok(length) = u32::try_from(data.len()) else {
+		return Err(DatabaseError::RecordTooLarge(data.len()));
+	};
+
+	// Pages are written back to front, so that each knows its successor.
+	let mut next_page_id = None;
+	for chunk in data.chunks(OverflowPage::<()>::CAPACITY).rev() {
+		let page_id = PageAllocator::alloc(t)?;
+		OverflowPage::new_unchecked(t.get_page_mut(page_id)?).init(next_page_id, chunk)?;
+		next_page_id = Some(page_id);
+	}
+	let first_page = match next_page_id {
+		Some(page_id) => page_id,
+		None => {
+			let page_id = PageAllocator::alloc(t)?;
+			OverflowPage::new_unchecked(t.get_page_mut(page_id)?).init(None, &[])?;
+			page_id
+		}
+	};
+	Ok(OverflowRef { length, first_page })
+}
+
+/// Reassembles a value from its chain of overflow pages.
+pub(super) fn read(
+	t: &mut impl TransactionApi,
+	overflow: OverflowRef,
+) -> Result<Vec<u8>, DatabaseError> {
+	let mut data = Vec::with_capacity(overflow.length as usize);
+	let mut page_id = Some(overflow.first_page);
+	while let Some(current) = page_id {
+		let page = OverflowPage::new(t.get_page(current)?)?;
+		page.read_data(&mut data)?;
+		page_id = page.get_next_page_id()?;
+	}
+	if data.len() != overflow.length as usize {
+		return Err(DatabaseError::PageFormat(format!(
+			"Expected an overflow value of length {}, but found {}",
+			overflow.length,
+			data.len()
+		)));
+	}
+	Ok(data)
+}
+
+/// Returns the pages of the chain, in order.
+pub(super) fn pages(
+	t: &mut impl TransactionApi,
+	overflow: OverflowRef,
+) -> Result<Vec<PageId>, DatabaseError> {
+	let mut pages = Vec::new();
+	let mut page_id = Some(overflow.first_page);
+	while let Some(current) = page_id {
+		pages.push(current);
+		page_id = OverflowPage::new(t.get_page(current)?)?.get_next_page_id()?;
+	}
+	Ok(pages)
+}
+
+/// Returns all pages of the chain to the page allocator.
+pub(super) fn free(
+	t: &mut impl TransactionApi,
+	overflow: OverflowRef,
+) -> Result<(), DatabaseError> {
+	let mut page_id = Some(overflow.first_page);
+	while let Some(current) = page_id {
+		page_id = OverflowPage::new(t.get_page(current)?)?.get_next_page_id()?;
+		PageAllocator::free(t, current)?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::page_store::{test_helpers::temp_storage, PageId, PageStorageApi};
+
+	use super::*;
+
+	#[test]
+	fn write_read_and_free() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let capacity = OverflowPage::<()>::CAPACITY;
+		let values: Vec<Vec<u8>> = [0, 1, capacity, 2 * capacity + 1]
+			.into_iter()
+			.map(|len| (0..len).map(|i| (i % 251) as u8).collect())
+			.collect();
+
+		// when
+		let refs: Vec<OverflowRef> = values
+			.iter()
+			.map(|value| write(&mut t, value).unwrap())
+			.collect();
+		let last = *refs.last().unwrap();
+		free(&mut t, last).unwrap();
+		let reused: Vec<PageId> = (0..3)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+
+		// then
+		for (value, overflow) in values.iter().zip(&refs[..refs.len() - 1]) {
+			assert_eq!(&read(&mut t, *overflow).unwrap(), value);
+		}
+		assert_eq!(last.length as usize, 2 * capacity + 1);
+		assert!(reused.contains(&last.first_page));
+		t.commit().unwrap();
+	}
+}
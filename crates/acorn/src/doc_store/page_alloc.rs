@@ -1,38 +1,195 @@
-use std::{mem, num::NonZero};
+use std::{collections::HashSet, mem, num::NonZero, ops::Range};
 
-use crate::page_store::{PageId, TransactionApi};
+use crate::{
+	files::{segment::PAGE_BODY_SIZE, WalIndex},
+	page_store::{PageId, PageStorageApi, ReadPage, TransactionApi},
+	trace::event,
+};
 
 use super::{
-	pages::{FreelistPage, MetaPage},
+	pages::{self, FreeSpaceMapPage, FreelistPage, MetaPage, PageKind},
 	DatabaseError,
 };
 
-struct PageAllocator;
+/// Allocates pages in shards, so that concurrent transactions don't all
+/// have to lock the same meta page to allocate.
+///
+/// Each transaction allocates from and frees to the shard chosen by its ID.
+/// Shard 0 uses the global meta page, which tracks the end of the allocated
+/// pages and takes new pages from there directly. The other shards each
+/// have their own meta page with a freelist and a range of new pages that
+/// they reserve from the global meta page in batches, so that they only
+/// rarely have to lock it.
+///
+/// The page right after the meta pages is never allocated, and holds the
+/// root of the [`Catalog`](super::catalog::Catalog) instead.
+pub(super) struct PageAllocator;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct VacuumStats {
+	/// The number of pages the end of the allocated pages moved back by.
+	pub pages_trimmed: usize,
+	/// The number of live pages that were moved to free pages further to the
+	/// front.
+	pub pages_relocated: usize,
+	/// The number of bytes by which the segment files shrank.
+	pub bytes_reclaimed: u64,
+}
+
+struct VacuumShard {
+	meta_page_id: PageId,
+	free_pages: HashSet<PageId>,
+	next_page_id: PageId,
+	reserved_end: PageId,
+	trimmed_reserved: bool,
+	trimmed_free: bool,
+}
 
 impl PageAllocator {
 	const META_PAGE_ID: PageId = PageId::new_unwrap(0, 1);
+	const NUM_SHARDS: u16 = 4;
+	const RESERVE_BATCH_SIZE: usize = 32;
+	pub const CATALOG_PAGE_ID: PageId = PageId::new_unwrap(0, 1 + Self::NUM_SHARDS);
 
 	pub fn init(t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
+		let first_page = Self::first_page();
+		let mut reserved_end = first_page;
+		for shard in 1..Self::NUM_SHARDS {
+			let reserved_start = reserved_end;
+			reserved_end = Self::page_id_plus(reserved_start, Self::RESERVE_BATCH_SIZE);
+			MetaPage::new_unchecked(t.get_page_mut(Self::shard_meta_page_id(shard))?)
+				.init_shard(reserved_start..reserved_end)?;
+		}
+
 		let mut meta_page = MetaPage::new_unchecked(t.get_page_mut(Self::META_PAGE_ID)?);
-		meta_page.init(Self::page_id_after(Self::META_PAGE_ID))?;
+		meta_page.init(reserved_end)?;
 		Ok(())
 	}
 
+	/// Allocates a page, which reads as all zeroes until it is written to.
 	pub fn alloc(t: &mut impl TransactionApi) -> Result<PageId, DatabaseError> {
-		if let Some(free_page) = Self::next_free_page(t)? {
+		let shard_page_id = Self::transaction_shard(t);
+		if let Some(free_page) = Self::next_free_page(t, shard_page_id)? {
+			// Reused pages still hold what was written to them before they
+			// were freed.
+			pages::clear_page(&mut t.get_page_mut(free_page)?)?;
 			return Ok(free_page);
 		}
-		Self::next_uninit_page(t)
+		if shard_page_id == Self::META_PAGE_ID {
+			return Ok(Self::next_uninit_pages(t, 1)?.start);
+		}
+		Self::next_reserved_page(t, shard_page_id)
+	}
+
+	/// Allocates `num_pages` pages that directly follow each other within a
+	/// segment, so that they can be read and written sequentially. The pages
+	/// read as all zeroes until they are written to. Returns the range from
+	/// the first page to the page after the last one.
+	///
+	/// Extents are always taken from the end of the allocated pages, since
+	/// the freelists don't keep track of which free pages are contiguous. If
+	/// the extent doesn't fit in the rest of the last segment, it starts at
+	/// the next segment, and the skipped pages are put on the freelist.
+	pub fn alloc_extent(
+		t: &mut impl TransactionApi,
+		num_pages: NonZero<u16>,
+	) -> Result<Range<PageId>, DatabaseError> {
+		let mut start = Self::meta_page(t, Self::META_PAGE_ID)?.get_next_page_id()?;
+		let pages_left = u16::MAX - start.page_num.get() + 1;
+		if pages_left < num_pages.get() {
+			let skipped = Self::next_uninit_pages(t, pages_left.into())?;
+			start = skipped.end;
+			Self::free_extent(t, skipped)?;
+		}
+		let extent = Self::next_uninit_pages(t, num_pages.get().into())?;
+		debug_assert_eq!(extent.start, start);
+		Ok(extent)
 	}
 
+	/// Frees the pages of an extent, see [`PageAllocator::alloc_extent`].
+	/// They are put on the freelist in reverse order, so that they are
+	/// allocated again in ascending order.
+	pub fn free_extent(
+		t: &mut impl TransactionApi,
+		extent: Range<PageId>,
+	) -> Result<(), DatabaseError> {
+		let mut page_id = extent.end;
+		while page_id > extent.start {
+			page_id = Self::page_id_before(page_id);
+			Self::free(t, page_id)?;
+		}
+		Ok(())
+	}
+
+	/// Frees a page. Until it is allocated again, reading it as the kind of
+	/// page it was fails.
 	pub fn free(t: &mut impl TransactionApi, page_id: PageId) -> Result<(), DatabaseError> {
-		let meta_page = Self::meta_page(t)?;
-		if let Some(freelist_head_id) = meta_page.get_freelist_head()? {
-			mem::drop(meta_page);
+		let shard_page_id = Self::transaction_shard(t);
+		let freelist_head = Self::meta_page(t, shard_page_id)?.get_freelist_head()?;
+		// The free-space map and the poisoning flag are shared by all shards.
+		let global_meta_page = Self::meta_page(t, Self::META_PAGE_ID)?;
+		let free_space_map_head = global_meta_page.get_free_space_map_head()?;
+		let poison = global_meta_page.get_poison_freed()?;
+		mem::drop(global_meta_page);
+
+		Self::untrack_free_space(t, free_space_map_head, page_id)?;
+		Self::push_free(t, shard_page_id, freelist_head, page_id, poison)
+	}
 
+	/// Sets whether pages that are freed from now on are poisoned: filled
+	/// with a recognizable pattern, in the same transaction, so that
+	/// [`PageAllocator::damaged_free_pages`] can detect writes to them through
+	/// dangling page IDs. Pages that become blocks of a freelist are not
+	/// poisoned. This is meant for debugging higher layers, since each freed
+	/// page is written to the WAL in full. The setting is stored in the
+	/// database.
+	pub fn set_poison_freed(
+		t: &mut impl TransactionApi,
+		poison: bool,
+	) -> Result<(), DatabaseError> {
+		Self::meta_page_mut(t, Self::META_PAGE_ID)?.set_poison_freed(poison)
+	}
+
+	/// The poisoned pages on the freelists whose poison pattern was
+	/// overwritten since they were freed, which means that something still
+	/// wrote to them afterwards.
+	pub fn damaged_free_pages(t: &mut impl TransactionApi) -> Result<Vec<PageId>, DatabaseError> {
+		let mut damaged = Vec::new();
+		for shard in 0..Self::NUM_SHARDS {
+			let shard_page_id = Self::shard_meta_page_id(shard);
+			for page_id in Self::free_pages(t, shard_page_id)? {
+				let page = t.get_page(page_id)?;
+				let mut kind = [0];
+				page.read(0, &mut kind)?;
+				if PageKind::from(kind[0]) == Some(PageKind::Poisoned)
+					&& !pages::is_poison_intact(&page)?
+				{
+					damaged.push(page_id);
+				}
+			}
+		}
+		damaged.sort_unstable();
+		Ok(damaged)
+	}
+
+	fn push_free(
+		t: &mut impl TransactionApi,
+		shard_page_id: PageId,
+		freelist_head: Option<PageId>,
+		page_id: PageId,
+		poison: bool,
+	) -> Result<(), DatabaseError> {
+		if let Some(freelist_head_id) = freelist_head {
 			let mut freelist_head = FreelistPage::new(t.get_page_mut(freelist_head_id)?)?;
-			if !freelist_head.is_full()? {
+			if freelist_head.can_push(page_id)? {
 				freelist_head.push_item(page_id)?;
+				mem::drop(freelist_head);
+				let mut page = t.get_page_mut(page_id)?;
+				if poison {
+					pages::poison(&mut page)?;
+				} else {
+					pages::mark_free(&mut page)?;
+				}
 			} else {
 				mem::drop(freelist_head);
 
@@ -41,24 +198,490 @@ impl PageAllocator {
 				new_freelist_head.set_next_page_id(Some(freelist_head_id))?;
 				mem::drop(new_freelist_head);
 
-				let mut meta_page = Self::meta_page_mut(t)?;
+				let mut meta_page = Self::meta_page_mut(t, shard_page_id)?;
 				meta_page.set_freelist_head(Some(page_id))?;
 			}
 			return Ok(());
 		}
-		mem::drop(meta_page);
 
 		let mut freelist_head = FreelistPage::new_unchecked(t.get_page_mut(page_id)?);
 		freelist_head.init()?;
 		mem::drop(freelist_head);
 
-		let mut meta_page = Self::meta_page_mut(t)?;
+		let mut meta_page = Self::meta_page_mut(t, shard_page_id)?;
 		meta_page.set_freelist_head(Some(page_id))?;
 		Ok(())
 	}
 
-	fn next_free_page(t: &mut impl TransactionApi) -> Result<Option<PageId>, DatabaseError> {
-		let Some(freelist_head_id) = Self::meta_page(t)?.get_freelist_head()? else {
+	/// Records how many bytes of free space the page has left, so that
+	/// [`PageAllocator::find_page_with_space`] can find it. The amount is
+	/// rounded down to one of 256 levels. Freeing the page forgets it again.
+	pub fn set_free_space(
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		free_bytes: usize,
+	) -> Result<(), DatabaseError> {
+		let level = Self::space_level(free_bytes);
+
+		let mut page_with_room = None;
+		let mut next_map_page =
+			Self::meta_page(t, Self::META_PAGE_ID)?.get_free_space_map_head()?;
+		while let Some(map_page_id) = next_map_page {
+			let map_page = FreeSpaceMapPage::new(t.get_page(map_page_id)?)?;
+			if let Some(index) = map_page.find_entry(page_id)? {
+				mem::drop(map_page);
+				FreeSpaceMapPage::new(t.get_page_mut(map_page_id)?)?
+					.set_entry(index, page_id, level)?;
+				return Ok(());
+			}
+			if page_with_room.is_none() && !map_page.is_full()? {
+				page_with_room = Some(map_page_id);
+			}
+			next_map_page = map_page.get_next_page_id()?;
+		}
+
+		if let Some(map_page_id) = page_with_room {
+			FreeSpaceMapPage::new(t.get_page_mut(map_page_id)?)?.push_entry(page_id, level)?;
+			return Ok(());
+		}
+
+		let old_head = Self::meta_page(t, Self::META_PAGE_ID)?.get_free_space_map_head()?;
+		let new_head = Self::alloc(t)?;
+		let mut map_page = FreeSpaceMapPage::new_unchecked(t.get_page_mut(new_head)?);
+		map_page.init()?;
+		map_page.set_next_page_id(old_head)?;
+		map_page.push_entry(page_id, level)?;
+		mem::drop(map_page);
+
+		Self::meta_page_mut(t, Self::META_PAGE_ID)?.set_free_space_map_head(Some(new_head))?;
+		Ok(())
+	}
+
+	/// Returns a page that was recorded with at least `bytes` bytes of free
+	/// space, if there is one.
+	pub fn find_page_with_space(
+		t: &mut impl TransactionApi,
+		bytes: usize,
+	) -> Result<Option<PageId>, DatabaseError> {
+		if bytes > PAGE_BODY_SIZE {
+			return Ok(None);
+		}
+		// The smallest level that guarantees `bytes` bytes, rounding up.
+		let min_level = (bytes * usize::from(u8::MAX)).div_ceil(PAGE_BODY_SIZE);
+
+		let mut next_map_page =
+			Self::meta_page(t, Self::META_PAGE_ID)?.get_free_space_map_head()?;
+		while let Some(map_page_id) = next_map_page {
+			let map_page = FreeSpaceMapPage::new(t.get_page(map_page_id)?)?;
+			for index in 0..map_page.get_length()? {
+				let (page_id, level) = map_page.get_entry(index)?;
+				if usize::from(level) >= min_level {
+					return Ok(Some(page_id));
+				}
+			}
+			next_map_page = map_page.get_next_page_id()?;
+		}
+		Ok(None)
+	}
+
+	fn space_level(free_bytes: usize) -> u8 {
+		let level = free_bytes.min(PAGE_BODY_SIZE) * usize::from(u8::MAX) / PAGE_BODY_SIZE;
+		u8::try_from(level).unwrap()
+	}
+
+	fn untrack_free_space(
+		t: &mut impl TransactionApi,
+		free_space_map_head: Option<PageId>,
+		page_id: PageId,
+	) -> Result<(), DatabaseError> {
+		let mut next_map_page = free_space_map_head;
+		while let Some(map_page_id) = next_map_page {
+			let map_page = FreeSpaceMapPage::new(t.get_page(map_page_id)?)?;
+			if let Some(index) = map_page.find_entry(page_id)? {
+				mem::drop(map_page);
+				FreeSpaceMapPage::new(t.get_page_mut(map_page_id)?)?.remove_entry(index)?;
+				return Ok(());
+			}
+			next_map_page = map_page.get_next_page_id()?;
+		}
+		Ok(())
+	}
+
+	/// Returns every page that is neither reachable, part of a freelist or the
+	/// free-space map, reserved by a shard, nor an allocator meta page to the
+	/// freelist. Freeing happens in transactions of at most `batch_size`
+	/// pages, so that the collection can run while the database is in use.
+	///
+	/// `reachable` has to include every page that is reachable as of the WAL
+	/// index `reachable_at`, such as the position of the snapshot it was
+	/// computed from (see [`PageStorageApi::snapshot_with_position`]). Pages
+	/// that were allocated and written to since then are skipped, since they
+	/// may be reachable by now, and so are pages that another transaction is
+	/// writing to. Each batch locks the allocator's meta pages before it
+	/// checks its pages again, so that no page is allocated or freed
+	/// concurrently in between.
+	pub fn collect_garbage<S: PageStorageApi>(
+		storage: &S,
+		reachable: &HashSet<PageId>,
+		reachable_at: WalIndex,
+		batch_size: usize,
+	) -> Result<usize, DatabaseError> {
+		let orphans = Self::find_orphans(storage, reachable)?;
+		let shard_page_ids: Vec<PageId> = (0..Self::NUM_SHARDS)
+			.map(Self::shard_meta_page_id)
+			.collect();
+		let mut num_freed = 0;
+		for batch in orphans.chunks(usize::max(batch_size, 1)) {
+			let mut t = storage.transaction()?;
+			t.lock_pages(&shard_page_ids)?;
+			let unused_pages = Self::unused_pages(&mut t)?;
+			let modified_pages: HashSet<PageId> =
+				storage.modified_pages(reachable_at)?.into_iter().collect();
+			for page_id in batch.iter().copied() {
+				if unused_pages.contains(&page_id) || modified_pages.contains(&page_id) {
+					continue;
+				}
+				Self::free(&mut t, page_id)?;
+				num_freed += 1;
+			}
+			t.commit()?;
+		}
+		Ok(num_freed)
+	}
+
+	/// Returns the pages below the end of the allocated pages that are
+	/// neither in `reachable` nor unused, in ascending order. Those pages were
+	/// allocated, but nothing refers to them anymore. The state of the
+	/// allocator is read in a transaction of its own, so pages that are
+	/// allocated concurrently may be returned as well, unless `reachable`
+	/// includes them; [`PageAllocator::collect_garbage`] checks them again
+	/// before freeing them.
+	pub fn find_orphans<S: PageStorageApi>(
+		storage: &S,
+		reachable: &HashSet<PageId>,
+	) -> Result<Vec<PageId>, DatabaseError> {
+		let mut t = storage.transaction()?;
+		let mut unused_pages = Self::unused_pages(&mut t)?;
+		// The catalog page is used, but never allocated.
+		unused_pages.insert(Self::CATALOG_PAGE_ID);
+		let next_page_id = Self::meta_page(&mut t, Self::META_PAGE_ID)?.get_next_page_id()?;
+		t.commit()?;
+
+		let mut orphans: Vec<PageId> = Vec::new();
+		let mut page_id = Self::page_id_after(Self::META_PAGE_ID);
+		while page_id != next_page_id {
+			if !reachable.contains(&page_id) && !unused_pages.contains(&page_id) {
+				orphans.push(page_id);
+			}
+			page_id = Self::page_id_after(page_id);
+		}
+		Ok(orphans)
+	}
+
+	/// Returns the pages of the segment that are in use, in ascending order.
+	/// The allocator's own pages, free pages and pages that a shard reserved
+	/// but hasn't handed out yet are skipped. The catalog page is included.
+	pub fn allocated_pages(
+		t: &mut impl TransactionApi,
+		segment_num: u32,
+	) -> Result<Vec<PageId>, DatabaseError> {
+		let unused_pages = Self::unused_pages(t)?;
+		let end = Self::meta_page(t, Self::META_PAGE_ID)?.get_next_page_id()?;
+
+		let mut pages: Vec<PageId> = Vec::new();
+		let mut page_id = PageId::max(
+			Self::page_id_after(Self::META_PAGE_ID),
+			PageId::new_unwrap(segment_num, 1),
+		);
+		while page_id < end && page_id.segment_num == segment_num {
+			if !unused_pages.contains(&page_id) {
+				pages.push(page_id);
+			}
+			page_id = Self::page_id_after(page_id);
+		}
+		Ok(pages)
+	}
+
+	/// The pages that hold the allocator's own state: the meta pages of the
+	/// shards, the blocks of their freelists, and the free-space map.
+	pub fn own_pages(t: &mut impl TransactionApi) -> Result<Vec<PageId>, DatabaseError> {
+		let mut pages = Self::free_space_map_pages(t)?;
+		for shard in 0..Self::NUM_SHARDS {
+			let shard_page_id = Self::shard_meta_page_id(shard);
+			pages.insert(shard_page_id);
+			let mut next_freelist_page = Self::meta_page(t, shard_page_id)?.get_freelist_head()?;
+			while let Some(freelist_page_id) = next_freelist_page {
+				if !pages.insert(freelist_page_id) {
+					return Err(DatabaseError::PageFormat(format!(
+						"Freelist contains a cycle at page {freelist_page_id}"
+					)));
+				}
+				next_freelist_page =
+					FreelistPage::new(t.get_page(freelist_page_id)?)?.get_next_page_id()?;
+			}
+		}
+		let mut pages: Vec<PageId> = pages.into_iter().collect();
+		pages.sort_unstable();
+		Ok(pages)
+	}
+
+	/// The pages below the end of the allocated pages that are not in use:
+	/// those of the freelists and the free-space map, the meta pages of the
+	/// shards, and the pages they reserved.
+	fn unused_pages(t: &mut impl TransactionApi) -> Result<HashSet<PageId>, DatabaseError> {
+		let mut unused_pages = Self::free_space_map_pages(t)?;
+		for shard in 0..Self::NUM_SHARDS {
+			let shard_page_id = Self::shard_meta_page_id(shard);
+			unused_pages.extend(Self::free_pages(t, shard_page_id)?);
+			if shard_page_id != Self::META_PAGE_ID {
+				unused_pages.insert(shard_page_id);
+				unused_pages.extend(Self::reserved_pages(t, shard_page_id)?);
+			}
+		}
+		Ok(unused_pages)
+	}
+
+	/// Moves the end of the allocated pages back past free and unused
+	/// reserved pages at the end, by at most `max_pages` pages, and returns
+	/// how many pages it moved back.
+	///
+	/// Pages at the end that are still in use are moved to the lowest free
+	/// page instead, if `relocate` agrees to it. It is called with the page
+	/// and its new location before the page is moved, and has to update
+	/// every reference to the page to the new location in the same
+	/// transaction, such as with [`BTree::relocate_page`]. If it can't, it
+	/// returns `false`, and the vacuum stops at that page. `relocate` must
+	/// not allocate or free pages. The allocator's own pages are never moved,
+	/// so they stop the vacuum as well.
+	///
+	/// [`BTree::relocate_page`]: super::b_tree::BTree::relocate_page
+	pub fn vacuum_step<T: TransactionApi>(
+		t: &mut T,
+		max_pages: usize,
+		relocate: &mut impl FnMut(&mut T, PageId, PageId) -> Result<bool, DatabaseError>,
+	) -> Result<usize, DatabaseError> {
+		let mut shards: Vec<VacuumShard> = Vec::new();
+		for shard in 0..Self::NUM_SHARDS {
+			let meta_page_id = Self::shard_meta_page_id(shard);
+			let free_pages = Self::free_pages(t, meta_page_id)?;
+			let meta_page = Self::meta_page(t, meta_page_id)?;
+			let next_page_id = meta_page.get_next_page_id()?;
+			// Shard 0 has no reserved range; it takes new pages from the end.
+			let reserved_end = if meta_page_id == Self::META_PAGE_ID {
+				next_page_id
+			} else {
+				meta_page.get_reserved_end()?
+			};
+			shards.push(VacuumShard {
+				meta_page_id,
+				free_pages,
+				next_page_id,
+				reserved_end,
+				trimmed_reserved: false,
+				trimmed_free: false,
+			});
+		}
+		let map_pages = Self::free_space_map_pages(t)?;
+
+		let first_page = Self::first_page();
+		let mut end = shards[0].next_page_id;
+		let mut trimmed: HashSet<PageId> = HashSet::new();
+		'trim: while trimmed.len() < max_pages && end > first_page {
+			let last_page = Self::page_id_before(end);
+			for shard in &mut shards {
+				if shard.free_pages.contains(&last_page) {
+					shard.trimmed_free = true;
+				} else if shard.reserved_end == end && shard.next_page_id < end {
+					shard.reserved_end = last_page;
+					shard.trimmed_reserved = true;
+				} else {
+					continue;
+				}
+				trimmed.insert(last_page);
+				end = last_page;
+				continue 'trim;
+			}
+
+			if map_pages.contains(&last_page) {
+				break;
+			}
+			let Some((shard, target)) = shards
+				.iter_mut()
+				.filter_map(|shard| {
+					let lowest = shard.free_pages.iter().copied().min()?;
+					Some((shard, lowest))
+				})
+				.min_by_key(|(_, lowest)| *lowest)
+			else {
+				break;
+			};
+			if target >= last_page || !relocate(t, last_page, target)? {
+				break;
+			}
+			t.move_page(last_page, target)?;
+			Self::retrack_free_space(t, last_page, target)?;
+			shard.free_pages.remove(&target);
+			shard.trimmed_free = true;
+			trimmed.insert(last_page);
+			end = last_page;
+		}
+		if trimmed.is_empty() {
+			return Ok(0);
+		}
+
+		for shard in shards {
+			if shard.trimmed_reserved {
+				Self::meta_page_mut(t, shard.meta_page_id)?.set_reserved_end(shard.reserved_end)?;
+			}
+			if !shard.trimmed_free {
+				continue;
+			}
+			// Rebuild the freelist from the pages that are left, some of which
+			// become the new freelist pages.
+			Self::meta_page_mut(t, shard.meta_page_id)?.set_freelist_head(None)?;
+			let poison = Self::meta_page(t, Self::META_PAGE_ID)?.get_poison_freed()?;
+			for page_id in shard.free_pages.difference(&trimmed).copied() {
+				let freelist_head = Self::meta_page(t, shard.meta_page_id)?.get_freelist_head()?;
+				Self::push_free(t, shard.meta_page_id, freelist_head, page_id, poison)?;
+			}
+		}
+		Self::meta_page_mut(t, Self::META_PAGE_ID)?.set_next_page_id(end)?;
+		Ok(trimmed.len())
+	}
+
+	/// Vacuums the allocated pages in transactions of at most `batch_size`
+	/// pages, so that the vacuum can run while the database is in use, and
+	/// then truncates the segment files to the new end. Live pages are
+	/// relocated with `relocate`, as in [`PageAllocator::vacuum_step`].
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "info", skip(storage, relocate))
+	)]
+	pub fn vacuum<'s, S: PageStorageApi>(
+		storage: &'s S,
+		batch_size: usize,
+		mut relocate: impl FnMut(&mut S::Transaction<'s>, PageId, PageId) -> Result<bool, DatabaseError>,
+	) -> Result<VacuumStats, DatabaseError> {
+		let batch_size = usize::max(batch_size, 1);
+		let mut pages_trimmed = 0;
+		let mut pages_relocated = 0;
+		loop {
+			let mut t = storage.transaction()?;
+			let mut num_relocated = 0;
+			let num_trimmed = Self::vacuum_step(&mut t, batch_size, &mut |t, from, to| {
+				let relocated = relocate(t, from, to)?;
+				num_relocated += usize::from(relocated);
+				Ok(relocated)
+			})?;
+			t.commit()?;
+			pages_trimmed += num_trimmed;
+			pages_relocated += num_relocated;
+			if num_trimmed < batch_size {
+				break;
+			}
+		}
+
+		// Make sure no page past the end is written again by a flush or
+		// recovery after the files are truncated.
+		storage.checkpoint()?;
+
+		// Locking the global meta page keeps the end from moving while the
+		// files are truncated.
+		let mut t = storage.transaction()?;
+		let end = Self::meta_page_mut(&mut t, Self::META_PAGE_ID)?.get_next_page_id()?;
+		let bytes_reclaimed = storage.truncate(end)?;
+		t.commit()?;
+		event!(
+			INFO,
+			pages_trimmed,
+			pages_relocated,
+			bytes_reclaimed,
+			"Vacuumed the allocated pages"
+		);
+
+		Ok(VacuumStats {
+			pages_trimmed,
+			pages_relocated,
+			bytes_reclaimed,
+		})
+	}
+
+	/// Moves the entry of the free-space map for `from` over to `to`, if
+	/// there is one.
+	fn retrack_free_space(
+		t: &mut impl TransactionApi,
+		from: PageId,
+		to: PageId,
+	) -> Result<(), DatabaseError> {
+		let mut next_map_page =
+			Self::meta_page(t, Self::META_PAGE_ID)?.get_free_space_map_head()?;
+		while let Some(map_page_id) = next_map_page {
+			let map_page = FreeSpaceMapPage::new(t.get_page(map_page_id)?)?;
+			if let Some(index) = map_page.find_entry(from)? {
+				let (_, level) = map_page.get_entry(index)?;
+				mem::drop(map_page);
+				FreeSpaceMapPage::new(t.get_page_mut(map_page_id)?)?.set_entry(index, to, level)?;
+				return Ok(());
+			}
+			next_map_page = map_page.get_next_page_id()?;
+		}
+		Ok(())
+	}
+
+	fn free_pages(
+		t: &mut impl TransactionApi,
+		shard_page_id: PageId,
+	) -> Result<HashSet<PageId>, DatabaseError> {
+		let mut free_pages: HashSet<PageId> = HashSet::new();
+		let mut next_freelist_page = Self::meta_page(t, shard_page_id)?.get_freelist_head()?;
+		while let Some(freelist_page_id) = next_freelist_page {
+			if !free_pages.insert(freelist_page_id) {
+				return Err(DatabaseError::PageFormat(format!(
+					"Freelist contains a cycle at page {freelist_page_id}"
+				)));
+			}
+			let freelist_page = FreelistPage::new(t.get_page(freelist_page_id)?)?;
+			for index in 0..freelist_page.get_length()? {
+				if let Some(page_id) = freelist_page.get_item(index)? {
+					free_pages.insert(page_id);
+				}
+			}
+			next_freelist_page = freelist_page.get_next_page_id()?;
+		}
+		Ok(free_pages)
+	}
+
+	fn reserved_pages(
+		t: &mut impl TransactionApi,
+		shard_page_id: PageId,
+	) -> Result<Vec<PageId>, DatabaseError> {
+		let meta_page = Self::meta_page(t, shard_page_id)?;
+		let mut page_id = meta_page.get_next_page_id()?;
+		let reserved_end = meta_page.get_reserved_end()?;
+		let mut reserved_pages: Vec<PageId> = Vec::new();
+		while page_id != reserved_end {
+			reserved_pages.push(page_id);
+			page_id = Self::page_id_after(page_id);
+		}
+		Ok(reserved_pages)
+	}
+
+	fn free_space_map_pages(t: &mut impl TransactionApi) -> Result<HashSet<PageId>, DatabaseError> {
+		let mut map_pages: HashSet<PageId> = HashSet::new();
+		let mut next_map_page =
+			Self::meta_page(t, Self::META_PAGE_ID)?.get_free_space_map_head()?;
+		while let Some(map_page_id) = next_map_page {
+			map_pages.insert(map_page_id);
+			next_map_page = FreeSpaceMapPage::new(t.get_page(map_page_id)?)?.get_next_page_id()?;
+		}
+		Ok(map_pages)
+	}
+
+	fn next_free_page(
+		t: &mut impl TransactionApi,
+		shard_page_id: PageId,
+	) -> Result<Option<PageId>, DatabaseError> {
+		let Some(freelist_head_id) = Self::meta_page(t, shard_page_id)?.get_freelist_head()? else {
 			return Ok(None);
 		};
 		let mut freelist_page = FreelistPage::new(t.get_page_mut(freelist_head_id)?)?;
@@ -69,52 +692,129 @@ impl PageAllocator {
 		let new_head = freelist_page.get_next_page_id()?;
 		mem::drop(freelist_page);
 
-		Self::meta_page_mut(t)?.set_freelist_head(new_head)?;
+		Self::meta_page_mut(t, shard_page_id)?.set_freelist_head(new_head)?;
 		Ok(Some(freelist_head_id))
 	}
 
-	fn next_uninit_page(t: &mut impl TransactionApi) -> Result<PageId, DatabaseError> {
-		let mut meta_page = Self::meta_page_mut(t)?;
+	fn next_reserved_page(
+		t: &mut impl TransactionApi,
+		shard_page_id: PageId,
+	) -> Result<PageId, DatabaseError> {
+		let mut meta_page = Self::meta_page_mut(t, shard_page_id)?;
 		let page_id = meta_page.get_next_page_id()?;
-		meta_page.set_next_page_id(Self::page_id_after(page_id))?;
-		Ok(page_id)
+		if page_id != meta_page.get_reserved_end()? {
+			meta_page.set_next_page_id(Self::page_id_after(page_id))?;
+			return Ok(page_id);
+		}
+		mem::drop(meta_page);
+
+		let reserved = Self::next_uninit_pages(t, Self::RESERVE_BATCH_SIZE)?;
+		let mut meta_page = Self::meta_page_mut(t, shard_page_id)?;
+		meta_page.set_next_page_id(Self::page_id_after(reserved.start))?;
+		meta_page.set_reserved_end(reserved.end)?;
+		Ok(reserved.start)
+	}
+
+	fn next_uninit_pages(
+		t: &mut impl TransactionApi,
+		num_pages: usize,
+	) -> Result<Range<PageId>, DatabaseError> {
+		let mut meta_page = Self::meta_page_mut(t, Self::META_PAGE_ID)?;
+		let start = meta_page.get_next_page_id()?;
+		let end = (0..num_pages)
+			.try_fold(start, |page_id, _| Self::checked_page_id_after(page_id))
+			.ok_or(DatabaseError::PageIdsExhausted)?;
+		meta_page.set_next_page_id(end)?;
+		event!(TRACE, start = ?start, num_pages, "Allocated new pages");
+		Ok(start..end)
 	}
 
+	fn transaction_shard(t: &impl TransactionApi) -> PageId {
+		let shard = t.id() % u64::from(Self::NUM_SHARDS);
+		Self::shard_meta_page_id(u16::try_from(shard).unwrap())
+	}
+
+	/// The first page that can be allocated, after the meta pages and the
+	/// catalog page.
+	fn first_page() -> PageId {
+		Self::page_id_after(Self::CATALOG_PAGE_ID)
+	}
+
+	/// Shard 0 uses the global meta page, and the other shards the pages
+	/// right after it.
+	fn shard_meta_page_id(shard: u16) -> PageId {
+		PageId::new(
+			Self::META_PAGE_ID.segment_num,
+			Self::META_PAGE_ID.page_num.checked_add(shard).unwrap(),
+		)
+	}
+
+	fn page_id_plus(page_id: PageId, num_pages: usize) -> PageId {
+		(0..num_pages).fold(page_id, |page_id, _| Self::page_id_after(page_id))
+	}
+
+	/// Panics after the last page of the last segment, which
+	/// [`PageAllocator::next_uninit_pages`] never hands out.
 	fn page_id_after(page_id: PageId) -> PageId {
+		Self::checked_page_id_after(page_id).expect("Page ID after the last possible page!")
+	}
+
+	/// The next page ID, continuing at the next segment after the last page
+	/// of a segment, or `None` after the last page of the last segment.
+	fn checked_page_id_after(page_id: PageId) -> Option<PageId> {
 		if page_id.page_num.get() == u16::MAX {
-			PageId::new(
-				page_id
-					.segment_num
-					.checked_add(1)
-					.expect("You've somehow managed to exhaust the space of page IDs ¯\\_(ツ)_/¯"),
+			Some(PageId::new(
+				page_id.segment_num.checked_add(1)?,
 				NonZero::new(1).unwrap(),
-			)
+			))
 		} else {
-			PageId::new(
+			Some(PageId::new(
 				page_id.segment_num,
 				page_id.page_num.checked_add(1).unwrap(),
-			)
+			))
 		}
 	}
 
-	fn meta_page<T: TransactionApi>(t: &mut T) -> Result<MetaPage<T::Page<'_>>, DatabaseError> {
-		MetaPage::new(t.get_page(Self::META_PAGE_ID)?)
+	fn page_id_before(page_id: PageId) -> PageId {
+		if page_id.page_num.get() == 1 {
+			PageId::new(page_id.segment_num - 1, NonZero::new(u16::MAX).unwrap())
+		} else {
+			PageId::new_unwrap(page_id.segment_num, page_id.page_num.get() - 1)
+		}
+	}
+
+	fn meta_page<T: TransactionApi>(
+		t: &mut T,
+		page_id: PageId,
+	) -> Result<MetaPage<T::Page<'_>>, DatabaseError> {
+		MetaPage::new(t.get_page(page_id)?)
 	}
 
 	fn meta_page_mut<T: TransactionApi>(
 		t: &mut T,
+		page_id: PageId,
 	) -> Result<MetaPage<T::PageMut<'_>>, DatabaseError> {
-		MetaPage::new(t.get_page_mut(Self::META_PAGE_ID)?)
+		MetaPage::new(t.get_page_mut(page_id)?)
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::{fs, sync::Arc};
+
 	use crate::{
-		doc_store::pages::PageKind,
-		page_store::{test_helpers::page_id, MockPage, MockPageMut, MockTransactionApi},
+		consts::PAGE_SIZE,
+		doc_store::{b_tree::BTree, catalog::Catalog, pages::PageKind, DbPointer},
+		files::{segment::SEGMENT_SIZE, DatabaseFolder},
+		page_store::{
+			test_helpers::{page_id, temp_storage},
+			MockPage, MockPageMut, MockTransactionApi, PageStorage, PageStorageApi, ReadPage,
+			WritePage,
+		},
 	};
+	use futures::executor::ThreadPool;
 	use mockall::{predicate::*, Sequence};
+	use tempfile::tempdir;
 
 	use super::*;
 
@@ -122,6 +822,55 @@ mod tests {
 	fn init() {
 		// expect
 		let mut t = MockTransactionApi::new();
+
+		// - initialize the shard meta pages with 32 reserved pages each
+		for (shard_page_num, reserved_start, reserved_end) in
+			[(2, 6, 38), (3, 38, 70), (4, 70, 102)]
+		{
+			t.expect_get_page_mut()
+				.once()
+				.with(eq(page_id!(0, shard_page_num)))
+				.returning(move |_| {
+					let mut page = MockPageMut::new();
+					page.expect_write()
+						.with(eq(0), eq([PageKind::FreelistMeta as u8]))
+						.once()
+						.returning(|_, _| Ok(()));
+					page.expect_write()
+						.with(eq(1), eq([0; 6]))
+						.once()
+						.returning(|_, _| Ok(()));
+					page.expect_write()
+						.with(
+							eq(7),
+							eq([
+								0_u32.to_ne_bytes().as_slice(),
+								u16::to_ne_bytes(reserved_start).as_slice(),
+							]
+							.concat()),
+						)
+						.once()
+						.returning(|_, _| Ok(()));
+					page.expect_write()
+						.with(eq(13), eq([0; 6]))
+						.once()
+						.returning(|_, _| Ok(()));
+					page.expect_write()
+						.with(
+							eq(19),
+							eq([
+								0_u32.to_ne_bytes().as_slice(),
+								u16::to_ne_bytes(reserved_end).as_slice(),
+							]
+							.concat()),
+						)
+						.once()
+						.returning(|_, _| Ok(()));
+					Ok(page)
+				});
+		}
+
+		// - initialize the global meta page
 		t.expect_get_page_mut()
 			.once()
 			.with(eq(page_id!(0, 1)))
@@ -141,11 +890,15 @@ mod tests {
 						eq(7),
 						eq([
 							0_u32.to_ne_bytes().as_slice(),
-							2_u16.to_ne_bytes().as_slice(),
+							102_u16.to_ne_bytes().as_slice(),
 						]
 						.concat()),
 					)
 					.returning(|_, _| Ok(()));
+				page.expect_write()
+					.with(eq(13), eq([0; 6]))
+					.once()
+					.returning(|_, _| Ok(()));
 				Ok(page)
 			});
 
@@ -157,6 +910,7 @@ mod tests {
 	fn alloc() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -245,6 +999,20 @@ mod tests {
 				Ok(page)
 			});
 
+		// - clear the reused page
+		t.expect_get_page_mut()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0x69, 0x420)))
+			.returning(|_| {
+				let mut page = MockPageMut::new();
+				page.expect_write()
+					.once()
+					.with(eq(0), eq(vec![0; PAGE_BODY_SIZE]))
+					.returning(|_, _| Ok(()));
+				Ok(page)
+			});
+
 		// when
 		let page_id = PageAllocator::alloc(&mut t).unwrap();
 
@@ -256,6 +1024,7 @@ mod tests {
 	fn alloc_with_empty_freelist_page() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -360,6 +1129,20 @@ mod tests {
 				Ok(page)
 			});
 
+		// - clear the reused page
+		t.expect_get_page_mut()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0x24, 0x25)))
+			.returning(|_| {
+				let mut page = MockPageMut::new();
+				page.expect_write()
+					.once()
+					.with(eq(0), eq(vec![0; PAGE_BODY_SIZE]))
+					.returning(|_, _| Ok(()));
+				Ok(page)
+			});
+
 		// when
 		let page_id = PageAllocator::alloc(&mut t).unwrap();
 
@@ -371,6 +1154,7 @@ mod tests {
 	fn alloc_with_empty_freelist() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -454,6 +1238,7 @@ mod tests {
 	fn alloc_with_empty_freelist_at_segment_boundary() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -537,6 +1322,7 @@ mod tests {
 	fn free() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -571,6 +1357,38 @@ mod tests {
 				Ok(page)
 			});
 
+		// - read the free-space map head page ID (None)
+		t.expect_get_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0, 1)))
+			.returning(|_| {
+				let mut page = MockPage::new();
+				page.expect_read()
+					.once()
+					.with(eq(0), always())
+					.returning(|_, buf| {
+						buf.copy_from_slice(&[PageKind::FreelistMeta as u8]);
+						Ok(())
+					});
+				page.expect_read()
+					.once()
+					.with(eq(13), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				// - read whether freed pages are poisoned (no)
+				page.expect_read()
+					.once()
+					.with(eq(25), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				Ok(page)
+			});
+
 		// - access the freelist head page (2000:1)
 		t.expect_get_page_mut()
 			.once()
@@ -615,6 +1433,20 @@ mod tests {
 				Ok(page)
 			});
 
+		// - mark the freed page
+		t.expect_get_page_mut()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0x69, 0x420)))
+			.returning(|_| {
+				let mut page = MockPageMut::new();
+				page.expect_write()
+					.once()
+					.with(eq(0), eq([PageKind::Free as u8]))
+					.returning(|_, _| Ok(()));
+				Ok(page)
+			});
+
 		// when
 		PageAllocator::free(&mut t, page_id!(0x69, 0x420)).unwrap();
 	}
@@ -623,6 +1455,7 @@ mod tests {
 	fn free_with_no_head() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -651,6 +1484,38 @@ mod tests {
 				Ok(page)
 			});
 
+		// - read the free-space map head page ID (None)
+		t.expect_get_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0, 1)))
+			.returning(|_| {
+				let mut page = MockPage::new();
+				page.expect_read()
+					.once()
+					.with(eq(0), always())
+					.returning(|_, buf| {
+						buf.copy_from_slice(&[PageKind::FreelistMeta as u8]);
+						Ok(())
+					});
+				page.expect_read()
+					.once()
+					.with(eq(13), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				// - read whether freed pages are poisoned (no)
+				page.expect_read()
+					.once()
+					.with(eq(25), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				Ok(page)
+			});
+
 		// - access the new freelist head page (69:420)
 		t.expect_get_page_mut()
 			.once()
@@ -661,7 +1526,7 @@ mod tests {
 				// - set the page type
 				page.expect_write()
 					.once()
-					.with(eq(0), eq([PageKind::FreelistBlock as u8]))
+					.with(eq(0), eq([PageKind::PackedFreelistBlock as u8]))
 					.returning(|_, _| Ok(()));
 				// - set the next freelist page id to None
 				page.expect_write()
@@ -672,7 +1537,7 @@ mod tests {
 				// - set the page length to 0
 				page.expect_write()
 					.once()
-					.with(eq(7), eq([0; 2]))
+					.with(eq(11), eq([0; 2]))
 					.returning(|_, _| Ok(()));
 				Ok(page)
 			});
@@ -715,6 +1580,7 @@ mod tests {
 	fn free_with_full_head() {
 		// expect
 		let mut t = MockTransactionApi::new();
+		t.expect_id().return_const(0_u64);
 		let mut seq = Sequence::new();
 
 		// - access the alloc meta page
@@ -749,6 +1615,38 @@ mod tests {
 				Ok(page)
 			});
 
+		// - read the free-space map head page ID (None)
+		t.expect_get_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(0, 1)))
+			.returning(|_| {
+				let mut page = MockPage::new();
+				page.expect_read()
+					.once()
+					.with(eq(0), always())
+					.returning(|_, buf| {
+						buf.copy_from_slice(&[PageKind::FreelistMeta as u8]);
+						Ok(())
+					});
+				page.expect_read()
+					.once()
+					.with(eq(13), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				// - read whether freed pages are poisoned (no)
+				page.expect_read()
+					.once()
+					.with(eq(25), always())
+					.returning(|_, buf| {
+						buf.fill(0);
+						Ok(())
+					});
+				Ok(page)
+			});
+
 		// - access the freelist head page (2000:1)
 		t.expect_get_page_mut()
 			.once()
@@ -769,7 +1667,9 @@ mod tests {
 					.once()
 					.with(eq(7), always())
 					.returning(|_, buf| {
-						buf.copy_from_slice(&(FreelistPage::<()>::NUM_SLOTS as u16).to_ne_bytes());
+						buf.copy_from_slice(
+							&(FreelistPage::<()>::LEGACY_NUM_SLOTS as u16).to_ne_bytes(),
+						);
 						Ok(())
 					});
 				Ok(page)
@@ -785,7 +1685,7 @@ mod tests {
 				// - set the page type
 				page.expect_write()
 					.once()
-					.with(eq(0), eq([PageKind::FreelistBlock as u8]))
+					.with(eq(0), eq([PageKind::PackedFreelistBlock as u8]))
 					.returning(|_, _| Ok(()));
 				// - reset the next freelist page id
 				page.expect_write()
@@ -808,7 +1708,7 @@ mod tests {
 				// - set the page length to 0
 				page.expect_write()
 					.once()
-					.with(eq(7), eq([0; 2]))
+					.with(eq(11), eq([0; 2]))
 					.returning(|_, _| Ok(()));
 				Ok(page)
 			});
@@ -846,4 +1746,596 @@ mod tests {
 		// when
 		PageAllocator::free(&mut t, page_id!(0x69, 0x420)).unwrap();
 	}
+
+	#[test]
+	fn free_into_packed_freelist_blocks() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let shard_page_id = PageAllocator::transaction_shard(&t);
+		let num_pages = FreelistPage::<()>::LEGACY_NUM_SLOTS as u16 + 1;
+		let pages: Vec<PageId> = (0..num_pages)
+			.map(|i| page_id!(0, 0x1000 + i))
+			.chain([page_id!(1, 0x1000), page_id!(1, 0x1001)])
+			.collect();
+
+		// when
+		for &page_id in &pages {
+			PageAllocator::free(&mut t, page_id).unwrap();
+		}
+
+		// then
+		let free_pages = PageAllocator::free_pages(&mut t, shard_page_id).unwrap();
+		assert_eq!(free_pages, pages.iter().copied().collect());
+
+		let head = PageAllocator::meta_page(&mut t, shard_page_id)
+			.unwrap()
+			.get_freelist_head()
+			.unwrap();
+		assert_eq!(head, Some(page_id!(1, 0x1000)));
+		let next = FreelistPage::new(t.get_page(page_id!(1, 0x1000)).unwrap())
+			.unwrap()
+			.get_next_page_id()
+			.unwrap();
+		assert_eq!(next, Some(page_id!(0, 0x1000)));
+		let length = FreelistPage::new(t.get_page(page_id!(0, 0x1000)).unwrap())
+			.unwrap()
+			.get_length()
+			.unwrap();
+		assert_eq!(length, num_pages as usize - 1);
+	}
+
+	#[test]
+	fn list_allocated_pages() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let pages: Vec<PageId> = (0..5)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		PageAllocator::free(&mut t, pages[2]).unwrap();
+
+		// when
+		let allocated = PageAllocator::allocated_pages(&mut t, 0).unwrap();
+		let in_next_segment = PageAllocator::allocated_pages(&mut t, 1).unwrap();
+
+		// then
+		assert_eq!(
+			allocated,
+			vec![
+				PageAllocator::CATALOG_PAGE_ID,
+				pages[0],
+				pages[1],
+				pages[3],
+				pages[4]
+			]
+		);
+		assert_eq!(in_next_segment, Vec::new());
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn read_pages_after_free_and_alloc() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let block = PageAllocator::alloc(&mut t).unwrap();
+		let page_id = PageAllocator::alloc(&mut t).unwrap();
+		MetaPage::new_unchecked(t.get_page_mut(page_id).unwrap())
+			.init(page_id!(1, 1))
+			.unwrap();
+		PageAllocator::free(&mut t, block).unwrap();
+
+		// when
+		PageAllocator::free(&mut t, page_id).unwrap();
+		let freed = MetaPage::new(t.get_page(page_id).unwrap()).err();
+		let reused = PageAllocator::alloc(&mut t).unwrap();
+		let mut buf = vec![1; PAGE_BODY_SIZE];
+		t.get_page(reused).unwrap().read(0, &mut buf).unwrap();
+
+		// then
+		assert!(matches!(
+			freed,
+			Some(DatabaseError::UnexpectedPageKind {
+				received: PageKind::Free,
+				..
+			})
+		));
+		assert_eq!(reused, page_id);
+		assert_eq!(buf, vec![0; PAGE_BODY_SIZE]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn detect_writes_to_poisoned_pages() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		PageAllocator::set_poison_freed(&mut t, true).unwrap();
+		let pages: Vec<PageId> = (0..3)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		for &page_id in &pages {
+			PageAllocator::free(&mut t, page_id).unwrap();
+		}
+
+		// when
+		let read_freed = MetaPage::new(t.get_page(pages[1]).unwrap()).err();
+		t.get_page_mut(pages[2])
+			.unwrap()
+			.write(100, &[1, 2, 3])
+			.unwrap();
+		let damaged = PageAllocator::damaged_free_pages(&mut t).unwrap();
+
+		// then
+		assert!(matches!(
+			read_freed,
+			Some(DatabaseError::UnexpectedPageKind {
+				received: PageKind::Poisoned,
+				..
+			})
+		));
+		assert_eq!(damaged, vec![pages[2]]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn collect_garbage() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let pages: Vec<PageId> = (0..5)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		PageAllocator::free(&mut t, pages[4]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let (_, reachable_at) = storage.snapshot_with_position().unwrap();
+		let reachable = HashSet::from([pages[0], pages[1]]);
+		let num_collected =
+			PageAllocator::collect_garbage(&storage, &reachable, reachable_at, 1).unwrap();
+
+		// then
+		assert_eq!(num_collected, 2);
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			Vec::new()
+		);
+
+		let mut t = storage.transaction().unwrap();
+		let free_pages: HashSet<PageId> = (0..PageAllocator::NUM_SHARDS)
+			.flat_map(|shard| {
+				let shard_page_id = PageAllocator::shard_meta_page_id(shard);
+				PageAllocator::free_pages(&mut t, shard_page_id).unwrap()
+			})
+			.collect();
+		t.commit().unwrap();
+		assert_eq!(free_pages, HashSet::from([pages[2], pages[3], pages[4]]));
+	}
+
+	#[test]
+	fn never_allocate_or_collect_catalog_page() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::default();
+		catalog.init(&mut t).unwrap();
+		let pages: Vec<PageId> = (0..4 * PageAllocator::RESERVE_BATCH_SIZE)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		// Garbage collection has to skip the catalog page even if the caller
+		// doesn't count it as reachable.
+		let reachable: HashSet<PageId> = catalog
+			.pages(&mut t)
+			.unwrap()
+			.into_iter()
+			.filter(|page_id| *page_id != PageAllocator::CATALOG_PAGE_ID)
+			.collect();
+		t.commit().unwrap();
+
+		// when
+		let (_, reachable_at) = storage.snapshot_with_position().unwrap();
+		let orphans = PageAllocator::find_orphans(&storage, &reachable).unwrap();
+		PageAllocator::collect_garbage(&storage, &reachable, reachable_at, 16).unwrap();
+
+		// then
+		assert!(!pages.contains(&PageAllocator::CATALOG_PAGE_ID));
+		assert!(!orphans.contains(&PageAllocator::CATALOG_PAGE_ID));
+		let mut t = storage.transaction().unwrap();
+		assert_eq!(catalog.entries(&mut t).unwrap().len(), 0);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn keep_pages_allocated_after_reachability_scan() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		PageAllocator::alloc(&mut t).unwrap();
+		t.commit().unwrap();
+
+		let (_, reachable_at) = storage.snapshot_with_position().unwrap();
+		let reachable = HashSet::new();
+
+		let mut t = storage.transaction().unwrap();
+		let new_page = PageAllocator::alloc(&mut t).unwrap();
+		t.get_page_mut(new_page).unwrap().write(0, &[1]).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let num_collected =
+			PageAllocator::collect_garbage(&storage, &reachable, reachable_at, 8).unwrap();
+
+		// then
+		assert_eq!(num_collected, 1);
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			vec![new_page]
+		);
+	}
+
+	#[test]
+	fn concurrent_allocs_use_separate_shards() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut transactions: Vec<_> = (0..PageAllocator::NUM_SHARDS)
+			.map(|_| storage.transaction().unwrap())
+			.collect();
+		let mut pages: Vec<PageId> = transactions
+			.iter_mut()
+			.flat_map(|t| {
+				(0..4)
+					.map(|_| PageAllocator::alloc(t).unwrap())
+					.collect::<Vec<_>>()
+			})
+			.collect();
+		for t in transactions {
+			t.commit().unwrap();
+		}
+		let mut t = storage.transaction().unwrap();
+		pages.extend(
+			(0..PageAllocator::RESERVE_BATCH_SIZE).map(|_| PageAllocator::alloc(&mut t).unwrap()),
+		);
+		t.commit().unwrap();
+
+		// then
+		let reachable: HashSet<PageId> = pages.iter().copied().collect();
+		assert_eq!(reachable.len(), pages.len());
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			Vec::new()
+		);
+	}
+
+	#[test]
+	fn alloc_across_segment_boundary() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		// What a crash while creating the next segment file leaves behind
+		let segments_dir = tempdir.path().join(DatabaseFolder::SEGMENTS_DIR_NAME);
+		fs::create_dir_all(&segments_dir).unwrap();
+		fs::write(segments_dir.join("1.tmp"), [0xff; 100]).unwrap();
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		PageAllocator::meta_page_mut(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.set_next_page_id(page_id!(0, u16::MAX))
+			.unwrap();
+
+		// when
+		let pages: Vec<PageId> = (0..2)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		t.get_page_mut(pages[1]).unwrap().write(0, &[1]).unwrap();
+		t.commit().unwrap();
+		storage.checkpoint().unwrap();
+
+		// then
+		assert_eq!(pages, vec![page_id!(0, u16::MAX), page_id!(1, 1)]);
+		let mut buf = [0];
+		storage
+			.transaction()
+			.unwrap()
+			.get_page(pages[1])
+			.unwrap()
+			.read(0, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [1]);
+		assert!(segments_dir.join("1").exists());
+		assert!(!segments_dir.join("1.tmp").exists());
+	}
+
+	#[test]
+	fn fail_to_alloc_past_last_segment() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		PageAllocator::meta_page_mut(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.set_next_page_id(page_id!(u32::MAX, u16::MAX - 1))
+			.unwrap();
+
+		// when
+		let last = PageAllocator::next_uninit_pages(&mut t, 1);
+		let past_last = PageAllocator::next_uninit_pages(&mut t, 1);
+
+		// then
+		assert_eq!(
+			last.unwrap(),
+			page_id!(u32::MAX, u16::MAX - 1)..page_id!(u32::MAX, u16::MAX)
+		);
+		assert!(matches!(past_last, Err(DatabaseError::PageIdsExhausted)));
+		assert_eq!(
+			PageAllocator::meta_page(&mut t, PageAllocator::META_PAGE_ID)
+				.unwrap()
+				.get_next_page_id()
+				.unwrap(),
+			page_id!(u32::MAX, u16::MAX)
+		);
+	}
+
+	#[test]
+	fn alloc_contiguous_extents() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		PageAllocator::meta_page_mut(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.set_next_page_id(page_id!(0, u16::MAX - 9))
+			.unwrap();
+
+		// when
+		let first = PageAllocator::alloc_extent(&mut t, NonZero::new(4).unwrap()).unwrap();
+		let second = PageAllocator::alloc_extent(&mut t, NonZero::new(8).unwrap()).unwrap();
+		let reused: Vec<PageId> = (0..6)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+
+		// then
+		assert_eq!(first, page_id!(0, u16::MAX - 9)..page_id!(0, u16::MAX - 5));
+		assert_eq!(second, page_id!(1, 1)..page_id!(1, 9));
+		// The pages left at the end of segment 0 were freed, and are reused in
+		// ascending order.
+		let skipped: Vec<PageId> = (u16::MAX - 5..=u16::MAX)
+			.map(|page_num| page_id!(0, page_num))
+			.collect();
+		assert_eq!(reused, skipped);
+	}
+
+	#[test]
+	fn free_extent() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let extent = PageAllocator::alloc_extent(&mut t, NonZero::new(16).unwrap()).unwrap();
+		let segment_num = extent.start.segment_num;
+		t.commit().unwrap();
+
+		// when
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::free_extent(&mut t, extent.clone()).unwrap();
+
+		// then
+		let allocated = PageAllocator::allocated_pages(&mut t, segment_num).unwrap();
+		assert!(allocated
+			.iter()
+			.all(|page_id| !(extent.start..extent.end).contains(page_id)));
+	}
+
+	#[test]
+	fn vacuum_free_pages_at_end() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let pages: Vec<PageId> = (0..6)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		t.commit().unwrap();
+		for page_id in [pages[2], pages[4], pages[5]] {
+			let mut t = storage.transaction().unwrap();
+			PageAllocator::free(&mut t, page_id).unwrap();
+			t.commit().unwrap();
+		}
+
+		// when
+		let stats = PageAllocator::vacuum(&storage, 1, |_, _, _| Ok(false)).unwrap();
+
+		// then
+		assert_eq!(
+			stats,
+			VacuumStats {
+				pages_trimmed: 2,
+				pages_relocated: 0,
+				bytes_reclaimed: (SEGMENT_SIZE - (usize::from(pages[4].page_num.get()) * PAGE_SIZE))
+					as u64
+			}
+		);
+		let mut t = storage.transaction().unwrap();
+		let next_page_id = PageAllocator::meta_page(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.get_next_page_id()
+			.unwrap();
+		let free_pages: HashSet<PageId> = (0..PageAllocator::NUM_SHARDS)
+			.flat_map(|shard| {
+				let shard_page_id = PageAllocator::shard_meta_page_id(shard);
+				PageAllocator::free_pages(&mut t, shard_page_id).unwrap()
+			})
+			.collect();
+		t.commit().unwrap();
+		assert_eq!(next_page_id, pages[4]);
+		assert_eq!(free_pages, HashSet::from([pages[2]]));
+
+		// when
+		for page_id in [pages[0], pages[1], pages[3]] {
+			let mut t = storage.transaction().unwrap();
+			PageAllocator::free(&mut t, page_id).unwrap();
+			t.commit().unwrap();
+		}
+		let stats = PageAllocator::vacuum(&storage, 16, |_, _, _| Ok(false)).unwrap();
+
+		// then
+		let first_page = PageAllocator::first_page();
+		assert_eq!(
+			stats.pages_trimmed,
+			usize::from(pages[4].page_num.get() - first_page.page_num.get())
+		);
+		let reachable: HashSet<PageId> = (0..PageAllocator::NUM_SHARDS)
+			.map(|_| {
+				let mut t = storage.transaction().unwrap();
+				let page_id = PageAllocator::alloc(&mut t).unwrap();
+				t.commit().unwrap();
+				page_id
+			})
+			.collect();
+		assert_eq!(reachable.len(), usize::from(PageAllocator::NUM_SHARDS));
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			Vec::new()
+		);
+	}
+
+	#[test]
+	fn vacuum_relocates_live_pages() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let root = PageAllocator::alloc(&mut t).unwrap();
+		let tree = BTree::new(root);
+		tree.init(&mut t).unwrap();
+		// Put the free-space map in front of the tree
+		PageAllocator::set_free_space(&mut t, root, 0).unwrap();
+		let fillers: Vec<PageId> = (0..64)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+		let num_keys = 4 * tree.leaf_capacity() as u64;
+		tree.bulk_load(
+			&mut t,
+			(0..num_keys).map(|key| (key, DbPointer::new(page_id!(9, 9), 0))),
+		)
+		.unwrap();
+		let last_tree_page = tree.pages(&mut t).unwrap().into_iter().max().unwrap();
+		PageAllocator::set_free_space(&mut t, last_tree_page, PAGE_BODY_SIZE / 2).unwrap();
+		for page_id in fillers {
+			PageAllocator::free(&mut t, page_id).unwrap();
+		}
+		let end_before = PageAllocator::meta_page(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.get_next_page_id()
+			.unwrap();
+		t.commit().unwrap();
+
+		// when
+		let stats =
+			PageAllocator::vacuum(&storage, 8, |t, from, to| tree.relocate_page(t, from, to))
+				.unwrap();
+
+		// then
+		assert!(stats.pages_relocated > 0);
+		assert!(stats.pages_trimmed >= stats.pages_relocated);
+		let mut t = storage.transaction().unwrap();
+		let end = PageAllocator::meta_page(&mut t, PageAllocator::META_PAGE_ID)
+			.unwrap()
+			.get_next_page_id()
+			.unwrap();
+		let tree_pages = tree.pages(&mut t).unwrap();
+		assert!(end < end_before);
+		assert!(tree_pages.iter().all(|page_id| *page_id < end));
+		for key in 0..num_keys {
+			assert!(tree.search(&mut t, key).unwrap().is_some());
+		}
+		// The free space of the last page moved along with it
+		let moved_to = PageAllocator::find_page_with_space(&mut t, 1)
+			.unwrap()
+			.unwrap();
+		assert_ne!(moved_to, last_tree_page);
+		assert!(tree_pages.contains(&moved_to));
+		t.commit().unwrap();
+		let reachable: HashSet<PageId> = tree_pages.into_iter().collect();
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			Vec::new()
+		);
+	}
+
+	#[test]
+	fn find_page_with_space() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let pages: Vec<PageId> = (0..3)
+			.map(|_| PageAllocator::alloc(&mut t).unwrap())
+			.collect();
+
+		// when
+		PageAllocator::set_free_space(&mut t, pages[0], 1000).unwrap();
+		PageAllocator::set_free_space(&mut t, pages[1], 8000).unwrap();
+		PageAllocator::set_free_space(&mut t, pages[2], PAGE_BODY_SIZE).unwrap();
+
+		// then
+		assert_eq!(
+			PageAllocator::find_page_with_space(&mut t, 500).unwrap(),
+			Some(pages[0])
+		);
+		assert_eq!(
+			PageAllocator::find_page_with_space(&mut t, 4000).unwrap(),
+			Some(pages[1])
+		);
+		assert_eq!(
+			PageAllocator::find_page_with_space(&mut t, 10000).unwrap(),
+			Some(pages[2])
+		);
+		assert_eq!(
+			PageAllocator::find_page_with_space(&mut t, PAGE_BODY_SIZE + 1).unwrap(),
+			None
+		);
+
+		PageAllocator::set_free_space(&mut t, pages[1], 10).unwrap();
+		PageAllocator::free(&mut t, pages[2]).unwrap();
+		assert_eq!(
+			PageAllocator::find_page_with_space(&mut t, 4000).unwrap(),
+			None
+		);
+		t.commit().unwrap();
+
+		let reachable = HashSet::from([pages[0], pages[1]]);
+		assert_eq!(
+			PageAllocator::find_orphans(&storage, &reachable).unwrap(),
+			Vec::new()
+		);
+	}
 }
@@ -1,9 +1,9 @@
 use std::{
 	mem::{self, size_of},
 	num::NonZeroU16,
+	ops::Range,
 };
 
-use static_assertions::const_assert_eq;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 use crate::{
@@ -12,7 +12,7 @@ use crate::{
 	utils::units::B,
 };
 
-use super::DatabaseError;
+use super::{document_repr::DbPointerRepr, DatabaseError, DbPointer};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -20,14 +20,34 @@ pub(crate) enum PageKind {
 	FreelistMeta = 0,
 	FreelistBlock = 1,
 	Records = 2,
+	BTreeNode = 3,
+	VarBTreeNode = 4,
+	Overflow = 5,
+	FreeSpaceMap = 6,
+	CatalogRoot = 7,
+	CatalogVersion = 8,
+	PackedFreelistBlock = 9,
+	PrefixVarBTreeNode = 10,
+	Free = 11,
+	Poisoned = 12,
 }
 
 impl PageKind {
-	fn from(value: u8) -> Option<Self> {
+	pub(super) fn from(value: u8) -> Option<Self> {
 		match value {
 			0 => Some(PageKind::FreelistMeta),
 			1 => Some(PageKind::FreelistBlock),
 			2 => Some(PageKind::Records),
+			3 => Some(PageKind::BTreeNode),
+			4 => Some(PageKind::VarBTreeNode),
+			5 => Some(PageKind::Overflow),
+			6 => Some(PageKind::FreeSpaceMap),
+			7 => Some(PageKind::CatalogRoot),
+			8 => Some(PageKind::CatalogVersion),
+			9 => Some(PageKind::PackedFreelistBlock),
+			10 => Some(PageKind::PrefixVarBTreeNode),
+			11 => Some(PageKind::Free),
+			12 => Some(PageKind::Poisoned),
 			_ => None,
 		}
 	}
@@ -56,6 +76,47 @@ fn assert_page_kind(page: &impl ReadPage, kind: PageKind) -> Result<(), Database
 	Ok(())
 }
 
+/// Marks a page that was put on a freelist, so that reading it as any other
+/// kind of page fails until it is allocated again.
+pub(super) fn mark_free(page: &mut impl WritePage) -> Result<(), DatabaseError> {
+	set_page_kind(page, PageKind::Free)
+}
+
+/// The pattern that the bodies of poisoned pages are filled with.
+const POISON: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+/// Marks a page that was put on a freelist like [`mark_free`], and fills the
+/// rest of it with a recognizable pattern, so that writes through dangling
+/// references to it can be detected with [`is_poison_intact`].
+pub(super) fn poison(page: &mut impl WritePage) -> Result<(), DatabaseError> {
+	let mut buf = vec![0; PAGE_BODY_SIZE];
+	buf[0] = PageKind::Poisoned as u8;
+	for (i, byte) in buf[PAGE_HEADER_SIZE..].iter_mut().enumerate() {
+		*byte = POISON[i % POISON.len()];
+	}
+	page.write(0, &buf)?;
+	Ok(())
+}
+
+/// Whether a page that was poisoned still holds exactly what [`poison`]
+/// wrote to it.
+pub(super) fn is_poison_intact(page: &impl ReadPage) -> Result<bool, DatabaseError> {
+	let mut buf = vec![0; PAGE_BODY_SIZE];
+	page.read(0, &mut buf)?;
+	Ok(buf[0] == PageKind::Poisoned as u8
+		&& buf[PAGE_HEADER_SIZE..]
+			.iter()
+			.enumerate()
+			.all(|(i, byte)| *byte == POISON[i % POISON.len()]))
+}
+
+/// Clears a page that is reused from a freelist, so that it reads like a page
+/// that was never written.
+pub(super) fn clear_page(page: &mut impl WritePage) -> Result<(), DatabaseError> {
+	page.write(0, &[0; PAGE_BODY_SIZE])?;
+	Ok(())
+}
+
 #[derive(AsBytes, FromZeroes, FromBytes)]
 #[repr(C, packed)]
 struct PageIdRepr {
@@ -108,6 +169,9 @@ pub(super) struct MetaPage<P>(P);
 impl<P> MetaPage<P> {
 	const FREELIST_HEAD_OFFSET: usize = PAGE_HEADER_SIZE;
 	const NEXT_PAGE_ID_OFFSET: usize = Self::FREELIST_HEAD_OFFSET + size_of::<PageIdRepr>();
+	const FREE_SPACE_MAP_HEAD_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const RESERVED_END_OFFSET: usize = Self::FREE_SPACE_MAP_HEAD_OFFSET + size_of::<PageIdRepr>();
+	const POISON_FREED_OFFSET: usize = Self::RESERVED_END_OFFSET + size_of::<PageIdRepr>();
 
 	pub fn new_unchecked(page: P) -> Self {
 		Self(page)
@@ -133,6 +197,30 @@ impl<P: ReadPage> MetaPage<P> {
 			.read(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes_mut())?;
 		repr.try_into()
 	}
+
+	pub fn get_free_space_map_head(&self) -> Result<Option<PageId>, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0
+			.read(Self::FREE_SPACE_MAP_HEAD_OFFSET, repr.as_bytes_mut())?;
+		Ok(repr.into())
+	}
+
+	/// For the meta page of an allocator shard, the end of the range of new
+	/// pages it has reserved; the next page ID is the start of that range.
+	pub fn get_reserved_end(&self) -> Result<PageId, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0
+			.read(Self::RESERVED_END_OFFSET, repr.as_bytes_mut())?;
+		repr.try_into()
+	}
+
+	/// For the global meta page, whether freed pages are poisoned. Meta pages
+	/// written before this flag existed read as not poisoning.
+	pub fn get_poison_freed(&self) -> Result<bool, DatabaseError> {
+		let mut byte = [0];
+		self.0.read(Self::POISON_FREED_OFFSET, &mut byte)?;
+		Ok(byte[0] != 0)
+	}
 }
 
 impl<P: WritePage> MetaPage<P> {
@@ -140,6 +228,13 @@ impl<P: WritePage> MetaPage<P> {
 		set_page_kind(&mut self.0, PageKind::FreelistMeta)?;
 		self.set_freelist_head(None)?;
 		self.set_next_page_id(next_page_id)?;
+		self.set_free_space_map_head(None)?;
+		Ok(())
+	}
+
+	pub fn init_shard(&mut self, reserved: Range<PageId>) -> Result<(), DatabaseError> {
+		self.init(reserved.start)?;
+		self.set_reserved_end(reserved.end)?;
 		Ok(())
 	}
 
@@ -154,66 +249,161 @@ impl<P: WritePage> MetaPage<P> {
 		self.0.write(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes())?;
 		Ok(())
 	}
+
+	pub fn set_free_space_map_head(&mut self, value: Option<PageId>) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0
+			.write(Self::FREE_SPACE_MAP_HEAD_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+
+	pub fn set_reserved_end(&mut self, value: PageId) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0.write(Self::RESERVED_END_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+
+	pub fn set_poison_freed(&mut self, value: bool) -> Result<(), DatabaseError> {
+		self.0
+			.write(Self::POISON_FREED_OFFSET, &[u8::from(value)])?;
+		Ok(())
+	}
 }
 
-pub(super) struct FreelistPage<P>(P);
+/// A block of the freelist.
+///
+/// Blocks written by current versions use the packed layout
+/// ([`PageKind::PackedFreelistBlock`]): every entry in a block lies in the
+/// same segment, so the block stores the segment number once, followed by a
+/// dense array of 16-bit page numbers. Blocks in the older layout
+/// ([`PageKind::FreelistBlock`]), which store a full page ID per entry, can
+/// still be read and pushed to; they are replaced by packed blocks as the
+/// freelist turns over.
+pub(super) struct FreelistPage<P> {
+	page: P,
+	packed: bool,
+}
 
 impl<P> FreelistPage<P> {
 	const NEXT_PAGE_ID_OFFSET: usize = PAGE_HEADER_SIZE;
-	const LENGTH_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+
+	const LEGACY_LENGTH_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const LEGACY_ITEMS_OFFSET: usize = Self::LEGACY_LENGTH_OFFSET + size_of::<u16>();
+
+	const SEGMENT_NUM_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const LENGTH_OFFSET: usize = Self::SEGMENT_NUM_OFFSET + size_of::<u32>();
 	const ITEMS_OFFSET: usize = Self::LENGTH_OFFSET + size_of::<u16>();
 
-	pub const NUM_SLOTS: usize = (PAGE_BODY_SIZE - Self::ITEMS_OFFSET) / size_of::<PageIdRepr>();
+	pub const LEGACY_NUM_SLOTS: usize =
+		(PAGE_BODY_SIZE - Self::LEGACY_ITEMS_OFFSET) / size_of::<PageIdRepr>();
+	pub const NUM_SLOTS: usize = (PAGE_BODY_SIZE - Self::ITEMS_OFFSET) / size_of::<u16>();
 
+	/// Wraps a page that is about to be initialized as a packed freelist
+	/// block.
 	pub fn new_unchecked(page: P) -> Self {
-		Self(page)
+		Self { page, packed: true }
 	}
 
-	fn offset_for_index(index: usize) -> Option<usize> {
-		let offset = Self::ITEMS_OFFSET + index * mem::size_of::<PageIdRepr>();
-		if offset >= PAGE_BODY_SIZE {
+	fn length_offset(&self) -> usize {
+		if self.packed {
+			Self::LENGTH_OFFSET
+		} else {
+			Self::LEGACY_LENGTH_OFFSET
+		}
+	}
+
+	fn num_slots(&self) -> usize {
+		if self.packed {
+			Self::NUM_SLOTS
+		} else {
+			Self::LEGACY_NUM_SLOTS
+		}
+	}
+
+	fn offset_for_index(&self, index: usize) -> Option<usize> {
+		if index >= self.num_slots() {
 			return None;
 		}
-		Some(offset)
+		if self.packed {
+			Some(Self::ITEMS_OFFSET + index * size_of::<u16>())
+		} else {
+			Some(Self::LEGACY_ITEMS_OFFSET + index * size_of::<PageIdRepr>())
+		}
 	}
 }
 
 impl<P: ReadPage> FreelistPage<P> {
 	pub fn new(page: P) -> Result<Self, DatabaseError> {
-		assert_page_kind(&page, PageKind::FreelistBlock)?;
-		Ok(Self::new_unchecked(page))
+		let mut byte: [u8; 1] = [0];
+		page.read(0, &mut byte)?;
+		let packed = match PageKind::from(byte[0]) {
+			Some(PageKind::PackedFreelistBlock) => true,
+			Some(PageKind::FreelistBlock) => false,
+			Some(received) => {
+				return Err(DatabaseError::UnexpectedPageKind {
+					expected: PageKind::PackedFreelistBlock,
+					received,
+				})
+			}
+			None => return Err(DatabaseError::UnknownPageKind(byte[0])),
+		};
+		Ok(Self { page, packed })
 	}
 
 	pub fn get_next_page_id(&self) -> Result<Option<PageId>, DatabaseError> {
 		let mut repr = PageIdRepr::new_zeroed();
-		self.0
+		self.page
 			.read(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes_mut())?;
 		Ok(repr.into())
 	}
 
 	pub fn get_length(&self) -> Result<usize, DatabaseError> {
 		let mut repr = [0; 2];
-		self.0.read(Self::LENGTH_OFFSET, &mut repr)?;
+		self.page.read(self.length_offset(), &mut repr)?;
 		Ok(u16::from_ne_bytes(repr).into())
 	}
 
-	pub fn is_full(&self) -> Result<bool, DatabaseError> {
-		Ok(self.get_length()? >= Self::NUM_SLOTS)
+	/// Whether `page_id` can be pushed to this block. A packed block only
+	/// accepts pages from the segment of the entries it already holds.
+	pub fn can_push(&self, page_id: PageId) -> Result<bool, DatabaseError> {
+		let length = self.get_length()?;
+		if length >= self.num_slots() {
+			return Ok(false);
+		}
+		if !self.packed || length == 0 {
+			return Ok(true);
+		}
+		Ok(self.get_segment_num()? == page_id.segment_num)
 	}
 
 	pub fn get_item(&self, index: usize) -> Result<Option<PageId>, DatabaseError> {
-		let mut repr = PageIdRepr::new_zeroed();
-		let Some(offset) = Self::offset_for_index(index) else {
+		let Some(offset) = self.offset_for_index(index) else {
 			return Ok(None);
 		};
-		self.0.read(offset, repr.as_bytes_mut())?;
-		Ok(repr.into())
+		if !self.packed {
+			let mut repr = PageIdRepr::new_zeroed();
+			self.page.read(offset, repr.as_bytes_mut())?;
+			return Ok(repr.into());
+		}
+		let mut repr = [0; 2];
+		self.page.read(offset, &mut repr)?;
+		let Some(page_num) = NonZeroU16::new(u16::from_ne_bytes(repr)) else {
+			return Ok(None);
+		};
+		Ok(Some(PageId::new(self.get_segment_num()?, page_num)))
+	}
+
+	fn get_segment_num(&self) -> Result<u32, DatabaseError> {
+		let mut repr = [0; 4];
+		self.page.read(Self::SEGMENT_NUM_OFFSET, &mut repr)?;
+		Ok(u32::from_ne_bytes(repr))
 	}
 }
 
 impl<P: WritePage> FreelistPage<P> {
 	pub fn init(&mut self) -> Result<(), DatabaseError> {
-		set_page_kind(&mut self.0, PageKind::FreelistBlock)?;
+		set_page_kind(&mut self.page, PageKind::PackedFreelistBlock)?;
+		self.packed = true;
 		self.set_next_page_id(None)?;
 		self.set_length(0)?;
 		Ok(())
@@ -221,29 +411,52 @@ impl<P: WritePage> FreelistPage<P> {
 
 	pub fn set_next_page_id(&mut self, value: Option<PageId>) -> Result<(), DatabaseError> {
 		let repr = PageIdRepr::from(value);
-		self.0.write(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes())?;
+		self.page
+			.write(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes())?;
 		Ok(())
 	}
 
 	fn set_length(&mut self, value: usize) -> Result<(), DatabaseError> {
 		let repr = u16::try_from(value).expect("Freelist page length must be 16-bit!");
-		self.0.write(Self::LENGTH_OFFSET, &repr.to_ne_bytes())?;
+		self.page.write(self.length_offset(), &repr.to_ne_bytes())?;
+		Ok(())
+	}
+
+	fn set_segment_num(&mut self, value: u32) -> Result<(), DatabaseError> {
+		self.page
+			.write(Self::SEGMENT_NUM_OFFSET, &value.to_ne_bytes())?;
 		Ok(())
 	}
 
 	fn set_item(&mut self, index: usize, value: Option<PageId>) -> Result<(), DatabaseError> {
-		let repr = PageIdRepr::from(value);
-		let Some(offset) = Self::offset_for_index(index) else {
+		let Some(offset) = self.offset_for_index(index) else {
 			return Err(DatabaseError::PageIndexOutOfBounds);
 		};
-		self.0.write(offset, repr.as_bytes())?;
+		if self.packed {
+			let page_num = value.map_or(0, |page_id| page_id.page_num.get());
+			self.page.write(offset, &page_num.to_ne_bytes())?;
+		} else {
+			let repr = PageIdRepr::from(value);
+			self.page.write(offset, repr.as_bytes())?;
+		}
 		Ok(())
 	}
 }
 
 impl<P: ReadPage + WritePage> FreelistPage<P> {
+	/// Pushes `value` to the block; callers must check
+	/// [`FreelistPage::can_push`] first.
 	pub fn push_item(&mut self, value: PageId) -> Result<(), DatabaseError> {
 		let index = self.get_length()?;
+		if self.packed {
+			if index == 0 {
+				self.set_segment_num(value.segment_num)?;
+			} else if self.get_segment_num()? != value.segment_num {
+				return Err(DatabaseError::PageFormat(format!(
+					"Cannot push page {value} to a freelist block for another segment"
+				)));
+			}
+		}
 		self.set_item(index, Some(value))?;
 		self.set_length(index + 1)?;
 		Ok(())
@@ -264,6 +477,1138 @@ impl<P: ReadPage + WritePage> FreelistPage<P> {
 	}
 }
 
+#[derive(AsBytes, FromZeroes, FromBytes)]
+#[repr(C, packed)]
+struct FreeSpaceEntryRepr {
+	page_id: PageIdRepr,
+	level: u8,
+}
+
+/// A page of the free-space map, which records for other pages roughly how
+/// much space they have left, as a level between 0 (full) and 255 (empty).
+pub(super) struct FreeSpaceMapPage<P>(P);
+
+impl<P> FreeSpaceMapPage<P> {
+	const NEXT_PAGE_ID_OFFSET: usize = PAGE_HEADER_SIZE;
+	const LENGTH_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const ENTRIES_OFFSET: usize = Self::LENGTH_OFFSET + size_of::<u16>();
+
+	pub const NUM_SLOTS: usize =
+		(PAGE_BODY_SIZE - Self::ENTRIES_OFFSET) / size_of::<FreeSpaceEntryRepr>();
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+
+	fn offset_for_index(index: usize) -> Result<usize, DatabaseError> {
+		if index >= Self::NUM_SLOTS {
+			return Err(DatabaseError::PageIndexOutOfBounds);
+		}
+		Ok(Self::ENTRIES_OFFSET + index * size_of::<FreeSpaceEntryRepr>())
+	}
+}
+
+impl<P: ReadPage> FreeSpaceMapPage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::FreeSpaceMap)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn get_next_page_id(&self) -> Result<Option<PageId>, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0
+			.read(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes_mut())?;
+		Ok(repr.into())
+	}
+
+	pub fn get_length(&self) -> Result<usize, DatabaseError> {
+		let mut repr = [0; 2];
+		self.0.read(Self::LENGTH_OFFSET, &mut repr)?;
+		Ok(u16::from_ne_bytes(repr).into())
+	}
+
+	pub fn is_full(&self) -> Result<bool, DatabaseError> {
+		Ok(self.get_length()? >= Self::NUM_SLOTS)
+	}
+
+	/// Returns the page and space level of the entry at `index`.
+	pub fn get_entry(&self, index: usize) -> Result<(PageId, u8), DatabaseError> {
+		let mut repr = FreeSpaceEntryRepr::new_zeroed();
+		self.0
+			.read(Self::offset_for_index(index)?, repr.as_bytes_mut())?;
+		Ok((repr.page_id.try_into()?, repr.level))
+	}
+
+	/// Returns the index of the entry for the page, if there is one.
+	pub fn find_entry(&self, page_id: PageId) -> Result<Option<usize>, DatabaseError> {
+		for index in 0..self.get_length()? {
+			if self.get_entry(index)?.0 == page_id {
+				return Ok(Some(index));
+			}
+		}
+		Ok(None)
+	}
+}
+
+impl<P: WritePage> FreeSpaceMapPage<P> {
+	pub fn init(&mut self) -> Result<(), DatabaseError> {
+		set_page_kind(&mut self.0, PageKind::FreeSpaceMap)?;
+		self.set_next_page_id(None)?;
+		self.set_length(0)?;
+		Ok(())
+	}
+
+	pub fn set_next_page_id(&mut self, value: Option<PageId>) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0.write(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+
+	fn set_length(&mut self, value: usize) -> Result<(), DatabaseError> {
+		let repr = u16::try_from(value).expect("Free-space map page length must be 16-bit!");
+		self.0.write(Self::LENGTH_OFFSET, &repr.to_ne_bytes())?;
+		Ok(())
+	}
+
+	pub fn set_entry(
+		&mut self,
+		index: usize,
+		page_id: PageId,
+		level: u8,
+	) -> Result<(), DatabaseError> {
+		let repr = FreeSpaceEntryRepr {
+			page_id: page_id.into(),
+			level,
+		};
+		self.0
+			.write(Self::offset_for_index(index)?, repr.as_bytes())?;
+		Ok(())
+	}
+}
+
+impl<P: ReadPage + WritePage> FreeSpaceMapPage<P> {
+	pub fn push_entry(&mut self, page_id: PageId, level: u8) -> Result<(), DatabaseError> {
+		let index = self.get_length()?;
+		self.set_entry(index, page_id, level)?;
+		self.set_length(index + 1)?;
+		Ok(())
+	}
+
+	/// Removes the entry at `index` by moving the last entry in its place.
+	pub fn remove_entry(&mut self, index: usize) -> Result<(), DatabaseError> {
+		let last = self.get_length()? - 1;
+		if index != last {
+			let (page_id, level) = self.get_entry(last)?;
+			self.set_entry(index, page_id, level)?;
+		}
+		self.set_length(last)?;
+		Ok(())
+	}
+}
+
+/// The contents of a B-tree node. Internal nodes have one more child than
+/// keys; the child at `i + 1` holds the keys that are at least `keys[i]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum BTreeNode {
+	Leaf(Vec<(u64, DbPointer)>),
+	Internal {
+		keys: Vec<u64>,
+		children: Vec<PageId>,
+	},
+}
+
+impl BTreeNode {
+	pub fn len(&self) -> usize {
+		match self {
+			Self::Leaf(entries) => entries.len(),
+			Self::Internal { keys, .. } => keys.len(),
+		}
+	}
+}
+
+pub(super) struct BTreePage<P>(P);
+
+impl<P> BTreePage<P> {
+	const IS_LEAF_OFFSET: usize = PAGE_HEADER_SIZE;
+	const LENGTH_OFFSET: usize = Self::IS_LEAF_OFFSET + size_of::<u8>();
+	const ITEMS_OFFSET: usize = Self::LENGTH_OFFSET + size_of::<u16>();
+
+	const LEAF_ENTRY_SIZE: usize = size_of::<u64>() + size_of::<DbPointerRepr>();
+	const INTERNAL_ENTRY_SIZE: usize = size_of::<u64>() + size_of::<PageIdRepr>();
+
+	pub const LEAF_CAPACITY: usize = (PAGE_BODY_SIZE - Self::ITEMS_OFFSET) / Self::LEAF_ENTRY_SIZE;
+	pub const INTERNAL_CAPACITY: usize =
+		(PAGE_BODY_SIZE - Self::ITEMS_OFFSET - size_of::<PageIdRepr>()) / Self::INTERNAL_ENTRY_SIZE;
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+}
+
+impl<P: ReadPage> BTreePage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::BTreeNode)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn read_node(&self) -> Result<BTreeNode, DatabaseError> {
+		let mut header = [0; 3];
+		self.0.read(Self::IS_LEAF_OFFSET, &mut header)?;
+		let is_leaf = header[0] != 0;
+		let len = usize::from(u16::from_ne_bytes([header[1], header[2]]));
+
+		if is_leaf {
+			if len > Self::LEAF_CAPACITY {
+				return Err(DatabaseError::PageFormat(format!(
+					"B-tree leaf has {len} entries, which exceeds its capacity"
+				)));
+			}
+			let mut buf = vec![0; len * Self::LEAF_ENTRY_SIZE];
+			self.0.read(Self::ITEMS_OFFSET, &mut buf)?;
+			let entries = buf
+				.chunks_exact(Self::LEAF_ENTRY_SIZE)
+				.map(|entry| {
+					let (key, value) = entry.split_at(size_of::<u64>());
+					let value = DbPointerRepr::read_from(value).unwrap();
+					Ok((
+						u64::from_ne_bytes(key.try_into().unwrap()),
+						value.try_into()?,
+					))
+				})
+				.collect::<Result<_, DatabaseError>>()?;
+			return Ok(BTreeNode::Leaf(entries));
+		}
+
+		if len > Self::INTERNAL_CAPACITY {
+			return Err(DatabaseError::PageFormat(format!(
+				"B-tree internal node has {len} keys, which exceeds its capacity"
+			)));
+		}
+		let mut buf = vec![0; size_of::<PageIdRepr>() + len * Self::INTERNAL_ENTRY_SIZE];
+		self.0.read(Self::ITEMS_OFFSET, &mut buf)?;
+		let (first_child, entries) = buf.split_at(size_of::<PageIdRepr>());
+		let mut keys = Vec::with_capacity(len);
+		let mut children = Vec::with_capacity(len + 1);
+		children.push(PageIdRepr::read_from(first_child).unwrap().try_into()?);
+		for entry in entries.chunks_exact(Self::INTERNAL_ENTRY_SIZE) {
+			let (key, child) = entry.split_at(size_of::<u64>());
+			keys.push(u64::from_ne_bytes(key.try_into().unwrap()));
+			children.push(PageIdRepr::read_from(child).unwrap().try_into()?);
+		}
+		Ok(BTreeNode::Internal { keys, children })
+	}
+}
+
+impl<P: WritePage> BTreePage<P> {
+	pub fn write_node(&mut self, node: &BTreeNode) -> Result<(), DatabaseError> {
+		let (is_leaf, buf) = match node {
+			BTreeNode::Leaf(entries) => {
+				if entries.len() > Self::LEAF_CAPACITY {
+					return Err(DatabaseError::PageIndexOutOfBounds);
+				}
+				let mut buf = Vec::with_capacity(entries.len() * Self::LEAF_ENTRY_SIZE);
+				for (key, value) in entries {
+					buf.extend_from_slice(&key.to_ne_bytes());
+					buf.extend_from_slice(DbPointerRepr::from(*value).as_bytes());
+				}
+				(true, buf)
+			}
+			BTreeNode::Internal { keys, children } => {
+				debug_assert_eq!(keys.len() + 1, children.len());
+				if keys.len() > Self::INTERNAL_CAPACITY {
+					return Err(DatabaseError::PageIndexOutOfBounds);
+				}
+				let mut buf = Vec::with_capacity(
+					size_of::<PageIdRepr>() + keys.len() * Self::INTERNAL_ENTRY_SIZE,
+				);
+				buf.extend_from_slice(PageIdRepr::from(children[0]).as_bytes());
+				for (key, child) in keys.iter().zip(&children[1..]) {
+					buf.extend_from_slice(&key.to_ne_bytes());
+					buf.extend_from_slice(PageIdRepr::from(*child).as_bytes());
+				}
+				(false, buf)
+			}
+		};
+		let len = u16::try_from(node.len()).expect("B-tree node length must be 16-bit!");
+
+		set_page_kind(&mut self.0, PageKind::BTreeNode)?;
+		let [len_low, len_high] = len.to_ne_bytes();
+		self.0.write(
+			Self::IS_LEAF_OFFSET,
+			&[u8::from(is_leaf), len_low, len_high],
+		)?;
+		self.0.write(Self::ITEMS_OFFSET, &buf)?;
+		Ok(())
+	}
+}
+
+impl<P: ReadPage + WritePage> BTreePage<P> {
+	/// Zeroes the space after the entries of the node, which may still hold
+	/// entries that were removed.
+	pub fn canonicalize(&mut self) -> Result<(), DatabaseError> {
+		let node = self.read_node()?;
+		self.0.write(0, &[0; PAGE_BODY_SIZE])?;
+		self.write_node(&node)
+	}
+}
+
+/// The contents of a B-tree node with variable-length keys, laid out like
+/// [`BTreeNode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum VarBTreeNode {
+	Leaf(Vec<(Vec<u8>, DbPointer)>),
+	Internal {
+		keys: Vec<Vec<u8>>,
+		children: Vec<PageId>,
+	},
+}
+
+impl VarBTreeNode {
+	pub fn len(&self) -> usize {
+		match self {
+			Self::Leaf(entries) => entries.len(),
+			Self::Internal { keys, .. } => keys.len(),
+		}
+	}
+
+	pub fn is_leaf(&self) -> bool {
+		matches!(self, Self::Leaf(..))
+	}
+
+	/// The number of bytes the node takes up on a page.
+	pub fn size(&self) -> usize {
+		match self {
+			Self::Leaf(entries) => Self::leaf_size(entries),
+			Self::Internal { keys, .. } => Self::internal_size(keys),
+		}
+	}
+
+	pub fn leaf_size(entries: &[(Vec<u8>, DbPointer)]) -> usize {
+		Self::size_of_keys(entries.iter().map(|(key, _)| key.as_slice()), true)
+	}
+
+	pub fn internal_size(keys: &[Vec<u8>]) -> usize {
+		Self::size_of_keys(keys.iter().map(Vec::as_slice), false)
+	}
+
+	fn size_of_keys<'a>(keys: impl Iterator<Item = &'a [u8]> + Clone, is_leaf: bool) -> usize {
+		let prefix_len = common_prefix_len(keys.clone());
+		let slots: usize = keys
+			.map(|key| VarBTreePage::<()>::slot_size(key.len() - prefix_len, is_leaf))
+			.sum();
+		VarBTreePage::<()>::SLOTS_OFFSET + prefix_len + slots
+	}
+}
+
+/// The length of the longest prefix that all `keys` share.
+fn common_prefix_len<'a>(mut keys: impl Iterator<Item = &'a [u8]>) -> usize {
+	let Some(first) = keys.next() else {
+		return 0;
+	};
+	keys.fold(first.len(), |len, key| {
+		first[..len]
+			.iter()
+			.zip(key)
+			.take_while(|(a, b)| a == b)
+			.count()
+	})
+}
+
+/// A slotted page holding a [`VarBTreeNode`].
+///
+/// After the header, the page has an array of 16-bit offsets, one for each
+/// entry, in key order. The offsets point to cells that are packed towards the
+/// end of the page, each consisting of the key length, the key and the value.
+/// Internal nodes keep their first child in the header.
+///
+/// The prefix that all keys in the node share is stored only once, in the
+/// last bytes of the page, with its length in the header; cells hold just the
+/// rest of each key. Pages written before prefixes were split off
+/// ([`PageKind::VarBTreeNode`]) have no prefix length in the header, and are
+/// still read.
+pub(super) struct VarBTreePage<P>(P);
+
+impl<P> VarBTreePage<P> {
+	const IS_LEAF_OFFSET: usize = PAGE_HEADER_SIZE;
+	const NUM_SLOTS_OFFSET: usize = Self::IS_LEAF_OFFSET + size_of::<u8>();
+	const FIRST_CHILD_OFFSET: usize = Self::NUM_SLOTS_OFFSET + size_of::<u16>();
+	const LEGACY_SLOTS_OFFSET: usize = Self::FIRST_CHILD_OFFSET + size_of::<PageIdRepr>();
+	const PREFIX_LEN_OFFSET: usize = Self::FIRST_CHILD_OFFSET + size_of::<PageIdRepr>();
+	const SLOTS_OFFSET: usize = Self::PREFIX_LEN_OFFSET + size_of::<u16>();
+
+	/// The number of bytes available for a node.
+	pub const CAPACITY: usize = PAGE_BODY_SIZE;
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+
+	/// The number of bytes an entry takes up, including its offset.
+	pub const fn slot_size(key_len: usize, is_leaf: bool) -> usize {
+		let value_size = if is_leaf {
+			size_of::<DbPointerRepr>()
+		} else {
+			size_of::<PageIdRepr>()
+		};
+		2 * size_of::<u16>() + key_len + value_size
+	}
+
+	fn page_format_error(reason: &str) -> DatabaseError {
+		DatabaseError::PageFormat(format!("Invalid variable-length B-tree node: {reason}"))
+	}
+}
+
+impl<P: ReadPage> VarBTreePage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		let mut byte: [u8; 1] = [0];
+		page.read(0, &mut byte)?;
+		match PageKind::from(byte[0]) {
+			Some(PageKind::PrefixVarBTreeNode | PageKind::VarBTreeNode) => {
+				Ok(Self::new_unchecked(page))
+			}
+			Some(received) => Err(DatabaseError::UnexpectedPageKind {
+				expected: PageKind::PrefixVarBTreeNode,
+				received,
+			}),
+			None => Err(DatabaseError::UnknownPageKind(byte[0])),
+		}
+	}
+
+	pub fn read_node(&self) -> Result<VarBTreeNode, DatabaseError> {
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		self.0.read(0, &mut buf)?;
+
+		let is_leaf = buf[Self::IS_LEAF_OFFSET] != 0;
+		let num_slots = usize::from(u16::from_ne_bytes([
+			buf[Self::NUM_SLOTS_OFFSET],
+			buf[Self::NUM_SLOTS_OFFSET + 1],
+		]));
+		let (slots_offset, prefix_len) = if buf[0] == PageKind::PrefixVarBTreeNode as u8 {
+			let prefix_len = usize::from(u16::from_ne_bytes([
+				buf[Self::PREFIX_LEN_OFFSET],
+				buf[Self::PREFIX_LEN_OFFSET + 1],
+			]));
+			(Self::SLOTS_OFFSET, prefix_len)
+		} else {
+			(Self::LEGACY_SLOTS_OFFSET, 0)
+		};
+		let slots_end = slots_offset + num_slots * size_of::<u16>();
+		let Some(cells_end) = buf
+			.len()
+			.checked_sub(prefix_len)
+			.filter(|&end| end >= slots_end)
+		else {
+			return Err(Self::page_format_error("too many slots"));
+		};
+		let (cells, prefix) = buf.split_at(cells_end);
+
+		let mut keys = Vec::with_capacity(num_slots);
+		let mut values = Vec::with_capacity(num_slots);
+		for slot in cells[slots_offset..slots_end].chunks_exact(size_of::<u16>()) {
+			let offset = usize::from(u16::from_ne_bytes([slot[0], slot[1]]));
+			let Some(cell) = cells.get(offset..).filter(|_| offset >= slots_end) else {
+				return Err(Self::page_format_error("cell offset out of bounds"));
+			};
+			let Some((key_len, cell)) = cell.split_first_chunk::<2>() else {
+				return Err(Self::page_format_error("cell offset out of bounds"));
+			};
+			let key_len = usize::from(u16::from_ne_bytes(*key_len));
+			if cell.len() < key_len {
+				return Err(Self::page_format_error("key out of bounds"));
+			}
+			let (suffix, value) = cell.split_at(key_len);
+			keys.push([prefix, suffix].concat());
+			values.push(value);
+		}
+
+		if is_leaf {
+			let entries = keys
+				.into_iter()
+				.zip(values)
+				.map(|(key, value)| {
+					let Some(value) = DbPointerRepr::read_from_prefix(value) else {
+						return Err(Self::page_format_error("value out of bounds"));
+					};
+					Ok((key, value.try_into()?))
+				})
+				.collect::<Result<_, DatabaseError>>()?;
+			return Ok(VarBTreeNode::Leaf(entries));
+		}
+
+		let first_child = PageIdRepr::read_from_prefix(&buf[Self::FIRST_CHILD_OFFSET..]).unwrap();
+		let mut children = Vec::with_capacity(num_slots + 1);
+		children.push(first_child.try_into()?);
+		for value in values {
+			let Some(child) = PageIdRepr::read_from_prefix(value) else {
+				return Err(Self::page_format_error("child out of bounds"));
+			};
+			children.push(child.try_into()?);
+		}
+		Ok(VarBTreeNode::Internal { keys, children })
+	}
+}
+
+impl<P: WritePage> VarBTreePage<P> {
+	pub fn write_node(&mut self, node: &VarBTreeNode) -> Result<(), DatabaseError> {
+		if node.size() > Self::CAPACITY {
+			return Err(DatabaseError::PageIndexOutOfBounds);
+		}
+
+		let mut cells: Vec<(&[u8], Vec<u8>)> = Vec::with_capacity(node.len());
+		let first_child = match node {
+			VarBTreeNode::Leaf(entries) => {
+				for (key, value) in entries {
+					cells.push((key, DbPointerRepr::from(*value).as_bytes().to_vec()));
+				}
+				PageIdRepr::from(None)
+			}
+			VarBTreeNode::Internal { keys, children } => {
+				debug_assert_eq!(keys.len() + 1, children.len());
+				for (key, child) in keys.iter().zip(&children[1..]) {
+					cells.push((key, PageIdRepr::from(*child).as_bytes().to_vec()));
+				}
+				PageIdRepr::from(children[0])
+			}
+		};
+
+		let prefix_len = common_prefix_len(cells.iter().map(|(key, _)| *key));
+		let prefix = cells.first().map_or(&[][..], |(key, _)| &key[..prefix_len]);
+		let cells_size: usize = cells
+			.iter()
+			.map(|(key, value)| size_of::<u16>() + key.len() - prefix_len + value.len())
+			.sum();
+		let cells_start = PAGE_BODY_SIZE - prefix_len - cells_size;
+		let mut header = Vec::with_capacity(Self::SLOTS_OFFSET + cells.len() * size_of::<u16>());
+		header.push(u8::from(node.is_leaf()));
+		header.extend_from_slice(
+			&u16::try_from(cells.len())
+				.expect("B-tree node length must be 16-bit!")
+				.to_ne_bytes(),
+		);
+		header.extend_from_slice(first_child.as_bytes());
+		header.extend_from_slice(
+			&u16::try_from(prefix_len)
+				.expect("B-tree keys must be shorter than a page!")
+				.to_ne_bytes(),
+		);
+		let mut cell_buf = Vec::with_capacity(cells_size + prefix_len);
+		for (key, value) in &cells {
+			let offset =
+				u16::try_from(cells_start + cell_buf.len()).expect("Page offsets must be 16-bit!");
+			header.extend_from_slice(&offset.to_ne_bytes());
+			let suffix = &key[prefix_len..];
+			let key_len =
+				u16::try_from(suffix.len()).expect("B-tree keys must be shorter than a page!");
+			cell_buf.extend_from_slice(&key_len.to_ne_bytes());
+			cell_buf.extend_from_slice(suffix);
+			cell_buf.extend_from_slice(value);
+		}
+		cell_buf.extend_from_slice(prefix);
+
+		set_page_kind(&mut self.0, PageKind::PrefixVarBTreeNode)?;
+		self.0.write(Self::IS_LEAF_OFFSET, &header)?;
+		self.0.write(cells_start, &cell_buf)?;
+		Ok(())
+	}
+}
+
+impl<P: ReadPage + WritePage> VarBTreePage<P> {
+	/// Zeroes the space between the slots and the cells of the node, which
+	/// may still hold cells that were removed. Nodes in the layout without a
+	/// shared prefix are rewritten in the current layout.
+	pub fn canonicalize(&mut self) -> Result<(), DatabaseError> {
+		let node = self.read_node()?;
+		self.0.write(0, &[0; PAGE_BODY_SIZE])?;
+		self.write_node(&node)
+	}
+}
+
+/// Reads `data`, padded or cut off to the size of a page, as a node of both
+/// kinds of B-tree.
+#[cfg(feature = "fuzzing")]
+pub(crate) fn fuzz_btree_page(data: &[u8]) {
+	use crate::page_store::StorageError;
+
+	struct FuzzPage(Vec<u8>);
+
+	impl ReadPage for FuzzPage {
+		fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
+			let Some(bytes) = offset
+				.checked_add(buf.len())
+				.and_then(|end| self.0.get(offset..end))
+			else {
+				return Err(StorageError::PageOutOfBounds {
+					offset,
+					len: buf.len(),
+				});
+			};
+			buf.copy_from_slice(bytes);
+			Ok(())
+		}
+	}
+
+	let mut page = data.to_vec();
+	page.resize(PAGE_BODY_SIZE, 0);
+	let page = FuzzPage(page);
+	let _ = BTreePage::new_unchecked(&page).read_node();
+	let _ = VarBTreePage::new_unchecked(&page).read_node();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(super) enum RecordKind {
+	/// The record is stored in the page itself.
+	Inline = 1,
+	/// The page stores the length of the record and the first of the
+	/// overflow pages that hold its data.
+	Overflow = 2,
+	/// The record was deleted, and the page stores the ID of the transaction
+	/// that deleted it.
+	Tombstone = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct RecordSlot {
+	pub kind: RecordKind,
+	offset: u16,
+	length: u16,
+}
+
+/// Refers to a value that is stored in a chain of overflow pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct OverflowRef {
+	pub length: u32,
+	pub first_page: PageId,
+}
+
+#[derive(FromZeroes, FromBytes, AsBytes)]
+#[repr(C, packed)]
+struct OverflowRefRepr {
+	length: u32,
+	first_page: PageIdRepr,
+}
+
+impl OverflowRef {
+	pub fn to_bytes(self) -> Vec<u8> {
+		OverflowRefRepr {
+			length: self.length,
+			first_page: self.first_page.into(),
+		}
+		.as_bytes()
+		.to_vec()
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DatabaseError> {
+		let Some(repr) = OverflowRefRepr::read_from(bytes) else {
+			return Err(DatabaseError::PageFormat(
+				"Invalid overflow record reference".to_string(),
+			));
+		};
+		Ok(Self {
+			length: repr.length,
+			first_page: repr.first_page.try_into()?,
+		})
+	}
+}
+
+#[derive(Debug, Clone, Copy, FromZeroes, FromBytes, AsBytes)]
+#[repr(C, packed)]
+struct RecordSlotRepr {
+	offset: u16,
+	length: u16,
+	kind: u8,
+}
+
+/// A slotted page of records.
+///
+/// After the header, the page has an array of slots, which hold the offset,
+/// length and kind of each record. The records themselves are stored at the
+/// end of the page, growing towards the slots. The index of a slot never
+/// changes while its record exists, so it can be used to refer to the record.
+pub(super) struct RecordPage<P>(P);
+
+impl<P> RecordPage<P> {
+	const NEXT_PAGE_ID_OFFSET: usize = PAGE_HEADER_SIZE;
+	const NUM_SLOTS_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const DATA_START_OFFSET: usize = Self::NUM_SLOTS_OFFSET + size_of::<u16>();
+	const GARBAGE_OFFSET: usize = Self::DATA_START_OFFSET + size_of::<u16>();
+	const SLOTS_OFFSET: usize = Self::GARBAGE_OFFSET + size_of::<u16>();
+
+	/// Records are always given at least this much space, so that they can be
+	/// turned into overflow records in place.
+	pub const MIN_RECORD_SIZE: usize = size_of::<OverflowRefRepr>();
+
+	/// Records longer than this are stored in overflow pages.
+	pub const MAX_INLINE_SIZE: usize =
+		(PAGE_BODY_SIZE - Self::SLOTS_OFFSET) / 4 - size_of::<RecordSlotRepr>();
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+
+	fn slot_offset(index: u16) -> usize {
+		Self::SLOTS_OFFSET + usize::from(index) * size_of::<RecordSlotRepr>()
+	}
+
+	fn reserved_size(length: usize) -> usize {
+		usize::max(length, Self::MIN_RECORD_SIZE)
+	}
+}
+
+impl<P: ReadPage> RecordPage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::Records)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn get_next_page_id(&self) -> Result<Option<PageId>, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0
+			.read(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes_mut())?;
+		Ok(repr.into())
+	}
+
+	/// The number of slots in the page, including empty ones.
+	pub fn num_slots(&self) -> Result<u16, DatabaseError> {
+		self.get_u16(Self::NUM_SLOTS_OFFSET)
+	}
+
+	pub fn get_slot(&self, index: u16) -> Result<Option<RecordSlot>, DatabaseError> {
+		if index >= self.num_slots()? {
+			return Ok(None);
+		}
+		let mut repr = RecordSlotRepr::new_zeroed();
+		self.0.read(Self::slot_offset(index), repr.as_bytes_mut())?;
+		let kind = match repr.kind {
+			0 => return Ok(None),
+			1 => RecordKind::Inline,
+			2 => RecordKind::Overflow,
+			3 => RecordKind::Tombstone,
+			other => {
+				return Err(DatabaseError::PageFormat(format!(
+					"Unknown record kind {other}"
+				)))
+			}
+		};
+		let slot = RecordSlot {
+			kind,
+			offset: repr.offset,
+			length: repr.length,
+		};
+		if usize::from(slot.offset) + usize::from(slot.length) > PAGE_BODY_SIZE {
+			return Err(DatabaseError::PageFormat(format!(
+				"Record {index} exceeds the page bounds"
+			)));
+		}
+		Ok(Some(slot))
+	}
+
+	pub fn read_record(&self, slot: RecordSlot) -> Result<Vec<u8>, DatabaseError> {
+		let mut buf = vec![0; slot.length.into()];
+		self.0.read(slot.offset.into(), &mut buf)?;
+		Ok(buf)
+	}
+
+	/// Whether a new record of the given length fits in the page.
+	pub fn can_insert(&self, length: usize) -> Result<bool, DatabaseError> {
+		let slot_size = if self.find_empty_slot()?.is_some() {
+			0
+		} else {
+			size_of::<RecordSlotRepr>()
+		};
+		Ok(self.available_space()? >= slot_size + Self::reserved_size(length))
+	}
+
+	/// The number of bytes that records could take up, including their slots,
+	/// if the page were compacted.
+	fn available_space(&self) -> Result<usize, DatabaseError> {
+		Ok(self.contiguous_space()? + usize::from(self.get_u16(Self::GARBAGE_OFFSET)?))
+	}
+
+	fn contiguous_space(&self) -> Result<usize, DatabaseError> {
+		let slots_end = Self::slot_offset(self.get_u16(Self::NUM_SLOTS_OFFSET)?);
+		let data_start = usize::from(self.get_u16(Self::DATA_START_OFFSET)?);
+		Ok(data_start.saturating_sub(slots_end))
+	}
+
+	fn find_empty_slot(&self) -> Result<Option<u16>, DatabaseError> {
+		for index in 0..self.get_u16(Self::NUM_SLOTS_OFFSET)? {
+			let mut repr = RecordSlotRepr::new_zeroed();
+			self.0.read(Self::slot_offset(index), repr.as_bytes_mut())?;
+			if repr.kind == 0 {
+				return Ok(Some(index));
+			}
+		}
+		Ok(None)
+	}
+
+	fn get_u16(&self, offset: usize) -> Result<u16, DatabaseError> {
+		let mut repr = [0; 2];
+		self.0.read(offset, &mut repr)?;
+		Ok(u16::from_ne_bytes(repr))
+	}
+}
+
+impl<P: WritePage> RecordPage<P> {
+	pub fn init(&mut self) -> Result<(), DatabaseError> {
+		set_page_kind(&mut self.0, PageKind::Records)?;
+		self.set_next_page_id(None)?;
+		self.set_u16(Self::NUM_SLOTS_OFFSET, 0)?;
+		self.set_u16(
+			Self::DATA_START_OFFSET,
+			u16::try_from(PAGE_BODY_SIZE).expect("Page offsets must be 16-bit!"),
+		)?;
+		self.set_u16(Self::GARBAGE_OFFSET, 0)?;
+		Ok(())
+	}
+
+	pub fn set_next_page_id(&mut self, value: Option<PageId>) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0.write(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+
+	fn set_slot(&mut self, index: u16, slot: Option<RecordSlot>) -> Result<(), DatabaseError> {
+		let repr = match slot {
+			Some(slot) => RecordSlotRepr {
+				offset: slot.offset,
+				length: slot.length,
+				kind: slot.kind as u8,
+			},
+			None => RecordSlotRepr::new_zeroed(),
+		};
+		self.0.write(Self::slot_offset(index), repr.as_bytes())?;
+		Ok(())
+	}
+
+	fn set_u16(&mut self, offset: usize, value: u16) -> Result<(), DatabaseError> {
+		self.0.write(offset, &value.to_ne_bytes())?;
+		Ok(())
+	}
+}
+
+impl<P: ReadPage + WritePage> RecordPage<P> {
+	/// Stores a record in a free slot, and returns the index of the slot, or
+	/// `None` if the page doesn't have enough space.
+	pub fn insert(&mut self, kind: RecordKind, data: &[u8]) -> Result<Option<u16>, DatabaseError> {
+		if !self.can_insert(data.len())? {
+			return Ok(None);
+		}
+
+		let index = match self.find_empty_slot()? {
+			Some(index) => index,
+			None => {
+				let index = self.get_u16(Self::NUM_SLOTS_OFFSET)?;
+				self.set_slot(index, None)?;
+				self.set_u16(Self::NUM_SLOTS_OFFSET, index + 1)?;
+				index
+			}
+		};
+		self.write_record(index, kind, data)?;
+		Ok(Some(index))
+	}
+
+	/// Replaces the record in a slot, and returns `false` without changing it
+	/// if the page doesn't have enough space for the new record.
+	pub fn replace(
+		&mut self,
+		index: u16,
+		kind: RecordKind,
+		data: &[u8],
+	) -> Result<bool, DatabaseError> {
+		let Some(slot) = self.get_slot(index)? else {
+			return Err(DatabaseError::PageIndexOutOfBounds);
+		};
+		let old_size = Self::reserved_size(slot.length.into());
+		let new_size = Self::reserved_size(data.len());
+		if new_size <= old_size {
+			self.0.write(slot.offset.into(), data)?;
+			self.add_garbage(old_size - new_size)?;
+			self.set_slot(
+				index,
+				Some(RecordSlot {
+					kind,
+					length: data.len().try_into().unwrap(),
+					..slot
+				}),
+			)?;
+			return Ok(true);
+		}
+		if self.available_space()? + old_size < new_size {
+			return Ok(false);
+		}
+		self.remove(index)?;
+		self.write_record(index, kind, data)?;
+		Ok(true)
+	}
+
+	/// Removes the record in a slot, which may then be reused.
+	pub fn remove(&mut self, index: u16) -> Result<Option<RecordSlot>, DatabaseError> {
+		let Some(slot) = self.get_slot(index)? else {
+			return Ok(None);
+		};
+		self.set_slot(index, None)?;
+		self.add_garbage(Self::reserved_size(slot.length.into()))?;
+		Ok(Some(slot))
+	}
+
+	/// Writes a record to an empty slot. The page must have enough space for
+	/// it, possibly after being compacted.
+	fn write_record(
+		&mut self,
+		index: u16,
+		kind: RecordKind,
+		data: &[u8],
+	) -> Result<(), DatabaseError> {
+		let size = Self::reserved_size(data.len());
+		if self.contiguous_space()? < size {
+			self.compact()?;
+		}
+		let offset = usize::from(self.get_u16(Self::DATA_START_OFFSET)?) - size;
+		let offset = u16::try_from(offset).expect("Page offsets must be 16-bit!");
+		self.0.write(offset.into(), data)?;
+		self.set_u16(Self::DATA_START_OFFSET, offset)?;
+		self.set_slot(
+			index,
+			Some(RecordSlot {
+				kind,
+				offset,
+				length: data.len().try_into().unwrap(),
+			}),
+		)
+	}
+
+	/// Moves all records to the end of the page, so that the space of removed
+	/// records can be reused.
+	fn compact(&mut self) -> Result<(), DatabaseError> {
+		let mut records = Vec::new();
+		for index in 0..self.get_u16(Self::NUM_SLOTS_OFFSET)? {
+			if let Some(slot) = self.get_slot(index)? {
+				records.push((index, slot, self.read_record(slot)?));
+			}
+		}
+
+		let mut data_start = PAGE_BODY_SIZE;
+		for (index, slot, data) in records {
+			data_start -= Self::reserved_size(data.len());
+			let offset = u16::try_from(data_start).expect("Page offsets must be 16-bit!");
+			self.0.write(data_start, &data)?;
+			self.set_slot(index, Some(RecordSlot { offset, ..slot }))?;
+		}
+		self.set_u16(
+			Self::DATA_START_OFFSET,
+			u16::try_from(data_start).expect("Page offsets must be 16-bit!"),
+		)?;
+		self.set_u16(Self::GARBAGE_OFFSET, 0)?;
+		Ok(())
+	}
+
+	/// Compacts the page, drops empty slots at the end of the slot array, and
+	/// zeroes all space that isn't taken up by slots or records, so that
+	/// pages with the same records have the same contents regardless of how
+	/// they were modified. Records keep their slots.
+	pub fn canonicalize(&mut self) -> Result<(), DatabaseError> {
+		self.compact()?;
+		let mut num_slots = self.get_u16(Self::NUM_SLOTS_OFFSET)?;
+		while num_slots > 0 && self.get_slot(num_slots - 1)?.is_none() {
+			num_slots -= 1;
+		}
+		let mut records = Vec::new();
+		for index in 0..num_slots {
+			if let Some(slot) = self.get_slot(index)? {
+				records.push((slot, self.read_record(slot)?));
+			}
+		}
+
+		let slots_end = Self::slot_offset(num_slots);
+		self.0
+			.write(slots_end, &vec![0; PAGE_BODY_SIZE - slots_end])?;
+		for (slot, data) in records {
+			self.0.write(slot.offset.into(), &data)?;
+		}
+		self.set_u16(Self::NUM_SLOTS_OFFSET, num_slots)
+	}
+
+	fn add_garbage(&mut self, size: usize) -> Result<(), DatabaseError> {
+		let garbage = usize::from(self.get_u16(Self::GARBAGE_OFFSET)?) + size;
+		self.set_u16(
+			Self::GARBAGE_OFFSET,
+			u16::try_from(garbage).expect("Page offsets must be 16-bit!"),
+		)
+	}
+}
+
+/// A page that holds part of a value that is too large for a single page,
+/// and links to the page with the next part.
+pub(super) struct OverflowPage<P>(P);
+
+impl<P> OverflowPage<P> {
+	const NEXT_PAGE_ID_OFFSET: usize = PAGE_HEADER_SIZE;
+	const LENGTH_OFFSET: usize = Self::NEXT_PAGE_ID_OFFSET + size_of::<PageIdRepr>();
+	const DATA_OFFSET: usize = Self::LENGTH_OFFSET + size_of::<u16>();
+
+	pub const CAPACITY: usize = PAGE_BODY_SIZE - Self::DATA_OFFSET;
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+}
+
+impl<P: ReadPage> OverflowPage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::Overflow)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn get_next_page_id(&self) -> Result<Option<PageId>, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0
+			.read(Self::NEXT_PAGE_ID_OFFSET, repr.as_bytes_mut())?;
+		Ok(repr.into())
+	}
+
+	/// Appends the data stored in the page to `buf`.
+	pub fn read_data(&self, buf: &mut Vec<u8>) -> Result<(), DatabaseError> {
+		let mut length = [0; 2];
+		self.0.read(Self::LENGTH_OFFSET, &mut length)?;
+		let length = usize::from(u16::from_ne_bytes(length));
+		if length > Self::CAPACITY {
+			return Err(DatabaseError::PageFormat(format!(
+				"Overflow page length {length} exceeds its capacity"
+			)));
+		}
+		let start = buf.len();
+		buf.resize(start + length, 0);
+		self.0.read(Self::DATA_OFFSET, &mut buf[start..])?;
+		Ok(())
+	}
+}
+
+impl<P: WritePage> OverflowPage<P> {
+	pub fn init(&mut self, next_page_id: Option<PageId>, data: &[u8]) -> Result<(), DatabaseError> {
+		if data.len() > Self::CAPACITY {
+			return Err(DatabaseError::PageIndexOutOfBounds);
+		}
+		set_page_kind(&mut self.0, PageKind::Overflow)?;
+		self.0.write(
+			Self::NEXT_PAGE_ID_OFFSET,
+			PageIdRepr::from(next_page_id).as_bytes(),
+		)?;
+		let length = u16::try_from(data.len()).expect("Page offsets must be 16-bit!");
+		self.0.write(Self::LENGTH_OFFSET, &length.to_ne_bytes())?;
+		self.0.write(Self::DATA_OFFSET, data)?;
+		Ok(())
+	}
+}
+
+/// The fixed page that refers to the current version of the catalog.
+pub(super) struct CatalogRootPage<P>(P);
+
+impl<P> CatalogRootPage<P> {
+	const CURRENT_OFFSET: usize = PAGE_HEADER_SIZE;
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+}
+
+impl<P: ReadPage> CatalogRootPage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::CatalogRoot)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn get_current(&self) -> Result<PageId, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0.read(Self::CURRENT_OFFSET, repr.as_bytes_mut())?;
+		repr.try_into()
+	}
+}
+
+impl<P: WritePage> CatalogRootPage<P> {
+	pub fn init(&mut self, current: PageId) -> Result<(), DatabaseError> {
+		set_page_kind(&mut self.0, PageKind::CatalogRoot)?;
+		self.set_current(current)
+	}
+
+	pub fn set_current(&mut self, value: PageId) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0.write(Self::CURRENT_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+}
+
+/// One version of the catalog. The serialized entries are stored in a chain
+/// of overflow pages, along with their checksum, and each version links to
+/// the one it replaced.
+pub(super) struct CatalogVersionPage<P>(P);
+
+impl<P> CatalogVersionPage<P> {
+	const VERSION_OFFSET: usize = PAGE_HEADER_SIZE;
+	const PREVIOUS_OFFSET: usize = Self::VERSION_OFFSET + size_of::<u64>();
+	const CHECKSUM_OFFSET: usize = Self::PREVIOUS_OFFSET + size_of::<PageIdRepr>();
+	const ENTRIES_OFFSET: usize = Self::CHECKSUM_OFFSET + size_of::<u32>();
+
+	pub fn new_unchecked(page: P) -> Self {
+		Self(page)
+	}
+}
+
+impl<P: ReadPage> CatalogVersionPage<P> {
+	pub fn new(page: P) -> Result<Self, DatabaseError> {
+		assert_page_kind(&page, PageKind::CatalogVersion)?;
+		Ok(Self::new_unchecked(page))
+	}
+
+	pub fn get_version(&self) -> Result<u64, DatabaseError> {
+		let mut repr = [0; 8];
+		self.0.read(Self::VERSION_OFFSET, &mut repr)?;
+		Ok(u64::from_ne_bytes(repr))
+	}
+
+	pub fn get_previous(&self) -> Result<Option<PageId>, DatabaseError> {
+		let mut repr = PageIdRepr::new_zeroed();
+		self.0.read(Self::PREVIOUS_OFFSET, repr.as_bytes_mut())?;
+		Ok(repr.into())
+	}
+
+	pub fn get_checksum(&self) -> Result<u32, DatabaseError> {
+		let mut repr = [0; 4];
+		self.0.read(Self::CHECKSUM_OFFSET, &mut repr)?;
+		Ok(u32::from_ne_bytes(repr))
+	}
+
+	pub fn get_entries(&self) -> Result<OverflowRef, DatabaseError> {
+		let mut repr = OverflowRefRepr::new_zeroed();
+		self.0.read(Self::ENTRIES_OFFSET, repr.as_bytes_mut())?;
+		OverflowRef::from_bytes(repr.as_bytes())
+	}
+}
+
+impl<P: WritePage> CatalogVersionPage<P> {
+	pub fn init(
+		&mut self,
+		version: u64,
+		previous: Option<PageId>,
+		checksum: u32,
+		entries: OverflowRef,
+	) -> Result<(), DatabaseError> {
+		set_page_kind(&mut self.0, PageKind::CatalogVersion)?;
+		self.0.write(Self::VERSION_OFFSET, &version.to_ne_bytes())?;
+		self.set_previous(previous)?;
+		self.0
+			.write(Self::CHECKSUM_OFFSET, &checksum.to_ne_bytes())?;
+		self.0.write(Self::ENTRIES_OFFSET, &entries.to_bytes())?;
+		Ok(())
+	}
+
+	pub fn set_previous(&mut self, value: Option<PageId>) -> Result<(), DatabaseError> {
+		let repr = PageIdRepr::from(value);
+		self.0.write(Self::PREVIOUS_OFFSET, repr.as_bytes())?;
+		Ok(())
+	}
+}
+
 pub(super) struct BlockPage<P>(P);
 
 impl<P> BlockPage<P> {
@@ -285,7 +1630,7 @@ impl<P> BlockPage<P> {
 		let preemptive_num_nodes =
 			preemptive_num_leaves.next_power_of_two() + preemptive_num_leaves - 1;
 
-		(preemptive_num_nodes + 7) / 8
+		preemptive_num_nodes.div_ceil(8)
 	}
 
 	pub fn new_unchecked(page: P) -> Self {
@@ -300,11 +1645,11 @@ impl<P> BlockPage<P> {
 }
 
 impl<P: ReadPage> BlockPage<P> {
-	fn get_alloc_tree_value(degree: usize, pos: usize) {
+	fn get_alloc_tree_value(_degree: usize, _pos: usize) {
 		todo!()
 	}
 
-	fn find_free_block(size: usize) {
+	fn find_free_block(_size: usize) {
 		todo!()
 	}
 }
@@ -0,0 +1,198 @@
+use std::{collections::HashMap, fmt};
+
+use crate::page_store::{CheckProblem, CheckReport, PageId, TransactionApi};
+
+use super::{
+	b_tree::BTree,
+	catalog::{Catalog, TreeKind},
+	page_alloc::PageAllocator,
+	var_b_tree::VarBTree,
+	DatabaseError,
+};
+
+/// The part of the database that a page belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum PageOwner {
+	Allocator,
+	Catalog,
+	/// A node of the named tree.
+	TreeNode(String),
+	/// A page with records that the named tree points to.
+	TreeRecords(String),
+}
+
+impl fmt::Display for PageOwner {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Allocator => write!(f, "the page allocator"),
+			Self::Catalog => write!(f, "the catalog"),
+			Self::TreeNode(name) => write!(f, "a node of tree '{name}'"),
+			Self::TreeRecords(name) => write!(f, "the records of tree '{name}'"),
+		}
+	}
+}
+
+/// Which part of the database each page belongs to, for debugging. Reports
+/// about broken pages can then name the tree a page belonged to, instead of
+/// only its ID.
+///
+/// Pages aren't tagged when they are allocated; instead, the owners are found
+/// by walking the allocator's state, the catalog and every tree in it. This
+/// only costs anything when it is needed, but pages that can no longer be
+/// read can't lead to others, so the pages below a broken tree node have no
+/// owner.
+pub(super) struct Provenance {
+	owners: HashMap<PageId, Vec<PageOwner>>,
+}
+
+impl Provenance {
+	pub fn collect(t: &mut impl TransactionApi, catalog: &Catalog) -> Result<Self, DatabaseError> {
+		let mut provenance = Self {
+			owners: HashMap::new(),
+		};
+		for page_id in PageAllocator::own_pages(t)? {
+			provenance.add(page_id, PageOwner::Allocator);
+		}
+		for page_id in catalog.pages(t)? {
+			provenance.add(page_id, PageOwner::Catalog);
+		}
+		for (name, entry) in catalog.entries(t)? {
+			let (pages, pointers) = match entry.kind {
+				TreeKind::BTree => {
+					let tree = BTree::new(entry.root);
+					let pointers = tree
+						.range(t, ..)
+						.map(|entry| Ok(entry?.1))
+						.collect::<Result<Vec<_>, DatabaseError>>()?;
+					(tree.pages(t)?, pointers)
+				}
+				TreeKind::VarBTree => {
+					let tree = VarBTree::new(entry.root);
+					let pointers = tree.entries(t)?.into_iter().map(|(_, pointer)| pointer);
+					(tree.pages(t)?, pointers.collect())
+				}
+			};
+			for page_id in pages {
+				provenance.add(page_id, PageOwner::TreeNode(name.clone()));
+			}
+			for pointer in pointers {
+				provenance.add(pointer.page_id(), PageOwner::TreeRecords(name.clone()));
+			}
+		}
+		Ok(provenance)
+	}
+
+	fn add(&mut self, page_id: PageId, owner: PageOwner) {
+		let owners = self.owners.entry(page_id).or_default();
+		if !owners.contains(&owner) {
+			owners.push(owner);
+		}
+	}
+
+	/// The parts of the database that the page belongs to. Records pages can
+	/// belong to several trees that point to the same records.
+	pub fn owners(&self, page_id: PageId) -> &[PageOwner] {
+		self.owners.get(&page_id).map_or(&[], Vec::as_slice)
+	}
+
+	/// Describes the page for error messages, like
+	/// `page 00000000:0007 (a node of tree 'users')`.
+	pub fn describe(&self, page_id: PageId) -> String {
+		let owners = self.owners(page_id);
+		if owners.is_empty() {
+			return format!("page {page_id} (no known owner)");
+		}
+		let owners: Vec<String> = owners.iter().map(ToString::to_string).collect();
+		format!("page {page_id} ({})", owners.join(", "))
+	}
+
+	/// Pairs each problem of the report with the owners of the page it
+	/// concerns. Problems with whole segments have no owners.
+	pub fn annotate<'a>(
+		&'a self,
+		report: &'a CheckReport,
+	) -> impl Iterator<Item = (&'a CheckProblem, &'a [PageOwner])> {
+		report.problems.iter().map(|problem| {
+			let owners = match problem {
+				CheckProblem::UnreadableSegment { .. } => &[],
+				CheckProblem::ChecksumMismatch(page_id)
+				| CheckProblem::UnreadablePage { page_id, .. } => self.owners(*page_id),
+			};
+			(problem, owners)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::{var_b_tree::KeyOrder, DbPointer},
+		page_store::{
+			test_helpers::{page_id, temp_storage},
+			PageStorageApi,
+		},
+	};
+
+	use super::*;
+
+	#[test]
+	fn find_owners_of_pages() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let catalog = Catalog::default();
+		catalog.init(&mut t).unwrap();
+		let users = catalog
+			.create_tree(&mut t, "users", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		let names = catalog
+			.create_tree(&mut t, "names", TreeKind::VarBTree, KeyOrder::Bytes)
+			.unwrap();
+		let records_page = page_id!(7, 1);
+		BTree::new(users)
+			.insert(&mut t, 1, DbPointer::new(records_page, 0))
+			.unwrap();
+		VarBTree::new(names)
+			.insert(&mut t, b"a", DbPointer::new(records_page, 0))
+			.unwrap();
+
+		// when
+		let provenance = Provenance::collect(&mut t, &catalog).unwrap();
+		let report = CheckReport {
+			num_segments: 1,
+			num_pages: 1,
+			problems: vec![CheckProblem::ChecksumMismatch(users)],
+		};
+		let annotated: Vec<_> = provenance.annotate(&report).collect();
+
+		// then
+		assert_eq!(
+			provenance.owners(PageAllocator::CATALOG_PAGE_ID),
+			[PageOwner::Catalog]
+		);
+		assert_eq!(
+			provenance.owners(names),
+			[PageOwner::TreeNode("names".to_string())]
+		);
+		assert_eq!(
+			provenance.owners(records_page),
+			[
+				PageOwner::TreeRecords("names".to_string()),
+				PageOwner::TreeRecords("users".to_string())
+			]
+		);
+		assert_eq!(provenance.owners(page_id!(9, 9)), []);
+		assert_eq!(
+			provenance.describe(users),
+			format!("page {users} (a node of tree 'users')")
+		);
+		assert_eq!(
+			annotated,
+			[(
+				&report.problems[0],
+				&[PageOwner::TreeNode("users".to_string())][..]
+			)]
+		);
+	}
+}
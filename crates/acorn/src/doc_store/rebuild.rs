@@ -0,0 +1,339 @@
+use std::{mem, sync::Arc};
+
+use log::error;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+
+use crate::{
+	page_store::{PageId, PageStorageApi, TransactionApi},
+	tasks::BlockingPool,
+	trace::event,
+};
+
+use super::{
+	b_tree::BTree,
+	catalog::{Catalog, CatalogEntry, TreeKind},
+	page_alloc::PageAllocator,
+	var_b_tree::VarBTree,
+	DatabaseError, DbPointer,
+};
+
+/// A change to a tree that is being rebuilt, which has to be applied to the
+/// rebuilt tree as well.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum TreeChange {
+	Insert(u64, DbPointer),
+	Delete(u64),
+	/// An insert into a tree with variable-length keys.
+	InsertVar(Vec<u8>, DbPointer),
+}
+
+const MISMATCHED_CHANGE: &str = "Captured a change that doesn't match the kind of the tree!";
+
+/// Rebuilds a tree of the catalog while it is in use.
+///
+/// A new tree is built from the entries of the old one, while the changes
+/// that are made to the old tree in the meantime are captured. Once it is
+/// built, the captured changes are applied to it, and the catalog entry is
+/// switched over to it in the same transaction, so that readers see either
+/// the old or the new tree, but never a mix of both.
+///
+/// Every transaction that modifies the tree while it is rebuilt has to do so
+/// while holding a [`RebuildWrites`] from [`TreeRebuild::writes`], and
+/// capture its changes once it committed.
+pub(super) struct TreeRebuild {
+	name: String,
+	entry: CatalogEntry,
+	new_root: PageId,
+	/// Whether the catalog was switched over to the new tree. Writers hold
+	/// this shared, so that switching over waits for their changes.
+	swapped: RwLock<bool>,
+	changes: Mutex<Vec<TreeChange>>,
+}
+
+/// Permission to modify a tree that is being rebuilt.
+pub(super) struct RebuildWrites<'a> {
+	rebuild: &'a TreeRebuild,
+	_swapped: RwLockReadGuard<'a, bool>,
+}
+
+impl RebuildWrites<'_> {
+	/// Records a change that was committed to the old tree.
+	pub fn capture(&self, change: TreeChange) {
+		self.rebuild.changes.lock().push(change);
+	}
+}
+
+impl TreeRebuild {
+	/// Starts rebuilding the tree `name`, by allocating the root of the new
+	/// tree. Changes to the tree have to be captured from now on.
+	pub fn begin<S: PageStorageApi>(
+		catalog: &Catalog,
+		storage: &S,
+		name: &str,
+	) -> Result<Self, DatabaseError> {
+		let mut t = storage.transaction()?;
+		let Some(entry) = catalog.get(&mut t, name)? else {
+			return Err(DatabaseError::TreeNotFound(name.to_string()));
+		};
+		let new_root = PageAllocator::alloc(&mut t)?;
+		match entry.kind {
+			TreeKind::BTree => BTree::new(new_root).init(&mut t)?,
+			TreeKind::VarBTree => VarBTree::new(new_root).init(&mut t)?,
+		}
+		t.commit()?;
+		Ok(Self {
+			name: name.to_string(),
+			entry,
+			new_root,
+			swapped: RwLock::new(false),
+			changes: Mutex::new(Vec::new()),
+		})
+	}
+
+	/// Allows modifying the old tree, or returns `None` if the catalog already
+	/// refers to the new tree, which has to be modified instead.
+	pub fn writes(&self) -> Option<RebuildWrites<'_>> {
+		let swapped = self.swapped.read();
+		if *swapped {
+			return None;
+		}
+		Some(RebuildWrites {
+			rebuild: self,
+			_swapped: swapped,
+		})
+	}
+
+	/// Copies the current entries of the old tree into the new one.
+	pub fn build<S: PageStorageApi>(
+		&self,
+		catalog: &Catalog,
+		storage: &S,
+	) -> Result<(), DatabaseError> {
+		let mut t = storage.read_only_transaction()?;
+		match self.entry.kind {
+			TreeKind::BTree => {
+				let entries = BTree::new(self.entry.root)
+					.range(&mut t, ..)
+					.collect::<Result<Vec<(u64, DbPointer)>, DatabaseError>>()?;
+				t.commit()?;
+
+				let mut t = storage.transaction()?;
+				BTree::new(self.new_root).bulk_load(&mut t, entries)?;
+				t.commit()?;
+			}
+			TreeKind::VarBTree => {
+				let entries = catalog.var_b_tree(&self.entry)?.entries(&mut t)?;
+				t.commit()?;
+
+				let mut t = storage.transaction()?;
+				self.new_tree(catalog)?.build(&mut t, entries)?;
+				t.commit()?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Applies the captured changes to the new tree, and switches the catalog
+	/// over to it in the same transaction. Waits for transactions that are
+	/// modifying the old tree to finish first.
+	///
+	/// Returns the pages of the old tree, which are no longer used, but not
+	/// freed yet.
+	pub fn swap<S: PageStorageApi>(
+		&self,
+		catalog: &Catalog,
+		storage: &S,
+	) -> Result<RetiredTree, DatabaseError> {
+		let mut swapped = self.swapped.write();
+		let changes = self.changes.lock().clone();
+
+		let mut t = storage.transaction()?;
+		match self.entry.kind {
+			TreeKind::BTree => {
+				let new_tree = BTree::new(self.new_root);
+				for change in changes {
+					match change {
+						TreeChange::Insert(key, value) => {
+							new_tree.insert(&mut t, key, value)?;
+						}
+						TreeChange::Delete(key) => {
+							new_tree.delete(&mut t, key)?;
+						}
+						TreeChange::InsertVar(..) => panic!("{MISMATCHED_CHANGE}"),
+					}
+				}
+			}
+			TreeKind::VarBTree => {
+				let entries = changes
+					.into_iter()
+					.map(|change| {
+						let TreeChange::InsertVar(key, value) = change else {
+							panic!("{MISMATCHED_CHANGE}");
+						};
+						(key, value)
+					})
+					.collect();
+				self.new_tree(catalog)?.build(&mut t, entries)?;
+			}
+		}
+		catalog.swap_root(&mut t, &self.name, self.entry.root, self.new_root)?;
+		t.commit()?;
+		*swapped = true;
+		mem::drop(swapped);
+		self.changes.lock().clear();
+
+		let mut t = storage.read_only_transaction()?;
+		let pages = match self.entry.kind {
+			TreeKind::BTree => BTree::new(self.entry.root).pages(&mut t)?,
+			TreeKind::VarBTree => VarBTree::new(self.entry.root).pages(&mut t)?,
+		};
+		t.commit()?;
+		event!(
+			INFO,
+			tree = %self.name,
+			num_pages = pages.len(),
+			"Swapped in a rebuilt tree"
+		);
+		Ok(RetiredTree { pages })
+	}
+
+	fn new_tree(&self, catalog: &Catalog) -> Result<VarBTree, DatabaseError> {
+		catalog.var_b_tree(&CatalogEntry {
+			root: self.new_root,
+			..self.entry
+		})
+	}
+}
+
+/// The pages of a tree that was replaced by a rebuilt one.
+///
+/// Past versions of the catalog still refer to the old tree, so once its
+/// pages are freed, those versions can't be used to read it anymore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct RetiredTree {
+	pages: Vec<PageId>,
+}
+
+impl RetiredTree {
+	pub fn pages(&self) -> &[PageId] {
+		&self.pages
+	}
+
+	/// Frees the pages in transactions of at most `batch_size` pages, so that
+	/// this can run while the database is in use.
+	pub fn free<S: PageStorageApi>(
+		self,
+		storage: &S,
+		batch_size: usize,
+	) -> Result<(), DatabaseError> {
+		for batch in self.pages.chunks(usize::max(batch_size, 1)) {
+			let mut t = storage.transaction()?;
+			for page_id in batch.iter().copied() {
+				PageAllocator::free(&mut t, page_id)?;
+			}
+			t.commit()?;
+		}
+		Ok(())
+	}
+
+	/// Frees the pages like [`RetiredTree::free`], but on a thread of the
+	/// database's [`BlockingPool`]. Errors are logged; pages that weren't freed
+	/// are left to [`PageAllocator::collect_garbage`].
+	pub fn free_in_background<S>(self, storage: Arc<S>, pool: &BlockingPool, batch_size: usize)
+	where
+		S: PageStorageApi + Send + Sync + 'static,
+	{
+		pool.get().spawn_ok(async move {
+			if let Err(err) = self.free(&*storage, batch_size) {
+				error!("Failed to free the pages of a rebuilt tree: {err}");
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		doc_store::var_b_tree::KeyOrder,
+		page_store::test_helpers::{page_id, temp_storage},
+	};
+
+	use super::*;
+
+	const NUM_KEYS: u64 = 500;
+
+	fn pointer(key: u64) -> DbPointer {
+		DbPointer::new(page_id!(1, 1), key as u16)
+	}
+
+	#[test]
+	fn rebuild_tree_while_it_is_modified() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let catalog = Catalog::new(page_id!(1, 1));
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		catalog.init(&mut t).unwrap();
+		let old_root = catalog
+			.create_tree(&mut t, "items", TreeKind::BTree, KeyOrder::Bytes)
+			.unwrap();
+		for key in 0..NUM_KEYS {
+			BTree::new(old_root)
+				.insert(&mut t, key, pointer(key))
+				.unwrap();
+		}
+		t.commit().unwrap();
+		let modify = |rebuild: &TreeRebuild, change: TreeChange| {
+			let writes = rebuild.writes().unwrap();
+			let mut t = storage.transaction().unwrap();
+			match change {
+				TreeChange::Insert(key, value) => {
+					BTree::new(old_root).insert(&mut t, key, value).unwrap();
+				}
+				TreeChange::Delete(key) => {
+					BTree::new(old_root).delete(&mut t, key).unwrap();
+				}
+				TreeChange::InsertVar(..) => unreachable!(),
+			}
+			t.commit().unwrap();
+			writes.capture(change);
+		};
+
+		// when
+		let rebuild = TreeRebuild::begin(&catalog, &storage, "items").unwrap();
+		modify(&rebuild, TreeChange::Delete(1));
+		rebuild.build(&catalog, &storage).unwrap();
+		modify(&rebuild, TreeChange::Insert(2, pointer(1000)));
+		modify(&rebuild, TreeChange::Insert(NUM_KEYS, pointer(NUM_KEYS)));
+		let retired = rebuild.swap(&catalog, &storage).unwrap();
+		let old_pages = retired.pages().to_vec();
+		retired.free(&storage, 4).unwrap();
+
+		// then
+		assert!(rebuild.writes().is_none());
+		let mut t = storage.transaction().unwrap();
+		let entry = catalog.get(&mut t, "items").unwrap().unwrap();
+		assert_ne!(entry.root, old_root);
+		assert!(old_pages.contains(&old_root));
+		assert!(!old_pages.contains(&entry.root));
+		let entries = BTree::new(entry.root)
+			.range(&mut t, ..)
+			.collect::<Result<Vec<(u64, DbPointer)>, DatabaseError>>()
+			.unwrap();
+		let expected: Vec<(u64, DbPointer)> = (0..=NUM_KEYS)
+			.filter(|key| *key != 1)
+			.map(|key| {
+				(
+					key,
+					if key == 2 {
+						pointer(1000)
+					} else {
+						pointer(key)
+					},
+				)
+			})
+			.collect();
+		assert_eq!(entries, expected);
+		t.commit().unwrap();
+	}
+}
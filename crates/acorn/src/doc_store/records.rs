@@ -0,0 +1,473 @@
+use std::borrow::Cow;
+
+use crate::page_store::{PageId, TransactionApi};
+
+use super::{
+	overflow,
+	page_alloc::PageAllocator,
+	pages::{OverflowRef, RecordKind, RecordPage},
+	DatabaseError, DbPointer,
+};
+
+/// Stores records of arbitrary length in a chain of slotted record pages,
+/// and refers to them by their page and slot index.
+///
+/// Records that are too large to share a page with others are stored in a
+/// chain of overflow pages instead, and the record page only keeps a
+/// reference to the chain.
+///
+/// With tombstones enabled, deleted records leave behind a tombstone with the
+/// ID of the deleting transaction, so that the deletion stays visible to
+/// readers that follow the records until it is purged.
+pub(super) struct RecordManager {
+	first_page: PageId,
+	tombstones: bool,
+}
+
+impl RecordManager {
+	pub fn new(first_page: PageId) -> Self {
+		Self {
+			first_page,
+			tombstones: false,
+		}
+	}
+
+	pub fn with_tombstones(mut self) -> Self {
+		self.tombstones = true;
+		self
+	}
+
+	pub fn init(&self, t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
+		RecordPage::new_unchecked(t.get_page_mut(self.first_page)?).init()
+	}
+
+	pub fn insert_record(
+		&self,
+		t: &mut impl TransactionApi,
+		data: &[u8],
+	) -> Result<DbPointer, DatabaseError> {
+		let (kind, stored) = Self::store_data(t, data)?;
+
+		let mut page_id = self.first_page;
+		loop {
+			let page = RecordPage::new(t.get_page(page_id)?)?;
+			let fits = page.can_insert(stored.len())?;
+			let next_page_id = page.get_next_page_id()?;
+			drop(page);
+
+			if fits {
+				let mut page = RecordPage::new(t.get_page_mut(page_id)?)?;
+				let index = page
+					.insert(kind, &stored)?
+					.expect("Record page unexpectedly ran out of space");
+				return Ok(DbPointer::new(page_id, index));
+			}
+			match next_page_id {
+				Some(next_page_id) => page_id = next_page_id,
+				None => break,
+			}
+		}
+
+		let new_page_id = PageAllocator::alloc(t)?;
+		let mut new_page = RecordPage::new_unchecked(t.get_page_mut(new_page_id)?);
+		new_page.init()?;
+		let index = new_page
+			.insert(kind, &stored)?
+			.expect("Record does not fit in an empty record page");
+		drop(new_page);
+		RecordPage::new(t.get_page_mut(page_id)?)?.set_next_page_id(Some(new_page_id))?;
+		Ok(DbPointer::new(new_page_id, index))
+	}
+
+	/// Returns the record, or `None` if there is none at the pointer.
+	pub fn get_record(
+		&self,
+		t: &mut impl TransactionApi,
+		pointer: DbPointer,
+	) -> Result<Option<Vec<u8>>, DatabaseError> {
+		let page = RecordPage::new(t.get_page(pointer.page_id())?)?;
+		let Some(slot) = page.get_slot(pointer.index())? else {
+			return Ok(None);
+		};
+		let data = page.read_record(slot)?;
+		drop(page);
+
+		match slot.kind {
+			RecordKind::Inline => Ok(Some(data)),
+			RecordKind::Overflow => {
+				let overflow = OverflowRef::from_bytes(&data)?;
+				Ok(Some(overflow::read(t, overflow)?))
+			}
+			RecordKind::Tombstone => Ok(None),
+		}
+	}
+
+	/// Returns the records at the pointers, in the same order, like
+	/// [`Self::get_record`]. The pointers are grouped by their page, so that
+	/// each record page is only accessed once.
+	pub fn get_records(
+		&self,
+		t: &mut impl TransactionApi,
+		pointers: &[DbPointer],
+	) -> Result<Vec<Option<Vec<u8>>>, DatabaseError> {
+		let mut order: Vec<usize> = (0..pointers.len()).collect();
+		order.sort_by_key(|i| pointers[*i].page_id());
+
+		let mut records = vec![None; pointers.len()];
+		let mut overflows = Vec::new();
+		for group in order.chunk_by(|a, b| pointers[*a].page_id() == pointers[*b].page_id()) {
+			let page = RecordPage::new(t.get_page(pointers[group[0]].page_id())?)?;
+			for &i in group {
+				let Some(slot) = page.get_slot(pointers[i].index())? else {
+					continue;
+				};
+				let data = page.read_record(slot)?;
+				match slot.kind {
+					RecordKind::Inline => records[i] = Some(data),
+					RecordKind::Overflow => overflows.push((i, OverflowRef::from_bytes(&data)?)),
+					RecordKind::Tombstone => (),
+				}
+			}
+		}
+
+		// Overflow chains are read once no record page is accessed anymore
+		for (i, overflow) in overflows {
+			records[i] = Some(overflow::read(t, overflow)?);
+		}
+		Ok(records)
+	}
+
+	/// Returns the ID of the transaction that deleted the record, if there is
+	/// a tombstone at the pointer.
+	pub fn get_tombstone(
+		&self,
+		t: &mut impl TransactionApi,
+		pointer: DbPointer,
+	) -> Result<Option<u64>, DatabaseError> {
+		let page = RecordPage::new(t.get_page(pointer.page_id())?)?;
+		let Some(slot) = page.get_slot(pointer.index())? else {
+			return Ok(None);
+		};
+		if slot.kind != RecordKind::Tombstone {
+			return Ok(None);
+		}
+		Ok(Some(Self::tombstone_id(&page.read_record(slot)?)?))
+	}
+
+	/// Replaces a record, keeping its pointer.
+	pub fn update_record(
+		&self,
+		t: &mut impl TransactionApi,
+		pointer: DbPointer,
+		data: &[u8],
+	) -> Result<(), DatabaseError> {
+		let old_overflow = Self::overflow_ref(t, pointer)?;
+		let (kind, stored) = Self::store_data(t, data)?;
+
+		let mut page = RecordPage::new(t.get_page_mut(pointer.page_id())?)?;
+		let replaced = page.replace(pointer.index(), kind, &stored)?;
+		drop(page);
+
+		if !replaced {
+			// Every record has space for an overflow reference, so moving the
+			// data to overflow pages always works.
+			let overflow = overflow::write(t, data)?;
+			let mut page = RecordPage::new(t.get_page_mut(pointer.page_id())?)?;
+			let replaced =
+				page.replace(pointer.index(), RecordKind::Overflow, &overflow.to_bytes())?;
+			debug_assert!(replaced);
+		}
+
+		if let Some(old_overflow) = old_overflow {
+			overflow::free(t, old_overflow)?;
+		}
+		Ok(())
+	}
+
+	pub fn delete_record(
+		&self,
+		t: &mut impl TransactionApi,
+		pointer: DbPointer,
+	) -> Result<(), DatabaseError> {
+		let old_overflow = Self::overflow_ref(t, pointer)?;
+		let transaction_id = t.id();
+		let mut page = RecordPage::new(t.get_page_mut(pointer.page_id())?)?;
+		if self.tombstones {
+			let replaced = page.replace(
+				pointer.index(),
+				RecordKind::Tombstone,
+				&transaction_id.to_ne_bytes(),
+			)?;
+			debug_assert!(replaced);
+		} else {
+			page.remove(pointer.index())?;
+		}
+		drop(page);
+		if let Some(old_overflow) = old_overflow {
+			overflow::free(t, old_overflow)?;
+		}
+		Ok(())
+	}
+
+	/// Removes the tombstones of records that were deleted by transactions
+	/// before `horizon`, so that their slots can be reused, and returns how
+	/// many were removed.
+	pub fn purge_tombstones(
+		&self,
+		t: &mut impl TransactionApi,
+		horizon: u64,
+	) -> Result<usize, DatabaseError> {
+		let mut num_purged = 0;
+		let mut page_id = Some(self.first_page);
+		while let Some(current) = page_id {
+			let page = RecordPage::new(t.get_page(current)?)?;
+			let mut purged = Vec::new();
+			for index in 0..page.num_slots()? {
+				let Some(slot) = page.get_slot(index)? else {
+					continue;
+				};
+				if slot.kind == RecordKind::Tombstone
+					&& Self::tombstone_id(&page.read_record(slot)?)? < horizon
+				{
+					purged.push(index);
+				}
+			}
+			page_id = page.get_next_page_id()?;
+			drop(page);
+
+			if !purged.is_empty() {
+				let mut page = RecordPage::new(t.get_page_mut(current)?)?;
+				for index in &purged {
+					page.remove(*index)?;
+				}
+				num_purged += purged.len();
+			}
+		}
+		Ok(num_purged)
+	}
+
+	/// Returns the overflow reference of a record, if it is stored in overflow
+	/// pages, or an error if there is no record at the pointer.
+	fn overflow_ref(
+		t: &mut impl TransactionApi,
+		pointer: DbPointer,
+	) -> Result<Option<OverflowRef>, DatabaseError> {
+		let page = RecordPage::new(t.get_page(pointer.page_id())?)?;
+		let Some(slot) = page.get_slot(pointer.index())? else {
+			return Err(DatabaseError::RecordNotFound(pointer));
+		};
+		match slot.kind {
+			RecordKind::Inline => Ok(None),
+			RecordKind::Overflow => Ok(Some(OverflowRef::from_bytes(&page.read_record(slot)?)?)),
+			RecordKind::Tombstone => Err(DatabaseError::RecordNotFound(pointer)),
+		}
+	}
+
+	fn tombstone_id(data: &[u8]) -> Result<u64, DatabaseError> {
+		let bytes = data
+			.try_into()
+			.map_err(|_| DatabaseError::PageFormat("Invalid tombstone".to_string()))?;
+		Ok(u64::from_ne_bytes(bytes))
+	}
+
+	/// Returns what the record page should store for a record, moving the data
+	/// to overflow pages if it is too large.
+	fn store_data<'a>(
+		t: &mut impl TransactionApi,
+		data: &'a [u8],
+	) -> Result<(RecordKind, Cow<'a, [u8]>), DatabaseError> {
+		if data.len() <= RecordPage::<()>::MAX_INLINE_SIZE {
+			return Ok((RecordKind::Inline, Cow::Borrowed(data)));
+		}
+		let overflow = overflow::write(t, data)?;
+		Ok((RecordKind::Overflow, Cow::Owned(overflow.to_bytes())))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+
+	use crate::{
+		files::segment::PAGE_BODY_SIZE,
+		page_store::{
+			test_helpers::{page_id, temp_storage},
+			PageStorageApi,
+		},
+	};
+
+	use super::*;
+
+	#[test]
+	fn small_records() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let records = RecordManager::new(page_id!(1, 1));
+		records.init(&mut t).unwrap();
+
+		// when
+		let first = records.insert_record(&mut t, b"first").unwrap();
+		let second = records.insert_record(&mut t, b"second").unwrap();
+		records
+			.update_record(&mut t, first, b"the first record, but longer")
+			.unwrap();
+		records.update_record(&mut t, second, b"2").unwrap();
+		records.delete_record(&mut t, first).unwrap();
+		let third = records.insert_record(&mut t, b"third").unwrap();
+
+		// then
+		assert_eq!(third, first);
+		assert_eq!(
+			records.get_record(&mut t, third).unwrap().as_deref(),
+			Some(b"third".as_slice())
+		);
+		assert_eq!(
+			records.get_record(&mut t, second).unwrap().as_deref(),
+			Some(b"2".as_slice())
+		);
+		records.delete_record(&mut t, second).unwrap();
+		assert_eq!(records.get_record(&mut t, second).unwrap(), None);
+		assert!(matches!(
+			records.delete_record(&mut t, second),
+			Err(DatabaseError::RecordNotFound(..))
+		));
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn large_records() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let records = RecordManager::new(page_id!(1, 1));
+		records.init(&mut t).unwrap();
+		let large: Vec<u8> = (0..3 * PAGE_BODY_SIZE).map(|i| i as u8).collect();
+		let medium = vec![1; 1000];
+
+		// when
+		let large_pointer = records.insert_record(&mut t, &large).unwrap();
+		let medium_pointers: Vec<DbPointer> = (0..100)
+			.map(|_| records.insert_record(&mut t, &medium).unwrap())
+			.collect();
+		records
+			.update_record(&mut t, medium_pointers[0], &large)
+			.unwrap();
+		records
+			.update_record(&mut t, large_pointer, b"small")
+			.unwrap();
+
+		// then
+		let pages: HashSet<PageId> = medium_pointers
+			.iter()
+			.map(|pointer| pointer.page_id())
+			.collect();
+		assert!(pages.len() >= 3);
+		assert_eq!(
+			records.get_record(&mut t, medium_pointers[0]).unwrap(),
+			Some(large)
+		);
+		for pointer in &medium_pointers[1..] {
+			assert_eq!(
+				records.get_record(&mut t, *pointer).unwrap().as_ref(),
+				Some(&medium)
+			);
+		}
+		assert_eq!(
+			records
+				.get_record(&mut t, large_pointer)
+				.unwrap()
+				.as_deref(),
+			Some(b"small".as_slice())
+		);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn get_multiple_records() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		let records = RecordManager::new(page_id!(1, 1));
+		records.init(&mut t).unwrap();
+		let large = vec![7; 2 * PAGE_BODY_SIZE];
+		let medium = vec![1; 1000];
+		let medium_pointers: Vec<DbPointer> = (0..100)
+			.map(|_| records.insert_record(&mut t, &medium).unwrap())
+			.collect();
+		let large_pointer = records.insert_record(&mut t, &large).unwrap();
+		let small_pointer = records.insert_record(&mut t, b"small").unwrap();
+		records.delete_record(&mut t, medium_pointers[1]).unwrap();
+
+		// when
+		let result = records
+			.get_records(
+				&mut t,
+				&[
+					small_pointer,
+					medium_pointers[99],
+					large_pointer,
+					medium_pointers[1],
+					medium_pointers[0],
+					small_pointer,
+				],
+			)
+			.unwrap();
+
+		// then
+		assert_eq!(
+			result,
+			[
+				Some(b"small".to_vec()),
+				Some(medium.clone()),
+				Some(large),
+				None,
+				Some(medium),
+				Some(b"small".to_vec()),
+			]
+		);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn tombstones() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let records = RecordManager::new(page_id!(1, 1)).with_tombstones();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		records.init(&mut t).unwrap();
+		let first = records.insert_record(&mut t, b"first").unwrap();
+		let second = records
+			.insert_record(&mut t, &vec![2; 3 * PAGE_BODY_SIZE])
+			.unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut t = storage.transaction().unwrap();
+		let first_deleted_by = t.id();
+		records.delete_record(&mut t, first).unwrap();
+		t.commit().unwrap();
+		let mut t = storage.transaction().unwrap();
+		let second_deleted_by = t.id();
+		records.delete_record(&mut t, second).unwrap();
+		let num_purged = records.purge_tombstones(&mut t, second_deleted_by).unwrap();
+		let third = records.insert_record(&mut t, b"third").unwrap();
+
+		// then
+		assert_eq!(num_purged, 1);
+		assert_eq!(third, first);
+		assert_eq!(records.get_record(&mut t, second).unwrap(), None);
+		assert_eq!(records.get_tombstone(&mut t, second).unwrap(), Some(t.id()));
+		assert!(matches!(
+			records.update_record(&mut t, second, b"second"),
+			Err(DatabaseError::RecordNotFound(..))
+		));
+		assert!(first_deleted_by < second_deleted_by);
+		assert_eq!(records.get_tombstone(&mut t, third).unwrap(), None);
+		t.commit().unwrap();
+	}
+}
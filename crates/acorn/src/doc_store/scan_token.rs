@@ -0,0 +1,168 @@
+use std::ops::Bound;
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::repr::Repr;
+
+use super::DatabaseError;
+
+/// Where a range scan over a tree left off, so that it can be continued in a
+/// different transaction, or even a different process, without keeping the
+/// original transaction open. It can be stored or sent elsewhere with
+/// [`ScanToken::to_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ScanToken {
+	/// The id of the scanned tree.
+	pub tree: u64,
+
+	/// The bounds of the keys that are left to scan.
+	pub start: Bound<u64>,
+	pub end: Bound<u64>,
+
+	/// The sequence number of the commit that the scan read as of, if it
+	/// read from a snapshot.
+	pub seq: Option<u64>,
+}
+
+impl ScanToken {
+	pub fn to_bytes(self) -> Vec<u8> {
+		ScanTokenRepr::from(self).as_bytes().to_vec()
+	}
+
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, DatabaseError> {
+		if bytes.len() != ScanTokenRepr::SIZE {
+			return Err(DatabaseError::InvalidScanToken);
+		}
+		ScanTokenRepr::from_bytes(bytes)
+	}
+}
+
+#[derive(AsBytes, FromZeroes, FromBytes)]
+#[repr(C, packed)]
+struct ScanTokenRepr {
+	tree: u64,
+	start_kind: u8,
+	start: u64,
+	end_kind: u8,
+	end: u64,
+	has_seq: u8,
+	seq: u64,
+}
+
+const BOUND_UNBOUNDED: u8 = 0;
+const BOUND_INCLUDED: u8 = 1;
+const BOUND_EXCLUDED: u8 = 2;
+
+impl ScanTokenRepr {
+	fn encode_bound(bound: Bound<u64>) -> (u8, u64) {
+		match bound {
+			Bound::Unbounded => (BOUND_UNBOUNDED, 0),
+			Bound::Included(key) => (BOUND_INCLUDED, key),
+			Bound::Excluded(key) => (BOUND_EXCLUDED, key),
+		}
+	}
+
+	fn decode_bound(kind: u8, key: u64) -> Result<Bound<u64>, DatabaseError> {
+		match kind {
+			BOUND_UNBOUNDED => Ok(Bound::Unbounded),
+			BOUND_INCLUDED => Ok(Bound::Included(key)),
+			BOUND_EXCLUDED => Ok(Bound::Excluded(key)),
+			_ => Err(DatabaseError::InvalidScanToken),
+		}
+	}
+}
+
+impl From<ScanToken> for ScanTokenRepr {
+	fn from(value: ScanToken) -> Self {
+		let (start_kind, start) = Self::encode_bound(value.start);
+		let (end_kind, end) = Self::encode_bound(value.end);
+		Self {
+			tree: value.tree,
+			start_kind,
+			start,
+			end_kind,
+			end,
+			has_seq: value.seq.is_some() as u8,
+			seq: value.seq.unwrap_or_default(),
+		}
+	}
+}
+
+impl TryFrom<ScanTokenRepr> for ScanToken {
+	type Error = DatabaseError;
+
+	fn try_from(value: ScanTokenRepr) -> Result<Self, Self::Error> {
+		let seq = match value.has_seq {
+			0 => None,
+			1 => Some(value.seq),
+			_ => return Err(DatabaseError::InvalidScanToken),
+		};
+		Ok(Self {
+			tree: value.tree,
+			start: ScanTokenRepr::decode_bound(value.start_kind, value.start)?,
+			end: ScanTokenRepr::decode_bound(value.end_kind, value.end)?,
+			seq,
+		})
+	}
+}
+
+impl Repr<ScanToken> for ScanTokenRepr {
+	type Error = DatabaseError;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn token_round_trip() {
+		// given
+		let tokens = [
+			ScanToken {
+				tree: 3,
+				start: Bound::Excluded(42),
+				end: Bound::Unbounded,
+				seq: Some(17),
+			},
+			ScanToken {
+				tree: u64::MAX,
+				start: Bound::Unbounded,
+				end: Bound::Included(0),
+				seq: None,
+			},
+		];
+
+		// when
+		let decoded: Vec<ScanToken> = tokens
+			.iter()
+			.map(|token| ScanToken::from_bytes(&token.to_bytes()).unwrap())
+			.collect();
+
+		// then
+		assert_eq!(decoded, tokens);
+	}
+
+	#[test]
+	fn reject_malformed_token() {
+		// given
+		let token = ScanToken {
+			tree: 3,
+			start: Bound::Included(1),
+			end: Bound::Excluded(2),
+			seq: Some(5),
+		}
+		.to_bytes();
+		let mut bad_bound = token.clone();
+		bad_bound[8] = 3;
+		let mut bad_seq_flag = token.clone();
+		bad_seq_flag[26] = 2;
+
+		// then
+		for bytes in [&token[..3], &bad_bound[..], &bad_seq_flag[..]] {
+			assert!(matches!(
+				ScanToken::from_bytes(bytes),
+				Err(DatabaseError::InvalidScanToken)
+			));
+		}
+	}
+}
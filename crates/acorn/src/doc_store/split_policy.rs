@@ -0,0 +1,124 @@
+/// Where the nodes of a B-tree are split once they overflow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct SplitPolicy {
+	/// The fraction of the entries of an overflowing leaf that stay in it,
+	/// while the rest move to the new right sibling.
+	pub leaf_fill: f32,
+
+	/// The fraction of the keys of an overflowing internal node that stay in
+	/// it, like [`Self::leaf_fill`].
+	pub internal_fill: f32,
+
+	/// Whether a node that overflows because of an entry after all of its
+	/// other entries is split right before that entry instead. If keys are
+	/// inserted in ascending order, this leaves every node but the last full,
+	/// instead of half empty.
+	pub append_optimized: bool,
+}
+
+impl Default for SplitPolicy {
+	fn default() -> Self {
+		Self {
+			leaf_fill: 0.5,
+			internal_fill: 0.5,
+			append_optimized: false,
+		}
+	}
+}
+
+impl SplitPolicy {
+	/// The number of the `len` entries of an overflowing leaf that stay in
+	/// it, if the entry that overflowed it is at `index`. The rest move to the
+	/// new right sibling, which always gets at least one entry.
+	pub fn leaf_split_point(&self, len: usize, index: usize) -> usize {
+		self.split_point(self.leaf_fill, len, index, len - 1)
+	}
+
+	/// The index of the key that moves up as the separator when an internal
+	/// node with `len` keys overflows, if the key that overflowed it is at
+	/// `index`. The keys before it stay in the node, and the ones after it
+	/// move to the new right sibling. Only a split at an appended key leaves
+	/// no keys for the right sibling, which then has the appended child alone.
+	pub fn internal_split_point(&self, len: usize, index: usize) -> usize {
+		self.split_point(self.internal_fill, len, index, len - 2)
+	}
+
+	/// Unless the node is split right before the appended entry, at least one
+	/// and at most `max` entries stay.
+	#[allow(
+		clippy::cast_precision_loss,
+		clippy::cast_possible_truncation,
+		clippy::cast_sign_loss
+	)]
+	fn split_point(&self, fill: f32, len: usize, index: usize, max: usize) -> usize {
+		if self.append_optimized && index == len - 1 {
+			return index;
+		}
+		((len as f32 * fill) as usize).clamp(1, max)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn split_in_half_by_default() {
+		// given
+		let policy = SplitPolicy::default();
+
+		// then
+		assert_eq!(policy.leaf_split_point(5, 4), 2);
+		assert_eq!(policy.internal_split_point(5, 4), 2);
+	}
+
+	#[test]
+	fn split_at_fill_factor() {
+		// given
+		let policy = SplitPolicy {
+			leaf_fill: 0.75,
+			internal_fill: 0.25,
+			append_optimized: false,
+		};
+
+		// then
+		assert_eq!(policy.leaf_split_point(5, 0), 3);
+		assert_eq!(policy.internal_split_point(5, 0), 1);
+	}
+
+	#[test]
+	fn keep_entries_on_both_sides() {
+		// given
+		let empty = SplitPolicy {
+			leaf_fill: 0.0,
+			internal_fill: 0.0,
+			append_optimized: false,
+		};
+		let full = SplitPolicy {
+			leaf_fill: 1.0,
+			internal_fill: 1.0,
+			append_optimized: false,
+		};
+
+		// then
+		assert_eq!(empty.leaf_split_point(5, 2), 1);
+		assert_eq!(empty.internal_split_point(5, 2), 1);
+		assert_eq!(full.leaf_split_point(5, 2), 4);
+		assert_eq!(full.internal_split_point(5, 2), 3);
+	}
+
+	#[test]
+	fn split_before_appended_entry() {
+		// given
+		let policy = SplitPolicy {
+			append_optimized: true,
+			..Default::default()
+		};
+
+		// then
+		assert_eq!(policy.leaf_split_point(5, 4), 4);
+		assert_eq!(policy.internal_split_point(5, 4), 4);
+		// Entries inserted elsewhere split the node as usual
+		assert_eq!(policy.leaf_split_point(5, 3), 2);
+	}
+}
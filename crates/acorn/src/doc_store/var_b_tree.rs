@@ -0,0 +1,784 @@
+use std::{cmp::Ordering, collections::HashMap, mem, vec};
+
+use crate::{
+	page_store::{PageId, TransactionApi},
+	utils::keys::prefix_upper_bound,
+};
+
+use super::{
+	amplification::tagged,
+	page_alloc::PageAllocator,
+	pages::{VarBTreeNode, VarBTreePage},
+	DatabaseError, DbPointer, Leaf,
+};
+
+/// Defines the order of the keys in a [`VarBTree`].
+pub(super) type Comparator = fn(&[u8], &[u8]) -> Ordering;
+
+/// The order of the keys in a [`VarBTree`], as it is recorded in the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(super) enum KeyOrder {
+	/// Lexicographic order of the bytes.
+	#[default]
+	Bytes,
+
+	/// Like `Bytes`, but ASCII letters compare equal to their lowercase
+	/// versions.
+	CaseInsensitive,
+
+	/// Keys that are decimal numbers, by their value, followed by all other
+	/// keys in byte order. Numbers that only differ in leading zeros are
+	/// ordered by their bytes.
+	Numeric,
+
+	/// A comparator registered in a [`Comparators`] under this id.
+	Custom(u8),
+}
+
+impl KeyOrder {
+	/// Returns how the order is stored: its kind, and the id of its
+	/// comparator if it is custom, or 0 otherwise.
+	pub fn to_repr(self) -> (u8, u8) {
+		match self {
+			Self::Bytes => (0, 0),
+			Self::CaseInsensitive => (1, 0),
+			Self::Numeric => (2, 0),
+			Self::Custom(id) => (3, id),
+		}
+	}
+
+	/// The inverse of [`Self::to_repr`], or `None` if the kind is unknown.
+	pub fn from_repr(order: u8, custom_order: u8) -> Option<Self> {
+		match order {
+			0 => Some(Self::Bytes),
+			1 => Some(Self::CaseInsensitive),
+			2 => Some(Self::Numeric),
+			3 => Some(Self::Custom(custom_order)),
+			_ => None,
+		}
+	}
+
+	pub fn comparator(self, comparators: &Comparators) -> Result<Comparator, DatabaseError> {
+		match self {
+			Self::Bytes => Ok(<[u8]>::cmp),
+			Self::CaseInsensitive => Ok(compare_case_insensitive),
+			Self::Numeric => Ok(compare_numeric),
+			Self::Custom(id) => comparators
+				.custom
+				.get(&id)
+				.copied()
+				.ok_or(DatabaseError::UnknownKeyOrder(id)),
+		}
+	}
+}
+
+/// The custom comparators that trees can be ordered by. Only their ids are
+/// stored in the database, so they have to be registered again every time it
+/// is opened.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Comparators {
+	custom: HashMap<u8, Comparator>,
+}
+
+impl Comparators {
+	pub fn with_custom(mut self, id: u8, comparator: Comparator) -> Self {
+		self.custom.insert(id, comparator);
+		self
+	}
+}
+
+fn compare_case_insensitive(a: &[u8], b: &[u8]) -> Ordering {
+	Iterator::cmp(
+		a.iter().map(u8::to_ascii_lowercase),
+		b.iter().map(u8::to_ascii_lowercase),
+	)
+}
+
+/// The digits of a decimal number without leading zeros, or `None` if the key
+/// isn't a number.
+fn significant_digits(key: &[u8]) -> Option<&[u8]> {
+	let is_number = !key.is_empty() && key.iter().all(u8::is_ascii_digit);
+	is_number.then(|| &key[key.iter().take_while(|digit| **digit == b'0').count()..])
+}
+
+fn compare_numeric(a: &[u8], b: &[u8]) -> Ordering {
+	// Without leading zeros, a longer number is always the larger one.
+	match (significant_digits(a), significant_digits(b)) {
+		(Some(a_digits), Some(b_digits)) => a_digits
+			.len()
+			.cmp(&b_digits.len())
+			.then_with(|| a_digits.cmp(b_digits))
+			.then_with(|| a.cmp(b)),
+		(Some(..), None) => Ordering::Less,
+		(None, Some(..)) => Ordering::Greater,
+		(None, None) => a.cmp(b),
+	}
+}
+
+type Split = (Vec<u8>, PageId);
+
+/// A B+ tree index like [`BTree`](super::b_tree::BTree), but with
+/// variable-length byte string keys, ordered by a configurable comparator.
+///
+/// Nodes are split based on their size in bytes rather than their number of
+/// entries. Keys may be at most a quarter of a node long, so that both halves
+/// of a split node are guaranteed to fit on a page.
+pub(super) struct VarBTree {
+	root: PageId,
+	comparator: Comparator,
+	node_capacity: usize,
+}
+
+impl VarBTree {
+	pub fn new(root: PageId) -> Self {
+		Self {
+			root,
+			comparator: <[u8]>::cmp,
+			node_capacity: VarBTreePage::<()>::CAPACITY,
+		}
+	}
+
+	pub fn with_comparator(mut self, comparator: Comparator) -> Self {
+		self.comparator = comparator;
+		self
+	}
+
+	pub fn max_key_len(&self) -> usize {
+		self.node_capacity / 4 - VarBTreePage::<()>::slot_size(0, true)
+	}
+
+	pub fn init(&self, t: &mut impl TransactionApi) -> Result<(), DatabaseError> {
+		Self::write_node(t, self.root, &VarBTreeNode::Leaf(Vec::new()))
+	}
+
+	pub fn search(
+		&self,
+		t: &mut impl TransactionApi,
+		key: &[u8],
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		tagged(t, self.root, |t| {
+			let mut page_id = self.root;
+			loop {
+				match Self::read_node(t, page_id)? {
+					VarBTreeNode::Leaf(entries) => {
+						let Ok(index) = self.entry_index(&entries, key) else {
+							return Ok(None);
+						};
+						return Ok(Some(entries[index].1));
+					}
+					VarBTreeNode::Internal { keys, children } => {
+						page_id = children[self.child_index(&keys, key)];
+					}
+				}
+			}
+		})
+	}
+
+	/// Returns the entries with keys that start with `prefix`, in key order.
+	///
+	/// The scan descends directly to the first leaf that may contain such a
+	/// key, and ends at the smallest key that is greater than all of them, see
+	/// [`prefix_upper_bound`]. Both are found with the comparator, so for
+	/// orders other than the byte-wise default, this returns the keys between
+	/// `prefix` and that bound in the tree's order instead.
+	pub fn scan_prefix<'t, T: TransactionApi>(
+		&self,
+		t: &'t mut T,
+		prefix: &[u8],
+	) -> PrefixScan<'t, T> {
+		PrefixScan {
+			t,
+			comparator: self.comparator,
+			root: Some(self.root),
+			prefix: prefix.to_vec(),
+			upper_bound: prefix_upper_bound(prefix),
+			stack: Vec::new(),
+			entries: Vec::new().into_iter(),
+		}
+	}
+
+	/// Returns all entries of the tree, in the order of its comparator.
+	pub fn entries(
+		&self,
+		t: &mut impl TransactionApi,
+	) -> Result<Vec<(Vec<u8>, DbPointer)>, DatabaseError> {
+		let mut entries = Vec::new();
+		let mut stack = vec![self.root];
+		while let Some(page_id) = stack.pop() {
+			match Self::read_node(t, page_id)? {
+				VarBTreeNode::Leaf(leaf_entries) => entries.extend(leaf_entries),
+				VarBTreeNode::Internal { children, .. } => stack.extend(children.into_iter().rev()),
+			}
+		}
+		Ok(entries)
+	}
+
+	/// Returns the leaves of the tree in the order of its comparator, each
+	/// with its page and entries.
+	pub fn leaves(&self, t: &mut impl TransactionApi) -> Result<Vec<Leaf<Vec<u8>>>, DatabaseError> {
+		let mut leaves = Vec::new();
+		let mut stack = vec![self.root];
+		while let Some(page_id) = stack.pop() {
+			match Self::read_node(t, page_id)? {
+				VarBTreeNode::Leaf(entries) => leaves.push((page_id, entries)),
+				VarBTreeNode::Internal { children, .. } => stack.extend(children.into_iter().rev()),
+			}
+		}
+		Ok(leaves)
+	}
+
+	/// Returns every page of the tree, starting with the root.
+	pub fn pages(&self, t: &mut impl TransactionApi) -> Result<Vec<PageId>, DatabaseError> {
+		let mut pages = vec![self.root];
+		let mut index = 0;
+		while let Some(page_id) = pages.get(index).copied() {
+			if let VarBTreeNode::Internal { children, .. } = Self::read_node(t, page_id)? {
+				pages.extend(children);
+			}
+			index += 1;
+		}
+		Ok(pages)
+	}
+
+	/// Inserts a value for `key`, and returns the value it replaced, if any.
+	pub fn insert(
+		&self,
+		t: &mut impl TransactionApi,
+		key: &[u8],
+		value: DbPointer,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		self.check_key_len(key)?;
+		tagged(t, self.root, |t| self.insert_unchecked(t, key, value))
+	}
+
+	/// Inserts many entries at once, in the order of the tree's comparator.
+	/// If several entries have equal keys, the last one wins. Nothing is
+	/// inserted if any of the keys is too long.
+	pub fn build(
+		&self,
+		t: &mut impl TransactionApi,
+		mut entries: Vec<(Vec<u8>, DbPointer)>,
+	) -> Result<(), DatabaseError> {
+		for (key, _) in &entries {
+			self.check_key_len(key)?;
+		}
+		// The sort is stable, so equal keys stay in their original order.
+		entries.sort_by(|(a, _), (b, _)| (self.comparator)(a, b));
+		for (key, value) in entries {
+			self.insert_unchecked(t, &key, value)?;
+		}
+		Ok(())
+	}
+
+	fn check_key_len(&self, key: &[u8]) -> Result<(), DatabaseError> {
+		if key.len() > self.max_key_len() {
+			return Err(DatabaseError::KeyTooLong {
+				len: key.len(),
+				max: self.max_key_len(),
+			});
+		}
+		Ok(())
+	}
+
+	fn insert_unchecked(
+		&self,
+		t: &mut impl TransactionApi,
+		key: &[u8],
+		value: DbPointer,
+	) -> Result<Option<DbPointer>, DatabaseError> {
+		let (replaced, split) = self.insert_into(t, self.root, key, value)?;
+		if let Some((separator, right)) = split {
+			// The root keeps its page, so its left half has to move.
+			let left = PageAllocator::alloc(t)?;
+			let left_node = Self::read_node(t, self.root)?;
+			Self::write_node(t, left, &left_node)?;
+			Self::write_node(
+				t,
+				self.root,
+				&VarBTreeNode::Internal {
+					keys: vec![separator],
+					children: vec![left, right],
+				},
+			)?;
+		}
+		Ok(replaced)
+	}
+
+	fn insert_into(
+		&self,
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		key: &[u8],
+		value: DbPointer,
+	) -> Result<(Option<DbPointer>, Option<Split>), DatabaseError> {
+		match Self::read_node(t, page_id)? {
+			VarBTreeNode::Leaf(mut entries) => {
+				match self.entry_index(&entries, key) {
+					Ok(index) => {
+						let replaced = entries[index].1;
+						entries[index].1 = value;
+						Self::write_node(t, page_id, &VarBTreeNode::Leaf(entries))?;
+						return Ok((Some(replaced), None));
+					}
+					Err(index) => entries.insert(index, (key.to_vec(), value)),
+				}
+				if VarBTreeNode::leaf_size(&entries) <= self.node_capacity {
+					Self::write_node(t, page_id, &VarBTreeNode::Leaf(entries))?;
+					return Ok((None, None));
+				}
+
+				let mid = Self::split_index(entries.iter().map(|(key, _)| key.len()), true);
+				let right_entries = entries.split_off(mid);
+				let separator = right_entries[0].0.clone();
+				let right = PageAllocator::alloc(t)?;
+				Self::write_node(t, right, &VarBTreeNode::Leaf(right_entries))?;
+				Self::write_node(t, page_id, &VarBTreeNode::Leaf(entries))?;
+				Ok((None, Some((separator, right))))
+			}
+			VarBTreeNode::Internal {
+				mut keys,
+				mut children,
+			} => {
+				let index = self.child_index(&keys, key);
+				let (replaced, split) = self.insert_into(t, children[index], key, value)?;
+				let Some((separator, new_child)) = split else {
+					return Ok((replaced, None));
+				};
+				keys.insert(index, separator);
+				children.insert(index + 1, new_child);
+				if VarBTreeNode::internal_size(&keys) <= self.node_capacity {
+					Self::write_node(t, page_id, &VarBTreeNode::Internal { keys, children })?;
+					return Ok((replaced, None));
+				}
+
+				let mid = Self::split_index(keys.iter().map(Vec::len), false);
+				let right_keys = keys.split_off(mid + 1);
+				let separator = keys.pop().unwrap();
+				let right_children = children.split_off(mid + 1);
+				let right = PageAllocator::alloc(t)?;
+				Self::write_node(
+					t,
+					right,
+					&VarBTreeNode::Internal {
+						keys: right_keys,
+						children: right_children,
+					},
+				)?;
+				Self::write_node(t, page_id, &VarBTreeNode::Internal { keys, children })?;
+				Ok((replaced, Some((separator, right))))
+			}
+		}
+	}
+
+	/// Finds the index at which to split a node with keys of the given lengths,
+	/// so that both halves take up about the same number of bytes. Internal
+	/// nodes promote the key at that index, so it is never the last one.
+	fn split_index(key_lens: impl ExactSizeIterator<Item = usize>, is_leaf: bool) -> usize {
+		let len = key_lens.len();
+		let sizes: Vec<usize> = key_lens
+			.map(|key_len| VarBTreePage::<()>::slot_size(key_len, is_leaf))
+			.collect();
+		let half = sizes.iter().sum::<usize>() / 2;
+		let mut total = 0;
+		let index = sizes
+			.iter()
+			.position(|size| {
+				total += size;
+				total >= half
+			})
+			.unwrap_or(0);
+		let max_index = if is_leaf { len - 1 } else { len - 2 };
+		index.clamp(1, max_index)
+	}
+
+	fn entry_index(&self, entries: &[(Vec<u8>, DbPointer)], key: &[u8]) -> Result<usize, usize> {
+		entries.binary_search_by(|(entry_key, _)| (self.comparator)(entry_key, key))
+	}
+
+	fn child_index(&self, keys: &[Vec<u8>], key: &[u8]) -> usize {
+		keys.partition_point(|separator| (self.comparator)(separator, key) != Ordering::Greater)
+	}
+
+	fn read_node(
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+	) -> Result<VarBTreeNode, DatabaseError> {
+		VarBTreePage::new(t.get_page(page_id)?)?.read_node()
+	}
+
+	fn write_node(
+		t: &mut impl TransactionApi,
+		page_id: PageId,
+		node: &VarBTreeNode,
+	) -> Result<(), DatabaseError> {
+		VarBTreePage::new_unchecked(t.get_page_mut(page_id)?).write_node(node)
+	}
+}
+
+/// An iterator over the entries of a [`VarBTree`] with keys that start with
+/// a prefix, returned by [`VarBTree::scan_prefix`].
+///
+/// Like [`Range`](super::b_tree::Range), it keeps the path from the root to
+/// the current leaf, since leaves don't link to their siblings.
+pub(super) struct PrefixScan<'t, T: TransactionApi> {
+	t: &'t mut T,
+	comparator: Comparator,
+	/// The root, until the first leaf has been reached.
+	root: Option<PageId>,
+	prefix: Vec<u8>,
+	/// The first key after the keys with the prefix, or `None` if there is
+	/// no such key.
+	upper_bound: Option<Vec<u8>>,
+	/// The children of each ancestor of the current leaf, along with the
+	/// index of the child that is being visited.
+	stack: Vec<(Vec<PageId>, usize)>,
+	entries: vec::IntoIter<(Vec<u8>, DbPointer)>,
+}
+
+impl<T: TransactionApi> PrefixScan<'_, T> {
+	/// Descends to the first leaf that may contain keys with the prefix.
+	fn seek_start(&mut self, root: PageId) -> Result<(), DatabaseError> {
+		let mut page_id = root;
+		loop {
+			match VarBTree::read_node(self.t, page_id)? {
+				VarBTreeNode::Leaf(mut entries) => {
+					let first = entries.partition_point(|(key, _)| {
+						(self.comparator)(key, &self.prefix) == Ordering::Less
+					});
+					entries.drain(..first);
+					self.entries = entries.into_iter();
+					return Ok(());
+				}
+				VarBTreeNode::Internal { keys, children } => {
+					let index = keys.partition_point(|separator| {
+						(self.comparator)(separator, &self.prefix) != Ordering::Greater
+					});
+					page_id = children[index];
+					self.stack.push((children, index));
+				}
+			}
+		}
+	}
+
+	/// Moves on to the leftmost leaf after the current one. Returns `false` if
+	/// there is none.
+	fn next_leaf(&mut self) -> Result<bool, DatabaseError> {
+		let mut page_id = loop {
+			let Some((children, index)) = self.stack.last_mut() else {
+				return Ok(false);
+			};
+			if *index + 1 < children.len() {
+				*index += 1;
+				break children[*index];
+			}
+			self.stack.pop();
+		};
+		loop {
+			match VarBTree::read_node(self.t, page_id)? {
+				VarBTreeNode::Leaf(entries) => {
+					self.entries = entries.into_iter();
+					return Ok(true);
+				}
+				VarBTreeNode::Internal { children, .. } => {
+					page_id = children[0];
+					self.stack.push((children, 0));
+				}
+			}
+		}
+	}
+
+	fn next_entry(&mut self) -> Result<Option<(Vec<u8>, DbPointer)>, DatabaseError> {
+		if let Some(root) = self.root.take() {
+			self.seek_start(root)?;
+		}
+		let entry = loop {
+			if let Some(entry) = self.entries.next() {
+				break entry;
+			}
+			if !self.next_leaf()? {
+				return Ok(None);
+			}
+		};
+		let past_end = self
+			.upper_bound
+			.as_ref()
+			.is_some_and(|upper_bound| (self.comparator)(&entry.0, upper_bound) != Ordering::Less);
+		if past_end {
+			self.finish();
+			return Ok(None);
+		}
+		Ok(Some(entry))
+	}
+
+	fn finish(&mut self) {
+		self.stack.clear();
+		mem::take(&mut self.entries);
+	}
+}
+
+impl<T: TransactionApi> Iterator for PrefixScan<'_, T> {
+	type Item = Result<(Vec<u8>, DbPointer), DatabaseError>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let result = self.next_entry();
+		if result.is_err() {
+			self.finish();
+		}
+		result.transpose()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::page_store::{
+		test_helpers::{page_id, temp_storage},
+		PageStorageApi, WritePage,
+	};
+
+	use super::*;
+
+	const NUM_KEYS: u64 = 200;
+
+	fn small_tree() -> VarBTree {
+		VarBTree {
+			node_capacity: 256,
+			..VarBTree::new(page_id!(1, 1))
+		}
+	}
+
+	fn pointer(index: u64) -> DbPointer {
+		DbPointer::new(page_id!(2, 1), index as u16)
+	}
+
+	/// Keys of different lengths, in an order that is neither ascending nor
+	/// descending.
+	fn shuffled_keys() -> impl Iterator<Item = (u64, Vec<u8>)> {
+		(0..NUM_KEYS).map(|i| {
+			let i = (i * 37) % NUM_KEYS;
+			(
+				i,
+				format!("{i}-{}", "x".repeat(i as usize % 40)).into_bytes(),
+			)
+		})
+	}
+
+	#[test]
+	fn insert_and_search() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+
+		// when
+		for (i, key) in shuffled_keys() {
+			assert_eq!(tree.insert(&mut t, &key, pointer(i)).unwrap(), None);
+		}
+		let too_long = tree.insert(&mut t, &[0; 256], pointer(0));
+
+		// then
+		for (i, key) in shuffled_keys() {
+			assert_eq!(tree.search(&mut t, &key).unwrap(), Some(pointer(i)));
+		}
+		assert_eq!(tree.search(&mut t, b"missing").unwrap(), None);
+		assert!(matches!(too_long, Err(DatabaseError::KeyTooLong { .. })));
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn share_key_prefixes() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		let prefix = "a/rather/long/prefix/shared/by/every/key/";
+		let key = |i: u64| format!("{prefix}{i:03}").into_bytes();
+
+		// when
+		for i in 0..NUM_KEYS {
+			tree.insert(&mut t, &key(i), pointer(i)).unwrap();
+		}
+
+		// then
+		for i in 0..NUM_KEYS {
+			assert_eq!(tree.search(&mut t, &key(i)).unwrap(), Some(pointer(i)));
+		}
+		// Without sharing the prefix, a leaf could hold at most 4 of these keys.
+		let slot_size = VarBTreePage::<()>::slot_size(key(0).len(), true);
+		assert_eq!((tree.node_capacity - 10) / slot_size, 4);
+		assert!(tree.pages(&mut t).unwrap().len() < NUM_KEYS as usize / 4);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn read_node_without_shared_prefix() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		// - a leaf node in the layout without a shared prefix
+		{
+			let mut page = t.get_page_mut(page_id!(1, 1)).unwrap();
+			page.write(0, &[4, 1]).unwrap();
+			page.write(2, &2_u16.to_ne_bytes()).unwrap();
+			page.write(10, &100_u16.to_ne_bytes()).unwrap();
+			page.write(12, &115_u16.to_ne_bytes()).unwrap();
+			for (offset, key, index) in [(100, b"acorn", 1_u16), (115, b"beech", 2)] {
+				page.write(offset, &5_u16.to_ne_bytes()).unwrap();
+				page.write(offset + 2, key).unwrap();
+				page.write(offset + 7, &2_u32.to_ne_bytes()).unwrap();
+				page.write(offset + 11, &1_u16.to_ne_bytes()).unwrap();
+				page.write(offset + 13, &index.to_ne_bytes()).unwrap();
+			}
+		}
+
+		// when
+		let acorn = tree.search(&mut t, b"acorn").unwrap();
+		let beech = tree.search(&mut t, b"beech").unwrap();
+
+		// then
+		assert_eq!(acorn, Some(pointer(1)));
+		assert_eq!(beech, Some(pointer(2)));
+	}
+
+	#[test]
+	fn custom_comparator() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree()
+			.with_comparator(|a, b| a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()));
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		tree.insert(&mut t, b"Acorn", pointer(1)).unwrap();
+
+		// when
+		let replaced = tree.insert(&mut t, b"ACORN", pointer(2)).unwrap();
+
+		// then
+		assert_eq!(replaced, Some(pointer(1)));
+		assert_eq!(tree.search(&mut t, b"acorn").unwrap(), Some(pointer(2)));
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn scan_key_prefixes() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let tree = small_tree();
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		for (i, key) in shuffled_keys() {
+			tree.insert(&mut t, &key, pointer(i)).unwrap();
+		}
+		let other_keys: [&[u8]; 5] = [
+			b"a\xff",
+			b"a\xff\xff",
+			b"a\xff\xff\x01",
+			b"b\x00",
+			b"\xff\xff",
+		];
+		for (i, key) in other_keys.into_iter().enumerate() {
+			tree.insert(&mut t, key, pointer(NUM_KEYS + i as u64))
+				.unwrap();
+		}
+		let mut scan = |prefix: &[u8]| -> Vec<Vec<u8>> {
+			tree.scan_prefix(&mut t, prefix)
+				.map(|entry| entry.unwrap().0)
+				.collect()
+		};
+
+		// when
+		let ones = scan(b"1");
+		let one_two = scan(b"12");
+		let carried = scan(b"a\xff");
+		let next_letter = scan(b"b");
+		let unbounded = scan(b"\xff");
+		let all = scan(b"");
+		let missing = scan(b"c");
+
+		// then
+		let mut expected_ones: Vec<Vec<u8>> = shuffled_keys()
+			.map(|(_, key)| key)
+			.filter(|key| key.starts_with(b"1"))
+			.collect();
+		expected_ones.sort();
+		assert_eq!(expected_ones.len(), 111);
+		assert_eq!(ones, expected_ones);
+		let expected_one_two: Vec<Vec<u8>> = expected_ones
+			.iter()
+			.filter(|key| key.starts_with(b"12"))
+			.cloned()
+			.collect();
+		assert_eq!(expected_one_two.len(), 11);
+		assert_eq!(one_two, expected_one_two);
+		assert_eq!(carried, other_keys[..3]);
+		assert_eq!(next_letter, [b"b\x00"]);
+		assert_eq!(unbounded, [b"\xff\xff"]);
+		assert_eq!(all.len(), NUM_KEYS as usize + other_keys.len());
+		assert!(all.is_sorted());
+		assert!(missing.is_empty());
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn build_in_key_order() {
+		// given
+		let (_tempdir, storage) = temp_storage(&Default::default());
+		let comparator = KeyOrder::Numeric
+			.comparator(&Comparators::default())
+			.unwrap();
+		let tree = small_tree().with_comparator(comparator);
+		let mut t = storage.transaction().unwrap();
+		PageAllocator::init(&mut t).unwrap();
+		tree.init(&mut t).unwrap();
+		let mut entries: Vec<(Vec<u8>, DbPointer)> = (0..NUM_KEYS)
+			.map(|i| (i * 37) % NUM_KEYS)
+			.map(|i| (i.to_string().into_bytes(), pointer(i)))
+			.collect();
+		entries.push((b"7".to_vec(), pointer(1000)));
+
+		// when
+		tree.build(&mut t, entries).unwrap();
+		let too_long = tree.build(&mut t, vec![(vec![0; 256], pointer(0))]);
+
+		// then
+		for i in 0..NUM_KEYS {
+			let expected = if i == 7 { pointer(1000) } else { pointer(i) };
+			assert_eq!(
+				tree.search(&mut t, i.to_string().as_bytes()).unwrap(),
+				Some(expected)
+			);
+		}
+		assert!(matches!(too_long, Err(DatabaseError::KeyTooLong { .. })));
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn built_in_key_orders() {
+		// given
+		let comparators = Comparators::default();
+		let mut keys: Vec<&[u8]> = vec![b"b", b"10", b"A", b"9", b"010", b"a", b"x1"];
+
+		// when
+		let numeric = KeyOrder::Numeric.comparator(&comparators).unwrap();
+		let case_insensitive = KeyOrder::CaseInsensitive.comparator(&comparators).unwrap();
+		keys.sort_by(|a, b| numeric(a, b));
+		let numeric_keys = keys.clone();
+		keys.sort_by(|a, b| case_insensitive(a, b));
+
+		// then
+		assert_eq!(
+			numeric_keys,
+			vec![&b"9"[..], b"010", b"10", b"A", b"a", b"b", b"x1"]
+		);
+		assert_eq!(
+			keys,
+			vec![&b"010"[..], b"10", b"9", b"A", b"a", b"b", b"x1"]
+		);
+	}
+}
@@ -0,0 +1,38 @@
+//! Names of the failpoints in the storage engine's I/O paths.
+//!
+//! With the `failpoints` feature enabled, each of these can be configured
+//! through the [`fail`](https://docs.rs/fail) crate, for example with
+//! `fail::cfg(failpoints::WAL_FSYNC, "return")`, to test how an application
+//! handles engine failures. A `return` action fails the operation with an I/O
+//! error, using the action's argument as the message if there is one; all
+//! other actions behave as documented by `fail`. Without the feature, the
+//! failpoints compile to nothing.
+
+/// Appending an item to the write-ahead log.
+pub const WAL_APPEND: &str = "acorn::wal::append";
+
+/// Syncing the write-ahead log to disk, which every commit waits for.
+pub const WAL_FSYNC: &str = "acorn::wal::fsync";
+
+/// Reading a page from a segment file.
+pub const PAGE_READ: &str = "acorn::page::read";
+
+/// Writing a page to a segment file.
+pub const PAGE_WRITE: &str = "acorn::page::write";
+
+/// Evicting a page from the page cache, before it is written back.
+pub const PAGE_EVICT: &str = "acorn::page::evict";
+
+/// Returns an I/O error from the enclosing function if the named failpoint is
+/// configured to `return`.
+macro_rules! failpoint {
+	($name:ident) => {
+		#[cfg(feature = "failpoints")]
+		fail::fail_point!($crate::failpoints::$name, |message: Option<String>| {
+			let message = message
+				.unwrap_or_else(|| format!("Failpoint {} triggered", $crate::failpoints::$name));
+			Err($crate::files::FileError::from(std::io::Error::other(message)).into())
+		});
+	};
+}
+pub(crate) use failpoint;
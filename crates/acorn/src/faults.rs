@@ -0,0 +1,556 @@
+//! Fault injection for testing that databases survive crashes.
+//!
+//! [`FaultyVfs`] wraps another [`Vfs`] and injects [`Fault`]s into the writes
+//! to it. [`CrashTest`] uses it to crash a database at each write of a
+//! workload in turn, and checks every time that recovery keeps all
+//! transactions that committed before the crash.
+
+use std::{
+	collections::HashMap,
+	ffi::OsString,
+	io,
+	panic::{self, AssertUnwindSafe},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::{
+	files::{memory::MemoryFile, DatabaseFolder, FileError},
+	Database, DatabaseBuilder, Error, PageId, Vfs, VfsFile,
+};
+
+/// The kind of file that a fault is injected into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+	Segment,
+	Wal,
+}
+
+impl FileKind {
+	fn of(path: &Path) -> Self {
+		if path.starts_with(DatabaseFolder::WAL_DIR_NAME) {
+			Self::Wal
+		} else {
+			Self::Segment
+		}
+	}
+}
+
+/// A fault that is injected into a write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+	/// The write fails with an I/O error, but the storage keeps working.
+	IoError,
+
+	/// The process crashes before the write.
+	Crash,
+
+	/// Only the first `len` bytes of the write reach the file before the
+	/// process crashes. In a WAL file, this is a partial append.
+	TornWrite { len: usize },
+}
+
+/// A [`Vfs`] that stores files in memory, and loses them once it is dropped.
+#[derive(Default)]
+pub struct MemoryVfs {
+	files: Mutex<HashMap<PathBuf, MemoryFile>>,
+}
+
+impl MemoryVfs {
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl Vfs for MemoryVfs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+		let mut files = self.files.lock();
+		let file = files.entry(path.to_path_buf()).or_default();
+		Ok(Box::new(file.share()))
+	}
+
+	fn exists(&self, path: &Path) -> io::Result<bool> {
+		Ok(self.files.lock().contains_key(path))
+	}
+
+	fn remove(&self, path: &Path) -> io::Result<()> {
+		match self.files.lock().remove(path) {
+			Some(..) => Ok(()),
+			None => Err(io::ErrorKind::NotFound.into()),
+		}
+	}
+
+	fn list(&self, path: &Path) -> io::Result<Vec<OsString>> {
+		Ok(self
+			.files
+			.lock()
+			.keys()
+			.filter(|file_path| file_path.parent() == Some(path))
+			.filter_map(|file_path| file_path.file_name())
+			.map(OsString::from)
+			.collect())
+	}
+}
+
+/// A [`Vfs`] that passes everything through to another one, but can inject
+/// [`Fault`]s into writes.
+///
+/// Once a crash was injected, all operations fail until
+/// [`FaultyVfs::restart`] is called. Files opened before the restart keep
+/// failing, so that a database that is still shutting down can't write to
+/// the restarted storage.
+pub struct FaultyVfs {
+	inner: Arc<dyn Vfs>,
+	state: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Debug, Default)]
+struct FaultState {
+	/// Incremented on every restart. Files opened in an earlier epoch fail.
+	epoch: u64,
+	crashed: bool,
+	lose_unsynced_writes: bool,
+	num_writes: HashMap<FileKind, u64>,
+	/// The faults to inject, by the kind of file and the index of the write.
+	faults: HashMap<(FileKind, u64), Fault>,
+	/// How to undo the writes to each file since it was last synced, in the
+	/// order they were made.
+	unsynced: HashMap<PathBuf, Vec<Undo>>,
+}
+
+#[derive(Debug)]
+struct Undo {
+	offset: u64,
+	/// The bytes that were overwritten, up to the previous end of the file.
+	old: Vec<u8>,
+	old_len: u64,
+}
+
+impl FaultyVfs {
+	pub fn new(inner: Arc<dyn Vfs>) -> Self {
+		Self {
+			inner,
+			state: Arc::default(),
+		}
+	}
+
+	/// Makes a crash also lose the writes to each file since it was last
+	/// synced, like a power loss would. By default, a crash only stops the
+	/// process, so everything that was written survives.
+	pub fn lose_unsynced_writes(self, lose: bool) -> Self {
+		self.state.lock().lose_unsynced_writes = lose;
+		self
+	}
+
+	/// Injects `fault` into the write with the given index to files of the
+	/// given kind, counting from the first write after the `FaultyVfs` was
+	/// created.
+	pub fn inject(&self, kind: FileKind, write_index: u64, fault: Fault) {
+		self.state.lock().faults.insert((kind, write_index), fault);
+	}
+
+	/// The number of writes to files of the given kind so far, including
+	/// those that a fault was injected into.
+	pub fn num_writes(&self, kind: FileKind) -> u64 {
+		self.state
+			.lock()
+			.num_writes
+			.get(&kind)
+			.copied()
+			.unwrap_or(0)
+	}
+
+	pub fn crashed(&self) -> bool {
+		self.state.lock().crashed
+	}
+
+	/// Crashes immediately, as if a crash had been injected.
+	pub fn crash(&self) {
+		self.state.lock().crashed = true;
+	}
+
+	/// Brings the storage back up after a crash, so that the database can be
+	/// opened on it again to recover. Faults that weren't injected yet are
+	/// removed.
+	pub fn restart(&self) -> io::Result<()> {
+		let mut state = self.state.lock();
+		let unsynced = std::mem::take(&mut state.unsynced);
+		if state.lose_unsynced_writes {
+			for (path, undos) in unsynced {
+				let file = self.inner.open(&path)?;
+				for undo in undos.iter().rev() {
+					file.write_all_at(&undo.old, undo.offset)?;
+					file.set_len(undo.old_len)?;
+				}
+			}
+		}
+		state.faults.clear();
+		state.crashed = false;
+		state.epoch += 1;
+		Ok(())
+	}
+
+	fn check(&self) -> io::Result<()> {
+		if self.state.lock().crashed {
+			return Err(crashed());
+		}
+		Ok(())
+	}
+}
+
+impl Vfs for FaultyVfs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+		self.check()?;
+		Ok(Box::new(FaultyFile {
+			inner: self.inner.open(path)?,
+			path: path.to_path_buf(),
+			kind: FileKind::of(path),
+			epoch: self.state.lock().epoch,
+			state: Arc::clone(&self.state),
+		}))
+	}
+
+	fn exists(&self, path: &Path) -> io::Result<bool> {
+		self.check()?;
+		self.inner.exists(path)
+	}
+
+	fn remove(&self, path: &Path) -> io::Result<()> {
+		self.check()?;
+		self.state.lock().unsynced.remove(path);
+		self.inner.remove(path)
+	}
+
+	fn list(&self, path: &Path) -> io::Result<Vec<OsString>> {
+		self.check()?;
+		self.inner.list(path)
+	}
+
+	fn sync_dir(&self, path: &Path) -> io::Result<()> {
+		self.check()?;
+		self.inner.sync_dir(path)
+	}
+}
+
+fn crashed() -> io::Error {
+	io::Error::other("The storage crashed")
+}
+
+struct FaultyFile {
+	inner: Box<dyn VfsFile>,
+	path: PathBuf,
+	kind: FileKind,
+	epoch: u64,
+	state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultyFile {
+	fn check(&self, state: &FaultState) -> io::Result<()> {
+		if state.crashed || state.epoch != self.epoch {
+			return Err(crashed());
+		}
+		Ok(())
+	}
+
+	/// Remembers the bytes from `offset` to `end` before they are changed, if
+	/// unsynced writes are lost on a crash.
+	fn record_undo(&self, state: &mut FaultState, offset: u64, end: u64) -> io::Result<()> {
+		if !state.lose_unsynced_writes {
+			return Ok(());
+		}
+		let old_len = self.inner.len()?;
+		let old_end = end.min(old_len);
+		let mut old = vec![0; usize::try_from(old_end.saturating_sub(offset)).unwrap()];
+		if !old.is_empty() {
+			self.inner.read_exact_at(&mut old, offset)?;
+		}
+		state
+			.unsynced
+			.entry(self.path.clone())
+			.or_default()
+			.push(Undo {
+				offset,
+				old,
+				old_len,
+			});
+		Ok(())
+	}
+}
+
+impl VfsFile for FaultyFile {
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		self.check(&self.state.lock())?;
+		self.inner.read_exact_at(buf, offset)
+	}
+
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+		let mut state = self.state.lock();
+		self.check(&state)?;
+		let num_writes = state.num_writes.entry(self.kind).or_default();
+		let write_index = *num_writes;
+		*num_writes += 1;
+		match state.faults.remove(&(self.kind, write_index)) {
+			Some(Fault::IoError) => {
+				return Err(io::Error::other("Injected I/O error"));
+			}
+			Some(Fault::Crash) => {
+				state.crashed = true;
+				return Err(crashed());
+			}
+			Some(Fault::TornWrite { len }) => {
+				// The part of the write that reached the file survives the
+				// crash, even though it wasn't synced.
+				self.inner
+					.write_all_at(&buf[..len.min(buf.len())], offset)?;
+				state.crashed = true;
+				return Err(crashed());
+			}
+			None => (),
+		}
+		self.record_undo(&mut state, offset, offset + buf.len() as u64)?;
+		self.inner.write_all_at(buf, offset)
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		self.check(&self.state.lock())?;
+		self.inner.len()
+	}
+
+	fn set_len(&self, len: u64) -> io::Result<()> {
+		let mut state = self.state.lock();
+		self.check(&state)?;
+		self.record_undo(&mut state, len, u64::MAX)?;
+		self.inner.set_len(len)
+	}
+
+	fn sync_data(&self) -> io::Result<()> {
+		let mut state = self.state.lock();
+		self.check(&state)?;
+		self.inner.sync_data()?;
+		state.unsynced.remove(&self.path);
+		Ok(())
+	}
+}
+
+#[derive(Debug, Error)]
+pub enum CrashTestError {
+	#[error(transparent)]
+	Database(#[from] Error),
+
+	#[error("After a fault at write {write_index}, page {page_id} holds transaction {found}, but transaction {expected} committed last")]
+	LostCommit {
+		write_index: u64,
+		page_id: PageId,
+		expected: u64,
+		found: u64,
+	},
+
+	#[error("After a fault at write {write_index}, transaction {transaction} was only partially recovered")]
+	PartialCommit { write_index: u64, transaction: u64 },
+}
+
+impl From<io::Error> for CrashTestError {
+	fn from(value: io::Error) -> Self {
+		Self::Database(FileError::from(value).into())
+	}
+}
+
+/// Checks that a database keeps every committed transaction when it crashes,
+/// by running a workload on it and injecting a fault into each of its writes
+/// in turn.
+///
+/// Each transaction of the workload writes its number to the same page in
+/// two segments, so that losing a commit, or recovering only part of one, is
+/// detected. The database is kept in memory, and opened with the options of
+/// the given builder.
+pub struct CrashTest {
+	builder: DatabaseBuilder,
+	kind: FileKind,
+	fault: Fault,
+	num_transactions: u64,
+	checkpoint_interval: Option<u64>,
+	lose_unsynced_writes: bool,
+}
+
+#[derive(Debug, Default)]
+struct WorkloadResult {
+	/// The last transaction that committed to each slot.
+	committed: [u64; CrashTest::NUM_SLOTS as usize],
+	/// The transaction that failed, which may or may not have committed.
+	uncertain: Option<u64>,
+}
+
+impl CrashTest {
+	const NUM_SLOTS: u16 = 4;
+	const SEGMENTS: [u32; 2] = [1, 2];
+
+	pub fn new(builder: DatabaseBuilder) -> Self {
+		Self {
+			builder,
+			kind: FileKind::Wal,
+			fault: Fault::Crash,
+			num_transactions: 16,
+			checkpoint_interval: None,
+			lose_unsynced_writes: false,
+		}
+	}
+
+	/// Sets the fault that is injected, and the kind of file whose writes it
+	/// is injected into. By default, the process crashes before a WAL write.
+	pub fn fault(mut self, kind: FileKind, fault: Fault) -> Self {
+		self.kind = kind;
+		self.fault = fault;
+		self
+	}
+
+	pub fn num_transactions(mut self, num_transactions: u64) -> Self {
+		self.num_transactions = num_transactions;
+		self
+	}
+
+	/// Takes a checkpoint after every `interval` transactions, so that the
+	/// workload also writes to the segment files.
+	pub fn checkpoint_interval(mut self, interval: Option<u64>) -> Self {
+		self.checkpoint_interval = interval;
+		self
+	}
+
+	/// See [`FaultyVfs::lose_unsynced_writes`]. This can only succeed if the
+	/// builder sets a [`Durability`](crate::Durability) that syncs.
+	pub fn lose_unsynced_writes(mut self, lose: bool) -> Self {
+		self.lose_unsynced_writes = lose;
+		self
+	}
+
+	/// Runs the workload once for every write it makes to the chosen kind of
+	/// file, injecting the fault into that write, and checks the database
+	/// after it recovered. Returns the number of faults that were injected.
+	pub fn run(&self) -> Result<u64, CrashTestError> {
+		for write_index in 0.. {
+			let vfs = Arc::new(
+				FaultyVfs::new(Arc::new(MemoryVfs::new()))
+					.lose_unsynced_writes(self.lose_unsynced_writes),
+			);
+			vfs.inject(self.kind, write_index, self.fault);
+			let mut result = WorkloadResult::default();
+			// A panic of the engine ends the process, just like a crash.
+			let _ = panic::catch_unwind(AssertUnwindSafe(|| self.run_workload(&vfs, &mut result)));
+			// The database is dropped without closing it.
+			vfs.crash();
+			if vfs.num_writes(self.kind) <= write_index {
+				return Ok(write_index);
+			}
+			vfs.restart()?;
+			let db = self.builder.clone().open_vfs(vfs)?;
+			self.verify(&db, write_index, &result)?;
+		}
+		unreachable!()
+	}
+
+	fn run_workload(&self, vfs: &Arc<FaultyVfs>, result: &mut WorkloadResult) {
+		let Ok(db) = self.builder.clone().open_vfs(vfs.clone()) else {
+			return;
+		};
+		for transaction in 1..=self.num_transactions {
+			result.uncertain = Some(transaction);
+			if Self::write_transaction(&db, transaction).is_err() {
+				return;
+			}
+			result.uncertain = None;
+			result.committed[Self::slot(transaction)] = transaction;
+			let checkpoint_due = self
+				.checkpoint_interval
+				.is_some_and(|interval| transaction % interval == 0);
+			if checkpoint_due && db.checkpoint().is_err() {
+				return;
+			}
+		}
+	}
+
+	fn write_transaction(db: &Database, transaction: u64) -> Result<(), Error> {
+		let mut t = db.begin_transaction()?;
+		for segment_num in Self::SEGMENTS {
+			t.write(
+				Self::page_id(segment_num, Self::slot(transaction)),
+				0,
+				&transaction.to_ne_bytes(),
+			)?;
+		}
+		t.commit()
+	}
+
+	fn verify(
+		&self,
+		db: &Database,
+		write_index: u64,
+		result: &WorkloadResult,
+	) -> Result<(), CrashTestError> {
+		for (slot, expected) in result.committed.iter().copied().enumerate() {
+			let uncertain = result
+				.uncertain
+				.filter(|transaction| Self::slot(*transaction) == slot);
+			let mut found = Vec::new();
+			for segment_num in Self::SEGMENTS {
+				let page_id = Self::page_id(segment_num, slot);
+				let mut buf = [0; 8];
+				db.read(page_id, 0, &mut buf)?;
+				let transaction = u64::from_ne_bytes(buf);
+				if transaction != expected && Some(transaction) != uncertain {
+					return Err(CrashTestError::LostCommit {
+						write_index,
+						page_id,
+						expected,
+						found: transaction,
+					});
+				}
+				found.push(transaction);
+			}
+			if found.iter().any(|transaction| *transaction != found[0]) {
+				return Err(CrashTestError::PartialCommit {
+					write_index,
+					transaction: found.into_iter().max().unwrap(),
+				});
+			}
+		}
+		Ok(())
+	}
+
+	fn slot(transaction: u64) -> usize {
+		usize::try_from(transaction % u64::from(Self::NUM_SLOTS)).unwrap()
+	}
+
+	fn page_id(segment_num: u32, slot: usize) -> PageId {
+		PageId::new_unwrap(segment_num, u16::try_from(slot + 1).unwrap())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::Durability;
+
+	use super::*;
+
+	#[test]
+	fn recover_from_faults_at_every_write() {
+		// given
+		let wal_test = CrashTest::new(Database::builder())
+			.fault(FileKind::Wal, Fault::TornWrite { len: 8 })
+			.num_transactions(6);
+		let segment_test = CrashTest::new(Database::builder().durability(Durability::Sync))
+			.fault(FileKind::Segment, Fault::Crash)
+			.num_transactions(6)
+			.checkpoint_interval(Some(2))
+			.lose_unsynced_writes(true);
+
+		// when
+		let wal_faults = wal_test.run().unwrap();
+		let segment_faults = segment_test.run().unwrap();
+
+		// then
+		assert!(wal_faults > 6);
+		assert!(segment_faults > 0);
+	}
+}
@@ -0,0 +1,369 @@
+use std::{
+	fmt,
+	num::{NonZeroU16, NonZeroU64},
+	sync::Arc,
+};
+
+use aes::{
+	cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit},
+	Aes256,
+};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::repr::U32;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The shortest data that can be encrypted. XTS steals ciphertext from the
+/// previous block to encrypt a partial last block, so there has to be at
+/// least one full block.
+pub(crate) const MIN_DATA_LENGTH: usize = BLOCK_SIZE;
+
+/// A 256-bit key that the pages and WAL items of a database are encrypted
+/// with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+	pub fn new(bytes: [u8; 32]) -> Self {
+		Self(bytes)
+	}
+}
+
+impl fmt::Debug for EncryptionKey {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("EncryptionKey(..)")
+	}
+}
+
+/// How a database folder is encrypted. The key itself is never stored, only
+/// a check value that identifies it.
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+pub(super) struct EncryptionRepr {
+	pub key_check: [u8; BLOCK_SIZE],
+	pub salt: [u8; BLOCK_SIZE],
+	pub mode: U32,
+}
+
+impl EncryptionRepr {
+	/// AES-256 in XTS mode, keyed by the location of the data.
+	pub const MODE_XTS: u32 = 1;
+}
+
+/// Encrypts page bodies and WAL item bodies with AES-256 in XTS mode, which
+/// keeps their length, so the file formats don't change otherwise.
+///
+/// The tweak of each page or item is its location: the segment and page
+/// number of a page, or the WAL generation and offset of an item. Segments
+/// and the WAL use separate keys, which are derived from the database key
+/// and salt.
+pub(crate) struct Cipher {
+	aes: Aes256,
+	salt: [u8; BLOCK_SIZE],
+	segments: Xts,
+	wal: Xts,
+}
+
+impl Cipher {
+	const SEGMENT_KEYS: u8 = 1;
+	const WAL_KEYS: u8 = 3;
+
+	pub fn new(key: &EncryptionKey, salt: [u8; BLOCK_SIZE]) -> Self {
+		let aes = Aes256::new(GenericArray::from_slice(&key.0));
+		let derive_xts = |purpose| Xts {
+			data: derive_key(&aes, salt, purpose),
+			tweak: derive_key(&aes, salt, purpose + 1),
+		};
+		Self {
+			segments: derive_xts(Self::SEGMENT_KEYS),
+			wal: derive_xts(Self::WAL_KEYS),
+			aes,
+			salt,
+		}
+	}
+
+	/// Creates a cipher with a new random salt, for a database that is
+	/// created.
+	pub fn generate(key: &EncryptionKey) -> Result<Self, getrandom::Error> {
+		let mut salt = [0; BLOCK_SIZE];
+		getrandom::getrandom(&mut salt)?;
+		Ok(Self::new(key, salt))
+	}
+
+	pub fn salt(&self) -> [u8; BLOCK_SIZE] {
+		self.salt
+	}
+
+	/// A value that identifies the key, without revealing it.
+	pub fn key_check(&self) -> [u8; BLOCK_SIZE] {
+		let mut block = GenericArray::default();
+		self.aes.encrypt_block(&mut block);
+		block.into()
+	}
+}
+
+/// Derives an AES-256 key for `purpose` from the database key, by
+/// encrypting two blocks made from the salt and the purpose.
+fn derive_key(aes: &Aes256, salt: [u8; BLOCK_SIZE], purpose: u8) -> Aes256 {
+	let mut key = [0; 2 * BLOCK_SIZE];
+	for (half, chunk) in (1..).zip(key.chunks_exact_mut(BLOCK_SIZE)) {
+		let mut block = GenericArray::from(salt);
+		block[0] ^= purpose;
+		block[1] ^= half;
+		aes.encrypt_block(&mut block);
+		chunk.copy_from_slice(&block);
+	}
+	Aes256::new(GenericArray::from_slice(&key))
+}
+
+/// AES-256 in XTS mode, as specified by IEEE 1619. A partial last block is
+/// encrypted with ciphertext stealing.
+struct Xts {
+	data: Aes256,
+	tweak: Aes256,
+}
+
+impl Xts {
+	fn encrypt(&self, tweak: [u8; BLOCK_SIZE], buf: &mut [u8]) {
+		assert!(
+			buf.len() >= MIN_DATA_LENGTH,
+			"Data to encrypt must be at least one block long"
+		);
+		let full_blocks = buf.len() / BLOCK_SIZE;
+		let partial_len = buf.len() % BLOCK_SIZE;
+		let mut tweak = self.initial_tweak(tweak);
+		for block in buf.chunks_exact_mut(BLOCK_SIZE).take(full_blocks - 1) {
+			self.encrypt_block(block, &tweak);
+			multiply_tweak(&mut tweak);
+		}
+
+		let (last_block, partial) = buf[(full_blocks - 1) * BLOCK_SIZE..].split_at_mut(BLOCK_SIZE);
+		self.encrypt_block(last_block, &tweak);
+		if partial_len > 0 {
+			multiply_tweak(&mut tweak);
+			partial.swap_with_slice(&mut last_block[..partial_len]);
+			self.encrypt_block(last_block, &tweak);
+		}
+	}
+
+	fn decrypt(&self, tweak: [u8; BLOCK_SIZE], buf: &mut [u8]) {
+		assert!(
+			buf.len() >= MIN_DATA_LENGTH,
+			"Data to decrypt must be at least one block long"
+		);
+		let full_blocks = buf.len() / BLOCK_SIZE;
+		let partial_len = buf.len() % BLOCK_SIZE;
+		let mut tweak = self.initial_tweak(tweak);
+		for block in buf.chunks_exact_mut(BLOCK_SIZE).take(full_blocks - 1) {
+			self.decrypt_block(block, &tweak);
+			multiply_tweak(&mut tweak);
+		}
+
+		let (last_block, partial) = buf[(full_blocks - 1) * BLOCK_SIZE..].split_at_mut(BLOCK_SIZE);
+		if partial_len > 0 {
+			// The last full block was encrypted with the tweak after its own.
+			let mut next_tweak = tweak;
+			multiply_tweak(&mut next_tweak);
+			self.decrypt_block(last_block, &next_tweak);
+			partial.swap_with_slice(&mut last_block[..partial_len]);
+		}
+		self.decrypt_block(last_block, &tweak);
+	}
+
+	fn initial_tweak(&self, tweak: [u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+		let mut block = GenericArray::from(tweak);
+		self.tweak.encrypt_block(&mut block);
+		block.into()
+	}
+
+	fn encrypt_block(&self, block: &mut [u8], tweak: &[u8; BLOCK_SIZE]) {
+		xor_block(block, tweak);
+		self.data.encrypt_block(GenericArray::from_mut_slice(block));
+		xor_block(block, tweak);
+	}
+
+	fn decrypt_block(&self, block: &mut [u8], tweak: &[u8; BLOCK_SIZE]) {
+		xor_block(block, tweak);
+		self.data.decrypt_block(GenericArray::from_mut_slice(block));
+		xor_block(block, tweak);
+	}
+}
+
+fn xor_block(block: &mut [u8], other: &[u8; BLOCK_SIZE]) {
+	for (byte, other_byte) in block.iter_mut().zip(other) {
+		*byte ^= other_byte;
+	}
+}
+
+/// Multiplies the tweak by the primitive element of GF(2^128), as the
+/// little-endian polynomial that XTS uses.
+fn multiply_tweak(tweak: &mut [u8; BLOCK_SIZE]) {
+	let value = u128::from_le_bytes(*tweak);
+	let reduction = if value >> 127 == 1 { 0x87 } else { 0 };
+	*tweak = ((value << 1) ^ reduction).to_le_bytes();
+}
+
+/// A cipher for the pages or items of a single file.
+#[derive(Clone)]
+pub(crate) struct FileCipher {
+	cipher: Arc<Cipher>,
+	is_wal: bool,
+	file_num: u64,
+}
+
+impl FileCipher {
+	pub fn segment(cipher: Arc<Cipher>, segment_num: u32) -> Self {
+		Self {
+			cipher,
+			is_wal: false,
+			file_num: segment_num.into(),
+		}
+	}
+
+	pub fn wal(cipher: Arc<Cipher>, generation: u64) -> Self {
+		Self {
+			cipher,
+			is_wal: true,
+			file_num: generation,
+		}
+	}
+
+	fn xts(&self) -> &Xts {
+		if self.is_wal {
+			&self.cipher.wal
+		} else {
+			&self.cipher.segments
+		}
+	}
+
+	fn tweak(&self, position: u64) -> [u8; BLOCK_SIZE] {
+		(u128::from(self.file_num) | u128::from(position) << 64).to_le_bytes()
+	}
+
+	/// Encrypts the body of the page `page_num`.
+	pub fn encrypt_page(&self, page_num: NonZeroU16, buf: &mut [u8]) {
+		self.xts().encrypt(self.tweak(page_num.get().into()), buf);
+	}
+
+	/// Decrypts the body of the page `page_num`.
+	pub fn decrypt_page(&self, page_num: NonZeroU16, buf: &mut [u8]) {
+		self.xts().decrypt(self.tweak(page_num.get().into()), buf);
+	}
+
+	/// Encrypts the body of the WAL item at `offset`, which has to be at
+	/// least [`MIN_DATA_LENGTH`] bytes long.
+	pub fn encrypt_item(&self, offset: NonZeroU64, buf: &mut [u8]) {
+		self.xts().encrypt(self.tweak(offset.get()), buf);
+	}
+
+	/// Decrypts the body of the WAL item at `offset`.
+	pub fn decrypt_item(&self, offset: NonZeroU64, buf: &mut [u8]) {
+		self.xts().decrypt(self.tweak(offset.get()), buf);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encrypt_and_decrypt_page() {
+		// given
+		let key = EncryptionKey::new([7; 32]);
+		let cipher = Arc::new(Cipher::generate(&key).unwrap());
+		let file_cipher = FileCipher::segment(Arc::clone(&cipher), 2);
+		let page_num = NonZeroU16::new(3).unwrap();
+		let plaintext: Vec<u8> = (0..100).collect();
+
+		// when
+		let mut encrypted = plaintext.clone();
+		file_cipher.encrypt_page(page_num, &mut encrypted);
+		let mut other_page = plaintext.clone();
+		file_cipher.encrypt_page(NonZeroU16::new(4).unwrap(), &mut other_page);
+		let mut decrypted = encrypted.clone();
+		FileCipher::segment(Arc::new(Cipher::new(&key, cipher.salt())), 2)
+			.decrypt_page(page_num, &mut decrypted);
+
+		// then
+		assert_ne!(encrypted, plaintext);
+		assert_ne!(encrypted, other_page);
+		assert_eq!(decrypted, plaintext);
+		assert_ne!(
+			Cipher::new(&EncryptionKey::new([8; 32]), cipher.salt()).key_check(),
+			cipher.key_check()
+		);
+	}
+
+	#[test]
+	fn encrypt_items_by_location() {
+		// given
+		let cipher = Arc::new(Cipher::generate(&EncryptionKey::new([7; 32])).unwrap());
+		let wal_cipher = FileCipher::wal(Arc::clone(&cipher), 2);
+		let segment_cipher = FileCipher::segment(Arc::clone(&cipher), 2);
+		let offset = NonZeroU64::new(3).unwrap();
+		let first: Vec<u8> = (0..40).collect();
+		let mut second = first.clone();
+		second[0] = 1;
+
+		// when
+		let mut first_encrypted = first.clone();
+		wal_cipher.encrypt_item(offset, &mut first_encrypted);
+		let mut second_encrypted = second.clone();
+		wal_cipher.encrypt_item(offset, &mut second_encrypted);
+		let mut in_segment = first.clone();
+		segment_cipher.encrypt_page(NonZeroU16::new(3).unwrap(), &mut in_segment);
+		let mut decrypted = second_encrypted.clone();
+		wal_cipher.decrypt_item(offset, &mut decrypted);
+
+		// then
+		assert_eq!(decrypted, second);
+		assert_ne!(first_encrypted, in_segment);
+		// Unlike a stream cipher, rewriting an item at the same offset
+		// doesn't reveal which bytes differ
+		let differing = first_encrypted
+			.iter()
+			.zip(&second_encrypted)
+			.filter(|(a, b)| a != b)
+			.count();
+		assert!(differing > 1);
+	}
+
+	#[test]
+	fn xts_test_vector() {
+		// given
+		let key: Vec<u8> = (0..64).collect();
+		let xts = Xts {
+			data: Aes256::new(GenericArray::from_slice(&key[..32])),
+			tweak: Aes256::new(GenericArray::from_slice(&key[32..])),
+		};
+		let tweak: [u8; BLOCK_SIZE] = std::array::from_fn(|i| 100 + i as u8);
+		let plaintext: Vec<u8> = (0..37).collect();
+
+		// when
+		let mut full_blocks = plaintext[..32].to_vec();
+		xts.encrypt(tweak, &mut full_blocks);
+		let mut stolen = plaintext.clone();
+		xts.encrypt(tweak, &mut stolen);
+		let mut decrypted = stolen.clone();
+		xts.decrypt(tweak, &mut decrypted);
+
+		// then
+		assert_eq!(
+			full_blocks,
+			hex("464dc01fe952c269ec12936cc12629fac5147edbe68934a1b02ff4593f0e4c43")
+		);
+		assert_eq!(
+			stolen,
+			hex("464dc01fe952c269ec12936cc12629fa9e5047c5fecc0f10586708346d4e2f30c5147edbe6")
+		);
+		assert_eq!(decrypted, plaintext);
+	}
+
+	fn hex(hex: &str) -> Vec<u8> {
+		(0..hex.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+			.collect()
+	}
+}
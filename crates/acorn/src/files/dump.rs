@@ -0,0 +1,277 @@
+use std::io::{self, Read, Write};
+
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::repr::{IoRepr, Repr, U16, U32};
+
+use super::{
+	generic::{FeatureFlags, FileType, GenericHeader, GenericHeaderRepr},
+	utils::CRC32,
+	FileError,
+};
+
+/// The version of the dump format. A dump holds the definitions of the trees
+/// and their entries, not pages, so it doesn't depend on the page size or the
+/// layout of the database it was taken from, and only changes if the entries
+/// themselves change.
+///
+/// Version 1 held page images, and can't be loaded anymore.
+pub(crate) const FORMAT_VERSION: u8 = 2;
+
+/// The header of an entry of a dump. It is followed by `key_len` bytes of
+/// key and `data_len` bytes of data, and every entry ends with the CRC32
+/// checksum of its header, key and data.
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C, packed)]
+struct EntryHeaderRepr {
+	kind: u8,
+	key_len: U16,
+	data_len: U32,
+}
+
+/// What an entry of a dump holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum EntryKind {
+	/// Marks the end of the dump, so that a truncated dump is detected.
+	End = 1,
+	/// The definition of a tree, with the tree's name as the key. The
+	/// records that follow it, up to the next tree, belong to it.
+	Tree = 2,
+	/// A key of the last tree, with the record it points to as the data.
+	Record = 3,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryHeader {
+	kind: EntryKind,
+	key_len: u16,
+	data_len: u32,
+}
+
+impl From<EntryHeader> for EntryHeaderRepr {
+	fn from(value: EntryHeader) -> Self {
+		Self {
+			kind: value.kind as u8,
+			key_len: value.key_len.into(),
+			data_len: value.data_len.into(),
+		}
+	}
+}
+
+impl TryFrom<EntryHeaderRepr> for EntryHeader {
+	type Error = FileError;
+
+	fn try_from(value: EntryHeaderRepr) -> Result<Self, Self::Error> {
+		let kind = match value.kind {
+			1 => EntryKind::End,
+			2 => EntryKind::Tree,
+			3 => EntryKind::Record,
+			kind => {
+				return Err(FileError::Corrupted(format!(
+					"Unknown dump entry kind {kind}"
+				)))
+			}
+		};
+		Ok(Self {
+			kind,
+			key_len: value.key_len.get(),
+			data_len: value.data_len.get(),
+		})
+	}
+}
+
+impl Repr<EntryHeader> for EntryHeaderRepr {
+	type Error = FileError;
+}
+
+/// Writes the entries of a dump that [`DumpReader`] reads.
+pub(crate) struct DumpWriter<W: Write> {
+	writer: W,
+}
+
+impl<W: Write> DumpWriter<W> {
+	pub fn new(mut writer: W) -> Result<Self, FileError> {
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				file_type: FileType::Dump,
+				content_offset: u16::try_from(GenericHeaderRepr::SIZE).unwrap(),
+				version: FORMAT_VERSION,
+				features: FeatureFlags::NONE,
+			},
+			&mut writer,
+		)?;
+		Ok(Self { writer })
+	}
+
+	/// Writes an entry of the given kind, which mustn't be
+	/// [`EntryKind::End`]; see [`Self::finish`] for that.
+	pub fn write_entry(
+		&mut self,
+		kind: EntryKind,
+		key: &[u8],
+		data: &[u8],
+	) -> Result<(), FileError> {
+		debug_assert_ne!(kind, EntryKind::End);
+		self.write(kind, key, data)
+	}
+
+	/// Writes the end of the dump, and returns the underlying writer.
+	pub fn finish(mut self) -> Result<W, FileError> {
+		self.write(EntryKind::End, &[], &[])?;
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	fn write(&mut self, kind: EntryKind, key: &[u8], data: &[u8]) -> Result<(), FileError> {
+		let header = EntryHeaderRepr::from(EntryHeader {
+			kind,
+			key_len: u16::try_from(key.len()).expect("Keys are shorter than 64 KiB"),
+			data_len: u32::try_from(data.len()).expect("Records are smaller than 4 GiB"),
+		});
+		let mut digest = CRC32.digest();
+		digest.update(header.as_bytes());
+		digest.update(key);
+		digest.update(data);
+		self.writer.write_all(header.as_bytes())?;
+		self.writer.write_all(key)?;
+		self.writer.write_all(data)?;
+		self.writer
+			.write_all(U32::new(digest.finalize()).as_bytes())?;
+		Ok(())
+	}
+}
+
+/// Reads the entries of a dump that was written with [`DumpWriter`].
+pub(crate) struct DumpReader<R: Read> {
+	reader: R,
+	finished: bool,
+}
+
+impl<R: Read> DumpReader<R> {
+	pub fn new(mut reader: R) -> Result<Self, FileError> {
+		let header = GenericHeaderRepr::deserialize(&mut reader)?;
+		if header.file_type != FileType::Dump {
+			return Err(FileError::WrongFileType(header.file_type));
+		}
+		if header.version != FORMAT_VERSION {
+			return Err(FileError::IncompatibleVersion(
+				header.file_type,
+				header.version,
+			));
+		}
+		header.check_features(FeatureFlags::NONE)?;
+		let skipped =
+			u64::from(header.content_offset).saturating_sub(GenericHeaderRepr::SIZE as u64);
+		io::copy(&mut (&mut reader).take(skipped), &mut io::sink())?;
+		Ok(Self {
+			reader,
+			finished: false,
+		})
+	}
+
+	/// Reads the next entry into `key` and `data`, which are resized to fit
+	/// it, and returns its kind, or `None` once the end of the dump was read.
+	pub fn next_entry(
+		&mut self,
+		key: &mut Vec<u8>,
+		data: &mut Vec<u8>,
+	) -> Result<Option<EntryKind>, FileError> {
+		if self.finished {
+			return Ok(None);
+		}
+		let mut header = EntryHeaderRepr::new_zeroed();
+		self.reader.read_exact(header.as_bytes_mut())?;
+		key.resize(header.key_len.get().into(), 0);
+		self.reader.read_exact(key)?;
+		// The data is read incrementally, so that a corrupted length doesn't
+		// allocate more memory than the dump holds.
+		data.clear();
+		let data_len = u64::from(header.data_len.get());
+		if (&mut self.reader).take(data_len).read_to_end(data)? as u64 != data_len {
+			return Err(FileError::UnexpectedEof);
+		}
+		let mut crc = U32::ZERO;
+		self.reader.read_exact(crc.as_bytes_mut())?;
+
+		let mut digest = CRC32.digest();
+		digest.update(header.as_bytes());
+		digest.update(key);
+		digest.update(data);
+		if digest.finalize() != crc.get() {
+			return Err(FileError::ChecksumMismatch);
+		}
+
+		let header = EntryHeader::try_from(header)?;
+		if header.kind == EntryKind::End {
+			self.finished = true;
+			return Ok(None);
+		}
+		Ok(Some(header.kind))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn write_and_read_dump() {
+		// given
+		let mut writer = DumpWriter::new(Vec::new()).unwrap();
+		writer
+			.write_entry(EntryKind::Tree, b"users", &[1, 0, 0])
+			.unwrap();
+		writer
+			.write_entry(EntryKind::Record, b"alice", &[1, 2, 3])
+			.unwrap();
+		writer.write_entry(EntryKind::Record, b"bob", &[]).unwrap();
+		let dump = writer.finish().unwrap();
+
+		// when
+		let mut reader = DumpReader::new(dump.as_slice()).unwrap();
+		let mut key = Vec::new();
+		let mut data = Vec::new();
+		let mut entries = Vec::new();
+		while let Some(kind) = reader.next_entry(&mut key, &mut data).unwrap() {
+			entries.push((kind, key.clone(), data.clone()));
+		}
+
+		// then
+		assert_eq!(
+			entries,
+			vec![
+				(EntryKind::Tree, b"users".to_vec(), vec![1, 0, 0]),
+				(EntryKind::Record, b"alice".to_vec(), vec![1, 2, 3]),
+				(EntryKind::Record, b"bob".to_vec(), vec![]),
+			]
+		);
+	}
+
+	#[test]
+	fn detect_corrupted_and_truncated_dumps() {
+		// given
+		let mut writer = DumpWriter::new(Vec::new()).unwrap();
+		writer
+			.write_entry(EntryKind::Record, b"key", &[1, 2, 3])
+			.unwrap();
+		let dump = writer.finish().unwrap();
+		let mut corrupted = dump.clone();
+		corrupted[GenericHeaderRepr::SIZE + EntryHeaderRepr::SIZE] = 7;
+		let truncated = &dump[..dump.len() - 1];
+
+		// when
+		let mut key = Vec::new();
+		let mut data = Vec::new();
+		let corrupted_result = DumpReader::new(corrupted.as_slice())
+			.unwrap()
+			.next_entry(&mut key, &mut data);
+		let mut truncated_reader = DumpReader::new(truncated).unwrap();
+		truncated_reader.next_entry(&mut key, &mut data).unwrap();
+		let truncated_result = truncated_reader.next_entry(&mut key, &mut data);
+
+		// then
+		assert!(matches!(corrupted_result, Err(FileError::ChecksumMismatch)));
+		assert!(matches!(truncated_result, Err(FileError::UnexpectedEof)));
+	}
+}
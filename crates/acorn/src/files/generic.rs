@@ -1,24 +1,42 @@
+use std::io::Read;
+
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
-use crate::repr::Repr;
+use crate::repr::{Repr, U16};
 
 use super::FileError;
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C, packed)]
-pub(super) struct GenericHeaderRepr {
+pub(crate) struct GenericHeaderRepr {
 	magic: [u8; 4],
 	byte_order: u8,
 	file_type: u8,
-	content_offset: u16,
+	content_offset: U16,
 	version: u8,
+	required_features: U16,
+	optional_features: U16,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The header of the format versions that predate feature flags, see
+/// [`GenericHeader::has_features`]. It is a prefix of [`GenericHeaderRepr`].
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C, packed)]
+pub(crate) struct LegacyHeaderRepr {
+	magic: [u8; 4],
+	byte_order: u8,
+	file_type: u8,
+	content_offset: U16,
+	version: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub(crate) enum FileType {
 	Wal = 0,
 	Segment = 1,
+	/// A logical dump of the trees of a database and their records.
+	Dump = 2,
 }
 
 impl TryFrom<u8> for FileType {
@@ -28,34 +46,138 @@ impl TryFrom<u8> for FileType {
 		match value {
 			0 => Ok(Self::Wal),
 			1 => Ok(Self::Segment),
+			2 => Ok(Self::Dump),
 			_ => Err(FileError::Corrupted(format!("Unknown file type {value}"))),
 		}
 	}
 }
 
-#[cfg(target_endian = "big")]
-const NATIVE_BYTE_ORDER: u8 = 0;
+/// The byte order of the integers in the file, see
+/// [`FileByteOrder`](crate::repr::FileByteOrder).
+#[cfg(all(target_endian = "big", not(feature = "portable-format")))]
+const FILE_BYTE_ORDER: u8 = 0;
+
+#[cfg(any(target_endian = "little", feature = "portable-format"))]
+const FILE_BYTE_ORDER: u8 = 1;
+
+pub(crate) const MAGIC: [u8; 4] = *b"ACRN";
+
+/// Format features used by a file, on top of what its format version implies.
+///
+/// Minor format changes that only add information are marked as optional
+/// features, which readers that don't know them can safely ignore. Changes
+/// that older readers would misinterpret are marked as required features,
+/// and files using unknown required features are refused.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FeatureFlags {
+	pub required: u16,
+	pub optional: u16,
+}
+
+impl FeatureFlags {
+	pub const NONE: Self = Self {
+		required: 0,
+		optional: 0,
+	};
 
-#[cfg(target_endian = "little")]
-const NATIVE_BYTE_ORDER: u8 = 1;
+	/// The features of a newly created file.
+	pub const fn new(encrypted: bool) -> Self {
+		Self {
+			required: if encrypted { FEATURE_ENCRYPTED } else { 0 },
+			optional: 0,
+		}
+	}
 
-const MAGIC: [u8; 4] = *b"ACRN";
+	/// Marks the file as possibly containing compressed items.
+	pub const fn with_compression(mut self) -> Self {
+		self.required |= FEATURE_COMPRESSED;
+		self
+	}
+
+	pub fn is_encrypted(self) -> bool {
+		self.required & FEATURE_ENCRYPTED != 0
+	}
+
+	pub fn is_compressed(self) -> bool {
+		self.required & FEATURE_COMPRESSED != 0
+	}
+
+	pub fn unknown(self, supported: FeatureFlags) -> FeatureFlags {
+		FeatureFlags {
+			required: self.required & !supported.required,
+			optional: self.optional & !supported.optional,
+		}
+	}
+}
+
+/// Required feature: the pages or items of the file are encrypted.
+pub(crate) const FEATURE_ENCRYPTED: u16 = 0b1;
+
+/// Required feature: some items of the file are compressed with zstd.
+pub(crate) const FEATURE_COMPRESSED: u16 = 0b10;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub(super) struct GenericHeader {
+pub(crate) struct GenericHeader {
 	pub file_type: FileType,
 	pub content_offset: u16,
 	pub version: u8,
+	pub features: FeatureFlags,
+}
+
+impl GenericHeader {
+	/// Whether the headers of files of `file_type` and `version` hold feature
+	/// flags. Older versions use the layout of [`LegacyHeaderRepr`].
+	pub fn has_features(file_type: FileType, version: u8) -> bool {
+		match file_type {
+			FileType::Wal | FileType::Segment => version >= 2,
+			FileType::Dump => true,
+		}
+	}
+
+	/// Reads a header in the layout that its file type and version use.
+	pub fn read(mut reader: impl Read) -> Result<Self, FileError> {
+		let mut repr = GenericHeaderRepr::new_zeroed();
+		let (legacy, rest) = repr.as_bytes_mut().split_at_mut(LegacyHeaderRepr::SIZE);
+		reader.read_exact(legacy)?;
+		let header = LegacyHeaderRepr::from_bytes(legacy)?;
+		if !Self::has_features(header.file_type, header.version) {
+			return Ok(header);
+		}
+		reader.read_exact(rest)?;
+		repr.try_into()
+	}
+
+	/// The size of the header in the layout of its version.
+	pub fn size(&self) -> usize {
+		if Self::has_features(self.file_type, self.version) {
+			GenericHeaderRepr::SIZE
+		} else {
+			LegacyHeaderRepr::SIZE
+		}
+	}
+
+	pub fn check_features(&self, supported: FeatureFlags) -> Result<(), FileError> {
+		let unknown = self.features.unknown(supported);
+		if unknown.required != 0 {
+			return Err(FileError::UnsupportedFeatures(
+				self.file_type,
+				unknown.required,
+			));
+		}
+		Ok(())
+	}
 }
 
 impl From<GenericHeader> for GenericHeaderRepr {
 	fn from(value: GenericHeader) -> Self {
 		Self {
 			magic: MAGIC,
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: FILE_BYTE_ORDER,
 			file_type: value.file_type as u8,
-			content_offset: value.content_offset,
+			content_offset: value.content_offset.into(),
 			version: value.version,
+			required_features: value.features.required.into(),
+			optional_features: value.features.optional.into(),
 		}
 	}
 }
@@ -67,13 +189,17 @@ impl TryFrom<GenericHeaderRepr> for GenericHeader {
 		if value.magic != MAGIC {
 			return Err(FileError::MissingMagic);
 		}
-		if value.byte_order != NATIVE_BYTE_ORDER {
+		if value.byte_order != FILE_BYTE_ORDER {
 			return Err(FileError::ByteOrderMismatch);
 		}
 		Ok(Self {
 			file_type: value.file_type.try_into()?,
-			content_offset: value.content_offset,
+			content_offset: value.content_offset.get(),
 			version: value.version,
+			features: FeatureFlags {
+				required: value.required_features.get(),
+				optional: value.optional_features.get(),
+			},
 		})
 	}
 }
@@ -82,6 +208,41 @@ impl Repr<GenericHeader> for GenericHeaderRepr {
 	type Error = FileError;
 }
 
+impl From<GenericHeader> for LegacyHeaderRepr {
+	fn from(value: GenericHeader) -> Self {
+		Self {
+			magic: MAGIC,
+			byte_order: FILE_BYTE_ORDER,
+			file_type: value.file_type as u8,
+			content_offset: value.content_offset.into(),
+			version: value.version,
+		}
+	}
+}
+
+impl TryFrom<LegacyHeaderRepr> for GenericHeader {
+	type Error = FileError;
+
+	fn try_from(value: LegacyHeaderRepr) -> Result<Self, Self::Error> {
+		if value.magic != MAGIC {
+			return Err(FileError::MissingMagic);
+		}
+		if value.byte_order != FILE_BYTE_ORDER {
+			return Err(FileError::ByteOrderMismatch);
+		}
+		Ok(Self {
+			file_type: value.file_type.try_into()?,
+			content_offset: value.content_offset.get(),
+			version: value.version,
+			features: FeatureFlags::NONE,
+		})
+	}
+}
+
+impl Repr<GenericHeader> for LegacyHeaderRepr {
+	type Error = FileError;
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -90,17 +251,20 @@ mod tests {
 	fn verify_header() {
 		let header_repr = GenericHeaderRepr {
 			magic: *b"ACRN",
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: FILE_BYTE_ORDER,
 			file_type: FileType::Wal as u8,
-			content_offset: 69,
+			content_offset: 69.into(),
 			version: 1,
+			required_features: 0.into(),
+			optional_features: 0.into(),
 		};
 		assert_eq!(
 			GenericHeader::try_from(header_repr).unwrap(),
 			GenericHeader {
 				file_type: FileType::Wal,
 				content_offset: 69,
-				version: 1
+				version: 1,
+				features: FeatureFlags::NONE
 			}
 		);
 	}
@@ -109,10 +273,12 @@ mod tests {
 	fn try_verify_header_with_missing_magic() {
 		let header_repr = GenericHeaderRepr {
 			magic: *b"KEKW",
-			byte_order: NATIVE_BYTE_ORDER,
+			byte_order: FILE_BYTE_ORDER,
 			file_type: FileType::Wal as u8,
-			content_offset: 69,
+			content_offset: 69.into(),
 			version: 1,
+			required_features: 0.into(),
+			optional_features: 0.into(),
 		};
 		let err = GenericHeader::try_from(header_repr).unwrap_err();
 		assert_eq!(err.to_string(), "The file is not an acorn database file");
@@ -122,10 +288,12 @@ mod tests {
 	fn try_verify_header_with_byte_order_mismatch() {
 		let header_repr = GenericHeaderRepr {
 			magic: *b"ACRN",
-			byte_order: !NATIVE_BYTE_ORDER,
+			byte_order: !FILE_BYTE_ORDER,
 			file_type: FileType::Wal as u8,
-			content_offset: 69,
+			content_offset: 69.into(),
 			version: 1,
+			required_features: 0.into(),
+			optional_features: 0.into(),
 		};
 		let err = GenericHeader::try_from(header_repr).unwrap_err();
 		assert_eq!(
@@ -133,4 +301,91 @@ mod tests {
 			"The file was created on a platform with a different byte order and cannot be opened"
 		);
 	}
+
+	#[cfg(any(target_endian = "little", feature = "portable-format"))]
+	#[test]
+	fn serialize_header_in_little_endian() {
+		let header_repr = GenericHeaderRepr::from(GenericHeader {
+			file_type: FileType::Segment,
+			content_offset: 0x1234,
+			version: 1,
+			features: FeatureFlags::NONE,
+		});
+		assert_eq!(
+			header_repr.as_bytes(),
+			[b'A', b'C', b'R', b'N', 1, 1, 0x34, 0x12, 1, 0, 0, 0, 0]
+		);
+	}
+
+	#[test]
+	fn accept_unknown_optional_features() {
+		let header = GenericHeader {
+			file_type: FileType::Segment,
+			content_offset: 69,
+			version: 1,
+			features: FeatureFlags {
+				required: 0b01,
+				optional: 0b110,
+			},
+		};
+		header
+			.check_features(FeatureFlags {
+				required: 0b01,
+				optional: 0b010,
+			})
+			.unwrap();
+	}
+
+	#[test]
+	fn reject_unknown_required_features() {
+		let header = GenericHeader {
+			file_type: FileType::Segment,
+			content_offset: 69,
+			version: 1,
+			features: FeatureFlags {
+				required: 0b11,
+				optional: 0,
+			},
+		};
+		let err = header.check_features(FeatureFlags::NONE).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"The Segment file requires unsupported format features 0x0003"
+		);
+	}
+
+	#[test]
+	fn read_legacy_header() {
+		let header = GenericHeader {
+			file_type: FileType::Wal,
+			content_offset: LegacyHeaderRepr::SIZE as u16,
+			version: 1,
+			features: FeatureFlags::NONE,
+		};
+		let mut bytes = LegacyHeaderRepr::from(header.clone()).as_bytes().to_vec();
+		// The first item of the file
+		bytes.extend([0xff; 8]);
+
+		let read = GenericHeader::read(bytes.as_slice()).unwrap();
+		assert_eq!(read, header);
+		assert_eq!(read.size(), LegacyHeaderRepr::SIZE);
+	}
+
+	#[test]
+	fn read_header_with_features() {
+		let header = GenericHeader {
+			file_type: FileType::Wal,
+			content_offset: GenericHeaderRepr::SIZE as u16,
+			version: 2,
+			features: FeatureFlags {
+				required: 0b01,
+				optional: 0b10,
+			},
+		};
+		let bytes = GenericHeaderRepr::from(header.clone()).as_bytes().to_vec();
+
+		let read = GenericHeader::read(bytes.as_slice()).unwrap();
+		assert_eq!(read, header);
+		assert_eq!(read.size(), GenericHeaderRepr::SIZE);
+	}
 }
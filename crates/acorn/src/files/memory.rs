@@ -0,0 +1,179 @@
+use std::{
+	io::{self, Read, Seek, SeekFrom, Write},
+	sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use super::{
+	utils::{SetLen, SyncData},
+	vfs::VfsFile,
+};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryFile {
+	data: Arc<Mutex<Vec<u8>>>,
+	position: u64,
+}
+
+impl MemoryFile {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn from_bytes(data: Vec<u8>) -> Self {
+		Self {
+			data: Arc::new(Mutex::new(data)),
+			position: 0,
+		}
+	}
+
+	/// Creates a new handle to the same file contents, with its own position.
+	pub fn share(&self) -> Self {
+		Self {
+			data: Arc::clone(&self.data),
+			position: 0,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.data.lock().len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	fn position(&self) -> usize {
+		usize::try_from(self.position).expect("Memory file position exceeded usize::MAX")
+	}
+}
+
+impl Read for MemoryFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let data = self.data.lock();
+		let start = usize::min(self.position(), data.len());
+		let num_read = usize::min(buf.len(), data.len() - start);
+		buf[..num_read].copy_from_slice(&data[start..start + num_read]);
+		self.position += num_read as u64;
+		Ok(num_read)
+	}
+}
+
+impl Write for MemoryFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let mut data = self.data.lock();
+		let start = self.position();
+		let end = start + buf.len();
+		if data.len() < end {
+			data.resize(end, 0);
+		}
+		data[start..end].copy_from_slice(buf);
+		self.position = end as u64;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Seek for MemoryFile {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let (base, offset) = match pos {
+			SeekFrom::Start(position) => {
+				self.position = position;
+				return Ok(position);
+			}
+			SeekFrom::End(offset) => (self.len() as u64, offset),
+			SeekFrom::Current(offset) => (self.position, offset),
+		};
+		let Some(position) = base.checked_add_signed(offset) else {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"Tried to seek to a negative position",
+			));
+		};
+		self.position = position;
+		Ok(position)
+	}
+}
+
+impl SyncData for MemoryFile {
+	fn sync_data(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl SetLen for MemoryFile {
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		let Ok(len) = usize::try_from(len) else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		self.data.lock().truncate(len);
+		Ok(())
+	}
+}
+
+impl VfsFile for MemoryFile {
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		let data = self.data.lock();
+		let start = usize::try_from(offset).unwrap_or(usize::MAX);
+		let Some(src) = data.get(start..start.saturating_add(buf.len())) else {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		};
+		buf.copy_from_slice(src);
+		Ok(())
+	}
+
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+		let Ok(start) = usize::try_from(offset) else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		let mut data = self.data.lock();
+		let end = start + buf.len();
+		if data.len() < end {
+			data.resize(end, 0);
+		}
+		data[start..end].copy_from_slice(buf);
+		Ok(())
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		Ok(self.data.lock().len() as u64)
+	}
+
+	fn set_len(&self, len: u64) -> io::Result<()> {
+		let Ok(len) = usize::try_from(len) else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		self.data.lock().resize(len, 0);
+		Ok(())
+	}
+
+	fn sync_data(&self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn write_seek_and_read() {
+		// given
+		let mut file = MemoryFile::new();
+		file.write_all(&[1, 2, 3, 4]).unwrap();
+
+		// when
+		file.seek(SeekFrom::Current(-3)).unwrap();
+		file.write_all(&[5, 6, 7, 8]).unwrap();
+		let mut shared = file.share();
+		let mut received = Vec::new();
+		shared.read_to_end(&mut received).unwrap();
+
+		// then
+		assert_eq!(received, [1, 5, 6, 7, 8]);
+	}
+}
@@ -2,29 +2,44 @@ use std::{
 	convert::Infallible,
 	ffi::OsString,
 	fmt,
-	fs::{self, ReadDir},
+	fs::{self, File, OpenOptions, ReadDir, TryLockError},
 	io,
 	num::{NonZero, NonZeroU16, NonZeroU64},
-	path::PathBuf,
+	path::{Path, PathBuf},
+	sync::Arc,
 };
 
 use thiserror::Error;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 #[cfg(test)]
 use mockall::automock;
 
 use self::{
+	crypto::{Cipher, EncryptionKey, EncryptionRepr, FileCipher},
 	generic::FileType,
-	segment::{SegmentFile, SegmentFileApi},
+	retry::{FaultCounts, Retrier, RetryPolicy},
+	segment::{ResizedSegmentFile, SegmentFile, SegmentFileApi, SegmentPageSizes},
+	sync::SyncPrimitive,
+	upgrade::FormatUpgrades,
 	wal::{WalFile, WalFileApi},
 };
+use crate::repr::U64;
 
 #[cfg(test)]
 use self::{segment::MockSegmentFileApi, wal::MockWalFileApi};
 
+pub(crate) mod crypto;
+pub(crate) mod dump;
 pub(super) mod generic;
+pub(crate) mod memory;
+pub(crate) mod overlay;
+pub(crate) mod retry;
 pub(crate) mod segment;
+pub(crate) mod sync;
+pub(crate) mod upgrade;
 pub(super) mod utils;
+pub(crate) mod vfs;
 pub(crate) mod wal;
 
 #[derive(Debug, Error)]
@@ -44,9 +59,15 @@ pub(crate) enum FileError {
 	#[error("Incompatible version of {0:?} file: {1}")]
 	IncompatibleVersion(FileType, u8),
 
+	#[error("The {0:?} file requires unsupported format features {1:#06x}")]
+	UnsupportedFeatures(FileType, u16),
+
 	#[error("Incompatible page version: {0}")]
 	IncompatiblePageVersion(u8),
 
+	#[error("A segment was created with a page size of {found} bytes, but pages of {expected} bytes were expected; segments with smaller pages have to be opened with `DatabaseBuilder::segment_page_size`, or can be migrated with `DatabaseBuilder::migrate_page_size`")]
+	PageSizeMismatch { found: usize, expected: usize },
+
 	#[error("Unexpected end of file")]
 	UnexpectedEof,
 
@@ -56,6 +77,24 @@ pub(crate) enum FileError {
 	#[error("Unexpected file in database folder: {}", _0.display())]
 	UnexpectedFile(OsString),
 
+	#[error("The database is encrypted, but no encryption key was provided")]
+	MissingKey,
+
+	#[error("The database is encrypted with a different key")]
+	WrongKey,
+
+	#[error("The database was created without encryption, so it can't be opened with a key")]
+	NotEncrypted,
+
+	#[error("The database is locked by another process that opened it")]
+	Locked,
+
+	#[error("A page was written back by another process since the database was opened, so it has to be refreshed")]
+	Stale,
+
+	#[error("There is no database in the folder at {}", _0.display())]
+	NoDatabase(PathBuf),
+
 	#[error(transparent)]
 	Io(io::Error),
 }
@@ -81,8 +120,8 @@ pub(crate) struct TransactionState {
 	pub last_index: WalIndex,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub(crate) struct PageId {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageId {
 	pub segment_num: u32,
 	pub page_num: NonZeroU16,
 }
@@ -118,21 +157,401 @@ pub(crate) struct WalIndex {
 }
 
 impl WalIndex {
+	/// The index of writes that aren't logged to the WAL, which is before
+	/// every logged write. Writing back a page with this index never waits for
+	/// the WAL, and recovery doesn't consider the page modified by it.
+	pub const UNLOGGED: Self = Self {
+		generation: 0,
+		offset: NonZeroU64::MIN,
+	};
+
 	pub fn new(generation: u64, offset: NonZeroU64) -> Self {
 		Self { generation, offset }
 	}
 }
 
+#[derive(Debug, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct BackupPointRepr {
+	generation: U64,
+	offset: U64,
+}
+
+/// How writes to the segment files are made to survive a crash of the
+/// operating system or a power loss. Commits are always durable once the WAL
+/// is synced, but a checkpoint deletes the WAL once the pages it covers are
+/// written to the segment files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Durability {
+	/// The operating system persists segment writes eventually, so a
+	/// checkpoint shortly before a power loss can lose committed pages.
+	#[default]
+	Os,
+
+	/// Segment files are synced before a checkpoint deletes the WAL, and new
+	/// segment and WAL files are synced together with their folder.
+	Sync,
+
+	/// Like [`Durability::Sync`], but pages are written to the segment files
+	/// directly, bypassing the page cache of the operating system. This is
+	/// only supported on Linux; elsewhere, it behaves like
+	/// [`Durability::Sync`].
+	Direct,
+}
+
+impl Durability {
+	pub(crate) fn syncs(self) -> bool {
+		self != Self::Os
+	}
+}
+
+/// How the folder of a database is locked against other processes while it
+/// is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FolderLock {
+	/// No other process can lock the folder.
+	Exclusive,
+	/// Other processes can lock the folder as shared as well, but not
+	/// exclusively.
+	Shared,
+	/// Like [`FolderLock::Shared`], but no other process can take a writer
+	/// lock at the same time, so that a single writer can share the folder
+	/// with readers.
+	Writer,
+}
+
+impl FolderLock {
+	/// Locks the folder at `path` against other processes, until the
+	/// returned guard is dropped. The lock is taken on a `lock` file in the
+	/// folder, and for a writer on a `writer_lock` file as well; the folder is
+	/// created if it doesn't exist yet.
+	pub fn acquire(self, path: &Path) -> Result<FolderLockGuard, FileError> {
+		let lock = Self::lock_file(path, DatabaseFolder::LOCK_FILE_NAME, self)?;
+		let writer = match self {
+			Self::Writer => Some(Self::lock_file(
+				path,
+				DatabaseFolder::WRITER_LOCK_FILE_NAME,
+				Self::Exclusive,
+			)?),
+			Self::Exclusive | Self::Shared => None,
+		};
+		Ok(FolderLockGuard {
+			_lock: lock,
+			_writer: writer,
+		})
+	}
+
+	fn lock_file(path: &Path, name: &str, lock: Self) -> Result<File, FileError> {
+		let lock_path = path.join(name);
+		// A shared lock doesn't need to write to the folder, unless no process
+		// locked it before.
+		let file = match lock {
+			Self::Shared if lock_path.exists() => File::open(&lock_path)?,
+			_ => {
+				fs::create_dir_all(path)?;
+				OpenOptions::new()
+					.create(true)
+					.truncate(false)
+					.write(true)
+					.open(&lock_path)?
+			}
+		};
+		let result = match lock {
+			Self::Exclusive => file.try_lock(),
+			Self::Shared | Self::Writer => file.try_lock_shared(),
+		};
+		match result {
+			Ok(()) => Ok(file),
+			Err(TryLockError::WouldBlock) => Err(FileError::Locked),
+			Err(TryLockError::Error(err)) => Err(err.into()),
+		}
+	}
+}
+
+/// A lock on the folder of a database, which is released when the guard is
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct FolderLockGuard {
+	_lock: File,
+	_writer: Option<File>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FaultStats {
+	pub segments: FaultCounts,
+	pub wal: FaultCounts,
+}
+
 pub(crate) struct DatabaseFolder {
 	path: PathBuf,
+	segment_retrier: Arc<Retrier>,
+	wal_retrier: Arc<Retrier>,
+	durability: Durability,
+	wal_path: PathBuf,
+	wal_archive: Option<PathBuf>,
+	wal_compression: bool,
+	segment_page_sizes: SegmentPageSizes,
+	cipher: Option<Arc<Cipher>>,
 }
 
 impl DatabaseFolder {
-	const SEGMENTS_DIR_NAME: &'static str = "segments";
-	const WAL_DIR_NAME: &'static str = "wal";
+	pub(crate) const SEGMENTS_DIR_NAME: &'static str = "segments";
+	pub(crate) const WAL_DIR_NAME: &'static str = "wal";
+	const BACKUP_POINT_FILE_NAME: &'static str = "backup_point";
+	const ENCRYPTION_FILE_NAME: &'static str = "encryption";
+	const FORMAT_BACKUP_DIR_NAME: &'static str = "format_backup";
+	const OPEN_MARKER_FILE_NAME: &'static str = "open";
+	pub(crate) const LOCK_FILE_NAME: &'static str = "lock";
+	pub(crate) const WRITER_LOCK_FILE_NAME: &'static str = "writer_lock";
 
 	pub fn open(path: PathBuf) -> Self {
-		Self { path }
+		Self::open_with_retry_policy(path, RetryPolicy::default())
+	}
+
+	pub fn open_with_retry_policy(path: PathBuf, policy: RetryPolicy) -> Self {
+		Self {
+			wal_path: path.join(Self::WAL_DIR_NAME),
+			path,
+			segment_retrier: Arc::new(Retrier::new(policy.clone())),
+			wal_retrier: Arc::new(Retrier::new(policy)),
+			durability: Durability::default(),
+			wal_archive: None,
+			wal_compression: false,
+			segment_page_sizes: SegmentPageSizes::default(),
+			cipher: None,
+		}
+	}
+
+	pub fn with_durability(mut self, durability: Durability) -> Self {
+		self.durability = durability;
+		self
+	}
+
+	/// Stores the WAL files in the folder at `wal_path` instead of the `wal`
+	/// folder of the database, if it is given.
+	pub fn with_wal_path(mut self, wal_path: Option<PathBuf>) -> Self {
+		if let Some(wal_path) = wal_path {
+			self.wal_path = wal_path;
+		}
+		self
+	}
+
+	/// Moves WAL files to the archive folder instead of deleting them once
+	/// they're no longer needed for recovery.
+	pub fn with_wal_archive(mut self, wal_archive: Option<PathBuf>) -> Self {
+		self.wal_archive = wal_archive;
+		self
+	}
+
+	/// Compresses large items of WAL files that are created from now on.
+	pub fn with_wal_compression(mut self, compress: bool) -> Self {
+		self.wal_compression = compress;
+		self
+	}
+
+	/// Creates segments with the given page sizes, and expects existing
+	/// segments to have been created with them.
+	pub fn with_segment_page_sizes(mut self, page_sizes: SegmentPageSizes) -> Self {
+		self.segment_page_sizes = page_sizes;
+		self
+	}
+
+	/// Encrypts the files of the database with `key`. A new database is
+	/// encrypted if a key is given; an existing one has to be opened with the
+	/// key it was created with.
+	pub fn with_encryption(mut self, key: Option<&EncryptionKey>) -> Result<Self, FileError> {
+		self.cipher = read_cipher(&self.path, key)?;
+		let Some(key) = key else {
+			return Ok(self);
+		};
+		if self.cipher.is_some() {
+			return Ok(self);
+		}
+		let is_new = !self.path.join(Self::SEGMENTS_DIR_NAME).exists() && !self.wal_path.exists();
+		if !is_new {
+			return Err(FileError::NotEncrypted);
+		}
+
+		let cipher = Cipher::generate(key).map_err(io::Error::from)?;
+		let repr = EncryptionRepr {
+			key_check: cipher.key_check(),
+			salt: cipher.salt(),
+			mode: EncryptionRepr::MODE_XTS.into(),
+		};
+		fs::create_dir_all(&self.path)?;
+		let path = self.path.join(Self::ENCRYPTION_FILE_NAME);
+		let temp_path = path.with_extension("tmp");
+		fs::write(&temp_path, repr.as_bytes())?;
+		if self.durability.syncs() {
+			File::open(&temp_path)?.sync_all()?;
+		}
+		fs::rename(temp_path, path)?;
+		self.sync_dir(self.path.clone())?;
+		self.cipher = Some(Arc::new(cipher));
+		Ok(self)
+	}
+
+	pub fn cipher(&self) -> Option<&Arc<Cipher>> {
+		self.cipher.as_ref()
+	}
+
+	/// The WAL index of the database that the folder holds a backup of, from
+	/// which on writes are not included in the backup, or `None` if the folder
+	/// doesn't hold a backup.
+	pub fn backup_point(&self) -> Result<Option<WalIndex>, FileError> {
+		let bytes = match fs::read(self.path.join(Self::BACKUP_POINT_FILE_NAME)) {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			result => result?,
+		};
+		let Some(repr) = BackupPointRepr::read_from(bytes.as_slice()) else {
+			return Err(FileError::Corrupted(
+				"The backup point has an unexpected size".to_string(),
+			));
+		};
+		let Some(offset) = NonZeroU64::new(repr.offset.get()) else {
+			return Err(FileError::Corrupted("The backup point is zero".to_string()));
+		};
+		Ok(Some(WalIndex::new(repr.generation.get(), offset)))
+	}
+
+	/// Replaces the backup point of the folder. The old point is kept if this
+	/// fails.
+	pub fn set_backup_point(&self, wal_index: WalIndex) -> Result<(), FileError> {
+		let path = self.path.join(Self::BACKUP_POINT_FILE_NAME);
+		let temp_path = path.with_extension("tmp");
+		let repr = BackupPointRepr {
+			generation: wal_index.generation.into(),
+			offset: wal_index.offset.get().into(),
+		};
+		fs::write(&temp_path, repr.as_bytes())?;
+		if self.durability.syncs() {
+			File::open(&temp_path)?.sync_all()?;
+		}
+		fs::rename(temp_path, path)?;
+		self.sync_dir(self.path.clone())
+	}
+
+	/// Whether the database was closed cleanly the last time it was opened
+	/// for writing, i.e. the marker of [`Self::mark_open`] was removed again.
+	/// Databases that were last opened before the marker existed count as
+	/// closed cleanly.
+	pub fn was_closed_cleanly(&self) -> bool {
+		!self.path.join(Self::OPEN_MARKER_FILE_NAME).exists()
+	}
+
+	/// Marks the database as open for writing, until [`Self::mark_closed`]
+	/// removes the marker again.
+	pub fn mark_open(&self) -> Result<(), FileError> {
+		fs::create_dir_all(&self.path)?;
+		let path = self.path.join(Self::OPEN_MARKER_FILE_NAME);
+		let file = File::create(path)?;
+		if self.durability.syncs() {
+			file.sync_all()?;
+		}
+		self.sync_dir(self.path.clone())
+	}
+
+	/// Removes the marker of [`Self::mark_open`] once the database was
+	/// closed cleanly.
+	pub fn mark_closed(&self) -> Result<(), FileError> {
+		match fs::remove_file(self.path.join(Self::OPEN_MARKER_FILE_NAME)) {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+			result => result?,
+		}
+		self.sync_dir(self.path.clone())
+	}
+
+	pub fn fault_stats(&self) -> FaultStats {
+		FaultStats {
+			segments: self.segment_retrier.counts(),
+			wal: self.wal_retrier.counts(),
+		}
+	}
+
+	/// Whether the segments of the database were created with a smaller page
+	/// size than the one they are configured with.
+	pub fn needs_page_size_migration(&self) -> Result<bool, FileError> {
+		let Some(&segment_num) = self.existing_segment_nums()?.first() else {
+			return Ok(false);
+		};
+		let segment = self.open_resized_segment(segment_num)?;
+		Ok(segment.page_size() != self.segment_page_sizes.page_size(segment_num))
+	}
+
+	/// Opens a segment file for reading, whatever page size it was created
+	/// with.
+	pub fn open_resized_segment(&self, segment_num: u32) -> Result<ResizedSegmentFile, FileError> {
+		let path = self
+			.path
+			.join(Self::SEGMENTS_DIR_NAME)
+			.join(segment_num.to_string());
+		let cipher = self
+			.cipher
+			.as_ref()
+			.map(|cipher| FileCipher::segment(Arc::clone(cipher), segment_num));
+		ResizedSegmentFile::open_file(path, cipher)
+	}
+
+	/// Upgrades the segment and WAL files that were written in an older
+	/// format, and returns how many were upgraded. The headers they had
+	/// before each step are kept in the `format_backup` folder of the
+	/// database.
+	pub fn upgrade_format(&self, upgrades: &FormatUpgrades) -> Result<usize, FileError> {
+		let backup_dir = self.path.join(Self::FORMAT_BACKUP_DIR_NAME);
+		let mut num_upgraded = 0;
+		for segment_num in self.existing_segment_nums()? {
+			let cipher = self
+				.cipher
+				.as_ref()
+				.map(|cipher| FileCipher::segment(Arc::clone(cipher), segment_num));
+			let upgraded = upgrades.upgrade_file(
+				&self.segment_file_path(segment_num)?,
+				FileType::Segment,
+				segment::FORMAT_VERSION,
+				cipher.as_ref(),
+				&backup_dir.join(Self::SEGMENTS_DIR_NAME),
+			)?;
+			num_upgraded += usize::from(upgraded);
+		}
+
+		if !self.wal_path.exists() {
+			return Ok(num_upgraded);
+		}
+		for entry in fs::read_dir(&self.wal_path)? {
+			let entry = entry?;
+			// Unexpected files make opening the WAL fail later on.
+			let Ok(generation) = entry.file_name().to_string_lossy().parse() else {
+				continue;
+			};
+			if !entry.path().is_file() {
+				continue;
+			}
+			let cipher = self
+				.cipher
+				.as_ref()
+				.map(|cipher| FileCipher::wal(Arc::clone(cipher), generation));
+			// WAL files of older versions are read as they are, and replaced by
+			// new generations over time, so only those that can't be read are
+			// upgraded.
+			let upgraded = upgrades.upgrade_file(
+				&entry.path(),
+				FileType::Wal,
+				wal::MIN_FORMAT_VERSION,
+				cipher.as_ref(),
+				&backup_dir.join(Self::WAL_DIR_NAME),
+			)?;
+			num_upgraded += usize::from(upgraded);
+		}
+		Ok(num_upgraded)
+	}
+
+	/// Like [`DatabaseFolderApi::segment_nums`], but doesn't create the
+	/// segments directory if it doesn't exist.
+	pub fn existing_segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		if !self.path.join(Self::SEGMENTS_DIR_NAME).exists() {
+			return Ok(Vec::new());
+		}
+		self.segment_nums()
 	}
 
 	fn segments_dir(&self) -> Result<PathBuf, FileError> {
@@ -145,15 +564,69 @@ impl DatabaseFolder {
 		self.segments_dir().map(|p| p.join(segment_num.to_string()))
 	}
 
+	/// Creates a segment file, such that a crash leaves either no file or a
+	/// complete one behind, which is durably stored whatever the durability
+	/// mode.
+	///
+	/// Pages of a segment are only logged to the WAL after they were read
+	/// from its file, and the allocator's metadata only reaches its segment
+	/// at a checkpoint. So after a crash, a segment file either doesn't exist
+	/// and nothing refers to it, or it exists and is merely unused so far, or
+	/// the WAL redoes the changes to it. A segment that the WAL refers to but
+	/// that is missing was therefore lost, rather than not yet created.
+	fn create_segment_file(
+		&self,
+		path: &Path,
+		cipher: Option<FileCipher>,
+		page_size: usize,
+	) -> Result<SegmentFile, FileError> {
+		// Leftovers of an interrupted creation are overwritten.
+		let temp_path = path.with_extension("tmp");
+		let file = SegmentFile::create_file_with_cipher(&temp_path, cipher, page_size)?;
+		file.sync()?;
+		fs::rename(temp_path, path)?;
+		File::open(self.segments_dir()?)?.sync_all()?;
+		Ok(file)
+	}
+
+	/// The folder that the WAL files are stored in.
+	pub fn wal_path(&self) -> &Path {
+		&self.wal_path
+	}
+
+	/// Whether the folder holds a database, which always has at least one
+	/// WAL file. Unlike [`DatabaseFolderApi::iter_wal_files`], this doesn't
+	/// create any folders.
+	pub fn has_database(&self) -> Result<bool, FileError> {
+		let entries = match fs::read_dir(&self.wal_path) {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+			result => result?,
+		};
+		for entry in entries {
+			if entry?.path().is_file() {
+				return Ok(true);
+			}
+		}
+		Ok(false)
+	}
+
 	fn wal_dir(&self) -> Result<PathBuf, FileError> {
-		let path = self.path.join(Self::WAL_DIR_NAME);
-		fs::create_dir_all(&path)?;
-		Ok(path)
+		fs::create_dir_all(&self.wal_path)?;
+		Ok(self.wal_path.clone())
 	}
 
 	fn wal_file_path(&self, generation: u64) -> Result<PathBuf, FileError> {
 		self.wal_dir().map(|p| p.join(generation.to_string()))
 	}
+
+	/// Makes the creation or deletion of files in the directory durable, if
+	/// the durability mode requires it.
+	fn sync_dir(&self, path: PathBuf) -> Result<(), FileError> {
+		if self.durability.syncs() {
+			File::open(path)?.sync_all()?;
+		}
+		Ok(())
+	}
 }
 
 #[cfg_attr(test, automock(
@@ -167,10 +640,19 @@ pub(crate) trait DatabaseFolderApi {
 	type IterWalFiles: Iterator<Item = Result<(u64, Self::WalFile), FileError>>;
 
 	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError>;
+	/// The numbers of the segments that have a file, in ascending order.
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError>;
 	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError>;
 	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError>;
 	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError>;
 	fn clear_wal_files(&self) -> Result<(), FileError>;
+
+	/// The primitive that makes writes to the files of the folder durable, or
+	/// `None` if they aren't stored in the local file system. The WAL has to
+	/// be created already.
+	fn sync_primitive(&self) -> Result<Option<SyncPrimitive>, FileError> {
+		Ok(None)
+	}
 }
 
 impl DatabaseFolderApi for DatabaseFolder {
@@ -180,26 +662,77 @@ impl DatabaseFolderApi for DatabaseFolder {
 
 	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError> {
 		let path = self.segment_file_path(segment_num)?;
-		if path.exists() {
-			SegmentFile::open_file(path)
+		let cipher = self
+			.cipher
+			.as_ref()
+			.map(|cipher| FileCipher::segment(Arc::clone(cipher), segment_num));
+		let page_size = self.segment_page_sizes.page_size(segment_num);
+		let file = if path.exists() {
+			SegmentFile::open_file_with_cipher(&path, cipher, page_size)?
 		} else {
-			SegmentFile::create_file(path)
+			self.create_segment_file(&path, cipher, page_size)?
+		};
+		let file = if self.durability == Durability::Direct {
+			file.with_direct_io(path)?
+		} else {
+			file
+		};
+		Ok(file.with_retrier(Arc::clone(&self.segment_retrier)))
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		let mut segment_nums = Vec::new();
+		for entry in fs::read_dir(self.segments_dir()?)? {
+			let entry = entry?;
+			if !entry.path().is_file() {
+				continue;
+			}
+			if let Ok(segment_num) = entry.file_name().to_string_lossy().parse() {
+				segment_nums.push(segment_num);
+			}
 		}
+		segment_nums.sort_unstable();
+		Ok(segment_nums)
 	}
 
 	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
 		let path = self.wal_file_path(generation)?;
-		if path.exists() {
-			WalFile::open_file(path)
+		let cipher = self
+			.cipher
+			.as_ref()
+			.map(|cipher| FileCipher::wal(Arc::clone(cipher), generation));
+		let file = if path.exists() {
+			WalFile::open_file_with_cipher(path, cipher)?
 		} else {
-			WalFile::create_file(path)
-		}
+			let mut file = WalFile::create_file_with_options(path, cipher, self.wal_compression)?;
+			if self.durability.syncs() {
+				file.sync()?;
+				self.sync_dir(self.wal_dir()?)?;
+			}
+			file
+		};
+		Ok(file.with_retrier(Arc::clone(&self.wal_retrier)))
 	}
 
 	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError> {
 		let path = self.wal_file_path(generation)?;
+		if let Some(archive) = &self.wal_archive {
+			fs::create_dir_all(archive)?;
+			// The archived files can only be decrypted with the salt of the
+			// database.
+			let encryption_path = archive.join(Self::ENCRYPTION_FILE_NAME);
+			if self.cipher.is_some() && !encryption_path.exists() {
+				fs::copy(self.path.join(Self::ENCRYPTION_FILE_NAME), encryption_path)?;
+			}
+			let archived_path = archive.join(generation.to_string());
+			fs::copy(&path, &archived_path)?;
+			if self.durability.syncs() {
+				File::open(archived_path)?.sync_all()?;
+				self.sync_dir(archive.clone())?;
+			}
+		}
 		fs::remove_file(path)?;
-		Ok(())
+		self.sync_dir(self.wal_dir()?)
 	}
 
 	fn clear_wal_files(&self) -> Result<(), FileError> {
@@ -208,30 +741,148 @@ impl DatabaseFolderApi for DatabaseFolder {
 	}
 
 	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
-		Ok(IterWalFiles(fs::read_dir(self.wal_dir()?)?))
+		Ok(IterWalFiles {
+			entries: fs::read_dir(self.wal_dir()?)?,
+			retrier: Arc::clone(&self.wal_retrier),
+			cipher: self.cipher.clone(),
+		})
+	}
+
+	fn sync_primitive(&self) -> Result<Option<SyncPrimitive>, FileError> {
+		// The WAL files are synced on every commit anyway, so syncing one
+		// doesn't write anything, unlike creating a file just for that.
+		if !self.wal_path.exists() {
+			return Ok(None);
+		}
+		for entry in fs::read_dir(&self.wal_path)? {
+			let entry = entry?;
+			let is_wal_file = entry.path().is_file()
+				&& entry.file_name().to_string_lossy().parse::<u64>().is_ok();
+			if is_wal_file {
+				return Ok(Some(sync::probe(&entry.path())?));
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Reads how the database in the folder at `path` is encrypted, and checks
+/// that `key` is the key it was encrypted with. Returns `None` if the
+/// database isn't encrypted.
+pub(crate) fn read_cipher(
+	path: &Path,
+	key: Option<&EncryptionKey>,
+) -> Result<Option<Arc<Cipher>>, FileError> {
+	let bytes = match fs::read(path.join(DatabaseFolder::ENCRYPTION_FILE_NAME)) {
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+		result => result?,
+	};
+	let Some(repr) = EncryptionRepr::read_from(bytes.as_slice()) else {
+		return Err(FileError::Corrupted(
+			"The encryption info has an unexpected size".to_string(),
+		));
+	};
+	if repr.mode.get() != EncryptionRepr::MODE_XTS {
+		return Err(FileError::Corrupted(format!(
+			"The database is encrypted with unsupported mode {}",
+			repr.mode
+		)));
+	}
+	let Some(key) = key else {
+		return Err(FileError::MissingKey);
+	};
+	let cipher = Cipher::new(key, repr.salt);
+	if cipher.key_check() != repr.key_check {
+		return Err(FileError::WrongKey);
 	}
+	Ok(Some(Arc::new(cipher)))
 }
 
-pub(crate) struct IterWalFiles(ReadDir);
+/// Swaps the directories at `a` and `b`, atomically on Linux. On other
+/// platforms, a crash in between the renames can leave `a` missing, with its
+/// content at a temporary path next to it.
+pub(crate) fn exchange_dirs(a: &Path, b: &Path) -> Result<(), FileError> {
+	#[cfg(target_os = "linux")]
+	{
+		use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+		let to_c_string = |path: &Path| {
+			CString::new(path.as_os_str().as_bytes())
+				.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+		};
+		let (a, b) = (to_c_string(a)?, to_c_string(b)?);
+		// Safety: both paths are valid, nul-terminated strings that outlive the
+		// call.
+		let result = unsafe {
+			libc::renameat2(
+				libc::AT_FDCWD,
+				a.as_ptr(),
+				libc::AT_FDCWD,
+				b.as_ptr(),
+				libc::RENAME_EXCHANGE,
+			)
+		};
+		if result != 0 {
+			return Err(io::Error::last_os_error().into());
+		}
+		Ok(())
+	}
+	#[cfg(not(target_os = "linux"))]
+	{
+		let temp_path = a.with_extension("exchange");
+		fs::rename(a, &temp_path)?;
+		fs::rename(b, a)?;
+		fs::rename(temp_path, b)?;
+		Ok(())
+	}
+}
+
+/// The paths of the WAL files in an archive folder, ordered by their
+/// generation.
+pub(crate) fn list_wal_archive(path: &Path) -> Result<Vec<(u64, PathBuf)>, FileError> {
+	let mut files = Vec::new();
+	for entry in fs::read_dir(path)? {
+		let entry = entry?;
+		if !entry.path().is_file() || entry.file_name() == DatabaseFolder::ENCRYPTION_FILE_NAME {
+			continue;
+		}
+		let Ok(generation) = entry.file_name().to_string_lossy().parse() else {
+			return Err(FileError::UnexpectedFile(entry.file_name()));
+		};
+		files.push((generation, entry.path()));
+	}
+	files.sort_unstable();
+	Ok(files)
+}
+
+pub(crate) struct IterWalFiles {
+	entries: ReadDir,
+	retrier: Arc<Retrier>,
+	cipher: Option<Arc<Cipher>>,
+}
 
 impl Iterator for IterWalFiles {
 	type Item = Result<(u64, WalFile), FileError>;
 
 	fn next(&mut self) -> Option<Self::Item> {
-		for entry_result in &mut self.0 {
+		for entry_result in &mut self.entries {
 			let entry = match entry_result {
 				Ok(entry) => entry,
 				Err(error) => return Some(Err(error.into())),
 			};
 			if entry.path().is_file() {
-				let file = match WalFile::open_file(entry.path()) {
-					Ok(file) => file,
-					Err(error) => return Some(Err(error)),
-				};
 				let Ok(generation): Result<u64, _> = entry.file_name().to_string_lossy().parse()
 				else {
 					return Some(Err(FileError::UnexpectedFile(entry.file_name())));
 				};
+				let cipher = self
+					.cipher
+					.as_ref()
+					.map(|cipher| FileCipher::wal(Arc::clone(cipher), generation));
+				let file = match WalFile::open_file_with_cipher(entry.path(), cipher) {
+					Ok(file) => file.with_retrier(Arc::clone(&self.retrier)),
+					Err(error) => return Some(Err(error)),
+				};
 
 				return Some(Ok((generation, file)));
 			}
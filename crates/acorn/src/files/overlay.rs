@@ -0,0 +1,425 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	fs,
+	io::ErrorKind,
+	num::NonZeroU16,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::consts::SHARED_READ_ATTEMPTS;
+
+use super::{
+	memory::MemoryFile,
+	segment::{SegmentFile, SegmentFileApi, PAGE_BODY_SIZE},
+	wal::{WalFile, WalFileApi},
+	DatabaseFolder, DatabaseFolderApi, FileError, WalIndex,
+};
+
+/// A database folder that reads from an on-disk base, but never writes to it.
+///
+/// Pages written through the overlay, as well as all WAL files, are kept in
+/// memory and are lost once the overlay is dropped. The WAL files present in
+/// the base folder are copied into memory when the overlay is opened, so that
+/// recovery sees the same state as it would on the base folder itself.
+///
+/// Another process may keep writing to the base folder. Pages it wrote back
+/// after the WAL was copied fail to read with [`FileError::Stale`], since they
+/// don't match the copied WAL anymore, and [`OverlayFolder::base_changed`]
+/// tells whether its WAL moved on.
+///
+/// Without a base, the whole database is kept in memory, including a WAL
+/// that can be recovered from as long as the folder is alive.
+pub(crate) struct OverlayFolder {
+	base: Option<DatabaseFolder>,
+	segments: Mutex<HashMap<u32, Arc<OverlaySegment>>>,
+	wal_files: Mutex<BTreeMap<u64, MemoryFile>>,
+	/// The length of each WAL file of the base when it was copied.
+	base_wal_lens: BTreeMap<u64, u64>,
+	/// The end of the copied WAL; pages of the base written at or after it
+	/// are stale.
+	horizon: Option<WalIndex>,
+}
+
+impl OverlayFolder {
+	pub fn open(base_path: PathBuf) -> Result<Self, FileError> {
+		Self::new(DatabaseFolder::open(base_path))
+	}
+
+	/// Opens an overlay over the folder of `base`, including its WAL folder.
+	pub fn new(base: DatabaseFolder) -> Result<Self, FileError> {
+		let mut wal_files = BTreeMap::new();
+		let mut base_wal_lens = BTreeMap::new();
+
+		for (generation, path) in Self::base_wal_paths(&base)? {
+			// A checkpoint of a writer may delete the file in the meantime
+			let data = match fs::read(path) {
+				Ok(data) => data,
+				Err(err) if err.kind() == ErrorKind::NotFound => continue,
+				Err(err) => return Err(err.into()),
+			};
+			base_wal_lens.insert(generation, data.len() as u64);
+			wal_files.insert(generation, MemoryFile::from_bytes(data));
+		}
+		// An item the writer was appending when the WAL was copied is cut off,
+		// so the horizon is the end of the last complete one.
+		let horizon = match wal_files.last_key_value() {
+			Some((generation, file)) => Some(WalIndex::new(
+				*generation,
+				WalFile::open(file.share())?.next_offset(),
+			)),
+			None => None,
+		};
+
+		Ok(Self {
+			base: Some(base),
+			segments: Mutex::new(HashMap::new()),
+			wal_files: Mutex::new(wal_files),
+			base_wal_lens,
+			horizon,
+		})
+	}
+
+	pub fn in_memory() -> Self {
+		Self {
+			base: None,
+			segments: Mutex::new(HashMap::new()),
+			wal_files: Mutex::new(BTreeMap::new()),
+			base_wal_lens: BTreeMap::new(),
+			horizon: None,
+		}
+	}
+
+	/// The path of the base folder, if there is one.
+	pub fn base_path(&self) -> Option<&Path> {
+		self.base.as_ref().map(|base| base.path.as_path())
+	}
+
+	/// Whether the WAL of the base changed since it was copied, because
+	/// another process wrote to the database or took a checkpoint.
+	pub fn base_changed(&self) -> Result<bool, FileError> {
+		let Some(base) = &self.base else {
+			return Ok(false);
+		};
+		let mut base_wal_lens = BTreeMap::new();
+		for (generation, path) in Self::base_wal_paths(base)? {
+			match fs::metadata(path) {
+				Ok(metadata) => base_wal_lens.insert(generation, metadata.len()),
+				Err(err) if err.kind() == ErrorKind::NotFound => continue,
+				Err(err) => return Err(err.into()),
+			};
+		}
+		Ok(base_wal_lens != self.base_wal_lens)
+	}
+
+	fn base_wal_paths(base: &DatabaseFolder) -> Result<Vec<(u64, PathBuf)>, FileError> {
+		let entries = match fs::read_dir(base.wal_path()) {
+			Ok(entries) => Some(entries),
+			Err(err) if err.kind() == ErrorKind::NotFound => None,
+			Err(err) => return Err(err.into()),
+		};
+		let mut paths = Vec::new();
+		for entry in entries.into_iter().flatten() {
+			let entry = entry?;
+			if !entry.path().is_file() {
+				continue;
+			}
+			let Ok(generation): Result<u64, _> = entry.file_name().to_string_lossy().parse() else {
+				return Err(FileError::UnexpectedFile(entry.file_name()));
+			};
+			paths.push((generation, entry.path()));
+		}
+		Ok(paths)
+	}
+
+	fn base_segment_file(&self, segment_num: u32) -> Result<Option<SegmentFile>, FileError> {
+		let Some(base) = &self.base else {
+			return Ok(None);
+		};
+		let path = base
+			.path
+			.join(DatabaseFolder::SEGMENTS_DIR_NAME)
+			.join(segment_num.to_string());
+		if !path.exists() {
+			return Ok(None);
+		}
+		SegmentFile::open_file_read_only(path).map(Some)
+	}
+}
+
+impl DatabaseFolderApi for OverlayFolder {
+	type SegmentFile = OverlaySegmentFile;
+	type WalFile = WalFile<MemoryFile>;
+	type IterWalFiles = std::vec::IntoIter<Result<(u64, Self::WalFile), FileError>>;
+
+	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError> {
+		let mut segments = self.segments.lock();
+		if let Some(segment) = segments.get(&segment_num) {
+			return Ok(OverlaySegmentFile(Arc::clone(segment)));
+		}
+		let segment = Arc::new(OverlaySegment {
+			base: self.base_segment_file(segment_num)?,
+			horizon: self.horizon,
+			pages: RwLock::new(HashMap::new()),
+		});
+		segments.insert(segment_num, Arc::clone(&segment));
+		Ok(OverlaySegmentFile(segment))
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		let mut segment_nums: Vec<u32> = self.segments.lock().keys().copied().collect();
+		let Some(base) = &self.base else {
+			segment_nums.sort_unstable();
+			return Ok(segment_nums);
+		};
+		let segments_dir = base.path.join(DatabaseFolder::SEGMENTS_DIR_NAME);
+		let entries = match fs::read_dir(segments_dir) {
+			Ok(entries) => Some(entries),
+			Err(err) if err.kind() == ErrorKind::NotFound => None,
+			Err(err) => return Err(err.into()),
+		};
+		for entry in entries.into_iter().flatten() {
+			let entry = entry?;
+			if !entry.path().is_file() {
+				continue;
+			}
+			if let Ok(segment_num) = entry.file_name().to_string_lossy().parse() {
+				segment_nums.push(segment_num);
+			}
+		}
+		segment_nums.sort_unstable();
+		segment_nums.dedup();
+		Ok(segment_nums)
+	}
+
+	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
+		let mut wal_files = self.wal_files.lock();
+		if let Some(file) = wal_files.get(&generation) {
+			return WalFile::open(file.share());
+		}
+		let file = MemoryFile::new();
+		let wal_file = WalFile::create(file.share())?;
+		wal_files.insert(generation, file);
+		Ok(wal_file)
+	}
+
+	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError> {
+		self.wal_files.lock().remove(&generation);
+		Ok(())
+	}
+
+	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
+		let files: Vec<_> = self
+			.wal_files
+			.lock()
+			.iter()
+			.map(|(generation, file)| Ok((*generation, WalFile::open(file.share())?)))
+			.collect();
+		Ok(files.into_iter())
+	}
+
+	fn clear_wal_files(&self) -> Result<(), FileError> {
+		self.wal_files.lock().clear();
+		Ok(())
+	}
+}
+
+struct OverlayPage {
+	body: Box<[u8]>,
+	wal_index: WalIndex,
+}
+
+struct OverlaySegment {
+	base: Option<SegmentFile>,
+	horizon: Option<WalIndex>,
+	pages: RwLock<HashMap<NonZeroU16, OverlayPage>>,
+}
+
+impl OverlaySegment {
+	fn read_base(
+		&self,
+		base: &SegmentFile,
+		page_num: NonZeroU16,
+		buf: &mut [u8],
+	) -> Result<Option<WalIndex>, FileError> {
+		// A page another process is writing back at the same time reads as
+		// torn, until its write completes.
+		let mut attempt = 1;
+		let wal_index = loop {
+			match base.read(page_num, buf) {
+				Err(FileError::ChecksumMismatch) if attempt < SHARED_READ_ATTEMPTS => attempt += 1,
+				result => break result?,
+			}
+		};
+		if let (Some(wal_index), Some(horizon)) = (wal_index, self.horizon) {
+			if wal_index >= horizon {
+				return Err(FileError::Stale);
+			}
+		}
+		Ok(wal_index)
+	}
+}
+
+pub(crate) struct OverlaySegmentFile(Arc<OverlaySegment>);
+
+impl SegmentFileApi for OverlaySegmentFile {
+	fn read(&self, page_num: NonZeroU16, buf: &mut [u8]) -> Result<Option<WalIndex>, FileError> {
+		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+
+		if let Some(page) = self.0.pages.read().get(&page_num) {
+			buf.copy_from_slice(&page.body);
+			return Ok(Some(page.wal_index));
+		}
+		match &self.0.base {
+			Some(base) => self.0.read_base(base, page_num, buf),
+			None => {
+				buf.fill(0);
+				Ok(None)
+			}
+		}
+	}
+
+	fn read_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &mut [u8],
+	) -> Result<Vec<Option<WalIndex>>, FileError> {
+		let mut page_num = first_page;
+		let mut wal_indices = Vec::with_capacity(bufs.len() / PAGE_BODY_SIZE);
+		for buf in bufs.chunks_exact_mut(PAGE_BODY_SIZE) {
+			wal_indices.push(self.read(page_num, buf)?);
+			page_num = page_num.saturating_add(1);
+		}
+		Ok(wal_indices)
+	}
+
+	fn write(
+		&self,
+		page_num: NonZeroU16,
+		buf: &[u8],
+		wal_index: WalIndex,
+	) -> Result<(), FileError> {
+		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+
+		let page = OverlayPage {
+			body: buf.into(),
+			wal_index,
+		};
+		self.0.pages.write().insert(page_num, page);
+		Ok(())
+	}
+
+	fn write_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &[u8],
+		wal_indices: &[WalIndex],
+	) -> Result<(), FileError> {
+		debug_assert_eq!(bufs.len(), wal_indices.len() * PAGE_BODY_SIZE);
+
+		let mut page_num = first_page;
+		for (buf, wal_index) in bufs.chunks_exact(PAGE_BODY_SIZE).zip(wal_indices) {
+			self.write(page_num, buf, *wal_index)?;
+			page_num = page_num.saturating_add(1);
+		}
+		Ok(())
+	}
+
+	fn truncate(&self, num_pages: u16) -> Result<u64, FileError> {
+		// The base segment is never modified, so no disk space is reclaimed
+		self.0
+			.pages
+			.write()
+			.retain(|page_num, _| page_num.get() <= num_pages);
+		Ok(0)
+	}
+
+	fn sync(&self) -> Result<(), FileError> {
+		Ok(())
+	}
+
+	fn initialized_pages(&self) -> Result<Vec<(NonZeroU16, WalIndex)>, FileError> {
+		let overlay_pages = self.0.pages.read();
+		let mut pages: Vec<(NonZeroU16, WalIndex)> = match &self.0.base {
+			Some(base) => base
+				.initialized_pages()?
+				.into_iter()
+				.filter(|(page_num, _)| !overlay_pages.contains_key(page_num))
+				.collect(),
+			None => Vec::new(),
+		};
+		pages.extend(
+			overlay_pages
+				.iter()
+				.map(|(page_num, page)| (*page_num, page.wal_index)),
+		);
+		pages.sort_unstable();
+		Ok(pages)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{files::test_helpers::wal_index, utils::test_helpers::non_zero};
+
+	use super::*;
+
+	#[test]
+	fn read_through_to_base_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let base = DatabaseFolder::open(tempdir.path().into());
+		base.open_segment_file(0)
+			.unwrap()
+			.write(non_zero!(1), &[1; PAGE_BODY_SIZE], wal_index!(0, 10))
+			.unwrap();
+		let overlay = OverlayFolder::open(tempdir.path().into()).unwrap();
+
+		// when
+		overlay
+			.open_segment_file(0)
+			.unwrap()
+			.write(non_zero!(2), &[2; PAGE_BODY_SIZE], wal_index!(0, 20))
+			.unwrap();
+
+		// then
+		let segment = overlay.open_segment_file(0).unwrap();
+		let mut buf = [0; PAGE_BODY_SIZE];
+		assert_eq!(
+			segment.read(non_zero!(1), &mut buf).unwrap(),
+			Some(wal_index!(0, 10))
+		);
+		assert_eq!(buf, [1; PAGE_BODY_SIZE]);
+		assert_eq!(
+			segment.read(non_zero!(2), &mut buf).unwrap(),
+			Some(wal_index!(0, 20))
+		);
+		assert_eq!(buf, [2; PAGE_BODY_SIZE]);
+
+		let base_segment = base.open_segment_file(0).unwrap();
+		assert_eq!(base_segment.read(non_zero!(2), &mut buf).unwrap(), None);
+	}
+
+	#[test]
+	fn keep_wal_in_memory() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let base = DatabaseFolder::open(tempdir.path().into());
+		base.open_wal_file(0).unwrap();
+		let overlay = OverlayFolder::open(tempdir.path().into()).unwrap();
+
+		// when
+		overlay.open_wal_file(1).unwrap();
+
+		// then
+		let generations: Vec<u64> = overlay
+			.iter_wal_files()
+			.unwrap()
+			.map(|file| file.unwrap().0)
+			.collect();
+		assert_eq!(generations, [0, 1]);
+		assert!(!tempdir.path().join("wal").join("1").exists());
+	}
+}
@@ -0,0 +1,198 @@
+use std::{
+	io,
+	sync::atomic::{AtomicU64, Ordering},
+	thread,
+	time::Duration,
+};
+
+use log::warn;
+
+use crate::consts::{
+	DEFAULT_IO_RETRY_ATTEMPTS, DEFAULT_IO_RETRY_BACKOFF, DEFAULT_IO_RETRY_MAX_BACKOFF,
+};
+
+/// Determines how often, and for which errors, failed I/O operations are
+/// retried before the error is reported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+	/// The maximum number of attempts per operation, including the first one.
+	pub max_attempts: u32,
+
+	/// The delay before the first retry; it doubles with every further retry.
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+
+	pub retry_kinds: Vec<io::ErrorKind>,
+	pub retry_os_errors: Vec<i32>,
+}
+
+impl RetryPolicy {
+	pub fn never() -> Self {
+		Self {
+			max_attempts: 1,
+			..Default::default()
+		}
+	}
+
+	fn is_retryable(&self, error: &io::Error) -> bool {
+		self.retry_kinds.contains(&error.kind())
+			|| error
+				.raw_os_error()
+				.is_some_and(|code| self.retry_os_errors.contains(&code))
+	}
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: DEFAULT_IO_RETRY_ATTEMPTS,
+			initial_backoff: DEFAULT_IO_RETRY_BACKOFF,
+			max_backoff: DEFAULT_IO_RETRY_MAX_BACKOFF,
+			retry_kinds: vec![io::ErrorKind::TimedOut, io::ErrorKind::WouldBlock],
+			retry_os_errors: Vec::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct FaultCounts {
+	/// The number of times a failed operation was retried.
+	pub retried: u64,
+
+	/// The number of operations that failed, even after retrying.
+	pub failed: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Retrier {
+	policy: RetryPolicy,
+	retried: AtomicU64,
+	failed: AtomicU64,
+}
+
+impl Retrier {
+	pub fn new(policy: RetryPolicy) -> Self {
+		Self {
+			policy,
+			retried: AtomicU64::new(0),
+			failed: AtomicU64::new(0),
+		}
+	}
+
+	/// Runs `op`, retrying it according to the policy. `op` must be safe to
+	/// repeat after it failed.
+	pub fn run<T>(&self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+		let mut backoff = self.policy.initial_backoff;
+		let mut attempt: u32 = 1;
+		loop {
+			let error = match op() {
+				Ok(value) => return Ok(value),
+				Err(error) => error,
+			};
+			if attempt >= self.policy.max_attempts || !self.policy.is_retryable(&error) {
+				self.failed.fetch_add(1, Ordering::Relaxed);
+				return Err(error);
+			}
+
+			warn!("I/O operation failed (attempt {attempt}), retrying in {backoff:?}: {error}");
+			self.retried.fetch_add(1, Ordering::Relaxed);
+			thread::sleep(backoff);
+			backoff = Duration::min(backoff * 2, self.policy.max_backoff);
+			attempt += 1;
+		}
+	}
+
+	pub fn counts(&self) -> FaultCounts {
+		FaultCounts {
+			retried: self.retried.load(Ordering::Relaxed),
+			failed: self.failed.load(Ordering::Relaxed),
+		}
+	}
+}
+
+impl Default for Retrier {
+	fn default() -> Self {
+		Self::new(RetryPolicy::never())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_policy() -> RetryPolicy {
+		RetryPolicy {
+			initial_backoff: Duration::ZERO,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn retry_transient_errors() {
+		// given
+		let retrier = Retrier::new(test_policy());
+		let mut num_calls = 0;
+
+		// when
+		let result = retrier.run(|| {
+			num_calls += 1;
+			if num_calls < 3 {
+				return Err(io::Error::from(io::ErrorKind::WouldBlock));
+			}
+			Ok(num_calls)
+		});
+
+		// then
+		assert_eq!(result.unwrap(), 3);
+		assert_eq!(
+			retrier.counts(),
+			FaultCounts {
+				retried: 2,
+				failed: 0
+			}
+		);
+	}
+
+	#[test]
+	fn dont_retry_other_errors() {
+		// given
+		let retrier = Retrier::new(test_policy());
+		let mut num_calls = 0;
+
+		// when
+		let result: io::Result<()> = retrier.run(|| {
+			num_calls += 1;
+			Err(io::Error::from(io::ErrorKind::PermissionDenied))
+		});
+
+		// then
+		assert!(result.is_err());
+		assert_eq!(num_calls, 1);
+		assert_eq!(
+			retrier.counts(),
+			FaultCounts {
+				retried: 0,
+				failed: 1
+			}
+		);
+	}
+
+	#[test]
+	fn give_up_after_max_attempts() {
+		// given
+		let retrier = Retrier::new(test_policy());
+
+		// when
+		let result: io::Result<()> = retrier.run(|| Err(io::Error::from(io::ErrorKind::TimedOut)));
+
+		// then
+		assert!(result.is_err());
+		assert_eq!(
+			retrier.counts(),
+			FaultCounts {
+				retried: 2,
+				failed: 1
+			}
+		);
+	}
+}
@@ -1,9 +1,9 @@
 use std::{
 	fs::{File, OpenOptions},
-	io::{Seek, SeekFrom},
 	num::{NonZeroU16, NonZeroU64},
-	os,
+	ops::RangeInclusive,
 	path::Path,
+	sync::Arc,
 };
 
 #[cfg(test)]
@@ -11,20 +11,41 @@ use mockall::automock;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 use super::{
-	generic::{GenericHeader, GenericHeaderRepr},
+	crypto::FileCipher,
+	generic::{FeatureFlags, GenericHeader, GenericHeaderRepr, FEATURE_ENCRYPTED},
+	retry::Retrier,
+	vfs::VfsFile,
 	FileError, WalIndex,
 };
 use crate::{
 	consts::PAGE_SIZE,
-	files::{generic::FileType, utils::CRC16},
-	repr::{IoRepr, Repr},
+	failpoints::failpoint,
+	files::{
+		generic::FileType,
+		utils::{AlignedPage, CRC16},
+	},
+	repr::{IoRepr, Repr, U16, U64},
+	utils::units::KIB,
 };
 
 const FORMAT_VERSION_UNINIT: u8 = 0;
-const FORMAT_VERSION: u8 = 1;
+/// Version 2 added feature flags to the header.
+pub(crate) const FORMAT_VERSION: u8 = 2;
+/// The oldest version of segments that can still be read. Apart from the
+/// header, version 1 segments are laid out like current ones, and writable
+/// databases upgrade them when they are opened.
+pub(crate) const MIN_FORMAT_VERSION: u8 = 1;
+pub(crate) const SUPPORTED_FEATURES: FeatureFlags = FeatureFlags {
+	required: FEATURE_ENCRYPTED,
+	optional: 0,
+};
 
 // 2 GiB when PAGE_SIZE = 32 KiB
-const SEGMENT_SIZE: usize = PAGE_SIZE << 16;
+pub(crate) const SEGMENT_SIZE: usize = PAGE_SIZE << 16;
+
+/// The smallest page size that segments can be created with. Pages are
+/// written with direct I/O, so they have to span whole blocks.
+pub(crate) const MIN_PAGE_SIZE: usize = 4 * KIB;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct InitPageHeader {
@@ -41,25 +62,25 @@ enum PageHeader {
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C, packed)]
 struct PageHeaderRepr {
-	wal_generation: u64,
-	wal_offset: u64,
-	crc: u16,
+	wal_generation: U64,
+	wal_offset: U64,
+	crc: U16,
 	format_version: u8,
 }
 impl Repr<PageHeader> for PageHeaderRepr {
 	type Error = FileError;
 }
 
-const PAGE_FORMAT_VERSION: u8 = 1;
+pub(crate) const PAGE_FORMAT_VERSION: u8 = 1;
 
 impl From<PageHeader> for PageHeaderRepr {
 	fn from(value: PageHeader) -> Self {
 		match value {
 			PageHeader::Uninit => Self::new_zeroed(),
 			PageHeader::Init(header) => Self {
-				wal_generation: header.wal_index.generation,
-				wal_offset: header.wal_index.offset.get(),
-				crc: header.crc,
+				wal_generation: header.wal_index.generation.into(),
+				wal_offset: header.wal_index.offset.get().into(),
+				crc: header.crc.into(),
 				format_version: PAGE_FORMAT_VERSION,
 			},
 		}
@@ -77,127 +98,422 @@ impl TryFrom<PageHeaderRepr> for PageHeader {
 		if value.format_version != PAGE_FORMAT_VERSION {
 			return Err(FileError::IncompatiblePageVersion(value.format_version));
 		}
-		let Some(wal_offset) = NonZeroU64::new(value.wal_offset) else {
+		let Some(wal_offset) = NonZeroU64::new(value.wal_offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self::Init(InitPageHeader {
-			wal_index: WalIndex::new(value.wal_generation, wal_offset),
-			crc: value.crc,
+			wal_index: WalIndex::new(value.wal_generation.get(), wal_offset),
+			crc: value.crc.get(),
 		}))
 	}
 }
 
 pub(crate) const PAGE_BODY_SIZE: usize = PAGE_SIZE - PageHeaderRepr::SIZE;
 
-pub(crate) struct SegmentFile {
-	file: File,
+/// Whether segments can be created with pages of `page_size` bytes.
+pub(crate) fn is_supported_page_size(page_size: usize) -> bool {
+	page_size.is_power_of_two() && (MIN_PAGE_SIZE..=PAGE_SIZE).contains(&page_size)
+}
+
+/// The page sizes that segments are created with. Segments use pages of
+/// [`PAGE_SIZE`] bytes, unless a smaller size was set for them.
+///
+/// Pages are always cached with [`PAGE_BODY_SIZE`] bytes; a page of a segment
+/// with smaller pages only stores the start of its body, and the rest of it
+/// is always zero.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SegmentPageSizes {
+	/// Ranges of segments and their page size. Later ranges take precedence
+	/// over earlier ones.
+	ranges: Vec<(RangeInclusive<u32>, usize)>,
+}
+
+impl SegmentPageSizes {
+	pub fn set(&mut self, segments: RangeInclusive<u32>, page_size: usize) {
+		self.ranges.push((segments, page_size));
+	}
+
+	pub fn page_size(&self, segment_num: u32) -> usize {
+		self.ranges
+			.iter()
+			.rev()
+			.find(|(segments, _)| segments.contains(&segment_num))
+			.map_or(PAGE_SIZE, |(_, page_size)| *page_size)
+	}
+
+	/// The number of bytes that the pages of the segment can store.
+	pub fn body_size(&self, segment_num: u32) -> usize {
+		self.page_size(segment_num) - PageHeaderRepr::SIZE
+	}
+
+	/// The page sizes that were set, in the order they were set in.
+	pub fn page_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+		self.ranges.iter().map(|(_, page_size)| *page_size)
+	}
+}
+
+pub(crate) struct SegmentFile<F = File> {
+	file: F,
+	/// The file opened for direct I/O, through which pages are written if
+	/// direct I/O is enabled.
+	direct_file: Option<F>,
+	retrier: Arc<Retrier>,
+	cipher: Option<FileCipher>,
+	page_size: usize,
 }
 
 impl SegmentFile {
 	pub fn create_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
-		let mut file = OpenOptions::new()
+		Self::create_file_with_cipher(path, None, PAGE_SIZE)
+	}
+
+	/// Creates a segment file with pages of `page_size` bytes, whose pages
+	/// are encrypted with `cipher`, if it is given.
+	pub fn create_file_with_cipher(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+		page_size: usize,
+	) -> Result<Self, FileError> {
+		let file = OpenOptions::new()
 			.create(true)
 			.truncate(true)
 			.read(true)
 			.write(true)
 			.open(path)?;
 
+		let segment = Self::create(file, cipher, page_size)?;
+		segment.file.set_len(segment.segment_size())?;
+		Ok(segment)
+	}
+
+	pub fn open_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
+		Self::open_file_with_cipher(path, None, PAGE_SIZE)
+	}
+
+	/// Opens a segment file, whose pages are decrypted with `cipher` if it is
+	/// encrypted. Fails if the segment wasn't created with pages of
+	/// `page_size` bytes.
+	pub fn open_file_with_cipher(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+		page_size: usize,
+	) -> Result<Self, FileError> {
+		let file = OpenOptions::new().read(true).write(true).open(path)?;
+		Self::open(file, cipher, page_size)
+	}
+
+	/// Opens a segment file for reading, whatever supported page size it was
+	/// created with.
+	pub fn open_file_read_only(path: impl AsRef<Path>) -> Result<Self, FileError> {
+		let file = OpenOptions::new().read(true).open(path)?;
+		let page_size = Self::read_page_size(&file, None)?;
+		if !is_supported_page_size(page_size) {
+			return Err(FileError::PageSizeMismatch {
+				found: page_size,
+				expected: PAGE_SIZE,
+			});
+		}
+		Self::open(file, None, page_size)
+	}
+
+	/// Writes pages to the segment file at `path` with direct I/O, bypassing
+	/// the page cache of the operating system.
+	#[cfg(target_os = "linux")]
+	pub fn with_direct_io(mut self, path: impl AsRef<Path>) -> Result<Self, FileError> {
+		use std::os::unix::fs::OpenOptionsExt;
+
+		let direct_file = OpenOptions::new()
+			.write(true)
+			.custom_flags(libc::O_DIRECT)
+			.open(path)?;
+		self.direct_file = Some(direct_file);
+		Ok(self)
+	}
+
+	#[cfg(not(target_os = "linux"))]
+	pub fn with_direct_io(self, _path: impl AsRef<Path>) -> Result<Self, FileError> {
+		Ok(self)
+	}
+}
+
+impl<F: VfsFile> SegmentFile<F> {
+	/// Initializes `file` as an empty segment with pages of `page_size`
+	/// bytes, which is recorded in its header. Only the header is written,
+	/// the file grows as pages are written to it.
+	pub fn create(
+		file: F,
+		cipher: Option<FileCipher>,
+		page_size: usize,
+	) -> Result<Self, FileError> {
+		debug_assert!(is_supported_page_size(page_size));
 		let header = GenericHeader {
 			file_type: FileType::Segment,
-			content_offset: u16::try_from(PAGE_SIZE).unwrap(),
+			content_offset: u16::try_from(page_size).unwrap(),
 			version: FORMAT_VERSION,
+			features: FeatureFlags::new(cipher.is_some()),
 		};
-		GenericHeaderRepr::serialize(header, &mut file)?;
-
-		file.set_len(SEGMENT_SIZE as u64)?;
+		let mut page_buf = vec![0; page_size];
+		GenericHeaderRepr::serialize(header, page_buf.as_mut_slice())?;
+		file.write_all_at(&page_buf, 0)?;
 
-		Ok(Self { file })
+		Ok(Self::new(file, cipher, page_size))
 	}
 
-	pub fn open_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
-		let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+	/// Opens the segment in `file`, which has to have been created with pages
+	/// of `page_size` bytes.
+	pub fn open(file: F, cipher: Option<FileCipher>, page_size: usize) -> Result<Self, FileError> {
+		// A crash while the file was created may have left its header page
+		// incomplete. None of its pages were written then, so it is created
+		// again.
+		// Whether the header is incomplete is decided by the page size stored
+		// in the header itself, so that segments with smaller pages aren't
+		// mistaken for incomplete ones.
+		let len = file.len()?;
+		if len < GenericHeaderRepr::SIZE as u64 {
+			return Self::create(file, cipher, page_size);
+		}
+		let found = match Self::read_page_size(&file, cipher.as_ref()) {
+			Ok(found) => found,
+			Err(
+				FileError::MissingMagic
+				| FileError::Corrupted(..)
+				| FileError::ChecksumMismatch
+				| FileError::UnexpectedEof,
+			) if len < page_size as u64 => {
+				return Self::create(file, cipher, page_size);
+			}
+			Err(err) => return Err(err),
+		};
+		if len < found as u64 {
+			return Self::create(file, cipher, page_size);
+		}
+		if found != page_size {
+			return Err(FileError::PageSizeMismatch {
+				found,
+				expected: page_size,
+			});
+		}
+		let segment = Self::new(file, cipher, page_size);
+		// Vacuuming may have truncated the segment, but always at a page boundary
+		let len = segment.file.len()?;
+		if len < page_size as u64 || len > segment.segment_size() || len % page_size as u64 != 0 {
+			return Err(FileError::Corrupted(format!(
+				"Storage segment has an invalid length of {len} bytes"
+			)));
+		}
 
-		file.seek(SeekFrom::Start(0))?;
-		let header = GenericHeaderRepr::deserialize(&mut file)?;
+		Ok(segment)
+	}
+
+	/// Checks the header of the segment in `file`, and returns the page size
+	/// that the segment was created with.
+	fn read_page_size(file: &F, cipher: Option<&FileCipher>) -> Result<usize, FileError> {
+		let mut header_buf = [0; GenericHeaderRepr::SIZE];
+		file.read_exact_at(&mut header_buf, 0)?;
+		let header = GenericHeader::read(header_buf.as_slice())?;
 
 		if header.file_type != FileType::Segment {
 			return Err(FileError::WrongFileType(header.file_type));
 		}
-		if header.version != FORMAT_VERSION {
+		if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&header.version) {
 			return Err(FileError::IncompatibleVersion(
 				header.file_type,
-				FORMAT_VERSION,
+				header.version,
 			));
 		}
-		if header.content_offset as usize != PAGE_SIZE {
+		header.check_features(SUPPORTED_FEATURES)?;
+		match (header.features.is_encrypted(), cipher) {
+			(true, None) => return Err(FileError::MissingKey),
+			(false, Some(..)) => return Err(FileError::NotEncrypted),
+			_ => (),
+		}
+		// The first page only holds the header, so the content offset is the
+		// page size the segment was created with.
+		let content_offset = usize::from(header.content_offset);
+		if !content_offset.is_power_of_two() || content_offset <= PageHeaderRepr::SIZE {
 			return Err(FileError::Corrupted(format!(
 				"Expected content offset {PAGE_SIZE}, but found {}",
 				header.content_offset
 			)));
 		}
-		if file.metadata()?.len() != SEGMENT_SIZE as u64 {
-			return Err(FileError::Corrupted(
-				"Storage segment has been truncated".to_string(),
-			));
+		Ok(content_offset)
+	}
+
+	fn new(file: F, cipher: Option<FileCipher>, page_size: usize) -> Self {
+		Self {
+			file,
+			direct_file: None,
+			retrier: Arc::default(),
+			cipher,
+			page_size,
 		}
+	}
 
-		Ok(Self { file })
+	/// The size of the segment once all of its pages are written.
+	fn segment_size(&self) -> u64 {
+		(self.page_size as u64) << 16
 	}
 
-	cfg_match! {
-		cfg(unix) => {
-			fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), FileError> {
-				os::unix::fs::FileExt::read_exact_at(&self.file, buf, offset)?;
-				Ok(())
-			}
+	pub fn with_retrier(mut self, retrier: Arc<Retrier>) -> Self {
+		self.retrier = retrier;
+		self
+	}
 
-			fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<(), FileError> {
-				os::unix::fs::FileExt::write_all_at(&self.file, buf, offset)?;
-				Ok(())
-			}
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<(), FileError> {
+		self.retrier.run(|| self.file.read_exact_at(buf, offset))?;
+		Ok(())
+	}
+
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<(), FileError> {
+		let file = self.direct_file.as_ref().unwrap_or(&self.file);
+		self.retrier.run(|| file.write_all_at(buf, offset))?;
+		Ok(())
+	}
+
+	/// Writes the header and body of a page into `page_buf`. The checksum
+	/// covers the encrypted body, so that corruption is detected before
+	/// decrypting it. Only the start of `buf` that fits into `page_buf` is
+	/// stored; the rest of it has to be zero.
+	fn encode_page(
+		&self,
+		page_buf: &mut [u8],
+		page_num: NonZeroU16,
+		buf: &[u8],
+		wal_index: WalIndex,
+	) {
+		let (header_buf, body_buf) = page_buf.split_at_mut(PageHeaderRepr::SIZE);
+		let (buf, rest) = buf.split_at(body_buf.len());
+		debug_assert!(rest.iter().all(|byte| *byte == 0));
+		body_buf.copy_from_slice(buf);
+		if let Some(cipher) = &self.cipher {
+			cipher.encrypt_page(page_num, body_buf);
 		}
-		_ => {
-			compile_error!("Functionality not implemented on this platform!");
+		let crc = CRC16.checksum(body_buf);
+		let header = PageHeader::Init(InitPageHeader { wal_index, crc });
+		header_buf.copy_from_slice(PageHeaderRepr::from(header).as_bytes());
+	}
+
+	/// Checks the page read into `page_buf`, and writes its decrypted body
+	/// into `buf`. Uninitialized pages, and the part of `buf` past the end of
+	/// the body, read as zeroes.
+	fn decode_page(
+		&self,
+		page_buf: &[u8],
+		page_num: NonZeroU16,
+		buf: &mut [u8],
+	) -> Result<Option<WalIndex>, FileError> {
+		let (header_buf, body) = page_buf.split_at(PageHeaderRepr::SIZE);
+		let PageHeader::Init(header) = PageHeaderRepr::from_bytes(header_buf)? else {
+			buf.fill(0);
+			return Ok(None);
+		};
+
+		let crc = CRC16.checksum(body);
+		if header.crc != crc {
+			return Err(FileError::ChecksumMismatch);
+		}
+
+		let (buf, rest) = buf.split_at_mut(body.len());
+		rest.fill(0);
+		buf.copy_from_slice(body);
+		if let Some(cipher) = &self.cipher {
+			cipher.decrypt_page(page_num, buf);
 		}
+
+		Ok(Some(header.wal_index))
 	}
 
 	#[inline]
-	fn get_page_offset(page_num: NonZeroU16) -> u64 {
-		page_num.get() as u64 * PAGE_SIZE as u64
+	fn get_page_offset(&self, page_num: NonZeroU16) -> u64 {
+		page_num.get() as u64 * self.page_size as u64
 	}
 }
 
 #[cfg_attr(test, automock)]
 pub(crate) trait SegmentFileApi {
 	fn read(&self, page_num: NonZeroU16, buf: &mut [u8]) -> Result<Option<WalIndex>, FileError>;
+
+	/// Reads consecutive pages, starting at `first_page`, with a single read.
+	/// `bufs` receives the bodies of all pages back to back.
+	fn read_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &mut [u8],
+	) -> Result<Vec<Option<WalIndex>>, FileError>;
+
 	fn write(&self, page_num: NonZeroU16, buf: &[u8], wal_index: WalIndex)
 		-> Result<(), FileError>;
+
+	/// Writes consecutive pages, starting at `first_page`, with a single write.
+	/// `bufs` contains the bodies of all pages back to back.
+	fn write_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &[u8],
+		wal_indices: &[WalIndex],
+	) -> Result<(), FileError>;
+
+	/// Shrinks the segment so that it only holds its first `num_pages` pages,
+	/// and returns the number of bytes that were reclaimed. Pages past the end
+	/// read as uninitialized, and writing them grows the segment again.
+	fn truncate(&self, num_pages: u16) -> Result<u64, FileError>;
+
+	/// Waits until all pages written to the segment are durably stored.
+	fn sync(&self) -> Result<(), FileError>;
+
+	/// The pages of the segment that were written at least once, in
+	/// ascending order, along with the WAL index of their last write. Only the
+	/// page headers are read.
+	fn initialized_pages(&self) -> Result<Vec<(NonZeroU16, WalIndex)>, FileError>;
 }
 
-impl SegmentFileApi for SegmentFile {
+impl<F: VfsFile> SegmentFileApi for SegmentFile<F> {
 	fn read(&self, page_num: NonZeroU16, buf: &mut [u8]) -> Result<Option<WalIndex>, FileError> {
 		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+		failpoint!(PAGE_READ);
 
 		let mut page_buf = [0; PAGE_SIZE];
-		self.read_exact_at(&mut page_buf, Self::get_page_offset(page_num))?;
-		let header = PageHeaderRepr::from_bytes(&page_buf[0..PageHeaderRepr::SIZE])?;
-		let PageHeader::Init(header) = header else {
-			buf.fill(0);
-			return Ok(None);
-		};
-
-		let body = &page_buf[PageHeaderRepr::SIZE..];
-
-		let crc = CRC16.checksum(body);
-		if header.crc != crc {
-			return Err(FileError::ChecksumMismatch);
+		let page_buf = &mut page_buf[..self.page_size];
+		match self.read_exact_at(page_buf, self.get_page_offset(page_num)) {
+			Err(FileError::UnexpectedEof) => {
+				buf.fill(0);
+				return Ok(None);
+			}
+			result => result?,
 		}
+		self.decode_page(page_buf, page_num, buf)
+	}
 
-		buf.copy_from_slice(body);
+	fn read_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &mut [u8],
+	) -> Result<Vec<Option<WalIndex>>, FileError> {
+		debug_assert_eq!(bufs.len() % PAGE_BODY_SIZE, 0);
+		failpoint!(PAGE_READ);
 
-		Ok(Some(header.wal_index))
+		let num_pages = bufs.len() / PAGE_BODY_SIZE;
+		let offset = self.get_page_offset(first_page);
+		// Pages past the end of a truncated segment are uninitialized
+		let num_stored = self.file.len()?.saturating_sub(offset) / self.page_size as u64;
+		let num_stored = usize::try_from(num_stored).map_or(num_pages, |n| n.min(num_pages));
+
+		let mut pages_buf = vec![0; num_stored * self.page_size];
+		self.read_exact_at(&mut pages_buf, offset)?;
+		let mut wal_indices = Vec::with_capacity(num_pages);
+		for ((page_buf, buf), page_num) in pages_buf
+			.chunks_exact(self.page_size)
+			.zip(bufs.chunks_exact_mut(PAGE_BODY_SIZE))
+			.zip(first_page.get()..)
+		{
+			let page_num = NonZeroU16::new(page_num).unwrap();
+			wal_indices.push(self.decode_page(page_buf, page_num, buf)?);
+		}
+		bufs[num_stored * PAGE_BODY_SIZE..].fill(0);
+		wal_indices.resize(num_pages, None);
+		Ok(wal_indices)
 	}
 
 	fn write(
@@ -207,28 +523,194 @@ impl SegmentFileApi for SegmentFile {
 		wal_index: WalIndex,
 	) -> Result<(), FileError> {
 		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+		failpoint!(PAGE_WRITE);
 
-		let crc = CRC16.checksum(buf);
-		let header = PageHeader::Init(InitPageHeader { wal_index, crc });
+		let mut page_buf = AlignedPage::new_zeroed();
+		let page_buf = &mut page_buf.as_bytes_mut()[..self.page_size];
+		self.encode_page(page_buf, page_num, buf, wal_index);
 
-		let mut page_buf = [0; PAGE_SIZE];
-		page_buf[0..PageHeaderRepr::SIZE].copy_from_slice(PageHeaderRepr::from(header).as_bytes());
-		page_buf[PageHeaderRepr::SIZE..].copy_from_slice(buf);
+		self.write_all_at(page_buf, self.get_page_offset(page_num))?;
+		Ok(())
+	}
+
+	fn write_pages(
+		&self,
+		first_page: NonZeroU16,
+		bufs: &[u8],
+		wal_indices: &[WalIndex],
+	) -> Result<(), FileError> {
+		debug_assert_eq!(bufs.len(), wal_indices.len() * PAGE_BODY_SIZE);
+		failpoint!(PAGE_WRITE);
+
+		// The buffer is allocated in whole pages of `PAGE_SIZE` bytes, so that
+		// it stays aligned for direct I/O.
+		let pages_len = wal_indices.len() * self.page_size;
+		let mut pages_buf = vec![AlignedPage::new_zeroed(); pages_len.div_ceil(PAGE_SIZE)];
+		let pages_buf = &mut pages_buf.as_bytes_mut()[..pages_len];
+		for (((page_buf, buf), wal_index), page_num) in pages_buf
+			.chunks_exact_mut(self.page_size)
+			.zip(bufs.chunks_exact(PAGE_BODY_SIZE))
+			.zip(wal_indices)
+			.zip(first_page.get()..)
+		{
+			let page_num = NonZeroU16::new(page_num).unwrap();
+			self.encode_page(page_buf, page_num, buf, *wal_index);
+		}
+
+		self.write_all_at(pages_buf, self.get_page_offset(first_page))?;
+		Ok(())
+	}
 
-		self.write_all_at(&page_buf, Self::get_page_offset(page_num))?;
+	fn truncate(&self, num_pages: u16) -> Result<u64, FileError> {
+		let len = self.file.len()?;
+		let new_len = (num_pages as u64 + 1) * self.page_size as u64;
+		if new_len >= len {
+			return Ok(0);
+		}
+		self.retrier.run(|| self.file.set_len(new_len))?;
+		self.sync()?;
+		Ok(len - new_len)
+	}
+
+	fn sync(&self) -> Result<(), FileError> {
+		self.retrier.run(|| self.file.sync_data())?;
 		Ok(())
 	}
+
+	fn initialized_pages(&self) -> Result<Vec<(NonZeroU16, WalIndex)>, FileError> {
+		// The first page of the file holds the file header
+		let num_pages = self.file.len()? / self.page_size as u64 - 1;
+		let mut pages = Vec::new();
+		let mut header_buf = [0; PageHeaderRepr::SIZE];
+		for page_num in 1..=u16::try_from(num_pages).unwrap_or(u16::MAX) {
+			let page_num = NonZeroU16::new(page_num).unwrap();
+			self.read_exact_at(&mut header_buf, self.get_page_offset(page_num))?;
+			if let PageHeader::Init(header) = PageHeaderRepr::from_bytes(&header_buf)? {
+				pages.push((page_num, header.wal_index));
+			}
+		}
+		Ok(pages)
+	}
+}
+
+/// A read-only view of a segment that was created with a different page size
+/// than the one this build uses, for migrating its pages to a new database.
+pub(crate) struct ResizedSegmentFile<F: VfsFile = File> {
+	segment: SegmentFile<F>,
+	page_size: usize,
+}
+
+impl ResizedSegmentFile {
+	pub fn open_file(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
+		Self::open(File::open(path)?, cipher)
+	}
+}
+
+impl<F: VfsFile> ResizedSegmentFile<F> {
+	pub fn open(file: F, cipher: Option<FileCipher>) -> Result<Self, FileError> {
+		let page_size = SegmentFile::read_page_size(&file, cipher.as_ref())?;
+		if page_size > PAGE_SIZE {
+			return Err(FileError::PageSizeMismatch {
+				found: page_size,
+				expected: PAGE_SIZE,
+			});
+		}
+		Ok(Self {
+			segment: SegmentFile::new(file, cipher, page_size),
+			page_size,
+		})
+	}
+
+	/// The page size that the segment was created with.
+	pub fn page_size(&self) -> usize {
+		self.page_size
+	}
+
+	/// The size of the page bodies of the segment.
+	pub fn body_size(&self) -> usize {
+		self.page_size - PageHeaderRepr::SIZE
+	}
+
+	/// The number of pages of the segment, excluding the header page.
+	pub fn num_pages(&self) -> Result<u16, FileError> {
+		let num_pages = (self.segment.file.len()? / self.page_size as u64).saturating_sub(1);
+		Ok(u16::try_from(num_pages).unwrap_or(u16::MAX))
+	}
+
+	/// Reads the body of a page into `buf`, which must hold
+	/// [`body_size`](Self::body_size) bytes. Returns `false` if the page was
+	/// never written.
+	pub fn read(&self, page_num: NonZeroU16, buf: &mut [u8]) -> Result<bool, FileError> {
+		let mut page_buf = vec![0; self.page_size];
+		let offset = u64::from(page_num.get()) * self.page_size as u64;
+		self.segment.read_exact_at(&mut page_buf, offset)?;
+		Ok(self
+			.segment
+			.decode_page(&page_buf, page_num, buf)?
+			.is_some())
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test_helpers {
+	use super::*;
+
+	/// Writes an unencrypted segment file with the given page size, as an
+	/// older build of acorn with that page size would have. Each page body
+	/// is padded with zeroes.
+	pub(crate) fn write_resized_segment(path: &Path, page_size: usize, pages: &[(u16, &[u8])]) {
+		let num_pages = pages
+			.iter()
+			.map(|(page_num, _)| *page_num)
+			.max()
+			.unwrap_or(0);
+		let mut file_buf = vec![0; page_size * (usize::from(num_pages) + 1)];
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				file_type: FileType::Segment,
+				content_offset: u16::try_from(page_size).unwrap(),
+				version: FORMAT_VERSION,
+				features: FeatureFlags::NONE,
+			},
+			&mut file_buf[..page_size],
+		)
+		.unwrap();
+		let tempdir = tempfile::tempdir().unwrap();
+		let writer = SegmentFile::create_file(tempdir.path().join("writer")).unwrap();
+		let mut body = vec![0; page_size - PageHeaderRepr::SIZE];
+		for (page_num, data) in pages {
+			body.fill(0);
+			body[..data.len()].copy_from_slice(data);
+			let offset = usize::from(*page_num) * page_size;
+			writer.encode_page(
+				&mut file_buf[offset..offset + page_size],
+				NonZeroU16::new(*page_num).unwrap(),
+				&body,
+				WalIndex::new(0, NonZeroU64::MIN),
+			);
+		}
+		std::fs::write(path, &file_buf).unwrap();
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use std::io::{Read, Write};
+	use std::{
+		fs,
+		io::{Read, Seek, SeekFrom, Write},
+	};
 
 	use pretty_assertions::assert_buf_eq;
 	use zerocopy::AsBytes;
 
 	use crate::{
-		files::{generic::GenericHeaderRepr, test_helpers::wal_index},
+		files::{
+			generic::{GenericHeaderRepr, LegacyHeaderRepr},
+			test_helpers::wal_index,
+		},
 		utils::test_helpers::non_zero,
 	};
 
@@ -247,6 +729,7 @@ mod tests {
 			file_type: FileType::Segment,
 			content_offset: PAGE_SIZE as u16,
 			version: FORMAT_VERSION,
+			features: FeatureFlags::NONE,
 		})
 		.as_bytes()
 		.to_vec();
@@ -266,6 +749,7 @@ mod tests {
 			file_type: FileType::Segment,
 			content_offset: PAGE_SIZE as u16,
 			version: FORMAT_VERSION,
+			features: FeatureFlags::NONE,
 		})
 		.as_bytes()
 		.to_vec();
@@ -277,6 +761,73 @@ mod tests {
 		SegmentFile::open_file(tempdir.path().join("0")).unwrap();
 	}
 
+	#[test]
+	fn recreate_segment_file_with_incomplete_header() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.write_all(&[0; GenericHeaderRepr::SIZE + 1]).unwrap();
+
+		// when
+		SegmentFile::open_file(tempdir.path().join("0")).unwrap();
+
+		// then
+		let mut file = File::open(tempdir.path().join("0")).unwrap();
+		let received: &mut [u8] = &mut [0; GenericHeaderRepr::SIZE];
+		file.read_exact(received).unwrap();
+		let header = GenericHeaderRepr::deserialize(&*received).unwrap();
+		assert_eq!(header.file_type, FileType::Segment);
+		assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), PAGE_SIZE as u64);
+	}
+
+	#[test]
+	fn open_segment_file_of_version_1() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file_start: Vec<u8> = LegacyHeaderRepr::from(GenericHeader {
+			file_type: FileType::Segment,
+			content_offset: PAGE_SIZE as u16,
+			version: 1,
+			features: FeatureFlags::NONE,
+		})
+		.as_bytes()
+		.to_vec();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.set_len(SEGMENT_SIZE as u64).unwrap();
+		file.write_all(&file_start).unwrap();
+
+		// then
+		SegmentFile::open_file(tempdir.path().join("0")).unwrap();
+	}
+
+	#[test]
+	fn read_segment_with_smaller_page_size() {
+		// given
+		let page_size = PAGE_SIZE / 2;
+		let body_size = page_size - PageHeaderRepr::SIZE;
+		let tempdir = tempfile::tempdir().unwrap();
+		test_helpers::write_resized_segment(
+			&tempdir.path().join("0"),
+			page_size,
+			&[(2, &vec![25; body_size])],
+		);
+
+		// when
+		let segment = ResizedSegmentFile::open_file(tempdir.path().join("0"), None).unwrap();
+		let mut first = vec![1; segment.body_size()];
+		let mut second = vec![0; segment.body_size()];
+		let first_init = segment.read(non_zero!(1), &mut first).unwrap();
+		let second_init = segment.read(non_zero!(2), &mut second).unwrap();
+
+		// then
+		assert_eq!(segment.page_size(), page_size);
+		assert_eq!(segment.num_pages().unwrap(), 2);
+		assert!(!first_init);
+		assert_buf_eq!(first, vec![0; body_size]);
+		assert!(second_init);
+		assert_buf_eq!(second, vec![25; body_size]);
+	}
+
 	#[test]
 	fn write_to_page() {
 		// given
@@ -298,9 +849,9 @@ mod tests {
 			received,
 			[
 				PageHeaderRepr {
-					wal_generation: 69,
-					wal_offset: 420,
-					crc: 0x0c78,
+					wal_generation: 69.into(),
+					wal_offset: 420.into(),
+					crc: 0x0c78.into(),
 					format_version: 1
 				}
 				.as_bytes(),
@@ -310,6 +861,94 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn write_consecutive_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		let bufs = [[1; PAGE_BODY_SIZE], [2; PAGE_BODY_SIZE]].concat();
+
+		// when
+		segment
+			.write_pages(non_zero!(7), &bufs, &[wal_index!(1, 2), wal_index!(1, 3)])
+			.unwrap();
+
+		// then
+		let mut data = [0; PAGE_BODY_SIZE];
+		assert_eq!(
+			segment.read(non_zero!(7), &mut data).unwrap(),
+			Some(wal_index!(1, 2))
+		);
+		assert_buf_eq!(data, [1; PAGE_BODY_SIZE]);
+		assert_eq!(
+			segment.read(non_zero!(8), &mut data).unwrap(),
+			Some(wal_index!(1, 3))
+		);
+		assert_buf_eq!(data, [2; PAGE_BODY_SIZE]);
+	}
+
+	#[test]
+	fn read_consecutive_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		let bufs = [[1; PAGE_BODY_SIZE], [2; PAGE_BODY_SIZE]].concat();
+		segment
+			.write_pages(non_zero!(7), &bufs, &[wal_index!(1, 2), wal_index!(1, 3)])
+			.unwrap();
+		segment.truncate(7).unwrap();
+
+		// when
+		let mut data = vec![0xff; 3 * PAGE_BODY_SIZE];
+		let wal_indices = segment.read_pages(non_zero!(6), &mut data).unwrap();
+
+		// then
+		assert_eq!(wal_indices, vec![None, Some(wal_index!(1, 2)), None]);
+		assert_buf_eq!(
+			data,
+			[
+				[0; PAGE_BODY_SIZE],
+				[1; PAGE_BODY_SIZE],
+				[0; PAGE_BODY_SIZE]
+			]
+			.concat()
+		);
+	}
+
+	#[test]
+	fn write_with_direct_io() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let segment = SegmentFile::create_file(&path)
+			.unwrap()
+			.with_direct_io(&path)
+			.unwrap();
+		let bufs = [[1; PAGE_BODY_SIZE], [2; PAGE_BODY_SIZE]].concat();
+
+		// when
+		segment
+			.write(non_zero!(3), &[3; PAGE_BODY_SIZE], wal_index!(1, 1))
+			.unwrap();
+		segment
+			.write_pages(non_zero!(7), &bufs, &[wal_index!(1, 2), wal_index!(1, 3)])
+			.unwrap();
+		segment.sync().unwrap();
+
+		// then
+		let mut data = [0; PAGE_BODY_SIZE];
+		assert_eq!(
+			segment.read(non_zero!(3), &mut data).unwrap(),
+			Some(wal_index!(1, 1))
+		);
+		assert_buf_eq!(data, [3; PAGE_BODY_SIZE]);
+		assert_eq!(
+			segment.read(non_zero!(8), &mut data).unwrap(),
+			Some(wal_index!(1, 3))
+		);
+		assert_buf_eq!(data, [2; PAGE_BODY_SIZE]);
+	}
+
 	#[test]
 	fn read_from_page() {
 		// given
@@ -327,4 +966,101 @@ mod tests {
 		assert_eq!(wal_index, Some(wal_index!(69, 420)));
 		assert_eq!(data, [25; PAGE_BODY_SIZE]);
 	}
+
+	#[test]
+	fn truncate_segment() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let segment = SegmentFile::create_file(tempdir.path().join("0")).unwrap();
+		segment
+			.write(non_zero!(1), &[1; PAGE_BODY_SIZE], wal_index!(0, 1))
+			.unwrap();
+		segment
+			.write(non_zero!(2), &[2; PAGE_BODY_SIZE], wal_index!(0, 2))
+			.unwrap();
+
+		// when
+		let reclaimed = segment.truncate(1).unwrap();
+
+		// then
+		assert_eq!(reclaimed, (SEGMENT_SIZE - 2 * PAGE_SIZE) as u64);
+		let segment = SegmentFile::open_file(tempdir.path().join("0")).unwrap();
+		let mut data = [0; PAGE_BODY_SIZE];
+		assert_eq!(
+			segment.read(non_zero!(1), &mut data).unwrap(),
+			Some(wal_index!(0, 1))
+		);
+		assert_eq!(segment.read(non_zero!(2), &mut data).unwrap(), None);
+		assert_eq!(data, [0; PAGE_BODY_SIZE]);
+		assert_eq!(segment.truncate(1).unwrap(), 0);
+	}
+
+	#[test]
+	fn open_segment_file_with_other_page_size() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let file_start: Vec<u8> = GenericHeaderRepr::from(GenericHeader {
+			file_type: FileType::Segment,
+			content_offset: (PAGE_SIZE / 2) as u16,
+			version: FORMAT_VERSION,
+			features: FeatureFlags::NONE,
+		})
+		.as_bytes()
+		.to_vec();
+		let mut file = File::create(tempdir.path().join("0")).unwrap();
+		file.set_len(SEGMENT_SIZE as u64).unwrap();
+		file.write_all(&file_start).unwrap();
+
+		// when
+		let result = SegmentFile::open_file(tempdir.path().join("0"));
+
+		// then
+		assert!(matches!(
+			result,
+			Err(FileError::PageSizeMismatch { found, expected: PAGE_SIZE }) if found == PAGE_SIZE / 2
+		));
+	}
+
+	#[test]
+	fn write_and_read_small_pages() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let segment = SegmentFile::create_file_with_cipher(&path, None, MIN_PAGE_SIZE).unwrap();
+		let body_size = MIN_PAGE_SIZE - PageHeaderRepr::SIZE;
+		let mut page = vec![0; PAGE_BODY_SIZE];
+		page[..body_size].fill(7);
+		segment.truncate(0).unwrap();
+
+		// when
+		segment
+			.write(non_zero!(2), &page, wal_index!(1, 2))
+			.unwrap();
+		segment
+			.write_pages(
+				non_zero!(3),
+				&[page.as_slice(), page.as_slice()].concat(),
+				&[wal_index!(1, 3), wal_index!(1, 4)],
+			)
+			.unwrap();
+		let reopened = SegmentFile::open_file_with_cipher(&path, None, MIN_PAGE_SIZE).unwrap();
+		let mut read = vec![1; PAGE_BODY_SIZE];
+		let wal_index = reopened.read(non_zero!(2), &mut read).unwrap();
+		let mut read_run = vec![1; 2 * PAGE_BODY_SIZE];
+		reopened.read_pages(non_zero!(3), &mut read_run).unwrap();
+		let mismatch = SegmentFile::open_file_with_cipher(&path, None, PAGE_SIZE);
+
+		// then
+		assert_eq!(wal_index, Some(wal_index!(1, 2)));
+		assert_buf_eq!(read, page);
+		assert_buf_eq!(read_run, [page.as_slice(), page.as_slice()].concat());
+		assert_eq!(fs::metadata(&path).unwrap().len(), 5 * MIN_PAGE_SIZE as u64);
+		assert!(matches!(
+			mismatch,
+			Err(FileError::PageSizeMismatch {
+				found: MIN_PAGE_SIZE,
+				expected: PAGE_SIZE
+			})
+		));
+	}
 }
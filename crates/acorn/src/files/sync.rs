@@ -0,0 +1,111 @@
+use std::{
+	fmt,
+	fs::{File, OpenOptions},
+	io,
+	path::Path,
+};
+
+/// How the written content of a file is made durable. Plain `fsync` doesn't
+/// flush the write cache of the drive on every platform, so where stronger
+/// primitives are available, they are preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SyncPrimitive {
+	/// `fdatasync`, or `fsync` where it isn't available.
+	Fdatasync,
+
+	/// `fcntl(F_FULLFSYNC)` on macOS, which also flushes the drive's cache.
+	FullFsync,
+
+	/// `fcntl(F_BARRIERFSYNC)` on macOS, which only orders the writes with a
+	/// barrier, for file systems that don't support [`Self::FullFsync`].
+	BarrierFsync,
+
+	/// `FlushFileBuffers` on Windows.
+	FlushFileBuffers,
+}
+
+impl fmt::Display for SyncPrimitive {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Fdatasync => write!(f, "fdatasync"),
+			Self::FullFsync => write!(f, "F_FULLFSYNC"),
+			Self::BarrierFsync => write!(f, "F_BARRIERFSYNC"),
+			Self::FlushFileBuffers => write!(f, "FlushFileBuffers"),
+		}
+	}
+}
+
+/// Makes the written content of the file durable with the strongest
+/// primitive its file system supports, and returns which one that was.
+#[cfg(target_vendor = "apple")]
+pub(crate) fn sync_file(file: &File) -> io::Result<SyncPrimitive> {
+	use std::os::fd::AsRawFd;
+
+	// Some file systems, like network shares, reject the fcntl calls, in
+	// which case the next weaker primitive is tried.
+	let fd = file.as_raw_fd();
+	// Safety: the file descriptor is open for as long as `file` is.
+	if unsafe { libc::fcntl(fd, libc::F_FULLFSYNC) } != -1 {
+		return Ok(SyncPrimitive::FullFsync);
+	}
+	// Safety: see above.
+	if unsafe { libc::fcntl(fd, libc::F_BARRIERFSYNC) } != -1 {
+		return Ok(SyncPrimitive::BarrierFsync);
+	}
+	file.sync_data()?;
+	Ok(SyncPrimitive::Fdatasync)
+}
+
+/// Makes the written content of the file durable, and returns which
+/// primitive was used for that.
+#[cfg(windows)]
+pub(crate) fn sync_file(file: &File) -> io::Result<SyncPrimitive> {
+	// The standard library syncs files with `FlushFileBuffers` on Windows.
+	file.sync_data()?;
+	Ok(SyncPrimitive::FlushFileBuffers)
+}
+
+/// Makes the written content of the file durable, and returns which
+/// primitive was used for that.
+#[cfg(not(any(target_vendor = "apple", windows)))]
+pub(crate) fn sync_file(file: &File) -> io::Result<SyncPrimitive> {
+	file.sync_data()?;
+	Ok(SyncPrimitive::Fdatasync)
+}
+
+/// Finds out which primitive [`sync_file`] uses for the existing file at
+/// `path`, by syncing it. The file isn't modified.
+pub(crate) fn probe(path: &Path) -> io::Result<SyncPrimitive> {
+	// Windows only flushes files that are open for writing.
+	let file = OpenOptions::new().write(true).open(path)?;
+	sync_file(&file)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn probe_sync_primitive() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("file");
+		std::fs::write(&path, [1, 2, 3]).unwrap();
+
+		// when
+		let primitive = probe(&path).unwrap();
+
+		// then
+		let expected = if cfg!(target_vendor = "apple") {
+			SyncPrimitive::FullFsync
+		} else if cfg!(windows) {
+			SyncPrimitive::FlushFileBuffers
+		} else {
+			SyncPrimitive::Fdatasync
+		};
+		assert_eq!(primitive, expected);
+		assert_eq!(std::fs::read(&path).unwrap(), [1, 2, 3]);
+		assert_eq!(tempdir.path().read_dir().unwrap().count(), 1);
+	}
+}
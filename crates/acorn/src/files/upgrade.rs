@@ -0,0 +1,305 @@
+use std::{
+	collections::HashMap,
+	fs::{self, File, OpenOptions},
+	io::{Read, Seek, SeekFrom},
+	path::Path,
+};
+
+use crate::repr::{IoRepr, Repr};
+
+use super::{
+	crypto::FileCipher,
+	generic::{FileType, GenericHeader, GenericHeaderRepr},
+	FileError,
+};
+
+/// Upgrades a file from one format version to the next, in place. Encrypted
+/// files are decrypted with the cipher.
+///
+/// Steps may be interrupted by a crash, after which they are run again on
+/// the partially upgraded file, so they have to be idempotent. They must
+/// write the new version to the header last, once the rest of the file is
+/// upgraded.
+pub(crate) type UpgradeStep = fn(&Path, Option<&FileCipher>) -> Result<(), FileError>;
+
+/// The steps that upgrade the files of older format versions to the current
+/// ones, by file type and the version they upgrade from.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FormatUpgrades {
+	steps: HashMap<(FileType, u8), UpgradeStep>,
+}
+
+impl FormatUpgrades {
+	/// The upgrades of the formats that released versions of acorn wrote.
+	/// Whenever the format version of a file type is bumped, a step from the
+	/// previous version has to be registered here.
+	pub fn builtin() -> Self {
+		let mut upgrades = Self::default();
+		upgrades.register(FileType::Segment, 1, upgrade_segment_from_v1);
+		upgrades
+	}
+
+	/// Registers the step that upgrades files of `file_type` from
+	/// `from_version` to the version after it.
+	pub fn register(&mut self, file_type: FileType, from_version: u8, step: UpgradeStep) {
+		self.steps.insert((file_type, from_version), step);
+	}
+
+	/// Upgrades the file at `path` to `version`, one step at a time, and
+	/// returns whether it had an older version.
+	///
+	/// Before each step, the header of the file is copied to a file in
+	/// `backup_dir`, named after the file and the version it had. Files that
+	/// have a newer version are left alone, opening them fails later on.
+	pub fn upgrade_file(
+		&self,
+		path: &Path,
+		file_type: FileType,
+		version: u8,
+		cipher: Option<&FileCipher>,
+		backup_dir: &Path,
+	) -> Result<bool, FileError> {
+		// A crash while the file was created may have left its header
+		// incomplete; opening the file creates it again.
+		let Some(mut header) = read_header(path)? else {
+			return Ok(false);
+		};
+		if header.file_type != file_type {
+			return Err(FileError::WrongFileType(header.file_type));
+		}
+		if header.version >= version {
+			return Ok(false);
+		}
+
+		while header.version < version {
+			let Some(step) = self.steps.get(&(file_type, header.version)) else {
+				return Err(FileError::IncompatibleVersion(file_type, header.version));
+			};
+			back_up_header(path, &header, backup_dir)?;
+			step(path, cipher)?;
+
+			let Some(upgraded) = read_header(path)? else {
+				return Err(FileError::UnexpectedEof);
+			};
+			if upgraded.version != header.version + 1 {
+				return Err(FileError::Corrupted(format!(
+					"Upgrading the {file_type:?} file from version {} left it at version {}",
+					header.version, upgraded.version
+				)));
+			}
+			header = upgraded;
+		}
+		Ok(true)
+	}
+}
+
+/// Reads the header of the file at `path`, or returns `None` if the file is
+/// too short to hold one.
+fn read_header(path: &Path) -> Result<Option<GenericHeader>, FileError> {
+	let mut buf = Vec::with_capacity(GenericHeaderRepr::SIZE);
+	File::open(path)?
+		.take(GenericHeaderRepr::SIZE as u64)
+		.read_to_end(&mut buf)?;
+	if buf.len() < GenericHeaderRepr::SIZE {
+		return Ok(None);
+	}
+	GenericHeader::read(buf.as_slice()).map(Some)
+}
+
+/// Rewrites the header of a version 1 segment in the layout of version 2,
+/// which added feature flags. The header page of version 1 segments is
+/// padded with zeroes, so the flags read from it are empty, and the rest of
+/// the segment stays the same.
+fn upgrade_segment_from_v1(path: &Path, _cipher: Option<&FileCipher>) -> Result<(), FileError> {
+	let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+	let header = GenericHeaderRepr::deserialize(&mut file)?;
+	file.seek(SeekFrom::Start(0))?;
+	GenericHeaderRepr::serialize(
+		GenericHeader {
+			version: 2,
+			..header
+		},
+		&mut file,
+	)?;
+	file.sync_all()?;
+	Ok(())
+}
+
+/// Durably copies everything up to the content of the file at `path` into
+/// `backup_dir`.
+fn back_up_header(path: &Path, header: &GenericHeader, backup_dir: &Path) -> Result<(), FileError> {
+	let mut buf = Vec::with_capacity(header.content_offset.into());
+	File::open(path)?
+		.take(header.content_offset.into())
+		.read_to_end(&mut buf)?;
+
+	fs::create_dir_all(backup_dir)?;
+	let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+	file_name.push(format!(".v{}", header.version));
+	let backup_path = backup_dir.join(file_name);
+	fs::write(&backup_path, &buf)?;
+	File::open(backup_path)?.sync_all()?;
+	File::open(backup_dir)?.sync_all()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+
+	use crate::{
+		consts::PAGE_SIZE,
+		files::{
+			generic::{FeatureFlags, LegacyHeaderRepr},
+			segment::{self, SegmentFile},
+		},
+	};
+
+	use super::*;
+
+	fn write_header(path: &Path, version: u8) {
+		let mut file = OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.write(true)
+			.open(path)
+			.unwrap();
+		GenericHeaderRepr::serialize(
+			GenericHeader {
+				file_type: FileType::Wal,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version,
+				features: FeatureFlags::NONE,
+			},
+			&mut file,
+		)
+		.unwrap();
+	}
+
+	fn bump_version(path: &Path, _cipher: Option<&FileCipher>) -> Result<(), FileError> {
+		let version = read_header(path)?.unwrap().version;
+		let mut file = OpenOptions::new().append(true).open(path)?;
+		file.write_all(&[version])?;
+		write_header(path, version + 1);
+		Ok(())
+	}
+
+	fn do_nothing(_path: &Path, _cipher: Option<&FileCipher>) -> Result<(), FileError> {
+		Ok(())
+	}
+
+	#[test]
+	fn upgrade_file_step_by_step() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("1");
+		let backup_dir = tempdir.path().join("backup");
+		write_header(&path, 1);
+		let old_header = fs::read(&path).unwrap();
+		let mut upgrades = FormatUpgrades::default();
+		upgrades.register(FileType::Wal, 1, bump_version);
+		upgrades.register(FileType::Wal, 2, bump_version);
+
+		// when
+		let upgraded = upgrades
+			.upgrade_file(&path, FileType::Wal, 3, None, &backup_dir)
+			.unwrap();
+
+		// then
+		assert!(upgraded);
+		assert_eq!(read_header(&path).unwrap().unwrap().version, 3);
+		assert_eq!(&fs::read(&path).unwrap()[GenericHeaderRepr::SIZE..], [1, 2]);
+		assert_eq!(fs::read(backup_dir.join("1.v1")).unwrap(), old_header);
+		assert!(backup_dir.join("1.v2").exists());
+	}
+
+	#[test]
+	fn leave_current_files_alone() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("1");
+		let backup_dir = tempdir.path().join("backup");
+		write_header(&path, 3);
+		let mut upgrades = FormatUpgrades::default();
+		upgrades.register(FileType::Wal, 3, bump_version);
+
+		// when
+		let upgraded = upgrades
+			.upgrade_file(&path, FileType::Wal, 3, None, &backup_dir)
+			.unwrap();
+
+		// then
+		assert!(!upgraded);
+		assert_eq!(read_header(&path).unwrap().unwrap().version, 3);
+		assert!(!backup_dir.exists());
+	}
+
+	#[test]
+	fn fail_for_missing_or_broken_steps() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("1");
+		let backup_dir = tempdir.path().join("backup");
+		write_header(&path, 1);
+		let mut upgrades = FormatUpgrades::default();
+
+		// expect
+		assert!(matches!(
+			upgrades.upgrade_file(&path, FileType::Wal, 2, None, &backup_dir),
+			Err(FileError::IncompatibleVersion(FileType::Wal, 1))
+		));
+		upgrades.register(FileType::Wal, 1, do_nothing);
+		assert!(matches!(
+			upgrades.upgrade_file(&path, FileType::Wal, 2, None, &backup_dir),
+			Err(FileError::Corrupted(..))
+		));
+		assert!(matches!(
+			upgrades.upgrade_file(&path, FileType::Segment, 2, None, &backup_dir),
+			Err(FileError::WrongFileType(FileType::Wal))
+		));
+	}
+
+	#[test]
+	fn upgrade_segment_from_version_1() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("0");
+		let backup_dir = tempdir.path().join("backup");
+		let mut file_buf = vec![0; PAGE_SIZE];
+		LegacyHeaderRepr::serialize(
+			GenericHeader {
+				file_type: FileType::Segment,
+				content_offset: PAGE_SIZE as u16,
+				version: 1,
+				features: FeatureFlags::NONE,
+			},
+			file_buf.as_mut_slice(),
+		)
+		.unwrap();
+		fs::write(&path, &file_buf).unwrap();
+
+		// when
+		let upgraded = FormatUpgrades::builtin()
+			.upgrade_file(
+				&path,
+				FileType::Segment,
+				segment::FORMAT_VERSION,
+				None,
+				&backup_dir,
+			)
+			.unwrap();
+
+		// then
+		assert!(upgraded);
+		assert_eq!(
+			read_header(&path).unwrap().unwrap(),
+			GenericHeader {
+				file_type: FileType::Segment,
+				content_offset: PAGE_SIZE as u16,
+				version: 2,
+				features: FeatureFlags::NONE,
+			}
+		);
+		SegmentFile::open_file(&path).unwrap();
+	}
+}
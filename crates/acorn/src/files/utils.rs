@@ -1,6 +1,71 @@
+use std::{
+	fs::File,
+	io::{self, Cursor},
+};
+
 use crc::Crc;
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::consts::PAGE_SIZE;
+
+use super::sync::sync_file;
 
 // TODO: there are tradeoffs here. Perhaps I should look more into selecting an
 // algorithm.
 pub(crate) const CRC32: Crc<u32> = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 pub(crate) const CRC16: Crc<u16> = Crc::<u16>::new(&crc::CRC_16_IBM_SDLC);
+
+/// Files whose written content can be made durable.
+pub(crate) trait SyncData {
+	fn sync_data(&mut self) -> io::Result<()>;
+}
+
+impl SyncData for File {
+	fn sync_data(&mut self) -> io::Result<()> {
+		sync_file(self)?;
+		Ok(())
+	}
+}
+
+impl<T> SyncData for Cursor<T> {
+	fn sync_data(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// Files that can be truncated.
+pub(crate) trait SetLen {
+	fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl SetLen for File {
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		File::set_len(self, len)
+	}
+}
+
+impl SetLen for Cursor<&mut Vec<u8>> {
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		let Ok(len) = usize::try_from(len) else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		self.get_mut().truncate(len);
+		Ok(())
+	}
+}
+
+impl SetLen for Cursor<Vec<u8>> {
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		let Ok(len) = usize::try_from(len) else {
+			return Err(io::ErrorKind::InvalidInput.into());
+		};
+		self.get_mut().truncate(len);
+		Ok(())
+	}
+}
+
+/// A page buffer that is aligned for direct I/O, which requires the memory
+/// of a write to be aligned to the block size of the device.
+#[derive(Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C, align(4096))]
+pub(crate) struct AlignedPage([u8; PAGE_SIZE]);
@@ -0,0 +1,359 @@
+use std::{
+	ffi::OsString,
+	fs::{self, File, OpenOptions},
+	io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+	os,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use super::{
+	segment::{SegmentFile, SegmentFileApi, SegmentPageSizes},
+	sync::sync_file,
+	utils::{SetLen, SyncData},
+	wal::{WalFile, WalFileApi},
+	DatabaseFolder, DatabaseFolderApi, Durability, FileError,
+};
+
+/// A file of a [`Vfs`]. Files are read and written at explicit offsets, so
+/// they can be shared between threads.
+pub trait VfsFile: Send + Sync {
+	/// Fills `buf` with the bytes at `offset`, or fails with
+	/// [`ErrorKind::UnexpectedEof`] if the file ends before.
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+	/// Writes all of `buf` at `offset`, growing the file if necessary.
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+	fn len(&self) -> io::Result<u64>;
+
+	fn is_empty(&self) -> io::Result<bool> {
+		Ok(self.len()? == 0)
+	}
+
+	fn set_len(&self, len: u64) -> io::Result<()>;
+
+	/// Waits until everything written to the file is durably stored.
+	fn sync_data(&self) -> io::Result<()>;
+}
+
+/// Where the files of a database are stored, if not in a folder of the local
+/// file system. Implementations can e.g. keep the files in object storage or
+/// on a block device, or wrap another `Vfs` to inject faults.
+///
+/// Paths are relative to the root of the database, like `segments/0`.
+pub trait Vfs: Send + Sync {
+	/// Opens the file at `path`, creating an empty one if it doesn't exist.
+	fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>>;
+
+	fn exists(&self, path: &Path) -> io::Result<bool>;
+
+	fn remove(&self, path: &Path) -> io::Result<()>;
+
+	/// The names of the files in the directory at `path`, in any order. A
+	/// directory that doesn't exist is empty.
+	fn list(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+	/// Makes the creation and removal of files in the directory at `path`
+	/// durable.
+	fn sync_dir(&self, _path: &Path) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl<F: VfsFile + ?Sized> VfsFile for Box<F> {
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		(**self).read_exact_at(buf, offset)
+	}
+
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+		(**self).write_all_at(buf, offset)
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		(**self).len()
+	}
+
+	fn set_len(&self, len: u64) -> io::Result<()> {
+		(**self).set_len(len)
+	}
+
+	fn sync_data(&self) -> io::Result<()> {
+		(**self).sync_data()
+	}
+}
+
+#[cfg(not(unix))]
+compile_error!("Functionality not implemented on this platform!");
+
+impl VfsFile for File {
+	#[cfg(unix)]
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+	}
+
+	#[cfg(unix)]
+	fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+		os::unix::fs::FileExt::write_all_at(self, buf, offset)
+	}
+
+	fn len(&self) -> io::Result<u64> {
+		Ok(self.metadata()?.len())
+	}
+
+	fn set_len(&self, len: u64) -> io::Result<()> {
+		File::set_len(self, len)
+	}
+
+	fn sync_data(&self) -> io::Result<()> {
+		sync_file(self)?;
+		Ok(())
+	}
+}
+
+/// A [`Vfs`] that stores the files in a folder of the local file system, in
+/// the same layout as [`Database::open`](crate::Database::open).
+#[derive(Debug, Clone)]
+pub struct OsVfs {
+	root: PathBuf,
+}
+
+impl OsVfs {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self { root: root.into() }
+	}
+}
+
+impl Vfs for OsVfs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn VfsFile>> {
+		let path = self.root.join(path);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		let file = OpenOptions::new()
+			.create(true)
+			.truncate(false)
+			.read(true)
+			.write(true)
+			.open(path)?;
+		Ok(Box::new(file))
+	}
+
+	fn exists(&self, path: &Path) -> io::Result<bool> {
+		self.root.join(path).try_exists()
+	}
+
+	fn remove(&self, path: &Path) -> io::Result<()> {
+		fs::remove_file(self.root.join(path))
+	}
+
+	fn list(&self, path: &Path) -> io::Result<Vec<OsString>> {
+		let entries = match fs::read_dir(self.root.join(path)) {
+			Ok(entries) => entries,
+			Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(err) => return Err(err),
+		};
+		let mut names = Vec::new();
+		for entry in entries {
+			let entry = entry?;
+			if entry.file_type()?.is_file() {
+				names.push(entry.file_name());
+			}
+		}
+		Ok(names)
+	}
+
+	fn sync_dir(&self, path: &Path) -> io::Result<()> {
+		File::open(self.root.join(path))?.sync_all()
+	}
+}
+
+/// Reads and writes a [`VfsFile`] at a position, like a [`File`], which is
+/// how WAL files are accessed.
+pub(crate) struct VfsCursor {
+	file: Box<dyn VfsFile>,
+	position: u64,
+}
+
+impl VfsCursor {
+	pub fn new(file: Box<dyn VfsFile>) -> Self {
+		Self { file, position: 0 }
+	}
+}
+
+impl Read for VfsCursor {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let remaining = self.file.len()?.saturating_sub(self.position);
+		let len = buf
+			.len()
+			.min(usize::try_from(remaining).unwrap_or(usize::MAX));
+		self.file.read_exact_at(&mut buf[..len], self.position)?;
+		self.position += len as u64;
+		Ok(len)
+	}
+}
+
+impl Write for VfsCursor {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.file.write_all_at(buf, self.position)?;
+		self.position += buf.len() as u64;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Seek for VfsCursor {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let position = match pos {
+			SeekFrom::Start(offset) => Some(offset),
+			SeekFrom::End(offset) => self.file.len()?.checked_add_signed(offset),
+			SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+		};
+		let Some(position) = position else {
+			return Err(io::Error::new(
+				ErrorKind::InvalidInput,
+				"Invalid seek to a negative position",
+			));
+		};
+		self.position = position;
+		Ok(position)
+	}
+}
+
+impl SyncData for VfsCursor {
+	fn sync_data(&mut self) -> io::Result<()> {
+		self.file.sync_data()
+	}
+}
+
+impl SetLen for VfsCursor {
+	fn set_len(&mut self, len: u64) -> io::Result<()> {
+		self.file.set_len(len)
+	}
+}
+
+/// A database folder whose files are stored in a [`Vfs`]. Encryption and
+/// WAL archiving are not supported.
+pub(crate) struct VfsFolder {
+	vfs: Arc<dyn Vfs>,
+	durability: Durability,
+	wal_compression: bool,
+	segment_page_sizes: SegmentPageSizes,
+}
+
+impl VfsFolder {
+	pub fn new(vfs: Arc<dyn Vfs>) -> Self {
+		Self {
+			vfs,
+			durability: Durability::default(),
+			wal_compression: false,
+			segment_page_sizes: SegmentPageSizes::default(),
+		}
+	}
+
+	pub fn with_durability(mut self, durability: Durability) -> Self {
+		self.durability = durability;
+		self
+	}
+
+	pub fn with_wal_compression(mut self, compress: bool) -> Self {
+		self.wal_compression = compress;
+		self
+	}
+
+	pub fn with_segment_page_sizes(mut self, page_sizes: SegmentPageSizes) -> Self {
+		self.segment_page_sizes = page_sizes;
+		self
+	}
+
+	fn sync_dir(&self, path: &Path) -> Result<(), FileError> {
+		if self.durability.syncs() {
+			self.vfs.sync_dir(path)?;
+		}
+		Ok(())
+	}
+
+	fn list_nums<T: std::str::FromStr>(&self, path: &Path) -> Result<Vec<T>, FileError> {
+		let mut nums = Vec::new();
+		for name in self.vfs.list(path)? {
+			let Ok(num) = name.to_string_lossy().parse() else {
+				return Err(FileError::UnexpectedFile(name));
+			};
+			nums.push(num);
+		}
+		Ok(nums)
+	}
+}
+
+impl DatabaseFolderApi for VfsFolder {
+	type SegmentFile = SegmentFile<Box<dyn VfsFile>>;
+	type WalFile = WalFile<VfsCursor>;
+	type IterWalFiles = std::vec::IntoIter<Result<(u64, Self::WalFile), FileError>>;
+
+	fn open_segment_file(&self, segment_num: u32) -> Result<Self::SegmentFile, FileError> {
+		let dir = Path::new(DatabaseFolder::SEGMENTS_DIR_NAME);
+		let path = dir.join(segment_num.to_string());
+		let page_size = self.segment_page_sizes.page_size(segment_num);
+		if self.vfs.exists(&path)? {
+			return SegmentFile::open(self.vfs.open(&path)?, None, page_size);
+		}
+		// New segments are synced whatever the durability mode, like those of
+		// a `DatabaseFolder`. A VFS can't rename files, but a segment file
+		// whose creation was interrupted is created again when it is opened.
+		let file = SegmentFile::create(self.vfs.open(&path)?, None, page_size)?;
+		file.sync()?;
+		self.vfs.sync_dir(dir)?;
+		Ok(file)
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, FileError> {
+		let mut segment_nums = self.list_nums(Path::new(DatabaseFolder::SEGMENTS_DIR_NAME))?;
+		segment_nums.sort_unstable();
+		Ok(segment_nums)
+	}
+
+	fn open_wal_file(&self, generation: u64) -> Result<Self::WalFile, FileError> {
+		let dir = Path::new(DatabaseFolder::WAL_DIR_NAME);
+		let path = dir.join(generation.to_string());
+		if self.vfs.exists(&path)? {
+			return WalFile::open(VfsCursor::new(self.vfs.open(&path)?));
+		}
+		let mut file = WalFile::create_with_options(
+			VfsCursor::new(self.vfs.open(&path)?),
+			None,
+			self.wal_compression,
+		)?;
+		if self.durability.syncs() {
+			file.sync()?;
+			self.sync_dir(dir)?;
+		}
+		Ok(file)
+	}
+
+	fn delete_wal_file(&self, generation: u64) -> Result<(), FileError> {
+		let dir = Path::new(DatabaseFolder::WAL_DIR_NAME);
+		self.vfs.remove(&dir.join(generation.to_string()))?;
+		self.sync_dir(dir)
+	}
+
+	fn iter_wal_files(&self) -> Result<Self::IterWalFiles, FileError> {
+		let mut generations: Vec<u64> = self.list_nums(Path::new(DatabaseFolder::WAL_DIR_NAME))?;
+		generations.sort_unstable();
+		let files: Vec<_> = generations
+			.into_iter()
+			.map(|generation| Ok((generation, self.open_wal_file(generation)?)))
+			.collect();
+		Ok(files.into_iter())
+	}
+
+	fn clear_wal_files(&self) -> Result<(), FileError> {
+		let dir = Path::new(DatabaseFolder::WAL_DIR_NAME);
+		for generation in self.list_nums::<u64>(dir)? {
+			self.vfs.remove(&dir.join(generation.to_string()))?;
+		}
+		self.sync_dir(dir)
+	}
+}
@@ -2,92 +2,148 @@ use std::{
 	borrow::Cow,
 	collections::HashMap,
 	fs::{File, OpenOptions},
-	io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
+	io::{self, BufReader, Read, Seek, SeekFrom, Write},
+	mem,
 	num::{NonZeroU16, NonZeroU64},
 	path::Path,
+	sync::Arc,
 };
 
 use static_assertions::assert_impl_all;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
-const FORMAT_VERSION: u8 = 1;
+/// Version 2 added feature flags to the header, and version 3 the next
+/// transaction id to checkpoints.
+pub(crate) const FORMAT_VERSION: u8 = 3;
+/// The oldest version of WAL files that can still be read. Items are
+/// appended to existing files in the format of their version, and new
+/// generations are created in the current one.
+pub(crate) const MIN_FORMAT_VERSION: u8 = 1;
+/// The first version whose checkpoints hold the next transaction id.
+const CHECKPOINT_TRANSACTION_ID_VERSION: u8 = 3;
+pub(crate) const SUPPORTED_FEATURES: FeatureFlags = FeatureFlags {
+	required: FEATURE_ENCRYPTED | FEATURE_COMPRESSED,
+	optional: 0,
+};
 
 #[cfg(test)]
 use mockall::automock;
 
 use crate::{
-	repr::{IoRepr, Repr},
+	failpoints::failpoint,
+	repr::{IoRepr, Repr, U16, U32, U64},
 	utils::units::MIB,
 };
 
 use super::{
-	generic::{FileType, GenericHeader, GenericHeaderRepr},
-	utils::CRC32,
+	crypto::{self, FileCipher},
+	generic::{
+		FeatureFlags, FileType, GenericHeader, GenericHeaderRepr, FEATURE_COMPRESSED,
+		FEATURE_ENCRYPTED,
+	},
+	retry::Retrier,
+	utils::{SetLen, SyncData, CRC32},
 	FileError, PageId, TransactionState, WalIndex,
 };
 
 const FLAG_UNDO: u8 = 0b00000001;
+const FLAG_COMPRESSED: u8 = 0b00000010;
+
+/// Write items with smaller bodies are never compressed, since compressing
+/// them hardly saves anything.
+const COMPRESSION_THRESHOLD: usize = 512;
+const COMPRESSION_LEVEL: i32 = 1;
+/// Items are compressed only if their uncompressed body would fit in an
+/// item, so that reading them can rely on this limit.
+const MAX_BODY_LENGTH: usize = u16::MAX as usize;
+/// The number of bytes that a write item takes up in addition to the
+/// content of the page before and after the write.
+pub(crate) const WRITE_ITEM_OVERHEAD: usize = mem::size_of::<ItemHeaderRepr>()
+	+ mem::size_of::<ItemFooterRepr>()
+	+ mem::size_of::<TransactionBlockRepr>()
+	+ mem::size_of::<WriteBlockRepr>();
+/// The largest data of a custom record that fits in a single item.
+pub(crate) const MAX_CUSTOM_RECORD_SIZE: usize =
+	MAX_BODY_LENGTH - mem::size_of::<TransactionBlockRepr>() - mem::size_of::<CustomBlockRepr>();
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 struct ItemHeaderRepr {
 	kind: u8,
 	flags: u8,
-	body_length: u16,
-	crc: u32,
-	prev_item: Option<NonZeroU64>,
+	body_length: U16,
+	crc: U32,
+	prev_item: U64,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 struct ItemFooterRepr {
-	item_start: u64,
+	item_start: U64,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 struct TransactionBlockRepr {
-	transaction_id: u64,
-	prev_transaction_generation: u64,
-	prev_transaction_offset: Option<NonZeroU64>,
+	transaction_id: U64,
+	prev_transaction_generation: U64,
+	prev_transaction_offset: U64,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C, packed)]
 struct WriteBlockRepr {
-	segment_num: u32,
-	page_num: u16,
-	offset: u16,
-	write_length: u16,
+	segment_num: U32,
+	page_num: U16,
+	offset: U16,
+	write_length: U16,
+}
+
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C, packed)]
+struct CustomBlockRepr {
+	record_type: U16,
+	version: U16,
+	data_length: U16,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 struct CheckpointBlockRepr {
-	num_dirty_pages: u64,
-	num_transactions: u64,
+	next_transaction_id: U64,
+	num_dirty_pages: U64,
+	num_transactions: U64,
+}
+
+/// The checkpoint block of versions before
+/// [`CHECKPOINT_TRANSACTION_ID_VERSION`].
+#[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct LegacyCheckpointBlockRepr {
+	num_dirty_pages: U64,
+	num_transactions: U64,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C, packed)]
 struct PageIdRepr {
-	segment_num: u32,
-	page_num: u16,
+	segment_num: U32,
+	page_num: U16,
 }
 
 #[derive(Debug, Clone, FromZeroes, FromBytes, AsBytes)]
 #[repr(C)]
 struct WalIndexRepr {
-	generation: u64,
-	offset: u64,
+	generation: U64,
+	offset: U64,
 }
 
 #[derive(Debug, Clone, FromBytes, FromZeroes, AsBytes)]
 #[repr(C)]
 struct TransactionStateRepr {
-	first_generation: u64,
-	last_generation: u64,
-	last_offset: u64,
+	first_generation: U64,
+	last_generation: U64,
+	last_offset: U64,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -96,6 +152,7 @@ enum ItemKind {
 	Write = 0,
 	Commit = 1,
 	Checkpoint = 2,
+	Custom = 3,
 }
 
 impl TryFrom<u8> for ItemKind {
@@ -106,6 +163,7 @@ impl TryFrom<u8> for ItemKind {
 			0 => Ok(Self::Write),
 			1 => Ok(Self::Commit),
 			2 => Ok(Self::Checkpoint),
+			3 => Ok(Self::Custom),
 			_ => Err(FileError::Corrupted(format!(
 				"Unknown WAL item kind {value}"
 			))),
@@ -127,9 +185,9 @@ impl From<ItemHeader> for ItemHeaderRepr {
 		Self {
 			kind: value.kind as u8,
 			flags: value.flags,
-			body_length: value.body_length,
-			crc: value.crc,
-			prev_item: value.prev_item,
+			body_length: value.body_length.into(),
+			crc: value.crc.into(),
+			prev_item: value.prev_item.map_or(0, NonZeroU64::get).into(),
 		}
 	}
 }
@@ -141,9 +199,9 @@ impl TryFrom<ItemHeaderRepr> for ItemHeader {
 		Ok(Self {
 			kind: ItemKind::try_from(value.kind)?,
 			flags: value.flags,
-			body_length: value.body_length,
-			crc: value.crc,
-			prev_item: value.prev_item,
+			body_length: value.body_length.get(),
+			crc: value.crc.get(),
+			prev_item: NonZeroU64::new(value.prev_item.get()),
 		})
 	}
 }
@@ -160,7 +218,7 @@ struct ItemFooter {
 impl From<ItemFooter> for ItemFooterRepr {
 	fn from(value: ItemFooter) -> Self {
 		Self {
-			item_start: value.item_start.get(),
+			item_start: value.item_start.get().into(),
 		}
 	}
 }
@@ -169,7 +227,7 @@ impl TryFrom<ItemFooterRepr> for ItemFooter {
 	type Error = FileError;
 
 	fn try_from(value: ItemFooterRepr) -> Result<Self, Self::Error> {
-		let Some(item_start) = NonZeroU64::new(value.item_start) else {
+		let Some(item_start) = NonZeroU64::new(value.item_start.get()) else {
 			return Err(FileError::Corrupted(
 				"WAL items cannot start at position 0".to_string(),
 			));
@@ -190,12 +248,16 @@ struct TransactionBlock {
 impl From<TransactionBlock> for TransactionBlockRepr {
 	fn from(value: TransactionBlock) -> Self {
 		Self {
-			transaction_id: value.transaction_id,
+			transaction_id: value.transaction_id.into(),
 			prev_transaction_generation: value
 				.prev_transaction_item
 				.map(|idx| idx.generation)
-				.unwrap_or_default(),
-			prev_transaction_offset: value.prev_transaction_item.map(|idx| idx.offset),
+				.unwrap_or_default()
+				.into(),
+			prev_transaction_offset: value
+				.prev_transaction_item
+				.map_or(0, |idx| idx.offset.get())
+				.into(),
 		}
 	}
 }
@@ -203,10 +265,9 @@ impl From<TransactionBlock> for TransactionBlockRepr {
 impl From<TransactionBlockRepr> for TransactionBlock {
 	fn from(value: TransactionBlockRepr) -> Self {
 		Self {
-			transaction_id: value.transaction_id,
-			prev_transaction_item: value
-				.prev_transaction_offset
-				.map(|offset| WalIndex::new(value.prev_transaction_generation, offset)),
+			transaction_id: value.transaction_id.get(),
+			prev_transaction_item: NonZeroU64::new(value.prev_transaction_offset.get())
+				.map(|offset| WalIndex::new(value.prev_transaction_generation.get(), offset)),
 		}
 	}
 }
@@ -225,10 +286,10 @@ struct WriteBlock {
 impl From<WriteBlock> for WriteBlockRepr {
 	fn from(value: WriteBlock) -> Self {
 		Self {
-			segment_num: value.page_id.segment_num,
-			page_num: value.page_id.page_num.get(),
-			offset: value.offset,
-			write_length: value.write_length,
+			segment_num: value.page_id.segment_num.into(),
+			page_num: value.page_id.page_num.get().into(),
+			offset: value.offset.into(),
+			write_length: value.write_length.into(),
 		}
 	}
 }
@@ -237,15 +298,15 @@ impl TryFrom<WriteBlockRepr> for WriteBlock {
 	type Error = FileError;
 
 	fn try_from(value: WriteBlockRepr) -> Result<Self, Self::Error> {
-		let Some(page_num) = NonZeroU16::new(value.page_num) else {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
 			return Err(FileError::Corrupted(
 				"0 is not a valid page number".to_string(),
 			));
 		};
 		Ok(Self {
-			page_id: PageId::new(value.segment_num, page_num),
-			offset: value.offset,
-			write_length: value.write_length,
+			page_id: PageId::new(value.segment_num.get(), page_num),
+			offset: value.offset.get(),
+			write_length: value.write_length.get(),
 		})
 	}
 }
@@ -254,17 +315,96 @@ impl Repr<WriteBlock> for WriteBlockRepr {
 	type Error = FileError;
 }
 
-type CheckpointBlock = CheckpointBlockRepr;
+struct CustomBlock {
+	record_type: u16,
+	version: u16,
+	data_length: u16,
+}
+
+impl From<CustomBlock> for CustomBlockRepr {
+	fn from(value: CustomBlock) -> Self {
+		Self {
+			record_type: value.record_type.into(),
+			version: value.version.into(),
+			data_length: value.data_length.into(),
+		}
+	}
+}
+
+impl From<CustomBlockRepr> for CustomBlock {
+	fn from(value: CustomBlockRepr) -> Self {
+		Self {
+			record_type: value.record_type.get(),
+			version: value.version.get(),
+			data_length: value.data_length.get(),
+		}
+	}
+}
+
+impl Repr<CustomBlock> for CustomBlockRepr {
+	type Error = FileError;
+}
+
+struct CheckpointBlock {
+	next_transaction_id: u64,
+	num_dirty_pages: u64,
+	num_transactions: u64,
+}
+
+impl From<CheckpointBlock> for CheckpointBlockRepr {
+	fn from(value: CheckpointBlock) -> Self {
+		Self {
+			next_transaction_id: value.next_transaction_id.into(),
+			num_dirty_pages: value.num_dirty_pages.into(),
+			num_transactions: value.num_transactions.into(),
+		}
+	}
+}
+
+impl From<CheckpointBlockRepr> for CheckpointBlock {
+	fn from(value: CheckpointBlockRepr) -> Self {
+		Self {
+			next_transaction_id: value.next_transaction_id.get(),
+			num_dirty_pages: value.num_dirty_pages.get(),
+			num_transactions: value.num_transactions.get(),
+		}
+	}
+}
 
 impl Repr<CheckpointBlock> for CheckpointBlockRepr {
 	type Error = FileError;
 }
 
+impl From<CheckpointBlock> for LegacyCheckpointBlockRepr {
+	fn from(value: CheckpointBlock) -> Self {
+		Self {
+			num_dirty_pages: value.num_dirty_pages.into(),
+			num_transactions: value.num_transactions.into(),
+		}
+	}
+}
+
+impl From<LegacyCheckpointBlockRepr> for CheckpointBlock {
+	fn from(value: LegacyCheckpointBlockRepr) -> Self {
+		Self {
+			// Recovery advances the next transaction id past the transactions
+			// in the log anyway.
+			next_transaction_id: 0,
+			num_dirty_pages: value.num_dirty_pages.get(),
+			num_transactions: value.num_transactions.get(),
+		}
+	}
+}
+
+impl Repr<CheckpointBlock> for LegacyCheckpointBlockRepr {
+	type Error = FileError;
+}
+
 impl From<PageId> for PageIdRepr {
 	fn from(value: PageId) -> Self {
 		Self {
-			segment_num: value.segment_num,
-			page_num: value.page_num.get(),
+			segment_num: value.segment_num.into(),
+			page_num: value.page_num.get().into(),
 		}
 	}
 }
@@ -273,12 +413,12 @@ impl TryFrom<PageIdRepr> for PageId {
 	type Error = FileError;
 
 	fn try_from(value: PageIdRepr) -> Result<Self, Self::Error> {
-		let Some(page_num) = NonZeroU16::new(value.page_num) else {
+		let Some(page_num) = NonZeroU16::new(value.page_num.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid page number 0".to_string(),
 			));
 		};
-		Ok(PageId::new(value.segment_num, page_num))
+		Ok(PageId::new(value.segment_num.get(), page_num))
 	}
 }
 
@@ -289,8 +429,8 @@ impl Repr<PageId> for PageIdRepr {
 impl From<WalIndex> for WalIndexRepr {
 	fn from(value: WalIndex) -> Self {
 		Self {
-			offset: value.offset.get(),
-			generation: value.generation,
+			offset: value.offset.get().into(),
+			generation: value.generation.into(),
 		}
 	}
 }
@@ -299,13 +439,13 @@ impl TryFrom<WalIndexRepr> for WalIndex {
 	type Error = FileError;
 
 	fn try_from(value: WalIndexRepr) -> Result<Self, Self::Error> {
-		let Some(offset) = NonZeroU64::new(value.offset) else {
+		let Some(offset) = NonZeroU64::new(value.offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self {
-			generation: value.generation,
+			generation: value.generation.get(),
 			offset,
 		})
 	}
@@ -318,9 +458,9 @@ impl Repr<WalIndex> for WalIndexRepr {
 impl From<TransactionState> for TransactionStateRepr {
 	fn from(value: TransactionState) -> Self {
 		Self {
-			first_generation: value.first_gen,
-			last_generation: value.last_index.generation,
-			last_offset: value.last_index.offset.get(),
+			first_generation: value.first_gen.into(),
+			last_generation: value.last_index.generation.into(),
+			last_offset: value.last_index.offset.get().into(),
 		}
 	}
 }
@@ -333,14 +473,14 @@ impl TryFrom<TransactionStateRepr> for TransactionState {
 	type Error = FileError;
 
 	fn try_from(value: TransactionStateRepr) -> Result<Self, Self::Error> {
-		let Some(last_offset) = NonZeroU64::new(value.last_offset) else {
+		let Some(last_offset) = NonZeroU64::new(value.last_offset.get()) else {
 			return Err(FileError::Corrupted(
 				"Found invalid WAL offset '0'".to_string(),
 			));
 		};
 		Ok(Self {
-			first_gen: value.first_generation,
-			last_index: WalIndex::new(value.last_generation, last_offset),
+			first_gen: value.first_generation.get(),
+			last_index: WalIndex::new(value.last_generation.get(), last_offset),
 		})
 	}
 }
@@ -349,78 +489,292 @@ const WRITE_BUF_LIMIT: usize = 2 * MIB;
 
 pub(crate) struct WalFile<F: Seek + Read + Write = File> {
 	body_start: u64,
+	version: u8,
+	features: FeatureFlags,
 	prev_item: Option<NonZeroU64>,
 	write_buf: Vec<u8>,
 	file: F,
 	next_offset: NonZeroU64,
+	torn_tail_len: u64,
+	retrier: Arc<Retrier>,
+	cipher: Option<FileCipher>,
 }
 assert_impl_all!(WalFile: Send, Sync);
 
 impl WalFile {
 	pub fn create_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
-		Self::create(
+		Self::create_file_with_cipher(path, None)
+	}
+
+	pub fn create_file_with_cipher(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
+		Self::create_file_with_options(path, cipher, false)
+	}
+
+	pub fn create_file_with_options(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+		compress: bool,
+	) -> Result<Self, FileError> {
+		Self::create_with_options(
 			OpenOptions::new()
 				.create(true)
 				.truncate(true)
 				.read(true)
 				.write(true)
 				.open(path)?,
+			cipher,
+			compress,
 		)
 	}
 
 	pub fn open_file(path: impl AsRef<Path>) -> Result<Self, FileError> {
-		Self::open(OpenOptions::new().read(true).write(true).open(path)?)
+		Self::open_file_with_cipher(path, None)
+	}
+
+	pub fn open_file_with_cipher(
+		path: impl AsRef<Path>,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
+		Self::open_with_cipher(
+			OpenOptions::new().read(true).write(true).open(path)?,
+			cipher,
+		)
 	}
 }
 
 impl<F: Seek + Read + Write> WalFile<F> {
-	fn create(mut file: F) -> Result<Self, FileError> {
+	pub fn create(file: F) -> Result<Self, FileError> {
+		Self::create_with_cipher(file, None)
+	}
+
+	/// Creates a WAL file whose item bodies are encrypted with `cipher`, if
+	/// it is given.
+	pub fn create_with_cipher(file: F, cipher: Option<FileCipher>) -> Result<Self, FileError> {
+		Self::create_with_options(file, cipher, false)
+	}
+
+	/// Creates a WAL file like [`WalFile::create_with_cipher`]. If `compress`
+	/// is set, large write items are compressed before they are encrypted.
+	pub fn create_with_options(
+		mut file: F,
+		cipher: Option<FileCipher>,
+		compress: bool,
+	) -> Result<Self, FileError> {
 		file.seek(SeekFrom::Start(0))?;
 		let content_offset = u16::try_from(GenericHeaderRepr::SIZE).unwrap();
+		let mut features = FeatureFlags::new(cipher.is_some());
+		if compress {
+			features = features.with_compression();
+		}
 		let meta = GenericHeader {
 			file_type: FileType::Wal,
 			content_offset,
 			version: FORMAT_VERSION,
+			features,
 		};
 		GenericHeaderRepr::serialize(meta, &mut file)?;
-		Self::new(file, content_offset.into())
+		Self::new(
+			file,
+			content_offset.into(),
+			FORMAT_VERSION,
+			features,
+			None,
+			cipher,
+		)
+	}
+
+	pub fn open(file: F) -> Result<Self, FileError>
+	where
+		F: SetLen,
+	{
+		Self::open_with_cipher(file, None)
 	}
 
-	fn open(mut file: F) -> Result<Self, FileError> {
+	/// Opens an existing WAL file. If the last write to it was interrupted,
+	/// the partially written items at its end are cut off. Encrypted files
+	/// are decrypted with `cipher`.
+	pub fn open_with_cipher(mut file: F, cipher: Option<FileCipher>) -> Result<Self, FileError>
+	where
+		F: SetLen,
+	{
+		// A crash while the file was created may have left its header
+		// incomplete. It can't hold any items then, so it is created again.
+		if file.seek(SeekFrom::End(0))? < GenericHeaderRepr::SIZE as u64 {
+			file.set_len(0)?;
+			return Self::create_with_cipher(file, cipher);
+		}
 		file.seek(SeekFrom::Start(0))?;
-		let header = GenericHeaderRepr::deserialize(&mut file)?;
+		let header = GenericHeader::read(&mut file)?;
 		if header.file_type != FileType::Wal {
 			return Err(FileError::WrongFileType(header.file_type));
 		}
-		if header.version != FORMAT_VERSION {
+		if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&header.version) {
 			return Err(FileError::IncompatibleVersion(
 				header.file_type,
 				header.version,
 			));
 		}
+		header.check_features(SUPPORTED_FEATURES)?;
+		match (header.features.is_encrypted(), &cipher) {
+			(true, None) => return Err(FileError::MissingKey),
+			(false, Some(..)) => return Err(FileError::NotEncrypted),
+			_ => (),
+		}
 
-		Self::new(file, header.content_offset.into())
+		let body_start = header.content_offset.into();
+		let file_len = file.seek(SeekFrom::End(0))?;
+		if body_start < header.size() as u64 || body_start > file_len {
+			return Err(FileError::Corrupted(format!(
+				"WAL content offset {body_start} is out of bounds"
+			)));
+		}
+		let (prev_item, body_end) = Self::find_last_item(
+			&mut file,
+			body_start,
+			file_len,
+			header.version,
+			cipher.as_ref(),
+		)?;
+		if body_end < file_len {
+			file.set_len(body_end)?;
+		}
+		let mut wal_file = Self::new(
+			file,
+			body_start,
+			header.version,
+			header.features,
+			prev_item,
+			cipher,
+		)?;
+		wal_file.torn_tail_len = file_len - body_end;
+		Ok(wal_file)
 	}
 
-	fn new(mut file: F, body_start: u64) -> Result<Self, FileError> {
-		let prev_footer_start =
-			file.seek(SeekFrom::End(-i64::try_from(ItemFooterRepr::SIZE).unwrap()))?;
-		let prev_item = if prev_footer_start > body_start {
-			let footer = ItemFooterRepr::deserialize(&mut file)?;
-			Some(footer.item_start)
-		} else {
-			None
-		};
+	fn new(
+		mut file: F,
+		body_start: u64,
+		version: u8,
+		features: FeatureFlags,
+		prev_item: Option<NonZeroU64>,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
 		let next_offset = NonZeroU64::new(file.seek(SeekFrom::End(0))?).unwrap();
 		Ok(Self {
 			body_start,
+			version,
+			features,
 			file,
 			write_buf: Vec::new(),
 			prev_item,
 			next_offset,
+			torn_tail_len: 0,
+			retrier: Arc::default(),
+			cipher,
 		})
 	}
 
+	/// Returns the offset of the last intact item, and where it ends.
+	///
+	/// Usually, that is the item that the footer at the end of the file points
+	/// to. If writing the last items was interrupted, that footer may be
+	/// missing or point to garbage, and the items are checked from the start
+	/// instead, up to the first one that is incomplete or fails its checksum.
+	fn find_last_item(
+		file: &mut F,
+		body_start: u64,
+		file_len: u64,
+		version: u8,
+		cipher: Option<&FileCipher>,
+	) -> Result<(Option<NonZeroU64>, u64), FileError> {
+		if file_len == body_start {
+			return Ok((None, body_start));
+		}
+		if let Some(item_start) = Self::check_last_item(file, body_start, file_len)? {
+			return Ok((Some(item_start), file_len));
+		}
+		Self::find_first_corrupt_item(file, body_start, file_len, version, cipher)
+	}
+
+	/// Checks the items from the start of the file, and returns the offset of
+	/// the last one before the first item that is incomplete or corrupted, and
+	/// where it ends.
+	fn find_first_corrupt_item(
+		file: &mut F,
+		body_start: u64,
+		file_len: u64,
+		version: u8,
+		cipher: Option<&FileCipher>,
+	) -> Result<(Option<NonZeroU64>, u64), FileError> {
+		file.seek(SeekFrom::Start(body_start))?;
+		let mut reader = ItemReader::new(&mut *file, None, version, cipher.cloned())?;
+		let mut buf = Vec::new();
+		let mut last_item = None;
+		let mut body_end = body_start;
+		loop {
+			match reader.read_item_exact(&mut buf) {
+				// The footer is skipped rather than read, so it may be missing.
+				Ok((offset, _)) if reader.offset <= file_len => {
+					last_item = Some(offset);
+					body_end = reader.offset;
+				}
+				Ok(..)
+				| Err(
+					FileError::UnexpectedEof
+					| FileError::ChecksumMismatch
+					| FileError::Corrupted(..),
+				) => break,
+				Err(error) => return Err(error),
+			}
+		}
+		Ok((last_item, body_end))
+	}
+
+	/// Returns the offset of the item that the footer at the end of the file
+	/// points to, if that item is intact and ends with the file.
+	fn check_last_item(
+		file: &mut F,
+		body_start: u64,
+		file_len: u64,
+	) -> Result<Option<NonZeroU64>, FileError> {
+		let min_item_size = (ItemHeaderRepr::SIZE + ItemFooterRepr::SIZE) as u64;
+		if file_len < body_start + min_item_size {
+			return Ok(None);
+		}
+		file.seek(SeekFrom::Start(file_len - ItemFooterRepr::SIZE as u64))?;
+		let item_start = match ItemFooterRepr::deserialize(&mut *file) {
+			Ok(footer) => footer.item_start,
+			Err(FileError::Corrupted(..)) => return Ok(None),
+			Err(error) => return Err(error),
+		};
+		if item_start.get() < body_start || item_start.get() > file_len - min_item_size {
+			return Ok(None);
+		}
+
+		file.seek(SeekFrom::Start(item_start.get()))?;
+		let header = match ItemHeaderRepr::deserialize(&mut *file) {
+			Ok(header) => header,
+			Err(FileError::Corrupted(..)) => return Ok(None),
+			Err(error) => return Err(error),
+		};
+		if item_start.get() + min_item_size + u64::from(header.body_length) != file_len {
+			return Ok(None);
+		}
+		let mut body = vec![0; header.body_length.into()];
+		file.read_exact(&mut body)?;
+		if CRC32.checksum(&body) != header.crc {
+			return Ok(None);
+		}
+		Ok(Some(item_start))
+	}
+
+	pub fn with_retrier(mut self, retrier: Arc<Retrier>) -> Self {
+		self.retrier = retrier;
+		self
+	}
+
 	fn write_transaction_block(writer: impl Write, data: TransactionData) -> Result<(), FileError> {
 		let block = TransactionBlock {
 			transaction_id: data.transaction_id,
@@ -451,21 +805,44 @@ impl<F: Seek + Read + Write> WalFile<F> {
 		Ok(())
 	}
 
+	fn write_custom_block(mut writer: impl Write, data: CustomData) -> Result<(), FileError> {
+		Self::write_transaction_block(&mut writer, data.transaction_data)?;
+
+		let block = CustomBlock {
+			record_type: data.record_type,
+			version: data.version,
+			data_length: data
+				.data
+				.len()
+				.try_into()
+				.expect("Custom record length must be 16-bit!"),
+		};
+		CustomBlockRepr::serialize(block, &mut writer)?;
+		writer.write_all(&data.data)?;
+		Ok(())
+	}
+
 	fn write_checkpoint_block(
 		mut writer: impl Write,
 		data: CheckpointData,
+		version: u8,
 	) -> Result<(), FileError> {
 		let block = CheckpointBlock {
+			next_transaction_id: data.next_transaction_id,
 			num_dirty_pages: data.dirty_pages.len() as u64,
 			num_transactions: data.transactions.len() as u64,
 		};
-		CheckpointBlockRepr::serialize(block, &mut writer)?;
+		if version >= CHECKPOINT_TRANSACTION_ID_VERSION {
+			CheckpointBlockRepr::serialize(block, &mut writer)?;
+		} else {
+			LegacyCheckpointBlockRepr::serialize(block, &mut writer)?;
+		}
 		for (page_id, wal_index) in data.dirty_pages.iter() {
 			PageIdRepr::serialize(*page_id, &mut writer)?;
 			WalIndexRepr::serialize(*wal_index, &mut writer)?;
 		}
 		for (transaction_id, transaction_state) in data.transactions.iter() {
-			writer.write_all(transaction_id.as_bytes())?;
+			writer.write_all(U64::new(*transaction_id).as_bytes())?;
 			TransactionStateRepr::serialize(transaction_state.clone(), &mut writer)?;
 		}
 
@@ -488,8 +865,21 @@ pub(crate) struct WriteData<'a> {
 	pub to: Cow<'a, [u8]>,
 }
 
+/// A record of a type that is defined outside of the WAL, see
+/// [`WalRecordHandler`](crate::WalRecordHandler).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CustomData<'a> {
+	pub transaction_data: TransactionData,
+	pub record_type: u16,
+	pub version: u16,
+	pub data: Cow<'a, [u8]>,
+	/// Whether the item records that the record was undone.
+	pub undo: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct CheckpointData<'a> {
+	pub next_transaction_id: u64,
 	pub transactions: Cow<'a, HashMap<u64, TransactionState>>,
 	pub dirty_pages: Cow<'a, HashMap<PageId, WalIndex>>,
 }
@@ -499,6 +889,52 @@ pub(crate) enum Item<'a> {
 	Write(WriteData<'a>),
 	Commit(TransactionData),
 	Checkpoint(CheckpointData<'a>),
+	Custom(CustomData<'a>),
+}
+
+impl Item<'_> {
+	pub fn into_owned(self) -> Item<'static> {
+		match self {
+			Self::Write(data) => Item::Write(WriteData {
+				transaction_data: data.transaction_data,
+				page_id: data.page_id,
+				offset: data.offset,
+				from: data.from.map(|from| Cow::Owned(from.into_owned())),
+				to: Cow::Owned(data.to.into_owned()),
+			}),
+			Self::Commit(data) => Item::Commit(data),
+			Self::Checkpoint(data) => Item::Checkpoint(CheckpointData {
+				next_transaction_id: data.next_transaction_id,
+				transactions: Cow::Owned(data.transactions.into_owned()),
+				dirty_pages: Cow::Owned(data.dirty_pages.into_owned()),
+			}),
+			Self::Custom(data) => Item::Custom(CustomData {
+				data: Cow::Owned(data.data.into_owned()),
+				..data
+			}),
+		}
+	}
+}
+
+/// A sequence of WAL items that lends each item from a buffer provided by the
+/// caller, so that reading many items doesn't need an allocation per item.
+pub(crate) trait ItemStream {
+	fn next_into<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError>;
+}
+
+impl<I> ItemStream for I
+where
+	I: Iterator<Item = Result<(NonZeroU64, Item<'static>), FileError>>,
+{
+	fn next_into<'b>(
+		&mut self,
+		_buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError> {
+		self.next().transpose()
+	}
 }
 
 #[cfg_attr(test, automock(
@@ -507,27 +943,56 @@ pub(crate) enum Item<'a> {
 ), allow(clippy::type_complexity))]
 #[allow(clippy::needless_lifetimes)]
 pub(crate) trait WalFileApi {
-	type IterItems<'a>: Iterator<Item = Result<(NonZeroU64, Item<'static>), FileError>> + 'a
+	type IterItems<'a>: ItemStream + 'a
 	where
 		Self: 'a;
-	type IterItemsReverse<'a>: Iterator<Item = Result<(NonZeroU64, Item<'static>), FileError>> + 'a
+	type IterItemsReverse<'a>: ItemStream + 'a
 	where
 		Self: 'a;
 
 	fn push_item<'a>(&mut self, item: Item<'a>) -> Result<NonZeroU64, FileError>;
 	fn flush(&mut self) -> Result<(), FileError>;
+	/// Flushes the file, and waits until its content is durably stored.
+	fn sync(&mut self) -> Result<(), FileError>;
 	fn read_item_at(&mut self, offset: NonZeroU64) -> Result<Item<'static>, FileError>;
 	fn iter_items<'a>(&'a mut self) -> Result<Self::IterItems<'a>, FileError>;
+	/// Iterates over the items starting with the one at `offset`, which must
+	/// be the offset of an item or the end of the file.
+	fn iter_items_from<'a>(
+		&'a mut self,
+		offset: NonZeroU64,
+	) -> Result<Self::IterItems<'a>, FileError>;
 	fn iter_items_reverse<'a>(&'a mut self) -> Result<Self::IterItemsReverse<'a>, FileError>;
 	fn next_offset(&self) -> NonZeroU64;
 	fn size(&self) -> usize;
+	/// The memory allocated for items that are not written to the file yet,
+	/// in bytes.
+	fn buffer_capacity(&self) -> usize;
+	fn unknown_features(&self) -> FeatureFlags;
+
+	/// The number of bytes of partially written items that were cut off the
+	/// end of the file when it was opened.
+	fn torn_tail_len(&self) -> u64;
+
+	/// Cuts off the file at the first item that is corrupted, so that all
+	/// items before it can still be read. Returns the offset the file was cut
+	/// at and the number of bytes that were discarded, if any were.
+	fn salvage(&mut self) -> Result<Option<(NonZeroU64, u64)>, FileError>;
 }
 
-impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
-	type IterItems<'a> = IterItems<&'a mut F> where F: 'a;
-	type IterItemsReverse<'a> = IterItemsReverse<&'a mut F> where F: 'a;
+impl<F: Seek + Read + Write + SyncData + SetLen> WalFileApi for WalFile<F> {
+	type IterItems<'a>
+		= IterItems<&'a mut F>
+	where
+		F: 'a;
+	type IterItemsReverse<'a>
+		= IterItemsReverse<&'a mut F>
+	where
+		F: 'a;
 
 	fn push_item(&mut self, item: Item<'_>) -> Result<NonZeroU64, FileError> {
+		failpoint!(WAL_APPEND);
+
 		let current_pos = self.next_offset;
 
 		let mut body_buffer: Vec<u8> = vec![];
@@ -540,6 +1005,21 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 					flags |= FLAG_UNDO;
 				}
 				Self::write_write_block(&mut body_buffer, write_data)?;
+				if self.features.is_compressed()
+					&& (COMPRESSION_THRESHOLD..=MAX_BODY_LENGTH).contains(&body_buffer.len())
+				{
+					let compressed = zstd::bulk::compress(&body_buffer, COMPRESSION_LEVEL)?;
+					// Encrypted bodies can't be shorter than a block.
+					let min_len = if self.cipher.is_some() {
+						crypto::MIN_DATA_LENGTH
+					} else {
+						0
+					};
+					if (min_len..body_buffer.len()).contains(&compressed.len()) {
+						body_buffer = compressed;
+						flags |= FLAG_COMPRESSED;
+					}
+				}
 			}
 			Item::Commit(transaction_data) => {
 				kind = ItemKind::Commit;
@@ -547,9 +1027,19 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 			}
 			Item::Checkpoint(checkpoint_data) => {
 				kind = ItemKind::Checkpoint;
-				Self::write_checkpoint_block(&mut body_buffer, checkpoint_data)?
+				Self::write_checkpoint_block(&mut body_buffer, checkpoint_data, self.version)?
+			}
+			Item::Custom(custom_data) => {
+				kind = ItemKind::Custom;
+				if custom_data.undo {
+					flags |= FLAG_UNDO;
+				}
+				Self::write_custom_block(&mut body_buffer, custom_data)?
 			}
 		};
+		if let Some(cipher) = &self.cipher {
+			cipher.encrypt_item(current_pos, &mut body_buffer);
+		}
 		let crc = CRC32.checksum(&body_buffer);
 
 		let item_header = ItemHeader {
@@ -585,8 +1075,27 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 	}
 
 	fn flush(&mut self) -> Result<(), FileError> {
-		self.file.write_all(&self.write_buf)?;
-		self.write_buf.clear();
+		// Written data is removed from the buffer right away, so that retrying
+		// after a partial write doesn't write it twice.
+		self.retrier.run(|| {
+			while !self.write_buf.is_empty() {
+				let num_written = match self.file.write(&self.write_buf) {
+					Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+					Ok(num_written) => num_written,
+					Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+					Err(err) => return Err(err),
+				};
+				self.write_buf.drain(..num_written);
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+
+	fn sync(&mut self) -> Result<(), FileError> {
+		self.flush()?;
+		failpoint!(WAL_FSYNC);
+		self.retrier.run(|| self.file.sync_data())?;
 		Ok(())
 	}
 
@@ -595,25 +1104,38 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 
 		self.flush()?;
 		self.file.seek(SeekFrom::Start(offset.get()))?;
-		let mut reader = ItemReader::new(&mut self.file, None)?;
-		let Some((read_offset, item)) = reader.read_item()? else {
+		let mut reader = ItemReader::new(&mut self.file, None, self.version, self.cipher.clone())?;
+		let mut buf = Vec::new();
+		let Some((read_offset, item)) = reader.read_item(&mut buf)? else {
 			return Err(FileError::UnexpectedEof);
 		};
 		debug_assert_eq!(read_offset, offset);
 
-		Ok(item)
+		Ok(item.into_owned())
 	}
 
 	fn iter_items(&mut self) -> Result<Self::IterItems<'_>, FileError> {
 		self.flush()?;
 		self.file.seek(SeekFrom::Start(self.body_start))?;
-		IterItems::new(&mut self.file)
+		IterItems::new(&mut self.file, self.version, self.cipher.clone())
+	}
+
+	fn iter_items_from(&mut self, offset: NonZeroU64) -> Result<Self::IterItems<'_>, FileError> {
+		self.flush()?;
+		self.file
+			.seek(SeekFrom::Start(u64::max(offset.get(), self.body_start)))?;
+		IterItems::new(&mut self.file, self.version, self.cipher.clone())
 	}
 
 	fn iter_items_reverse(&mut self) -> Result<Self::IterItemsReverse<'_>, FileError> {
 		self.flush()?;
 		self.file.seek(SeekFrom::End(0))?;
-		IterItemsReverse::new(&mut self.file, self.prev_item)
+		IterItemsReverse::new(
+			&mut self.file,
+			self.prev_item,
+			self.version,
+			self.cipher.clone(),
+		)
 	}
 
 	#[inline]
@@ -625,21 +1147,64 @@ impl<F: Seek + Read + Write> WalFileApi for WalFile<F> {
 	fn next_offset(&self) -> NonZeroU64 {
 		self.next_offset
 	}
+
+	fn buffer_capacity(&self) -> usize {
+		self.write_buf.capacity()
+	}
+
+	fn unknown_features(&self) -> FeatureFlags {
+		self.features.unknown(SUPPORTED_FEATURES)
+	}
+
+	fn torn_tail_len(&self) -> u64 {
+		self.torn_tail_len
+	}
+
+	fn salvage(&mut self) -> Result<Option<(NonZeroU64, u64)>, FileError> {
+		self.flush()?;
+		let file_len = self.file.seek(SeekFrom::End(0))?;
+		let (prev_item, body_end) = Self::find_first_corrupt_item(
+			&mut self.file,
+			self.body_start,
+			file_len,
+			self.version,
+			self.cipher.as_ref(),
+		)?;
+		if body_end == file_len {
+			return Ok(None);
+		}
+		self.file.set_len(body_end)?;
+		self.retrier.run(|| self.file.sync_data())?;
+		self.prev_item = prev_item;
+		self.next_offset = NonZeroU64::new(body_end).expect("WAL file unexpectedly at position 0");
+		Ok(Some((self.next_offset, file_len - body_end)))
+	}
 }
 
 struct ItemReader<F: Read + Seek> {
 	offset: u64,
 	reader: BufReader<F>,
 	prev_item: Option<NonZeroU64>,
+	/// The format version of the file, which decides how checkpoints are laid
+	/// out.
+	version: u8,
+	cipher: Option<FileCipher>,
 }
 
 impl<F: Read + Seek> ItemReader<F> {
-	fn new(mut file: F, prev_item: Option<NonZeroU64>) -> Result<Self, FileError> {
+	fn new(
+		mut file: F,
+		prev_item: Option<NonZeroU64>,
+		version: u8,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
 		let offset = file.stream_position()?;
 		Ok(Self {
 			offset,
 			reader: BufReader::new(file),
 			prev_item,
+			version,
+			cipher,
 		})
 	}
 
@@ -652,34 +1217,61 @@ impl<F: Read + Seek> ItemReader<F> {
 		})
 	}
 
-	fn read_write_data(
-		mut body: impl Read,
-		is_undo: bool,
-	) -> Result<WriteData<'static>, FileError> {
+	/// Reads a write item, borrowing the written data from `body`.
+	fn read_write_data<'b>(mut body: &'b [u8], is_undo: bool) -> Result<WriteData<'b>, FileError> {
 		let transaction_data = Self::read_transaction_data(&mut body)?;
 
 		let write_block = WriteBlockRepr::deserialize(&mut body)?;
-		let from: Option<Vec<u8>> = if is_undo {
+		let write_length = usize::from(write_block.write_length);
+		let from = if is_undo {
 			None
 		} else {
-			let mut from = vec![0; write_block.write_length.into()];
-			body.read_exact(&mut from)?;
-			Some(from)
+			Some(Self::take_bytes(&mut body, write_length)?)
 		};
-		let mut to: Vec<u8> = vec![0; write_block.write_length.into()];
-		body.read_exact(&mut to)?;
+		let to = Self::take_bytes(&mut body, write_length)?;
 
 		Ok(WriteData {
 			transaction_data,
 			page_id: write_block.page_id,
 			offset: write_block.offset,
-			from: from.map(Cow::Owned),
-			to: Cow::Owned(to),
+			from: from.map(Cow::Borrowed),
+			to: Cow::Borrowed(to),
+		})
+	}
+
+	/// Reads a custom item, borrowing the record data from `body`.
+	fn read_custom_data(mut body: &[u8], is_undo: bool) -> Result<CustomData<'_>, FileError> {
+		let transaction_data = Self::read_transaction_data(&mut body)?;
+		let custom_block = CustomBlockRepr::deserialize(&mut body)?;
+		let data = Self::take_bytes(&mut body, custom_block.data_length.into())?;
+
+		Ok(CustomData {
+			transaction_data,
+			record_type: custom_block.record_type,
+			version: custom_block.version,
+			data: Cow::Borrowed(data),
+			undo: is_undo,
 		})
 	}
 
-	fn read_checkpoint_data(mut body: impl Read) -> Result<CheckpointData<'static>, FileError> {
-		let checkpoint_block = CheckpointBlock::deserialize(&mut body)?;
+	fn take_bytes<'b>(body: &mut &'b [u8], len: usize) -> Result<&'b [u8], FileError> {
+		if body.len() < len {
+			return Err(FileError::UnexpectedEof);
+		}
+		let (bytes, rest) = body.split_at(len);
+		*body = rest;
+		Ok(bytes)
+	}
+
+	fn read_checkpoint_data(
+		mut body: impl Read,
+		version: u8,
+	) -> Result<CheckpointData<'static>, FileError> {
+		let checkpoint_block = if version >= CHECKPOINT_TRANSACTION_ID_VERSION {
+			CheckpointBlockRepr::deserialize(&mut body)?
+		} else {
+			LegacyCheckpointBlockRepr::deserialize(&mut body)?
+		};
 
 		let mut dirty_pages: HashMap<PageId, WalIndex> = HashMap::new();
 		for _ in 0..checkpoint_block.num_dirty_pages {
@@ -690,60 +1282,100 @@ impl<F: Read + Seek> ItemReader<F> {
 
 		let mut transactions: HashMap<u64, TransactionState> = HashMap::new();
 		for _ in 0..checkpoint_block.num_transactions {
-			let mut tid_bytes = [0; 8];
-			body.read_exact(&mut tid_bytes)?;
-			let transaction_id = u64::from_ne_bytes(tid_bytes);
+			let mut transaction_id = U64::new_zeroed();
+			body.read_exact(transaction_id.as_bytes_mut())?;
+			let transaction_id = transaction_id.get();
 			let transaction_state = TransactionStateRepr::deserialize(&mut body)?;
 			transactions.insert(transaction_id, transaction_state);
 		}
 
 		Ok(CheckpointData {
+			next_transaction_id: checkpoint_block.next_transaction_id,
 			dirty_pages: Cow::Owned(dirty_pages),
 			transactions: Cow::Owned(transactions),
 		})
 	}
 
-	fn read_item_exact(&mut self) -> Result<(NonZeroU64, Item<'static>), FileError> {
+	/// Reads the next item into `buf`, which is reused rather than reallocated
+	/// if it is already large enough.
+	fn read_item_exact<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<(NonZeroU64, Item<'b>), FileError> {
 		let header = ItemHeaderRepr::deserialize(&mut self.reader)?;
-		let mut body_buf: Box<[u8]> = vec![0; header.body_length.into()].into();
-		self.reader.read_exact(&mut body_buf)?;
+		// Following the previous items must come to an end, even if they are
+		// corrupted.
+		if header
+			.prev_item
+			.is_some_and(|prev| prev.get() >= self.offset)
+		{
+			return Err(FileError::Corrupted(format!(
+				"WAL item at offset {} points to a later previous item",
+				self.offset
+			)));
+		}
+		buf.clear();
+		buf.resize(header.body_length.into(), 0);
+		self.reader.read_exact(buf)?;
 		self.prev_item = header.prev_item;
 
-		if CRC32.checksum(&body_buf) != header.crc {
+		if CRC32.checksum(buf) != header.crc {
 			return Err(FileError::ChecksumMismatch);
 		}
+		let item_offset =
+			NonZeroU64::new(self.offset).expect("WAL was unexpectedly read at offset 0");
+		if let Some(cipher) = &self.cipher {
+			if buf.len() < crypto::MIN_DATA_LENGTH {
+				return Err(FileError::Corrupted(format!(
+					"Encrypted WAL item at offset {item_offset} is too short"
+				)));
+			}
+			cipher.decrypt_item(item_offset, buf);
+		}
+		if header.flags & FLAG_COMPRESSED != 0 {
+			*buf = zstd::bulk::decompress(buf, MAX_BODY_LENGTH).map_err(|err| {
+				FileError::Corrupted(format!(
+					"Failed to decompress WAL item at offset {item_offset}: {err}"
+				))
+			})?;
+		}
 
 		let is_undo = header.flags & FLAG_UNDO != 0;
 
-		let mut body_cursor = Cursor::new(body_buf);
+		let body: &'b [u8] = buf;
 		let item = match header.kind {
-			ItemKind::Write => Item::Write(Self::read_write_data(&mut body_cursor, is_undo)?),
-			ItemKind::Commit => Item::Commit(Self::read_transaction_data(&mut body_cursor)?),
-			ItemKind::Checkpoint => Item::Checkpoint(Self::read_checkpoint_data(&mut body_cursor)?),
+			ItemKind::Write => Item::Write(Self::read_write_data(body, is_undo)?),
+			ItemKind::Commit => Item::Commit(Self::read_transaction_data(body)?),
+			ItemKind::Checkpoint => {
+				Item::Checkpoint(Self::read_checkpoint_data(body, self.version)?)
+			}
+			ItemKind::Custom => Item::Custom(Self::read_custom_data(body, is_undo)?),
 		};
 
 		self.reader
 			.seek_relative(i64::try_from(ItemFooterRepr::SIZE).unwrap())?;
 
-		let item_offset = self.offset;
 		self.offset +=
 			(ItemHeaderRepr::SIZE + header.body_length as usize + ItemFooterRepr::SIZE) as u64;
 
-		Ok((
-			NonZeroU64::new(item_offset).expect("WAL was unexpectedly read at offset 0"),
-			item,
-		))
+		Ok((item_offset, item))
 	}
 
-	fn read_item(&mut self) -> Result<Option<(NonZeroU64, Item<'static>)>, FileError> {
-		match self.read_item_exact() {
+	fn read_item<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError> {
+		match self.read_item_exact(buf) {
 			Err(FileError::UnexpectedEof) => Ok(None),
 			Err(other_error) => Err(other_error),
 			Ok(value) => Ok(Some(value)),
 		}
 	}
 
-	fn read_prev_item(&mut self) -> Result<Option<(NonZeroU64, Item<'static>)>, FileError> {
+	fn read_prev_item<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError> {
 		let Some(prev_item) = self.prev_item else {
 			return Ok(None);
 		};
@@ -756,7 +1388,7 @@ impl<F: Read + Seek> ItemReader<F> {
 				relative_offset_abs
 			})?;
 		self.offset = prev_item.get();
-		self.read_item()
+		self.read_item(buf)
 	}
 }
 
@@ -765,18 +1397,19 @@ pub(crate) struct IterItems<F: Read + Seek> {
 }
 
 impl<F: Read + Seek> IterItems<F> {
-	fn new(file: F) -> Result<Self, FileError> {
+	fn new(file: F, version: u8, cipher: Option<FileCipher>) -> Result<Self, FileError> {
 		Ok(Self {
-			reader: ItemReader::new(file, None)?,
+			reader: ItemReader::new(file, None, version, cipher)?,
 		})
 	}
 }
 
-impl<F: Read + Seek> Iterator for IterItems<F> {
-	type Item = Result<(NonZeroU64, Item<'static>), FileError>;
-
-	fn next(&mut self) -> Option<Self::Item> {
-		self.reader.read_item().transpose()
+impl<F: Read + Seek> ItemStream for IterItems<F> {
+	fn next_into<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError> {
+		self.reader.read_item(buf)
 	}
 }
 
@@ -785,28 +1418,37 @@ pub(crate) struct IterItemsReverse<F: Read + Seek> {
 }
 
 impl<F: Read + Seek> IterItemsReverse<F> {
-	fn new(file: F, prev_item: Option<NonZeroU64>) -> Result<Self, FileError> {
+	fn new(
+		file: F,
+		prev_item: Option<NonZeroU64>,
+		version: u8,
+		cipher: Option<FileCipher>,
+	) -> Result<Self, FileError> {
 		Ok(Self {
-			reader: ItemReader::new(file, prev_item)?,
+			reader: ItemReader::new(file, prev_item, version, cipher)?,
 		})
 	}
 }
 
-impl<F: Read + Seek> Iterator for IterItemsReverse<F> {
-	type Item = Result<(NonZeroU64, Item<'static>), FileError>;
-
-	fn next(&mut self) -> Option<Self::Item> {
-		self.reader.read_prev_item().transpose()
+impl<F: Read + Seek> ItemStream for IterItemsReverse<F> {
+	fn next_into<'b>(
+		&mut self,
+		buf: &'b mut Vec<u8>,
+	) -> Result<Option<(NonZeroU64, Item<'b>)>, FileError> {
+		self.reader.read_prev_item(buf)
 	}
 }
 
 #[cfg(test)]
 mod tests {
+	use std::io::Cursor;
+
 	use pretty_assertions::assert_buf_eq;
 
 	use crate::{
 		files::{
-			generic::GenericHeaderRepr,
+			crypto::{Cipher, EncryptionKey},
+			generic::{GenericHeaderRepr, LegacyHeaderRepr},
 			test_helpers::{page_id, wal_index},
 		},
 		utils::test_helpers::non_zero,
@@ -829,6 +1471,7 @@ mod tests {
 				file_type: FileType::Wal,
 				content_offset: GenericHeaderRepr::SIZE as u16,
 				version: FORMAT_VERSION,
+				features: FeatureFlags::NONE,
 			})
 			.as_bytes(),
 		);
@@ -846,6 +1489,7 @@ mod tests {
 				file_type: FileType::Wal,
 				content_offset: GenericHeaderRepr::SIZE as u16,
 				version: FORMAT_VERSION,
+				features: FeatureFlags::NONE,
 			})
 			.as_bytes(),
 		);
@@ -857,6 +1501,233 @@ mod tests {
 		assert!(result.is_ok());
 	}
 
+	#[test]
+	fn read_and_append_to_wal_of_version_1() {
+		// given
+		let mut file = Vec::<u8>::new();
+		file.extend(
+			LegacyHeaderRepr::from(GenericHeader {
+				file_type: FileType::Wal,
+				content_offset: LegacyHeaderRepr::SIZE as u16,
+				version: 1,
+				features: FeatureFlags::NONE,
+			})
+			.as_bytes(),
+		);
+		let mut wal_file = WalFile::new(
+			Cursor::new(&mut file),
+			LegacyHeaderRepr::SIZE as u64,
+			1,
+			FeatureFlags::NONE,
+			None,
+			None,
+		)
+		.unwrap();
+		wal_file
+			.push_item(Item::Checkpoint(CheckpointData {
+				next_transaction_id: 70,
+				dirty_pages: Cow::Owned(HashMap::new()),
+				transactions: Cow::Owned(HashMap::new()),
+			}))
+			.unwrap();
+		wal_file.flush().unwrap();
+		drop(wal_file);
+		let commit = Item::Commit(TransactionData {
+			transaction_id: 69,
+			prev_transaction_item: None,
+		});
+
+		// when
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let commit_offset = wal_file.push_item(commit.clone()).unwrap();
+		wal_file.flush().unwrap();
+		drop(wal_file);
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+
+		// then
+		let mut iter = wal_file.iter_items().unwrap();
+		let mut buf = Vec::new();
+		assert_eq!(
+			iter.next_into(&mut buf).unwrap(),
+			Some((
+				non_zero!(LegacyHeaderRepr::SIZE as u64),
+				Item::Checkpoint(CheckpointData {
+					// Checkpoints of version 1 don't hold the next transaction id.
+					next_transaction_id: 0,
+					dirty_pages: Cow::Owned(HashMap::new()),
+					transactions: Cow::Owned(HashMap::new()),
+				})
+			))
+		);
+		// The checkpoint block of version 1 is 16 bytes long
+		assert_eq!(commit_offset, non_zero!(49));
+		assert_eq!(
+			iter.next_into(&mut buf).unwrap(),
+			Some((commit_offset, commit))
+		);
+		assert!(iter.next_into(&mut buf).unwrap().is_none());
+	}
+
+	#[test]
+	fn open_wal_with_unknown_optional_features() {
+		// given
+		let mut file = Vec::<u8>::new();
+		file.extend(
+			GenericHeaderRepr::from(GenericHeader {
+				file_type: FileType::Wal,
+				content_offset: GenericHeaderRepr::SIZE as u16,
+				version: FORMAT_VERSION,
+				features: FeatureFlags {
+					required: 0,
+					optional: 0b100,
+				},
+			})
+			.as_bytes(),
+		);
+
+		// when
+		let wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+
+		// then
+		assert_eq!(
+			wal_file.unknown_features(),
+			FeatureFlags {
+				required: 0,
+				optional: 0b100
+			}
+		);
+	}
+
+	#[test]
+	fn cut_off_torn_tail() {
+		// given
+		let commit = |transaction_id| {
+			Item::Commit(TransactionData {
+				transaction_id,
+				prev_transaction_item: None,
+			})
+		};
+		let mut file = Vec::<u8>::new();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		wal_file.push_item(commit(1)).unwrap();
+		wal_file.push_item(commit(2)).unwrap();
+		wal_file.flush().unwrap();
+		let intact_len = file.len();
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		wal_file.push_item(commit(3)).unwrap();
+		wal_file.flush().unwrap();
+		file.truncate(file.len() - 3);
+		let mut torn_file = file.clone();
+		torn_file.truncate(intact_len);
+		torn_file.extend([0xab; 40]);
+
+		// when
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let torn_footer_len = wal_file.torn_tail_len();
+		wal_file.push_item(commit(4)).unwrap();
+		wal_file.flush().unwrap();
+		let mut reopened = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let mut garbage_file = WalFile::open(Cursor::new(&mut torn_file)).unwrap();
+
+		// then
+		let mut buf = Vec::new();
+		let mut transaction_ids = Vec::new();
+		let mut items = reopened.iter_items_reverse().unwrap();
+		while let Some((_, item)) = items.next_into(&mut buf).unwrap() {
+			let Item::Commit(data) = item else {
+				panic!("Expected a commit item, but got {item:?}");
+			};
+			transaction_ids.push(data.transaction_id);
+		}
+		assert!(torn_footer_len > 0);
+		assert_eq!(reopened.torn_tail_len(), 0);
+		assert_eq!(transaction_ids, [4, 2, 1]);
+		assert_eq!(garbage_file.torn_tail_len(), 40);
+		assert_eq!(garbage_file.size(), intact_len);
+		let mut items = garbage_file.iter_items().unwrap();
+		assert!(items.next_into(&mut buf).unwrap().is_some());
+		assert!(items.next_into(&mut buf).unwrap().is_some());
+		assert!(items.next_into(&mut buf).unwrap().is_none());
+	}
+
+	#[test]
+	fn salvage_items_before_corruption() {
+		// given
+		let commit = |transaction_id| {
+			Item::Commit(TransactionData {
+				transaction_id,
+				prev_transaction_item: None,
+			})
+		};
+		let mut file = Vec::<u8>::new();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		wal_file.push_item(commit(1)).unwrap();
+		let corrupt_offset = wal_file.push_item(commit(2)).unwrap();
+		wal_file.push_item(commit(3)).unwrap();
+		wal_file.flush().unwrap();
+		let file_len = file.len() as u64;
+		let corrupt_byte = usize::try_from(corrupt_offset.get()).unwrap() + ItemHeaderRepr::SIZE;
+		file[corrupt_byte] ^= 0xff;
+
+		// when
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let unsalvaged = {
+			let mut buf = Vec::new();
+			let mut items = wal_file.iter_items().unwrap();
+			items.next_into(&mut buf).unwrap();
+			items.next_into(&mut buf).map(|_| ())
+		};
+		let salvaged = wal_file.salvage().unwrap();
+		let salvaged_again = wal_file.salvage().unwrap();
+		wal_file.push_item(commit(4)).unwrap();
+		wal_file.flush().unwrap();
+		let mut reopened = WalFile::open(Cursor::new(&mut file)).unwrap();
+
+		// then
+		assert!(matches!(unsalvaged, Err(FileError::ChecksumMismatch)));
+		assert_eq!(
+			salvaged,
+			Some((corrupt_offset, file_len - corrupt_offset.get()))
+		);
+		assert_eq!(salvaged_again, None);
+		let mut buf = Vec::new();
+		let mut transaction_ids = Vec::new();
+		let mut items = reopened.iter_items_reverse().unwrap();
+		while let Some((_, item)) = items.next_into(&mut buf).unwrap() {
+			let Item::Commit(data) = item else {
+				panic!("Expected a commit item, but got {item:?}");
+			};
+			transaction_ids.push(data.transaction_id);
+		}
+		assert_eq!(transaction_ids, [4, 1]);
+	}
+
+	#[test]
+	fn reject_item_pointing_to_itself() {
+		// given
+		let mut file = Vec::<u8>::new();
+		let mut wal_file = WalFile::create(Cursor::new(&mut file)).unwrap();
+		let item_start = wal_file
+			.push_item(Item::Commit(TransactionData {
+				transaction_id: 1,
+				prev_transaction_item: None,
+			}))
+			.unwrap();
+		wal_file.flush().unwrap();
+		let prev_item_offset = GenericHeaderRepr::SIZE + 8;
+		file[prev_item_offset..prev_item_offset + 8]
+			.copy_from_slice(U64::new(item_start.get()).as_bytes());
+
+		// when
+		let mut wal_file = WalFile::open(Cursor::new(&mut file)).unwrap();
+		let mut buf = Vec::new();
+		let mut items = wal_file.iter_items_reverse().unwrap();
+		let result = items.next_into(&mut buf);
+
+		// then
+		assert!(matches!(result, Err(FileError::Corrupted(..))));
+	}
+
 	#[test]
 	fn push_write_item() {
 		// given
@@ -884,26 +1755,26 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Write as u8,
 				flags: 0,
-				body_length: 42,
-				crc: 0x994f0abc,
-				prev_item: NonZeroU64::new(0),
+				body_length: 42.into(),
+				crc: 0x994f0abc.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(24),
-				transaction_id: 25,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 24.into(),
+				transaction_id: 25.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WriteBlockRepr {
-				segment_num: 123,
-				page_num: 456,
-				offset: 445,
-				write_length: 4,
+				segment_num: 123.into(),
+				page_num: 456.into(),
+				offset: 445.into(),
+				write_length: 4.into(),
 			}
 			.as_bytes(),
 		);
@@ -911,7 +1782,7 @@ mod tests {
 		expected_body.extend([4, 5, 6, 7]);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (GenericHeaderRepr::SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
@@ -941,23 +1812,23 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Commit as u8,
 				flags: 0,
-				body_length: 24,
-				crc: 0x8b777949,
-				prev_item: NonZeroU64::new(0),
+				body_length: 24.into(),
+				crc: 0x8b777949.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(25),
-				transaction_id: 69,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 25.into(),
+				transaction_id: 69.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (GenericHeaderRepr::SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
@@ -993,33 +1864,33 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Write as u8,
 				flags: FLAG_UNDO,
-				body_length: 38,
-				crc: 0x1af2b54e,
-				prev_item: NonZeroU64::new(0),
+				body_length: 38.into(),
+				crc: 0x1af2b54e.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			TransactionBlockRepr {
-				prev_transaction_generation: 123,
-				prev_transaction_offset: NonZeroU64::new(24),
-				transaction_id: 25,
+				prev_transaction_generation: 123.into(),
+				prev_transaction_offset: 24.into(),
+				transaction_id: 25.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WriteBlockRepr {
-				offset: 445,
-				segment_num: 123,
-				page_num: 456,
-				write_length: 4,
+				offset: 445.into(),
+				segment_num: 123.into(),
+				page_num: 456.into(),
+				write_length: 4.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend([4, 5, 6, 7]);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (GenericHeaderRepr::SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
@@ -1047,6 +1918,7 @@ mod tests {
 		);
 		wal_file
 			.push_item(Item::Checkpoint(CheckpointData {
+				next_transaction_id: 70,
 				dirty_pages: Cow::Borrowed(&dirty_pages),
 				transactions: Cow::Borrowed(&transactions),
 			}))
@@ -1059,45 +1931,46 @@ mod tests {
 			ItemHeaderRepr {
 				kind: ItemKind::Checkpoint as u8,
 				flags: 0,
-				body_length: 70,
-				crc: 0x3420af22,
-				prev_item: NonZeroU64::new(0),
+				body_length: 78.into(),
+				crc: 0x394b59e0.into(),
+				prev_item: 0.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			CheckpointBlockRepr {
-				num_dirty_pages: 1,
-				num_transactions: 1,
+				next_transaction_id: 70.into(),
+				num_dirty_pages: 1.into(),
+				num_transactions: 1.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			PageIdRepr {
-				segment_num: 1,
-				page_num: 2,
+				segment_num: 1.into(),
+				page_num: 2.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			WalIndexRepr {
-				generation: 0,
-				offset: 3,
+				generation: 0.into(),
+				offset: 3.into(),
 			}
 			.as_bytes(),
 		);
-		expected_body.extend(69_u64.to_ne_bytes());
+		expected_body.extend(U64::new(69).as_bytes());
 		expected_body.extend(
 			TransactionStateRepr {
-				first_generation: 0,
-				last_generation: 1,
-				last_offset: 420,
+				first_generation: 0.into(),
+				last_generation: 1.into(),
+				last_offset: 420.into(),
 			}
 			.as_bytes(),
 		);
 		expected_body.extend(
 			ItemFooterRepr {
-				item_start: GenericHeaderRepr::SIZE as u64,
+				item_start: (GenericHeaderRepr::SIZE as u64).into(),
 			}
 			.as_bytes(),
 		);
@@ -1129,6 +2002,119 @@ mod tests {
 		assert_eq!(wal_file.read_item_at(offset).unwrap(), item)
 	}
 
+	#[test]
+	fn write_and_read_custom_record() {
+		// given
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new())).unwrap();
+		let item = Item::Custom(CustomData {
+			transaction_data: TransactionData {
+				transaction_id: 3,
+				prev_transaction_item: Some(wal_index!(1, 24)),
+			},
+			record_type: 7,
+			version: 2,
+			data: Cow::Owned(vec![1, 2, 3]),
+			undo: false,
+		});
+
+		// when
+		let offset = wal_file.push_item(item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// then
+		assert_eq!(wal_file.read_item_at(offset).unwrap(), item)
+	}
+
+	#[test]
+	fn write_and_read_compressed() {
+		// given
+		let mut wal_file =
+			WalFile::create_with_options(Cursor::new(Vec::new()), None, true).unwrap();
+		let large_item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_id: page_id!(123, 456),
+			offset: 0,
+			from: Some(Cow::Owned(vec![0; 4096])),
+			to: Cow::Owned([1, 2, 3, 4].repeat(1024)),
+		});
+		let small_item = Item::Write(WriteData {
+			transaction_data: TransactionData {
+				transaction_id: 0,
+				prev_transaction_item: None,
+			},
+			page_id: page_id!(123, 456),
+			offset: 420,
+			from: Some(Cow::Owned(vec![0, 0, 0, 0])),
+			to: Cow::Owned(vec![1, 2, 3, 4]),
+		});
+
+		// when
+		let large_offset = wal_file.push_item(large_item.clone()).unwrap();
+		let small_offset = wal_file.push_item(small_item.clone()).unwrap();
+		wal_file.flush().unwrap();
+
+		// then
+		assert!(wal_file.size() < 1024);
+		let mut header_bytes = &wal_file.file.get_ref()[large_offset.get() as usize..];
+		let header = ItemHeaderRepr::deserialize(&mut header_bytes).unwrap();
+		assert_eq!(header.flags, FLAG_COMPRESSED);
+		let mut header_bytes = &wal_file.file.get_ref()[small_offset.get() as usize..];
+		let header = ItemHeaderRepr::deserialize(&mut header_bytes).unwrap();
+		assert_eq!(header.flags, 0);
+		assert_eq!(wal_file.read_item_at(large_offset).unwrap(), large_item);
+		let mut iter = wal_file.iter_items().unwrap();
+		let mut buf = Vec::new();
+		assert_eq!(
+			iter.next_into(&mut buf).unwrap(),
+			Some((large_offset, large_item))
+		);
+		assert_eq!(
+			iter.next_into(&mut buf).unwrap(),
+			Some((small_offset, small_item))
+		);
+		assert_eq!(iter.next_into(&mut buf).unwrap(), None);
+	}
+
+	#[test]
+	fn write_and_read_encrypted_and_compressed() {
+		// given
+		let cipher = Arc::new(Cipher::new(&EncryptionKey::new([7; 32]), [3; 16]));
+		let mut wal_file = WalFile::create_with_options(
+			Cursor::new(Vec::new()),
+			Some(FileCipher::wal(cipher, 1)),
+			true,
+		)
+		.unwrap();
+		let items = [vec![0; 4096], [1, 2, 3, 4].repeat(1024)].map(|to| {
+			Item::Write(WriteData {
+				transaction_data: TransactionData {
+					transaction_id: 0,
+					prev_transaction_item: None,
+				},
+				page_id: page_id!(123, 456),
+				offset: 0,
+				from: Some(Cow::Owned(vec![0; 4096])),
+				to: Cow::Owned(to),
+			})
+		});
+
+		// when
+		let offsets = items.clone().map(|item| wal_file.push_item(item).unwrap());
+		wal_file.flush().unwrap();
+
+		// then
+		for (offset, item) in offsets.into_iter().zip(items) {
+			let mut header_bytes = &wal_file.file.get_ref()[offset.get() as usize..];
+			let header = ItemHeaderRepr::deserialize(&mut header_bytes).unwrap();
+			assert!(usize::from(header.body_length) >= crypto::MIN_DATA_LENGTH);
+			assert!(!header_bytes.starts_with(&[0; 16]));
+			assert_eq!(wal_file.read_item_at(offset).unwrap(), item);
+		}
+	}
+
 	#[test]
 	fn write_and_iter() {
 		// given
@@ -1158,15 +2144,51 @@ mod tests {
 
 		// then
 		let mut iter = wal_file.iter_items().unwrap();
+		let mut buf = Vec::new();
 		assert_eq!(
-			iter.next().unwrap().unwrap(),
-			(non_zero!(9), items[0].clone())
+			iter.next_into(&mut buf).unwrap(),
+			Some((non_zero!(13), items[0].clone()))
 		);
 		assert_eq!(
-			iter.next().unwrap().unwrap(),
-			(non_zero!(75), items[1].clone())
+			iter.next_into(&mut buf).unwrap(),
+			Some((non_zero!(79), items[1].clone()))
 		);
-		assert!(dbg!(iter.next()).is_none());
+		assert!(iter.next_into(&mut buf).unwrap().is_none());
+	}
+
+	#[test]
+	fn iter_borrows_from_buffer() {
+		// given
+		let mut wal_file = WalFile::create(Cursor::new(Vec::new())).unwrap();
+		wal_file
+			.push_item(Item::Write(WriteData {
+				transaction_data: TransactionData {
+					transaction_id: 0,
+					prev_transaction_item: None,
+				},
+				page_id: page_id!(1, 2),
+				offset: 0,
+				from: None,
+				to: Cow::Owned(vec![1, 2, 3, 4]),
+			}))
+			.unwrap();
+		let mut buf = Vec::new();
+
+		// when
+		let mut iter = wal_file.iter_items().unwrap();
+		let item = iter.next_into(&mut buf).unwrap();
+
+		// then
+		assert!(matches!(
+			item,
+			Some((
+				_,
+				Item::Write(WriteData {
+					to: Cow::Borrowed([1, 2, 3, 4]),
+					..
+				})
+			))
+		));
 	}
 
 	#[test]
@@ -1198,15 +2220,16 @@ mod tests {
 
 		// then
 		let mut iter = wal_file.iter_items_reverse().unwrap();
+		let mut buf = Vec::new();
 		assert_eq!(
-			iter.next().unwrap().unwrap(),
-			(non_zero!(75), items[1].clone())
+			iter.next_into(&mut buf).unwrap(),
+			Some((non_zero!(79), items[1].clone()))
 		);
 		assert_eq!(
-			iter.next().unwrap().unwrap(),
-			(non_zero!(9), items[0].clone())
+			iter.next_into(&mut buf).unwrap(),
+			Some((non_zero!(13), items[0].clone()))
 		);
-		assert!(iter.next().is_none());
+		assert!(iter.next_into(&mut buf).unwrap().is_none());
 	}
 }
 
@@ -0,0 +1,133 @@
+//! A description of the on-disk format, for tools that need to check whether
+//! they can read a data directory without parsing the files themselves.
+//!
+//! Every file in a data directory starts with a generic header containing the
+//! [`FormatDescription::magic`] bytes, the file kind, its format version and
+//! its feature flags. The values reported here are the ones this version of
+//! acorn writes, and new fields are only ever added.
+
+use crate::{
+	consts::PAGE_SIZE,
+	files::{
+		generic::{FeatureFlags, MAGIC},
+		segment::{self, PAGE_BODY_SIZE},
+		wal,
+	},
+};
+
+/// A checksum algorithm, named after its entry in the catalogue of
+/// parametrised CRC algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+	/// CRC-16/IBM-SDLC
+	Crc16IbmSdlc,
+	/// CRC-32/ISO-HDLC
+	Crc32IsoHdlc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFormat {
+	pub version: u8,
+	/// Features that a reader must know to be able to read the file.
+	pub required_features: u16,
+	/// Features that readers that don't know them may ignore.
+	pub optional_features: u16,
+	pub checksum: ChecksumAlgorithm,
+}
+
+impl FileFormat {
+	fn new(version: u8, features: FeatureFlags, checksum: ChecksumAlgorithm) -> Self {
+		Self {
+			version,
+			required_features: features.required,
+			optional_features: features.optional,
+			checksum,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatDescription {
+	pub magic: [u8; 4],
+	/// The size of a page on disk, including its header.
+	pub page_size: usize,
+	/// The number of bytes of each page that are available for data.
+	pub page_body_size: usize,
+	pub page_format_version: u8,
+	/// The maximum size of a segment file in bytes.
+	pub segment_size: usize,
+	/// The format of the segment files, which hold the database pages and
+	/// their metadata. The checksum covers each page body.
+	pub segment: FileFormat,
+	/// The format of the WAL files. The checksum covers each item body.
+	pub wal: FileFormat,
+}
+
+/// Describes the on-disk format that this version of acorn reads and writes.
+pub fn describe() -> FormatDescription {
+	FormatDescription {
+		magic: MAGIC,
+		page_size: PAGE_SIZE,
+		page_body_size: PAGE_BODY_SIZE,
+		page_format_version: segment::PAGE_FORMAT_VERSION,
+		segment_size: segment::SEGMENT_SIZE,
+		segment: FileFormat::new(
+			segment::FORMAT_VERSION,
+			segment::SUPPORTED_FEATURES,
+			ChecksumAlgorithm::Crc16IbmSdlc,
+		),
+		wal: FileFormat::new(
+			wal::FORMAT_VERSION,
+			wal::SUPPORTED_FEATURES,
+			ChecksumAlgorithm::Crc32IsoHdlc,
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use tempfile::tempdir;
+
+	use crate::{
+		files::{
+			generic::{FileType, GenericHeaderRepr},
+			segment::SegmentFile,
+			utils::{CRC16, CRC32},
+			wal::WalFile,
+		},
+		repr::IoRepr,
+	};
+
+	use super::*;
+
+	#[test]
+	fn describe_written_files() {
+		// given
+		let dir = tempdir().unwrap();
+		let segment_path = dir.path().join("segment");
+		SegmentFile::create_file(&segment_path).unwrap();
+		let mut wal_file = Vec::new();
+		WalFile::create(Cursor::new(&mut wal_file)).unwrap();
+
+		// when
+		let description = describe();
+
+		// then
+		let segment_header =
+			GenericHeaderRepr::deserialize(std::fs::File::open(segment_path).unwrap()).unwrap();
+		assert_eq!(segment_header.file_type, FileType::Segment);
+		assert_eq!(segment_header.version, description.segment.version);
+		assert_eq!(
+			usize::from(segment_header.content_offset),
+			description.page_size
+		);
+		let wal_header = GenericHeaderRepr::deserialize(wal_file.as_slice()).unwrap();
+		assert_eq!(wal_header.file_type, FileType::Wal);
+		assert_eq!(wal_header.version, description.wal.version);
+		assert_eq!(&wal_file[..4], description.magic.as_slice());
+		assert_eq!(CRC16.algorithm, &crc::CRC_16_IBM_SDLC);
+		assert_eq!(CRC32.algorithm, &crc::CRC_32_ISO_HDLC);
+	}
+}
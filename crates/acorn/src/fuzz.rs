@@ -0,0 +1,83 @@
+//! Entry points for fuzzing the parsers of the on-disk formats, e.g. with
+//! `cargo fuzz` or OSS-Fuzz. Each takes arbitrary bytes and parses them like
+//! the corresponding file or page would be read; invalid input must result
+//! in an error rather than a panic or a hang.
+
+use std::num::NonZeroU16;
+
+use crate::{
+	consts::PAGE_SIZE,
+	files::{
+		memory::MemoryFile,
+		segment::{SegmentFile, SegmentFileApi, PAGE_BODY_SIZE},
+		wal::{ItemStream, WalFile, WalFileApi},
+	},
+};
+
+/// Parses `data` as a WAL file, and reads its items both forwards and
+/// backwards.
+pub fn fuzz_wal_item(data: &[u8]) {
+	let Ok(mut wal_file) = WalFile::open(MemoryFile::from_bytes(data.to_vec())) else {
+		return;
+	};
+	let mut buf = Vec::new();
+	if let Ok(mut items) = wal_file.iter_items() {
+		while let Ok(Some(..)) = items.next_into(&mut buf) {}
+	}
+	if let Ok(mut items) = wal_file.iter_items_reverse() {
+		while let Ok(Some(..)) = items.next_into(&mut buf) {}
+	}
+}
+
+/// Parses `data` as a segment file, including the file header and the
+/// headers of its pages, and reads the pages that are initialized.
+pub fn fuzz_meta(data: &[u8]) {
+	let Ok(segment) = SegmentFile::open(MemoryFile::from_bytes(data.to_vec()), None, PAGE_SIZE)
+	else {
+		return;
+	};
+	let mut buf = vec![0; PAGE_BODY_SIZE];
+	let _ = segment.read(NonZeroU16::MIN, &mut buf);
+	if let Ok(pages) = segment.initialized_pages() {
+		for (page_num, _) in pages {
+			let _ = segment.read(page_num, &mut buf);
+		}
+	}
+}
+
+/// Parses `data`, padded or cut off to the size of a page, as a node of
+/// both kinds of B-tree.
+pub fn fuzz_btree_page(data: &[u8]) {
+	crate::doc_store::fuzz_btree_page(data);
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::wal::{Item, TransactionData};
+
+	use super::*;
+
+	#[test]
+	fn parse_invalid_input() {
+		// given
+		let mut wal = Vec::new();
+		let mut wal_file = WalFile::create(std::io::Cursor::new(&mut wal)).unwrap();
+		wal_file
+			.push_item(Item::Commit(TransactionData {
+				transaction_id: 1,
+				prev_transaction_item: None,
+			}))
+			.unwrap();
+		wal_file.flush().unwrap();
+		let mut corrupted_wal = wal.clone();
+		let last = corrupted_wal.len() - 9;
+		corrupted_wal[last] ^= 0xff;
+
+		// expect
+		for data in [&[][..], &[0xff; 64], &wal, &corrupted_wal] {
+			fuzz_wal_item(data);
+			fuzz_meta(data);
+			fuzz_btree_page(data);
+		}
+	}
+}
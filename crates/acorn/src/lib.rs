@@ -6,21 +6,53 @@
 	warn(clippy::cast_possible_truncation)
 )]
 // Unstable features
-#![feature(trait_alias)]
-#![feature(duration_constructors)]
-#![feature(async_closure)]
-#![feature(buf_read_has_data_left)]
-#![feature(cfg_match)]
-#![feature(os_str_display)]
 #![cfg_attr(test, feature(test))]
 
 #[cfg(test)]
 extern crate test;
 
 mod consts;
+mod database;
 mod doc_store;
+pub mod failpoints;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
 mod files;
+pub mod format;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
 mod page_store;
+mod page_type;
 mod repr;
 mod tasks;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tools;
+mod trace;
 mod utils;
+mod writer;
+
+pub use database::{
+	Database, DatabaseBuilder, Error, ErrorKind, IsolationLevel, Snapshot, Transaction, WalStream,
+};
+pub use files::{
+	crypto::EncryptionKey,
+	sync::SyncPrimitive,
+	vfs::{OsVfs, Vfs, VfsFile},
+	Durability, PageId,
+};
+pub use page_store::{
+	CacheSimulator, CacheStats, CancellationToken, CheckProblem, CheckReport, CheckpointStats,
+	CheckpointTrigger, CustomRecord, LockGraph, LockWait, MaintenanceStats, MemoryUsage,
+	OpenReport, OpenWarning, SegmentIoStats, SimulatedCacheStats, Stats, TransactionLocks,
+	TransactionSize, UsageForecast, UsageForecaster, WalPosition, WalRecord, WalRecordHandler,
+	WalRecordHandlerError, WalTransaction, WalTransactionStatus,
+};
+pub use page_type::{PageType, PageTypeMismatch};
+pub use tasks::TaskPanic;
+pub use utils::{
+	cache::{EvictionPolicy, EvictionStats},
+	histogram::LatencyHistogram,
+	units::{ByteSize, ParseSizeError},
+};
+pub use writer::SingleWriter;
@@ -0,0 +1,149 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use crate::files::{
+	crypto::{Cipher, FileCipher},
+	list_wal_archive,
+	wal::{self, ItemStream, WalFile, WalFileApi},
+};
+
+use super::{PageId, PageStorageApi, StorageError, TransactionApi, WritePage};
+
+/// A write of a transaction whose commit hasn't been replayed yet.
+struct PendingWrite {
+	page_id: PageId,
+	offset: u16,
+	data: Box<[u8]>,
+}
+
+/// Replays the WAL generations in the archive folder onto empty storage, up
+/// to and including the commit of `transaction_id`.
+///
+/// Every archived transaction is replayed as a transaction of its own, in
+/// the order in which they committed, so the storage ends up in the state
+/// right after `transaction_id` committed. Transactions that never committed
+/// are skipped. Encrypted WAL files are decrypted with `cipher`.
+pub(crate) fn restore<S: PageStorageApi>(
+	storage: &S,
+	archive: &Path,
+	transaction_id: u64,
+	cipher: Option<&Arc<Cipher>>,
+) -> Result<(), StorageError> {
+	let mut pending: HashMap<u64, Vec<PendingWrite>> = HashMap::new();
+	let mut buf = Vec::new();
+	for (expected_generation, (generation, path)) in (0..).zip(list_wal_archive(archive)?) {
+		if generation != expected_generation {
+			return Err(StorageError::IncompleteWalArchive(expected_generation));
+		}
+		let cipher = cipher.map(|cipher| FileCipher::wal(Arc::clone(cipher), generation));
+		let mut file = WalFile::open_file_with_cipher(path, cipher)?;
+		let mut items = file.iter_items()?;
+		while let Some((_, item)) = items.next_into(&mut buf)? {
+			match item {
+				wal::Item::Write(data) => {
+					pending
+						.entry(data.transaction_data.transaction_id)
+						.or_default()
+						.push(PendingWrite {
+							page_id: data.page_id,
+							offset: data.offset,
+							data: data.to.into(),
+						});
+				}
+				wal::Item::Commit(data) => {
+					let writes = pending.remove(&data.transaction_id).unwrap_or_default();
+					replay(storage, writes)?;
+					if data.transaction_id == transaction_id {
+						return Ok(());
+					}
+				}
+				// The effects of custom records are outside of the pages, so
+				// restoring them is up to the application.
+				wal::Item::Custom(..) | wal::Item::Checkpoint(..) => (),
+			}
+		}
+	}
+	Err(StorageError::RestorePointNotFound(transaction_id))
+}
+
+fn replay<S: PageStorageApi>(storage: &S, writes: Vec<PendingWrite>) -> Result<(), StorageError> {
+	let mut t = storage.transaction()?;
+	for write in writes {
+		t.get_page_mut(write.page_id)?
+			.write(write.offset.into(), &write.data)?;
+	}
+	t.commit()
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::ThreadPool;
+	use tempfile::tempdir;
+
+	use crate::{
+		files::DatabaseFolder,
+		page_store::{test_helpers::page_id, PageStorage, ReadPage},
+	};
+
+	use super::*;
+
+	#[test]
+	fn restore_from_wal_archive() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let archive = tempdir.path().join("archive");
+		let folder = Arc::new(
+			DatabaseFolder::open(tempdir.path().join("db")).with_wal_archive(Some(archive.clone())),
+		);
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let storage =
+			PageStorage::create(folder, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		let mut transaction_ids = Vec::new();
+		for i in 1..=3 {
+			let mut t = storage.transaction().unwrap();
+			transaction_ids.push(t.id());
+			t.get_page_mut(page_id!(1, 1))
+				.unwrap()
+				.write(0, &[i; 4])
+				.unwrap();
+			t.get_page_mut(page_id!(1, i.into()))
+				.unwrap()
+				.write(8, &[i; 2])
+				.unwrap();
+			t.commit().unwrap();
+			storage.checkpoint().unwrap();
+		}
+		storage.checkpoint().unwrap();
+
+		// when
+		let restored_folder = Arc::new(DatabaseFolder::open(tempdir.path().join("restored")));
+		let restored =
+			PageStorage::create(restored_folder, thread_pool, &Default::default()).unwrap();
+		restore(&restored, &archive, transaction_ids[1], None).unwrap();
+
+		// then
+		let mut buf = [0; 4];
+		restored
+			.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [2; 4]);
+		let mut buf = [0; 2];
+		restored
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(8, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [2; 2]);
+		restored
+			.get_page(page_id!(1, 3))
+			.unwrap()
+			.read(8, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [0; 2]);
+		assert!(matches!(
+			restore(&restored, &archive, 1000, None),
+			Err(StorageError::RestorePointNotFound(1000))
+		));
+	}
+}
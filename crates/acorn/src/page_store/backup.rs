@@ -0,0 +1,235 @@
+use crate::files::{segment::PAGE_BODY_SIZE, PageId, WalIndex};
+
+use super::{PageStorageApi, ReadPage, SnapshotApi, StorageError, TransactionApi, WritePage};
+
+/// Rewrites a page image before it is copied into a backup, see
+/// [`backup`].
+pub(crate) type Canonicalize = fn(PageId, &mut [u8]);
+
+/// The number of pages that are copied in a single transaction.
+const BATCH_SIZE: usize = 64;
+
+/// Copies the state of the storage into the target storage, page by page,
+/// and returns the WAL index from which on writes are not included.
+///
+/// The pages are read through a snapshot, so transactions can keep writing
+/// to the storage while it is copied, and the copy still reflects a single
+/// commit. If `since` is the index returned by a previous backup into the
+/// same target, only the pages that were modified since then are copied;
+/// otherwise, the target has to be empty, and pages that were never written
+/// are skipped.
+///
+/// If `canonicalize` is given, it is applied to every page before it is
+/// copied, e.g. to zero the unused parts of pages, so that backups of
+/// databases with the same content consist of the same bytes.
+pub(crate) fn backup<S: PageStorageApi, T: PageStorageApi>(
+	storage: &S,
+	target: &T,
+	since: Option<WalIndex>,
+	canonicalize: Option<Canonicalize>,
+) -> Result<WalIndex, StorageError> {
+	// Every page that the snapshot sees as written is stored by the time the
+	// snapshot begins, so listing the pages afterwards finds all of them.
+	let (snapshot, position) = storage.snapshot_with_position()?;
+	let page_ids = match since {
+		Some(since) => storage.modified_pages(since)?,
+		None => storage.stored_pages()?,
+	};
+	let mut buf = vec![0; PAGE_BODY_SIZE];
+	let mut t = target.transaction()?;
+	let mut num_copied = 0;
+	for page_id in page_ids {
+		snapshot.get_page(page_id)?.read(0, &mut buf)?;
+		if let Some(canonicalize) = canonicalize {
+			canonicalize(page_id, &mut buf);
+		}
+		// A page that was cleared since the previous backup still has to be
+		// copied.
+		if since.is_none() && buf.iter().all(|byte| *byte == 0) {
+			continue;
+		}
+		t.get_page_mut(page_id)?.write(0, &buf)?;
+		num_copied += 1;
+		if num_copied % BATCH_SIZE == 0 {
+			t.commit()?;
+			t = target.transaction()?;
+		}
+	}
+	t.commit()?;
+	Ok(position)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use futures::executor::ThreadPool;
+	use tempfile::tempdir;
+
+	use crate::{
+		files::DatabaseFolder,
+		page_store::{test_helpers::page_id, PageStorage},
+	};
+
+	use super::*;
+
+	#[test]
+	fn backup_while_writing() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().join("db")));
+		let storage =
+			PageStorage::create(folder, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 4])
+			.unwrap();
+		t.get_page_mut(page_id!(2, 3))
+			.unwrap()
+			.write(100, &[2; 4])
+			.unwrap();
+		t.commit().unwrap();
+		let mut writer = storage.transaction().unwrap();
+		writer
+			.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[3; 4])
+			.unwrap();
+
+		// when
+		let target_folder = Arc::new(DatabaseFolder::open(tempdir.path().join("backup")));
+		let target = PageStorage::create(target_folder, thread_pool, &Default::default()).unwrap();
+		backup(&storage, &target, None, None).unwrap();
+		writer.commit().unwrap();
+
+		// then
+		let mut buf = [0; 4];
+		target
+			.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [1; 4]);
+		target
+			.get_page(page_id!(2, 3))
+			.unwrap()
+			.read(100, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [2; 4]);
+		assert_eq!(
+			target.stored_pages().unwrap(),
+			[page_id!(1, 1), page_id!(2, 3)]
+		);
+	}
+
+	#[test]
+	fn incremental_backup() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().join("db")));
+		let storage =
+			PageStorage::create(folder, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		let mut t = storage.transaction().unwrap();
+		for page_num in 1..=3 {
+			t.get_page_mut(page_id!(1, page_num))
+				.unwrap()
+				.write(0, &[1; 4])
+				.unwrap();
+		}
+		t.commit().unwrap();
+		storage.checkpoint().unwrap();
+		let target_folder = Arc::new(DatabaseFolder::open(tempdir.path().join("backup")));
+		let target = PageStorage::create(target_folder, thread_pool, &Default::default()).unwrap();
+		let backup_point = backup(&storage, &target, None, None).unwrap();
+
+		// when
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[0; 4])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 3))
+			.unwrap()
+			.write(0, &[2; 4])
+			.unwrap();
+		t.commit().unwrap();
+		storage.checkpoint().unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(2, 1))
+			.unwrap()
+			.write(0, &[3; 4])
+			.unwrap();
+		t.commit().unwrap();
+		let modified = storage.modified_pages(backup_point).unwrap();
+		let next_backup_point = backup(&storage, &target, Some(backup_point), None).unwrap();
+
+		// then
+		assert_eq!(modified, [page_id!(1, 1), page_id!(1, 3), page_id!(2, 1)]);
+		assert!(next_backup_point > backup_point);
+		assert_eq!(storage.modified_pages(next_backup_point).unwrap(), []);
+		let mut buf = [0; 4];
+		for (page_id, expected) in [
+			(page_id!(1, 1), [0; 4]),
+			(page_id!(1, 2), [1; 4]),
+			(page_id!(1, 3), [2; 4]),
+			(page_id!(2, 1), [3; 4]),
+		] {
+			target.get_page(page_id).unwrap().read(0, &mut buf).unwrap();
+			assert_eq!(buf, expected);
+		}
+	}
+
+	#[test]
+	fn canonicalize_backed_up_pages() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().join("db")));
+		let storage =
+			PageStorage::create(folder, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[0, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+
+		// when
+		let target_folder = Arc::new(DatabaseFolder::open(tempdir.path().join("backup")));
+		let target = PageStorage::create(target_folder, thread_pool, &Default::default()).unwrap();
+		// Treats the first byte as the length of the used part of the page.
+		backup(
+			&storage,
+			&target,
+			None,
+			Some(|_, page| {
+				let len = usize::from(page[0]) + 1;
+				page[len..].fill(0);
+			}),
+		)
+		.unwrap();
+
+		// then
+		let mut buf = [0; 4];
+		target
+			.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [1, 2, 0, 0]);
+		assert_eq!(target.stored_pages().unwrap(), [page_id!(1, 1)]);
+		storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut buf)
+			.unwrap();
+		assert_eq!(buf, [0, 2, 3, 4]);
+	}
+}
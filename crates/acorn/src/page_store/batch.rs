@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::files::wal::WRITE_ITEM_OVERHEAD;
+
+/// Changes to a page that are at most this many bytes apart are logged as a
+/// single write, since logging the unchanged bytes between them in both the
+/// old and the new content takes less space than another write item.
+const MAX_MERGED_GAP: usize = WRITE_ITEM_OVERHEAD / 2;
+
+/// A change to a region of a page, with the content of the region before
+/// and after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PageDiff {
+	pub offset: usize,
+	pub from: Vec<u8>,
+	pub to: Vec<u8>,
+}
+
+/// The original contents of all regions of a page that a transaction has
+/// written to, kept until the writes are logged on commit.
+///
+/// Overlapping and adjacent writes are merged into a single run, so that each
+/// contiguous modified region of the page produces only one WAL item.
+#[derive(Debug, Default)]
+pub(super) struct PageWriteBatch {
+	runs: BTreeMap<usize, Vec<u8>>,
+}
+
+impl PageWriteBatch {
+	/// Records a write at `offset`, where `from` is the content of the page
+	/// that is about to be overwritten. Returns by how many bytes the recorded
+	/// content grew.
+	pub fn record(&mut self, offset: usize, from: &[u8]) -> usize {
+		if from.is_empty() {
+			return 0;
+		}
+
+		let mut start = offset;
+		let mut end = offset + from.len();
+		let merged_runs: Vec<usize> = self
+			.runs
+			.range(..=end)
+			.filter(|(run_start, run)| *run_start + run.len() >= offset)
+			.map(|(run_start, _)| *run_start)
+			.collect();
+		for run_start in &merged_runs {
+			start = usize::min(start, *run_start);
+			end = usize::max(end, run_start + self.runs[run_start].len());
+		}
+
+		let merged_len: usize = merged_runs.iter().map(|start| self.runs[start].len()).sum();
+		let mut merged = vec![0; end - start];
+		merged[offset - start..offset - start + from.len()].copy_from_slice(from);
+		// Earlier writes already recorded the original content of their region,
+		// so those take precedence.
+		for run_start in merged_runs {
+			let run = self.runs.remove(&run_start).unwrap();
+			merged[run_start - start..run_start - start + run.len()].copy_from_slice(&run);
+		}
+		self.runs.insert(start, merged);
+		end - start - merged_len
+	}
+
+	pub fn runs(&self) -> impl Iterator<Item = (usize, &[u8])> {
+		self.runs
+			.iter()
+			.map(|(offset, run)| (*offset, run.as_slice()))
+	}
+
+	/// Returns the changes that the recorded writes made to the page, in
+	/// ascending order, reading its current content with `read`. Regions that
+	/// were written to but ended up unchanged are left out, and changes that
+	/// are close to each other are merged, so that a page that was written to
+	/// many times is logged with as few writes as possible.
+	pub fn diffs(&self, mut read: impl FnMut(usize, &mut [u8])) -> Vec<PageDiff> {
+		let mut diffs: Vec<PageDiff> = Vec::new();
+		for (offset, from) in self.runs() {
+			let mut to = vec![0; from.len()];
+			read(offset, &mut to);
+			let Some(range) = changed_range(from, &to) else {
+				continue;
+			};
+			let start = offset + range.start;
+			if let Some(last) = diffs.last_mut() {
+				let last_end = last.offset + last.from.len();
+				if start - last_end <= MAX_MERGED_GAP {
+					// The bytes in between weren't changed, so they are the same
+					// before and after.
+					let mut gap = vec![0; start - last_end];
+					read(last_end, &mut gap);
+					last.from.extend_from_slice(&gap);
+					last.to.extend_from_slice(&gap);
+					last.from.extend_from_slice(&from[range.clone()]);
+					last.to.extend_from_slice(&to[range]);
+					continue;
+				}
+			}
+			diffs.push(PageDiff {
+				offset: start,
+				from: from[range.clone()].to_vec(),
+				to: to[range].to_vec(),
+			});
+		}
+		diffs
+	}
+}
+
+/// Returns the smallest range outside of which `from` and `to` are equal, or
+/// `None` if they are equal entirely.
+fn changed_range(from: &[u8], to: &[u8]) -> Option<Range<usize>> {
+	debug_assert_eq!(from.len(), to.len());
+
+	let start = from.iter().zip(to).position(|(a, b)| a != b)?;
+	let end = from.len() - from.iter().zip(to).rev().position(|(a, b)| a != b)?;
+	Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn merge_overlapping_and_adjacent_writes() {
+		// given
+		let mut batch = PageWriteBatch::default();
+
+		// when
+		batch.record(10, &[1, 2, 3]);
+		batch.record(20, &[4, 5]);
+		batch.record(12, &[9, 6, 7]);
+		batch.record(15, &[8]);
+		batch.record(30, &[10]);
+
+		// then
+		let runs: Vec<(usize, &[u8])> = batch.runs().collect();
+		assert_eq!(
+			runs,
+			[
+				(10, [1, 2, 3, 6, 7, 8].as_slice()),
+				(20, [4, 5].as_slice()),
+				(30, [10].as_slice())
+			]
+		);
+	}
+
+	#[test]
+	fn merge_writes_spanning_multiple_runs() {
+		// given
+		let mut batch = PageWriteBatch::default();
+		batch.record(2, &[1]);
+		batch.record(5, &[2]);
+
+		// when
+		batch.record(0, &[0, 0, 0, 0, 0, 0, 0, 0]);
+
+		// then
+		let runs: Vec<(usize, &[u8])> = batch.runs().collect();
+		assert_eq!(runs, [(0, [0, 0, 1, 0, 0, 2, 0, 0].as_slice())]);
+	}
+
+	#[test]
+	fn merge_nearby_changes() {
+		// given
+		let mut page = [0; 200];
+		let mut batch = PageWriteBatch::default();
+		batch.record(10, &[0, 0]);
+		batch.record(15, &[0]);
+		batch.record(20, &[0, 0]);
+		batch.record(150, &[0]);
+		page[10..12].copy_from_slice(&[1, 2]);
+		page[20..22].copy_from_slice(&[0, 3]);
+		page[150] = 4;
+
+		// when
+		let diffs =
+			batch.diffs(|offset, buf| buf.copy_from_slice(&page[offset..offset + buf.len()]));
+
+		// then
+		assert_eq!(
+			diffs,
+			[
+				PageDiff {
+					offset: 10,
+					from: vec![0; 12],
+					to: vec![1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
+				},
+				PageDiff {
+					offset: 150,
+					from: vec![0],
+					to: vec![4],
+				}
+			]
+		);
+	}
+
+	#[test]
+	fn trim_unchanged_bytes() {
+		assert_eq!(changed_range(&[1, 2, 3, 4], &[1, 5, 6, 4]), Some(1..3));
+		assert_eq!(changed_range(&[1, 2, 3], &[1, 2, 3]), None);
+	}
+}
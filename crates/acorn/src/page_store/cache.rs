@@ -1,23 +1,24 @@
 use std::{
-	alloc::{alloc_zeroed, dealloc, Layout},
-	collections::HashMap,
+	alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout},
+	collections::{hash_map::Entry, HashMap},
+	fs,
+	future::Future,
+	io,
 	marker::PhantomData,
-	mem,
-	num::NonZeroU64,
+	mem::{self, ManuallyDrop},
+	num::{NonZeroU16, NonZeroU64},
+	path::Path,
 	ptr::{self, NonNull},
 	sync::{
-		atomic::{AtomicBool, AtomicUsize, Ordering},
+		atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
 		Arc,
 	},
-	time::Duration,
+	time::{Duration, Instant},
 };
 
 use futures::executor::ThreadPool;
 use log::error;
-use parking_lot::{
-	lock_api::{RawRwLock as _, RawRwLockDowngrade},
-	Mutex, RawRwLock, RwLock, RwLockReadGuard,
-};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use static_assertions::assert_impl_all;
 
 #[cfg(test)]
@@ -26,31 +27,132 @@ use mockall::automock;
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
 use crate::{
-	consts::{DEFAULT_FLUSH_PERIOD, DEFAULT_MAX_DIRTY_PAGES, DEFAULT_PAGE_CACHE_SIZE},
-	files::{segment::PAGE_BODY_SIZE, WalIndex},
-	tasks::{Timer, TimerHandle},
-	utils::cache::CacheReplacer,
+	consts::{
+		DEFAULT_COALESCE_WINDOW, DEFAULT_FLUSH_PERIOD, DEFAULT_MAX_DIRTY_AGE,
+		DEFAULT_MAX_DIRTY_PAGES, DEFAULT_MAX_TRANSACTION_PAGES, DEFAULT_PAGE_CACHE_SIZE,
+	},
+	failpoints::failpoint,
+	files::{segment::PAGE_BODY_SIZE, FileError, WalIndex},
+	repr::{U16, U32},
+	tasks::{BackgroundTasks, BlockingPool, TaskMonitor, TaskPanic, Timer, TimerHandle},
+	trace::event,
+	utils::{
+		cache::{CacheReplacer, EvictionPolicy, EvictionStats, EvictionTuning},
+		memory::hash_table_size,
+		rate_limit::RateLimiter,
+		sharded::ShardedMap,
+		units::ByteSize,
+	},
 };
 
 use super::{
-	physical::{PhysicalStorage, PhysicalStorageApi, WriteOp},
+	latch::FrameLock,
+	physical::{PhysicalStorage, PhysicalStorageApi, WriteOp, WriteRunOp},
 	PageId, StorageError,
 };
 
 #[derive(Debug, Clone, PartialEq)]
 pub(crate) struct PageCacheConfig {
 	pub page_cache_size: usize,
+	pub eviction_policy: EvictionPolicy,
+	pub eviction_tuning: EvictionTuning,
+	/// The fraction of the cache that may be dirty before the oldest dirty
+	/// pages are flushed in the background.
 	pub max_dirty_pages: f32,
+	/// The time after which a dirty page is flushed in the background.
+	pub max_dirty_age: Duration,
+	/// Dirty pages that were modified within this time are flushed in the
+	/// background only once the cache has too many dirty pages, so that the
+	/// writes to frequently modified pages are coalesced.
+	pub coalesce_window: Duration,
+	/// The fraction of the cache that a single transaction may keep locked
+	/// before it starts spilling its pages to disk.
+	pub max_transaction_pages: f32,
+	/// The number of bytes a single transaction may hold, in the cache, in
+	/// its write set and spilled to disk together, or `None` for no limit.
+	pub max_transaction_size: Option<usize>,
+	/// How often the background flush checks for pages that exceed
+	/// `max_dirty_age`.
 	pub flush_period: Duration,
+	/// The number of bytes per second that background flushes may write, or
+	/// `None` for no limit.
+	pub background_io_rate: Option<usize>,
+	/// Whether the periodic flush is restarted after it panicked.
+	pub restart_panicked_tasks: bool,
+	/// The number of threads of the pool that runs blocking work like
+	/// background prefetches, or `None` for one per CPU.
+	pub blocking_threads: Option<usize>,
 }
 
 impl Default for PageCacheConfig {
 	fn default() -> Self {
 		Self {
 			page_cache_size: DEFAULT_PAGE_CACHE_SIZE,
+			eviction_policy: EvictionPolicy::default(),
+			eviction_tuning: EvictionTuning::default(),
 			max_dirty_pages: DEFAULT_MAX_DIRTY_PAGES,
+			max_dirty_age: DEFAULT_MAX_DIRTY_AGE,
+			coalesce_window: DEFAULT_COALESCE_WINDOW,
+			max_transaction_pages: DEFAULT_MAX_TRANSACTION_PAGES,
+			max_transaction_size: None,
 			flush_period: DEFAULT_FLUSH_PERIOD,
+			background_io_rate: None,
+			restart_panicked_tasks: false,
+			blocking_threads: None,
+		}
+	}
+}
+
+impl PageCacheConfig {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if self.page_cache_size < BUFFERED_PAGE_SIZE {
+			return Err(StorageError::InvalidConfig(format!(
+				"The page cache size of {} can't hold a single page, which takes {}",
+				ByteSize(self.page_cache_size),
+				ByteSize(BUFFERED_PAGE_SIZE)
+			)));
+		}
+		if !(self.max_dirty_pages > 0.0 && self.max_dirty_pages <= 1.0) {
+			return Err(StorageError::InvalidConfig(format!(
+				"The maximum dirty ratio must be in (0, 1], but is {}",
+				self.max_dirty_pages
+			)));
 		}
+		if !(self.max_transaction_pages > 0.0 && self.max_transaction_pages <= 1.0) {
+			return Err(StorageError::InvalidConfig(format!(
+				"The fraction of the cache a transaction may hold must be in (0, 1], but is {}",
+				self.max_transaction_pages
+			)));
+		}
+		if self
+			.max_transaction_size
+			.is_some_and(|max| max < PAGE_BODY_SIZE)
+		{
+			return Err(StorageError::InvalidConfig(format!(
+				"The maximum size of a transaction has to allow for at least one page of {}",
+				ByteSize(PAGE_BODY_SIZE)
+			)));
+		}
+		if !(self.eviction_tuning.history_ratio >= 0.0
+			&& self.eviction_tuning.history_ratio.is_finite())
+		{
+			return Err(StorageError::InvalidConfig(format!(
+				"The eviction history ratio must be a non-negative number, but is {}",
+				self.eviction_tuning.history_ratio
+			)));
+		}
+		if self.eviction_tuning.promotion_threshold == 0 {
+			return Err(StorageError::InvalidConfig(
+				"The promotion threshold must be at least 1".to_string(),
+			));
+		}
+		Ok(())
+	}
+
+	pub fn transaction_page_limit(&self) -> usize {
+		let num_pages = self.page_cache_size / BUFFERED_PAGE_SIZE;
+		#[allow(clippy::cast_possible_truncation)]
+		usize::max((num_pages as f32 * self.max_transaction_pages) as usize, 1)
 	}
 }
 
@@ -93,34 +195,34 @@ impl BufferedPageHeader {
 }
 
 const HEADER_SIZE: usize = mem::size_of::<BufferedPageHeader>();
-const BUFFERED_PAGE_SIZE: usize = PAGE_BODY_SIZE + HEADER_SIZE;
+pub(super) const BUFFERED_PAGE_SIZE: usize = PAGE_BODY_SIZE + HEADER_SIZE;
 
+/// The memory for each page is allocated when it is first used, and can be
+/// released again, so that the memory usage of the cache can shrink.
 struct PageBuffer {
-	buf: Option<NonNull<u8>>,
-	num_pages: usize,
+	pages: Box<[AtomicPtr<u8>]>,
 	num_filled: AtomicUsize,
+	/// The number of optimistic reads in progress, which may be copying from
+	/// a page without holding its lock.
+	optimistic_reads: AtomicUsize,
 }
 
+const BUFFERED_PAGE_LAYOUT: Layout = Layout::new::<[u8; BUFFERED_PAGE_SIZE]>();
+
 impl PageBuffer {
 	fn new(num_pages: usize) -> Self {
-		let buf_size = num_pages * BUFFERED_PAGE_SIZE;
-		let buf = if buf_size != 0 {
-			// Safety: buf_size is guaranteed not to be zero, so the layout is not
-			// zero-sized.
-			unsafe { alloc_zeroed(Layout::from_size_align(buf_size, 1).unwrap()) }
-		} else {
-			ptr::null_mut()
-		};
 		Self {
-			buf: NonNull::new(buf),
-			num_pages,
+			pages: std::iter::repeat_with(|| AtomicPtr::new(ptr::null_mut()))
+				.take(num_pages)
+				.collect(),
 			num_filled: AtomicUsize::new(0),
+			optimistic_reads: AtomicUsize::new(0),
 		}
 	}
 
 	fn push_page(&self) -> Option<usize> {
 		let num_filled = self.num_filled.load(Ordering::Acquire);
-		if num_filled == self.num_pages {
+		if num_filled == self.pages.len() {
 			return None;
 		}
 		self.num_filled.store(num_filled + 1, Ordering::Release);
@@ -128,11 +230,31 @@ impl PageBuffer {
 	}
 
 	fn page_ptr(&self, index: usize) -> Option<NonNull<u8>> {
-		if index >= self.num_pages {
-			return None;
+		let slot = self.pages.get(index)?;
+		if let Some(page) = NonNull::new(slot.load(Ordering::Acquire)) {
+			return Some(page);
+		}
+
+		// Safety: BUFFERED_PAGE_LAYOUT is not zero-sized.
+		let new_page = unsafe { alloc_zeroed(BUFFERED_PAGE_LAYOUT) };
+		if new_page.is_null() {
+			handle_alloc_error(BUFFERED_PAGE_LAYOUT);
+		}
+		match slot.compare_exchange(
+			ptr::null_mut(),
+			new_page,
+			Ordering::AcqRel,
+			Ordering::Acquire,
+		) {
+			Ok(_) => NonNull::new(new_page),
+			Err(existing) => {
+				// Another thread allocated the page first.
+				// Safety: `new_page` was allocated above with the same layout, and was never
+				// shared.
+				unsafe { dealloc(new_page, BUFFERED_PAGE_LAYOUT) };
+				NonNull::new(existing)
+			}
 		}
-		// Safety: the resulting pointer is guaranteed to be in the allocated buffer.
-		Some(unsafe { self.buf?.add(index * BUFFERED_PAGE_SIZE) })
 	}
 
 	/// # Safety:
@@ -148,12 +270,58 @@ impl PageBuffer {
 	/// # Safety:
 	/// The caller must ensure that no shared reference, and no other mutable
 	/// references to the same page exist.
+	#[allow(clippy::mut_from_ref)]
 	unsafe fn get_page_mut(&self, index: usize) -> Option<&mut [u8]> {
 		Some(std::slice::from_raw_parts_mut(
 			self.page_ptr(index)?.as_ptr(),
 			BUFFERED_PAGE_SIZE,
 		))
 	}
+
+	/// Copies the body of the page at `index` into `buf`, without allocating
+	/// it if it isn't. Returns `false` if the page isn't allocated.
+	///
+	/// # Safety:
+	/// The caller must ensure that `index` is in bounds, and that the page
+	/// isn't written to during the copy, by copying it in
+	/// [`FrameLock::read_optimistic`].
+	unsafe fn copy_body_optimistic(&self, index: usize, buf: &mut [u8]) -> bool {
+		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+		// Registering the read before loading the pointer ensures that `release`
+		// either sees the read and waits for it, or that the read sees the
+		// released pointer.
+		self.optimistic_reads.fetch_add(1, Ordering::SeqCst);
+		let page = self.pages[index].load(Ordering::SeqCst);
+		let copied = !page.is_null();
+		if copied {
+			ptr::copy_nonoverlapping(page.add(HEADER_SIZE), buf.as_mut_ptr(), buf.len());
+		}
+		self.optimistic_reads.fetch_sub(1, Ordering::SeqCst);
+		copied
+	}
+
+	/// Frees the memory of the page at `index`. It is zeroed when it is next
+	/// used.
+	///
+	/// # Safety:
+	/// The caller must ensure that no references to the page exist.
+	unsafe fn release(&self, index: usize) {
+		let page = self.pages[index].swap(ptr::null_mut(), Ordering::SeqCst);
+		if !page.is_null() {
+			// Optimistic reads may still be copying from the page.
+			while self.optimistic_reads.load(Ordering::SeqCst) != 0 {
+				std::hint::spin_loop();
+			}
+			dealloc(page, BUFFERED_PAGE_LAYOUT);
+		}
+	}
+
+	fn num_allocated(&self) -> usize {
+		self.pages
+			.iter()
+			.filter(|page| !page.load(Ordering::Relaxed).is_null())
+			.count()
+	}
 }
 
 // Safety: The PageBuffer has no functionality that would make it unsafe
@@ -166,18 +334,10 @@ unsafe impl Sync for PageBuffer {}
 
 impl Drop for PageBuffer {
 	fn drop(&mut self) {
-		if let Some(buf) = self.buf {
-			let buf_size = self.num_pages * BUFFERED_PAGE_SIZE;
-			// Safety:
-			// - `self.buf` is guaranteed not to be null
-			// - The buffer is never reallocated, so the layout stays the same
-			unsafe {
-				dealloc(
-					buf.as_ptr(),
-					Layout::from_size_align(buf_size, mem::align_of::<BufferedPageHeader>())
-						.unwrap(),
-				)
-			}
+		for index in 0..self.pages.len() {
+			// Safety: we have exclusive access to the buffer, so no references to the
+			// page can exist.
+			unsafe { self.release(index) };
 		}
 	}
 }
@@ -185,7 +345,7 @@ impl Drop for PageBuffer {
 #[derive(Clone)]
 pub(crate) struct PageReadGuard<'a> {
 	page: &'a [u8],
-	lock: &'a RawRwLock,
+	lock: &'a FrameLock,
 	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
 }
 
@@ -218,12 +378,59 @@ impl<'a> Drop for PageReadGuard<'a> {
 	}
 }
 
-pub(crate) struct PageWriteGuard<'a> {
+/// Unlike [`PageReadGuard`], this keeps the buffer and locks alive itself,
+/// so that transactions can hold on to it without borrowing the cache.
+pub(crate) struct PageWriteGuard {
 	index: usize,
-	page: &'a mut [u8],
-	lock: &'a RawRwLock,
-	_marker: PhantomData<RwLockReadGuard<'a, [u8]>>,
+	buf: Arc<PageBuffer>,
+	locks: Arc<Box<[FrameLock]>>,
+}
+
+impl PageWriteGuard {
+	/// Takes ownership of the exclusive lock on the page at `index`, which has
+	/// to be held already.
+	fn new(index: usize, locks: &Arc<Box<[FrameLock]>>, buf: &Arc<PageBuffer>) -> Self {
+		// Allocate the page right away, like the cache always did for locked pages.
+		buf.page_ptr(index)
+			.expect("Tried to index page buffer out of bounds!");
+		Self {
+			index,
+			buf: Arc::clone(buf),
+			locks: Arc::clone(locks),
+		}
+	}
+
+	/// Gives up the guard without releasing the lock, and returns the index of
+	/// the page.
+	fn into_index(self) -> usize {
+		let this = ManuallyDrop::new(self);
+		// Safety: `this` is never dropped, so the fields are only dropped here.
+		mem::drop(unsafe { (ptr::read(&this.buf), ptr::read(&this.locks)) });
+		this.index
+	}
+
+	fn lock(&self) -> &FrameLock {
+		&self.locks[self.index]
+	}
+
+	fn page(&self) -> &[u8] {
+		// Safety: the existence of this object guarantees that the exclusive lock
+		// is owned by the current context.
+		unsafe { self.buf.get_page(self.index) }.expect("Tried to index page buffer out of bounds!")
+	}
+
+	fn page_mut(&mut self) -> &mut [u8] {
+		// Safety: the existence of this object guarantees that the exclusive lock
+		// is owned by the current context, and `self` is borrowed mutably.
+		unsafe { self.buf.get_page_mut(self.index) }
+			.expect("Tried to index page buffer out of bounds!")
+	}
 }
+// Transactions hold on to page locks between operations, and may be moved to
+// another thread in the meantime. parking_lot's locks support being unlocked
+// from a different thread than they were locked on, as long as deadlock
+// detection is disabled; the `send_guard` feature enforces this.
+assert_impl_all!(PageWriteGuard: Send);
 
 #[cfg_attr(test, automock)]
 pub(crate) trait PageWriteGuardApi {
@@ -235,21 +442,21 @@ pub(crate) trait PageWriteGuardApi {
 	fn write(&mut self, offset: usize, buf: &[u8], wal_index: WalIndex);
 }
 
-impl<'a> PageWriteGuardApi for PageWriteGuard<'a> {
+impl PageWriteGuardApi for PageWriteGuard {
 	fn header(&self) -> &BufferedPageHeader {
-		BufferedPageHeader::ref_from(&self.page[0..HEADER_SIZE]).unwrap()
+		BufferedPageHeader::ref_from(&self.page()[0..HEADER_SIZE]).unwrap()
 	}
 
 	fn header_mut(&mut self) -> &mut BufferedPageHeader {
-		BufferedPageHeader::mut_from(&mut self.page[0..HEADER_SIZE]).unwrap()
+		BufferedPageHeader::mut_from(&mut self.page_mut()[0..HEADER_SIZE]).unwrap()
 	}
 
 	fn body(&self) -> &[u8] {
-		&self.page[HEADER_SIZE..]
+		&self.page()[HEADER_SIZE..]
 	}
 
 	fn body_mut(&mut self) -> &mut [u8] {
-		&mut self.page[HEADER_SIZE..]
+		&mut self.page_mut()[HEADER_SIZE..]
 	}
 
 	fn read(&self, offset: usize, buf: &mut [u8]) {
@@ -264,29 +471,130 @@ impl<'a> PageWriteGuardApi for PageWriteGuard<'a> {
 	}
 }
 
-impl<'a> Drop for PageWriteGuard<'a> {
+impl Drop for PageWriteGuard {
 	fn drop(&mut self) {
 		// Safety: the existence of this object guarantees the lock is owned by the
 		// current context
-		unsafe { self.lock.unlock_exclusive() };
+		unsafe { self.lock().unlock_exclusive() };
 	}
 }
 
+/// How often an optimistic read is retried after it conflicted with a writer,
+/// before falling back to locking the page.
+const MAX_OPTIMISTIC_ATTEMPTS: usize = 3;
+
+/// Counters of the page cache, since the database was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+	/// The number of page accesses that found the page in the cache.
+	pub hits: u64,
+	/// The number of pages that had to be read from storage.
+	pub misses: u64,
+	/// The number of pages that were removed from the cache to make room for
+	/// others, or to free memory.
+	pub evictions: u64,
+	/// The number of pages that are currently dirty.
+	pub dirty_pages: usize,
+	/// The largest number of dirty pages at any time.
+	pub peak_dirty_pages: usize,
+	/// The number of optimistic page reads that had to be retried, because
+	/// the page was modified while it was read.
+	pub optimistic_conflicts: u64,
+	/// How the eviction policy treated the cached pages.
+	pub eviction: EvictionStats,
+}
+
+#[derive(Debug, Default)]
+struct CacheCounters {
+	hits: AtomicU64,
+	misses: AtomicU64,
+	evictions: AtomicU64,
+	peak_dirty_pages: AtomicUsize,
+	optimistic_conflicts: AtomicU64,
+}
+
 pub(crate) struct PageCache<PS: PhysicalStorageApi = PhysicalStorage> {
 	buf: Arc<PageBuffer>,
 	physical_storage: Arc<PS>,
 	thread_pool: Arc<ThreadPool>,
-	indices: Arc<RwLock<HashMap<PageId, usize>>>,
+	blocking_pool: Arc<BlockingPool>,
+	indices: Arc<PageIndices>,
 	replacer: RwLock<CacheReplacer<PageId>>,
+	/// How often each pinned page was pinned, see [`PageCacheApi::pin`].
+	pins: ShardedMap<PageId, usize>,
 	scrap: Mutex<Vec<usize>>,
 	has_scrap: AtomicBool,
-	dirty_list: Arc<Mutex<Vec<PageId>>>,
-	locks: Arc<Box<[RawRwLock]>>,
+	dirty_pages: Arc<DirtyPages>,
+	locks: Arc<Box<[FrameLock]>>,
 	max_num_dirty: usize,
+	coalesce_window: Duration,
+	io_limiter: Arc<RateLimiter>,
 	flush_timer_handle: TimerHandle,
+	tasks: Arc<TaskMonitor>,
+	counters: CacheCounters,
 }
 assert_impl_all!(PageCache: Send, Sync);
 
+/// Where in the buffer each cached page is stored.
+type PageIndices = ShardedMap<PageId, usize>;
+
+/// The pages that may be dirty, along with when they were modified since they
+/// were last flushed.
+type DirtyPages = ShardedMap<PageId, DirtyPage>;
+
+#[derive(Debug, Clone, Copy)]
+struct DirtyPage {
+	since: Instant,
+	last_modified: Instant,
+}
+
+impl DirtyPage {
+	fn new(now: Instant) -> Self {
+		Self {
+			since: now,
+			last_modified: now,
+		}
+	}
+
+	fn modified_within(&self, now: Instant, window: Duration) -> bool {
+		now.duration_since(self.last_modified) < window
+	}
+}
+
+/// Consecutive dirty pages of a segment, copied out of the cache so that they
+/// can be written back with a single write.
+#[derive(Default)]
+struct WriteRun {
+	first_page: Option<PageId>,
+	indices: Vec<usize>,
+	wal_indices: Vec<WalIndex>,
+	bufs: Vec<u8>,
+}
+
+impl WriteRun {
+	const MAX_LEN: usize = 32;
+
+	/// Whether the page can be added to the run, because it directly follows
+	/// its last page.
+	fn continues(&self, page_id: PageId) -> bool {
+		let Some(first_page) = self.first_page else {
+			return true;
+		};
+		first_page.segment_num == page_id.segment_num
+			&& self.indices.len() < Self::MAX_LEN
+			&& usize::from(first_page.page_num.get()) + self.indices.len()
+				== usize::from(page_id.page_num.get())
+	}
+
+	fn push(&mut self, page_id: PageId, index: usize, guard: &PageReadGuard) {
+		debug_assert!(self.continues(page_id));
+		self.first_page.get_or_insert(page_id);
+		self.indices.push(index);
+		self.wal_indices.push(guard.header().wal_index());
+		self.bufs.extend_from_slice(guard.body());
+	}
+}
+
 // Safety: `buf`'s internal pointer is never leaked in any form.
 unsafe impl<PS: PhysicalStorageApi + Send + Sync> Send for PageCache<PS> {}
 
@@ -303,38 +611,63 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
 	) -> Self {
 		let num_pages = config.page_cache_size / BUFFERED_PAGE_SIZE;
 		let buf = Arc::new(PageBuffer::new(num_pages));
-		let replacer = CacheReplacer::new(num_pages);
-		let indices = Arc::new(RwLock::new(HashMap::new()));
-		let dirty_list = Arc::new(Mutex::new(Vec::new()));
+		let replacer = CacheReplacer::new(config.eviction_policy, num_pages)
+			.with_tuning(&config.eviction_tuning);
+		let indices = Arc::new(PageIndices::new());
+		let dirty_pages = Arc::new(DirtyPages::new());
 		let locks = Arc::new(
-			std::iter::repeat_with(|| RawRwLock::INIT)
+			std::iter::repeat_with(FrameLock::new)
 				.take(num_pages)
 				.collect(),
 		);
 
+		let io_limiter = Arc::new(RateLimiter::new(config.background_io_rate));
+
+		let tasks = Arc::new(TaskMonitor::new(config.restart_panicked_tasks));
 		let (flush_timer, flush_timer_handle) = Timer::new(config.flush_period);
-		thread_pool.spawn_ok(Self::periodic_flush_task(
-			flush_timer,
-			Arc::clone(&physical_storage),
-			Arc::clone(&dirty_list),
-			Arc::clone(&indices),
-			Arc::clone(&locks),
-			Arc::clone(&buf),
-		));
+		tasks.spawn_periodic(&thread_pool, "flush", flush_timer, {
+			let max_age = config.max_dirty_age;
+			let coalesce_window = config.coalesce_window;
+			let physical_storage = Arc::clone(&physical_storage);
+			let dirty_pages = Arc::clone(&dirty_pages);
+			let indices = Arc::clone(&indices);
+			let locks = Arc::clone(&locks);
+			let buf = Arc::clone(&buf);
+			let io_limiter = Arc::clone(&io_limiter);
+			move |timer| {
+				Self::periodic_flush_task(
+					timer,
+					max_age,
+					coalesce_window,
+					Arc::clone(&physical_storage),
+					Arc::clone(&dirty_pages),
+					Arc::clone(&indices),
+					Arc::clone(&locks),
+					Arc::clone(&buf),
+					Arc::clone(&io_limiter),
+				)
+			}
+		});
 
 		Self {
 			buf,
 			physical_storage,
 			thread_pool,
+			blocking_pool: Arc::new(BlockingPool::new(config.blocking_threads)),
 			replacer: RwLock::new(replacer),
 			indices,
+			pins: ShardedMap::new(),
 			scrap: Mutex::new(Vec::new()),
 			has_scrap: AtomicBool::new(false),
-			dirty_list,
+			dirty_pages,
 			locks,
 			#[allow(clippy::cast_possible_truncation)]
 			max_num_dirty: usize::max((num_pages as f32 * config.max_dirty_pages) as usize, 1),
+			coalesce_window: config.coalesce_window,
+			io_limiter,
 			flush_timer_handle,
+			tasks,
+			counters: CacheCounters::default(),
 		}
 	}
 
@@ -345,18 +678,21 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
 
 		loop {
 			if let Some(evicted) = maybe_evict {
-				let indices = self.indices.read();
-				let index = *indices
-					.get(&evicted)
-					.expect("Tried to evict a page that is not in the cache!");
-
 				// If we are trying to evict the same page that we're inserting, or if the page
-				// we're trying to evict is currently locked, we reinsert it and try the next
-				// candidate.
+				// we're trying to evict is currently locked or pinned, we reinsert it and try
+				// the next candidate. The page we're inserting is not in the indices yet, so it
+				// has to be checked first.
 				//
 				// Note that this ends up in an infinite loop if all pages in the cache are
-				// locked over an extended period, but that should rarely happen.
-				if evicted == page_id || self.locks[index].is_locked() {
+				// locked or pinned over an extended period, but that should rarely happen.
+				let is_locked = || {
+					let index = self
+						.indices
+						.get(&evicted)
+						.expect("Tried to evict a page that is not in the cache!");
+					self.locks[index].is_locked()
+				};
+				if evicted == page_id || is_locked() || self.pins.contains_key(&evicted) {
 					let mut replacer = self.replacer.write();
 					maybe_evict = replacer.evict_replace(evicted);
 					continue;
@@ -367,42 +703,207 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
 		maybe_evict
 	}
 
-	fn get_store_index(&self, page_id: PageId) -> usize {
-		let indices = self.indices.read();
-		if let Some(stored_index) = indices.get(&page_id).copied() {
-			return stored_index;
+	/// Assigns a frame to the page, and locks it exclusively. The frame is
+	/// locked before it is assigned, so that optimistic reads of the page
+	/// don't see what the frame held before.
+	fn store_guard(&self, page_id: PageId) -> Result<PageWriteGuard, StorageError> {
+		if let Some(stored_index) = self.indices.get(&page_id) {
+			return Ok(Self::load_mut_direct(&self.locks, &self.buf, stored_index));
 		}
-		mem::drop(indices);
 
 		if self.has_scrap.load(Ordering::Relaxed) {
 			let mut scrap = self.scrap.lock();
 			if let Some(scrap_index) = scrap.pop() {
-				return scrap_index;
+				mem::drop(scrap);
+				let mut replacer = self.replacer.write();
+				let evicted = replacer.evict_replace(page_id);
+				debug_assert!(evicted.is_none());
+				mem::drop(replacer);
+
+				let guard = Self::load_mut_direct(&self.locks, &self.buf, scrap_index);
+				self.indices.insert(page_id, scrap_index);
+				return Ok(guard);
 			}
 		}
 
-		let maybe_evict = self.evict_for(page_id);
-		let mut indices = self.indices.write();
-		if let Some(evict) = maybe_evict {
-			let index = indices
+		if let Some(evict) = self.evict_for(page_id) {
+			let index = self
+				.indices
 				.remove(&evict)
 				.expect("Tried to evict a page that is not in the cache!");
-			indices.insert(page_id, index);
-			index
+			let mut guard = Self::load_mut_direct(&self.locks, &self.buf, index);
+
+			if let Err(error) = self.write_back(evict, &mut guard) {
+				self.indices.insert(evict, index);
+				let mut replacer = self.replacer.write();
+				replacer.remove(&page_id);
+				replacer.evict_replace(evict);
+				return Err(error);
+			}
+			self.indices.insert(page_id, index);
+			self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+			event!(TRACE, page_id = ?evict, "Evicted a page");
+			Ok(guard)
 		} else {
 			let index = self
 				.buf
 				.push_page()
 				.expect("Failed to evict a page when the buffer was full!");
-			indices.insert(page_id, index);
-			index
+			let guard = Self::load_mut_direct(&self.locks, &self.buf, index);
+			self.indices.insert(page_id, index);
+			Ok(guard)
+		}
+	}
+
+	/// Writes a page that is being evicted back to physical storage if it is
+	/// dirty, since its changes would be lost otherwise.
+	fn write_back(&self, page_id: PageId, guard: &mut PageWriteGuard) -> Result<(), StorageError> {
+		failpoint!(PAGE_EVICT);
+		if !guard.header().dirty() {
+			return Ok(());
 		}
+		self.physical_storage.write(WriteOp {
+			wal_index: guard.header().wal_index(),
+			page_id,
+			buf: guard.body(),
+		})?;
+		guard.header_mut().set_dirty(false);
+		Ok(())
+	}
+
+	/// Removes a page from the cache and frees its memory, unless it is dirty,
+	/// pinned or currently locked.
+	fn release_page(&self, page_id: PageId, index: usize) -> bool {
+		if self.pins.contains_key(&page_id) {
+			return false;
+		}
+		let lock = &self.locks[index];
+		if !lock.try_lock_exclusive() {
+			return false;
+		}
+
+		let released = self.indices.update(&page_id, |indices| {
+			if indices.get(&page_id) != Some(&index) {
+				return false;
+			}
+			// Safety: The safety of the reference is guaranteed by acquiring the exclusive
+			// lock.
+			let page = unsafe { self.buf.get_page(index) }
+				.expect("Tried to index page buffer out of bounds!");
+			if BufferedPageHeader::ref_from(&page[0..HEADER_SIZE])
+				.unwrap()
+				.dirty()
+			{
+				return false;
+			}
+			indices.remove(&page_id);
+			self.replacer.write().remove(&page_id);
+			// Safety: The exclusive lock is held, and the reference to the page has been
+			// dropped.
+			unsafe { self.buf.release(index) };
+			self.has_scrap.store(true, Ordering::Relaxed);
+			self.scrap.lock().push(index);
+			true
+		});
+
+		// Safety: The lock was acquired above.
+		unsafe { lock.unlock_exclusive() };
+		released
+	}
+
+	/// Remembers that a page may become dirty, so that it is considered in the
+	/// next flush.
+	fn track_dirty(&self, page_id: PageId) {
+		let now = Instant::now();
+		self.dirty_pages.update(&page_id, |dirty_pages| {
+			dirty_pages
+				.entry(page_id)
+				.and_modify(|page| page.last_modified = now)
+				.or_insert_with(|| DirtyPage::new(now));
+		});
+		let num_dirty = self.dirty_pages.len();
+		self.counters
+			.peak_dirty_pages
+			.fetch_max(num_dirty, Ordering::Relaxed);
+		if num_dirty >= self.max_num_dirty {
+			// Flushing only the oldest half keeps the flush short, and leaves
+			// the pages that are most likely to be modified again in the cache.
+			self.tasks.spawn(
+				&self.thread_pool,
+				"flush",
+				Self::single_flush_task(
+					self.max_num_dirty / 2,
+					self.coalesce_window,
+					Arc::clone(&self.physical_storage),
+					Arc::clone(&self.dirty_pages),
+					Arc::clone(&self.indices),
+					Arc::clone(&self.locks),
+					Arc::clone(&self.buf),
+					Some(Arc::clone(&self.io_limiter)),
+				),
+			);
+		}
+	}
+
+	fn flush_filtered(&self, filter: impl Fn(PageId) -> bool) -> Result<(), StorageError> {
+		Self::flush(
+			&self.physical_storage,
+			&self.dirty_pages,
+			&self.indices,
+			&self.locks,
+			&self.buf,
+			None,
+			|dirty_pages| {
+				dirty_pages
+					.keys()
+					.copied()
+					.filter(|page_id| filter(*page_id))
+					.collect()
+			},
+		)
+	}
+
+	/// Selects the dirty pages that were modified the longest time ago, such
+	/// that `keep` pages remain. Pages modified within the `coalesce_window`
+	/// are kept over all others.
+	fn select_oldest(
+		dirty_pages: &HashMap<PageId, DirtyPage>,
+		keep: usize,
+		coalesce_window: Duration,
+	) -> Vec<PageId> {
+		let now = Instant::now();
+		let mut pages: Vec<(PageId, DirtyPage)> = dirty_pages
+			.iter()
+			.map(|(page_id, page)| (*page_id, *page))
+			.collect();
+		pages.sort_unstable_by_key(|(_, page)| {
+			(page.modified_within(now, coalesce_window), page.since)
+		});
+		pages.truncate(pages.len().saturating_sub(keep));
+		pages.into_iter().map(|(page_id, _)| page_id).collect()
+	}
+
+	/// Selects the pages that have been dirty for at least `max_age`, unless
+	/// they were modified within the `coalesce_window`.
+	fn select_expired(
+		dirty_pages: &HashMap<PageId, DirtyPage>,
+		max_age: Duration,
+		coalesce_window: Duration,
+	) -> Vec<PageId> {
+		let now = Instant::now();
+		dirty_pages
+			.iter()
+			.filter(|(_, page)| {
+				now.duration_since(page.since) >= max_age
+					&& !page.modified_within(now, coalesce_window)
+			})
+			.map(|(page_id, _)| *page_id)
+			.collect()
 	}
 
 	fn get_load_index(&self, page_id: PageId) -> Option<usize> {
-		let indices = self.indices.read();
-		let index = indices.get(&page_id).copied()?;
-		mem::drop(indices);
+		let index = self.indices.get(&page_id)?;
+		self.counters.hits.fetch_add(1, Ordering::Relaxed);
 
 		let replacer = self.replacer.read();
 		let access_successful = replacer.access(&page_id);
@@ -413,7 +914,7 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
 	}
 
 	fn load_direct<'a>(
-		locks: &'a [RawRwLock],
+		locks: &'a [FrameLock],
 		buf: &'a PageBuffer,
 		index: usize,
 	) -> PageReadGuard<'a> {
@@ -431,139 +932,348 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
 		}
 	}
 
-	fn load_mut_direct<'a>(
-		locks: &'a [RawRwLock],
-		buf: &'a PageBuffer,
+	fn load_mut_direct(
+		locks: &Arc<Box<[FrameLock]>>,
+		buf: &Arc<PageBuffer>,
 		index: usize,
-	) -> PageWriteGuard<'a> {
-		let lock = &locks[index];
-		lock.lock_exclusive();
-		// Safety: The safety of the reference is guaranteed by acquiring the exclusive
-		// lock.
-		let page =
-			unsafe { buf.get_page_mut(index) }.expect("Triet to index page buffer out of bounds!");
-
-		PageWriteGuard {
-			index,
-			lock,
-			page,
-			_marker: PhantomData,
-		}
+	) -> PageWriteGuard {
+		locks[index].lock_exclusive();
+		PageWriteGuard::new(index, locks, buf)
 	}
 
 	fn flush(
 		physical_storage: &PS,
-		dirty_list: &Mutex<Vec<PageId>>,
-		indices: &RwLock<HashMap<PageId, usize>>,
-		locks: &[RawRwLock],
-		buf: &PageBuffer,
+		dirty_pages: &DirtyPages,
+		indices: &PageIndices,
+		locks: &Arc<Box<[FrameLock]>>,
+		buf: &Arc<PageBuffer>,
+		limiter: Option<&RateLimiter>,
+		select: impl FnOnce(&HashMap<PageId, DirtyPage>) -> Vec<PageId>,
 	) -> Result<(), StorageError> {
-		let mut dirty_list_guard = dirty_list.lock();
-		let dirty_list_copy = dirty_list_guard.clone();
-		dirty_list_guard.clear();
-		mem::drop(dirty_list_guard);
-
+		let candidates: HashMap<PageId, DirtyPage> = dirty_pages.entries().into_iter().collect();
+		let dirty_pages_copy: Vec<(PageId, DirtyPage)> = select(&candidates)
+			.into_iter()
+			.filter_map(|page_id| Some((page_id, dirty_pages.remove(&page_id)?)))
+			.collect();
+
+		// Writing the pages in order, and consecutive pages with a single write,
+		// keeps the I/O as sequential as possible.
+		let mut page_ids: Vec<PageId> = dirty_pages_copy
+			.iter()
+			.map(|(page_id, _)| *page_id)
+			.collect();
+		page_ids.sort_unstable();
+
+		let mut run = WriteRun::default();
 		let mut error: Option<StorageError> = None;
-		for page_id in dirty_list_copy.iter().copied() {
-			let indices = indices.read();
-			let Some(index) = indices.get(&page_id).copied() else {
+		for page_id in page_ids {
+			let Some(index) = indices.get(&page_id) else {
 				continue;
 			};
-			mem::drop(indices);
 
+			if !run.continues(page_id) {
+				if let Err(err) = Self::write_run(physical_storage, locks, buf, limiter, &mut run) {
+					error = Some(err);
+					break;
+				}
+			}
 			let guard = Self::load_direct(locks, buf, index);
-			if !guard.header().dirty() {
-				continue;
+			if guard.header().dirty() {
+				run.push(page_id, index, &guard);
 			}
-			if let Err(err) = physical_storage.write(WriteOp {
-				wal_index: guard.header().wal_index(),
-				page_id,
-				buf: guard.body(),
-			}) {
-				error = Some(err);
-				break;
-			};
-			mem::drop(guard);
-
-			let mut guard_mut = Self::load_mut_direct(locks, buf, index);
-			guard_mut.header_mut().set_dirty(false);
+		}
+		if error.is_none() {
+			error = Self::write_run(physical_storage, locks, buf, limiter, &mut run).err();
 		}
 
 		if let Some(err) = error {
-			let mut dirty_list_guard = dirty_list.lock();
-			dirty_list_guard.extend(&dirty_list_copy);
+			for (page_id, dirty_page) in dirty_pages_copy {
+				dirty_pages.insert(page_id, dirty_page);
+			}
 			return Err(err);
 		}
 
+		event!(
+			DEBUG,
+			num_pages = dirty_pages_copy.len(),
+			"Flushed dirty pages"
+		);
+		Ok(())
+	}
+
+	/// Writes a run of pages back to storage, and marks them as clean. If
+	/// there is a limiter, waits until it allows the write first.
+	fn write_run(
+		physical_storage: &PS,
+		locks: &Arc<Box<[FrameLock]>>,
+		buf: &Arc<PageBuffer>,
+		limiter: Option<&RateLimiter>,
+		run: &mut WriteRun,
+	) -> Result<(), StorageError> {
+		let run = mem::take(run);
+		let Some(first_page) = run.first_page else {
+			return Ok(());
+		};
+		if let Some(limiter) = limiter {
+			limiter.acquire(run.bufs.len());
+		}
+		if let [wal_index] = run.wal_indices[..] {
+			physical_storage.write(WriteOp {
+				wal_index,
+				page_id: first_page,
+				buf: &run.bufs,
+			})?;
+		} else {
+			physical_storage.write_run(WriteRunOp {
+				first_page,
+				bufs: &run.bufs,
+				wal_indices: &run.wal_indices,
+			})?;
+		}
+		for index in run.indices {
+			let mut guard_mut = Self::load_mut_direct(locks, buf, index);
+			guard_mut.header_mut().set_dirty(false);
+		}
 		Ok(())
 	}
 
 	async fn flush_ok(
 		physical_storage: &PS,
-		dirty_list: &Mutex<Vec<PageId>>,
-		indices: &RwLock<HashMap<PageId, usize>>,
-		locks: &[RawRwLock],
-		buf: &PageBuffer,
+		dirty_pages: &DirtyPages,
+		indices: &PageIndices,
+		locks: &Arc<Box<[FrameLock]>>,
+		buf: &Arc<PageBuffer>,
+		limiter: Option<&RateLimiter>,
+		select: impl FnOnce(&HashMap<PageId, DirtyPage>) -> Vec<PageId>,
 	) {
-		if let Err(err) = Self::flush(physical_storage, dirty_list, indices, locks, buf) {
+		if let Err(err) = Self::flush(
+			physical_storage,
+			dirty_pages,
+			indices,
+			locks,
+			buf,
+			limiter,
+			select,
+		) {
 			error!("Page cache flush failed: {err}");
 		}
 	}
 
+	/// Flushes the oldest dirty pages, until only `keep` of them remain.
+	#[allow(clippy::too_many_arguments)]
 	async fn single_flush_task(
+		keep: usize,
+		coalesce_window: Duration,
 		physical_storage: Arc<PS>,
-		dirty_list: Arc<Mutex<Vec<PageId>>>,
-		indices: Arc<RwLock<HashMap<PageId, usize>>>,
-		locks: Arc<Box<[RawRwLock]>>,
+		dirty_pages: Arc<DirtyPages>,
+		indices: Arc<PageIndices>,
+		locks: Arc<Box<[FrameLock]>>,
 		buf: Arc<PageBuffer>,
+		limiter: Option<Arc<RateLimiter>>,
 	) {
-		Self::flush_ok(&physical_storage, &dirty_list, &indices, &locks, &buf).await;
+		Self::flush_ok(
+			&physical_storage,
+			&dirty_pages,
+			&indices,
+			&locks,
+			&buf,
+			limiter.as_deref(),
+			|dirty_pages| Self::select_oldest(dirty_pages, keep, coalesce_window),
+		)
+		.await;
 	}
 
+	/// Periodically flushes the pages that have been dirty for longer than
+	/// `max_age`, until the cache is dropped.
+	#[allow(clippy::too_many_arguments)]
 	async fn periodic_flush_task(
-		timer: Timer,
+		mut timer: Timer,
+		max_age: Duration,
+		coalesce_window: Duration,
 		physical_storage: Arc<PS>,
-		dirty_list: Arc<Mutex<Vec<PageId>>>,
-		indices: Arc<RwLock<HashMap<PageId, usize>>>,
-		locks: Arc<Box<[RawRwLock]>>,
+		dirty_pages: Arc<DirtyPages>,
+		indices: Arc<PageIndices>,
+		locks: Arc<Box<[FrameLock]>>,
 		buf: Arc<PageBuffer>,
+		limiter: Arc<RateLimiter>,
 	) {
 		while timer.wait() {
-			Self::flush_ok(&physical_storage, &dirty_list, &indices, &locks, &buf).await;
+			Self::flush_ok(
+				&physical_storage,
+				&dirty_pages,
+				&indices,
+				&locks,
+				&buf,
+				Some(&limiter),
+				|dirty_pages| Self::select_expired(dirty_pages, max_age, coalesce_window),
+			)
+			.await;
 		}
 	}
 }
 
+/// The result of loading a cached page with a time limit, see
+/// [`PageCacheApi::load_until`].
+#[derive(Debug)]
+pub(crate) enum TimedLoad<G> {
+	Loaded(G),
+	/// The page isn't cached.
+	Missing,
+	/// The page was still locked by another thread at the time limit.
+	Busy,
+}
+
 #[cfg_attr(test, automock(
     type ReadGuard<'a> = MockPageReadGuardApi;
-    type WriteGuard<'a> = MockPageWriteGuardApi;
+    type WriteGuard = MockPageWriteGuardApi;
 ))]
 #[allow(clippy::needless_lifetimes)]
 pub(crate) trait PageCacheApi {
 	type ReadGuard<'a>: PageReadGuardApi + 'a
 	where
 		Self: 'a;
-	type WriteGuard<'a>: PageWriteGuardApi + 'a
-	where
-		Self: 'a;
+	type WriteGuard: PageWriteGuardApi;
 
 	fn has_page(&self, page_id: PageId) -> bool;
 	fn load<'a>(&'a self, page_id: PageId) -> Option<Self::ReadGuard<'a>>;
-	fn load_mut<'a>(&'a self, page_id: PageId) -> Option<Self::WriteGuard<'a>>;
-	fn store<'a>(&'a self, page_id: PageId) -> Self::WriteGuard<'a>;
+	fn try_load<'a>(&'a self, page_id: PageId) -> Option<Self::ReadGuard<'a>>;
+	/// Like [`Self::load`], but stops waiting for the page to be unlocked at
+	/// `until`.
+	fn load_until<'a>(&'a self, page_id: PageId, until: Instant) -> TimedLoad<Self::ReadGuard<'a>>;
+	/// Copies the body of a cached page into `buf` without locking it, so
+	/// that the read doesn't block writers or wait for them. Returns `false`
+	/// if the page isn't cached, or if it was locked for writing during every
+	/// attempt, in which case it has to be loaded with [`Self::load`] instead.
+	fn read_optimistic(&self, page_id: PageId, buf: &mut [u8]) -> bool;
+	fn load_mut<'a>(&'a self, page_id: PageId) -> Option<Self::WriteGuard>;
+	/// Like [`Self::load_mut`], but stops waiting for the page to be unlocked
+	/// at `until`.
+	fn load_mut_until<'a>(
+		&'a self,
+		page_id: PageId,
+		until: Instant,
+	) -> TimedLoad<Self::WriteGuard>;
+	fn store<'a>(&'a self, page_id: PageId) -> Result<Self::WriteGuard, StorageError>;
 	fn flush(&self);
 	fn flush_sync(&self) -> Result<(), StorageError>;
+	fn flush_segment(&self, segment_num: u32) -> Result<(), StorageError>;
+	fn flush_pages(&self, page_ids: &[PageId]) -> Result<(), StorageError>;
+	/// The cached pages that weren't written back since they were modified,
+	/// in ascending order.
+	fn dirty_pages(&self) -> Vec<PageId>;
+	/// The pages that are currently cached, in ascending order.
+	fn cached_pages(&self) -> Vec<PageId>;
+	/// The cached pages that were modified at or after `since`, and not
+	/// written back yet, in ascending order. Pages that are locked for
+	/// writing are included, since they may have been.
+	fn dirty_since(&self, since: WalIndex) -> Vec<PageId>;
 	fn scrap(&self, page_id: PageId);
-	fn downgrade_guard<'a>(&'a self, guard: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>;
+	/// Keeps the page in the cache until it is unpinned as often as it was
+	/// pinned, without a guard having to be held. Neither eviction nor
+	/// [`Self::shrink_to`] remove pinned pages; a page that isn't cached yet
+	/// stays once it is loaded. Scrapping a page still removes it.
+	fn pin(&self, page_id: PageId);
+	fn unpin(&self, page_id: PageId);
+	fn num_cached_pages(&self) -> usize;
+	fn num_dirty_pages(&self) -> usize;
+	fn capacity(&self) -> usize;
+	fn shrink_to(&self, target_pages: usize) -> usize;
+	fn release_clean(&self) -> usize;
+	fn set_background_io_rate(&self, rate: Option<usize>);
+	/// The memory allocated for cached pages, in bytes.
+	fn buffer_memory(&self) -> usize;
+	/// The approximate memory used to keep track of the cached pages, in
+	/// bytes.
+	fn index_memory(&self) -> usize;
+	fn stats(&self) -> CacheStats;
+	/// The pool that runs the blocking work of the cache and of the database
+	/// it belongs to.
+	fn blocking_pool(&self) -> &Arc<BlockingPool>;
+	fn downgrade_guard<'a>(&'a self, guard: Self::WriteGuard) -> Self::ReadGuard<'a>;
+}
+
+impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCache<PS> {
+	/// Runs a task on the [`BlockingPool`] of the cache, whose panics are
+	/// reported like those of the cache's own tasks.
+	pub fn spawn_blocking<F>(&self, task: &'static str, future: F)
+	where
+		F: Future<Output = ()> + Send + 'static,
+	{
+		self.tasks.spawn(self.blocking_pool.get(), task, future);
+	}
+
+	/// Writes the IDs of the currently cached pages to the file at `path`, so
+	/// that they can be read back into the cache with [`Self::saved_state`]
+	/// after a restart. The previous file is kept if this fails.
+	pub fn save_state(&self, path: &Path) -> Result<(), StorageError> {
+		let bytes: Vec<u8> = self
+			.cached_pages()
+			.into_iter()
+			.flat_map(|page_id| {
+				let repr = CachedPageRepr {
+					segment_num: page_id.segment_num.into(),
+					page_num: page_id.page_num.get().into(),
+					padding: 0.into(),
+				};
+				repr.as_bytes().to_vec()
+			})
+			.collect();
+		let temp_path = path.with_extension("tmp");
+		fs::write(&temp_path, bytes).map_err(FileError::from)?;
+		fs::rename(temp_path, path).map_err(FileError::from)?;
+		Ok(())
+	}
+
+	/// Reads the page IDs that were written by [`Self::save_state`], in
+	/// ascending order. If there is no file at `path`, no pages are returned.
+	pub fn saved_state(path: &Path) -> Result<Vec<PageId>, StorageError> {
+		let bytes = match fs::read(path) {
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			result => result.map_err(FileError::from)?,
+		};
+		let chunks = bytes.chunks_exact(mem::size_of::<CachedPageRepr>());
+		if !chunks.remainder().is_empty() {
+			return Err(FileError::Corrupted(
+				"The saved cache state has an unexpected size".to_string(),
+			)
+			.into());
+		}
+		chunks
+			.map(|chunk| {
+				let repr = CachedPageRepr::read_from(chunk).unwrap();
+				let Some(page_num) = NonZeroU16::new(repr.page_num.get()) else {
+					return Err(FileError::Corrupted(
+						"The saved cache state contains page number zero".to_string(),
+					)
+					.into());
+				};
+				Ok(PageId::new(repr.segment_num.get(), page_num))
+			})
+			.collect()
+	}
+}
+
+/// A page ID in the file written by [`PageCache::save_state`].
+#[derive(Debug, FromZeroes, FromBytes, AsBytes)]
+#[repr(C)]
+struct CachedPageRepr {
+	segment_num: U32,
+	page_num: U16,
+	padding: U16,
+}
+
+impl<PS: PhysicalStorageApi> BackgroundTasks for PageCache<PS> {
+	fn task_panic(&self) -> Option<TaskPanic> {
+		self.tasks.panic()
+	}
 }
 
 impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCacheApi for PageCache<PS> {
 	type ReadGuard<'a> = PageReadGuard<'a>;
-	type WriteGuard<'a> = PageWriteGuard<'a>;
+	type WriteGuard = PageWriteGuard;
 
 	fn has_page(&self, page_id: PageId) -> bool {
-		let indices = self.indices.read();
-		indices.contains_key(&page_id)
+		self.indices.contains_key(&page_id)
 	}
 
 	fn load(&self, page_id: PageId) -> Option<PageReadGuard<'_>> {
@@ -571,76 +1281,293 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCacheApi for PageCache<
 		Some(Self::load_direct(&self.locks, &self.buf, index))
 	}
 
-	fn load_mut(&self, page_id: PageId) -> Option<Self::WriteGuard<'_>> {
+	/// Like [`Self::load`], but returns `None` instead of waiting if the page
+	/// is locked exclusively.
+	fn try_load(&self, page_id: PageId) -> Option<PageReadGuard<'_>> {
 		let index = self.get_load_index(page_id)?;
+		let lock = &self.locks[index];
+		if !lock.try_lock_shared() {
+			return None;
+		}
+		// Safety: The safety of the reference is guaranteed by acquiring the shared
+		// lock.
+		let page =
+			unsafe { self.buf.get_page(index) }.expect("Tried to index page buffer out of bounds!");
+		Some(PageReadGuard {
+			lock,
+			page,
+			_marker: PhantomData,
+		})
+	}
+
+	fn load_until(&self, page_id: PageId, until: Instant) -> TimedLoad<PageReadGuard<'_>> {
+		let Some(index) = self.get_load_index(page_id) else {
+			return TimedLoad::Missing;
+		};
+		let lock = &self.locks[index];
+		if !lock.lock_shared_until(until) {
+			return TimedLoad::Busy;
+		}
+		// Safety: The safety of the reference is guaranteed by acquiring the shared
+		// lock.
+		let page =
+			unsafe { self.buf.get_page(index) }.expect("Tried to index page buffer out of bounds!");
+		TimedLoad::Loaded(PageReadGuard {
+			lock,
+			page,
+			_marker: PhantomData,
+		})
+	}
+
+	fn read_optimistic(&self, page_id: PageId, buf: &mut [u8]) -> bool {
+		for _ in 0..MAX_OPTIMISTIC_ATTEMPTS {
+			let Some(index) = self.indices.get(&page_id) else {
+				return false;
+			};
+			let read = self.locks[index].read_optimistic(|| {
+				// Safety: The index comes from the indices, so it is in bounds, and the
+				// page is copied in `read_optimistic`.
+				let copied = unsafe { self.buf.copy_body_optimistic(index, buf) };
+				// The frame may have been reused for another page before the read
+				// started. Frames are only assigned to a page while they are locked
+				// exclusively, so checking this during the read is enough.
+				copied && self.indices.get(&page_id) == Some(index)
+			});
+			match read {
+				Some(true) => {
+					self.counters.hits.fetch_add(1, Ordering::Relaxed);
+					self.replacer.read().access(&page_id);
+					return true;
+				}
+				Some(false) => return false,
+				None => {
+					self.counters
+						.optimistic_conflicts
+						.fetch_add(1, Ordering::Relaxed);
+					std::hint::spin_loop();
+				}
+			}
+		}
+		false
+	}
+
+	fn load_mut(&self, page_id: PageId) -> Option<Self::WriteGuard> {
+		let index = self.get_load_index(page_id)?;
+		self.track_dirty(page_id);
 		Some(Self::load_mut_direct(&self.locks, &self.buf, index))
 	}
 
-	fn store(&self, page_id: PageId) -> PageWriteGuard<'_> {
-		let mut dirty_list = self.dirty_list.lock();
-		dirty_list.push(page_id);
-		if dirty_list.len() >= self.max_num_dirty {
-			self.thread_pool.spawn_ok(Self::single_flush_task(
-				Arc::clone(&self.physical_storage),
-				Arc::clone(&self.dirty_list),
-				Arc::clone(&self.indices),
-				Arc::clone(&self.locks),
-				Arc::clone(&self.buf),
-			));
+	fn load_mut_until(&self, page_id: PageId, until: Instant) -> TimedLoad<PageWriteGuard> {
+		let Some(index) = self.get_load_index(page_id) else {
+			return TimedLoad::Missing;
+		};
+		self.track_dirty(page_id);
+		if !self.locks[index].lock_exclusive_until(until) {
+			return TimedLoad::Busy;
 		}
-		mem::drop(dirty_list);
+		TimedLoad::Loaded(PageWriteGuard::new(index, &self.locks, &self.buf))
+	}
 
-		let index = self.get_store_index(page_id);
-		Self::load_mut_direct(&self.locks, &self.buf, index)
+	fn store(&self, page_id: PageId) -> Result<PageWriteGuard, StorageError> {
+		self.counters.misses.fetch_add(1, Ordering::Relaxed);
+		self.track_dirty(page_id);
+		self.store_guard(page_id)
 	}
 
 	fn flush(&self) {
 		let physical_storage = Arc::clone(&self.physical_storage);
-		let dirty_list = Arc::clone(&self.dirty_list);
+		let dirty_pages = Arc::clone(&self.dirty_pages);
 		let indices = Arc::clone(&self.indices);
 		let locks = Arc::clone(&self.locks);
 		let buf = Arc::clone(&self.buf);
-		self.thread_pool.spawn_ok(Self::single_flush_task(
-			physical_storage,
-			dirty_list,
-			indices,
-			locks,
-			buf,
-		))
+		self.tasks.spawn(
+			&self.thread_pool,
+			"flush",
+			Self::single_flush_task(
+				0,
+				Duration::ZERO,
+				physical_storage,
+				dirty_pages,
+				indices,
+				locks,
+				buf,
+				None,
+			),
+		)
 	}
 
 	fn flush_sync(&self) -> Result<(), StorageError> {
-		Self::flush(
-			&self.physical_storage,
-			&self.dirty_list,
-			&self.indices,
-			&self.locks,
-			&self.buf,
-		)
+		self.flush_filtered(|_| true)
+	}
+
+	fn flush_segment(&self, segment_num: u32) -> Result<(), StorageError> {
+		self.flush_filtered(|page_id| page_id.segment_num == segment_num)
+	}
+
+	fn flush_pages(&self, page_ids: &[PageId]) -> Result<(), StorageError> {
+		self.flush_filtered(|page_id| page_ids.contains(&page_id))
+	}
+
+	fn dirty_pages(&self) -> Vec<PageId> {
+		let mut candidates: Vec<PageId> = self.dirty_pages.keys();
+		candidates.sort_unstable();
+		candidates
+			.into_iter()
+			.filter(|page_id| {
+				let Some(index) = self.indices.get(page_id) else {
+					return false;
+				};
+				Self::load_direct(&self.locks, &self.buf, index)
+					.header()
+					.dirty()
+			})
+			.collect()
+	}
+
+	fn cached_pages(&self) -> Vec<PageId> {
+		let mut pages: Vec<PageId> = self.indices.keys();
+		pages.sort_unstable();
+		pages
+	}
+
+	fn dirty_since(&self, since: WalIndex) -> Vec<PageId> {
+		let mut indices: Vec<(PageId, usize)> = self.indices.entries();
+		indices.sort_unstable();
+		indices
+			.into_iter()
+			.filter(|(_, index)| {
+				let lock = &self.locks[*index];
+				if !lock.try_lock_shared() {
+					return true;
+				}
+				// Safety: The safety of the reference is guaranteed by acquiring the shared
+				// lock.
+				let page = unsafe { self.buf.get_page(*index) }
+					.expect("Tried to index page buffer out of bounds!");
+				let header = BufferedPageHeader::ref_from(&page[0..HEADER_SIZE]).unwrap();
+				let modified = header.dirty() && header.wal_index() >= since;
+				// Safety: The lock was acquired above.
+				unsafe { lock.unlock_shared() };
+				modified
+			})
+			.map(|(page_id, _)| page_id)
+			.collect()
 	}
 
 	fn scrap(&self, page_id: PageId) {
-		let mut indices = self.indices.write();
-		let Some(index) = indices.remove(&page_id) else {
+		let Some(index) = self.indices.remove(&page_id) else {
 			return;
 		};
-		mem::drop(indices);
+		self.replacer.write().remove(&page_id);
 
 		self.has_scrap.store(true, Ordering::Relaxed);
 		self.scrap.lock().push(index);
 	}
 
-	fn downgrade_guard<'a>(&'a self, guard: PageWriteGuard<'a>) -> PageReadGuard<'a> {
-		let lock = guard.lock;
+	fn pin(&self, page_id: PageId) {
+		self.pins.update(&page_id, |pins| {
+			*pins.entry(page_id).or_default() += 1;
+		});
+	}
+
+	fn unpin(&self, page_id: PageId) {
+		self.pins.update(&page_id, |pins| {
+			let Entry::Occupied(mut entry) = pins.entry(page_id) else {
+				debug_assert!(false, "Unpinned page {page_id} that wasn't pinned!");
+				return;
+			};
+			*entry.get_mut() -= 1;
+			if *entry.get() == 0 {
+				entry.remove();
+			}
+		});
+	}
+
+	fn num_cached_pages(&self) -> usize {
+		self.indices.len()
+	}
+
+	/// The number of pages that were modified since they were last flushed.
+	/// This may include some pages that were flushed in the meantime.
+	fn num_dirty_pages(&self) -> usize {
+		self.dirty_pages.len()
+	}
+
+	fn capacity(&self) -> usize {
+		self.buf.pages.len()
+	}
+
+	fn buffer_memory(&self) -> usize {
+		self.buf.num_allocated() * BUFFERED_PAGE_SIZE
+			+ self.buf.pages.len() * mem::size_of::<AtomicPtr<u8>>()
+			+ self.locks.len() * mem::size_of::<FrameLock>()
+	}
+
+	fn index_memory(&self) -> usize {
+		hash_table_size::<(PageId, usize)>(self.indices.capacity())
+			+ hash_table_size::<(PageId, usize)>(self.pins.capacity())
+			+ hash_table_size::<(PageId, DirtyPage)>(self.dirty_pages.capacity())
+			+ self.replacer.read().heap_size()
+	}
+
+	/// Evicts clean pages that are not currently in use until at most
+	/// `target_pages` pages remain in the cache, and frees their memory.
+	///
+	/// Returns the number of evicted pages.
+	fn shrink_to(&self, target_pages: usize) -> usize {
+		let cached: Vec<(PageId, usize)> = self.indices.entries();
+
+		let mut num_released = 0;
+		for (page_id, index) in cached {
+			if self.num_cached_pages() <= target_pages {
+				break;
+			}
+			if self.release_page(page_id, index) {
+				num_released += 1;
+			}
+		}
+		self.counters
+			.evictions
+			.fetch_add(num_released as u64, Ordering::Relaxed);
+		event!(DEBUG, num_released, "Released cached pages");
+		num_released
+	}
+
+	fn release_clean(&self) -> usize {
+		self.shrink_to(0)
+	}
+
+	fn set_background_io_rate(&self, rate: Option<usize>) {
+		self.io_limiter.set_rate(rate);
+	}
+
+	fn stats(&self) -> CacheStats {
+		CacheStats {
+			hits: self.counters.hits.load(Ordering::Relaxed),
+			misses: self.counters.misses.load(Ordering::Relaxed),
+			evictions: self.counters.evictions.load(Ordering::Relaxed),
+			dirty_pages: self.num_dirty_pages(),
+			peak_dirty_pages: self.counters.peak_dirty_pages.load(Ordering::Relaxed),
+			optimistic_conflicts: self.counters.optimistic_conflicts.load(Ordering::Relaxed),
+			eviction: self.replacer.read().stats(),
+		}
+	}
+
+	fn blocking_pool(&self) -> &Arc<BlockingPool> {
+		&self.blocking_pool
+	}
+
+	fn downgrade_guard<'a>(&'a self, guard: PageWriteGuard) -> PageReadGuard<'a> {
+		let index = guard.into_index();
+		let lock = &self.locks[index];
 		// Safety: the existance of the PageWriteGuard guarantees that the lock is owned
 		// in the current context
 		unsafe { lock.downgrade() };
 
 		// Safety: we have the shared lock for this page
-		let page = unsafe { self.buf.get_page(guard.index) }
+		let page = unsafe { self.buf.get_page(index) }
 			.expect("Got out of bounds buffer index while upgrading guard");
 
-		mem::forget(guard);
 		PageReadGuard {
 			page,
 			lock,
@@ -651,6 +1578,9 @@ impl<PS: PhysicalStorageApi + Send + Sync + 'static> PageCacheApi for PageCache<
 
 #[cfg(test)]
 mod tests {
+	use std::thread;
+
+	use mockall::Sequence;
 	use pretty_assertions::assert_buf_eq;
 
 	use crate::{
@@ -679,6 +1609,7 @@ mod tests {
 		let expected_page = [69; PAGE_BODY_SIZE];
 		cache
 			.store(page_id!(69, 420))
+			.unwrap()
 			.write(0, &expected_page, wal_index!(1, 2));
 
 		let mut received_page = [0; PAGE_BODY_SIZE];
@@ -710,6 +1641,86 @@ mod tests {
 		assert!(guard.is_none())
 	}
 
+	#[test]
+	fn read_optimistic_only_while_not_written() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				..Default::default()
+			},
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		let mut expected_page = [0; PAGE_BODY_SIZE];
+		expected_page[0..3].copy_from_slice(&[1, 2, 3]);
+		cache
+			.store(page_id!(69, 420))
+			.unwrap()
+			.write(0, &expected_page, wal_index!(1, 2));
+
+		// when
+		let mut received_page = [0; PAGE_BODY_SIZE];
+		let read = cache.read_optimistic(page_id!(69, 420), &mut received_page);
+		let guard = cache.load_mut(page_id!(69, 420)).unwrap();
+		let read_while_locked = cache.read_optimistic(page_id!(69, 420), &mut [0; PAGE_BODY_SIZE]);
+		mem::drop(guard);
+		let read_uncached = cache.read_optimistic(page_id!(1, 1), &mut [0; PAGE_BODY_SIZE]);
+
+		// then
+		assert!(read);
+		assert_buf_eq!(expected_page, received_page);
+		assert!(!read_while_locked);
+		assert_eq!(
+			cache.stats().optimistic_conflicts,
+			MAX_OPTIMISTIC_ATTEMPTS as u64
+		);
+		assert!(!read_uncached);
+	}
+
+	#[test]
+	fn read_optimistic_never_sees_partial_writes() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 2 * MIB,
+				..Default::default()
+			},
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache
+			.store(page_id!(69, 420))
+			.unwrap()
+			.write(0, &[0; PAGE_BODY_SIZE], wal_index!(1, 2));
+
+		// when
+		let reads = thread::scope(|scope| {
+			scope.spawn(|| {
+				for value in 1..=100 {
+					cache.load_mut(page_id!(69, 420)).unwrap().write(
+						0,
+						&[value; PAGE_BODY_SIZE],
+						wal_index!(1, 2),
+					);
+				}
+			});
+			let mut reads = Vec::new();
+			let mut buf = vec![0; PAGE_BODY_SIZE];
+			for _ in 0..100 {
+				if cache.read_optimistic(page_id!(69, 420), &mut buf) {
+					reads.push(buf.clone());
+				}
+			}
+			reads
+		});
+
+		// then
+		for read in reads {
+			assert!(read.iter().all(|byte| *byte == read[0]));
+		}
+	}
+
 	#[test]
 	fn evict_correct_page() {
 		// given
@@ -723,17 +1734,17 @@ mod tests {
 		);
 
 		// when
-		cache.store(page_id!(1, 1)); // add 1, 1 to recent
-		cache.store(page_id!(2, 2)); // add 2, 2 to recent
-		cache.store(page_id!(3, 3)); // add 3, 3 to recent
-		cache.store(page_id!(4, 4)); // add 4, 4 to recent
+		cache.store(page_id!(1, 1)).unwrap(); // add 1, 1 to recent
+		cache.store(page_id!(2, 2)).unwrap(); // add 2, 2 to recent
+		cache.store(page_id!(3, 3)).unwrap(); // add 3, 3 to recent
+		cache.store(page_id!(4, 4)).unwrap(); // add 4, 4 to recent
 		cache.load(page_id!(1, 1)); // 1, 1 was referenced in recent
 		cache.load(page_id!(2, 2)); // 2, 2 was referenced in recent
 		cache.load(page_id!(1, 1)); // 1, 1 is promoted to frequent
 
 		// recent is large, therefore 3, 3 is evicted as it is the first
 		// non-referenced item in frequent
-		cache.store(page_id!(5, 5));
+		cache.store(page_id!(5, 5)).unwrap();
 
 		// then
 		assert!(cache.load(page_id!(1, 1)).is_some());
@@ -743,6 +1754,49 @@ mod tests {
 		assert!(cache.load(page_id!(5, 5)).is_some());
 	}
 
+	#[test]
+	fn write_back_evicted_dirty_page() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		physical
+			.expect_write()
+			.once()
+			.withf(|write_op| {
+				write_op.page_id == page_id!(3, 3)
+					&& write_op.wal_index == wal_index!(1, 2)
+					&& write_op.buf[0..3] == [1, 2, 3]
+			})
+			.returning(|_| Ok(()));
+
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::new(physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache.store(page_id!(1, 1)).unwrap();
+		cache.store(page_id!(2, 2)).unwrap();
+		cache
+			.store(page_id!(3, 3))
+			.unwrap()
+			.write(0, &[1, 2, 3], wal_index!(1, 2));
+		cache.store(page_id!(4, 4)).unwrap();
+		cache.load(page_id!(1, 1));
+		cache.load(page_id!(2, 2));
+		cache.load(page_id!(1, 1));
+
+		// when
+		let guard = cache.store(page_id!(5, 5)).unwrap();
+
+		// then
+		assert!(!guard.header().dirty());
+		mem::drop(guard);
+		assert!(cache.load(page_id!(3, 3)).is_none());
+	}
+
 	#[test]
 	fn doesnt_evict_locked_page() {
 		// given
@@ -756,17 +1810,17 @@ mod tests {
 		);
 
 		// when
-		cache.store(page_id!(1, 1)); // add 1, 1 to recent
-		cache.store(page_id!(2, 2)); // add 2, 2 to recent
-		let guard = cache.store(page_id!(3, 3)); // add 3, 3 to recent
-		cache.store(page_id!(4, 4)); // add 4, 4 to recent
+		cache.store(page_id!(1, 1)).unwrap(); // add 1, 1 to recent
+		cache.store(page_id!(2, 2)).unwrap(); // add 2, 2 to recent
+		let guard = cache.store(page_id!(3, 3)).unwrap(); // add 3, 3 to recent
+		cache.store(page_id!(4, 4)).unwrap(); // add 4, 4 to recent
 		cache.load(page_id!(1, 1)); // 1, 1 was referenced in recent
 		cache.load(page_id!(2, 2)); // 2, 2 was referenced in recent
 		cache.load(page_id!(1, 1)); // 1, 1 is promoted to frequent
 
 		// recent is large, therefore 3, 3 would be evicted, but it is locked, so 4, 4
 		// is evicted instead
-		cache.store(page_id!(5, 5));
+		cache.store(page_id!(5, 5)).unwrap();
 
 		mem::drop(guard);
 
@@ -777,4 +1831,334 @@ mod tests {
 		assert!(cache.load(page_id!(4, 4)).is_none());
 		assert!(cache.load(page_id!(5, 5)).is_some());
 	}
+
+	#[test]
+	fn keep_pinned_pages() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache.pin(page_id!(3, 3));
+		cache.pin(page_id!(3, 3));
+		cache.pin(page_id!(4, 4));
+		cache.store(page_id!(1, 1)).unwrap();
+		cache.store(page_id!(2, 2)).unwrap();
+		cache.store(page_id!(3, 3)).unwrap();
+		cache.store(page_id!(4, 4)).unwrap();
+
+		// when
+		cache.store(page_id!(5, 5)).unwrap();
+		cache.unpin(page_id!(4, 4));
+		cache.unpin(page_id!(3, 3));
+		let num_released = cache.release_clean();
+
+		// then
+		assert_eq!(num_released, 3);
+		assert!(cache.load(page_id!(3, 3)).is_some());
+		cache.unpin(page_id!(3, 3));
+		assert_eq!(cache.release_clean(), 1);
+		assert!(cache.load(page_id!(3, 3)).is_none());
+	}
+
+	#[test]
+	fn release_clean_pages() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache.store(page_id!(1, 1)).unwrap();
+		cache.store(page_id!(2, 2)).unwrap();
+		cache
+			.store(page_id!(3, 3))
+			.unwrap()
+			.write(0, &[1, 2, 3], wal_index!(1, 2));
+		let guard = cache.store(page_id!(4, 4)).unwrap();
+
+		// when
+		let num_released = cache.release_clean();
+		mem::drop(guard);
+
+		// then
+		assert_eq!(num_released, 2);
+		assert_eq!(cache.buf.num_allocated(), 2);
+		assert!(cache.load(page_id!(1, 1)).is_none());
+		assert!(cache.load(page_id!(2, 2)).is_none());
+		assert!(cache.load(page_id!(3, 3)).is_some());
+		assert!(cache.load(page_id!(4, 4)).is_some());
+
+		cache.store(page_id!(5, 5)).unwrap();
+		assert!(cache.load(page_id!(5, 5)).is_some());
+	}
+
+	#[test]
+	fn save_and_restore_state() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("cache");
+		let cache = PageCache::new(
+			&PageCacheConfig::default(),
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache.store(page_id!(2, 5)).unwrap();
+		cache.store(page_id!(1, 7)).unwrap();
+		cache.store(page_id!(1, 3)).unwrap();
+
+		// when
+		let missing = PageCache::<MockPhysicalStorageApi>::saved_state(&path).unwrap();
+		cache.save_state(&path).unwrap();
+		let restored = PageCache::<MockPhysicalStorageApi>::saved_state(&path).unwrap();
+
+		// then
+		assert_eq!(missing, vec![]);
+		assert_eq!(
+			restored,
+			vec![page_id!(1, 3), page_id!(1, 7), page_id!(2, 5)]
+		);
+	}
+
+	#[test]
+	fn reject_corrupted_state() {
+		// given
+		let tempdir = tempfile::tempdir().unwrap();
+		let path = tempdir.path().join("cache");
+		fs::write(&path, [1, 0, 0, 0, 3, 0, 0]).unwrap();
+
+		// when
+		let result = PageCache::<MockPhysicalStorageApi>::saved_state(&path);
+
+		// then
+		assert!(matches!(
+			result,
+			Err(StorageError::File(FileError::Corrupted(..)))
+		));
+	}
+
+	#[test]
+	fn list_pages_in_ascending_order() {
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * BUFFERED_PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::new(MockPhysicalStorageApi::new()),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		let page_ids = [
+			page_id!(2, 1),
+			page_id!(1, 3),
+			page_id!(3, 2),
+			page_id!(1, 1),
+			page_id!(2, 5),
+		];
+		for (i, page_id) in page_ids.into_iter().enumerate() {
+			let mut guard = cache.store(page_id).unwrap();
+			if i % 2 == 0 {
+				guard.write(0, &[1], wal_index!(1, i as u64 + 1));
+			}
+		}
+
+		// when
+		let cached = cache.cached_pages();
+		let dirty = cache.dirty_pages();
+		let dirty_since = cache.dirty_since(wal_index!(1, 2));
+
+		// then
+		assert_eq!(
+			cached,
+			[
+				page_id!(1, 1),
+				page_id!(1, 3),
+				page_id!(2, 1),
+				page_id!(2, 5),
+				page_id!(3, 2)
+			]
+		);
+		assert_eq!(dirty, [page_id!(2, 1), page_id!(2, 5), page_id!(3, 2)]);
+		assert_eq!(dirty_since, [page_id!(2, 5), page_id!(3, 2)]);
+	}
+
+	#[test]
+	fn flush_single_segment() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		physical
+			.expect_write()
+			.once()
+			.withf(|write_op| {
+				write_op.page_id == page_id!(1, 2)
+					&& write_op.wal_index == wal_index!(3, 4)
+					&& write_op.buf[0..2] == [1, 2]
+			})
+			.returning(|_| Ok(()));
+
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 4 * BUFFERED_PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::new(physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache
+			.store(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2], wal_index!(3, 4));
+		cache
+			.store(page_id!(2, 2))
+			.unwrap()
+			.write(0, &[3, 4], wal_index!(3, 5));
+
+		// when
+		cache.flush_segment(1).unwrap();
+
+		// then
+		assert_eq!(cache.dirty_pages(), [page_id!(2, 2)]);
+	}
+
+	#[test]
+	fn flush_consecutive_pages_at_once() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		let mut seq = Sequence::new();
+		physical
+			.expect_write_run()
+			.once()
+			.in_sequence(&mut seq)
+			.withf(|op| {
+				op.first_page == page_id!(1, 2)
+					&& op.wal_indices == [wal_index!(1, 2), wal_index!(1, 3), wal_index!(1, 4)]
+					&& op.bufs.len() == 3 * PAGE_BODY_SIZE
+					&& op.bufs[PAGE_BODY_SIZE] == 3
+			})
+			.returning(|_| Ok(()));
+		for page_id in [page_id!(1, 6), page_id!(2, 1)] {
+			physical
+				.expect_write()
+				.once()
+				.in_sequence(&mut seq)
+				.withf(move |op| op.page_id == page_id)
+				.returning(|_| Ok(()));
+		}
+
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * BUFFERED_PAGE_SIZE,
+				max_dirty_pages: 1.0,
+				..Default::default()
+			},
+			Arc::new(physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		for page_id in [
+			page_id!(2, 1),
+			page_id!(1, 4),
+			page_id!(1, 6),
+			page_id!(1, 2),
+			page_id!(1, 3),
+		] {
+			cache.store(page_id).unwrap().write(
+				0,
+				&[page_id.page_num.get() as u8],
+				wal_index!(1, page_id.page_num.get() as u64),
+			);
+		}
+
+		// when
+		cache.flush_sync().unwrap();
+
+		// then
+		assert_eq!(cache.dirty_pages(), []);
+	}
+
+	#[test]
+	fn flush_expired_pages_in_background() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		physical
+			.expect_write()
+			.once()
+			.withf(|write_op| write_op.page_id == page_id!(1, 2))
+			.returning(|_| Ok(()));
+
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * BUFFERED_PAGE_SIZE,
+				max_dirty_pages: 1.0,
+				max_dirty_age: Duration::from_millis(20),
+				flush_period: Duration::from_millis(10),
+				..Default::default()
+			},
+			Arc::new(physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+
+		// when
+		cache
+			.store(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2], wal_index!(3, 4));
+		thread::sleep(Duration::from_millis(100));
+
+		// then
+		assert_eq!(cache.dirty_pages(), []);
+	}
+
+	#[test]
+	fn coalesce_writes_to_hot_pages() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		physical
+			.expect_write()
+			.once()
+			.withf(|write_op| write_op.page_id == page_id!(1, 2))
+			.returning(|_| Ok(()));
+
+		// given
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * BUFFERED_PAGE_SIZE,
+				max_dirty_pages: 1.0,
+				max_dirty_age: Duration::from_millis(20),
+				coalesce_window: Duration::from_millis(50),
+				flush_period: Duration::from_millis(10),
+				..Default::default()
+			},
+			Arc::new(physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		cache
+			.store(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2], wal_index!(3, 4));
+
+		// when
+		for i in 0..10 {
+			thread::sleep(Duration::from_millis(10));
+			cache
+				.load_mut(page_id!(1, 2))
+				.unwrap()
+				.write(0, &[i, 2], wal_index!(3, 5));
+		}
+		let dirty_while_hot = cache.dirty_pages();
+		thread::sleep(Duration::from_millis(200));
+
+		// then
+		assert_eq!(dirty_while_hot, [page_id!(1, 2)]);
+		assert_eq!(cache.dirty_pages(), []);
+	}
 }
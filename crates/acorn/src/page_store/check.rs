@@ -0,0 +1,31 @@
+use super::PageId;
+
+/// A problem with the stored data that was found by an integrity check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckProblem {
+	/// The segment file could not be opened, e.g. because its header is
+	/// invalid, or listing its pages failed.
+	UnreadableSegment { segment_num: u32, reason: String },
+
+	/// The stored checksum of the page does not match its content, usually
+	/// because a write to it was torn.
+	ChecksumMismatch(PageId),
+
+	/// The page could not be read for a reason other than its checksum.
+	UnreadablePage { page_id: PageId, reason: String },
+}
+
+/// The result of checking the integrity of all segment files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckReport {
+	pub num_segments: usize,
+	pub num_pages: usize,
+	/// The problems that were found, in ascending order of their segments.
+	pub problems: Vec<CheckProblem>,
+}
+
+impl CheckReport {
+	pub fn is_ok(&self) -> bool {
+		self.problems.is_empty()
+	}
+}
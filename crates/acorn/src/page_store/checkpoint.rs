@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::consts::{
+	DEFAULT_CHECKPOINT_DIRTY_RATIO, DEFAULT_CHECKPOINT_PERIOD, DEFAULT_CHECKPOINT_POLL_INTERVAL,
+	DEFAULT_CHECKPOINT_WAL_SIZE,
+};
+
+/// When checkpoints are taken automatically. Each trigger can be disabled by
+/// setting it to `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CheckpointPolicy {
+	/// Checkpoint once the WAL has grown to this many bytes.
+	pub wal_size: Option<usize>,
+	/// Checkpoint once this fraction of the page cache is dirty.
+	pub dirty_ratio: Option<f32>,
+	/// Checkpoint once this much time has passed since the last checkpoint.
+	pub period: Option<Duration>,
+	/// How often the triggers are checked.
+	pub poll_interval: Duration,
+}
+
+impl Default for CheckpointPolicy {
+	fn default() -> Self {
+		Self {
+			wal_size: Some(DEFAULT_CHECKPOINT_WAL_SIZE),
+			dirty_ratio: Some(DEFAULT_CHECKPOINT_DIRTY_RATIO),
+			period: Some(DEFAULT_CHECKPOINT_PERIOD),
+			poll_interval: DEFAULT_CHECKPOINT_POLL_INTERVAL,
+		}
+	}
+}
+
+/// The reason a checkpoint was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointTrigger {
+	Manual,
+	WalSize,
+	DirtyRatio,
+	Period,
+	/// Taken by maintenance while the database was idle.
+	Idle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointStats {
+	/// The number of checkpoints since the database was opened.
+	pub num_checkpoints: u64,
+	pub last_trigger: Option<CheckpointTrigger>,
+	pub last_duration: Option<Duration>,
+	/// The time since the last checkpoint, or since the database was opened.
+	pub since_last: Duration,
+	/// The current size of the WAL in bytes.
+	pub wal_size: usize,
+	/// The current fraction of the page cache that is dirty.
+	pub dirty_ratio: f32,
+}
+
+#[derive(Debug)]
+struct TrackerState {
+	last_checkpoint: Instant,
+	num_checkpoints: u64,
+	last_trigger: Option<CheckpointTrigger>,
+	last_duration: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub(super) struct CheckpointTracker {
+	state: Mutex<TrackerState>,
+}
+
+impl Default for CheckpointTracker {
+	fn default() -> Self {
+		Self {
+			state: Mutex::new(TrackerState {
+				last_checkpoint: Instant::now(),
+				num_checkpoints: 0,
+				last_trigger: None,
+				last_duration: None,
+			}),
+		}
+	}
+}
+
+impl CheckpointTracker {
+	/// Returns the first trigger of the policy that currently applies.
+	pub fn trigger(
+		&self,
+		policy: &CheckpointPolicy,
+		wal_size: usize,
+		dirty_ratio: f32,
+	) -> Option<CheckpointTrigger> {
+		if policy.wal_size.is_some_and(|limit| wal_size >= limit) {
+			return Some(CheckpointTrigger::WalSize);
+		}
+		if policy.dirty_ratio.is_some_and(|limit| dirty_ratio >= limit) {
+			return Some(CheckpointTrigger::DirtyRatio);
+		}
+		let since_last = self.state.lock().last_checkpoint.elapsed();
+		if policy.period.is_some_and(|period| since_last >= period) {
+			return Some(CheckpointTrigger::Period);
+		}
+		None
+	}
+
+	pub fn record(&self, trigger: CheckpointTrigger, started: Instant) {
+		let mut state = self.state.lock();
+		state.last_checkpoint = Instant::now();
+		state.num_checkpoints += 1;
+		state.last_trigger = Some(trigger);
+		state.last_duration = Some(started.elapsed());
+	}
+
+	pub fn stats(&self, wal_size: usize, dirty_ratio: f32) -> CheckpointStats {
+		let state = self.state.lock();
+		CheckpointStats {
+			num_checkpoints: state.num_checkpoints,
+			last_trigger: state.last_trigger,
+			last_duration: state.last_duration,
+			since_last: state.last_checkpoint.elapsed(),
+			wal_size,
+			dirty_ratio,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn trigger_checkpoints() {
+		// given
+		let tracker = CheckpointTracker::default();
+		let policy = CheckpointPolicy {
+			wal_size: Some(100),
+			dirty_ratio: Some(0.5),
+			period: None,
+			..Default::default()
+		};
+
+		// expect
+		assert_eq!(tracker.trigger(&policy, 10, 0.1), None);
+		assert_eq!(
+			tracker.trigger(&policy, 100, 0.1),
+			Some(CheckpointTrigger::WalSize)
+		);
+		assert_eq!(
+			tracker.trigger(&policy, 10, 0.6),
+			Some(CheckpointTrigger::DirtyRatio)
+		);
+		assert_eq!(
+			tracker.trigger(
+				&CheckpointPolicy {
+					period: Some(Duration::ZERO),
+					..policy
+				},
+				10,
+				0.1
+			),
+			Some(CheckpointTrigger::Period)
+		);
+	}
+}
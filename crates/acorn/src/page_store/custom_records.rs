@@ -0,0 +1,198 @@
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+
+use super::StorageError;
+
+/// The error returned by a [`WalRecordHandler`].
+pub type WalRecordHandlerError = Box<dyn Error + Send + Sync>;
+
+/// A record of a type that is not defined by the database itself, logged
+/// with [`Transaction::log_record`](crate::Transaction::log_record).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomRecord {
+	pub transaction_id: u64,
+	/// Identifies the handler of the record, see
+	/// [`DatabaseBuilder::wal_record_handler`](crate::DatabaseBuilder::wal_record_handler).
+	pub record_type: u16,
+	/// The version of the format of `data`, which lets handlers read records
+	/// that were logged by older versions of the application.
+	pub version: u16,
+	pub data: Vec<u8>,
+}
+
+/// Makes the effects of custom WAL records crash-safe, the way the database
+/// does for the writes to its pages.
+///
+/// When the database is recovered after a crash, the records that were
+/// logged since the last checkpoint are redone in the order in which they
+/// were logged. Afterwards, the records of transactions that didn't commit
+/// are undone, latest first. A crash during recovery can cause records to be
+/// redone or undone more than once, so both have to be idempotent. The
+/// effects of records that were logged before the last checkpoint are not
+/// redone, so they must be durable by the time the next checkpoint starts.
+pub trait WalRecordHandler: Send + Sync {
+	/// Reapplies the effect of a record.
+	fn redo(&self, record: &CustomRecord) -> Result<(), WalRecordHandlerError>;
+
+	/// Reverts the effect of a record of a transaction that didn't commit.
+	/// The effect may never have been applied.
+	fn undo(&self, record: &CustomRecord) -> Result<(), WalRecordHandlerError>;
+}
+
+/// The handlers of custom WAL records, by record type.
+#[derive(Clone, Default)]
+pub(crate) struct WalRecordHandlers(HashMap<u16, Arc<dyn WalRecordHandler>>);
+
+impl WalRecordHandlers {
+	pub fn register(&mut self, record_type: u16, handler: Arc<dyn WalRecordHandler>) {
+		self.0.insert(record_type, handler);
+	}
+
+	pub fn redo(&self, record: &CustomRecord) -> Result<(), StorageError> {
+		self.get(record.record_type)?
+			.redo(record)
+			.map_err(|source| StorageError::WalRecordHandler {
+				record_type: record.record_type,
+				source,
+			})
+	}
+
+	pub fn undo(&self, record: &CustomRecord) -> Result<(), StorageError> {
+		self.get(record.record_type)?
+			.undo(record)
+			.map_err(|source| StorageError::WalRecordHandler {
+				record_type: record.record_type,
+				source,
+			})
+	}
+
+	/// Fails if no handler is registered for the record type.
+	pub fn check(&self, record_type: u16) -> Result<(), StorageError> {
+		self.get(record_type).map(|_| ())
+	}
+
+	fn get(&self, record_type: u16) -> Result<&dyn WalRecordHandler, StorageError> {
+		self.0
+			.get(&record_type)
+			.map(Arc::as_ref)
+			.ok_or(StorageError::UnknownWalRecordType(record_type))
+	}
+}
+
+impl fmt::Debug for WalRecordHandlers {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut record_types: Vec<u16> = self.0.keys().copied().collect();
+		record_types.sort_unstable();
+		f.debug_tuple("WalRecordHandlers")
+			.field(&record_types)
+			.finish()
+	}
+}
+
+impl PartialEq for WalRecordHandlers {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.len() == other.0.len()
+			&& self.0.iter().all(|(record_type, handler)| {
+				other
+					.0
+					.get(record_type)
+					.is_some_and(|other| Arc::ptr_eq(handler, other))
+			})
+	}
+}
+
+impl Eq for WalRecordHandlers {}
+
+#[cfg(test)]
+pub(crate) mod test_helpers {
+	use parking_lot::Mutex;
+
+	use super::*;
+
+	#[derive(Debug, Clone, PartialEq, Eq)]
+	pub(crate) enum HandlerCall {
+		Redo(CustomRecord),
+		Undo(CustomRecord),
+	}
+
+	/// A handler that keeps track of the records it redoes and undoes.
+	#[derive(Debug, Default)]
+	pub(crate) struct RecordingHandler {
+		pub calls: Mutex<Vec<HandlerCall>>,
+	}
+
+	impl WalRecordHandler for RecordingHandler {
+		fn redo(&self, record: &CustomRecord) -> Result<(), WalRecordHandlerError> {
+			self.calls.lock().push(HandlerCall::Redo(record.clone()));
+			Ok(())
+		}
+
+		fn undo(&self, record: &CustomRecord) -> Result<(), WalRecordHandlerError> {
+			self.calls.lock().push(HandlerCall::Undo(record.clone()));
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use self::test_helpers::{HandlerCall, RecordingHandler};
+
+	use super::*;
+
+	struct FailingHandler;
+
+	impl WalRecordHandler for FailingHandler {
+		fn redo(&self, _record: &CustomRecord) -> Result<(), WalRecordHandlerError> {
+			Err("redo failed".into())
+		}
+
+		fn undo(&self, _record: &CustomRecord) -> Result<(), WalRecordHandlerError> {
+			Ok(())
+		}
+	}
+
+	fn record(record_type: u16) -> CustomRecord {
+		CustomRecord {
+			transaction_id: 1,
+			record_type,
+			version: 0,
+			data: vec![1, 2, 3],
+		}
+	}
+
+	#[test]
+	fn dispatch_by_record_type() {
+		// given
+		let first = Arc::new(RecordingHandler::default());
+		let second = Arc::new(RecordingHandler::default());
+		let mut handlers = WalRecordHandlers::default();
+		handlers.register(1, Arc::clone(&first) as Arc<dyn WalRecordHandler>);
+		handlers.register(2, Arc::clone(&second) as Arc<dyn WalRecordHandler>);
+
+		// when
+		handlers.redo(&record(1)).unwrap();
+		handlers.undo(&record(2)).unwrap();
+
+		// then
+		assert_eq!(*first.calls.lock(), [HandlerCall::Redo(record(1))]);
+		assert_eq!(*second.calls.lock(), [HandlerCall::Undo(record(2))]);
+	}
+
+	#[test]
+	fn fail_for_unknown_or_failing_handlers() {
+		// given
+		let mut handlers = WalRecordHandlers::default();
+		handlers.register(1, Arc::new(FailingHandler));
+
+		// expect
+		assert!(matches!(
+			handlers.redo(&record(2)),
+			Err(StorageError::UnknownWalRecordType(2))
+		));
+		assert!(matches!(
+			handlers.redo(&record(1)),
+			Err(StorageError::WalRecordHandler { record_type: 1, .. })
+		));
+		assert!(handlers.undo(&record(1)).is_ok());
+	}
+}
@@ -0,0 +1,178 @@
+use std::{
+	collections::VecDeque,
+	time::{Duration, Instant},
+};
+
+use super::Stats;
+
+#[derive(Debug, Clone, Copy)]
+struct UsageSample {
+	at: Instant,
+	segment_size: u64,
+	wal_size: u64,
+}
+
+/// Projects how the storage used by a database grows, from [`Stats`] that
+/// are recorded periodically, so that running out of space can be noticed
+/// before writes start failing.
+///
+/// Growth rates are computed over the samples within a sliding window; older
+/// samples are discarded as new ones are recorded.
+#[derive(Debug, Clone)]
+pub struct UsageForecaster {
+	window: Duration,
+	samples: VecDeque<UsageSample>,
+}
+
+/// The storage used by a database, and how fast it grew within the window of
+/// a [`UsageForecaster`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageForecast {
+	/// The size of the written pages of all segments, in bytes.
+	pub segment_size: u64,
+	/// The size of the WAL, in bytes.
+	pub wal_size: u64,
+	/// How fast the segments grew, in bytes per second.
+	pub segment_growth: f64,
+	/// How fast the WAL grew, in bytes per second. Since checkpoints shrink
+	/// the WAL, this may be negative.
+	pub wal_growth: f64,
+}
+
+impl UsageForecaster {
+	pub fn new(window: Duration) -> Self {
+		Self {
+			window,
+			samples: VecDeque::new(),
+		}
+	}
+
+	/// Records the storage usage of `stats` as of now.
+	pub fn record(&mut self, stats: &Stats) {
+		self.record_at(Instant::now(), stats);
+	}
+
+	/// Records the storage usage of `stats` as of `at`, which must not be
+	/// earlier than that of previously recorded stats.
+	pub fn record_at(&mut self, at: Instant, stats: &Stats) {
+		self.samples.push_back(UsageSample {
+			at,
+			segment_size: stats.segment_size(),
+			wal_size: stats.wal_size as u64,
+		});
+		while self
+			.samples
+			.front()
+			.is_some_and(|sample| at.duration_since(sample.at) > self.window)
+		{
+			self.samples.pop_front();
+		}
+	}
+
+	/// The current usage and its growth, or `None` until stats that were
+	/// recorded at different times are within the window.
+	#[allow(clippy::cast_precision_loss)]
+	pub fn forecast(&self) -> Option<UsageForecast> {
+		let first = self.samples.front()?;
+		let last = self.samples.back()?;
+		let elapsed = last.at.duration_since(first.at).as_secs_f64();
+		if elapsed == 0.0 {
+			return None;
+		}
+		let growth = |from: u64, to: u64| (to as f64 - from as f64) / elapsed;
+		Some(UsageForecast {
+			segment_size: last.segment_size,
+			wal_size: last.wal_size,
+			segment_growth: growth(first.segment_size, last.segment_size),
+			wal_growth: growth(first.wal_size, last.wal_size),
+		})
+	}
+}
+
+impl UsageForecast {
+	/// The combined size of the segments and the WAL, in bytes.
+	pub fn total_size(&self) -> u64 {
+		self.segment_size + self.wal_size
+	}
+
+	/// How fast the combined size of the segments and the WAL grew, in bytes
+	/// per second.
+	pub fn growth(&self) -> f64 {
+		self.segment_growth + self.wal_growth
+	}
+
+	/// The estimated time until the storage used reaches `limit` bytes, such
+	/// as a quota, or the current size plus the free space on the disk.
+	/// Returns `None` if usage isn't growing, and [`Duration::ZERO`] if the
+	/// limit is already reached.
+	#[allow(clippy::cast_precision_loss)]
+	pub fn time_until(&self, limit: u64) -> Option<Duration> {
+		let remaining = limit.saturating_sub(self.total_size());
+		if remaining == 0 {
+			return Some(Duration::ZERO);
+		}
+		let growth = self.growth();
+		if growth <= 0.0 {
+			return None;
+		}
+		Duration::try_from_secs_f64(remaining as f64 / growth).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::BTreeMap;
+
+	use crate::consts::PAGE_SIZE;
+
+	use super::*;
+
+	fn stats(segment_pages: usize, wal_size: usize) -> Stats {
+		Stats {
+			segment_pages: BTreeMap::from([(1, segment_pages)]),
+			wal_size,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn forecast_growth_within_window() {
+		// given
+		let start = Instant::now();
+		let mut forecaster = UsageForecaster::new(Duration::from_secs(100));
+
+		// when
+		let before_samples = forecaster.forecast();
+		forecaster.record_at(start, &stats(0, 0));
+		forecaster.record_at(start + Duration::from_secs(50), &stats(10, 500));
+		forecaster.record_at(start + Duration::from_secs(150), &stats(20, 1000));
+		forecaster.record_at(start + Duration::from_secs(250), &stats(30, 500));
+
+		// then
+		assert_eq!(before_samples, None);
+		let forecast = forecaster.forecast().unwrap();
+		assert_eq!(forecast.segment_size, 30 * PAGE_SIZE as u64);
+		assert_eq!(forecast.wal_size, 500);
+		assert_eq!(forecast.segment_growth, (10 * PAGE_SIZE) as f64 / 100.0);
+		assert_eq!(forecast.wal_growth, -5.0);
+		assert_eq!(forecast.time_until(0), Some(Duration::ZERO));
+		let limit = forecast.total_size() + (10 * PAGE_SIZE) as u64 - 500;
+		let time_until_limit = forecast.time_until(limit).unwrap();
+		assert_eq!(time_until_limit.as_secs_f64().round(), 100.0);
+	}
+
+	#[test]
+	fn no_time_until_limit_if_not_growing() {
+		// given
+		let start = Instant::now();
+		let mut forecaster = UsageForecaster::new(Duration::from_secs(100));
+
+		// when
+		forecaster.record_at(start, &stats(10, 1000));
+		forecaster.record_at(start + Duration::from_secs(10), &stats(10, 0));
+
+		// then
+		let forecast = forecaster.forecast().unwrap();
+		assert_eq!(forecast.time_until(u64::MAX), None);
+	}
+}
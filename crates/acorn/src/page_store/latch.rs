@@ -0,0 +1,306 @@
+//! The latches of the page cache's buffer frames.
+//!
+//! Normally, frames are latched with `parking_lot`'s raw reader-writer lock.
+//! When the crate is built for `miri` or `loom` (with `--cfg loom`), a
+//! spinning lock built only on atomics is used instead, which both of them
+//! can check. Under `loom`, its atomics are `loom`'s, so frame latches can
+//! only be used inside a `loom` model then.
+
+use std::time::Instant;
+
+#[cfg(loom)]
+use loom::{
+	sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering},
+	thread::yield_now,
+};
+#[cfg(not(loom))]
+use std::{
+	sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering},
+	thread::yield_now,
+};
+
+#[cfg(not(any(miri, loom)))]
+use parking_lot::{
+	lock_api::{RawRwLock as _, RawRwLockDowngrade, RawRwLockTimed},
+	RawRwLock as RawLatch,
+};
+
+/// A reader-writer lock that only uses atomics, which is held exclusively
+/// while its state is `EXCLUSIVE`, and otherwise counts the shared holders.
+#[cfg(any(miri, loom))]
+struct RawLatch {
+	state: AtomicUsize,
+}
+
+#[cfg(any(miri, loom))]
+impl RawLatch {
+	const EXCLUSIVE: usize = usize::MAX;
+
+	fn new() -> Self {
+		Self {
+			state: AtomicUsize::new(0),
+		}
+	}
+
+	fn lock_shared(&self) {
+		while !self.try_lock_shared() {
+			yield_now();
+		}
+	}
+
+	fn try_lock_shared(&self) -> bool {
+		let mut state = self.state.load(Ordering::Relaxed);
+		while state != Self::EXCLUSIVE {
+			match self.state.compare_exchange_weak(
+				state,
+				state + 1,
+				Ordering::Acquire,
+				Ordering::Relaxed,
+			) {
+				Ok(_) => return true,
+				Err(current) => state = current,
+			}
+		}
+		false
+	}
+
+	fn try_lock_shared_until(&self, until: Instant) -> bool {
+		while !self.try_lock_shared() {
+			if Instant::now() >= until {
+				return false;
+			}
+			yield_now();
+		}
+		true
+	}
+
+	unsafe fn unlock_shared(&self) {
+		self.state.fetch_sub(1, Ordering::Release);
+	}
+
+	fn lock_exclusive(&self) {
+		while !self.try_lock_exclusive() {
+			yield_now();
+		}
+	}
+
+	fn try_lock_exclusive(&self) -> bool {
+		self.state
+			.compare_exchange(0, Self::EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed)
+			.is_ok()
+	}
+
+	fn try_lock_exclusive_until(&self, until: Instant) -> bool {
+		while !self.try_lock_exclusive() {
+			if Instant::now() >= until {
+				return false;
+			}
+			yield_now();
+		}
+		true
+	}
+
+	unsafe fn unlock_exclusive(&self) {
+		self.state.store(0, Ordering::Release);
+	}
+
+	unsafe fn downgrade(&self) {
+		self.state.store(1, Ordering::Release);
+	}
+
+	fn is_locked(&self) -> bool {
+		self.state.load(Ordering::Relaxed) != 0
+	}
+}
+
+/// The lock of a buffer frame, along with a version that is odd while the
+/// frame is locked exclusively, and incremented whenever that lock is
+/// acquired or released. This allows reading a frame optimistically without
+/// locking it, by checking that its version didn't change during the read.
+///
+/// Unlike in a seqlock, optimistic reads never race with writes: once a
+/// writer locked the frame exclusively, it waits for the optimistic reads
+/// that already started before it returns, and later ones see the odd
+/// version and don't read the frame.
+pub(super) struct FrameLock {
+	lock: RawLatch,
+	version: AtomicU64,
+	/// The number of optimistic reads in progress.
+	optimistic_reads: AtomicUsize,
+}
+
+impl FrameLock {
+	pub fn new() -> Self {
+		Self {
+			#[cfg(not(any(miri, loom)))]
+			lock: RawLatch::INIT,
+			#[cfg(any(miri, loom))]
+			lock: RawLatch::new(),
+			version: AtomicU64::new(0),
+			optimistic_reads: AtomicUsize::new(0),
+		}
+	}
+
+	pub fn lock_shared(&self) {
+		self.lock.lock_shared();
+	}
+
+	pub fn try_lock_shared(&self) -> bool {
+		self.lock.try_lock_shared()
+	}
+
+	/// Like [`Self::lock_shared`], but gives up and returns `false` if the
+	/// lock is still held exclusively at `until`.
+	pub fn lock_shared_until(&self, until: Instant) -> bool {
+		self.lock.try_lock_shared_until(until)
+	}
+
+	/// # Safety:
+	/// The caller must hold a shared lock.
+	pub unsafe fn unlock_shared(&self) {
+		self.lock.unlock_shared();
+	}
+
+	pub fn lock_exclusive(&self) {
+		self.lock.lock_exclusive();
+		self.begin_exclusive();
+	}
+
+	pub fn try_lock_exclusive(&self) -> bool {
+		if !self.lock.try_lock_exclusive() {
+			return false;
+		}
+		self.begin_exclusive();
+		true
+	}
+
+	/// Like [`Self::lock_exclusive`], but gives up and returns `false` if the
+	/// lock is still held at `until`.
+	pub fn lock_exclusive_until(&self, until: Instant) -> bool {
+		if !self.lock.try_lock_exclusive_until(until) {
+			return false;
+		}
+		self.begin_exclusive();
+		true
+	}
+
+	/// Makes the version odd, and waits for the optimistic reads that started
+	/// before, so that the frame can be written to.
+	fn begin_exclusive(&self) {
+		self.version.fetch_add(1, Ordering::Relaxed);
+		// Like in a seqlock, the writes to the frame mustn't become visible
+		// before the odd version. The fence is also sequentially consistent,
+		// so that either an optimistic read sees the odd version, or this sees
+		// that the read is in progress.
+		fence(Ordering::SeqCst);
+		while self.optimistic_reads.load(Ordering::Acquire) != 0 {
+			yield_now();
+		}
+	}
+
+	/// # Safety:
+	/// The caller must hold the exclusive lock.
+	pub unsafe fn unlock_exclusive(&self) {
+		self.version.fetch_add(1, Ordering::Release);
+		self.lock.unlock_exclusive();
+	}
+
+	/// # Safety:
+	/// The caller must hold the exclusive lock.
+	pub unsafe fn downgrade(&self) {
+		self.version.fetch_add(1, Ordering::Release);
+		self.lock.downgrade();
+	}
+
+	pub fn is_locked(&self) -> bool {
+		self.lock.is_locked()
+	}
+
+	/// Runs `read`, which reads the frame without locking it, unless the
+	/// frame is locked exclusively. Returns `None` if it is, or if it was
+	/// locked exclusively while `read` ran, which may then have read an
+	/// outdated state of the frame.
+	pub fn read_optimistic<T>(&self, read: impl FnOnce() -> T) -> Option<T> {
+		self.optimistic_reads.fetch_add(1, Ordering::Relaxed);
+		fence(Ordering::SeqCst);
+		let version = self.version.load(Ordering::Acquire);
+		let result = (version & 1 == 0).then(read);
+		fence(Ordering::Acquire);
+		let unchanged = self.version.load(Ordering::Relaxed) == version;
+		self.optimistic_reads.fetch_sub(1, Ordering::Release);
+		result.filter(|_| unchanged)
+	}
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+	use loom::{cell::UnsafeCell, sync::Arc, thread};
+
+	use super::*;
+
+	struct Frame {
+		lock: FrameLock,
+		data: UnsafeCell<[u64; 2]>,
+	}
+
+	// Safety: `data` is only accessed while holding `lock`.
+	unsafe impl Sync for Frame {}
+
+	#[test]
+	fn readers_see_complete_writes() {
+		loom::model(|| {
+			let frame = Arc::new(Frame {
+				lock: FrameLock::new(),
+				data: UnsafeCell::new([0; 2]),
+			});
+
+			let writer = thread::spawn({
+				let frame = Arc::clone(&frame);
+				move || {
+					frame.lock.lock_exclusive();
+					frame.data.with_mut(|data| unsafe { *data = [1; 2] });
+					unsafe { frame.lock.downgrade() };
+					unsafe { frame.lock.unlock_shared() };
+				}
+			});
+
+			frame.lock.lock_shared();
+			let data = frame.data.with(|data| unsafe { *data });
+			unsafe { frame.lock.unlock_shared() };
+			assert!(data == [0; 2] || data == [1; 2]);
+
+			writer.join().unwrap();
+			let data = frame
+				.lock
+				.read_optimistic(|| frame.data.with(|data| unsafe { *data }));
+			assert_eq!(data, Some([1; 2]));
+		});
+	}
+
+	#[test]
+	fn optimistic_reads_dont_race_with_writes() {
+		loom::model(|| {
+			let frame = Arc::new(Frame {
+				lock: FrameLock::new(),
+				data: UnsafeCell::new([0; 2]),
+			});
+
+			let writer = thread::spawn({
+				let frame = Arc::clone(&frame);
+				move || {
+					frame.lock.lock_exclusive();
+					frame.data.with_mut(|data| unsafe { *data = [1; 2] });
+					unsafe { frame.lock.unlock_exclusive() };
+				}
+			});
+
+			// loom fails the model if the read races with the write.
+			let data = frame
+				.lock
+				.read_optimistic(|| frame.data.with(|data| unsafe { *data }));
+			writer.join().unwrap();
+
+			assert!(matches!(data, None | Some([0, 0] | [1, 1])));
+		});
+	}
+}
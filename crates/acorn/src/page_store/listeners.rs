@@ -0,0 +1,64 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use parking_lot::RwLock;
+
+use super::{CheckpointTrigger, PageId};
+
+type TransactionListener = Arc<dyn Fn(u64, &BTreeSet<PageId>) + Send + Sync>;
+type CheckpointListener = Arc<dyn Fn(CheckpointTrigger) + Send + Sync>;
+
+/// The functions that are called when transactions commit or are rolled
+/// back, and when checkpoints are taken.
+///
+/// Listeners are called on the thread that completed the transaction or took
+/// the checkpoint, after it is done, so they hold up that thread, but can
+/// start new transactions.
+#[derive(Default)]
+pub(crate) struct Listeners {
+	commit: RwLock<Vec<TransactionListener>>,
+	rollback: RwLock<Vec<TransactionListener>>,
+	checkpoint: RwLock<Vec<CheckpointListener>>,
+}
+
+impl Listeners {
+	pub fn on_commit(&self, listener: impl Fn(u64, &BTreeSet<PageId>) + Send + Sync + 'static) {
+		self.commit.write().push(Arc::new(listener));
+	}
+
+	pub fn on_rollback(&self, listener: impl Fn(u64, &BTreeSet<PageId>) + Send + Sync + 'static) {
+		self.rollback.write().push(Arc::new(listener));
+	}
+
+	pub fn on_checkpoint(&self, listener: impl Fn(CheckpointTrigger) + Send + Sync + 'static) {
+		self.checkpoint.write().push(Arc::new(listener));
+	}
+
+	/// Whether any listeners are interested in transactions, so that the
+	/// pages they wrote have to be collected.
+	pub fn has_transaction_listeners(&self) -> bool {
+		!self.commit.read().is_empty() || !self.rollback.read().is_empty()
+	}
+
+	pub fn committed(&self, transaction_id: u64, pages: &BTreeSet<PageId>) {
+		// The listeners are called without holding the lock, so that they can
+		// register more listeners.
+		let listeners = self.commit.read().clone();
+		for listener in listeners {
+			listener(transaction_id, pages);
+		}
+	}
+
+	pub fn rolled_back(&self, transaction_id: u64, pages: &BTreeSet<PageId>) {
+		let listeners = self.rollback.read().clone();
+		for listener in listeners {
+			listener(transaction_id, pages);
+		}
+	}
+
+	pub fn checkpointed(&self, trigger: CheckpointTrigger) {
+		let listeners = self.checkpoint.read().clone();
+		for listener in listeners {
+			listener(trigger);
+		}
+	}
+}
@@ -0,0 +1,523 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::{PageId, StorageError};
+
+/// A snapshot of which transactions hold which pages, and which of them are
+/// waiting for pages held by others.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LockGraph {
+	/// The transactions that hold or wait for pages, ordered by their IDs.
+	pub transactions: Vec<TransactionLocks>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionLocks {
+	pub transaction_id: u64,
+	/// The pages held by the transaction, in ascending order.
+	pub held: Vec<PageId>,
+	pub waiting: Option<LockWait>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockWait {
+	pub page_id: PageId,
+	/// The transaction that holds the page.
+	pub owner: u64,
+	/// How long the transaction has been waiting for the page.
+	pub duration: Duration,
+}
+
+impl TransactionLocks {
+	fn new(transaction_id: u64) -> Self {
+		Self {
+			transaction_id,
+			held: Vec::new(),
+			waiting: None,
+		}
+	}
+}
+
+impl fmt::Display for LockGraph {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, transaction) in self.transactions.iter().enumerate() {
+			if i != 0 {
+				writeln!(f)?;
+			}
+			write!(
+				f,
+				"Transaction {} holds {} pages",
+				transaction.transaction_id,
+				transaction.held.len()
+			)?;
+			if let Some(wait) = &transaction.waiting {
+				write!(
+					f,
+					", and has waited {:?} for page {} held by transaction {}",
+					wait.duration, wait.page_id, wait.owner
+				)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// How often a waiting transaction checks whether it was cancelled.
+const CANCELLATION_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Cancels waits of the transactions it was given to. Clones share the same
+/// state, so a transaction can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Makes transactions that use the token fail instead of waiting for
+	/// pages held by other transactions, including those that are waiting
+	/// right now.
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// Limits how long a transaction waits for pages held by others.
+#[derive(Debug, Clone, Default)]
+struct WaitLimit {
+	deadline: Option<Instant>,
+	cancellation: Option<CancellationToken>,
+}
+
+impl WaitLimit {
+	fn check(&self, transaction_id: u64, page_id: PageId) -> Result<(), StorageError> {
+		if self
+			.cancellation
+			.as_ref()
+			.is_some_and(CancellationToken::is_cancelled)
+		{
+			return Err(StorageError::Cancelled {
+				transaction_id,
+				page_id,
+			});
+		}
+		if self
+			.deadline
+			.is_some_and(|deadline| Instant::now() >= deadline)
+		{
+			return Err(StorageError::TimedOut {
+				transaction_id,
+				page_id,
+			});
+		}
+		Ok(())
+	}
+
+	/// The time at which the limit has to be checked again, if a waiting
+	/// transaction isn't woken up before.
+	fn next_check(&self) -> Option<Instant> {
+		let poll = self
+			.cancellation
+			.as_ref()
+			.map(|_| Instant::now() + CANCELLATION_POLL_INTERVAL);
+		match (self.deadline, poll) {
+			(Some(deadline), Some(poll)) => Some(Instant::min(deadline, poll)),
+			(deadline, poll) => deadline.or(poll),
+		}
+	}
+}
+
+/// The wait limit of a single transaction, for waits outside of the lock
+/// manager, such as for the lock of a cached page.
+#[derive(Debug, Clone)]
+pub(super) struct AccessLimit {
+	transaction_id: u64,
+	limit: WaitLimit,
+}
+
+impl AccessLimit {
+	/// Fails if the transaction reached its deadline or was cancelled while
+	/// waiting for the page.
+	pub fn check(&self, page_id: PageId) -> Result<(), StorageError> {
+		self.limit.check(self.transaction_id, page_id)
+	}
+
+	/// The time at which a wait has to stop to check the limit again.
+	pub fn next_check(&self) -> Instant {
+		self.limit
+			.next_check()
+			.expect("Access limits always have a deadline or a cancellation token")
+	}
+}
+
+#[derive(Debug)]
+struct Wait {
+	owner: u64,
+	page_id: PageId,
+	since: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	owners: HashMap<PageId, u64>,
+	waits_for: HashMap<u64, Wait>,
+	limits: HashMap<u64, WaitLimit>,
+}
+
+impl State {
+	fn other_owner(&self, page_id: PageId, accessor: Option<u64>) -> Option<u64> {
+		self.owners
+			.get(&page_id)
+			.copied()
+			.filter(|owner| Some(*owner) != accessor)
+	}
+
+	/// Whether `waiter` waiting for `owner` would close a cycle in the
+	/// wait-for graph. The graph never contains cycles, so following the
+	/// edges from `owner` always terminates.
+	fn would_deadlock(&self, waiter: u64, owner: u64) -> bool {
+		let mut current = owner;
+		loop {
+			if current == waiter {
+				return true;
+			}
+			match self.waits_for.get(&current) {
+				Some(wait) => current = wait.owner,
+				None => return false,
+			}
+		}
+	}
+
+	fn lock_graph(&self) -> LockGraph {
+		let now = Instant::now();
+		let mut transactions: BTreeMap<u64, TransactionLocks> = BTreeMap::new();
+		for (page_id, owner) in &self.owners {
+			transactions
+				.entry(*owner)
+				.or_insert_with(|| TransactionLocks::new(*owner))
+				.held
+				.push(*page_id);
+		}
+		for (waiter, wait) in &self.waits_for {
+			let transaction = transactions
+				.entry(*waiter)
+				.or_insert_with(|| TransactionLocks::new(*waiter));
+			transaction.waiting = Some(LockWait {
+				page_id: wait.page_id,
+				owner: wait.owner,
+				duration: now.saturating_duration_since(wait.since),
+			});
+		}
+		let mut transactions: Vec<TransactionLocks> = transactions.into_values().collect();
+		for transaction in &mut transactions {
+			transaction.held.sort_unstable();
+		}
+		LockGraph { transactions }
+	}
+}
+
+/// Keeps track of which transaction holds which pages, from the first time
+/// the transaction writes to them until it completes.
+///
+/// Transactions register which transaction they are waiting for, so that
+/// waiting for a page that would never be released can be detected, and
+/// fails with [`StorageError::Deadlock`] instead.
+#[derive(Debug, Default)]
+pub(super) struct LockManager {
+	state: Mutex<State>,
+	released: Condvar,
+}
+
+impl LockManager {
+	/// Waits until the page is not held by another transaction, and then
+	/// takes it for `transaction_id`.
+	pub fn lock(&self, page_id: PageId, transaction_id: u64) -> Result<(), StorageError> {
+		let mut state = self.state.lock();
+		self.wait(&mut state, page_id, Some(transaction_id))?;
+		state.owners.insert(page_id, transaction_id);
+		Ok(())
+	}
+
+	/// Waits until the page is not held by a transaction other than
+	/// `accessor`.
+	pub fn wait_for(&self, page_id: PageId, accessor: Option<u64>) -> Result<(), StorageError> {
+		let mut state = self.state.lock();
+		self.wait(&mut state, page_id, accessor)
+	}
+
+	pub fn is_locked(&self, page_id: PageId, accessor: Option<u64>) -> bool {
+		self.state.lock().other_owner(page_id, accessor).is_some()
+	}
+
+	pub fn lock_graph(&self) -> LockGraph {
+		self.state.lock().lock_graph()
+	}
+
+	/// Makes the transaction fail with [`StorageError::TimedOut`] when it
+	/// waits for a page past `deadline`, until it releases all its pages.
+	pub fn set_deadline(&self, transaction_id: u64, deadline: Instant) {
+		let mut state = self.state.lock();
+		state.limits.entry(transaction_id).or_default().deadline = Some(deadline);
+	}
+
+	/// Makes the transaction fail with [`StorageError::Cancelled`] when it
+	/// waits for a page after the token was cancelled, until it releases all
+	/// its pages.
+	pub fn set_cancellation(&self, transaction_id: u64, token: CancellationToken) {
+		let mut state = self.state.lock();
+		state.limits.entry(transaction_id).or_default().cancellation = Some(token);
+	}
+
+	/// The limit that was set for the waits of `accessor`, if any.
+	pub fn access_limit(&self, accessor: Option<u64>) -> Option<AccessLimit> {
+		let transaction_id = accessor?;
+		let limit = self
+			.state
+			.lock()
+			.limits
+			.get(&transaction_id)
+			.filter(|limit| limit.next_check().is_some())?
+			.clone();
+		Some(AccessLimit {
+			transaction_id,
+			limit,
+		})
+	}
+
+	/// Releases a single page, if it is held by `transaction_id`.
+	pub fn release(&self, page_id: PageId, transaction_id: u64) {
+		let mut state = self.state.lock();
+		if state.other_owner(page_id, Some(transaction_id)).is_some() {
+			return;
+		}
+		state.owners.remove(&page_id);
+		drop(state);
+		self.released.notify_all();
+	}
+
+	pub fn release_all(&self, transaction_id: u64) {
+		let mut state = self.state.lock();
+		state.owners.retain(|_, owner| *owner != transaction_id);
+		state.limits.remove(&transaction_id);
+		drop(state);
+		self.released.notify_all();
+	}
+
+	fn wait(
+		&self,
+		state: &mut parking_lot::MutexGuard<'_, State>,
+		page_id: PageId,
+		accessor: Option<u64>,
+	) -> Result<(), StorageError> {
+		let since = Instant::now();
+		while let Some(owner) = state.other_owner(page_id, accessor) {
+			let mut next_check = None;
+			if let Some(waiter) = accessor {
+				let deadlock = state.would_deadlock(waiter, owner);
+				state.waits_for.insert(
+					waiter,
+					Wait {
+						owner,
+						page_id,
+						since,
+					},
+				);
+				if deadlock {
+					// The graph includes the wait that closes the cycle
+					let lock_graph = state.lock_graph();
+					state.waits_for.remove(&waiter);
+					return Err(StorageError::Deadlock {
+						transaction_id: waiter,
+						page_id,
+						lock_graph,
+					});
+				}
+				if let Some(limit) = state.limits.get(&waiter) {
+					let checked = limit.check(waiter, page_id);
+					next_check = limit.next_check();
+					if let Err(err) = checked {
+						state.waits_for.remove(&waiter);
+						return Err(err);
+					}
+				}
+			}
+			match next_check {
+				Some(next_check) => {
+					self.released.wait_until(state, next_check);
+				}
+				None => self.released.wait(state),
+			}
+		}
+		if let Some(waiter) = accessor {
+			state.waits_for.remove(&waiter);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{sync::Arc, thread, time::Duration};
+
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn detect_deadlock() {
+		// given
+		let locks = Arc::new(LockManager::default());
+		locks.lock(page_id!(1, 1), 0).unwrap();
+		locks.lock(page_id!(1, 2), 1).unwrap();
+		let waiting = thread::spawn({
+			let locks = Arc::clone(&locks);
+			move || locks.lock(page_id!(1, 1), 1)
+		});
+		while !locks.state.lock().waits_for.contains_key(&1) {
+			thread::sleep(Duration::from_millis(1));
+		}
+
+		// when
+		let result = locks.lock(page_id!(1, 2), 0);
+
+		// then
+		let Err(StorageError::Deadlock {
+			transaction_id: 0,
+			lock_graph,
+			..
+		}) = result
+		else {
+			panic!("Expected a deadlock, but got {result:?}");
+		};
+		assert_eq!(lock_graph.transactions.len(), 2);
+		assert_eq!(lock_graph.transactions[0].held, [page_id!(1, 1)]);
+		assert_eq!(
+			lock_graph.transactions[0]
+				.waiting
+				.as_ref()
+				.map(|wait| (wait.page_id, wait.owner)),
+			Some((page_id!(1, 2), 1))
+		);
+		assert_eq!(lock_graph.transactions[1].held, [page_id!(1, 2)]);
+		assert_eq!(
+			lock_graph.transactions[1]
+				.waiting
+				.as_ref()
+				.map(|wait| (wait.page_id, wait.owner)),
+			Some((page_id!(1, 1), 0))
+		);
+		assert_eq!(locks.lock_graph().transactions[0].waiting, None);
+		locks.release_all(0);
+		waiting.join().unwrap().unwrap();
+		assert!(!locks.is_locked(page_id!(1, 1), Some(1)));
+		assert!(locks.is_locked(page_id!(1, 1), Some(0)));
+	}
+
+	#[test]
+	fn list_holders_and_waiters() {
+		// given
+		let locks = Arc::new(LockManager::default());
+		locks.lock(page_id!(1, 3), 4).unwrap();
+		locks.lock(page_id!(1, 1), 4).unwrap();
+		locks.lock(page_id!(1, 2), 7).unwrap();
+		let waiting = thread::spawn({
+			let locks = Arc::clone(&locks);
+			move || locks.lock(page_id!(1, 1), 7)
+		});
+		while !locks.state.lock().waits_for.contains_key(&7) {
+			thread::sleep(Duration::from_millis(1));
+		}
+
+		// when
+		let lock_graph = locks.lock_graph();
+		locks.release_all(4);
+		waiting.join().unwrap().unwrap();
+
+		// then
+		assert_eq!(
+			lock_graph
+				.transactions
+				.iter()
+				.map(|transaction| (transaction.transaction_id, transaction.held.clone()))
+				.collect::<Vec<_>>(),
+			[
+				(4, vec![page_id!(1, 1), page_id!(1, 3)]),
+				(7, vec![page_id!(1, 2)])
+			]
+		);
+		assert_eq!(lock_graph.transactions[0].waiting, None);
+		assert_eq!(
+			lock_graph.transactions[1]
+				.waiting
+				.as_ref()
+				.map(|wait| (wait.page_id, wait.owner)),
+			Some((page_id!(1, 1), 4))
+		);
+		let description = lock_graph.to_string();
+		assert!(description.starts_with("Transaction 4 holds 2 pages\nTransaction 7 holds 1 pages"));
+		assert!(description.ends_with(&format!(
+			"for page {} held by transaction 4",
+			page_id!(1, 1)
+		)));
+		assert_eq!(
+			locks.lock_graph().transactions[0].held,
+			[page_id!(1, 1), page_id!(1, 2)]
+		);
+	}
+
+	#[test]
+	fn limit_waits() {
+		// given
+		let locks = Arc::new(LockManager::default());
+		locks.lock(page_id!(1, 1), 0).unwrap();
+		locks.set_deadline(1, Instant::now() + Duration::from_millis(20));
+		let token = CancellationToken::new();
+		locks.set_cancellation(2, token.clone());
+		let cancelled = thread::spawn({
+			let locks = Arc::clone(&locks);
+			move || locks.lock(page_id!(1, 1), 2)
+		});
+		while !locks.state.lock().waits_for.contains_key(&2) {
+			thread::sleep(Duration::from_millis(1));
+		}
+
+		// when
+		let timed_out = locks.lock(page_id!(1, 1), 1);
+		token.cancel();
+
+		// then
+		assert!(matches!(
+			timed_out,
+			Err(StorageError::TimedOut {
+				transaction_id: 1,
+				..
+			})
+		));
+		assert!(matches!(
+			cancelled.join().unwrap(),
+			Err(StorageError::Cancelled {
+				transaction_id: 2,
+				..
+			})
+		));
+		assert!(locks.lock_graph().transactions[0].waiting.is_none());
+		assert_eq!(locks.lock_graph().transactions.len(), 1);
+		locks.release_all(1);
+		locks.release_all(0);
+		locks.lock(page_id!(1, 1), 1).unwrap();
+	}
+}
@@ -0,0 +1,175 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::{
+	consts::{DEFAULT_IDLE_MAINTENANCE_AFTER, DEFAULT_IDLE_SCRUB_PAGES},
+	files::PageId,
+};
+
+use super::CheckProblem;
+
+/// Maintenance that is done in the background while the database is idle,
+/// so that applications that only use it interactively don't have to
+/// schedule it themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct IdleMaintenancePolicy {
+	/// Run maintenance once no transaction ran for this long, and again after
+	/// each further period of idleness. `None` disables idle maintenance.
+	pub idle_after: Option<Duration>,
+	/// How many stored pages each run reads back to verify their checksums.
+	pub scrub_pages: usize,
+}
+
+impl Default for IdleMaintenancePolicy {
+	fn default() -> Self {
+		Self {
+			idle_after: Some(DEFAULT_IDLE_MAINTENANCE_AFTER),
+			scrub_pages: DEFAULT_IDLE_SCRUB_PAGES,
+		}
+	}
+}
+
+/// What idle maintenance did since the database was opened.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaintenanceStats {
+	/// The number of times maintenance ran.
+	pub num_runs: u64,
+	/// The time since maintenance last ran, if it ran at all.
+	pub since_last: Option<Duration>,
+	/// The number of checkpoints taken by maintenance.
+	pub num_checkpoints: u64,
+	/// The number of pages that were read back to verify their checksums.
+	pub pages_scrubbed: u64,
+	/// The problems found by scrubbing pages.
+	pub problems: Vec<CheckProblem>,
+	/// The number of bytes of WAL generations that were deleted.
+	pub wal_bytes_trimmed: u64,
+}
+
+/// The result of a single run of idle maintenance.
+#[derive(Debug, Default)]
+pub(super) struct MaintenanceRun {
+	pub checkpointed: bool,
+	pub scrubbed: Vec<PageId>,
+	pub problems: Vec<CheckProblem>,
+	pub wal_bytes_trimmed: usize,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+	last_run: Option<Instant>,
+	/// The last page that was scrubbed, after which the next run continues.
+	scrub_cursor: Option<PageId>,
+	stats: MaintenanceStats,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct MaintenanceTracker {
+	state: Mutex<TrackerState>,
+}
+
+impl MaintenanceTracker {
+	/// Whether maintenance should run, given for how long no transaction ran,
+	/// or `None` if one is running.
+	pub fn is_due(&self, policy: &IdleMaintenancePolicy, idle_for: Option<Duration>) -> bool {
+		let (Some(idle_after), Some(idle_for)) = (policy.idle_after, idle_for) else {
+			return false;
+		};
+		if idle_for < idle_after {
+			return false;
+		}
+		self.state
+			.lock()
+			.last_run
+			.is_none_or(|last_run| last_run.elapsed() >= idle_after)
+	}
+
+	/// Picks the next `num_pages` of the stored pages to scrub, continuing
+	/// after those of the last run, and starting over at the first page once
+	/// the last one was scrubbed.
+	pub fn pages_to_scrub(&self, stored_pages: &[PageId], num_pages: usize) -> Vec<PageId> {
+		let cursor = self.state.lock().scrub_cursor;
+		let start = cursor.map_or(0, |cursor| {
+			stored_pages.partition_point(|page_id| *page_id <= cursor)
+		});
+		stored_pages[start..]
+			.iter()
+			.chain(&stored_pages[..start])
+			.take(num_pages)
+			.copied()
+			.collect()
+	}
+
+	pub fn record(&self, run: MaintenanceRun) {
+		let mut state = self.state.lock();
+		state.last_run = Some(Instant::now());
+		if let Some(last) = run.scrubbed.last() {
+			state.scrub_cursor = Some(*last);
+		}
+		state.stats.num_runs += 1;
+		state.stats.num_checkpoints += u64::from(run.checkpointed);
+		state.stats.pages_scrubbed += run.scrubbed.len() as u64;
+		state.stats.problems.extend(run.problems);
+		state.stats.wal_bytes_trimmed += run.wal_bytes_trimmed as u64;
+	}
+
+	pub fn stats(&self) -> MaintenanceStats {
+		let state = self.state.lock();
+		MaintenanceStats {
+			since_last: state.last_run.map(|last_run| last_run.elapsed()),
+			..state.stats.clone()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn run_when_idle() {
+		// given
+		let tracker = MaintenanceTracker::default();
+		let policy = IdleMaintenancePolicy {
+			idle_after: Some(Duration::from_secs(10)),
+			..Default::default()
+		};
+
+		// expect
+		assert!(!tracker.is_due(&policy, None));
+		assert!(!tracker.is_due(&policy, Some(Duration::from_secs(5))));
+		assert!(tracker.is_due(&policy, Some(Duration::from_secs(10))));
+		tracker.record(MaintenanceRun::default());
+		assert!(!tracker.is_due(&policy, Some(Duration::from_secs(20))));
+		assert!(!tracker.is_due(
+			&IdleMaintenancePolicy {
+				idle_after: None,
+				..Default::default()
+			},
+			Some(Duration::from_secs(20))
+		));
+	}
+
+	#[test]
+	fn scrub_pages_in_turn() {
+		// given
+		let tracker = MaintenanceTracker::default();
+		let stored_pages = [page_id!(1, 1), page_id!(1, 2), page_id!(2, 1)];
+
+		// when
+		let first = tracker.pages_to_scrub(&stored_pages, 2);
+		tracker.record(MaintenanceRun {
+			scrubbed: first.clone(),
+			..Default::default()
+		});
+		let second = tracker.pages_to_scrub(&stored_pages, 2);
+
+		// then
+		assert_eq!(first, [page_id!(1, 1), page_id!(1, 2)]);
+		assert_eq!(second, [page_id!(2, 1), page_id!(1, 1)]);
+		assert_eq!(tracker.stats().pages_scrubbed, 2);
+	}
+}
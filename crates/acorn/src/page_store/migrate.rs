@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use futures::executor::ThreadPool;
+
+use crate::files::{segment::PAGE_BODY_SIZE, DatabaseFolder, PageId};
+
+use super::{
+	wal::{Wal, WalApi, WalConfig},
+	PageStorageApi, StorageError, TransactionApi, WritePage,
+};
+
+/// The number of pages that are copied in a single transaction.
+const BATCH_SIZE: usize = 64;
+
+/// Copies the pages of a database that was created with a smaller page size
+/// into the target storage, and returns the number of pages that were
+/// copied.
+///
+/// Pages keep their IDs, and their bodies are padded with zeroes to the page
+/// size of this build. The WAL of the source isn't migrated, so the source
+/// must have been closed cleanly; otherwise, this fails without copying
+/// anything.
+pub(crate) fn migrate_pages<T: PageStorageApi>(
+	source: Arc<DatabaseFolder>,
+	thread_pool: Arc<ThreadPool>,
+	config: &WalConfig,
+	target: &T,
+) -> Result<usize, StorageError> {
+	let report = Wal::open(Arc::clone(&source), thread_pool, config)?.dry_run_recovery()?;
+	if !report.redo_pages.is_empty()
+		|| !report.undo_transactions.is_empty()
+		|| report.num_redo_records != 0
+	{
+		return Err(StorageError::MigrationNeedsRecovery);
+	}
+
+	let mut buf = vec![0; PAGE_BODY_SIZE];
+	let mut t = target.transaction()?;
+	let mut num_copied = 0;
+	for segment_num in source.existing_segment_nums()? {
+		let segment = source.open_resized_segment(segment_num)?;
+		let body_size = segment.body_size();
+		for page_num in 1..=segment.num_pages()? {
+			let page_id = PageId::new_unwrap(segment_num, page_num);
+			if !segment.read(page_id.page_num, &mut buf[..body_size])? {
+				continue;
+			}
+			buf[body_size..].fill(0);
+			t.get_page_mut(page_id)?.write(0, &buf)?;
+			num_copied += 1;
+			if num_copied % BATCH_SIZE == 0 {
+				t.commit()?;
+				t = target.transaction()?;
+			}
+		}
+	}
+	t.commit()?;
+	Ok(num_copied)
+}
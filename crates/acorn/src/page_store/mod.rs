@@ -1,11 +1,22 @@
-use std::collections::hash_map::Entry;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::mem;
+use std::path::Path;
 use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use futures::executor::ThreadPool;
 use log::warn;
+use parking_lot::{Mutex, RwLock};
 use thiserror::Error;
 
 #[cfg(test)]
@@ -14,25 +25,97 @@ use mockall::{automock, mock};
 #[cfg(test)]
 use crate::page_store::cache::{MockPageReadGuardApi, MockPageWriteGuardApi};
 
-use crate::files::DatabaseFolder;
+use crate::files::overlay::OverlayFolder;
+use crate::files::sync::SyncPrimitive;
+use crate::files::vfs::VfsFolder;
+use crate::files::wal::MAX_CUSTOM_RECORD_SIZE;
+use crate::files::DatabaseFolderApi;
 use crate::files::FileError;
 use crate::page_store::cache::PageWriteGuardApi;
+use crate::{
+	consts::PAGE_SIZE,
+	files::segment::{is_supported_page_size, SegmentPageSizes, MIN_PAGE_SIZE, PAGE_BODY_SIZE},
+};
 
 pub(crate) use crate::files::PageId;
 use crate::files::TransactionState;
 use crate::files::WalIndex;
+use crate::page_type::PageTypeMismatch;
+use crate::tasks::BackgroundTasks;
+use crate::tasks::BlockingPool;
+use crate::tasks::TaskPanic;
+use crate::trace::event;
 
+pub use cache::CacheStats;
 use cache::{PageCache, PageCacheApi, PageCacheConfig};
+pub use physical::SegmentIoStats;
 use physical::{PhysicalStorage, PhysicalStorageApi, PhysicalStorageConfig};
 
-use wal::{Wal, WalApi, WalConfig};
+pub(crate) use wal::WalSubscription;
+use wal::{NoWal, Wal, WalApi, WalConfig};
+pub use wal::{WalPosition, WalRecord, WalTransaction, WalTransactionStatus};
 
-use self::cache::PageReadGuardApi;
+pub(crate) use self::archive::restore;
+pub(crate) use self::backup::{backup, Canonicalize};
+use self::batch::PageWriteBatch;
+use self::cache::{PageReadGuardApi, TimedLoad};
+pub use self::check::{CheckProblem, CheckReport};
+pub(crate) use self::checkpoint::CheckpointPolicy;
+use self::checkpoint::CheckpointTracker;
+pub use self::checkpoint::{CheckpointStats, CheckpointTrigger};
+pub(crate) use self::custom_records::WalRecordHandlers;
+pub use self::custom_records::{CustomRecord, WalRecordHandler, WalRecordHandlerError};
+pub use self::forecast::{UsageForecast, UsageForecaster};
+pub(crate) use self::listeners::Listeners;
+use self::locks::{AccessLimit, LockManager};
+pub use self::locks::{CancellationToken, LockGraph, LockWait, TransactionLocks};
+pub(crate) use self::maintenance::IdleMaintenancePolicy;
+pub use self::maintenance::MaintenanceStats;
+use self::maintenance::{MaintenanceRun, MaintenanceTracker};
+pub(crate) use self::migrate::migrate_pages;
 use self::physical::ReadOp;
 use self::physical::WriteOp;
+pub(crate) use self::quota::Quota;
+use self::quota::QuotaTracker;
+use self::read_set::ReadSet;
+use self::reads::{InFlightReads, OwnedInFlightRead, StillReading};
+pub(crate) use self::savepoint::SavepointId;
+use self::savepoint::Savepoints;
+pub use self::simulation::{CacheSimulator, SimulatedCacheStats};
+use self::spill::SpillFile;
+pub(crate) use self::stats::AccessStats;
+pub use self::stats::Stats;
+use self::stats::{AccessCounters, TransactionCounters};
+pub(crate) use self::throttle::WriteThrottle;
+use self::throttle::{ThrottleLimit, Throttler};
+pub(crate) use self::unlogged::UnloggedSegments;
+pub(crate) use self::versions::VersionRetention;
+use self::versions::VersionStore;
 
+mod archive;
+mod backup;
+mod batch;
 mod cache;
+mod check;
+mod checkpoint;
+mod custom_records;
+mod forecast;
+mod latch;
+mod listeners;
+mod locks;
+mod maintenance;
+mod migrate;
 mod physical;
+mod quota;
+mod read_set;
+mod reads;
+mod savepoint;
+mod simulation;
+mod spill;
+mod stats;
+mod throttle;
+mod unlogged;
+mod versions;
 mod wal;
 
 #[derive(Debug, Error)]
@@ -43,6 +126,112 @@ pub(crate) enum StorageError {
 	#[error("The maximum number of in-flight transactions has been reached")]
 	TransactionLimitReached,
 
+	#[error("Tried to access {len} bytes at offset {offset}, which is out of page bounds")]
+	PageOutOfBounds { offset: usize, len: usize },
+
+	#[error("Page {0} is corrupted; its checksum does not match its contents")]
+	ChecksumMismatch(PageId),
+
+	#[error("Page {0} was modified by another transaction after it was read, so the transaction has to be aborted")]
+	ReadConflict(PageId),
+
+	#[error("Page {0} was modified by another transaction since the snapshot the transaction reads from, so the transaction has to be aborted")]
+	WriteConflict(PageId),
+
+	#[error("Segment {0} is frozen, so its pages can't be modified")]
+	FrozenSegment(u32),
+
+	#[error("Segment {0} can't be frozen while transactions are writing to it")]
+	SegmentInUse(u32),
+
+	#[error("Writing to segment {segment_num} would exceed the quota of {max_segments} segments")]
+	QuotaExceeded {
+		segment_num: u32,
+		max_segments: usize,
+	},
+
+	#[error("Writes are throttled because {0}, and it didn't catch up in time")]
+	Busy(ThrottleLimit),
+
+	#[error("Invalid configuration: {0}")]
+	InvalidConfig(String),
+
+	#[error("Segment {segment_num} is missing, even though the WAL has writes to {num_pages} of its pages")]
+	MissingSegment { segment_num: u32, num_pages: usize },
+
+	#[error("Verifying the pages written by recovery found {} problems", .0.problems.len())]
+	RecoveryVerificationFailed(CheckReport),
+
+	#[error("The state as of commit {0} is not retained")]
+	SnapshotUnavailable(u64),
+
+	#[error("The WAL at position {0} was already deleted by a checkpoint")]
+	WalPositionUnavailable(WalPosition),
+
+	#[error("The database has no WAL")]
+	NoWal,
+
+	#[error("WAL records can only be applied to a follower")]
+	NotAFollower,
+
+	#[error("Tried to write to a page in a read-only transaction")]
+	ReadOnlyTransaction,
+
+	#[error("Savepoint {0} does not exist, or was released or rolled back past")]
+	UnknownSavepoint(u64),
+
+	#[error("Transaction {transaction_id} would deadlock waiting for page {page_id}, and has to be aborted. Locks:\n{lock_graph}")]
+	Deadlock {
+		transaction_id: u64,
+		page_id: PageId,
+		lock_graph: LockGraph,
+	},
+
+	#[error("Transaction {transaction_id} timed out waiting for page {page_id}")]
+	TimedOut {
+		transaction_id: u64,
+		page_id: PageId,
+	},
+
+	#[error("Transaction {transaction_id} was cancelled while waiting for page {page_id}")]
+	Cancelled {
+		transaction_id: u64,
+		page_id: PageId,
+	},
+
+	#[error("The WAL archive is missing generation {0}")]
+	IncompleteWalArchive(u64),
+
+	#[error("Transaction {0} did not commit in the archived WAL")]
+	RestorePointNotFound(u64),
+
+	#[error("{0}, so the database no longer accepts transactions")]
+	TaskPanicked(TaskPanic),
+
+	#[error("{0}")]
+	PageTypeMismatch(PageTypeMismatch),
+
+	#[error("No handler is registered for WAL records of type {0}")]
+	UnknownWalRecordType(u16),
+
+	#[error("The handler of WAL records of type {record_type} failed: {source}")]
+	WalRecordHandler {
+		record_type: u16,
+		#[source]
+		source: WalRecordHandlerError,
+	},
+
+	#[error("A custom WAL record of {len} bytes exceeds the maximum of {max} bytes")]
+	CustomRecordTooLarge { len: usize, max: usize },
+
+	#[error(
+		"The transaction would hold {size} bytes of pages, more than the maximum of {max} bytes"
+	)]
+	TransactionTooLarge { size: usize, max: usize },
+
+	#[error("The database wasn't closed cleanly, so its page size can't be migrated; open and close it with the version of acorn that created it first")]
+	MigrationNeedsRecovery,
+
 	#[error(transparent)]
 	File(#[from] FileError),
 }
@@ -52,6 +241,228 @@ pub(crate) struct PageStorageConfig {
 	pub physical_storage: PhysicalStorageConfig,
 	pub page_cache: PageCacheConfig,
 	pub wal: WalConfig,
+	pub checkpoint: CheckpointPolicy,
+	pub idle_maintenance: IdleMaintenancePolicy,
+	pub version_retention: VersionRetention,
+	/// Whether opening storage recreates segments that the WAL has writes to,
+	/// but that are missing, instead of failing.
+	pub recreate_missing_segments: bool,
+	/// Whether recovery reads back the pages it wrote, and fails if any of
+	/// them is corrupted.
+	pub verify_after_recovery: bool,
+	pub quota: Quota,
+	pub segment_page_sizes: SegmentPageSizes,
+	pub write_throttle: WriteThrottle,
+	pub unlogged_segments: UnloggedSegments,
+}
+
+impl PageStorageConfig {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if let Some(page_size) = self
+			.segment_page_sizes
+			.page_sizes()
+			.find(|page_size| !is_supported_page_size(*page_size))
+		{
+			return Err(StorageError::InvalidConfig(format!(
+				"Segments can't have pages of {page_size} bytes; page sizes must be powers of two from {MIN_PAGE_SIZE} to {PAGE_SIZE} bytes"
+			)));
+		}
+		self.physical_storage.validate()?;
+		self.quota.validate()?;
+		self.write_throttle.validate()?;
+		self.wal.validate()?;
+		self.page_cache.validate()
+	}
+}
+
+/// A problem that was found while opening a database, but that didn't keep
+/// it from being opened. See [`Database::open_with_report`].
+///
+/// [`Database::open_with_report`]: crate::Database::open_with_report
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OpenWarning {
+	/// The WAL is larger than `threshold`, which suggests that checkpoints
+	/// don't keep up with the writes.
+	LargeWal { size: usize, threshold: usize },
+	/// A WAL generation uses optional format features that this version of
+	/// acorn doesn't know, and ignores.
+	UnknownWalFeatures { generation: u64, features: u16 },
+	/// The last write to a WAL generation was interrupted, and the
+	/// incomplete items at its end were discarded.
+	TornWalTail { generation: u64, len: u64 },
+	/// The WAL was corrupted, and was cut off before the corruption, see
+	/// [`DatabaseBuilder::salvage_wal`].
+	///
+	/// [`DatabaseBuilder::salvage_wal`]: crate::DatabaseBuilder::salvage_wal
+	SalvagedWal {
+		generation: u64,
+		offset: u64,
+		len: u64,
+		dropped_generations: Vec<u64>,
+	},
+	/// A segment that the WAL has writes to is missing, and is recreated
+	/// from them, see [`DatabaseBuilder::recreate_missing_segments`].
+	///
+	/// [`DatabaseBuilder::recreate_missing_segments`]: crate::DatabaseBuilder::recreate_missing_segments
+	MissingSegment { segment_num: u32, num_pages: usize },
+	/// The database wasn't closed with [`Database::close`] the last time it
+	/// was opened for writing, e.g. because the process crashed, so it was
+	/// recovered from the WAL.
+	///
+	/// [`Database::close`]: crate::Database::close
+	UncleanShutdown,
+	/// The segments from `first` to `last` that exist have pages of
+	/// `page_size` bytes instead of the default size, see
+	/// [`DatabaseBuilder::segment_page_size`].
+	///
+	/// [`DatabaseBuilder::segment_page_size`]: crate::DatabaseBuilder::segment_page_size
+	UnusualPageSize {
+		first: u32,
+		last: u32,
+		page_size: usize,
+	},
+}
+
+impl fmt::Display for OpenWarning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::LargeWal { size, threshold } => write!(
+				f,
+				"The WAL is {size} bytes large, which exceeds the warning threshold of {threshold} bytes"
+			),
+			Self::UnknownWalFeatures {
+				generation,
+				features,
+			} => write!(
+				f,
+				"WAL generation {generation} uses optional format features {features:#06x}, which will be ignored"
+			),
+			Self::TornWalTail { generation, len } => write!(
+				f,
+				"The last write to WAL generation {generation} was interrupted; {len} bytes of incomplete items were discarded"
+			),
+			Self::SalvagedWal {
+				generation,
+				offset,
+				len,
+				dropped_generations,
+			} => write!(
+				f,
+				"WAL generation {generation} is corrupted at offset {offset}; {len} bytes of items after it and {} later generations were discarded, so the transactions that committed there are lost",
+				dropped_generations.len()
+			),
+			Self::MissingSegment {
+				segment_num,
+				num_pages,
+			} => write!(
+				f,
+				"Segment {segment_num} is missing, even though the WAL has writes to {num_pages} of its pages; it will be recreated, but only those pages can be restored"
+			),
+			Self::UncleanShutdown => write!(
+				f,
+				"The database wasn't closed cleanly the last time it was open, and was recovered from the WAL"
+			),
+			Self::UnusualPageSize {
+				first,
+				last,
+				page_size,
+			} => write!(
+				f,
+				"Segments {first} to {last} have pages of {page_size} bytes instead of the default of {PAGE_SIZE} bytes"
+			),
+		}
+	}
+}
+
+/// What was found while opening a database, see
+/// [`Database::open_with_report`](crate::Database::open_with_report).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OpenReport {
+	pub warnings: Vec<OpenWarning>,
+	/// How writes to the files of the database are made durable, or `None`
+	/// if they aren't stored in the local file system or the database was
+	/// opened read-only.
+	pub sync_primitive: Option<SyncPrimitive>,
+}
+
+impl OpenReport {
+	pub(crate) fn log(&self) {
+		for warning in &self.warnings {
+			warn!("{warning}");
+		}
+	}
+}
+
+/// The approximate heap memory used by the storage engine, in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+	/// The memory of the cached pages.
+	pub page_cache: usize,
+	/// The maps that keep track of the cached and dirty pages, and of which
+	/// pages to evict next.
+	pub cache_index: usize,
+	/// Buffered WAL items that are not written to the WAL files yet.
+	pub wal_buffers: usize,
+	/// The original content of the pages that running transactions modified.
+	pub write_sets: usize,
+	/// Replaced page versions that are kept for snapshots.
+	pub page_versions: usize,
+}
+
+impl MemoryUsage {
+	pub fn total(&self) -> usize {
+		self.page_cache + self.cache_index + self.wal_buffers + self.write_sets + self.page_versions
+	}
+}
+
+/// What a transaction holds on to until it completes, in bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionSize {
+	/// The pages that the transaction keeps locked in the page cache.
+	pub cached: usize,
+	/// The original content of the parts of pages that the transaction
+	/// modified, which it needs to undo its writes.
+	pub write_set: usize,
+	/// The modified pages that were spilled to a temporary file, so that they
+	/// don't take up the page cache, see
+	/// [`DatabaseBuilder::max_transaction_pages`](crate::DatabaseBuilder::max_transaction_pages).
+	pub spilled: usize,
+}
+
+impl TransactionSize {
+	pub fn total(&self) -> usize {
+		self.cached + self.write_set + self.spilled
+	}
+}
+
+/// How urgently the embedding application needs memory to be given back, e.g.
+/// in response to a memory pressure signal from the operating system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MemoryPressure {
+	/// Release about half of the cached pages that can be released without I/O.
+	Moderate,
+
+	/// Flush the cache, and release as many cached pages as possible.
+	Critical,
+}
+
+/// A summary of what recovery would do, without any of it being applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RecoveryReport {
+	/// The number of WAL items that were read and validated.
+	pub num_items: usize,
+	pub num_redo_writes: usize,
+	/// The pages that would be redone, in ascending order.
+	pub redo_pages: BTreeSet<PageId>,
+	/// The transactions that would be rolled back, in ascending order.
+	pub undo_transactions: Vec<u64>,
+	pub num_undo_writes: usize,
+	/// The number of custom records that would be redone, see
+	/// [`WalRecordHandler`].
+	pub num_redo_records: usize,
+	pub num_undo_records: usize,
 }
 
 pub(crate) trait ReadPage {
@@ -80,12 +491,40 @@ impl<T: WritePage> WritePage for &mut T {
 	}
 }
 
+fn check_page_bounds(offset: usize, len: usize) -> Result<(), StorageError> {
+	if offset
+		.checked_add(len)
+		.is_some_and(|end| end <= PAGE_BODY_SIZE)
+	{
+		Ok(())
+	} else {
+		Err(StorageError::PageOutOfBounds { offset, len })
+	}
+}
+
+/// Checks that a write fits into a page that can store `body_size` bytes.
+/// The rest of such a page is always zero, so zeroes can still be written to
+/// it.
+fn check_write_bounds(offset: usize, buf: &[u8], body_size: usize) -> Result<(), StorageError> {
+	check_page_bounds(offset, buf.len())?;
+	let stored = body_size.saturating_sub(offset).min(buf.len());
+	if buf[stored..].iter().any(|byte| *byte != 0) {
+		return Err(StorageError::PageOutOfBounds {
+			offset,
+			len: buf.len(),
+		});
+	}
+	Ok(())
+}
+
 enum WriteablePageGuard<'t, 'a, PC>
 where
 	PC: PageCacheApi + 't,
 {
 	Shared(PC::ReadGuard<'t>),
-	Exclusive(&'a PC::WriteGuard<'t>),
+	Exclusive(&'a PC::WriteGuard),
+	Image(Arc<[u8]>),
+	Copy(Box<[u8]>),
 }
 
 pub(crate) struct Page<'t, 'a, PC>
@@ -100,51 +539,64 @@ where
 	PC: PageCacheApi + 't,
 {
 	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
+		check_page_bounds(offset, buf.len())?;
 		match &self.guard {
 			WriteablePageGuard::Shared(guard) => guard.read(offset, buf),
 			WriteablePageGuard::Exclusive(guard) => guard.read(offset, buf),
+			WriteablePageGuard::Image(image) => {
+				buf.copy_from_slice(&image[offset..offset + buf.len()]);
+			}
+			WriteablePageGuard::Copy(image) => {
+				buf.copy_from_slice(&image[offset..offset + buf.len()]);
+			}
 		}
 		Ok(())
 	}
 }
 
-pub(crate) struct PageMut<'t, 'a, PC, W>
+pub(crate) struct PageMut<'a, PC>
 where
-	PC: PageCacheApi + 't,
+	PC: PageCacheApi + 'a,
 {
-	page_id: PageId,
-	transaction_id: u64,
-	guard: &'a mut PC::WriteGuard<'t>,
-	wal: &'a W,
+	guard: &'a mut PC::WriteGuard,
+	batch: &'a mut PageWriteBatch,
+	savepoint_batch: Option<&'a mut PageWriteBatch>,
+	write_set_size: &'a mut usize,
+	write_set_memory: &'a AtomicUsize,
+	/// The number of bytes that the segment of the page stores of it.
+	body_size: usize,
 }
 
-impl<'t, 'a, PC, W> ReadPage for PageMut<'t, 'a, PC, W>
+impl<'a, PC> ReadPage for PageMut<'a, PC>
 where
 	PC: PageCacheApi + 'a,
 {
 	fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), StorageError> {
+		check_page_bounds(offset, buf.len())?;
 		self.guard.read(offset, buf);
 		Ok(())
 	}
 }
 
-impl<'t, 'a, PC, W> WritePage for PageMut<'t, 'a, PC, W>
+impl<'a, PC> WritePage for PageMut<'a, PC>
 where
 	PC: PageCacheApi + 'a,
-	W: WalApi,
 {
 	fn write(&mut self, offset: usize, buf: &[u8]) -> Result<(), StorageError> {
+		check_write_bounds(offset, buf, self.body_size)?;
 		let mut from: Box<[u8]> = vec![0; buf.len()].into();
 		self.guard.read(offset, &mut from);
+		let mut grown = self.batch.record(offset, &from);
+		if let Some(savepoint_batch) = self.savepoint_batch.as_deref_mut() {
+			grown += savepoint_batch.record(offset, &from);
+		}
+		*self.write_set_size += grown;
+		self.write_set_memory.fetch_add(grown, Ordering::Relaxed);
 
-		let wal_index = self.wal.log_write(wal::WriteLog {
-			transaction_id: self.transaction_id,
-			page_id: self.page_id,
-			offset: u16::try_from(offset).expect("Write offset must be 16-bit!"),
-			from: &from,
-			to: buf,
-		})?;
-		self.guard.write(offset, buf, wal_index);
+		// The write is only logged on commit, so the page is not marked as
+		// dirty yet. This is fine, since the transaction holds the page lock
+		// until then, so the cache cannot flush it in the meantime.
+		self.guard.body_mut()[offset..offset + buf.len()].copy_from_slice(buf);
 		Ok(())
 	}
 }
@@ -171,55 +623,415 @@ mock! {
 	}
 }
 
-pub(crate) struct Transaction<'t, PS = PhysicalStorage, PC = PageCache, W = Wal>
+pub(crate) struct Transaction<PS = PhysicalStorage, PC = PageCache, W = Wal>
 where
 	PS: PhysicalStorageApi,
 	PC: PageCacheApi,
 	W: WalApi,
 {
 	id: u64,
-	locks: HashMap<PageId, PC::WriteGuard<'t>>,
-	storage: &'t PageStorage<PS, PC, W>,
+	locks: HashMap<PageId, PC::WriteGuard>,
+	write_batches: HashMap<PageId, PageWriteBatch>,
+	savepoints: Savepoints,
+	spill: Option<SpillFile>,
+	reads: Option<ReadSet>,
+	/// The contents of the pages the transaction read, as of when it first
+	/// read them, if its reads are repeatable.
+	read_images: Option<Mutex<HashMap<PageId, Arc<[u8]>>>>,
+	/// The sequence number of the commit that pages the transaction didn't
+	/// lock are read as of, if it reads from a snapshot.
+	snapshot_seq: Option<u64>,
+	read_only: bool,
+	storage: Arc<PageStorage<PS, PC, W>>,
 	completed: bool,
+	/// The number of bytes of original page content that the transaction
+	/// recorded, which count towards the memory usage of the storage until
+	/// it is dropped.
+	write_set_size: usize,
+	/// The custom records that are logged on commit.
+	records: Vec<CustomRecord>,
+	/// The number of records that were logged before each savepoint.
+	record_marks: Vec<(SavepointId, usize)>,
+	/// What the page accesses of the transaction are attributed to, see
+	/// [`TransactionApi::tag_accesses`].
+	access_tag: Option<PageId>,
 }
 
-impl<'t, PS, PC, W> Transaction<'t, PS, PC, W>
+impl<PS, PC, W> Transaction<PS, PC, W>
 where
 	PS: PhysicalStorageApi,
 	PC: PageCacheApi,
 	W: WalApi,
 {
-	fn new(id: u64, storage: &'t PageStorage<PS, PC, W>) -> Self {
+	fn new(id: u64, storage: Arc<PageStorage<PS, PC, W>>) -> Self {
 		Self {
 			id,
 			storage,
 			locks: HashMap::new(),
+			write_batches: HashMap::new(),
+			savepoints: Savepoints::default(),
+			spill: None,
+			reads: None,
+			read_images: None,
+			snapshot_seq: None,
+			read_only: false,
 			completed: false,
+			write_set_size: 0,
+			records: Vec::new(),
+			record_marks: Vec::new(),
+			access_tag: None,
+		}
+	}
+
+	/// Makes the transaction keep track of the pages it reads, and fail to
+	/// commit with [`StorageError::ReadConflict`] if another transaction
+	/// modified any of them in the meantime.
+	pub fn with_read_tracking(mut self) -> Self {
+		self.reads = Some(ReadSet::new(self.storage.versions.begin_tracking()));
+		self
+	}
+
+	/// Makes the transaction keep the contents of each page it reads, so that
+	/// reading it again returns the same contents until the transaction
+	/// modifies it. Unlike with [`Transaction::with_snapshot_reads`], pages
+	/// that weren't read yet are read as of when they are first read, so the
+	/// pages may reflect different commits. The kept pages count towards the
+	/// memory of the transaction until it completes.
+	pub fn with_repeatable_reads(mut self) -> Self {
+		self.read_images = Some(Mutex::new(HashMap::new()));
+		self
+	}
+
+	/// Makes the transaction read pages it didn't lock as of the last commit
+	/// before it started, and fail to commit with
+	/// [`StorageError::WriteConflict`] if another transaction committed to any
+	/// of the pages it modified in the meantime.
+	pub fn with_snapshot_reads(mut self) -> Self {
+		self.snapshot_seq = Some(self.storage.versions.begin_tracked_snapshot());
+		self
+	}
+
+	/// Makes the transaction read-only, and read pages as of the commit with
+	/// sequence number `seq`, like [`Self::with_snapshot_reads`] does as of
+	/// when it started. Fails with [`StorageError::SnapshotUnavailable`] if
+	/// that state isn't retained anymore.
+	pub fn with_snapshot_reads_at(mut self, seq: u64) -> Result<Self, StorageError> {
+		if !self.storage.versions.begin_tracked_snapshot_at(seq) {
+			return Err(StorageError::SnapshotUnavailable(seq));
+		}
+		self.read_only = true;
+		self.snapshot_seq = Some(seq);
+		Ok(self)
+	}
+
+	/// Makes the transaction fail with [`StorageError::TimedOut`] instead of
+	/// waiting for a page past `deadline`, whether it is held by another
+	/// transaction, locked in the cache, or being read by another thread.
+	pub fn with_deadline(self, deadline: Instant) -> Self {
+		self.storage.lock_manager.set_deadline(self.id, deadline);
+		self
+	}
+
+	/// Makes the transaction fail with [`StorageError::Cancelled`] instead of
+	/// waiting for a page once `token` is cancelled, like
+	/// [`Self::with_deadline`].
+	pub fn with_cancellation(self, token: CancellationToken) -> Self {
+		self.storage.lock_manager.set_cancellation(self.id, token);
+		self
+	}
+
+	/// Verifies that none of the pages the transaction read were modified
+	/// since. The pages are locked first, so that they also can't be modified
+	/// until the transaction has committed.
+	fn validate_reads(&self) -> Result<(), StorageError> {
+		let Some(reads) = &self.reads else {
+			return Ok(());
+		};
+		for (page_id, seq) in reads.pages() {
+			self.storage.lock_manager.lock(page_id, self.id)?;
+			if self.storage.versions.modified_since(page_id, seq) {
+				return Err(StorageError::ReadConflict(page_id));
+			}
+		}
+		Ok(())
+	}
+
+	/// Verifies that no other transaction committed to the pages the
+	/// transaction modified since its snapshot was taken.
+	fn validate_writes(&self) -> Result<(), StorageError> {
+		let Some(seq) = self.snapshot_seq else {
+			return Ok(());
+		};
+		for page_id in self.write_batches.keys() {
+			if self.storage.versions.modified_since(*page_id, seq) {
+				return Err(StorageError::WriteConflict(*page_id));
+			}
+		}
+		Ok(())
+	}
+
+	fn end_read_tracking(&mut self) {
+		if let Some(reads) = self.reads.take() {
+			self.storage.versions.end_tracking(reads.tracking_seq);
 		}
+		if let Some(seq) = self.snapshot_seq.take() {
+			self.storage.versions.end_snapshot(seq);
+			self.storage.versions.end_tracking(seq);
+		}
+	}
+
+	/// Attributes an access to a page to the tag of the transaction, if it has
+	/// one. The page counts as read from storage if it is neither locked by
+	/// the transaction nor cached.
+	fn record_access(&self, page_id: PageId) {
+		let Some(tag) = self.access_tag else {
+			return;
+		};
+		let from_storage =
+			!self.locks.contains_key(&page_id) && !self.storage.cache.has_page(page_id);
+		self.storage.access_counters.access(tag, from_storage);
 	}
 
 	fn acquire_lock(&mut self, page_id: PageId) -> Result<(), StorageError> {
-		if let Entry::Vacant(e) = self.locks.entry(page_id) {
-			let guard = self.storage.write_guard(page_id)?;
-			e.insert(guard);
+		if self.read_only {
+			return Err(StorageError::ReadOnlyTransaction);
+		}
+		if self.locks.contains_key(&page_id) {
+			return Ok(());
+		}
+		let spilled = self
+			.spill
+			.as_ref()
+			.is_some_and(|spill| spill.contains(page_id));
+		if let Some(max) = self.storage.max_transaction_size.filter(|_| !spilled) {
+			let size = self.size().total() + PAGE_BODY_SIZE;
+			if size > max {
+				return Err(StorageError::TransactionTooLarge { size, max });
+			}
+		}
+		// Only throttled before locking the first page, since the flushes and
+		// checkpoints it waits for would wait for the pages it holds.
+		if self.locks.is_empty() {
+			self.storage.throttle_writes()?;
+		}
+
+		self.storage.lock_manager.lock(page_id, self.id)?;
+		// Checking only after locking the page makes sure that a segment is
+		// either frozen before, or found to be in use while freezing it.
+		if self.storage.is_frozen(page_id) {
+			self.storage.lock_manager.release(page_id, self.id);
+			return Err(StorageError::FrozenSegment(page_id.segment_num));
+		}
+		if let Err(err) = self.storage.claim_segment(page_id.segment_num) {
+			self.storage.lock_manager.release(page_id, self.id);
+			return Err(err);
+		}
+		let mut guard = self.storage.write_guard(page_id, Some(self.id))?;
+		self.storage
+			.versions
+			.record_pending(page_id, self.id, guard.body());
+		if let Some(spill) = self.spill.as_mut().filter(|spill| spill.contains(page_id)) {
+			let mut image = vec![0; PAGE_BODY_SIZE];
+			spill.take(page_id, &mut image)?;
+			guard.body_mut().copy_from_slice(&image);
+		}
+		self.locks.insert(page_id, guard);
+		self.spill_pages(page_id)
+	}
+
+	fn size(&self) -> TransactionSize {
+		TransactionSize {
+			cached: self.locks.len() * PAGE_BODY_SIZE,
+			write_set: self.write_set_size,
+			spilled: self
+				.spill
+				.as_ref()
+				.map_or(0, |spill| spill.num_spilled() * PAGE_BODY_SIZE),
+		}
+	}
+
+	/// Releases a page that the transaction locked, but did not modify.
+	fn release_lock(&mut self, page_id: PageId) -> Result<(), StorageError> {
+		debug_assert!(!self.write_batches.contains_key(&page_id));
+		self.locks.remove(&page_id);
+		if let Some(spill) = self.spill.as_mut().filter(|spill| spill.contains(page_id)) {
+			// The cache already has the original content of spilled pages.
+			let mut image = vec![0; PAGE_BODY_SIZE];
+			spill.take(page_id, &mut image)?;
+		}
+		self.storage.versions.release_pending(page_id, self.id);
+		self.storage.lock_manager.release(page_id, self.id);
+		Ok(())
+	}
+
+	/// Moves locked pages other than `keep` out of the cache until the
+	/// transaction is within its page limit. The modified images of those
+	/// pages are kept in the spill file, and the cache gets their original
+	/// content back. The transaction keeps holding the pages in the lock
+	/// manager.
+	fn spill_pages(&mut self, keep: PageId) -> Result<(), StorageError> {
+		while self.locks.len() > self.storage.transaction_page_limit {
+			let Some(page_id) = self.locks.keys().copied().find(|id| *id != keep) else {
+				break;
+			};
+			if self.spill.is_none() {
+				self.spill = Some(SpillFile::new()?);
+			}
+			let spill = self.spill.as_mut().unwrap();
+
+			let guard = self.locks.get_mut(&page_id).unwrap();
+			spill.store(page_id, guard.body())?;
+			for (offset, from) in self.write_batches[&page_id].runs() {
+				guard.body_mut()[offset..offset + from.len()].copy_from_slice(from);
+			}
+			self.locks.remove(&page_id);
+		}
+		Ok(())
+	}
+
+	/// Logs the writes of the transaction together with its commit. The
+	/// changes are collected first, so that they can be appended to the WAL
+	/// in one batch, which only holds up other committing transactions once.
+	fn log_transaction(&mut self) -> Result<(), StorageError> {
+		let mut changes = Vec::new();
+		let mut image = vec![0; PAGE_BODY_SIZE];
+		for (page_id, batch) in &self.write_batches {
+			let guard = self.locks.get(page_id);
+			if guard.is_none() {
+				self.spill.as_ref().unwrap().load(*page_id, &mut image)?;
+			}
+			let diffs = batch.diffs(|offset, buf| match guard {
+				Some(guard) => guard.read(offset, buf),
+				None => buf.copy_from_slice(&image[offset..offset + buf.len()]),
+			});
+			changes.extend(diffs.into_iter().map(|diff| (*page_id, diff)));
+		}
+		let (unlogged, changes): (Vec<_>, Vec<_>) = changes
+			.into_iter()
+			.partition(|(page_id, _)| self.storage.unlogged_segments.contains(page_id.segment_num));
+
+		let logs: Vec<_> = changes
+			.iter()
+			.map(|(page_id, diff)| wal::WriteLog {
+				transaction_id: self.id,
+				page_id: *page_id,
+				offset: u16::try_from(diff.offset).expect("Write offset must be 16-bit!"),
+				from: &diff.from,
+				to: &diff.to,
+			})
+			.collect();
+		let records: Vec<_> = self
+			.records
+			.iter()
+			.map(|record| wal::CustomLog {
+				transaction_id: self.id,
+				record_type: record.record_type,
+				version: record.version,
+				data: &record.data,
+			})
+			.collect();
+		let indices = self.storage.wal.log_transaction(
+			&logs,
+			&records,
+			wal::CommitLog {
+				transaction_id: self.id,
+			},
+		)?;
+
+		let unlogged_writes = unlogged.iter().map(|change| (change, WalIndex::UNLOGGED));
+		for ((page_id, diff), wal_index) in changes.iter().zip(indices).chain(unlogged_writes) {
+			if let Some(guard) = self.locks.get_mut(page_id) {
+				guard.write(diff.offset, &diff.to, wal_index);
+				continue;
+			}
+
+			// Spilled pages are brought back one at a time, so that the
+			// transaction never holds more pages than its limit in the cache.
+			let mut guard = self.storage.write_guard(*page_id, Some(self.id))?;
+			let spill = self.spill.as_mut().unwrap();
+			if spill.contains(*page_id) {
+				spill.take(*page_id, &mut image)?;
+				guard.body_mut().copy_from_slice(&image);
+			}
+			guard.write(diff.offset, &diff.to, wal_index);
+		}
+		self.write_batches.clear();
+		self.records.clear();
+		Ok(())
+	}
+
+	/// Undoes the records that weren't logged yet from `len` on, latest
+	/// first, since their effects may already have been applied.
+	fn undo_records_from(&mut self, len: usize) -> Result<(), StorageError> {
+		while self.records.len() > len {
+			let record = self.records.pop().unwrap();
+			self.storage.record_handlers.undo(&record)?;
 		}
 		Ok(())
 	}
 
+	/// The pages that the transaction wrote to, if it can write and there
+	/// are listeners that are notified of them.
+	fn written_pages(&self) -> Option<BTreeSet<PageId>> {
+		if self.read_only || !self.storage.listeners.has_transaction_listeners() {
+			return None;
+		}
+		Some(self.write_batches.keys().copied().collect())
+	}
+
 	fn undo_impl(&mut self) -> Result<(), StorageError> {
-		self.storage.wal.undo(self.id, |write_op| {
-			let Some(guard) = self.locks.get_mut(&write_op.page_id) else {
-				panic!("An undo operation tried to undo a write to a page that the transaction did not access!");
+		let written_pages = self.written_pages();
+		self.undo_records_from(0)?;
+
+		// Writes that were not logged yet can simply be reverted in the cache.
+		// Pages that are still spilled already have their original content
+		// there.
+		for (page_id, batch) in self.write_batches.drain() {
+			if self
+				.spill
+				.as_ref()
+				.is_some_and(|spill| spill.contains(page_id))
+			{
+				continue;
+			}
+			let mut unlocked_guard;
+			let guard = match self.locks.get_mut(&page_id) {
+				Some(guard) => guard,
+				None => {
+					unlocked_guard = self.storage.write_guard(page_id, Some(self.id))?;
+					&mut unlocked_guard
+				}
 			};
-			guard.write(write_op.offset.into(), write_op.buf, write_op.index);
-			Ok(())
-		})?;
+			for (offset, from) in batch.runs() {
+				guard.body_mut()[offset..offset + from.len()].copy_from_slice(from);
+			}
+		}
+
+		if !self.read_only {
+			self.storage.wal.undo(self.id, |write_op| {
+				if let Some(guard) = self.locks.get_mut(&write_op.page_id) {
+					guard.write(write_op.offset.into(), write_op.buf, write_op.index);
+				} else {
+					let mut guard = self.storage.write_guard(write_op.page_id, Some(self.id))?;
+					guard.write(write_op.offset.into(), write_op.buf, write_op.index);
+				}
+				Ok(())
+			})?;
+		}
+		self.storage.versions.abort(self.id);
+		self.end_read_tracking();
+		self.storage.lock_manager.release_all(self.id);
 		self.storage.transaction_enumerator.end();
+		self.storage.transaction_counters.abort();
+		event!(DEBUG, transaction_id = self.id, "Transaction aborted");
+		if let Some(pages) = written_pages {
+			self.storage.listeners.rolled_back(self.id, &pages);
+		}
 		Ok(())
 	}
 }
 
-impl<'t, PS, PC, W> Drop for Transaction<'t, PS, PC, W>
+impl<PS, PC, W> Drop for Transaction<PS, PC, W>
 where
 	PS: PhysicalStorageApi,
 	PC: PageCacheApi,
@@ -231,6 +1043,9 @@ where
 			self.undo_impl()
 				.expect("A transaction was dropped without being completed, and failed to undo!");
 		}
+		self.storage
+			.write_set_memory
+			.fetch_sub(self.write_set_size, Ordering::Relaxed);
 	}
 }
 
@@ -247,54 +1062,307 @@ pub(crate) trait TransactionApi {
 		Self: 'a;
 
 	fn id(&self) -> u64;
+
+	/// The sequence number of the commit that the transaction reads pages
+	/// as of, if it reads from a snapshot.
+	fn snapshot_seq(&self) -> Option<u64>;
+
+	/// What the transaction holds on to so far, see [`TransactionSize`].
+	fn size(&self) -> TransactionSize;
+
+	/// Attributes the page accesses of the transaction from now on to `tag`,
+	/// or to nothing, and returns the tag they were attributed to before. The
+	/// accesses of each tag are counted in [`PageStorageApi::access_stats`].
+	fn tag_accesses(&mut self, tag: Option<PageId>) -> Option<PageId>;
+
+	/// Counts an operation on the structure identified by `tag`, whose page
+	/// accesses are attributed to the tag, see
+	/// [`TransactionApi::tag_accesses`].
+	fn count_operation(&self, tag: PageId);
+
 	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError>;
 	fn get_page_mut(&mut self, page_id: PageId) -> Result<Self::PageMut<'_>, StorageError>;
+
+	/// Locks all of the pages for writing, in ascending order so that
+	/// structural changes that lock the same pages can't deadlock each other.
+	/// If any of them can't be locked, none of the pages that weren't locked
+	/// before are kept.
+	fn lock_pages(&mut self, page_ids: &[PageId]) -> Result<(), StorageError>;
+
+	/// Starts reading the pages into the cache, so that accessing them later
+	/// doesn't have to wait for storage. This is only a hint; the pages are
+	/// not locked, and may be evicted again before they are accessed.
+	fn prefetch(&self, page_ids: &[PageId]) -> Result<(), StorageError>;
+
+	/// Replaces the content of the page `to` with that of the page `from`.
+	fn copy_page(&mut self, from: PageId, to: PageId) -> Result<(), StorageError>;
+
+	/// Copies the content of the page `from` to the page `to`, and clears
+	/// `from`. Both pages are locked first, and both writes are logged as part
+	/// of the transaction, so after a crash the content is in exactly one of
+	/// them.
+	fn move_page(&mut self, from: PageId, to: PageId) -> Result<(), StorageError>;
+
+	/// Marks the current state of the transaction, so that later writes can
+	/// be reverted with [`TransactionApi::rollback_to`] without aborting the
+	/// whole transaction.
+	fn savepoint(&mut self) -> SavepointId;
+
+	/// Reverts all writes since the savepoint. The savepoint is kept, but
+	/// later savepoints are removed. Pages stay locked.
+	fn rollback_to(&mut self, savepoint: SavepointId) -> Result<(), StorageError>;
+
+	/// Removes the savepoint and all later ones, keeping their writes.
+	fn release(&mut self, savepoint: SavepointId) -> Result<(), StorageError>;
+
+	/// Logs a custom record as part of the transaction, to be redone or
+	/// undone by the handler registered for `record_type` during recovery.
+	/// Records are logged on commit, after the writes of the transaction. If
+	/// the transaction is undone or rolled back past the record instead, the
+	/// handler undoes it right away.
+	fn log_record(
+		&mut self,
+		record_type: u16,
+		version: u16,
+		data: &[u8],
+	) -> Result<(), StorageError>;
+
 	fn commit(self) -> Result<(), StorageError>;
 	fn undo(self) -> Result<(), StorageError>;
 }
 
-impl<'t, PS, PC, W> TransactionApi for Transaction<'t, PS, PC, W>
+impl<PS, PC, W> TransactionApi for Transaction<PS, PC, W>
 where
 	PS: PhysicalStorageApi,
-	PC: PageCacheApi + 't,
-	W: WalApi + 't,
+	PC: PageCacheApi,
+	W: WalApi,
+	PageStorage<PS, PC, W>: Sync,
 {
-	type Page<'a> = Page<'t, 'a, PC> where Self: 'a;
-	type PageMut<'a> = PageMut<'t, 'a, PC, W> where Self: 'a;
+	type Page<'a>
+		= Page<'a, 'a, PC>
+	where
+		Self: 'a;
+	type PageMut<'a>
+		= PageMut<'a, PC>
+	where
+		Self: 'a;
 
 	fn id(&self) -> u64 {
 		self.id
 	}
 
+	fn snapshot_seq(&self) -> Option<u64> {
+		self.snapshot_seq
+	}
+
+	fn size(&self) -> TransactionSize {
+		Transaction::size(self)
+	}
+
+	fn tag_accesses(&mut self, tag: Option<PageId>) -> Option<PageId> {
+		mem::replace(&mut self.access_tag, tag)
+	}
+
+	fn count_operation(&self, tag: PageId) {
+		self.storage.access_counters.operation(tag);
+	}
+
 	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError> {
+		self.record_access(page_id);
 		if let Some(guard) = self.locks.get(&page_id) {
 			Ok(Page {
 				guard: WriteablePageGuard::Exclusive(guard),
 			})
-		} else {
+		} else if let Some(spill) = self.spill.as_ref().filter(|spill| spill.contains(page_id)) {
+			let mut image = vec![0; PAGE_BODY_SIZE];
+			spill.load(page_id, &mut image)?;
+			Ok(Page {
+				guard: WriteablePageGuard::Image(image.into()),
+			})
+		} else if self.storage.is_frozen(page_id) {
+			// Frozen pages can't be modified, so their reads never conflict.
+			self.storage.read_page(page_id, None)
+		} else if let Some(seq) = self.snapshot_seq {
+			self.storage.snapshot_page(page_id, seq)
+		} else if let Some(read_images) = &self.read_images {
+			if let Some(image) = read_images.lock().get(&page_id) {
+				return Ok(Page {
+					guard: WriteablePageGuard::Image(Arc::clone(image)),
+				});
+			}
+			let mut image = vec![0; PAGE_BODY_SIZE];
+			self.storage
+				.read_page_into(page_id, Some(self.id), &mut image)?;
+			let image: Arc<[u8]> = image.into();
+			read_images.lock().insert(page_id, Arc::clone(&image));
 			Ok(Page {
-				guard: WriteablePageGuard::Shared(self.storage.read_guard(page_id)?),
+				guard: WriteablePageGuard::Image(image),
 			})
+		} else {
+			let page = self.storage.read_page(page_id, Some(self.id))?;
+			if let Some(reads) = &self.reads {
+				reads.record(page_id, self.storage.versions.last_commit());
+			}
+			Ok(page)
 		}
 	}
 
 	fn get_page_mut<'a>(&'a mut self, page_id: PageId) -> Result<Self::PageMut<'a>, StorageError> {
+		self.record_access(page_id);
 		self.acquire_lock(page_id)?;
-		let guard: &'a mut PC::WriteGuard<'t> = self.locks.get_mut(&page_id).unwrap();
+		let guard: &'a mut PC::WriteGuard = self.locks.get_mut(&page_id).unwrap();
+		let batch = self.write_batches.entry(page_id).or_default();
+		let savepoint_batch = self.savepoints.batch(page_id);
 		Ok(PageMut {
-			page_id,
-			transaction_id: self.id,
 			guard,
-			wal: &self.storage.wal,
+			batch,
+			savepoint_batch,
+			write_set_size: &mut self.write_set_size,
+			write_set_memory: &self.storage.write_set_memory,
+			body_size: self
+				.storage
+				.segment_page_sizes
+				.body_size(page_id.segment_num),
 		})
 	}
 
-	fn commit(mut self) -> Result<(), StorageError> {
-		self.storage.wal.log_commit(wal::CommitLog {
+	fn lock_pages(&mut self, page_ids: &[PageId]) -> Result<(), StorageError> {
+		let mut page_ids = page_ids.to_vec();
+		page_ids.sort_unstable();
+		page_ids.dedup();
+
+		let mut acquired = Vec::with_capacity(page_ids.len());
+		for page_id in page_ids {
+			let held = self.locks.contains_key(&page_id)
+				|| self
+					.spill
+					.as_ref()
+					.is_some_and(|spill| spill.contains(page_id));
+			if held {
+				continue;
+			}
+			acquired.push(page_id);
+			if let Err(error) = self.acquire_lock(page_id) {
+				for page_id in acquired {
+					self.release_lock(page_id)?;
+				}
+				return Err(error);
+			}
+		}
+		Ok(())
+	}
+
+	fn prefetch(&self, page_ids: &[PageId]) -> Result<(), StorageError> {
+		self.storage.prefetch(page_ids)
+	}
+
+	fn copy_page(&mut self, from: PageId, to: PageId) -> Result<(), StorageError> {
+		if from == to {
+			return Ok(());
+		}
+		let mut image = vec![0; PAGE_BODY_SIZE];
+		self.get_page(from)?.read(0, &mut image)?;
+		self.get_page_mut(to)?.write(0, &image)
+	}
+
+	fn move_page(&mut self, from: PageId, to: PageId) -> Result<(), StorageError> {
+		if from == to {
+			return Ok(());
+		}
+		self.lock_pages(&[PageId::min(from, to), PageId::max(from, to)])?;
+		self.copy_page(from, to)?;
+		self.get_page_mut(from)?.write(0, &[0; PAGE_BODY_SIZE])
+	}
+
+	fn savepoint(&mut self) -> SavepointId {
+		let savepoint = self.savepoints.create();
+		self.record_marks.push((savepoint, self.records.len()));
+		savepoint
+	}
+
+	fn rollback_to(&mut self, savepoint: SavepointId) -> Result<(), StorageError> {
+		let Some(rewound) = self.savepoints.rewind(savepoint) else {
+			return Err(StorageError::UnknownSavepoint(savepoint.get()));
+		};
+		let mark = self
+			.record_marks
+			.iter()
+			.position(|(id, _)| *id == savepoint)
+			.unwrap();
+		self.record_marks.truncate(mark + 1);
+		self.undo_records_from(self.record_marks[mark].1)?;
+		for writes in rewound {
+			for (page_id, batch) in writes {
+				// Locking the page again brings it back if it was spilled.
+				self.acquire_lock(page_id)?;
+				let guard = self.locks.get_mut(&page_id).unwrap();
+				for (offset, from) in batch.runs() {
+					guard.body_mut()[offset..offset + from.len()].copy_from_slice(from);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn release(&mut self, savepoint: SavepointId) -> Result<(), StorageError> {
+		if !self.savepoints.release(savepoint) {
+			return Err(StorageError::UnknownSavepoint(savepoint.get()));
+		}
+		self.record_marks
+			.retain(|(id, _)| id.get() < savepoint.get());
+		Ok(())
+	}
+
+	fn log_record(
+		&mut self,
+		record_type: u16,
+		version: u16,
+		data: &[u8],
+	) -> Result<(), StorageError> {
+		if self.read_only {
+			return Err(StorageError::ReadOnlyTransaction);
+		}
+		if data.len() > MAX_CUSTOM_RECORD_SIZE {
+			return Err(StorageError::CustomRecordTooLarge {
+				len: data.len(),
+				max: MAX_CUSTOM_RECORD_SIZE,
+			});
+		}
+		self.storage.record_handlers.check(record_type)?;
+		self.records.push(CustomRecord {
 			transaction_id: self.id,
-		})?;
+			record_type,
+			version,
+			data: data.to_vec(),
+		});
+		Ok(())
+	}
+
+	#[cfg_attr(
+		feature = "tracing",
+		tracing::instrument(level = "debug", skip_all, fields(transaction_id = self.id))
+	)]
+	fn commit(mut self) -> Result<(), StorageError> {
+		self.validate_reads()?;
+		self.validate_writes()?;
+		let written_pages = self.written_pages();
+		// Read-only transactions have nothing to log, and don't count as a
+		// commit for snapshots and read tracking.
+		if !self.read_only {
+			let storage = Arc::clone(&self.storage);
+			let _commit = storage.commit_gate.read();
+			self.log_transaction()?;
+			storage.versions.commit(self.id);
+		}
+		self.end_read_tracking();
+		self.storage.lock_manager.release_all(self.id);
 		self.storage.transaction_enumerator.end();
+		self.storage.transaction_counters.commit();
 		self.completed = true;
+		event!(DEBUG, "Transaction committed");
+		if let Some(pages) = written_pages {
+			self.storage.listeners.committed(self.id, &pages);
+		}
 		Ok(())
 	}
 
@@ -305,86 +1373,417 @@ where
 	}
 }
 
-#[derive(Debug)]
-struct TransactionEnumerator {
-	next_id: AtomicU64,
-	num_transactions: AtomicU64,
+/// A read-only view of the committed state of the page storage at the time
+/// it was taken. Reading from a snapshot never waits for transactions that
+/// are writing to the same pages.
+pub(crate) struct Snapshot<PS = PhysicalStorage, PC = PageCache, W = Wal> {
+	seq: u64,
+	storage: Arc<PageStorage<PS, PC, W>>,
 }
 
-impl TransactionEnumerator {
-	fn new() -> Self {
-		Self {
-			next_id: AtomicU64::new(0),
-			num_transactions: AtomicU64::new(0),
-		}
-	}
-
-	fn begin(&self) -> Option<u64> {
-		let num_transactions = self.num_transactions.load(Ordering::Acquire);
-		let num_transactions = num_transactions.checked_add(1)?;
-		self.num_transactions
-			.store(num_transactions, Ordering::Release);
-		let id = self.next_id.load(Ordering::Acquire);
-		self.next_id.store(id.wrapping_add(1), Ordering::Release);
-		Some(id)
-	}
-
-	fn end(&self) {
-		let num_transactions = self.num_transactions.load(Ordering::Acquire);
-		self.num_transactions
-			.store(num_transactions.saturating_sub(1), Ordering::Release);
+impl<PS, PC, W> Drop for Snapshot<PS, PC, W> {
+	fn drop(&mut self) {
+		self.storage.versions.end_snapshot(self.seq);
 	}
 }
 
-pub(crate) struct PageStorage<PS = PhysicalStorage, PC = PageCache, W = Wal> {
+#[cfg_attr(test, automock(
+    type Page = MockPage;
+))]
+pub(crate) trait SnapshotApi {
+	type Page<'a>: ReadPage + 'a
+	where
+		Self: 'a;
+
+	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError>;
+
+	/// The sequence number of the last commit that the snapshot sees.
+	fn seq(&self) -> u64;
+}
+
+impl<PS, PC, W> SnapshotApi for Snapshot<PS, PC, W>
+where
+	PS: PhysicalStorageApi,
+	PC: PageCacheApi,
+{
+	type Page<'a>
+		= Page<'a, 'a, PC>
+	where
+		Self: 'a;
+
+	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError> {
+		self.storage.snapshot_page(page_id, self.seq)
+	}
+
+	fn seq(&self) -> u64 {
+		self.seq
+	}
+}
+
+#[derive(Debug)]
+struct TransactionEnumerator {
+	next_id: AtomicU64,
+	num_transactions: AtomicU64,
+	/// When a transaction last began or ended.
+	last_activity: Mutex<Instant>,
+}
+
+impl TransactionEnumerator {
+	fn new() -> Self {
+		Self {
+			next_id: AtomicU64::new(0),
+			num_transactions: AtomicU64::new(0),
+			last_activity: Mutex::new(Instant::now()),
+		}
+	}
+
+	fn begin(&self) -> Option<u64> {
+		let num_transactions = self.num_transactions.load(Ordering::Acquire);
+		let num_transactions = num_transactions.checked_add(1)?;
+		self.num_transactions
+			.store(num_transactions, Ordering::Release);
+		*self.last_activity.lock() = Instant::now();
+		let id = self.next_id.load(Ordering::Acquire);
+		self.next_id.store(id.wrapping_add(1), Ordering::Release);
+		Some(id)
+	}
+
+	fn resume_at(&self, next_id: u64) {
+		self.next_id.store(next_id, Ordering::Release);
+	}
+
+	fn end(&self) {
+		let num_transactions = self.num_transactions.load(Ordering::Acquire);
+		self.num_transactions
+			.store(num_transactions.saturating_sub(1), Ordering::Release);
+		*self.last_activity.lock() = Instant::now();
+	}
+
+	/// How long no transaction has been running, or `None` if one is.
+	fn idle_for(&self) -> Option<Duration> {
+		if self.num_transactions.load(Ordering::Acquire) != 0 {
+			return None;
+		}
+		Some(self.last_activity.lock().elapsed())
+	}
+}
+
+pub(crate) struct PageStorage<PS = PhysicalStorage, PC = PageCache, W = Wal> {
 	physical: Arc<PS>,
-	cache: PC,
+	cache: Arc<PC>,
 	wal: W,
 	transaction_enumerator: TransactionEnumerator,
+	transaction_counters: TransactionCounters,
+	access_counters: AccessCounters,
+	lock_manager: LockManager,
+	in_flight_reads: Arc<InFlightReads>,
+	/// Reads prefetched pages in the background, if set. Otherwise, they are
+	/// read before [`PageStorage::prefetch`] returns.
+	background_prefetch: Option<BackgroundPrefetch>,
+	versions: VersionStore,
+	transaction_page_limit: usize,
+	max_transaction_size: Option<usize>,
+	checkpoint_policy: CheckpointPolicy,
+	checkpoints: CheckpointTracker,
+	listeners: Listeners,
+	idle_maintenance_policy: IdleMaintenancePolicy,
+	maintenance: MaintenanceTracker,
+	/// Held shared while a transaction logs its commit and makes it visible,
+	/// so that no commit is halfway done while it is held exclusively.
+	commit_gate: RwLock<()>,
+	/// The total size of the original page content recorded by running
+	/// transactions.
+	write_set_memory: AtomicUsize,
+	/// The segments whose pages can only be read anymore.
+	frozen_segments: RwLock<HashSet<u32>>,
+	quota: QuotaTracker,
+	segment_page_sizes: SegmentPageSizes,
+	unlogged_segments: UnloggedSegments,
+	throttler: Throttler,
+	verify_after_recovery: bool,
+	/// Undo the custom records of transactions that are undone before they
+	/// are logged.
+	record_handlers: WalRecordHandlers,
 }
 
-impl PageStorage {
+/// Reads the pages into the cache, after their reads were registered.
+type BackgroundPrefetch = Box<dyn Fn(Vec<PageId>, Vec<OwnedInFlightRead>) + Send + Sync>;
+
+pub(crate) type ScratchPageStorage = PageStorage<PhysicalStorage, PageCache, NoWal>;
+
+pub(crate) type InMemoryPageStorage = PageStorage<
+	PhysicalStorage<OverlayFolder>,
+	PageCache<PhysicalStorage<OverlayFolder>>,
+	Wal<OverlayFolder>,
+>;
+
+pub(crate) type VfsPageStorage =
+	PageStorage<PhysicalStorage<VfsFolder>, PageCache<PhysicalStorage<VfsFolder>>, Wal<VfsFolder>>;
+
+impl<DF> PageStorage<PhysicalStorage<DF>, PageCache<PhysicalStorage<DF>>, Wal<DF>>
+where
+	DF: DatabaseFolderApi + Send + Sync + 'static,
+{
 	pub fn create(
-		folder: Arc<DatabaseFolder>,
+		folder: Arc<DF>,
 		thread_pool: Arc<ThreadPool>,
 		config: &PageStorageConfig,
-	) -> Result<Self, StorageError> {
-		let physical_storage = Arc::new(PhysicalStorage::new(
-			Arc::clone(&folder),
-			&config.physical_storage,
-		));
-		Ok(Self::new(
-			Arc::clone(&physical_storage),
-			PageCache::new(
-				&config.page_cache,
-				Arc::clone(&physical_storage),
-				Arc::clone(&thread_pool),
-			),
-			Wal::create(Arc::clone(&folder), thread_pool, &config.wal)?,
-		))
+	) -> Result<Arc<Self>, StorageError> {
+		let wal = Wal::create(Arc::clone(&folder), Arc::clone(&thread_pool), &config.wal)?;
+		Ok(Arc::new(Self::assemble(folder, thread_pool, config, wal)))
 	}
 
 	pub fn open(
-		folder: Arc<DatabaseFolder>,
+		folder: Arc<DF>,
 		thread_pool: Arc<ThreadPool>,
 		config: &PageStorageConfig,
-	) -> Result<Self, StorageError> {
-		let physical_storage = Arc::new(PhysicalStorage::new(
-			Arc::clone(&folder),
-			&config.physical_storage,
-		));
-		Ok(Self::new(
-			Arc::clone(&physical_storage),
-			PageCache::new(
-				&config.page_cache,
-				Arc::clone(&physical_storage),
-				Arc::clone(&thread_pool),
-			),
-			Wal::open(Arc::clone(&folder), thread_pool, &config.wal)?,
+	) -> Result<Arc<Self>, StorageError> {
+		let (storage, report) = Self::open_with_report(folder, thread_pool, config)?;
+		report.log();
+		Ok(storage)
+	}
+
+	/// Opens the storage in `folder` and recovers it, or creates it if the
+	/// folder doesn't hold a WAL yet.
+	pub fn open_or_create(
+		folder: Arc<DF>,
+		thread_pool: Arc<ThreadPool>,
+		config: &PageStorageConfig,
+	) -> Result<(Arc<Self>, OpenReport), StorageError> {
+		// Checking for WAL files by opening them would already cut off their
+		// torn tails, which then wouldn't be reported.
+		let wal = Wal::open(Arc::clone(&folder), Arc::clone(&thread_pool), &config.wal)?;
+		if !wal.is_initialized() {
+			mem::drop(wal);
+			let storage = Self::create(folder, thread_pool, config)?;
+			storage.clear_unlogged_segments()?;
+			return Ok((storage, OpenReport::default()));
+		}
+		let (storage, report) = Self::open_wal(folder, thread_pool, config, wal)?;
+		storage.recover()?;
+		storage.clear_unlogged_segments()?;
+		Ok((storage, report))
+	}
+
+	pub fn open_with_report(
+		folder: Arc<DF>,
+		thread_pool: Arc<ThreadPool>,
+		config: &PageStorageConfig,
+	) -> Result<(Arc<Self>, OpenReport), StorageError> {
+		let wal = Wal::open(Arc::clone(&folder), Arc::clone(&thread_pool), &config.wal)?;
+		Self::open_wal(folder, thread_pool, config, wal)
+	}
+
+	fn open_wal(
+		folder: Arc<DF>,
+		thread_pool: Arc<ThreadPool>,
+		config: &PageStorageConfig,
+		wal: Wal<DF>,
+	) -> Result<(Arc<Self>, OpenReport), StorageError> {
+		let storage = Self::assemble(Arc::clone(&folder), thread_pool, config, wal);
+		let mut report = OpenReport {
+			warnings: storage.wal.open_warnings(),
+			sync_primitive: folder.sync_primitive()?,
+		};
+		report
+			.warnings
+			.extend(storage.check_segments(&*folder, config)?);
+		Ok((Arc::new(storage), report))
+	}
+
+	/// Clears the unlogged segments, which only ever hold data of the session
+	/// that wrote it. This happens after recovery, in case the WAL still has
+	/// writes to them from before they were unlogged.
+	fn clear_unlogged_segments(&self) -> Result<(), StorageError> {
+		if self.unlogged_segments.is_empty() {
+			return Ok(());
+		}
+		for page_id in self.cache.cached_pages() {
+			if self.unlogged_segments.contains(page_id.segment_num) {
+				self.cache.scrap(page_id);
+			}
+		}
+		for segment_num in self.physical.segment_nums()? {
+			if self.unlogged_segments.contains(segment_num) {
+				self.physical.clear_segment(segment_num)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Checks that all segments that the WAL has writes to still exist.
+	/// Otherwise, they would silently be recreated empty on first access,
+	/// losing all pages that weren't modified since the last checkpoint.
+	fn check_segments(
+		&self,
+		folder: &DF,
+		config: &PageStorageConfig,
+	) -> Result<Vec<OpenWarning>, StorageError> {
+		let recovery = self.wal.dry_run_recovery()?;
+		let mut referenced: BTreeMap<u32, usize> = BTreeMap::new();
+		for page_id in &recovery.redo_pages {
+			*referenced.entry(page_id.segment_num).or_default() += 1;
+		}
+		let present = folder.segment_nums()?;
+
+		let mut warnings = self.page_size_warnings(&present);
+		for (segment_num, num_pages) in referenced {
+			if present.binary_search(&segment_num).is_ok() {
+				continue;
+			}
+			if !config.recreate_missing_segments {
+				return Err(StorageError::MissingSegment {
+					segment_num,
+					num_pages,
+				});
+			}
+			warnings.push(OpenWarning::MissingSegment {
+				segment_num,
+				num_pages,
+			});
+		}
+		Ok(warnings)
+	}
+
+	/// Warns about the segments that don't have pages of the default size,
+	/// with one warning for each run of them with the same page size.
+	fn page_size_warnings(&self, segment_nums: &[u32]) -> Vec<OpenWarning> {
+		let mut warnings: Vec<OpenWarning> = Vec::new();
+		for &segment_num in segment_nums {
+			let page_size = self.segment_page_sizes.page_size(segment_num);
+			if page_size == PAGE_SIZE {
+				continue;
+			}
+			if let Some(OpenWarning::UnusualPageSize {
+				last,
+				page_size: last_page_size,
+				..
+			}) = warnings.last_mut()
+			{
+				if *last_page_size == page_size && *last + 1 == segment_num {
+					*last = segment_num;
+					continue;
+				}
+			}
+			warnings.push(OpenWarning::UnusualPageSize {
+				first: segment_num,
+				last: segment_num,
+				page_size,
+			});
+		}
+		warnings
+	}
+
+	/// The position of the next item that is logged to the WAL.
+	pub fn wal_position(&self) -> Result<WalPosition, StorageError> {
+		Ok(self.wal.position()?.into())
+	}
+
+	/// Streams the transactions that commit at or after `from` from the WAL.
+	pub fn wal_stream(&self, from: WalPosition) -> Result<WalSubscription<DF>, StorageError> {
+		self.wal.subscribe(from.index())
+	}
+
+	/// Summarizes the transactions that have items in the WAL.
+	pub fn wal_transactions(&self) -> Result<Vec<WalTransaction>, StorageError> {
+		self.wal.transactions()
+	}
+
+	/// Deletes the WAL generations that are no longer needed, without a
+	/// checkpoint. Returns the number of bytes that were deleted.
+	pub fn compact_wal(&self) -> Result<usize, StorageError> {
+		self.wal.compact()
+	}
+}
+
+impl<DF> PageStorage<PhysicalStorage<DF>, PageCache<PhysicalStorage<DF>>, NoWal>
+where
+	DF: DatabaseFolderApi + Send + Sync + 'static,
+{
+	/// Creates page storage without a WAL, for data that doesn't need to
+	/// survive a crash. Transactions can still be undone.
+	pub fn create_scratch(
+		folder: Arc<DF>,
+		thread_pool: Arc<ThreadPool>,
+		config: &PageStorageConfig,
+	) -> Arc<Self> {
+		Arc::new(Self::assemble(
+			folder,
+			thread_pool,
+			config,
+			NoWal::default(),
 		))
 	}
 }
 
+impl<DF, W> PageStorage<PhysicalStorage<DF>, PageCache<PhysicalStorage<DF>>, W>
+where
+	DF: DatabaseFolderApi + Send + Sync + 'static,
+{
+	/// Builds page storage on top of the folder, so that every way of
+	/// creating or opening it wires its parts up the same way: the physical
+	/// storage first, then the page cache that writes back to it, and the
+	/// WAL that was opened on the same folder.
+	fn assemble(
+		folder: Arc<DF>,
+		thread_pool: Arc<ThreadPool>,
+		config: &PageStorageConfig,
+		wal: W,
+	) -> Self {
+		let physical_storage = Arc::new(PhysicalStorage::new(folder, &config.physical_storage));
+		Self::new(
+			Arc::clone(&physical_storage),
+			PageCache::new(&config.page_cache, physical_storage, thread_pool),
+			wal,
+		)
+		.with_config(config)
+		.with_background_prefetch()
+	}
+
+	fn with_background_prefetch(mut self) -> Self {
+		let physical = Arc::clone(&self.physical);
+		let cache = Arc::downgrade(&self.cache);
+		self.background_prefetch = Some(Box::new(move |page_ids, reads| {
+			let Some(spawner) = cache.upgrade() else {
+				return;
+			};
+			let physical = Arc::clone(&physical);
+			let cache = Weak::clone(&cache);
+			spawner.spawn_blocking("prefetch", async move {
+				let Some(cache) = cache.upgrade() else {
+					return;
+				};
+				if let Err(error) = Self::read_into_cache(&*physical, &*cache, &page_ids) {
+					warn!("Failed to prefetch pages: {error}");
+				}
+				mem::drop(reads);
+			});
+		}));
+		self
+	}
+}
+
+impl<PS, W> PageStorage<PS, PageCache<PS>, W>
+where
+	PS: PhysicalStorageApi + Send + Sync + 'static,
+{
+	/// Writes the IDs of the currently cached pages to the file at `path`,
+	/// see [`PageCache::save_state`].
+	pub fn save_cache_state(&self, path: &Path) -> Result<(), StorageError> {
+		self.cache.save_state(path)
+	}
+
+	/// Prefetches the pages that were cached when [`Self::save_cache_state`]
+	/// wrote the file at `path`, as far as they fit into the cache. Returns
+	/// the number of pages that are prefetched.
+	pub fn warm_cache_from(&self, path: &Path) -> Result<usize, StorageError> {
+		let mut page_ids = PageCache::<PS>::saved_state(path)?;
+		page_ids.truncate(self.cache.capacity());
+		self.prefetch(&page_ids)?;
+		Ok(page_ids.len())
+	}
+}
+
 impl<PS, PC, W> PageStorage<PS, PC, W>
 where
 	PS: PhysicalStorageApi,
@@ -393,14 +1792,88 @@ where
 	fn new(physical: Arc<PS>, cache: PC, wal: W) -> Self {
 		Self {
 			physical,
-			cache,
+			cache: Arc::new(cache),
 			wal,
 			transaction_enumerator: TransactionEnumerator::new(),
+			transaction_counters: TransactionCounters::default(),
+			access_counters: AccessCounters::default(),
+			lock_manager: LockManager::default(),
+			in_flight_reads: Arc::default(),
+			background_prefetch: None,
+			versions: VersionStore::default(),
+			transaction_page_limit: usize::MAX,
+			max_transaction_size: None,
+			checkpoint_policy: CheckpointPolicy::default(),
+			checkpoints: CheckpointTracker::default(),
+			listeners: Listeners::default(),
+			idle_maintenance_policy: IdleMaintenancePolicy::default(),
+			maintenance: MaintenanceTracker::default(),
+			commit_gate: RwLock::new(()),
+			write_set_memory: AtomicUsize::new(0),
+			frozen_segments: RwLock::new(HashSet::new()),
+			quota: QuotaTracker::default(),
+			segment_page_sizes: SegmentPageSizes::default(),
+			unlogged_segments: UnloggedSegments::default(),
+			throttler: Throttler::default(),
+			verify_after_recovery: false,
+			record_handlers: WalRecordHandlers::default(),
 		}
 	}
 
-	fn load_into_cache(&self, page_id: PageId) -> Result<PC::WriteGuard<'_>, StorageError> {
-		let mut guard = self.cache.store(page_id);
+	fn with_config(mut self, config: &PageStorageConfig) -> Self {
+		self.transaction_page_limit = config.page_cache.transaction_page_limit();
+		self.max_transaction_size = config.page_cache.max_transaction_size;
+		self.checkpoint_policy = config.checkpoint.clone();
+		self.idle_maintenance_policy = config.idle_maintenance.clone();
+		self.versions = VersionStore::new(config.version_retention.clone());
+		self.verify_after_recovery = config.verify_after_recovery;
+		self.quota = QuotaTracker::new(&config.quota);
+		self.segment_page_sizes = config.segment_page_sizes.clone();
+		self.unlogged_segments = config.unlogged_segments.clone();
+		self.throttler = Throttler::new(&config.write_throttle);
+		self.record_handlers = config.wal.record_handlers.clone();
+		self
+	}
+
+	/// The page sizes that the segments of the storage are created with.
+	pub fn segment_page_sizes(&self) -> &SegmentPageSizes {
+		&self.segment_page_sizes
+	}
+
+	#[allow(clippy::cast_precision_loss)]
+	fn dirty_ratio(&self) -> f32 {
+		self.cache.num_dirty_pages() as f32 / usize::max(self.cache.capacity(), 1) as f32
+	}
+
+	/// Reads a page from storage into the cache. If another thread is already
+	/// doing so, waits for it to finish instead, and returns `None`. The wait
+	/// fails once the access limit is reached.
+	fn load_into_cache(
+		&self,
+		page_id: PageId,
+		limit: Option<&AccessLimit>,
+	) -> Result<Option<PC::WriteGuard>, StorageError> {
+		let read = match limit {
+			Some(limit) => loop {
+				match self
+					.in_flight_reads
+					.begin_until(page_id, limit.next_check())
+				{
+					Ok(read) => break read,
+					Err(StillReading) => limit.check(page_id)?,
+				}
+			},
+			None => self.in_flight_reads.begin(page_id),
+		};
+		let Some(_read) = read else {
+			return Ok(None);
+		};
+		// Another thread may have finished reading the page between the cache
+		// miss and registering this read.
+		if self.cache.has_page(page_id) {
+			return Ok(None);
+		}
+		let mut guard = self.cache.store(page_id)?;
 		if let Err(error) = self.physical.read(ReadOp {
 			page_id,
 			buf: guard.body_mut(),
@@ -408,56 +1881,417 @@ where
 			self.cache.scrap(page_id);
 			return Err(error);
 		}
-		Ok(guard)
+		Ok(Some(guard))
+	}
+
+	/// Reads the pages that aren't cached into the cache, with a single read
+	/// for each run of consecutive pages. Pages that another thread is already
+	/// reading are skipped.
+	///
+	/// With background prefetching, the pages are only registered as being
+	/// read here, so that threads accessing them wait for the background read
+	/// instead of reading them again.
+	fn prefetch(&self, page_ids: &[PageId]) -> Result<(), StorageError> {
+		let page_ids = page_ids
+			.iter()
+			.copied()
+			.filter(|page_id| !self.cache.has_page(*page_id));
+		if let Some(background_prefetch) = &self.background_prefetch {
+			let (page_ids, reads) = page_ids
+				.filter_map(|page_id| {
+					let read = self.in_flight_reads.try_begin_owned(page_id)?;
+					Some((page_id, read))
+				})
+				.unzip::<_, _, Vec<_>, Vec<_>>();
+			if !page_ids.is_empty() {
+				background_prefetch(page_ids, reads);
+			}
+			return Ok(());
+		}
+		let (page_ids, _reads) = page_ids
+			.filter_map(|page_id| {
+				let read = self.in_flight_reads.try_begin(page_id)?;
+				Some((page_id, read))
+			})
+			.unzip::<_, _, Vec<_>, Vec<_>>();
+		Self::read_into_cache(&*self.physical, &*self.cache, &page_ids)
+	}
+
+	/// Reads the pages into the cache, whose reads must have been registered
+	/// in the in-flight reads.
+	fn read_into_cache(physical: &PS, cache: &PC, page_ids: &[PageId]) -> Result<(), StorageError> {
+		let mut guards = Vec::new();
+		for page_id in page_ids.iter().copied() {
+			// Another thread may have finished reading the page between the
+			// cache miss and registering the read.
+			if cache.has_page(page_id) {
+				continue;
+			}
+			match cache.store(page_id) {
+				Ok(guard) => guards.push((page_id, guard)),
+				Err(error) => {
+					for (page_id, _) in &guards {
+						cache.scrap(*page_id);
+					}
+					return Err(error);
+				}
+			}
+		}
+		let mut pages: Vec<(PageId, &mut [u8])> = guards
+			.iter_mut()
+			.map(|(page_id, guard)| (*page_id, guard.body_mut()))
+			.collect();
+		if let Err(error) = physical.read_pages(&mut pages) {
+			for (page_id, _) in &guards {
+				cache.scrap(*page_id);
+			}
+			return Err(error);
+		}
+		Ok(())
+	}
+
+	/// Locks a page for reading, after waiting for transactions other than
+	/// `accessor` that are writing to it.
+	fn read_guard(
+		&self,
+		page_id: PageId,
+		accessor: Option<u64>,
+	) -> Result<PC::ReadGuard<'_>, StorageError> {
+		let limit = self.lock_manager.access_limit(accessor);
+		loop {
+			self.lock_manager.wait_for(page_id, accessor)?;
+			let guard = match self.load_cached(page_id, limit.as_ref())? {
+				Some(guard) => guard,
+				None => match self.load_into_cache(page_id, limit.as_ref())? {
+					Some(guard) => self.cache.downgrade_guard(guard),
+					None => continue,
+				},
+			};
+			// A transaction may have started writing to the page, and released
+			// it from the cache by spilling it, while waiting for the lock.
+			if !self.lock_manager.is_locked(page_id, accessor) {
+				return Ok(guard);
+			}
+		}
+	}
+
+	/// Reads a page, after waiting for transactions other than `accessor` that
+	/// are writing to it. Cached pages are copied without locking them if
+	/// possible, so that reads don't block writers; otherwise the page is
+	/// locked for reading.
+	fn read_page<'a>(
+		&self,
+		page_id: PageId,
+		accessor: Option<u64>,
+	) -> Result<Page<'_, 'a, PC>, StorageError> {
+		self.lock_manager.wait_for(page_id, accessor)?;
+		let mut image: Box<[u8]> = vec![0; PAGE_BODY_SIZE].into();
+		if self.read_optimistic(page_id, accessor, &mut image) {
+			return Ok(Page {
+				guard: WriteablePageGuard::Copy(image),
+			});
+		}
+		Ok(Page {
+			guard: WriteablePageGuard::Shared(self.read_guard(page_id, accessor)?),
+		})
+	}
+
+	/// Like [`read_page`](Self::read_page), but copies the body of the page
+	/// into `buf`.
+	fn read_page_into(
+		&self,
+		page_id: PageId,
+		accessor: Option<u64>,
+		buf: &mut [u8],
+	) -> Result<(), StorageError> {
+		self.lock_manager.wait_for(page_id, accessor)?;
+		if self.read_optimistic(page_id, accessor, buf) {
+			return Ok(());
+		}
+		self.read_guard(page_id, accessor)?.read(0, buf);
+		Ok(())
+	}
+
+	fn read_optimistic(&self, page_id: PageId, accessor: Option<u64>, buf: &mut [u8]) -> bool {
+		// A transaction may have locked the page while it was copied, and
+		// might not have modified it in the cache yet.
+		self.cache.read_optimistic(page_id, buf) && !self.lock_manager.is_locked(page_id, accessor)
+	}
+
+	/// Locks a page for reading, unless it is locked for writing. Pages held by
+	/// transactions in the lock manager are not waited for.
+	fn try_read_guard(&self, page_id: PageId) -> Result<Option<PC::ReadGuard<'_>>, StorageError> {
+		if !self.cache.has_page(page_id) {
+			if let Some(guard) = self.load_into_cache(page_id, None)? {
+				return Ok(Some(self.cache.downgrade_guard(guard)));
+			}
+		}
+		Ok(self.cache.try_load(page_id))
+	}
+
+	fn is_frozen(&self, page_id: PageId) -> bool {
+		self.frozen_segments.read().contains(&page_id.segment_num)
+	}
+
+	/// Counts the segment towards the quota, unless it already does. Pages
+	/// are only evicted once they are written to storage, so the segments in
+	/// use have a file or cached pages.
+	fn claim_segment(&self, segment_num: u32) -> Result<(), StorageError> {
+		self.quota.claim(segment_num, || {
+			let mut segment_nums = self.physical.segment_nums()?;
+			segment_nums.extend(
+				self.cache
+					.cached_pages()
+					.into_iter()
+					.map(|page_id| page_id.segment_num),
+			);
+			Ok(segment_nums)
+		})
+	}
+
+	/// Reads a page as of the commit with sequence number `seq`, without
+	/// waiting for transactions that are writing to it.
+	fn snapshot_page<'a>(
+		&self,
+		page_id: PageId,
+		seq: u64,
+	) -> Result<Page<'_, 'a, PC>, StorageError> {
+		loop {
+			if let Some(image) = self.versions.get(page_id, seq) {
+				return Ok(Page {
+					guard: WriteablePageGuard::Image(image),
+				});
+			}
+			if let Some(guard) = self.try_read_guard(page_id)? {
+				// A transaction may have modified the page between checking the
+				// versions and acquiring the lock.
+				if let Some(image) = self.versions.get(page_id, seq) {
+					return Ok(Page {
+						guard: WriteablePageGuard::Image(image),
+					});
+				}
+				return Ok(Page {
+					guard: WriteablePageGuard::Shared(guard),
+				});
+			}
+			// The page was locked by a transaction that hasn't kept its
+			// original image yet, or that is just finishing its commit.
+			thread::yield_now();
+		}
+	}
+
+	/// Locks a page for writing, after waiting for transactions other than
+	/// `accessor` that are writing to it.
+	fn write_guard(
+		&self,
+		page_id: PageId,
+		accessor: Option<u64>,
+	) -> Result<PC::WriteGuard, StorageError> {
+		let limit = self.lock_manager.access_limit(accessor);
+		loop {
+			self.lock_manager.wait_for(page_id, accessor)?;
+			let guard = match self.load_cached_mut(page_id, limit.as_ref())? {
+				Some(guard) => guard,
+				None => match self.load_into_cache(page_id, limit.as_ref())? {
+					Some(guard) => guard,
+					None => continue,
+				},
+			};
+			if !self.lock_manager.is_locked(page_id, accessor) {
+				return Ok(guard);
+			}
+		}
 	}
 
-	fn read_guard(&self, page_id: PageId) -> Result<PC::ReadGuard<'_>, StorageError> {
-		if let Some(guard) = self.cache.load(page_id) {
-			return Ok(guard);
+	/// Locks a cached page for reading, or returns `None` if it isn't cached.
+	/// Waiting for the lock fails once the access limit is reached.
+	fn load_cached(
+		&self,
+		page_id: PageId,
+		limit: Option<&AccessLimit>,
+	) -> Result<Option<PC::ReadGuard<'_>>, StorageError> {
+		let Some(limit) = limit else {
+			return Ok(self.cache.load(page_id));
+		};
+		loop {
+			match self.cache.load_until(page_id, limit.next_check()) {
+				TimedLoad::Loaded(guard) => return Ok(Some(guard)),
+				TimedLoad::Missing => return Ok(None),
+				TimedLoad::Busy => limit.check(page_id)?,
+			}
 		}
-		let guard = self.load_into_cache(page_id)?;
-		Ok(self.cache.downgrade_guard(guard))
 	}
 
-	fn write_guard(&self, page_id: PageId) -> Result<PC::WriteGuard<'_>, StorageError> {
-		if let Some(guard) = self.cache.load_mut(page_id) {
-			return Ok(guard);
+	/// Like [`Self::load_cached`], but locks the page for writing.
+	fn load_cached_mut(
+		&self,
+		page_id: PageId,
+		limit: Option<&AccessLimit>,
+	) -> Result<Option<PC::WriteGuard>, StorageError> {
+		let Some(limit) = limit else {
+			return Ok(self.cache.load_mut(page_id));
+		};
+		loop {
+			match self.cache.load_mut_until(page_id, limit.next_check()) {
+				TimedLoad::Loaded(guard) => return Ok(Some(guard)),
+				TimedLoad::Missing => return Ok(None),
+				TimedLoad::Busy => limit.check(page_id)?,
+			}
 		}
-		self.load_into_cache(page_id)
+	}
+}
+
+impl<PS, PC, W> BackgroundTasks for PageStorage<PS, PC, W>
+where
+	PC: BackgroundTasks,
+	W: BackgroundTasks,
+{
+	fn task_panic(&self) -> Option<TaskPanic> {
+		self.cache.task_panic().or_else(|| self.wal.task_panic())
+	}
+}
+
+impl<PS, PC, W> PageStorage<PS, PC, W>
+where
+	PS: PhysicalStorageApi,
+	PC: PageCacheApi,
+	W: WalApi,
+{
+	/// The pool that runs blocking work for the storage, see
+	/// [`PageCacheApi::blocking_pool`].
+	pub fn blocking_pool(&self) -> &Arc<BlockingPool> {
+		self.cache.blocking_pool()
+	}
+
+	/// Waits while the WAL or the dirty pages are too far behind the writes,
+	/// see [`WriteThrottle`]. Dirty pages are flushed in the background
+	/// meanwhile, and the WAL shrinks with the next automatic checkpoint.
+	fn throttle_writes(&self) -> Result<(), StorageError> {
+		self.throttler.wait(
+			|| (self.wal.size(), self.dirty_ratio()),
+			|limit| {
+				event!(DEBUG, %limit, "Throttling writes");
+				if limit == ThrottleLimit::DirtyRatio {
+					self.cache.flush();
+				}
+			},
+		)
+	}
+
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+	fn checkpoint_with(&self, trigger: CheckpointTrigger) -> Result<(), StorageError> {
+		let started = Instant::now();
+		let generation = self.wal.start_checkpoint()?;
+		self.cache.flush_sync()?;
+		self.physical.sync()?;
+		self.wal.finish_checkpoint(generation)?;
+		self.checkpoints.record(trigger, started);
+		self.listeners.checkpointed(trigger);
+		Ok(())
 	}
 }
 
 #[cfg_attr(test, automock(
     type Page<'a> = MockPage;
     type Transaction<'a> = MockTransactionApi;
+    type Snapshot<'a> = MockSnapshotApi;
 ))]
 pub(crate) trait PageStorageApi {
 	type Page<'a>: ReadPage + 'a
 	where
 		Self: 'a;
 	type Transaction<'a>: TransactionApi
+	where
+		Self: 'a;
+	type Snapshot<'a>: SnapshotApi
 	where
 		Self: 'a;
 
 	fn recover(&self) -> Result<(), StorageError>;
+	fn dry_run_recovery(&self) -> Result<RecoveryReport, StorageError>;
 	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError>;
 	fn transaction(&self) -> Result<Self::Transaction<'_>, StorageError>;
+
+	/// Begins a transaction that can only read pages. It only ever takes
+	/// shared locks on pages, so it can run at the same time as any number of
+	/// other readers of the same pages, and it writes nothing to the WAL.
+	fn read_only_transaction(&self) -> Result<Self::Transaction<'_>, StorageError>;
+	fn snapshot(&self) -> Self::Snapshot<'_>;
+	fn snapshot_at(&self, seq: u64) -> Result<Self::Snapshot<'_>, StorageError>;
+	/// Takes a snapshot, along with the WAL index of the first write that it
+	/// doesn't see.
+	fn snapshot_with_position(&self) -> Result<(Self::Snapshot<'_>, WalIndex), StorageError>;
 	fn flush(&self);
 	fn flush_sync(&self) -> Result<(), StorageError>;
+	fn set_background_io_rate(&self, rate: Option<usize>);
+	fn segment_stats(&self) -> BTreeMap<u32, SegmentIoStats>;
+	/// The pages that were written at least once, in ascending order. Pages
+	/// that a transaction is writing to for the first time may be included.
+	fn stored_pages(&self) -> Result<Vec<PageId>, StorageError>;
+	/// The pages that were modified at or after the WAL index `since`, in
+	/// ascending order. Pages that a transaction is writing to may be
+	/// included.
+	fn modified_pages(&self, since: WalIndex) -> Result<Vec<PageId>, StorageError>;
+	fn lock_graph(&self) -> LockGraph;
+	fn checkpoint(&self) -> Result<(), StorageError>;
+	fn needs_checkpoint(&self) -> bool;
+	fn auto_checkpoint(&self) -> Result<Option<CheckpointTrigger>, StorageError>;
+	fn checkpoint_stats(&self) -> CheckpointStats;
+	/// The listeners that are notified of the transactions and checkpoints
+	/// of the storage.
+	fn listeners(&self) -> &Listeners;
+	/// Runs the maintenance of the idle maintenance policy if no transaction
+	/// ran for long enough, and returns whether it did: a checkpoint if one
+	/// is needed, scrubbing the next few stored pages, and deleting WAL
+	/// generations that are no longer needed.
+	fn idle_maintenance(&self) -> Result<bool, StorageError>;
+	fn maintenance_stats(&self) -> MaintenanceStats;
+	fn release_memory(&self, pressure: MemoryPressure) -> Result<usize, StorageError>;
+	fn memory_usage(&self) -> MemoryUsage;
+	fn stats(&self) -> Result<Stats, StorageError>;
+	/// The page accesses of transactions, by the tag they were attributed to
+	/// with [`TransactionApi::tag_accesses`].
+	fn access_stats(&self) -> BTreeMap<PageId, AccessStats>;
+	/// Checks that every page in storage can be read back intact. Modified
+	/// pages that are only cached so far are not covered.
+	fn check(&self) -> Result<CheckReport, StorageError>;
+	/// Makes the pages of the segment read-only until the storage is dropped,
+	/// after writing them back and verifying their checksums.
+	fn freeze_segment(&self, segment_num: u32) -> Result<(), StorageError>;
+	fn truncate(&self, end: PageId) -> Result<u64, StorageError>;
 }
 
-impl<PS, PC, W> PageStorageApi for PageStorage<PS, PC, W>
+// Transactions keep the storage alive on their own, so that they don't have
+// to borrow it.
+impl<PS, PC, W> PageStorageApi for Arc<PageStorage<PS, PC, W>>
 where
 	PS: PhysicalStorageApi,
 	PC: PageCacheApi,
 	W: WalApi,
+	PageStorage<PS, PC, W>: Sync,
 {
-	type Page<'a> = Page<'a, 'a, PC> where Self: 'a;
-	type Transaction<'a> = Transaction<'a, PS, PC, W> where Self: 'a;
+	type Page<'a>
+		= Page<'a, 'a, PC>
+	where
+		Self: 'a;
+	type Transaction<'a>
+		= Transaction<PS, PC, W>
+	where
+		Self: 'a;
+	type Snapshot<'a>
+		= Snapshot<PS, PC, W>
+	where
+		Self: 'a;
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all))]
 	fn recover(&self) -> Result<(), StorageError> {
+		let redo_pages = if self.verify_after_recovery {
+			Some(self.wal.dry_run_recovery()?.redo_pages)
+		} else {
+			None
+		};
 		self.wal.recover(&mut |write_op| {
-			let mut guard = self.write_guard(write_op.page_id)?;
+			let mut guard = self.write_guard(write_op.page_id, None)?;
 			guard.write(write_op.offset.into(), write_op.buf, write_op.index);
 			self.physical.write(WriteOp {
 				wal_index: write_op.index,
@@ -465,20 +2299,63 @@ where
 				buf: guard.body(),
 			})?;
 			Ok(())
-		})
+		})?;
+		self.transaction_enumerator
+			.resume_at(self.wal.next_transaction_id());
+
+		if let Some(redo_pages) = redo_pages {
+			let page_ids: Vec<PageId> = redo_pages.into_iter().collect();
+			let report = self.physical.check_pages(&page_ids)?;
+			if !report.is_ok() {
+				return Err(StorageError::RecoveryVerificationFailed(report));
+			}
+		}
+		Ok(())
+	}
+
+	fn dry_run_recovery(&self) -> Result<RecoveryReport, StorageError> {
+		self.wal.dry_run_recovery()
 	}
 
 	fn get_page(&self, page_id: PageId) -> Result<Self::Page<'_>, StorageError> {
-		Ok(Page {
-			guard: WriteablePageGuard::Shared(self.read_guard(page_id)?),
-		})
+		self.read_page(page_id, None)
 	}
 
-	fn transaction(&self) -> Result<Transaction<'_, PS, PC, W>, StorageError> {
+	fn transaction(&self) -> Result<Transaction<PS, PC, W>, StorageError> {
 		let Some(transaction_id) = self.transaction_enumerator.begin() else {
 			return Err(StorageError::TransactionLimitReached);
 		};
-		Ok(Transaction::new(transaction_id, self))
+		event!(DEBUG, transaction_id, "Transaction started");
+		Ok(Transaction::new(transaction_id, Arc::clone(self)))
+	}
+
+	fn read_only_transaction(&self) -> Result<Transaction<PS, PC, W>, StorageError> {
+		let mut transaction = self.transaction()?;
+		transaction.read_only = true;
+		Ok(transaction)
+	}
+
+	fn snapshot(&self) -> Snapshot<PS, PC, W> {
+		Snapshot {
+			seq: self.versions.begin_snapshot(),
+			storage: Arc::clone(self),
+		}
+	}
+
+	fn snapshot_with_position(&self) -> Result<(Snapshot<PS, PC, W>, WalIndex), StorageError> {
+		let _commits = self.commit_gate.write();
+		let position = self.wal.position()?;
+		Ok((self.snapshot(), position))
+	}
+
+	fn snapshot_at(&self, seq: u64) -> Result<Snapshot<PS, PC, W>, StorageError> {
+		if !self.versions.begin_snapshot_at(seq) {
+			return Err(StorageError::SnapshotUnavailable(seq));
+		}
+		Ok(Snapshot {
+			seq,
+			storage: Arc::clone(self),
+		})
 	}
 
 	fn flush(&self) {
@@ -488,27 +2365,233 @@ where
 	fn flush_sync(&self) -> Result<(), StorageError> {
 		self.cache.flush_sync()
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use std::{
-		fs::File,
-		io::{Read, Seek, SeekFrom},
-	};
+	fn set_background_io_rate(&self, rate: Option<usize>) {
+		self.cache.set_background_io_rate(rate);
+	}
 
-	use mockall::{predicate::*, Sequence};
-	use pretty_assertions::assert_buf_eq;
-	use tempfile::tempdir;
+	fn segment_stats(&self) -> BTreeMap<u32, SegmentIoStats> {
+		self.physical.segment_stats()
+	}
+
+	fn stored_pages(&self) -> Result<Vec<PageId>, StorageError> {
+		// Pages are only evicted once they are written to storage, so a page
+		// that is no longer cached is found in storage.
+		let mut pages = self.cache.cached_pages();
+		pages.extend(
+			self.physical
+				.initialized_pages()?
+				.into_iter()
+				.map(|(page_id, _)| page_id),
+		);
+		pages.sort_unstable();
+		pages.dedup();
+		Ok(pages)
+	}
+
+	fn modified_pages(&self, since: WalIndex) -> Result<Vec<PageId>, StorageError> {
+		// A dirty page is only marked as clean once it is written to storage,
+		// so checking the cache first finds every page in one of the two.
+		let mut pages = self.cache.dirty_since(since);
+		pages.extend(
+			self.physical
+				.initialized_pages()?
+				.into_iter()
+				.filter(|(_, wal_index)| *wal_index >= since)
+				.map(|(page_id, _)| page_id),
+		);
+		pages.sort_unstable();
+		pages.dedup();
+		Ok(pages)
+	}
+
+	fn lock_graph(&self) -> LockGraph {
+		self.lock_manager.lock_graph()
+	}
+
+	/// Writes all pages that were modified before the checkpoint started to
+	/// storage, so that the WAL before that point is no longer needed.
+	fn checkpoint(&self) -> Result<(), StorageError> {
+		self.checkpoint_with(CheckpointTrigger::Manual)
+	}
+
+	fn needs_checkpoint(&self) -> bool {
+		self.wal.needs_checkpoint()
+	}
+
+	/// Takes a checkpoint if any of the triggers of the checkpoint policy
+	/// apply, and returns the trigger.
+	fn auto_checkpoint(&self) -> Result<Option<CheckpointTrigger>, StorageError> {
+		if !self.needs_checkpoint() {
+			return Ok(None);
+		}
+		let (wal_size, dirty_ratio) = (self.wal.size(), self.dirty_ratio());
+		// Writes that are throttled wait for a checkpoint to catch up
+		let Some(trigger) = self
+			.checkpoints
+			.trigger(&self.checkpoint_policy, wal_size, dirty_ratio)
+			.or_else(|| {
+				self.throttler
+					.exceeded(wal_size, dirty_ratio)
+					.map(ThrottleLimit::checkpoint_trigger)
+			})
+		else {
+			return Ok(None);
+		};
+		self.checkpoint_with(trigger)?;
+		Ok(Some(trigger))
+	}
+
+	fn checkpoint_stats(&self) -> CheckpointStats {
+		self.checkpoints.stats(self.wal.size(), self.dirty_ratio())
+	}
+
+	fn listeners(&self) -> &Listeners {
+		&self.listeners
+	}
+
+	fn idle_maintenance(&self) -> Result<bool, StorageError> {
+		let policy = &self.idle_maintenance_policy;
+		if !self
+			.maintenance
+			.is_due(policy, self.transaction_enumerator.idle_for())
+		{
+			return Ok(false);
+		}
+		event!(DEBUG, "Running idle maintenance");
+
+		let mut run = MaintenanceRun::default();
+		if self.needs_checkpoint() {
+			self.checkpoint_with(CheckpointTrigger::Idle)?;
+			run.checkpointed = true;
+		}
+		let stored_pages: Vec<PageId> = self
+			.physical
+			.initialized_pages()?
+			.into_iter()
+			.map(|(page_id, _)| page_id)
+			.collect();
+		run.scrubbed = self
+			.maintenance
+			.pages_to_scrub(&stored_pages, policy.scrub_pages);
+		run.problems = self.physical.check_pages(&run.scrubbed)?.problems;
+		for problem in &run.problems {
+			warn!("Idle maintenance found a problem: {problem:?}");
+		}
+		run.wal_bytes_trimmed = self.wal.compact()?;
+		self.maintenance.record(run);
+		Ok(true)
+	}
+
+	fn maintenance_stats(&self) -> MaintenanceStats {
+		self.maintenance.stats()
+	}
+
+	fn release_memory(&self, pressure: MemoryPressure) -> Result<usize, StorageError> {
+		match pressure {
+			MemoryPressure::Moderate => Ok(self.cache.shrink_to(self.cache.num_cached_pages() / 2)),
+			MemoryPressure::Critical => {
+				self.cache.flush_sync()?;
+				Ok(self.cache.release_clean())
+			}
+		}
+	}
+
+	fn memory_usage(&self) -> MemoryUsage {
+		MemoryUsage {
+			page_cache: self.cache.buffer_memory(),
+			cache_index: self.cache.index_memory(),
+			wal_buffers: self.wal.buffer_memory(),
+			write_sets: self.write_set_memory.load(Ordering::Relaxed),
+			page_versions: self.versions.memory_usage(),
+		}
+	}
+
+	fn access_stats(&self) -> BTreeMap<PageId, AccessStats> {
+		self.access_counters.get()
+	}
+
+	fn stats(&self) -> Result<Stats, StorageError> {
+		let mut segment_pages = BTreeMap::new();
+		for page_id in self.stored_pages()? {
+			*segment_pages.entry(page_id.segment_num).or_default() += 1;
+		}
+		Ok(Stats {
+			cache: self.cache.stats(),
+			wal_bytes_written: self.wal.bytes_written(),
+			commits: self.transaction_counters.commits(),
+			aborts: self.transaction_counters.aborts(),
+			segment_pages,
+			wal_size: self.wal.size(),
+			memory: self.memory_usage(),
+			throttled_writes: self.throttler.num_throttled(),
+		})
+	}
+
+	fn check(&self) -> Result<CheckReport, StorageError> {
+		self.physical.check()
+	}
+
+	fn freeze_segment(&self, segment_num: u32) -> Result<(), StorageError> {
+		if !self.frozen_segments.write().insert(segment_num) {
+			return Ok(());
+		}
+		let in_use = self
+			.lock_manager
+			.lock_graph()
+			.transactions
+			.iter()
+			.flat_map(|transaction| &transaction.held)
+			.any(|page_id| page_id.segment_num == segment_num);
+		let result = if in_use {
+			Err(StorageError::SegmentInUse(segment_num))
+		} else {
+			self.cache
+				.flush_segment(segment_num)
+				.and_then(|()| self.physical.verify_segment(segment_num))
+		};
+		if result.is_err() {
+			self.frozen_segments.write().remove(&segment_num);
+		}
+		result
+	}
+
+	/// Shrinks the segment files so that `end` is the first page past them,
+	/// and returns the number of bytes that were reclaimed.
+	///
+	/// The pages past `end` must not be in use, and must not have been
+	/// modified since the last checkpoint, since flushing or recovering them
+	/// would grow the files again.
+	fn truncate(&self, end: PageId) -> Result<u64, StorageError> {
+		self.physical.truncate(end)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		fs::{self, File},
+		io::{Read, Seek, SeekFrom},
+		mem,
+		time::Duration,
+	};
+
+	use mockall::{predicate::*, Sequence};
+	use pretty_assertions::assert_buf_eq;
+	use tempfile::tempdir;
 	use test::Bencher;
 	use tests::wal::{CommitLog, WriteLog};
 
-	use crate::{consts::PAGE_SIZE, files::segment::PAGE_BODY_SIZE, utils::units::KIB};
+	use crate::{
+		consts::PAGE_SIZE,
+		files::{overlay::OverlayFolder, segment::PAGE_BODY_SIZE, DatabaseFolder},
+		utils::units::KIB,
+	};
 
 	use self::{
 		cache::MockPageCacheApi,
 		physical::MockPhysicalStorageApi,
-		test_helpers::{page_id, wal_index},
+		test_helpers::{page_id, temp_storage, wal_index, HandlerCall, RecordingHandler},
 		wal::MockWalApi,
 	};
 
@@ -538,6 +2621,7 @@ mod tests {
 			.unwrap();
 			Ok(())
 		});
+		wal.expect_next_transaction_id().returning(|| 0);
 		let mut seq = Sequence::new();
 
 		cache
@@ -546,6 +2630,12 @@ mod tests {
 			.in_sequence(&mut seq)
 			.with(eq(page_id!(1, 2)))
 			.returning(|_| None);
+		cache
+			.expect_has_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(1, 2)))
+			.returning(|_| false);
 		cache
 			.expect_store()
 			.once()
@@ -560,7 +2650,7 @@ mod tests {
 				guard
 					.expect_write()
 					.with(eq(10), eq([1, 2, 3]), eq(wal_index!(69, 420)));
-				guard
+				Ok(guard)
 			});
 		physical
 			.expect_read()
@@ -608,7 +2698,7 @@ mod tests {
 			})
 			.returning(|_| Ok(()));
 		// given
-		let page_storage = PageStorage::new(Arc::new(physical), cache, wal);
+		let page_storage = Arc::new(PageStorage::new(Arc::new(physical), cache, wal));
 
 		// when
 		page_storage.recover().unwrap();
@@ -621,12 +2711,24 @@ mod tests {
 		let mut cache = MockPageCacheApi::new();
 		let wal = MockWalApi::new();
 		let mut seq = Sequence::new();
+		cache
+			.expect_read_optimistic()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(69, 420)), always())
+			.returning(|_, _| false);
 		cache
 			.expect_load()
 			.once()
 			.in_sequence(&mut seq)
 			.with(eq(page_id!(69, 420)))
 			.returning(|_| None);
+		cache
+			.expect_has_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(69, 420)))
+			.returning(|_| false);
 		cache
 			.expect_store()
 			.once()
@@ -637,7 +2739,7 @@ mod tests {
 				guard
 					.expect_body_mut()
 					.returning(|| vec![0; PAGE_BODY_SIZE]);
-				guard
+				Ok(guard)
 			});
 		physical
 			.expect_read()
@@ -668,7 +2770,7 @@ mod tests {
 			});
 
 		// given
-		let storage = PageStorage::new(Arc::new(physical), cache, wal);
+		let storage = Arc::new(PageStorage::new(Arc::new(physical), cache, wal));
 
 		// when
 		let mut buf = [0; 5];
@@ -682,6 +2784,57 @@ mod tests {
 		assert_buf_eq!(buf, [10, 11, 12, 13, 14]);
 	}
 
+	#[test]
+	fn coalesce_concurrent_reads() {
+		// expect
+		let mut physical = MockPhysicalStorageApi::new();
+		physical
+			.expect_read()
+			.once()
+			.withf(|read_op| read_op.page_id == page_id!(1, 2))
+			.returning(|read_op| {
+				thread::sleep(Duration::from_millis(50));
+				read_op.buf.fill(7);
+				Ok(Some(wal_index!(1, 2)))
+			});
+
+		// given
+		let physical = Arc::new(physical);
+		let cache = PageCache::new(
+			&PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				..Default::default()
+			},
+			Arc::clone(&physical),
+			Arc::new(ThreadPool::new().unwrap()),
+		);
+		let storage = Arc::new(PageStorage::new(physical, cache, MockWalApi::new()));
+
+		// when
+		let bufs: Vec<[u8; 4]> = thread::scope(|scope| {
+			let readers: Vec<_> = (0..8)
+				.map(|_| {
+					scope.spawn(|| {
+						let mut buf = [0; 4];
+						storage
+							.get_page(page_id!(1, 2))
+							.unwrap()
+							.read(0, &mut buf)
+							.unwrap();
+						buf
+					})
+				})
+				.collect();
+			readers
+				.into_iter()
+				.map(|reader| reader.join().unwrap())
+				.collect()
+		});
+
+		// then
+		assert!(bufs.iter().all(|buf| *buf == [7; 4]));
+	}
+
 	#[test]
 	fn transaction() {
 		// expect
@@ -696,6 +2849,12 @@ mod tests {
 			.in_sequence(&mut seq)
 			.with(eq(page_id!(1, 2)))
 			.returning(|_| None);
+		cache
+			.expect_has_page()
+			.once()
+			.in_sequence(&mut seq)
+			.with(eq(page_id!(1, 2)))
+			.returning(|_| false);
 		cache
 			.expect_store()
 			.once()
@@ -709,24 +2868,45 @@ mod tests {
 					.once()
 					.in_sequence(&mut seq)
 					.returning(|| vec![0; PAGE_BODY_SIZE]);
+				guard
+					.expect_body()
+					.once()
+					.in_sequence(&mut seq)
+					.return_const(vec![0; PAGE_BODY_SIZE]);
 				guard
 					.expect_read()
 					.once()
 					.in_sequence(&mut seq)
 					.with(eq(10), always())
-					.returning(|_, buf| buf.copy_from_slice(&[69, 25]));
-				guard.expect_write().once().in_sequence(&mut seq).with(
-					eq(10),
-					eq([1, 2]),
-					eq(wal_index!(24, 25)),
-				);
+					.returning(|_, buf| buf.copy_from_slice(&[69]));
+				guard
+					.expect_body_mut()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|| vec![0; PAGE_BODY_SIZE]);
 				guard
 					.expect_read()
 					.once()
 					.in_sequence(&mut seq)
+					.with(eq(11), always())
+					.returning(|_, buf| buf.copy_from_slice(&[25]));
+				guard
+					.expect_body_mut()
+					.once()
+					.in_sequence(&mut seq)
+					.returning(|| vec![0; PAGE_BODY_SIZE]);
+				guard
+					.expect_read()
+					.times(2)
+					.in_sequence(&mut seq)
 					.with(eq(10), always())
 					.returning(|_, buf| buf.copy_from_slice(&[1, 2]));
-				guard
+				guard.expect_write().once().in_sequence(&mut seq).with(
+					eq(10),
+					eq([1, 2]),
+					eq(wal_index!(24, 25)),
+				);
+				Ok(guard)
 			});
 		physical
 			.expect_read()
@@ -737,34 +2917,34 @@ mod tests {
 				read_op.buf.fill(0);
 				Ok(Some(wal_index!(69, 420)))
 			});
-		wal.expect_log_write()
+		wal.expect_log_transaction()
 			.once()
 			.in_sequence(&mut seq)
-			.withf(|write_log| {
-				*write_log
-					== WriteLog {
+			.withf(|write_logs, records, commit_log| {
+				write_logs
+					== [WriteLog {
 						transaction_id: 0,
 						page_id: page_id!(1, 2),
 						offset: 10,
 						from: &[69, 25],
 						to: &[1, 2],
-					}
+					}] && records.is_empty()
+					&& *commit_log == CommitLog { transaction_id: 0 }
 			})
-			.returning(|_| Ok(wal_index!(24, 25)));
-		wal.expect_log_commit()
-			.once()
-			.in_sequence(&mut seq)
-			.with(eq(CommitLog { transaction_id: 0 }))
-			.returning(|_| Ok(wal_index!(24, 25)));
+			.returning(|_, _, _| Ok(vec![wal_index!(24, 25)]));
 
 		// given
-		let storage = PageStorage::new(Arc::new(physical), cache, wal);
+		let storage = Arc::new(PageStorage::new(Arc::new(physical), cache, wal));
 
 		// when
 		let mut t = storage.transaction().unwrap();
 		t.get_page_mut(page_id!(1, 2))
 			.unwrap()
-			.write(10, &[1, 2])
+			.write(10, &[1])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(11, &[2])
 			.unwrap();
 		let mut received = [0; 2];
 		t.get_page(page_id!(1, 2))
@@ -815,29 +2995,1186 @@ mod tests {
 		assert_buf_eq!(buf, expected);
 	}
 
-	#[bench]
-	fn bench_write_and_commit(b: &mut Bencher) {
-		let tempdir = tempdir().unwrap();
+	#[test]
+	fn integration_undo() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(2, &[5, 6, 7, 8])
+			.unwrap();
+		t.undo().unwrap();
+
+		// then
+		let mut data = [0; 6];
+		page_storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4, 0, 0]);
+	}
 
+	#[test]
+	fn integration_copy_and_move_pages() {
+		// given
+		let tempdir = tempdir().unwrap();
 		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
 		let thread_pool = Arc::new(ThreadPool::new().unwrap());
-		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+		let page_storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 5))
+			.unwrap()
+			.write(0, &[4, 5, 6])
+			.unwrap();
+		t.commit().unwrap();
 
-		const DATA: &[u8] = &[69; 16 * KIB];
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		t.copy_page(page_id!(1, 2), page_id!(1, 3)).unwrap();
+		t.move_page(page_id!(1, 5), page_id!(1, 1)).unwrap();
+		t.move_page(page_id!(1, 2), page_id!(1, 2)).unwrap();
+		t.commit().unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		t.move_page(page_id!(1, 3), page_id!(1, 4)).unwrap();
+		t.undo().unwrap();
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+		page_storage.recover().unwrap();
 
-		b.iter(|| {
-			let mut t = page_storage.transaction().unwrap();
+		// then
+		let read = |page_id| {
+			let mut data = [0; 3];
+			page_storage
+				.get_page(page_id)
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			data
+		};
+		assert_buf_eq!(read(page_id!(1, 1)), [4, 5, 6]);
+		assert_buf_eq!(read(page_id!(1, 2)), [1, 2, 3]);
+		assert_buf_eq!(read(page_id!(1, 3)), [1, 2, 3]);
+		assert_buf_eq!(read(page_id!(1, 4)), [0, 0, 0]);
+		assert_buf_eq!(read(page_id!(1, 5)), [0, 0, 0]);
+	}
 
-			let mut page = t.get_page_mut(page_id!(69, 420)).unwrap();
-			page.write(25, DATA).unwrap();
+	#[test]
+	fn integration_idle_maintenance() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&PageStorageConfig {
+			idle_maintenance: IdleMaintenancePolicy {
+				idle_after: Some(Duration::ZERO),
+				scrub_pages: 2,
+			},
+			..Default::default()
+		});
+		let mut t = page_storage.transaction().unwrap();
+		for page in 1..=3 {
+			t.get_page_mut(page_id!(1, page))
+				.unwrap()
+				.write(0, &[1, 2, 3])
+				.unwrap();
+		}
+		t.commit().unwrap();
 
+		// when
+		let ran_while_idle = page_storage.idle_maintenance().unwrap();
+		let t = page_storage.transaction().unwrap();
+		let ran_while_busy = page_storage.idle_maintenance().unwrap();
+		t.undo().unwrap();
+
+		// then
+		assert!(ran_while_idle);
+		assert!(!ran_while_busy);
+		let stats = page_storage.maintenance_stats();
+		assert_eq!(stats.num_runs, 1);
+		assert_eq!(stats.num_checkpoints, 1);
+		assert_eq!(stats.pages_scrubbed, 2);
+		assert_eq!(stats.problems, []);
+		assert_eq!(
+			page_storage.checkpoint_stats().last_trigger,
+			Some(CheckpointTrigger::Idle)
+		);
+	}
+
+	#[test]
+	fn integration_log_transactions_contiguously() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let write_pages = |first_page| {
+			let mut t = page_storage.transaction().unwrap();
+			for page in first_page..first_page + 3 {
+				t.get_page_mut(page_id!(1, page))
+					.unwrap()
+					.write(0, &[1, 2, 3])
+					.unwrap();
+			}
 			t.commit().unwrap();
-		})
+		};
+		// Creates the segment, which concurrent transactions would race for.
+		write_pages(1);
+
+		// when
+		thread::scope(|scope| {
+			for i in 1..5 {
+				let write_pages = &write_pages;
+				scope.spawn(move || write_pages(i * 3 + 1));
+			}
+		});
+
+		// then
+		let mut transactions = page_storage.wal_transactions().unwrap();
+		transactions.sort_by_key(|transaction| transaction.first);
+		assert_eq!(transactions.len(), 5);
+		for transaction in &transactions {
+			assert_eq!(transaction.num_writes, 3);
+			assert_eq!(transaction.status, WalTransactionStatus::Committed);
+		}
+		for pair in transactions.windows(2) {
+			assert!(pair[0].last < pair[1].first);
+		}
 	}
-}
 
-#[cfg(test)]
-pub(crate) mod test_helpers {
-	pub(crate) use crate::files::test_helpers::page_id;
-	pub(super) use crate::files::test_helpers::wal_index;
+	#[test]
+	fn integration_rollback_on_drop() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		mem::drop(t);
+
+		// then
+		let mut t = page_storage.transaction().unwrap();
+		let mut data = [0; 4];
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [0, 0, 0, 0]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_prefetch_pages() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let page_storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		for i in 1..=4 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(0, &[i as u8; 4])
+				.unwrap();
+		}
+		t.commit().unwrap();
+		page_storage.checkpoint().unwrap();
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+
+		// when
+		let t = page_storage.transaction().unwrap();
+		t.prefetch(&[page_id!(1, 1), page_id!(1, 2), page_id!(1, 3)])
+			.unwrap();
+		let mut data = [0; 4];
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+
+		// then
+		assert_buf_eq!(data, [2; 4]);
+		assert!(page_storage.cache.has_page(page_id!(1, 1)));
+		assert!(page_storage.cache.has_page(page_id!(1, 2)));
+		assert!(page_storage.cache.has_page(page_id!(1, 3)));
+		assert!(!page_storage.cache.has_page(page_id!(1, 4)));
+		assert_eq!(page_storage.cache.stats().misses, 3);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_rollback_to_savepoint() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 4])
+			.unwrap();
+
+		// when
+		let first = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[2; 4])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[3; 4])
+			.unwrap();
+		let second = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(2, &[4; 2])
+			.unwrap();
+		t.rollback_to(first).unwrap();
+		let second_result = t.rollback_to(second);
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[5; 4])
+			.unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert!(matches!(
+			second_result,
+			Err(StorageError::UnknownSavepoint(..))
+		));
+		let t = page_storage.transaction().unwrap();
+		let mut data = [0; 4];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1; 4]);
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [5; 4]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_custom_records() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let handler = Arc::new(RecordingHandler::default());
+		let mut config = PageStorageConfig::default();
+		config
+			.wal
+			.record_handlers
+			.register(7, Arc::clone(&handler) as Arc<dyn WalRecordHandler>);
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), Arc::clone(&thread_pool), &config).unwrap();
+		let start = page_storage.wal_position().unwrap();
+		let record = |transaction_id, data| CustomRecord {
+			transaction_id,
+			record_type: 7,
+			version: 1,
+			data: vec![data],
+		};
+
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		t.log_record(7, 1, &[1]).unwrap();
+		let savepoint = t.savepoint();
+		t.log_record(7, 1, &[2]).unwrap();
+		t.rollback_to(savepoint).unwrap();
+		let unknown_result = t.log_record(8, 1, &[3]);
+		let too_large_result = t.log_record(7, 1, &vec![0; MAX_CUSTOM_RECORD_SIZE + 1]);
+		t.commit().unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		t.log_record(7, 1, &[4]).unwrap();
+		t.undo().unwrap();
+		let mut stream = page_storage.wal_stream(start).unwrap();
+		let streamed = [
+			stream.next_record(false).unwrap(),
+			stream.next_record(false).unwrap(),
+		];
+		mem::drop(stream);
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, thread_pool, &config).unwrap();
+		page_storage.recover().unwrap();
+
+		// then
+		assert!(matches!(
+			unknown_result,
+			Err(StorageError::UnknownWalRecordType(8))
+		));
+		assert!(matches!(
+			too_large_result,
+			Err(StorageError::CustomRecordTooLarge { .. })
+		));
+		assert!(matches!(
+			streamed[0],
+			Some(WalRecord::Custom {
+				transaction_id: 0,
+				record_type: 7,
+				version: 1,
+				ref data,
+			}) if data == &[1]
+		));
+		assert!(matches!(
+			streamed[1],
+			Some(WalRecord::Commit {
+				transaction_id: 0,
+				..
+			})
+		));
+		assert_eq!(
+			*handler.calls.lock(),
+			[
+				HandlerCall::Undo(record(0, 2)),
+				HandlerCall::Undo(record(1, 4)),
+				HandlerCall::Redo(record(0, 1)),
+			]
+		);
+	}
+
+	#[test]
+	fn integration_release_savepoint_with_spilled_pages() {
+		// given
+		let config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let (_tempdir, page_storage) = temp_storage(&config);
+		let mut t = page_storage.transaction().unwrap();
+		let outer = t.savepoint();
+		for i in 1..=16 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(0, &[1; 4])
+				.unwrap();
+		}
+
+		// when
+		let inner = t.savepoint();
+		for i in 1..=16 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(2, &[2; 4])
+				.unwrap();
+		}
+		t.release(inner).unwrap();
+		let release_result = t.release(inner);
+		t.rollback_to(outer).unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert!(matches!(
+			release_result,
+			Err(StorageError::UnknownSavepoint(..))
+		));
+		let t = page_storage.transaction().unwrap();
+		for i in 1..=16 {
+			let mut data = [0; 6];
+			t.get_page(page_id!(1, i))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [0; 6]);
+		}
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_rollback_to_savepoint_after_spill() {
+		// given
+		let config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let (_tempdir, page_storage) = temp_storage(&config);
+		let mut t = page_storage.transaction().unwrap();
+		for i in 1..=16 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(0, &[1; 4])
+				.unwrap();
+		}
+
+		// when
+		let savepoint = t.savepoint();
+		for i in 1..=32 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(2, &[2; 4])
+				.unwrap();
+		}
+		t.rollback_to(savepoint).unwrap();
+		let mut spilled_data = [0; 6];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut spilled_data)
+			.unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert_buf_eq!(spilled_data, [1, 1, 1, 1, 0, 0]);
+		let t = page_storage.transaction().unwrap();
+		for i in 1..=32 {
+			let mut data = [0; 6];
+			t.get_page(page_id!(1, i))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			let expected = if i <= 16 { [1, 1, 1, 1, 0, 0] } else { [0; 6] };
+			assert_buf_eq!(data, expected);
+		}
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_rollback_nested_savepoints() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 2])
+			.unwrap();
+
+		// when
+		let outer = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(2, &[2; 2])
+			.unwrap();
+		let inner = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(4, &[3; 2])
+			.unwrap();
+		t.rollback_to(inner).unwrap();
+		let mut after_inner = [0; 6];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut after_inner)
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[4; 2])
+			.unwrap();
+		t.rollback_to(outer).unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert_buf_eq!(after_inner, [1, 1, 2, 2, 0, 0]);
+		let t = page_storage.transaction().unwrap();
+		let mut data = [0; 6];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 1, 0, 0, 0, 0]);
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [0; 6]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_release_and_rollback_to_outer_savepoint() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 2])
+			.unwrap();
+		let outer = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(2, &[2; 2])
+			.unwrap();
+		let inner = t.savepoint();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[3; 2])
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[3; 2])
+			.unwrap();
+
+		// when
+		t.release(inner).unwrap();
+		let mut after_release = [0; 4];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut after_release)
+			.unwrap();
+		t.rollback_to(outer).unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert_buf_eq!(after_release, [3, 3, 2, 2]);
+		let t = page_storage.transaction().unwrap();
+		let mut data = [0; 4];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 1, 0, 0]);
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [0; 4]);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn integration_read_only_transactions() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 4])
+			.unwrap();
+		t.commit().unwrap();
+		let wal_size = page_storage.wal.size();
+
+		// when
+		let mut first = page_storage.read_only_transaction().unwrap();
+		let second = page_storage.read_only_transaction().unwrap();
+		let mut first_data = [0; 4];
+		let mut second_data = [0; 4];
+		let first_page = first.get_page(page_id!(1, 1)).unwrap();
+		let second_page = second.get_page(page_id!(1, 1)).unwrap();
+		first_page.read(0, &mut first_data).unwrap();
+		second_page.read(0, &mut second_data).unwrap();
+		mem::drop(first_page);
+		mem::drop(second_page);
+		let write_result = first.get_page_mut(page_id!(1, 1)).map(|_| ());
+		first.commit().unwrap();
+		second.commit().unwrap();
+
+		// then
+		assert_buf_eq!(first_data, [1; 4]);
+		assert_buf_eq!(second_data, [1; 4]);
+		assert!(matches!(
+			write_result,
+			Err(StorageError::ReadOnlyTransaction)
+		));
+		assert_eq!(page_storage.wal.size(), wal_size);
+	}
+
+	#[test]
+	fn integration_apply_config_to_every_kind_of_storage() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().join("durable")));
+		let scratch_folder = Arc::new(DatabaseFolder::open(tempdir.path().join("scratch")));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let mut config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				max_transaction_size: Some(4 * PAGE_SIZE),
+				..Default::default()
+			},
+			..Default::default()
+		};
+		config.segment_page_sizes.set(2..=2, PAGE_SIZE / 2);
+
+		// when
+		let created =
+			PageStorage::create(Arc::clone(&folder), Arc::clone(&thread_pool), &config).unwrap();
+		let created_capacity = created.cache.capacity();
+		mem::drop(created);
+		let opened = PageStorage::open(folder, Arc::clone(&thread_pool), &config).unwrap();
+		let scratch = PageStorage::create_scratch(scratch_folder, thread_pool, &config);
+
+		// then
+		assert_eq!(created_capacity, 8);
+		assert_eq!(opened.cache.capacity(), 8);
+		assert_eq!(scratch.cache.capacity(), 8);
+		assert_eq!(opened.max_transaction_size, Some(4 * PAGE_SIZE));
+		assert_eq!(scratch.max_transaction_size, Some(4 * PAGE_SIZE));
+		assert_eq!(opened.segment_page_sizes().page_size(2), PAGE_SIZE / 2);
+		assert_eq!(scratch.segment_page_sizes().page_size(2), PAGE_SIZE / 2);
+	}
+
+	#[test]
+	fn integration_spill_large_transaction() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let page_storage =
+			PageStorage::create(Arc::clone(&folder), Arc::clone(&thread_pool), &config).unwrap();
+
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		for i in 1..=32 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(0, &[i as u8; 4])
+				.unwrap();
+		}
+		let mut spilled_data = [0; 4];
+		t.get_page(page_id!(1, 1))
+			.unwrap()
+			.read(0, &mut spilled_data)
+			.unwrap();
+		t.commit().unwrap();
+
+		// then
+		assert_buf_eq!(spilled_data, [1; 4]);
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, thread_pool, &config).unwrap();
+		page_storage.recover().unwrap();
+		for i in 1..=32 {
+			let mut data = [0; 4];
+			page_storage
+				.get_page(page_id!(1, i))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [i as u8; 4]);
+		}
+	}
+
+	#[test]
+	fn integration_rollback_spilled_transaction() {
+		// given
+		let config = PageStorageConfig {
+			page_cache: PageCacheConfig {
+				page_cache_size: 8 * PAGE_SIZE,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let (_tempdir, page_storage) = temp_storage(&config);
+
+		// when
+		let mut t = page_storage.transaction().unwrap();
+		for i in 1..=32 {
+			t.get_page_mut(page_id!(1, i))
+				.unwrap()
+				.write(0, &[1, 2, 3, 4])
+				.unwrap();
+		}
+		t.undo().unwrap();
+
+		// then
+		for i in 1..=32 {
+			let mut data = [0; 4];
+			page_storage
+				.get_page(page_id!(1, i))
+				.unwrap()
+				.read(0, &mut data)
+				.unwrap();
+			assert_buf_eq!(data, [0, 0, 0, 0]);
+		}
+	}
+
+	#[test]
+	fn integration_checkpoint() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let page_storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		assert!(page_storage.needs_checkpoint());
+
+		// when
+		page_storage.checkpoint().unwrap();
+
+		// then
+		assert!(!page_storage.needs_checkpoint());
+		let wal_files: Vec<_> = fs::read_dir(tempdir.path().join("wal"))
+			.unwrap()
+			.map(|entry| entry.unwrap().file_name())
+			.collect();
+		assert_eq!(wal_files, ["2"]);
+
+		mem::drop(page_storage);
+		let page_storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+		page_storage.recover().unwrap();
+		let mut data = [0; 4];
+		page_storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn integration_auto_checkpoint() {
+		// given
+		let config = PageStorageConfig {
+			checkpoint: CheckpointPolicy {
+				wal_size: Some(1),
+				dirty_ratio: None,
+				period: None,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		let (_tempdir, page_storage) = temp_storage(&config);
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+
+		// when
+		let first = page_storage.auto_checkpoint().unwrap();
+		let second = page_storage.auto_checkpoint().unwrap();
+
+		// then
+		assert_eq!(first, Some(CheckpointTrigger::WalSize));
+		assert_eq!(second, None);
+		let stats = page_storage.checkpoint_stats();
+		assert_eq!(stats.num_checkpoints, 1);
+		assert_eq!(stats.last_trigger, Some(CheckpointTrigger::WalSize));
+	}
+
+	#[test]
+	fn integration_lock_pages_all_or_nothing() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t1 = page_storage.transaction().unwrap();
+		t1.lock_pages(&[page_id!(1, 3)]).unwrap();
+		let mut t2 = page_storage.transaction().unwrap();
+		t2.lock_pages(&[page_id!(1, 1)]).unwrap();
+
+		thread::scope(|s| {
+			let waiting = s.spawn(move || {
+				t1.lock_pages(&[page_id!(1, 1)]).unwrap();
+				t1.commit().unwrap();
+			});
+			// Give the first transaction time to start waiting for the second.
+			thread::sleep(Duration::from_millis(50));
+
+			// when
+			let result = t2.lock_pages(&[page_id!(1, 3), page_id!(1, 2)]);
+
+			// then
+			assert!(matches!(result, Err(StorageError::Deadlock { .. })));
+			assert!(!page_storage.lock_manager.is_locked(page_id!(1, 2), None));
+			assert!(page_storage.lock_manager.is_locked(page_id!(1, 1), None));
+			t2.undo().unwrap();
+			waiting.join().unwrap();
+		});
+	}
+
+	#[test]
+	fn integration_time_out_on_locked_cache_pages() {
+		// given
+		let (_tempdir, page_storage) = temp_storage(&Default::default());
+		let mut t = page_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 1))
+			.unwrap()
+			.write(0, &[1; 4])
+			.unwrap();
+		t.commit().unwrap();
+		let frame = page_storage.cache.load_mut(page_id!(1, 1)).unwrap();
+
+		// when
+		let deadline = Instant::now() + Duration::from_millis(20);
+		let mut t = page_storage.transaction().unwrap().with_deadline(deadline);
+		let write_result = t.get_page_mut(page_id!(1, 1)).map(|_| ());
+		let token = CancellationToken::new();
+		token.cancel();
+		let reader = page_storage.transaction().unwrap().with_cancellation(token);
+		let read_result = reader.get_page(page_id!(1, 1)).map(|_| ());
+		drop(frame);
+
+		// then
+		assert!(matches!(
+			write_result,
+			Err(StorageError::TimedOut { page_id, .. }) if page_id == page_id!(1, 1)
+		));
+		assert!(Instant::now() >= deadline);
+		assert!(matches!(
+			read_result,
+			Err(StorageError::Cancelled { page_id, .. }) if page_id == page_id!(1, 1)
+		));
+		t.undo().unwrap();
+		reader.undo().unwrap();
+	}
+
+	#[test]
+	fn integration_resume_transaction_ids() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let page_storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		for _ in 0..3 {
+			page_storage.transaction().unwrap().commit().unwrap();
+		}
+		mem::drop(page_storage);
+
+		// when
+		let page_storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+		page_storage.recover().unwrap();
+		let t = page_storage.transaction().unwrap();
+
+		// then
+		assert_eq!(t.id(), 3);
+		t.commit().unwrap();
+	}
+
+	#[test]
+	fn open_with_report() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let config = PageStorageConfig {
+			wal: WalConfig {
+				size_warning_threshold: 8,
+				..Default::default()
+			},
+			..Default::default()
+		};
+		PageStorage::create(Arc::clone(&folder), Arc::clone(&thread_pool), &config).unwrap();
+
+		// when
+		let (_, report) = PageStorage::open_with_report(folder, thread_pool, &config).unwrap();
+
+		// then
+		assert!(matches!(
+			report.warnings.as_slice(),
+			[OpenWarning::LargeWal { threshold: 8, .. }]
+		));
+		assert!(report.sync_primitive.is_some());
+	}
+
+	#[test]
+	fn detect_missing_segment() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3])
+			.unwrap();
+		t.commit().unwrap();
+		mem::drop(storage);
+		fs::remove_file(tempdir.path().join("segments").join("1")).unwrap();
+
+		// when
+		let strict = PageStorage::open_with_report(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		);
+		let (storage, report) = PageStorage::open_with_report(
+			folder,
+			thread_pool,
+			&PageStorageConfig {
+				recreate_missing_segments: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		storage.recover().unwrap();
+
+		// then
+		assert!(matches!(
+			strict,
+			Err(StorageError::MissingSegment {
+				segment_num: 1,
+				num_pages: 1
+			})
+		));
+		assert_eq!(
+			report.warnings,
+			[OpenWarning::MissingSegment {
+				segment_num: 1,
+				num_pages: 1
+			}]
+		);
+		let mut data = [0; 3];
+		storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_eq!(data, [1, 2, 3]);
+	}
+
+	#[test]
+	fn salvage_corrupted_wal() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3])
+			.unwrap();
+		t.commit().unwrap();
+		let corrupt_position = storage.wal_position().unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[4, 5, 6])
+			.unwrap();
+		t.commit().unwrap();
+		mem::drop(storage);
+		// Flip a byte of the checksum of the first item of the second
+		// transaction.
+		let wal_path = tempdir
+			.path()
+			.join("wal")
+			.join(corrupt_position.generation().to_string());
+		let mut wal = fs::read(&wal_path).unwrap();
+		wal[usize::try_from(corrupt_position.offset().get()).unwrap() + 4] ^= 0xff;
+		fs::write(&wal_path, wal).unwrap();
+
+		// when
+		let strict = PageStorage::open(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.and_then(|storage| storage.recover());
+		let (storage, report) = PageStorage::open_with_report(
+			folder,
+			thread_pool,
+			&PageStorageConfig {
+				wal: WalConfig {
+					salvage: true,
+					..Default::default()
+				},
+				..Default::default()
+			},
+		)
+		.unwrap();
+		storage.recover().unwrap();
+
+		// then
+		assert!(matches!(
+			strict,
+			Err(StorageError::File(FileError::ChecksumMismatch))
+		));
+		assert!(matches!(
+			report.warnings.as_slice(),
+			[OpenWarning::SalvagedWal { generation, offset, len, dropped_generations }]
+				if *generation == corrupt_position.generation()
+					&& *offset == corrupt_position.offset().get()
+					&& *len > 0
+					&& dropped_generations.is_empty()
+		));
+		let mut data = [0; 3];
+		storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_eq!(data, [1, 2, 3]);
+	}
+
+	#[test]
+	fn integration_overlay() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let base_storage =
+			PageStorage::create(folder, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		let mut t = base_storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		base_storage.flush_sync().unwrap();
+		mem::drop(base_storage);
+
+		let overlay = Arc::new(OverlayFolder::open(tempdir.path().to_path_buf()).unwrap());
+		let overlay_storage =
+			PageStorage::open(overlay, Arc::clone(&thread_pool), &Default::default()).unwrap();
+		overlay_storage.recover().unwrap();
+
+		// when
+		let mut t = overlay_storage.transaction().unwrap();
+		let mut overlay_data = [0; 4];
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut overlay_data)
+			.unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[5, 6, 7, 8])
+			.unwrap();
+		t.commit().unwrap();
+		overlay_storage.flush_sync().unwrap();
+		mem::drop(overlay_storage);
+
+		// then
+		assert_buf_eq!(overlay_data, [1, 2, 3, 4]);
+
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let base_storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+		base_storage.recover().unwrap();
+		let t = base_storage.transaction().unwrap();
+		let mut base_data = [0; 4];
+		t.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut base_data)
+			.unwrap();
+		assert_buf_eq!(base_data, [1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn integration_in_memory() {
+		// given
+		let folder = Arc::new(OverlayFolder::in_memory());
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let storage = PageStorage::create(
+			Arc::clone(&folder),
+			Arc::clone(&thread_pool),
+			&Default::default(),
+		)
+		.unwrap();
+		let mut t = storage.transaction().unwrap();
+		t.get_page_mut(page_id!(1, 2))
+			.unwrap()
+			.write(0, &[1, 2, 3, 4])
+			.unwrap();
+		t.commit().unwrap();
+		mem::drop(storage);
+
+		// when
+		let storage = PageStorage::open(folder, thread_pool, &Default::default()).unwrap();
+		storage.recover().unwrap();
+
+		// then
+		let mut data = [0; 4];
+		storage
+			.get_page(page_id!(1, 2))
+			.unwrap()
+			.read(0, &mut data)
+			.unwrap();
+		assert_buf_eq!(data, [1, 2, 3, 4]);
+	}
+
+	#[bench]
+	fn bench_write_and_commit(b: &mut Bencher) {
+		let tempdir = tempdir().unwrap();
+
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let thread_pool = Arc::new(ThreadPool::new().unwrap());
+		let page_storage = PageStorage::create(folder, thread_pool, &Default::default()).unwrap();
+
+		const DATA: &[u8] = &[69; 16 * KIB];
+
+		b.iter(|| {
+			let mut t = page_storage.transaction().unwrap();
+
+			let mut page = t.get_page_mut(page_id!(69, 420)).unwrap();
+			page.write(25, DATA).unwrap();
+
+			t.commit().unwrap();
+		})
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test_helpers {
+	pub(crate) use super::custom_records::test_helpers::{HandlerCall, RecordingHandler};
+	pub(crate) use crate::files::test_helpers::page_id;
+	pub(super) use crate::files::test_helpers::wal_index;
+
+	use std::sync::Arc;
+
+	use tempfile::{tempdir, TempDir};
+
+	use futures::executor::ThreadPool;
+
+	use crate::files::DatabaseFolder;
+
+	use super::{PageStorage, PageStorageConfig};
+
+	/// Creates a page storage with `config` in a new temporary directory.
+	///
+	/// The directory is deleted when the returned [`TempDir`] is dropped, so
+	/// it has to be kept alive for as long as the storage is used.
+	pub(crate) fn temp_storage(config: &PageStorageConfig) -> (TempDir, Arc<PageStorage>) {
+		let tempdir = tempdir().unwrap();
+		let folder = Arc::new(DatabaseFolder::open(tempdir.path().to_path_buf()));
+		let storage =
+			PageStorage::create(folder, Arc::new(ThreadPool::new().unwrap()), config).unwrap();
+		(tempdir, storage)
+	}
 }
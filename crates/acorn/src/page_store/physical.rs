@@ -1,18 +1,37 @@
-use std::{collections::HashMap, mem, sync::Arc};
+use std::{
+	cmp,
+	collections::{BTreeMap, BTreeSet, HashMap},
+	mem,
+	num::NonZeroU16,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	time::Instant,
+};
 
 #[cfg(test)]
 use mockall::automock;
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use static_assertions::assert_impl_all;
 
 use crate::{
 	consts::DEFAULT_MAX_NUM_OPEN_SEGMENTS,
-	files::{segment::SegmentFileApi, DatabaseFolder, DatabaseFolderApi},
-	utils::cache::CacheReplacer,
+	files::{
+		segment::{SegmentFileApi, PAGE_BODY_SIZE},
+		DatabaseFolder, DatabaseFolderApi, Durability, FileError,
+	},
+	utils::{
+		cache::{CacheReplacer, EvictionPolicy},
+		histogram::{AtomicLatencyHistogram, LatencyHistogram},
+	},
 };
 
-use super::{PageId, StorageError, WalIndex};
+use super::{
+	check::{CheckProblem, CheckReport},
+	PageId, StorageError, WalIndex,
+};
 
 pub(crate) struct PhysicalStorage<DF = DatabaseFolder>
 where
@@ -20,19 +39,79 @@ where
 {
 	folder: Arc<DF>,
 	descriptor_cache: RwLock<DescriptorCache<DF>>,
+	segment_counters: RwLock<HashMap<u32, Arc<SegmentCounters>>>,
+	durability: Durability,
+	/// The segments that were written to since they were last synced.
+	unsynced_segments: Mutex<BTreeSet<u32>>,
 }
 
 assert_impl_all!(PhysicalStorage: Send, Sync);
 
+/// I/O statistics of a single segment, since the database was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentIoStats {
+	pub reads: u64,
+	pub writes: u64,
+	pub bytes_read: u64,
+	pub bytes_written: u64,
+	pub failed_reads: u64,
+	pub failed_writes: u64,
+	pub read_latency: LatencyHistogram,
+	pub write_latency: LatencyHistogram,
+}
+
+#[derive(Debug, Default)]
+struct SegmentCounters {
+	reads: AtomicU64,
+	writes: AtomicU64,
+	bytes_read: AtomicU64,
+	bytes_written: AtomicU64,
+	failed_reads: AtomicU64,
+	failed_writes: AtomicU64,
+	read_latency: AtomicLatencyHistogram,
+	write_latency: AtomicLatencyHistogram,
+}
+
+impl SegmentCounters {
+	fn load(&self) -> SegmentIoStats {
+		SegmentIoStats {
+			reads: self.reads.load(Ordering::Relaxed),
+			writes: self.writes.load(Ordering::Relaxed),
+			bytes_read: self.bytes_read.load(Ordering::Relaxed),
+			bytes_written: self.bytes_written.load(Ordering::Relaxed),
+			failed_reads: self.failed_reads.load(Ordering::Relaxed),
+			failed_writes: self.failed_writes.load(Ordering::Relaxed),
+			read_latency: self.read_latency.load(),
+			write_latency: self.write_latency.load(),
+		}
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct PhysicalStorageConfig {
+	/// How many segment files are kept open at most. Once the limit is
+	/// reached, the least recently used file is closed, and opened again
+	/// when it is used next.
 	pub max_num_open_segments: usize,
+	pub durability: Durability,
+}
+
+impl PhysicalStorageConfig {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if self.max_num_open_segments == 0 {
+			return Err(StorageError::InvalidConfig(
+				"At least one segment file has to be kept open".to_string(),
+			));
+		}
+		Ok(())
+	}
 }
 
 impl Default for PhysicalStorageConfig {
 	fn default() -> Self {
 		Self {
 			max_num_open_segments: DEFAULT_MAX_NUM_OPEN_SEGMENTS,
+			durability: Durability::default(),
 		}
 	}
 }
@@ -46,7 +125,28 @@ where
 		Self {
 			folder,
 			descriptor_cache,
+			segment_counters: RwLock::new(HashMap::new()),
+			durability: config.durability,
+			unsynced_segments: Mutex::new(BTreeSet::new()),
+		}
+	}
+
+	fn mark_unsynced(&self, segment_num: u32) {
+		if self.durability.syncs() {
+			self.unsynced_segments.lock().insert(segment_num);
+		}
+	}
+
+	fn counters(&self, segment_num: u32) -> Arc<SegmentCounters> {
+		if let Some(counters) = self.segment_counters.read().get(&segment_num) {
+			return Arc::clone(counters);
 		}
+		Arc::clone(
+			self.segment_counters
+				.write()
+				.entry(segment_num)
+				.or_default(),
+		)
 	}
 
 	fn use_segment<T>(
@@ -62,11 +162,84 @@ where
 
 		let segment_file = self.folder.open_segment_file(segment_num)?;
 		let mut cache_mut = self.descriptor_cache.write();
-		let segment_file = cache_mut.store_descriptor(segment_num, segment_file);
-		handler(segment_file)
+		// Another thread may have opened the segment in the meantime.
+		if !cache_mut.has_descriptor(segment_num) {
+			cache_mut.store_descriptor(segment_num, segment_file);
+		}
+		handler(cache_mut.get_descriptor(segment_num).unwrap())
+	}
+
+	/// Reads consecutive pages in the same segment, starting at `first_page`,
+	/// with a single read. If a checksum doesn't match, the pages are read
+	/// again one by one to find out which one is corrupted.
+	fn read_run(
+		&self,
+		first_page: PageId,
+		bufs: &mut [u8],
+	) -> Result<Vec<Option<WalIndex>>, StorageError> {
+		let counters = self.counters(first_page.segment_num);
+		let result = self.use_segment(first_page.segment_num, |segment| {
+			let start = Instant::now();
+			let wal_indices = segment.read_pages(first_page.page_num, bufs)?;
+			counters.read_latency.record(start.elapsed());
+			Ok(wal_indices)
+		});
+		match result {
+			Ok(wal_indices) => {
+				counters
+					.reads
+					.fetch_add(wal_indices.len() as u64, Ordering::Relaxed);
+				counters
+					.bytes_read
+					.fetch_add(bufs.len() as u64, Ordering::Relaxed);
+				Ok(wal_indices)
+			}
+			Err(StorageError::File(FileError::ChecksumMismatch)) => bufs
+				.chunks_exact_mut(PAGE_BODY_SIZE)
+				.zip(first_page.page_num.get()..)
+				.map(|(buf, page_num)| {
+					self.read(ReadOp {
+						page_id: PageId::new(
+							first_page.segment_num,
+							NonZeroU16::new(page_num).unwrap(),
+						),
+						buf,
+					})
+				})
+				.collect(),
+			Err(err) => {
+				counters.failed_reads.fetch_add(1, Ordering::Relaxed);
+				Err(err)
+			}
+		}
+	}
+
+	/// Reads the pages of a segment in batches, and calls `handle` with the
+	/// pages of each batch and the result of reading them.
+	fn read_segment_pages(
+		&self,
+		segment_num: u32,
+		page_nums: impl Iterator<Item = NonZeroU16>,
+		mut handle: impl FnMut(&[NonZeroU16], Result<(), StorageError>) -> Result<(), StorageError>,
+	) -> Result<(), StorageError> {
+		let page_nums: Vec<NonZeroU16> = page_nums.collect();
+		let mut bufs = vec![0; SCAN_BATCH_SIZE * PAGE_BODY_SIZE];
+		for batch in page_nums.chunks(SCAN_BATCH_SIZE) {
+			let mut pages: Vec<(PageId, &mut [u8])> = batch
+				.iter()
+				.map(|page_num| PageId::new(segment_num, *page_num))
+				.zip(bufs.chunks_exact_mut(PAGE_BODY_SIZE))
+				.collect();
+			let result = self.read_pages(&mut pages).map(|_| ());
+			handle(batch, result)?;
+		}
+		Ok(())
 	}
 }
 
+/// The number of pages that are read at once when scanning a segment.
+const SCAN_BATCH_SIZE: usize = 64;
+
 #[derive(Debug)]
 pub(crate) struct ReadOp<'a> {
 	pub page_id: PageId,
@@ -80,27 +253,313 @@ pub(crate) struct WriteOp<'a> {
 	pub buf: &'a [u8],
 }
 
+/// A write of consecutive pages in the same segment, starting at
+/// `first_page`. `bufs` contains the bodies of all pages back to back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WriteRunOp<'a> {
+	pub first_page: PageId,
+	pub bufs: &'a [u8],
+	pub wal_indices: &'a [WalIndex],
+}
+
 #[cfg_attr(test, automock)]
 #[allow(clippy::needless_lifetimes)]
 pub(crate) trait PhysicalStorageApi {
 	fn read<'a>(&self, op: ReadOp<'a>) -> Result<Option<WalIndex>, StorageError>;
 
+	/// Reads the pages into their buffers, with a single read for each run of
+	/// consecutive pages, and returns the WAL indices of their last writes in
+	/// the same order.
+	fn read_pages<'a, 'b>(
+		&self,
+		pages: &'a mut [(PageId, &'b mut [u8])],
+	) -> Result<Vec<Option<WalIndex>>, StorageError>;
+
 	fn write<'a>(&self, op: WriteOp<'a>) -> Result<(), StorageError>;
+
+	fn write_run<'a>(&self, op: WriteRunOp<'a>) -> Result<(), StorageError>;
+
+	fn segment_stats(&self) -> BTreeMap<u32, SegmentIoStats>;
+
+	/// The pages that were written to storage at least once, in ascending
+	/// order, along with the WAL index of their last write.
+	fn initialized_pages(&self) -> Result<Vec<(PageId, WalIndex)>, StorageError>;
+
+	/// The numbers of the segments that have a file, in ascending order.
+	fn segment_nums(&self) -> Result<Vec<u32>, StorageError>;
+
+	/// Reads every page that was written to storage, and reports those that
+	/// can't be read back intact, without stopping at the first one.
+	fn check(&self) -> Result<CheckReport, StorageError>;
+
+	/// Like [`Self::check`], but only reads the given pages.
+	fn check_pages(&self, page_ids: &[PageId]) -> Result<CheckReport, StorageError>;
+
+	/// Reads every page of the segment that was written to storage, and fails
+	/// on the first one that can't be read back intact.
+	fn verify_segment(&self, segment_num: u32) -> Result<(), StorageError>;
+
+	/// Shrinks the segment files so that `end` is the first page past them,
+	/// and returns the number of bytes that were reclaimed.
+	fn truncate(&self, end: PageId) -> Result<u64, StorageError>;
+
+	/// Shrinks the file of the segment to no pages at all, and returns the
+	/// number of bytes that were reclaimed.
+	fn clear_segment(&self, segment_num: u32) -> Result<u64, StorageError>;
+
+	/// Waits until all pages written so far are durably stored, if the
+	/// durability mode requires it.
+	fn sync(&self) -> Result<(), StorageError>;
 }
 
 impl<DF: DatabaseFolderApi> PhysicalStorageApi for PhysicalStorage<DF> {
 	fn read(&self, op: ReadOp) -> Result<Option<WalIndex>, StorageError> {
-		self.use_segment(op.page_id.segment_num, |segment| {
-			let wal_index = segment.read(op.page_id.page_num, op.buf)?;
+		let counters = self.counters(op.page_id.segment_num);
+		let len = op.buf.len() as u64;
+		let result = self.use_segment(op.page_id.segment_num, |segment| {
+			let start = Instant::now();
+			let wal_index = match segment.read(op.page_id.page_num, op.buf) {
+				Err(FileError::ChecksumMismatch) => {
+					return Err(StorageError::ChecksumMismatch(op.page_id))
+				}
+				result => result?,
+			};
+			counters.read_latency.record(start.elapsed());
 			Ok(wal_index)
-		})
+		});
+		match result {
+			Ok(..) => {
+				counters.reads.fetch_add(1, Ordering::Relaxed);
+				counters.bytes_read.fetch_add(len, Ordering::Relaxed);
+			}
+			Err(..) => {
+				counters.failed_reads.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+		result
+	}
+
+	fn read_pages(
+		&self,
+		pages: &mut [(PageId, &mut [u8])],
+	) -> Result<Vec<Option<WalIndex>>, StorageError> {
+		let mut order: Vec<(PageId, usize)> = pages
+			.iter()
+			.enumerate()
+			.map(|(i, (page_id, _))| (*page_id, i))
+			.collect();
+		order.sort_unstable();
+		let mut wal_indices = vec![None; pages.len()];
+		let mut bufs = Vec::new();
+		for run in order.chunk_by(|(prev, _), (next, _)| {
+			prev.segment_num == next.segment_num
+				&& prev.page_num.checked_add(1) == Some(next.page_num)
+		}) {
+			bufs.resize(run.len() * PAGE_BODY_SIZE, 0);
+			let run_indices = self.read_run(run[0].0, &mut bufs)?;
+			for (((_, i), buf), wal_index) in run
+				.iter()
+				.zip(bufs.chunks_exact(PAGE_BODY_SIZE))
+				.zip(run_indices)
+			{
+				pages[*i].1.copy_from_slice(buf);
+				wal_indices[*i] = wal_index;
+			}
+		}
+		Ok(wal_indices)
 	}
 
 	fn write(&self, op: WriteOp) -> Result<(), StorageError> {
-		self.use_segment(op.page_id.segment_num, |segment| {
+		let counters = self.counters(op.page_id.segment_num);
+		let result = self.use_segment(op.page_id.segment_num, |segment| {
+			let start = Instant::now();
 			segment.write(op.page_id.page_num, op.buf, op.wal_index)?;
+			counters.write_latency.record(start.elapsed());
 			Ok(())
-		})
+		});
+		match result {
+			Ok(()) => {
+				self.mark_unsynced(op.page_id.segment_num);
+				counters.writes.fetch_add(1, Ordering::Relaxed);
+				counters
+					.bytes_written
+					.fetch_add(op.buf.len() as u64, Ordering::Relaxed);
+			}
+			Err(..) => {
+				counters.failed_writes.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+		result
+	}
+
+	fn write_run(&self, op: WriteRunOp) -> Result<(), StorageError> {
+		let counters = self.counters(op.first_page.segment_num);
+		let result = self.use_segment(op.first_page.segment_num, |segment| {
+			let start = Instant::now();
+			segment.write_pages(op.first_page.page_num, op.bufs, op.wal_indices)?;
+			counters.write_latency.record(start.elapsed());
+			Ok(())
+		});
+		match result {
+			Ok(()) => {
+				self.mark_unsynced(op.first_page.segment_num);
+				counters
+					.writes
+					.fetch_add(op.wal_indices.len() as u64, Ordering::Relaxed);
+				counters
+					.bytes_written
+					.fetch_add(op.bufs.len() as u64, Ordering::Relaxed);
+			}
+			Err(..) => {
+				counters.failed_writes.fetch_add(1, Ordering::Relaxed);
+			}
+		}
+		result
+	}
+
+	fn segment_stats(&self) -> BTreeMap<u32, SegmentIoStats> {
+		self.segment_counters
+			.read()
+			.iter()
+			.map(|(segment_num, counters)| (*segment_num, counters.load()))
+			.collect()
+	}
+
+	fn initialized_pages(&self) -> Result<Vec<(PageId, WalIndex)>, StorageError> {
+		let mut pages = Vec::new();
+		for segment_num in self.folder.segment_nums()? {
+			let segment_pages =
+				self.use_segment(segment_num, |segment| Ok(segment.initialized_pages()?))?;
+			pages.extend(
+				segment_pages
+					.into_iter()
+					.map(|(page_num, wal_index)| (PageId::new(segment_num, page_num), wal_index)),
+			);
+		}
+		Ok(pages)
+	}
+
+	fn segment_nums(&self) -> Result<Vec<u32>, StorageError> {
+		Ok(self.folder.segment_nums()?)
+	}
+
+	fn check(&self) -> Result<CheckReport, StorageError> {
+		let mut report = CheckReport::default();
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		for segment_num in self.folder.segment_nums()? {
+			let segment_pages =
+				match self.use_segment(segment_num, |segment| Ok(segment.initialized_pages()?)) {
+					Ok(segment_pages) => segment_pages,
+					Err(err) => {
+						report.problems.push(CheckProblem::UnreadableSegment {
+							segment_num,
+							reason: err.to_string(),
+						});
+						continue;
+					}
+				};
+			report.num_segments += 1;
+			let page_nums = segment_pages.into_iter().map(|(page_num, _)| page_num);
+			self.read_segment_pages(segment_num, page_nums, |batch, result| {
+				report.num_pages += batch.len();
+				if result.is_ok() {
+					return Ok(());
+				}
+				// Read the pages of the batch one by one to find all that are
+				// broken.
+				for page_num in batch {
+					let page_id = PageId::new(segment_num, *page_num);
+					match self.read(ReadOp {
+						page_id,
+						buf: &mut buf,
+					}) {
+						Ok(..) => (),
+						Err(StorageError::ChecksumMismatch(page_id)) => {
+							report
+								.problems
+								.push(CheckProblem::ChecksumMismatch(page_id));
+						}
+						Err(err) => report.problems.push(CheckProblem::UnreadablePage {
+							page_id,
+							reason: err.to_string(),
+						}),
+					}
+				}
+				Ok(())
+			})?;
+		}
+		Ok(report)
+	}
+
+	fn check_pages(&self, page_ids: &[PageId]) -> Result<CheckReport, StorageError> {
+		let mut report = CheckReport {
+			num_pages: page_ids.len(),
+			..Default::default()
+		};
+		let mut segment_nums: Vec<u32> =
+			page_ids.iter().map(|page_id| page_id.segment_num).collect();
+		segment_nums.sort_unstable();
+		segment_nums.dedup();
+		report.num_segments = segment_nums.len();
+
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		for page_id in page_ids.iter().copied() {
+			match self.read(ReadOp {
+				page_id,
+				buf: &mut buf,
+			}) {
+				Ok(..) => (),
+				Err(StorageError::ChecksumMismatch(page_id)) => {
+					report
+						.problems
+						.push(CheckProblem::ChecksumMismatch(page_id));
+				}
+				Err(err) => report.problems.push(CheckProblem::UnreadablePage {
+					page_id,
+					reason: err.to_string(),
+				}),
+			}
+		}
+		Ok(report)
+	}
+
+	fn verify_segment(&self, segment_num: u32) -> Result<(), StorageError> {
+		let segment_pages =
+			self.use_segment(segment_num, |segment| Ok(segment.initialized_pages()?))?;
+		let page_nums = segment_pages.into_iter().map(|(page_num, _)| page_num);
+		self.read_segment_pages(segment_num, page_nums, |_, result| result)
+	}
+
+	fn truncate(&self, end: PageId) -> Result<u64, StorageError> {
+		let mut reclaimed = 0;
+		for segment_num in self.folder.segment_nums()? {
+			let num_pages = match segment_num.cmp(&end.segment_num) {
+				cmp::Ordering::Less => continue,
+				cmp::Ordering::Equal => end.page_num.get() - 1,
+				cmp::Ordering::Greater => 0,
+			};
+			reclaimed +=
+				self.use_segment(segment_num, |segment| Ok(segment.truncate(num_pages)?))?;
+		}
+		Ok(reclaimed)
+	}
+
+	fn clear_segment(&self, segment_num: u32) -> Result<u64, StorageError> {
+		self.use_segment(segment_num, |segment| Ok(segment.truncate(0)?))
+	}
+
+	fn sync(&self) -> Result<(), StorageError> {
+		let segment_nums = mem::take(&mut *self.unsynced_segments.lock());
+		for (i, segment_num) in segment_nums.iter().enumerate() {
+			if let Err(err) = self.use_segment(*segment_num, |segment| Ok(segment.sync()?)) {
+				// Keep the segments that weren't synced for the next attempt
+				self.unsynced_segments
+					.lock()
+					.extend(segment_nums.iter().skip(i));
+				return Err(err);
+			}
+		}
+		Ok(())
 	}
 }
 
@@ -113,7 +572,7 @@ struct DescriptorCache<DF: DatabaseFolderApi> {
 impl<DF: DatabaseFolderApi> DescriptorCache<DF> {
 	fn new(config: &PhysicalStorageConfig) -> Self {
 		let descriptors = HashMap::with_capacity(config.max_num_open_segments);
-		let replacer = CacheReplacer::new(config.max_num_open_segments);
+		let replacer = CacheReplacer::new(EvictionPolicy::default(), config.max_num_open_segments);
 		Self {
 			descriptors,
 			replacer,
@@ -121,6 +580,10 @@ impl<DF: DatabaseFolderApi> DescriptorCache<DF> {
 		}
 	}
 
+	pub fn has_descriptor(&self, segment_num: u32) -> bool {
+		self.descriptors.contains_key(&segment_num)
+	}
+
 	pub fn get_descriptor(&self, segment_num: u32) -> Option<&DF::SegmentFile> {
 		let descriptor = self.descriptors.get(&segment_num)?;
 		let access_successful = self.replacer.access(&segment_num);
@@ -194,6 +657,153 @@ mod tests {
 			.unwrap();
 	}
 
+	#[test]
+	fn reopen_segments_beyond_open_limit() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_segment_file()
+			.times(2)
+			.with(eq(1))
+			.returning(|_| {
+				let mut segment = MockSegmentFileApi::new();
+				segment.expect_write().returning(|_, _, _| Ok(()));
+				Ok(segment)
+			});
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(2))
+			.returning(|_| {
+				let mut segment = MockSegmentFileApi::new();
+				segment.expect_write().returning(|_, _, _| Ok(()));
+				Ok(segment)
+			});
+
+		// given
+		let storage = PhysicalStorage::new(
+			Arc::new(folder),
+			&PhysicalStorageConfig {
+				max_num_open_segments: 1,
+				..Default::default()
+			},
+		);
+
+		// when
+		for page_id in [
+			page_id!(1, 1),
+			page_id!(1, 2),
+			page_id!(2, 1),
+			page_id!(1, 1),
+		] {
+			storage
+				.write(WriteOp {
+					page_id,
+					buf: &[1; PAGE_BODY_SIZE],
+					wal_index: wal_index!(1, 1),
+				})
+				.unwrap();
+		}
+	}
+
+	#[test]
+	fn read_consecutive_pages_at_once() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(69))
+			.returning(|_| {
+				let mut segment = MockSegmentFileApi::new();
+				segment
+					.expect_read_pages()
+					.once()
+					.withf(|first_page, bufs| {
+						*first_page == non_zero!(1) && bufs.len() == 2 * PAGE_BODY_SIZE
+					})
+					.returning(|_, bufs| {
+						bufs.fill(1);
+						Ok(vec![Some(wal_index!(1, 1)), None])
+					});
+				segment
+					.expect_read_pages()
+					.once()
+					.withf(|first_page, bufs| {
+						*first_page == non_zero!(5) && bufs.len() == PAGE_BODY_SIZE
+					})
+					.returning(|_, bufs| {
+						bufs.fill(2);
+						Ok(vec![Some(wal_index!(1, 5))])
+					});
+				Ok(segment)
+			});
+
+		// given
+		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+		let mut bufs = [[0; PAGE_BODY_SIZE]; 3];
+		let [first, second, third] = &mut bufs;
+
+		// when
+		let wal_indices = storage
+			.read_pages(&mut [
+				(page_id!(69, 5), third),
+				(page_id!(69, 2), second),
+				(page_id!(69, 1), first),
+			])
+			.unwrap();
+
+		// then
+		assert_eq!(
+			wal_indices,
+			vec![Some(wal_index!(1, 5)), None, Some(wal_index!(1, 1))]
+		);
+		assert_eq!(
+			bufs,
+			[
+				[1; PAGE_BODY_SIZE],
+				[1; PAGE_BODY_SIZE],
+				[2; PAGE_BODY_SIZE]
+			]
+		);
+	}
+
+	#[test]
+	fn sync_written_segments() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder
+			.expect_open_segment_file()
+			.once()
+			.with(eq(69))
+			.returning(|_| {
+				let mut segment = MockSegmentFileApi::new();
+				segment.expect_write().returning(|_, _, _| Ok(()));
+				segment.expect_sync().once().returning(|| Ok(()));
+				Ok(segment)
+			});
+
+		// given
+		let storage = PhysicalStorage::new(
+			Arc::new(folder),
+			&PhysicalStorageConfig {
+				durability: Durability::Sync,
+				..Default::default()
+			},
+		);
+
+		// when
+		storage
+			.write(WriteOp {
+				page_id: page_id!(69, 420),
+				buf: &[1; PAGE_BODY_SIZE],
+				wal_index: wal_index!(69, 420),
+			})
+			.unwrap();
+		storage.sync().unwrap();
+		storage.sync().unwrap();
+	}
+
 	#[test]
 	fn read_from_storage() {
 		// expect
@@ -231,4 +841,119 @@ mod tests {
 		assert_eq!(wal_index, Some(wal_index!(69, 420)));
 		assert_eq!(buf[0..3], [1, 2, 3]);
 	}
+
+	#[test]
+	fn report_corrupted_page() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_open_segment_file().returning(|_| {
+			let mut segment = MockSegmentFileApi::new();
+			segment
+				.expect_read()
+				.returning(|_, _| Err(FileError::ChecksumMismatch));
+			Ok(segment)
+		});
+
+		// given
+		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+
+		// when
+		let result = storage.read(ReadOp {
+			page_id: page_id!(69, 420),
+			buf: &mut [0; PAGE_BODY_SIZE],
+		});
+
+		// then
+		assert!(matches!(
+			result,
+			Err(StorageError::ChecksumMismatch(page_id)) if page_id == page_id!(69, 420)
+		));
+	}
+
+	#[test]
+	fn check_all_pages() {
+		// given
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_segment_nums().returning(|| Ok(vec![1, 2]));
+		folder.expect_open_segment_file().returning(|segment_num| {
+			if segment_num == 2 {
+				return Err(FileError::UnexpectedEof);
+			}
+			let mut segment = MockSegmentFileApi::new();
+			segment.expect_initialized_pages().returning(|| {
+				Ok(vec![
+					(non_zero!(1), wal_index!(1, 1)),
+					(non_zero!(2), wal_index!(1, 2)),
+				])
+			});
+			segment
+				.expect_read_pages()
+				.returning(|_, _| Err(FileError::ChecksumMismatch));
+			segment.expect_read().returning(|page_num, _| {
+				if page_num.get() == 2 {
+					return Err(FileError::ChecksumMismatch);
+				}
+				Ok(Some(wal_index!(1, 1)))
+			});
+			Ok(segment)
+		});
+		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+
+		// when
+		let report = storage.check().unwrap();
+
+		// then
+		assert_eq!(report.num_segments, 1);
+		assert_eq!(report.num_pages, 2);
+		assert_eq!(report.problems.len(), 2);
+		assert_eq!(
+			report.problems[0],
+			CheckProblem::ChecksumMismatch(page_id!(1, 2))
+		);
+		assert!(matches!(
+			report.problems[1],
+			CheckProblem::UnreadableSegment { segment_num: 2, .. }
+		));
+	}
+
+	#[test]
+	fn count_segment_io() {
+		// given
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_open_segment_file().returning(|_| {
+			let mut segment = MockSegmentFileApi::new();
+			segment.expect_read().returning(|_, _| Ok(None));
+			segment.expect_write().returning(|_, _, _| Ok(()));
+			Ok(segment)
+		});
+		let storage = PhysicalStorage::new(Arc::new(folder), &Default::default());
+
+		// when
+		for _ in 0..2 {
+			storage
+				.read(ReadOp {
+					page_id: page_id!(1, 1),
+					buf: &mut [0; PAGE_BODY_SIZE],
+				})
+				.unwrap();
+		}
+		storage
+			.write(WriteOp {
+				page_id: page_id!(2, 1),
+				buf: &[1; PAGE_BODY_SIZE],
+				wal_index: wal_index!(1, 1),
+			})
+			.unwrap();
+
+		// then
+		let stats = storage.segment_stats();
+		assert_eq!(stats.len(), 2);
+		assert_eq!(stats[&1].reads, 2);
+		assert_eq!(stats[&1].bytes_read, 2 * PAGE_BODY_SIZE as u64);
+		assert_eq!(stats[&1].read_latency.count(), 2);
+		assert_eq!(stats[&1].writes, 0);
+		assert_eq!(stats[&2].writes, 1);
+		assert_eq!(stats[&2].bytes_written, PAGE_BODY_SIZE as u64);
+		assert_eq!(stats[&2].write_latency.count(), 1);
+	}
 }
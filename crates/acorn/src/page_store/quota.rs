@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+
+use parking_lot::Mutex;
+
+use crate::files::segment::SEGMENT_SIZE;
+
+use super::StorageError;
+
+/// Limits on how much storage a database may use. Segment files count with
+/// their full size, whether or not all of their pages were written to; the
+/// WAL isn't counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Quota {
+	pub max_size: Option<u64>,
+	pub max_segments: Option<u32>,
+}
+
+impl Quota {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if self.max_num_segments() == Some(0) {
+			return Err(StorageError::InvalidConfig(format!(
+				"The quota has to allow for at least one segment of {SEGMENT_SIZE} bytes"
+			)));
+		}
+		Ok(())
+	}
+
+	/// The number of segments that may be used, if it is limited.
+	fn max_num_segments(&self) -> Option<usize> {
+		let by_size = self
+			.max_size
+			.map(|max_size| usize::try_from(max_size / SEGMENT_SIZE as u64).unwrap_or(usize::MAX));
+		let by_count = self
+			.max_segments
+			.map(|max_segments| usize::try_from(max_segments).unwrap_or(usize::MAX));
+		match (by_size, by_count) {
+			(Some(by_size), Some(by_count)) => Some(by_size.min(by_count)),
+			(by_size, by_count) => by_size.or(by_count),
+		}
+	}
+}
+
+/// Enforces a [`Quota`] when transactions start writing to a segment, so
+/// that the writes of a committed transaction never fail for lack of space
+/// later on.
+///
+/// The segments in use are counted from the segment files once the first
+/// page is written, so the count carries over a restart. From then on, a
+/// segment counts as soon as a transaction writes to it, even if the
+/// transaction is aborted, until the storage is opened again.
+#[derive(Debug, Default)]
+pub(super) struct QuotaTracker {
+	max_num_segments: Option<usize>,
+	/// The segments in use, once they were counted.
+	segments: Mutex<Option<BTreeSet<u32>>>,
+}
+
+impl QuotaTracker {
+	pub fn new(quota: &Quota) -> Self {
+		Self {
+			max_num_segments: quota.max_num_segments(),
+			segments: Mutex::new(None),
+		}
+	}
+
+	/// Fails with [`StorageError::QuotaExceeded`] if writing to the segment
+	/// would exceed the quota. The segments that are in use are listed with
+	/// `in_use` the first time.
+	pub fn claim(
+		&self,
+		segment_num: u32,
+		in_use: impl FnOnce() -> Result<Vec<u32>, StorageError>,
+	) -> Result<(), StorageError> {
+		let Some(max_segments) = self.max_num_segments else {
+			return Ok(());
+		};
+		let mut segments = self.segments.lock();
+		let segments = match &mut *segments {
+			Some(segments) => segments,
+			None => segments.insert(in_use()?.into_iter().collect()),
+		};
+		if segments.contains(&segment_num) {
+			return Ok(());
+		}
+		if segments.len() >= max_segments {
+			return Err(StorageError::QuotaExceeded {
+				segment_num,
+				max_segments,
+			});
+		}
+		segments.insert(segment_num);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn limit_segments_by_count_and_size() {
+		// given
+		let quota = Quota {
+			max_size: Some(3 * SEGMENT_SIZE as u64 + 1),
+			max_segments: Some(4),
+		};
+		let tracker = QuotaTracker::new(&quota);
+
+		// when
+		tracker.claim(1, || Ok(vec![1, 2])).unwrap();
+		tracker.claim(5, || unreachable!()).unwrap();
+		tracker.claim(2, || unreachable!()).unwrap();
+		let exceeded = tracker.claim(6, || unreachable!());
+
+		// then
+		assert!(matches!(
+			exceeded,
+			Err(StorageError::QuotaExceeded {
+				segment_num: 6,
+				max_segments: 3
+			})
+		));
+		assert!(Quota {
+			max_size: Some(1),
+			max_segments: None
+		}
+		.validate()
+		.is_err());
+	}
+}
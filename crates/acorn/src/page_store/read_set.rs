@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+
+use super::PageId;
+
+/// The pages that a transaction read, along with the sequence number of the
+/// last commit at the time it first read each of them.
+///
+/// Transactions that track their reads verify on commit that none of these
+/// pages were modified since, which makes all of their reads repeatable.
+#[derive(Debug)]
+pub(super) struct ReadSet {
+	/// The sequence number at which the version store started tracking
+	/// modifications for this transaction.
+	pub tracking_seq: u64,
+	pages: Mutex<HashMap<PageId, u64>>,
+}
+
+impl ReadSet {
+	pub fn new(tracking_seq: u64) -> Self {
+		Self {
+			tracking_seq,
+			pages: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Records a read of the page, unless it was read before.
+	pub fn record(&self, page_id: PageId, seq: u64) {
+		self.pages.lock().entry(page_id).or_insert(seq);
+	}
+
+	/// Returns the pages that were read, in ascending order.
+	pub fn pages(&self) -> Vec<(PageId, u64)> {
+		let mut pages: Vec<(PageId, u64)> = self
+			.pages
+			.lock()
+			.iter()
+			.map(|(page_id, seq)| (*page_id, *seq))
+			.collect();
+		pages.sort_unstable();
+		pages
+	}
+}
@@ -0,0 +1,177 @@
+use std::{collections::HashSet, sync::Arc, time::Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::PageId;
+
+/// Keeps track of the pages that are currently being read from storage, so
+/// that threads that miss the cache on the same page at the same time only
+/// read it once.
+#[derive(Debug, Default)]
+pub(super) struct InFlightReads {
+	pages: Mutex<HashSet<PageId>>,
+	finished: Condvar,
+}
+
+impl InFlightReads {
+	/// Registers a read of the page, unless another thread is already reading
+	/// it. In that case, waits until that read has finished, and returns
+	/// `None`; the page is then either in the cache, or the read failed.
+	pub fn begin(&self, page_id: PageId) -> Option<InFlightRead<'_>> {
+		let mut pages = self.pages.lock();
+		if pages.insert(page_id) {
+			return Some(InFlightRead {
+				reads: self,
+				page_id,
+			});
+		}
+		while pages.contains(&page_id) {
+			self.finished.wait(&mut pages);
+		}
+		None
+	}
+
+	/// Like [`Self::begin`], but stops waiting for the read of another thread
+	/// at `until`, in which case it returns `Err(StillReading)`.
+	pub fn begin_until(
+		&self,
+		page_id: PageId,
+		until: Instant,
+	) -> Result<Option<InFlightRead<'_>>, StillReading> {
+		let mut pages = self.pages.lock();
+		if pages.insert(page_id) {
+			return Ok(Some(InFlightRead {
+				reads: self,
+				page_id,
+			}));
+		}
+		while pages.contains(&page_id) {
+			if self.finished.wait_until(&mut pages, until).timed_out() {
+				return Err(StillReading);
+			}
+		}
+		Ok(None)
+	}
+
+	/// Registers a read of the page, unless another thread is already reading
+	/// it. Unlike [`Self::begin`], doesn't wait for that read to finish.
+	pub fn try_begin(&self, page_id: PageId) -> Option<InFlightRead<'_>> {
+		if !self.pages.lock().insert(page_id) {
+			return None;
+		}
+		Some(InFlightRead {
+			reads: self,
+			page_id,
+		})
+	}
+
+	/// Like [`Self::try_begin`], but the returned read keeps the reads alive,
+	/// so that it can be finished by a background task.
+	pub fn try_begin_owned(self: &Arc<Self>, page_id: PageId) -> Option<OwnedInFlightRead> {
+		if !self.pages.lock().insert(page_id) {
+			return None;
+		}
+		Some(OwnedInFlightRead {
+			reads: Arc::clone(self),
+			page_id,
+		})
+	}
+
+	fn finish(&self, page_id: PageId) {
+		self.pages.lock().remove(&page_id);
+		self.finished.notify_all();
+	}
+}
+
+/// Another thread was still reading the page at the time limit, see
+/// [`InFlightReads::begin_until`].
+#[derive(Debug)]
+pub(super) struct StillReading;
+
+/// Marks a page as being read until it is dropped.
+pub(super) struct InFlightRead<'a> {
+	reads: &'a InFlightReads,
+	page_id: PageId,
+}
+
+impl Drop for InFlightRead<'_> {
+	fn drop(&mut self) {
+		self.reads.finish(self.page_id);
+	}
+}
+
+/// Like [`InFlightRead`], but owns a reference to the reads.
+pub(super) struct OwnedInFlightRead {
+	reads: Arc<InFlightReads>,
+	page_id: PageId,
+}
+
+impl Drop for OwnedInFlightRead {
+	fn drop(&mut self) {
+		self.reads.finish(self.page_id);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{thread, time::Duration};
+
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn wait_for_in_flight_read() {
+		// given
+		let reads = Arc::new(InFlightReads::default());
+		let read = reads.begin(page_id!(1, 2)).unwrap();
+		let waiting = thread::spawn({
+			let reads = Arc::clone(&reads);
+			move || reads.begin(page_id!(1, 2)).is_none()
+		});
+
+		// when
+		let other_page = reads.begin(page_id!(1, 3));
+		thread::sleep(Duration::from_millis(10));
+		drop(read);
+
+		// then
+		assert!(other_page.is_some());
+		assert!(waiting.join().unwrap());
+		assert!(reads.begin(page_id!(1, 2)).is_some());
+	}
+
+	#[test]
+	fn finish_owned_read_on_another_thread() {
+		// given
+		let reads = Arc::new(InFlightReads::default());
+		let read = reads.try_begin_owned(page_id!(1, 2)).unwrap();
+
+		// when
+		let second_result = reads.try_begin_owned(page_id!(1, 2));
+		let background = thread::spawn(move || {
+			thread::sleep(Duration::from_millis(10));
+			drop(read);
+		});
+		let waited = reads.begin(page_id!(1, 2));
+		background.join().unwrap();
+
+		// then
+		assert!(second_result.is_none());
+		assert!(waited.is_none());
+		assert!(reads.try_begin(page_id!(1, 2)).is_some());
+	}
+
+	#[test]
+	fn stop_waiting_for_in_flight_read() {
+		// given
+		let reads = InFlightReads::default();
+		let _read = reads.begin(page_id!(1, 2)).unwrap();
+
+		// when
+		let result = reads.begin_until(page_id!(1, 2), Instant::now() + Duration::from_millis(10));
+
+		// then
+		assert!(result.is_err());
+	}
+}
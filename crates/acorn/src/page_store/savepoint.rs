@@ -0,0 +1,91 @@
+use std::{collections::HashMap, mem};
+
+use super::{batch::PageWriteBatch, PageId};
+
+/// Identifies a savepoint of the transaction that created it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SavepointId(u64);
+
+impl SavepointId {
+	pub fn get(self) -> u64 {
+		self.0
+	}
+}
+
+/// The original contents of the regions that a transaction wrote to after a
+/// savepoint, and before the next one.
+type SavepointWrites = HashMap<PageId, PageWriteBatch>;
+
+/// The savepoints of a transaction, from oldest to latest.
+///
+/// Writes are only recorded for the latest savepoint, so rolling back to a
+/// savepoint means reverting the writes of it and all later savepoints, latest
+/// first.
+#[derive(Debug, Default)]
+pub(super) struct Savepoints {
+	next_id: u64,
+	stack: Vec<(SavepointId, SavepointWrites)>,
+}
+
+impl Savepoints {
+	pub fn create(&mut self) -> SavepointId {
+		let id = SavepointId(self.next_id);
+		self.next_id += 1;
+		self.stack.push((id, HashMap::new()));
+		id
+	}
+
+	/// The batch in which writes to the page have to be recorded, if there is
+	/// a savepoint.
+	pub fn batch(&mut self, page_id: PageId) -> Option<&mut PageWriteBatch> {
+		let (_, writes) = self.stack.last_mut()?;
+		Some(writes.entry(page_id).or_default())
+	}
+
+	/// Forgets the writes since the savepoint, and returns them latest first,
+	/// so that they can be reverted. The savepoint itself is kept. Returns
+	/// `None` if there is no such savepoint.
+	pub fn rewind(&mut self, id: SavepointId) -> Option<Vec<SavepointWrites>> {
+		let index = self.index_of(id)?;
+		let mut rewound: Vec<SavepointWrites> = self
+			.stack
+			.drain(index + 1..)
+			.rev()
+			.map(|(_, writes)| writes)
+			.collect();
+		rewound.push(mem::take(&mut self.stack[index].1));
+		Some(rewound)
+	}
+
+	/// Removes the savepoint and all later ones, while keeping their writes,
+	/// so that rolling back to an earlier savepoint still reverts them.
+	/// Returns `false` if there is no such savepoint.
+	pub fn release(&mut self, id: SavepointId) -> bool {
+		let Some(index) = self.index_of(id) else {
+			return false;
+		};
+		let released: Vec<SavepointWrites> = self
+			.stack
+			.drain(index..)
+			.map(|(_, writes)| writes)
+			.collect();
+		let Some((_, earlier)) = self.stack.last_mut() else {
+			return true;
+		};
+		for writes in released {
+			for (page_id, batch) in writes {
+				let earlier_batch = earlier.entry(page_id).or_default();
+				for (offset, from) in batch.runs() {
+					earlier_batch.record(offset, from);
+				}
+			}
+		}
+		true
+	}
+
+	fn index_of(&self, id: SavepointId) -> Option<usize> {
+		self.stack
+			.iter()
+			.position(|(savepoint_id, _)| *savepoint_id == id)
+	}
+}
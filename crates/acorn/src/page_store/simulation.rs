@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use crate::utils::cache::{CacheReplacer, EvictionPolicy};
+
+use super::{cache::BUFFERED_PAGE_SIZE, PageId};
+
+/// The projected performance of a hypothetical page cache on an access trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedCacheStats {
+	/// The size of the page cache in bytes.
+	pub page_cache_size: usize,
+	pub eviction_policy: EvictionPolicy,
+	/// The number of accesses to pages that were already cached.
+	pub hits: u64,
+	/// The number of accesses that would have read the page from disk.
+	pub misses: u64,
+}
+
+impl SimulatedCacheStats {
+	/// The fraction of accesses that were hits, or 0 for an empty trace.
+	pub fn hit_rate(&self) -> f64 {
+		let accesses = self.hits + self.misses;
+		if accesses == 0 {
+			return 0.0;
+		}
+		self.hits as f64 / accesses as f64
+	}
+}
+
+struct SimulatedCache {
+	stats: SimulatedCacheStats,
+	num_pages: usize,
+	replacer: CacheReplacer<PageId>,
+	cached: HashSet<PageId>,
+}
+
+impl SimulatedCache {
+	fn new(page_cache_size: usize, eviction_policy: EvictionPolicy) -> Self {
+		let num_pages = page_cache_size / BUFFERED_PAGE_SIZE;
+		Self {
+			stats: SimulatedCacheStats {
+				page_cache_size,
+				eviction_policy,
+				hits: 0,
+				misses: 0,
+			},
+			num_pages,
+			replacer: CacheReplacer::new(eviction_policy, num_pages),
+			cached: HashSet::new(),
+		}
+	}
+
+	fn access(&mut self, page_id: PageId) {
+		if self.cached.contains(&page_id) {
+			self.replacer.access(&page_id);
+			self.stats.hits += 1;
+			return;
+		}
+		self.stats.misses += 1;
+		if self.num_pages == 0 {
+			return;
+		}
+		if let Some(evicted) = self.replacer.evict_replace(page_id) {
+			self.cached.remove(&evicted);
+		}
+		self.cached.insert(page_id);
+	}
+}
+
+/// Replays a trace of page accesses against page caches of different sizes
+/// and eviction policies, to project their hit rates without having to run
+/// the database with each of them.
+///
+/// The simulated caches use the same replacement logic as the real page
+/// cache, but hold no page data, so simulating even large caches is cheap.
+#[derive(Debug, Default, Clone)]
+pub struct CacheSimulator {
+	configs: Vec<(usize, EvictionPolicy)>,
+}
+
+impl CacheSimulator {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a cache with a size in bytes and an eviction policy to the
+	/// simulation.
+	pub fn cache(mut self, page_cache_size: usize, eviction_policy: EvictionPolicy) -> Self {
+		self.configs.push((page_cache_size, eviction_policy));
+		self
+	}
+
+	/// Replays the trace against every cache, in the order they were added.
+	/// The trace is only iterated once, so it can be streamed from a file.
+	pub fn run(&self, trace: impl IntoIterator<Item = PageId>) -> Vec<SimulatedCacheStats> {
+		let mut caches: Vec<SimulatedCache> = self
+			.configs
+			.iter()
+			.map(|(size, policy)| SimulatedCache::new(*size, *policy))
+			.collect();
+		for page_id in trace {
+			for cache in &mut caches {
+				cache.access(page_id);
+			}
+		}
+		caches.into_iter().map(|cache| cache.stats).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::page_store::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn simulate_cache_sizes_and_policies() {
+		// given
+		let hot = [page_id!(1, 1), page_id!(1, 2)];
+		let trace: Vec<PageId> = (0..10)
+			.flat_map(|i| {
+				let cold = PageId::new_unwrap(2, i + 1);
+				[hot[0], hot[1], cold]
+			})
+			.collect();
+		let simulator = CacheSimulator::new()
+			.cache(0, EvictionPolicy::Lru)
+			.cache(2 * BUFFERED_PAGE_SIZE, EvictionPolicy::Lru)
+			.cache(4 * BUFFERED_PAGE_SIZE, EvictionPolicy::Adaptive);
+
+		// when
+		let stats = simulator.run(trace);
+
+		// then
+		assert_eq!(stats.len(), 3);
+		assert_eq!((stats[0].hits, stats[0].misses), (0, 30));
+		assert_eq!((stats[1].hits, stats[1].misses), (0, 30));
+		assert_eq!((stats[2].hits, stats[2].misses), (18, 12));
+		assert_eq!(stats[2].page_cache_size, 4 * BUFFERED_PAGE_SIZE);
+		assert_eq!(stats[2].eviction_policy, EvictionPolicy::Adaptive);
+		assert_eq!(stats[2].hit_rate(), 0.6);
+	}
+}
@@ -0,0 +1,97 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs::File,
+	io::{Read, Seek, SeekFrom, Write},
+};
+
+use parking_lot::Mutex;
+
+use crate::files::{segment::PAGE_BODY_SIZE, FileError};
+
+use super::{PageId, StorageError};
+
+/// Temporary storage for the modified images of pages that a transaction had
+/// to release from the cache, because it holds too many of them.
+pub(super) struct SpillFile {
+	file: Mutex<File>,
+	slots: HashMap<PageId, u64>,
+	spilled: HashSet<PageId>,
+}
+
+impl SpillFile {
+	pub fn new() -> Result<Self, StorageError> {
+		Ok(Self {
+			file: Mutex::new(tempfile::tempfile().map_err(FileError::from)?),
+			slots: HashMap::new(),
+			spilled: HashSet::new(),
+		})
+	}
+
+	pub fn contains(&self, page_id: PageId) -> bool {
+		self.spilled.contains(&page_id)
+	}
+
+	/// The number of pages that are currently spilled.
+	pub fn num_spilled(&self) -> usize {
+		self.spilled.len()
+	}
+
+	pub fn store(&mut self, page_id: PageId, body: &[u8]) -> Result<(), StorageError> {
+		debug_assert_eq!(body.len(), PAGE_BODY_SIZE);
+
+		let next_slot = self.slots.len() as u64;
+		let slot = *self.slots.entry(page_id).or_insert(next_slot);
+		let mut file = self.file.lock();
+		file.seek(SeekFrom::Start(slot * PAGE_BODY_SIZE as u64))
+			.map_err(FileError::from)?;
+		file.write_all(body).map_err(FileError::from)?;
+		self.spilled.insert(page_id);
+		Ok(())
+	}
+
+	pub fn load(&self, page_id: PageId, buf: &mut [u8]) -> Result<(), StorageError> {
+		debug_assert!(self.contains(page_id));
+		debug_assert_eq!(buf.len(), PAGE_BODY_SIZE);
+
+		let slot = self.slots[&page_id];
+		let mut file = self.file.lock();
+		file.seek(SeekFrom::Start(slot * PAGE_BODY_SIZE as u64))
+			.map_err(FileError::from)?;
+		file.read_exact(buf).map_err(FileError::from)?;
+		Ok(())
+	}
+
+	/// Loads the image of a spilled page, and forgets that it was spilled.
+	pub fn take(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), StorageError> {
+		self.load(page_id, buf)?;
+		self.spilled.remove(&page_id);
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn store_and_take_pages() {
+		// given
+		let mut spill = SpillFile::new().unwrap();
+		spill.store(page_id!(1, 1), &[1; PAGE_BODY_SIZE]).unwrap();
+		spill.store(page_id!(1, 2), &[2; PAGE_BODY_SIZE]).unwrap();
+		spill.store(page_id!(1, 1), &[3; PAGE_BODY_SIZE]).unwrap();
+
+		// when
+		let mut buf = vec![0; PAGE_BODY_SIZE];
+		spill.take(page_id!(1, 1), &mut buf).unwrap();
+
+		// then
+		assert_eq!(buf, [3; PAGE_BODY_SIZE]);
+		assert!(!spill.contains(page_id!(1, 1)));
+		assert!(spill.contains(page_id!(1, 2)));
+		spill.load(page_id!(1, 2), &mut buf).unwrap();
+		assert_eq!(buf, [2; PAGE_BODY_SIZE]);
+	}
+}
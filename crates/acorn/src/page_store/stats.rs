@@ -0,0 +1,126 @@
+use std::{
+	collections::BTreeMap,
+	sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+
+use crate::{consts::PAGE_SIZE, files::PageId};
+
+use super::{cache::CacheStats, MemoryUsage};
+
+/// A snapshot of what the storage engine did since the database was opened,
+/// and of its current state.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+	pub cache: CacheStats,
+	/// The number of bytes of items written to the WAL.
+	pub wal_bytes_written: u64,
+	/// The number of transactions that committed.
+	pub commits: u64,
+	/// The number of transactions that were undone, either explicitly or
+	/// because they failed to commit.
+	pub aborts: u64,
+	/// The number of pages that were written at least once, for each segment
+	/// that has any.
+	pub segment_pages: BTreeMap<u32, usize>,
+	/// The current size of the WAL in bytes.
+	pub wal_size: usize,
+	pub memory: MemoryUsage,
+	/// The number of transactions whose writes had to wait for the WAL or
+	/// the dirty pages to catch up, see
+	/// [`DatabaseBuilder::throttle_wal_size`](crate::DatabaseBuilder::throttle_wal_size).
+	pub throttled_writes: u64,
+}
+
+impl Stats {
+	/// The size of the pages that were written to any segment, in bytes.
+	pub fn segment_size(&self) -> u64 {
+		self.segment_pages
+			.values()
+			.map(|num_pages| (num_pages * PAGE_SIZE) as u64)
+			.sum()
+	}
+}
+
+#[derive(Debug, Default)]
+pub(super) struct TransactionCounters {
+	commits: AtomicU64,
+	aborts: AtomicU64,
+}
+
+impl TransactionCounters {
+	pub fn commit(&self) {
+		self.commits.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn abort(&self) {
+		self.aborts.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn commits(&self) -> u64 {
+		self.commits.load(Ordering::Relaxed)
+	}
+
+	pub fn aborts(&self) -> u64 {
+		self.aborts.load(Ordering::Relaxed)
+	}
+}
+
+/// What the operations on a structure, like a tree, cost in page accesses,
+/// see [`TransactionApi::tag_accesses`](super::TransactionApi::tag_accesses).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct AccessStats {
+	/// The number of operations on the structure.
+	pub operations: u64,
+	/// The number of pages that the operations accessed.
+	pub page_accesses: u64,
+	/// The number of accessed pages that had to be read from storage, because
+	/// they weren't cached.
+	pub storage_reads: u64,
+}
+
+impl AccessStats {
+	/// The average number of pages that an operation accessed, or 0 if there
+	/// were no operations.
+	pub fn read_amplification(&self) -> f64 {
+		if self.operations == 0 {
+			return 0.0;
+		}
+		self.page_accesses as f64 / self.operations as f64
+	}
+
+	/// The share of page accesses that missed the cache, or 0 if there were
+	/// no accesses.
+	pub fn miss_ratio(&self) -> f64 {
+		if self.page_accesses == 0 {
+			return 0.0;
+		}
+		self.storage_reads as f64 / self.page_accesses as f64
+	}
+}
+
+/// The [`AccessStats`] of each tag that accesses were attributed to.
+#[derive(Debug, Default)]
+pub(super) struct AccessCounters {
+	tags: Mutex<BTreeMap<PageId, AccessStats>>,
+}
+
+impl AccessCounters {
+	pub fn operation(&self, tag: PageId) {
+		self.tags.lock().entry(tag).or_default().operations += 1;
+	}
+
+	pub fn access(&self, tag: PageId, from_storage: bool) {
+		let mut tags = self.tags.lock();
+		let stats = tags.entry(tag).or_default();
+		stats.page_accesses += 1;
+		if from_storage {
+			stats.storage_reads += 1;
+		}
+	}
+
+	pub fn get(&self) -> BTreeMap<PageId, AccessStats> {
+		self.tags.lock().clone()
+	}
+}
@@ -0,0 +1,209 @@
+use std::{
+	fmt,
+	sync::atomic::{AtomicU64, Ordering},
+	thread,
+	time::{Duration, Instant},
+};
+
+use crate::consts::{DEFAULT_THROTTLE_MAX_WAIT, THROTTLE_POLL_INTERVAL};
+
+use super::{CheckpointTrigger, StorageError};
+
+/// Limits on how far the WAL and the dirty pages may fall behind the writes
+/// of transactions, before further writes are throttled. Each limit can be
+/// disabled by setting it to `None`, which they are by default.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WriteThrottle {
+	/// Throttle writes once the WAL has grown to this many bytes.
+	pub wal_size: Option<usize>,
+	/// Throttle writes once this fraction of the page cache is dirty.
+	pub dirty_ratio: Option<f32>,
+	/// How long a throttled write waits for the database to catch up, before
+	/// it fails with [`StorageError::Busy`].
+	pub max_wait: Duration,
+}
+
+impl Default for WriteThrottle {
+	fn default() -> Self {
+		Self {
+			wal_size: None,
+			dirty_ratio: None,
+			max_wait: DEFAULT_THROTTLE_MAX_WAIT,
+		}
+	}
+}
+
+impl WriteThrottle {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if let Some(ratio) = self.dirty_ratio {
+			if !(ratio > 0.0 && ratio <= 1.0) {
+				return Err(StorageError::InvalidConfig(format!(
+					"The dirty ratio at which writes are throttled must be in (0, 1], but is {ratio}"
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// The first limit that is currently exceeded.
+	pub fn exceeded(&self, wal_size: usize, dirty_ratio: f32) -> Option<ThrottleLimit> {
+		if self.wal_size.is_some_and(|limit| wal_size >= limit) {
+			return Some(ThrottleLimit::WalSize);
+		}
+		if self.dirty_ratio.is_some_and(|limit| dirty_ratio >= limit) {
+			return Some(ThrottleLimit::DirtyRatio);
+		}
+		None
+	}
+}
+
+/// A limit of a [`WriteThrottle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ThrottleLimit {
+	WalSize,
+	DirtyRatio,
+}
+
+impl ThrottleLimit {
+	/// The trigger of the checkpoint that is taken to catch up with the
+	/// limit.
+	pub fn checkpoint_trigger(self) -> CheckpointTrigger {
+		match self {
+			Self::WalSize => CheckpointTrigger::WalSize,
+			Self::DirtyRatio => CheckpointTrigger::DirtyRatio,
+		}
+	}
+}
+
+impl fmt::Display for ThrottleLimit {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::WalSize => write!(f, "the WAL has grown too large"),
+			Self::DirtyRatio => write!(f, "too much of the page cache is dirty"),
+		}
+	}
+}
+
+/// Makes writes wait while a [`WriteThrottle`] limit is exceeded.
+#[derive(Debug, Default)]
+pub(super) struct Throttler {
+	throttle: WriteThrottle,
+	num_throttled: AtomicU64,
+}
+
+impl Throttler {
+	pub fn new(throttle: &WriteThrottle) -> Self {
+		Self {
+			throttle: throttle.clone(),
+			num_throttled: AtomicU64::new(0),
+		}
+	}
+
+	pub fn exceeded(&self, wal_size: usize, dirty_ratio: f32) -> Option<ThrottleLimit> {
+		self.throttle.exceeded(wal_size, dirty_ratio)
+	}
+
+	/// Waits until `state` returns the WAL size and dirty ratio within the
+	/// limits, and fails with [`StorageError::Busy`] if they aren't within
+	/// `max_wait`. Without any limits, `state` isn't called at all. `catch_up`
+	/// is called once with the limit that is exceeded first, to start catching
+	/// up with it.
+	pub fn wait(
+		&self,
+		state: impl Fn() -> (usize, f32),
+		catch_up: impl FnOnce(ThrottleLimit),
+	) -> Result<(), StorageError> {
+		if self.throttle.wal_size.is_none() && self.throttle.dirty_ratio.is_none() {
+			return Ok(());
+		}
+		let (wal_size, dirty_ratio) = state();
+		let Some(mut limit) = self.exceeded(wal_size, dirty_ratio) else {
+			return Ok(());
+		};
+		self.num_throttled.fetch_add(1, Ordering::Relaxed);
+		catch_up(limit);
+		let deadline = Instant::now() + self.throttle.max_wait;
+		loop {
+			let now = Instant::now();
+			if now >= deadline {
+				return Err(StorageError::Busy(limit));
+			}
+			thread::sleep(THROTTLE_POLL_INTERVAL.min(deadline - now));
+			let (wal_size, dirty_ratio) = state();
+			match self.exceeded(wal_size, dirty_ratio) {
+				Some(exceeded) => limit = exceeded,
+				None => return Ok(()),
+			}
+		}
+	}
+
+	/// The number of writes that were throttled, whether or not they could
+	/// proceed in the end.
+	pub fn num_throttled(&self) -> u64 {
+		self.num_throttled.load(Ordering::Relaxed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::Cell;
+
+	use super::*;
+
+	#[test]
+	fn wait_until_caught_up() {
+		// given
+		let throttler = Throttler::new(&WriteThrottle {
+			wal_size: Some(100),
+			dirty_ratio: Some(0.5),
+			max_wait: Duration::from_secs(10),
+		});
+		let wal_size = Cell::new(200);
+		let caught_up = Cell::new(None);
+
+		// when
+		let within = throttler.wait(|| (50, 0.2), |_| unreachable!());
+		let throttled = throttler.wait(
+			|| (wal_size.replace(0), 0.2),
+			|limit| caught_up.set(Some(limit)),
+		);
+
+		// then
+		assert!(within.is_ok());
+		assert!(throttled.is_ok());
+		assert_eq!(caught_up.get(), Some(ThrottleLimit::WalSize));
+		assert_eq!(throttler.num_throttled(), 1);
+	}
+
+	#[test]
+	fn skip_state_without_limits() {
+		// given
+		let throttler = Throttler::new(&WriteThrottle::default());
+
+		// when
+		let result = throttler.wait(|| unreachable!(), |_| unreachable!());
+
+		// then
+		assert!(result.is_ok());
+		assert_eq!(throttler.num_throttled(), 0);
+	}
+
+	#[test]
+	fn fail_when_busy() {
+		// given
+		let throttler = Throttler::new(&WriteThrottle {
+			wal_size: None,
+			dirty_ratio: Some(0.5),
+			max_wait: Duration::ZERO,
+		});
+
+		// when
+		let result = throttler.wait(|| (0, 0.6), |_| {});
+
+		// then
+		assert!(matches!(
+			result,
+			Err(StorageError::Busy(ThrottleLimit::DirtyRatio))
+		));
+	}
+}
@@ -0,0 +1,26 @@
+use std::ops::RangeInclusive;
+
+/// The segments whose pages are not logged to the WAL. Their writes become
+/// visible on commit like any other, but they are never redone or undone by
+/// recovery; instead, the segments are cleared whenever the storage is
+/// opened, so they only ever hold data of the current session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct UnloggedSegments {
+	ranges: Vec<RangeInclusive<u32>>,
+}
+
+impl UnloggedSegments {
+	pub fn add(&mut self, segments: RangeInclusive<u32>) {
+		self.ranges.push(segments);
+	}
+
+	pub fn contains(&self, segment_num: u32) -> bool {
+		self.ranges
+			.iter()
+			.any(|segments| segments.contains(&segment_num))
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.ranges.iter().all(RangeInclusive::is_empty)
+	}
+}
@@ -0,0 +1,477 @@
+use std::{
+	collections::{BTreeMap, HashMap, VecDeque},
+	mem,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::utils::memory::hash_table_size;
+
+use super::PageId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Replacement {
+	/// The page is being modified by the transaction with this id.
+	Pending(u64),
+
+	/// The page was modified by the commit with this sequence number.
+	Committed(u64),
+}
+
+impl Replacement {
+	fn sequence_number(self) -> u64 {
+		match self {
+			Self::Pending(_) => u64::MAX,
+			Self::Committed(seq) => seq,
+		}
+	}
+}
+
+/// How many replaced page versions are kept after no snapshot needs them
+/// anymore, so that snapshots of past commits can still be taken.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct VersionRetention {
+	/// The maximum total size of the retained page images, in bytes.
+	pub max_size: usize,
+
+	/// The time after which a replaced version is no longer retained, or
+	/// `None` if versions are only limited by size.
+	pub max_age: Option<Duration>,
+}
+
+/// A version that is retained, in the order of the commits that replaced
+/// them.
+#[derive(Debug)]
+struct RetainedVersion {
+	page_id: PageId,
+	replaced_by: u64,
+	replaced_at: Instant,
+	size: usize,
+}
+
+#[derive(Debug)]
+struct Version {
+	image: Arc<[u8]>,
+	replaced_by: Replacement,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	last_commit: u64,
+	snapshots: BTreeMap<u64, usize>,
+	pages: HashMap<PageId, Vec<Version>>,
+	pending: HashMap<u64, Vec<PageId>>,
+	trackers: BTreeMap<u64, usize>,
+	last_modified: HashMap<PageId, u64>,
+	retention: VersionRetention,
+	retained: VecDeque<RetainedVersion>,
+	retained_size: usize,
+
+	/// The oldest commit that a snapshot can still be taken at, because all
+	/// versions that were replaced after it are still there.
+	horizon: u64,
+}
+
+impl State {
+	/// Drops all versions that no active snapshot could still read, and that
+	/// are not retained.
+	fn collect_garbage(&mut self, page_ids: impl IntoIterator<Item = PageId>) {
+		let oldest_snapshot = self.snapshots.keys().next().copied();
+		let horizon = self.horizon;
+		for page_id in page_ids {
+			let Some(versions) = self.pages.get_mut(&page_id) else {
+				continue;
+			};
+			versions.retain(|version| match version.replaced_by {
+				Replacement::Pending(..) => true,
+				Replacement::Committed(seq) => {
+					horizon < seq || oldest_snapshot.is_some_and(|oldest| oldest < seq)
+				}
+			});
+			if versions.is_empty() {
+				self.pages.remove(&page_id);
+			}
+		}
+	}
+
+	/// Stops retaining the oldest versions until the retention limits are met
+	/// again, and moves the horizon past them.
+	fn expire_retained(&mut self, now: Instant) {
+		while let Some(oldest) = self.retained.front() {
+			let too_old = self
+				.retention
+				.max_age
+				.is_some_and(|max_age| now.duration_since(oldest.replaced_at) >= max_age);
+			if !too_old && self.retained_size <= self.retention.max_size {
+				break;
+			}
+			let oldest = self.retained.pop_front().unwrap();
+			self.retained_size -= oldest.size;
+			self.horizon = u64::max(self.horizon, oldest.replaced_by);
+			self.collect_garbage([oldest.page_id]);
+		}
+	}
+}
+
+/// Keeps the images that pages had before they were modified by a
+/// transaction, so that snapshots can keep reading them without waiting for
+/// the page lock.
+///
+/// Commits are numbered in the order they become visible; a snapshot reads
+/// the state of the database as of the last commit before it started.
+/// Depending on the [`VersionRetention`], replaced versions are also kept for
+/// a while, so that snapshots can be taken at past commits.
+#[derive(Debug, Default)]
+pub(super) struct VersionStore {
+	state: Mutex<State>,
+}
+
+impl VersionStore {
+	pub fn new(retention: VersionRetention) -> Self {
+		Self {
+			state: Mutex::new(State {
+				retention,
+				..Default::default()
+			}),
+		}
+	}
+
+	/// Registers a new snapshot, and returns the sequence number of the last
+	/// commit it can see.
+	pub fn begin_snapshot(&self) -> u64 {
+		let mut state = self.state.lock();
+		let seq = state.last_commit;
+		*state.snapshots.entry(seq).or_default() += 1;
+		seq
+	}
+
+	/// Registers a new snapshot that sees the state as of the commit with the
+	/// given sequence number, or returns `false` if that state is not known
+	/// anymore, or not yet.
+	pub fn begin_snapshot_at(&self, seq: u64) -> bool {
+		let mut state = self.state.lock();
+		state.expire_retained(Instant::now());
+		if seq < state.horizon || seq > state.last_commit {
+			return false;
+		}
+		*state.snapshots.entry(seq).or_default() += 1;
+		true
+	}
+
+	pub fn end_snapshot(&self, seq: u64) {
+		let mut state = self.state.lock();
+		let Some(count) = state.snapshots.get_mut(&seq) else {
+			return;
+		};
+		*count -= 1;
+		if *count == 0 {
+			state.snapshots.remove(&seq);
+			let page_ids: Vec<PageId> = state.pages.keys().copied().collect();
+			state.collect_garbage(page_ids);
+		}
+	}
+
+	/// Keeps the image of a page that a transaction is about to modify, unless
+	/// it already did so.
+	pub fn record_pending(&self, page_id: PageId, transaction_id: u64, image: &[u8]) {
+		let mut state = self.state.lock();
+		let versions = state.pages.entry(page_id).or_default();
+		if versions
+			.iter()
+			.any(|version| version.replaced_by == Replacement::Pending(transaction_id))
+		{
+			return;
+		}
+		versions.push(Version {
+			image: image.into(),
+			replaced_by: Replacement::Pending(transaction_id),
+		});
+		state
+			.pending
+			.entry(transaction_id)
+			.or_default()
+			.push(page_id);
+	}
+
+	/// Makes the modifications of a transaction visible to new snapshots.
+	pub fn commit(&self, transaction_id: u64) {
+		let mut state = self.state.lock();
+		state.last_commit += 1;
+		let seq = state.last_commit;
+		let page_ids = state.pending.remove(&transaction_id).unwrap_or_default();
+		if !state.trackers.is_empty() {
+			for page_id in &page_ids {
+				state.last_modified.insert(*page_id, seq);
+			}
+		}
+		let now = Instant::now();
+		for page_id in &page_ids {
+			let mut size = 0;
+			for version in state.pages.get_mut(page_id).into_iter().flatten() {
+				if version.replaced_by == Replacement::Pending(transaction_id) {
+					version.replaced_by = Replacement::Committed(seq);
+					size = version.image.len();
+				}
+			}
+			state.retained.push_back(RetainedVersion {
+				page_id: *page_id,
+				replaced_by: seq,
+				replaced_at: now,
+				size,
+			});
+			state.retained_size += size;
+		}
+		if page_ids.is_empty() {
+			// Nothing was replaced, so the state as of this commit is the same
+			// as before.
+			return;
+		}
+		state.expire_retained(now);
+	}
+
+	/// Forgets the image kept for a page that a transaction locked, but
+	/// released again without modifying it.
+	pub fn release_pending(&self, page_id: PageId, transaction_id: u64) {
+		let mut state = self.state.lock();
+		if let Some(page_ids) = state.pending.get_mut(&transaction_id) {
+			page_ids.retain(|pending| *pending != page_id);
+		}
+		let Some(versions) = state.pages.get_mut(&page_id) else {
+			return;
+		};
+		versions.retain(|version| version.replaced_by != Replacement::Pending(transaction_id));
+		if versions.is_empty() {
+			state.pages.remove(&page_id);
+		}
+	}
+
+	/// Forgets the images kept for a transaction that was undone.
+	pub fn abort(&self, transaction_id: u64) {
+		let mut state = self.state.lock();
+		let page_ids = state.pending.remove(&transaction_id).unwrap_or_default();
+		for page_id in page_ids {
+			let Some(versions) = state.pages.get_mut(&page_id) else {
+				continue;
+			};
+			versions.retain(|version| version.replaced_by != Replacement::Pending(transaction_id));
+			if versions.is_empty() {
+				state.pages.remove(&page_id);
+			}
+		}
+	}
+
+	/// Returns the sequence number of the last commit.
+	pub fn last_commit(&self) -> u64 {
+		self.state.lock().last_commit
+	}
+
+	/// Starts keeping track of which pages are modified by commits after the
+	/// last one, and returns its sequence number.
+	pub fn begin_tracking(&self) -> u64 {
+		let mut state = self.state.lock();
+		let seq = state.last_commit;
+		*state.trackers.entry(seq).or_default() += 1;
+		seq
+	}
+
+	/// Registers a new snapshot, and starts keeping track of the pages
+	/// modified by commits it doesn't see. Returns its sequence number, which
+	/// has to be passed to both [`Self::end_snapshot`] and
+	/// [`Self::end_tracking`].
+	pub fn begin_tracked_snapshot(&self) -> u64 {
+		let mut state = self.state.lock();
+		let seq = state.last_commit;
+		*state.snapshots.entry(seq).or_default() += 1;
+		*state.trackers.entry(seq).or_default() += 1;
+		seq
+	}
+
+	/// Like [`Self::begin_tracked_snapshot`], but for the commit with the
+	/// given sequence number, or returns `false` like
+	/// [`Self::begin_snapshot_at`]. Pages modified before tracking began may
+	/// not be known as modified, so this only suits snapshots that aren't
+	/// written to.
+	pub fn begin_tracked_snapshot_at(&self, seq: u64) -> bool {
+		if !self.begin_snapshot_at(seq) {
+			return false;
+		}
+		*self.state.lock().trackers.entry(seq).or_default() += 1;
+		true
+	}
+
+	pub fn end_tracking(&self, seq: u64) {
+		let mut state = self.state.lock();
+		let Some(count) = state.trackers.get_mut(&seq) else {
+			return;
+		};
+		*count -= 1;
+		if *count == 0 {
+			state.trackers.remove(&seq);
+			let oldest_tracker = state.trackers.keys().next().copied();
+			state
+				.last_modified
+				.retain(|_, modified| oldest_tracker.is_some_and(|oldest| oldest < *modified));
+		}
+	}
+
+	/// Whether a commit after the one with sequence number `seq` modified the
+	/// page. This is only known while tracking began at or before `seq`.
+	pub fn modified_since(&self, page_id: PageId, seq: u64) -> bool {
+		self.state
+			.lock()
+			.last_modified
+			.get(&page_id)
+			.is_some_and(|modified| *modified > seq)
+	}
+
+	/// Returns the image of the page that a snapshot with the given sequence
+	/// number should see, or `None` if it should see the current one.
+	pub fn get(&self, page_id: PageId, seq: u64) -> Option<Arc<[u8]>> {
+		let state = self.state.lock();
+		state
+			.pages
+			.get(&page_id)?
+			.iter()
+			.filter(|version| version.replaced_by.sequence_number() > seq)
+			.min_by_key(|version| version.replaced_by.sequence_number())
+			.map(|version| Arc::clone(&version.image))
+	}
+
+	/// The approximate memory used by the stored page images, in bytes.
+	pub fn memory_usage(&self) -> usize {
+		let state = self.state.lock();
+		let images: usize = state
+			.pages
+			.values()
+			.flatten()
+			.map(|version| version.image.len() + mem::size_of::<Version>())
+			.sum();
+		images + hash_table_size::<(PageId, Vec<Version>)>(state.pages.capacity())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn snapshot_reads_replaced_versions() {
+		// given
+		let versions = VersionStore::default();
+		let old_snapshot = versions.begin_snapshot();
+		versions.record_pending(page_id!(1, 2), 0, &[1]);
+		versions.commit(0);
+		let middle_snapshot = versions.begin_snapshot();
+		versions.record_pending(page_id!(1, 2), 1, &[2]);
+
+		// when
+		let old = versions.get(page_id!(1, 2), old_snapshot);
+		let middle = versions.get(page_id!(1, 2), middle_snapshot);
+		versions.commit(1);
+		let new_snapshot = versions.begin_snapshot();
+		let new = versions.get(page_id!(1, 2), new_snapshot);
+
+		// then
+		assert_eq!(old.as_deref(), Some([1].as_slice()));
+		assert_eq!(middle.as_deref(), Some([2].as_slice()));
+		assert_eq!(new, None);
+	}
+
+	#[test]
+	fn track_modified_pages() {
+		// given
+		let versions = VersionStore::default();
+		versions.record_pending(page_id!(1, 2), 0, &[1]);
+		versions.commit(0);
+		let tracking = versions.begin_tracking();
+		versions.record_pending(page_id!(1, 3), 1, &[2]);
+		versions.commit(1);
+
+		// when
+		let modified_2 = versions.modified_since(page_id!(1, 2), tracking);
+		let modified_3 = versions.modified_since(page_id!(1, 3), tracking);
+		let modified_3_later = versions.modified_since(page_id!(1, 3), versions.last_commit());
+		versions.end_tracking(tracking);
+
+		// then
+		assert!(!modified_2);
+		assert!(modified_3);
+		assert!(!modified_3_later);
+		assert!(versions.state.lock().last_modified.is_empty());
+	}
+
+	#[test]
+	fn drop_versions_without_snapshots() {
+		// given
+		let versions = VersionStore::default();
+		let snapshot = versions.begin_snapshot();
+		versions.record_pending(page_id!(1, 2), 0, &[1]);
+		versions.commit(0);
+		versions.record_pending(page_id!(1, 3), 1, &[2]);
+		versions.abort(1);
+
+		// when
+		versions.end_snapshot(snapshot);
+
+		// then
+		assert_eq!(versions.get(page_id!(1, 2), snapshot), None);
+		assert!(versions.state.lock().pages.is_empty());
+	}
+
+	#[test]
+	fn retain_versions_up_to_size() {
+		// given
+		let versions = VersionStore::new(VersionRetention {
+			max_size: 2,
+			max_age: None,
+		});
+		for (transaction_id, image) in [[1], [2], [3]].iter().enumerate() {
+			versions.record_pending(page_id!(1, 2), transaction_id as u64, image);
+			versions.commit(transaction_id as u64);
+		}
+
+		// when
+		let too_old = versions.begin_snapshot_at(0);
+		let retained = versions.begin_snapshot_at(1);
+		let too_new = versions.begin_snapshot_at(4);
+
+		// then
+		assert!(!too_old);
+		assert!(retained);
+		assert!(!too_new);
+		assert_eq!(
+			versions.get(page_id!(1, 2), 1).as_deref(),
+			Some([2].as_slice())
+		);
+		assert_eq!(
+			versions.get(page_id!(1, 2), 2).as_deref(),
+			Some([3].as_slice())
+		);
+		versions.end_snapshot(1);
+		assert_eq!(versions.state.lock().pages[&page_id!(1, 2)].len(), 2);
+	}
+
+	#[test]
+	fn expire_retained_versions() {
+		// given
+		let versions = VersionStore::new(VersionRetention {
+			max_size: usize::MAX,
+			max_age: Some(Duration::ZERO),
+		});
+		versions.record_pending(page_id!(1, 2), 0, &[1]);
+		versions.commit(0);
+
+		// when
+		let expired = versions.begin_snapshot_at(0);
+		let current = versions.begin_snapshot_at(1);
+
+		// then
+		assert!(!expired);
+		assert!(current);
+		assert!(versions.state.lock().pages.is_empty());
+	}
+}
@@ -1,8 +1,14 @@
 use std::{
 	borrow::{Borrow, Cow},
 	collections::{hash_map::Entry, HashMap, VecDeque},
-	mem,
-	sync::Arc,
+	fmt, mem,
+	num::NonZeroU64,
+	path::PathBuf,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+	},
+	thread,
 	time::Duration,
 };
 
@@ -11,24 +17,65 @@ use log::error;
 #[cfg(test)]
 use mockall::{automock, concretize};
 
-use parking_lot::{Mutex, MutexGuard, RwLock};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock};
 use static_assertions::assert_impl_all;
 
 use crate::{
-	consts::{DEFAULT_CHECKPOINT_PERIOD, DEFAULT_MAX_WAL_GENERATION_SIZE},
+	consts::{
+		DEFAULT_CHECKPOINT_PERIOD, DEFAULT_GROUP_COMMIT_DELAY, DEFAULT_MAX_WAL_GENERATION_SIZE,
+		DEFAULT_WAL_SIZE_WARNING_THRESHOLD,
+	},
 	files::{
-		wal::{self, CheckpointData, WalFileApi},
+		wal::{self, CheckpointData, ItemStream, WalFileApi},
 		DatabaseFolder, DatabaseFolderApi,
 	},
-	tasks::{Timer, TimerHandle},
+	tasks::{BackgroundTasks, TaskMonitor, TaskPanic, Timer, TimerHandle},
+	trace::event,
 };
 
-use super::{PageId, StorageError, TransactionState, WalIndex};
+use super::{
+	custom_records::{CustomRecord, WalRecordHandlers},
+	OpenWarning, PageId, RecoveryReport, StorageError, TransactionState, WalIndex,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct WalConfig {
 	pub max_generation_size: usize,
 	pub checkpoint_period: Duration,
+	pub size_warning_threshold: usize,
+	/// How long a commit waits for other commits to share a WAL sync with.
+	pub group_commit_delay: Duration,
+	/// The folder that WAL generations are stored in, instead of the `wal`
+	/// folder of the database.
+	pub path: Option<PathBuf>,
+	/// The folder that WAL generations are moved to once they are no longer
+	/// needed for recovery, instead of being deleted.
+	pub archive: Option<PathBuf>,
+	/// Whether large write items are compressed in new WAL generations.
+	pub compress: bool,
+	/// Whether the periodic checkpoint is restarted after it panicked.
+	pub restart_panicked_tasks: bool,
+	/// Redo and undo custom records during recovery.
+	pub record_handlers: WalRecordHandlers,
+	/// Whether a WAL with a corrupted item is cut off at that item when it is
+	/// opened, instead of failing recovery.
+	pub salvage: bool,
+}
+
+impl WalConfig {
+	pub fn validate(&self) -> Result<(), StorageError> {
+		if self.max_generation_size == 0 {
+			return Err(StorageError::InvalidConfig(
+				"WAL files must be allowed to grow larger than 0 bytes".to_string(),
+			));
+		}
+		if self.path.is_some() && self.path == self.archive {
+			return Err(StorageError::InvalidConfig(
+				"The WAL can't be stored in the folder it is archived to".to_string(),
+			));
+		}
+		Ok(())
+	}
 }
 
 impl Default for WalConfig {
@@ -36,6 +83,14 @@ impl Default for WalConfig {
 		Self {
 			max_generation_size: DEFAULT_MAX_WAL_GENERATION_SIZE,
 			checkpoint_period: DEFAULT_CHECKPOINT_PERIOD,
+			size_warning_threshold: DEFAULT_WAL_SIZE_WARNING_THRESHOLD,
+			group_commit_delay: DEFAULT_GROUP_COMMIT_DELAY,
+			path: None,
+			archive: None,
+			compress: false,
+			restart_panicked_tasks: false,
+			record_handlers: WalRecordHandlers::default(),
+			salvage: false,
 		}
 	}
 }
@@ -57,6 +112,14 @@ pub(crate) struct WriteLog<'a> {
 	pub to: &'a [u8],
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CustomLog<'a> {
+	pub transaction_id: u64,
+	pub record_type: u16,
+	pub version: u16,
+	pub data: &'a [u8],
+}
+
 #[derive(Debug, Clone)]
 struct UndoLog<'a> {
 	transaction_id: u64,
@@ -65,18 +128,130 @@ struct UndoLog<'a> {
 	to: Cow<'a, [u8]>,
 }
 
+/// An item of an incomplete transaction that has to be undone.
+#[derive(Debug, Clone)]
+enum Compensation {
+	Write(UndoLog<'static>),
+	Custom(CustomRecord),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct CommitLog {
 	pub transaction_id: u64,
 }
 
+/// A point in the WAL, between two of its items. Positions of later items
+/// compare greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WalPosition(WalIndex);
+
+impl WalPosition {
+	pub fn new(generation: u64, offset: NonZeroU64) -> Self {
+		Self(WalIndex::new(generation, offset))
+	}
+
+	pub fn generation(&self) -> u64 {
+		self.0.generation
+	}
+
+	pub fn offset(&self) -> NonZeroU64 {
+		self.0.offset
+	}
+
+	pub(crate) fn index(&self) -> WalIndex {
+		self.0
+	}
+}
+
+impl From<WalIndex> for WalPosition {
+	fn from(index: WalIndex) -> Self {
+		Self(index)
+	}
+}
+
+impl fmt::Display for WalPosition {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}", self.0.generation, self.0.offset)
+	}
+}
+
+/// A change of a committed transaction, as streamed from the WAL. The writes
+/// and custom records of a transaction are followed by its commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+	/// `data` was written to the page at `offset`.
+	Write {
+		transaction_id: u64,
+		page_id: PageId,
+		offset: u16,
+		data: Vec<u8>,
+	},
+
+	/// A custom record was logged, see
+	/// [`Transaction::log_record`](crate::Transaction::log_record).
+	Custom {
+		transaction_id: u64,
+		record_type: u16,
+		version: u16,
+		data: Vec<u8>,
+	},
+
+	/// All writes of the transaction were streamed. A stream that starts at
+	/// `position` continues with the next transaction.
+	Commit {
+		transaction_id: u64,
+		position: WalPosition,
+	},
+}
+
+/// What became of a transaction that has items in the WAL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalTransactionStatus {
+	Committed,
+
+	/// The transaction was undone, either explicitly or because it was still
+	/// open when recovery ran.
+	RolledBack,
+
+	/// Neither a commit nor a rollback of the transaction was logged, e.g.
+	/// because the database crashed while the transaction was committing.
+	Open,
+}
+
+/// A summary of the items a transaction has in the WAL. Only generations that
+/// are still kept are included, so earlier writes of a long-running
+/// transaction may be missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalTransaction {
+	pub transaction_id: u64,
+	/// The position of the first item of the transaction.
+	pub first: WalPosition,
+	/// The position of the last item of the transaction.
+	pub last: WalPosition,
+	/// The number of writes, including those logged to roll the transaction
+	/// back.
+	pub num_writes: usize,
+	/// The number of bytes of page data the writes changed.
+	pub num_bytes: usize,
+	pub status: WalTransactionStatus,
+}
+
 pub(crate) struct Wal<DF: DatabaseFolderApi = DatabaseFolder> {
 	folder: Arc<DF>,
 	thread_pool: Arc<ThreadPool>,
 	generations: Arc<RwLock<GenerationQueue<DF>>>,
 	state: Arc<Mutex<State>>,
 	max_generation_size: usize,
+	size_warning_threshold: usize,
+	group_commit_delay: Duration,
+	group_commit: Arc<GroupCommit>,
 	checkpoint_timer_handle: TimerHandle,
+	tasks: Arc<TaskMonitor>,
+	/// The number of bytes of items logged since the WAL was opened.
+	bytes_written: AtomicU64,
+	record_handlers: WalRecordHandlers,
+	/// What was discarded when the WAL was salvaged on opening.
+	salvaged: Option<OpenWarning>,
 }
 assert_impl_all!(Wal: Send, Sync);
 
@@ -91,7 +266,7 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		gens.push_generation(0, folder.open_wal_file(0)?);
 
 		let wal = Self::new(folder, thread_pool, config, gens, State::default());
-		Self::log_checkpoint(&wal.generations, &wal.state)?;
+		Self::log_checkpoint(&wal.generations.read(), &wal.state)?;
 
 		Ok(wal)
 	}
@@ -103,19 +278,56 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 	) -> Result<Self, StorageError> {
 		let mut wal_files: Vec<(u64, DF::WalFile)> = Result::from_iter(folder.iter_wal_files()?)?;
 		wal_files.sort_by(|(gen_1, _), (gen_2, _)| u64::cmp(gen_1, gen_2));
+		let salvaged = if config.salvage {
+			Self::salvage(&*folder, &mut wal_files)?
+		} else {
+			None
+		};
 
 		let mut gens: GenerationQueue<DF> = GenerationQueue::new();
 		for (gen, file) in wal_files {
 			gens.push_generation(gen, file);
 		}
 
-		Ok(Self::new(
-			folder,
-			thread_pool,
-			config,
-			gens,
-			State::default(),
-		))
+		let mut wal = Self::new(folder, thread_pool, config, gens, State::default());
+		wal.salvaged = salvaged;
+		Ok(wal)
+	}
+
+	/// Cuts off the WAL at its first corrupted item, and deletes the
+	/// generations after it, so that the transactions that committed before
+	/// the corruption can be recovered. Those that committed after it are
+	/// lost, and pages that were written back after it may still hold their
+	/// changes.
+	fn salvage(
+		folder: &DF,
+		wal_files: &mut Vec<(u64, DF::WalFile)>,
+	) -> Result<Option<OpenWarning>, StorageError> {
+		let mut corrupt = None;
+		for (gen, file) in wal_files.iter_mut() {
+			if let Some(cut) = file.salvage()? {
+				corrupt = Some((*gen, cut));
+				break;
+			}
+		}
+		let Some((generation, (offset, len))) = corrupt else {
+			return Ok(None);
+		};
+		let dropped_generations: Vec<u64> = wal_files
+			.iter()
+			.map(|(gen, _)| *gen)
+			.filter(|gen| *gen > generation)
+			.collect();
+		wal_files.retain(|(gen, _)| *gen <= generation);
+		for gen in &dropped_generations {
+			folder.delete_wal_file(*gen)?;
+		}
+		Ok(Some(OpenWarning::SalvagedWal {
+			generation,
+			offset: offset.get(),
+			len,
+			dropped_generations,
+		}))
 	}
 
 	fn new(
@@ -128,13 +340,21 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		let generations = Arc::new(RwLock::new(generations));
 		let state = Arc::new(Mutex::new(state));
 
+		let tasks = Arc::new(TaskMonitor::new(config.restart_panicked_tasks));
 		let (checkpoint_timer, checkpoint_timer_handle) = Timer::new(config.checkpoint_period);
-		thread_pool.spawn_ok(Self::periodic_checkpoint_task(
-			checkpoint_timer,
-			Arc::clone(&generations),
-			Arc::clone(&state),
-			Arc::clone(&folder),
-		));
+		tasks.spawn_periodic(&thread_pool, "WAL checkpoint", checkpoint_timer, {
+			let generations = Arc::clone(&generations);
+			let state = Arc::clone(&state);
+			let folder = Arc::clone(&folder);
+			move |timer| {
+				Self::periodic_checkpoint_task(
+					timer,
+					Arc::clone(&generations),
+					Arc::clone(&state),
+					Arc::clone(&folder),
+				)
+			}
+		});
 
 		Self {
 			folder,
@@ -142,21 +362,159 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 			generations,
 			state,
 			max_generation_size: config.max_generation_size,
+			size_warning_threshold: config.size_warning_threshold,
+			group_commit_delay: config.group_commit_delay,
+			group_commit: Arc::default(),
 			checkpoint_timer_handle,
+			tasks,
+			bytes_written: AtomicU64::new(0),
+			record_handlers: config.record_handlers.clone(),
+			salvaged: None,
 		}
 	}
 
+	/// Whether the folder held any WAL files when the WAL was opened.
+	pub fn is_initialized(&self) -> bool {
+		!self.generations.read().generations.is_empty()
+	}
+
+	pub fn open_warnings(&self) -> Vec<OpenWarning> {
+		let mut warnings: Vec<OpenWarning> = self.salvaged.iter().cloned().collect();
+		let gens = self.generations.read();
+
+		let mut size: usize = 0;
+		for gen in &gens.generations {
+			let file = gen.file.lock();
+			size = size.saturating_add(file.size());
+
+			let unknown_features = file.unknown_features();
+			if unknown_features.optional != 0 {
+				warnings.push(OpenWarning::UnknownWalFeatures {
+					generation: gen.gen_num,
+					features: unknown_features.optional,
+				});
+			}
+
+			let torn_tail_len = file.torn_tail_len();
+			if torn_tail_len != 0 {
+				warnings.push(OpenWarning::TornWalTail {
+					generation: gen.gen_num,
+					len: torn_tail_len,
+				});
+			}
+		}
+
+		if size > self.size_warning_threshold {
+			warnings.push(OpenWarning::LargeWal {
+				size,
+				threshold: self.size_warning_threshold,
+			});
+		}
+		warnings
+	}
+
+	/// Streams the transactions that commit at or after `from`, once their
+	/// commits are durable. The WAL generations the subscription still has to
+	/// read are kept until then.
+	pub fn subscribe(&self, from: WalIndex) -> Result<WalSubscription<DF>, StorageError> {
+		let gens = self.generations.read();
+		let Some(first_gen) = gens.generations.front().map(|gen| gen.gen_num) else {
+			return Err(StorageError::WalNotInitialized);
+		};
+		if first_gen > from.generation {
+			return Err(StorageError::WalPositionUnavailable(from.into()));
+		}
+
+		let mut state = self.state.lock();
+		let id = state.next_subscription_id;
+		state.next_subscription_id += 1;
+		state.subscriptions.insert(id, first_gen);
+		mem::drop(state);
+
+		Ok(WalSubscription {
+			id,
+			generations: Arc::clone(&self.generations),
+			state: Arc::clone(&self.state),
+			group_commit: Arc::clone(&self.group_commit),
+			from,
+			read_until: WalIndex::new(first_gen, NonZeroU64::MIN),
+			pending: HashMap::new(),
+			ready: VecDeque::new(),
+		})
+	}
+
+	/// Summarizes the transactions that have items in the WAL, in the order
+	/// of their first items, without keeping any of the written data.
+	pub fn transactions(&self) -> Result<Vec<WalTransaction>, StorageError> {
+		// acquire exclusive gen lock to get a consistent view of the WAL
+		let gens = self.generations.write();
+		Self::flush_impl(&gens)?;
+
+		let mut transactions: Vec<WalTransaction> = Vec::new();
+		let mut indices: HashMap<u64, usize> = HashMap::new();
+		let mut buf = Vec::new();
+		for generation in &gens.generations {
+			let mut file = generation.file.lock();
+			let mut items = file.iter_items()?;
+			while let Some((offset, item)) = items.next_into(&mut buf)? {
+				let position = WalPosition::new(generation.gen_num, offset);
+				let transaction_id = match &item {
+					wal::Item::Write(data) => data.transaction_data.transaction_id,
+					wal::Item::Commit(data) => data.transaction_id,
+					wal::Item::Custom(data) => data.transaction_data.transaction_id,
+					wal::Item::Checkpoint(..) => continue,
+				};
+				let index = *indices.entry(transaction_id).or_insert_with(|| {
+					transactions.push(WalTransaction {
+						transaction_id,
+						first: position,
+						last: position,
+						num_writes: 0,
+						num_bytes: 0,
+						status: WalTransactionStatus::Open,
+					});
+					transactions.len() - 1
+				});
+				let transaction = &mut transactions[index];
+				transaction.last = position;
+				match item {
+					wal::Item::Write(data) => {
+						transaction.num_writes += 1;
+						transaction.num_bytes += data.to.len();
+						// Only the writes that undo a transaction have no
+						// previous data.
+						if data.from.is_none() {
+							transaction.status = WalTransactionStatus::RolledBack;
+						}
+					}
+					wal::Item::Commit(..) => {
+						if transaction.status == WalTransactionStatus::Open {
+							transaction.status = WalTransactionStatus::Committed;
+						}
+					}
+					wal::Item::Custom(data) => {
+						if data.undo {
+							transaction.status = WalTransactionStatus::RolledBack;
+						}
+					}
+					wal::Item::Checkpoint(..) => (),
+				}
+			}
+		}
+		Ok(transactions)
+	}
+
 	fn log_checkpoint(
-		generations: &RwLock<GenerationQueue<DF>>,
+		generations: &GenerationQueue<DF>,
 		state: &Mutex<State>,
 	) -> Result<(), StorageError> {
-		let generations = generations.read();
 		let Some(mut wal_file) = generations.current_generation() else {
 			return Err(StorageError::WalNotInitialized);
 		};
 
 		let state = state.lock();
 		wal_file.push_item(wal::Item::Checkpoint(CheckpointData {
+			next_transaction_id: state.next_transaction_id,
 			dirty_pages: Cow::Borrowed(&state.dirty_pages),
 			transactions: Cow::Borrowed(&state.transactions),
 		}))?;
@@ -188,30 +546,51 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		Ok(())
 	}
 
-	fn read_initial_state(&self, file: &mut DF::WalFile) -> Result<(), StorageError> {
-		let mut checkpoint_data: Option<wal::CheckpointData> = None;
-		for item_result in file.iter_items()? {
-			if let (_, wal::Item::Checkpoint(data)) = item_result? {
-				checkpoint_data = Some(data);
-				break;
+	fn read_initial_state(file: &mut DF::WalFile) -> Result<Option<State>, StorageError> {
+		let mut items = file.iter_items()?;
+		let mut buf = Vec::new();
+		while let Some((_, item)) = items.next_into(&mut buf)? {
+			if let wal::Item::Checkpoint(data) = item {
+				return Ok(Some(State::new(
+					data.dirty_pages.into_owned(),
+					data.transactions.into_owned(),
+					data.next_transaction_id,
+				)));
 			}
 		}
+		Ok(None)
+	}
 
-		let mut state = self.state.lock();
-		*state = match checkpoint_data {
-			Some(data) => State::new(
-				data.dirty_pages.into_owned(),
-				data.transactions.into_owned(),
-			),
-			None => State::default(),
-		};
-		Ok(())
+	/// Restores the state at the end of the WAL from the latest checkpoint. A
+	/// crash while a generation was started can keep its checkpoint from being
+	/// written, in which case the one of the previous generation is used.
+	fn read_final_state(gens: &GenerationQueue<DF>) -> Result<State, StorageError> {
+		if gens.generations.is_empty() {
+			return Err(StorageError::WalNotInitialized);
+		}
+		let mut state = State::default();
+		let mut start = 0;
+		for (i, generation) in gens.generations.iter().enumerate().rev() {
+			if let Some(initial_state) = Self::read_initial_state(&mut generation.file.lock())? {
+				state = initial_state;
+				start = i;
+				break;
+			}
+		}
+		for generation in gens.generations.iter().skip(start) {
+			Self::recover_state(&mut state, &mut generation.file.lock(), generation.gen_num)?;
+		}
+		Ok(state)
 	}
 
-	fn recover_state(&self, file: &mut DF::WalFile, gen_num: u64) -> Result<(), StorageError> {
-		let mut state = self.state.lock();
-		for item_result in file.iter_items()? {
-			let (offset, item) = item_result?;
+	fn recover_state(
+		state: &mut State,
+		file: &mut DF::WalFile,
+		gen_num: u64,
+	) -> Result<(), StorageError> {
+		let mut items = file.iter_items()?;
+		let mut buf = Vec::new();
+		while let Some((offset, item)) = items.next_into(&mut buf)? {
 			state.handle_item(WalIndex::new(gen_num, offset), &item);
 		}
 		Ok(())
@@ -224,12 +603,10 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		mut handle: impl FnMut(PartialWriteOp) -> Result<(), StorageError>,
 	) -> Result<(), StorageError> {
 		let state = self.state.lock();
-		let Some(first_dirty_index) = state.dirty_pages.get(&data.page_id).copied() else {
-			return Ok(());
-		};
+		let needs_redo = state.needs_redo(index, data.page_id);
 		mem::drop(state);
 
-		if index < first_dirty_index {
+		if !needs_redo {
 			return Ok(());
 		}
 
@@ -249,25 +626,49 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		gen_num: u64,
 		mut handle: impl FnMut(PartialWriteOp) -> Result<(), StorageError>,
 	) -> Result<(), StorageError> {
-		for item_result in file.iter_items()? {
-			let (offset, item) = item_result?;
+		let mut items = file.iter_items()?;
+		let mut buf = Vec::new();
+		while let Some((offset, item)) = items.next_into(&mut buf)? {
 			let index = WalIndex::new(gen_num, offset);
 
-			if let wal::Item::Write(data) = item {
-				self.redo_write(index, data, &mut handle)?;
+			match item {
+				wal::Item::Write(data) => self.redo_write(index, data, &mut handle)?,
+				wal::Item::Custom(data) => self.redo_record(data)?,
+				wal::Item::Commit(..) | wal::Item::Checkpoint(..) => (),
 			}
 		}
 		Ok(())
 	}
 
-	fn create_undo_log(write: wal::WriteData<'_>) -> Option<UndoLog<'_>> {
+	/// Redoes a custom record, or undoes it again if the item logged that it
+	/// was undone.
+	fn redo_record(&self, data: wal::CustomData) -> Result<(), StorageError> {
+		let undo = data.undo;
+		let record = Self::custom_record(data);
+		if undo {
+			self.record_handlers.undo(&record)
+		} else {
+			self.record_handlers.redo(&record)
+		}
+	}
+
+	fn custom_record(data: wal::CustomData) -> CustomRecord {
+		CustomRecord {
+			transaction_id: data.transaction_data.transaction_id,
+			record_type: data.record_type,
+			version: data.version,
+			data: data.data.into_owned(),
+		}
+	}
+
+	fn create_undo_log(write: wal::WriteData<'_>) -> Option<UndoLog<'static>> {
 		let from_buf = write.from?;
 
 		Some(UndoLog {
 			transaction_id: write.transaction_data.transaction_id,
 			page_id: write.page_id,
 			offset: write.offset,
-			to: from_buf,
+			to: Cow::Owned(from_buf.into_owned()),
 		})
 	}
 
@@ -295,44 +696,21 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		mut handle: impl FnMut(PartialWriteOp) -> Result<(), StorageError>,
 	) -> Result<(), StorageError> {
 		let state = self.state.lock();
-		let last_indices: Vec<WalIndex> = transaction_ids
-			.iter()
-			.filter_map(|tid| state.transactions.get(tid).map(|ts| ts.last_index))
-			.collect();
-
-		if last_indices.is_empty() {
+		let Some(first_gen) = state.first_transaction_gen(transaction_ids) else {
 			return Ok(());
-		}
-
-		let lowest_index: WalIndex = *last_indices.iter().min().unwrap();
+		};
 		mem::drop(state);
 
-		let mut compensation_items: Vec<UndoLog> = Vec::new();
-
-		'gen_loop: for generation in gens.generations.iter().rev() {
-			let mut wal_file = generation.file.lock();
-			'item_loop: for item_result in wal_file.iter_items_reverse()? {
-				let (offset, item) = item_result?;
-				let index = WalIndex::new(generation.gen_num, offset);
-				if index < lowest_index {
-					break 'gen_loop;
-				}
-
-				if let wal::Item::Write(data) = item {
-					if !transaction_ids.contains(&data.transaction_data.transaction_id) {
-						continue 'item_loop;
-					}
-					if let Some(compensation_item) = Self::create_undo_log(data) {
-						compensation_items.push(compensation_item);
-					}
+		let compensation_items = Self::collect_undo_logs(transaction_ids, first_gen, gens)?;
+		for item in compensation_items {
+			match item {
+				Compensation::Write(log) => {
+					self.apply_undo_log(log, gens, &mut handle)?;
 				}
+				Compensation::Custom(record) => self.undo_record(record, gens)?,
 			}
 		}
 
-		for item in compensation_items {
-			self.apply_undo_log(item, gens, &mut handle)?;
-		}
-
 		for tid in transaction_ids {
 			self.push_raw_item(wal::Item::Commit(self.create_transaction_data(*tid)), gens)?;
 
@@ -344,6 +722,48 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		Ok(())
 	}
 
+	fn collect_undo_logs(
+		transaction_ids: &[u64],
+		first_gen: u64,
+		gens: &GenerationQueue<DF>,
+	) -> Result<Vec<Compensation>, StorageError> {
+		let mut compensation_items: Vec<Compensation> = Vec::new();
+
+		// The transactions may have written anywhere from the start of their
+		// first generation, so all of it has to be searched.
+		let mut buf = Vec::new();
+		for generation in gens.generations.iter().rev() {
+			if generation.gen_num < first_gen {
+				break;
+			}
+			let mut wal_file = generation.file.lock();
+			let mut items = wal_file.iter_items_reverse()?;
+			'item_loop: while let Some((_, item)) = items.next_into(&mut buf)? {
+				match item {
+					wal::Item::Write(data) => {
+						if !transaction_ids.contains(&data.transaction_data.transaction_id) {
+							continue 'item_loop;
+						}
+						if let Some(undo_log) = Self::create_undo_log(data) {
+							compensation_items.push(Compensation::Write(undo_log));
+						}
+					}
+					wal::Item::Custom(data) => {
+						if data.undo
+							|| !transaction_ids.contains(&data.transaction_data.transaction_id)
+						{
+							continue 'item_loop;
+						}
+						compensation_items.push(Compensation::Custom(Self::custom_record(data)));
+					}
+					wal::Item::Commit(..) | wal::Item::Checkpoint(..) => (),
+				}
+			}
+		}
+
+		Ok(compensation_items)
+	}
+
 	fn push_raw_item(
 		&self,
 		item: wal::Item,
@@ -352,23 +772,42 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		let Some(mut wal_file) = gens.current_generation() else {
 			return Err(StorageError::WalNotInitialized);
 		};
-		let index = WalIndex::new(gens.current_gen_num, wal_file.next_offset());
+		let index = self.append_item(item, gens.current_gen_num, &mut wal_file)?;
+		self.checkpoint_if_full(&wal_file);
+		Ok(index)
+	}
+
+	fn append_item(
+		&self,
+		item: wal::Item,
+		gen_num: u64,
+		wal_file: &mut DF::WalFile,
+	) -> Result<WalIndex, StorageError> {
+		let index = WalIndex::new(gen_num, wal_file.next_offset());
 
 		let mut state = self.state.lock();
 		state.handle_item(index, &item);
 		mem::drop(state);
 
 		wal_file.push_item(item)?;
+		self.bytes_written.fetch_add(
+			wal_file.next_offset().get() - index.offset.get(),
+			Ordering::Relaxed,
+		);
+		Ok(index)
+	}
 
+	fn checkpoint_if_full(&self, wal_file: &DF::WalFile) {
 		if wal_file.size() >= self.max_generation_size {
 			let generations = Arc::clone(&self.generations);
 			let state = Arc::clone(&self.state);
 			let folder = Arc::clone(&self.folder);
-			self.thread_pool
-				.spawn_ok(Self::single_checkpoint_task(generations, state, folder))
+			self.tasks.spawn(
+				&self.thread_pool,
+				"WAL checkpoint",
+				Self::single_checkpoint_task(generations, state, folder),
+			)
 		}
-
-		Ok(index)
 	}
 
 	fn create_transaction_data(&self, transaction_id: u64) -> wal::TransactionData {
@@ -413,6 +852,24 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		self.push_raw_item(wal::Item::Write(write_data), gens)
 	}
 
+	/// Logs that the record is undone before undoing it, like for writes, so
+	/// that recovery undoes it again if it is interrupted.
+	fn undo_record(
+		&self,
+		record: CustomRecord,
+		gens: &GenerationQueue<DF>,
+	) -> Result<(), StorageError> {
+		let custom_data = wal::CustomData {
+			transaction_data: self.create_transaction_data(record.transaction_id),
+			record_type: record.record_type,
+			version: record.version,
+			data: Cow::Borrowed(&record.data),
+			undo: true,
+		};
+		self.push_raw_item(wal::Item::Custom(custom_data), gens)?;
+		self.record_handlers.undo(&record)
+	}
+
 	fn flush_impl(gens: &GenerationQueue<DF>) -> Result<(), StorageError> {
 		if let Some(mut gen) = gens.current_generation() {
 			gen.flush()?;
@@ -420,20 +877,79 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		Ok(())
 	}
 
-	async fn checkpoint(
+	/// Syncs the current generation, and returns the index up to which the WAL
+	/// is now durable.
+	fn sync_impl(gens: &GenerationQueue<DF>) -> Result<WalIndex, StorageError> {
+		let Some(mut gen) = gens.current_generation() else {
+			return Err(StorageError::WalNotInitialized);
+		};
+		let end = WalIndex::new(gens.current_gen_num, gen.next_offset());
+		gen.sync()?;
+		event!(
+			DEBUG,
+			generation = end.generation,
+			offset = end.offset.get(),
+			"Synced the WAL"
+		);
+		Ok(end)
+	}
+
+	/// Waits until the item at `index` is durable. Commits that arrive while
+	/// another one is syncing the WAL are covered by the next sync, so that
+	/// concurrent commits share a single sync.
+	fn sync_commit(&self, index: WalIndex) -> Result<(), StorageError> {
+		let mut state = self.group_commit.state.lock();
+		loop {
+			if state.synced_until.is_some_and(|synced| index < synced) {
+				return Ok(());
+			}
+			if !state.syncing {
+				break;
+			}
+			self.group_commit.synced.wait(&mut state);
+		}
+		state.syncing = true;
+		mem::drop(state);
+
+		if !self.group_commit_delay.is_zero() {
+			thread::sleep(self.group_commit_delay);
+		}
+		let result = Self::sync_impl(&self.generations.read());
+
+		let mut state = self.group_commit.state.lock();
+		state.syncing = false;
+		if let Ok(synced_until) = result {
+			state.synced_until = Option::max(state.synced_until, Some(synced_until));
+		}
+		mem::drop(state);
+		self.group_commit.synced.notify_all();
+
+		result?;
+		Ok(())
+	}
+
+	/// Starts a new WAL generation that begins with a checkpoint, and deletes
+	/// the generations that are no longer needed. Returns the number of the
+	/// new generation.
+	fn checkpoint(
 		generations: &RwLock<GenerationQueue<DF>>,
 		state: &Mutex<State>,
 		folder: &DF,
-	) -> Result<(), StorageError> {
+	) -> Result<u64, StorageError> {
 		let mut gens_mut = generations.write();
-		Self::flush_impl(&gens_mut)?;
+		// Commits only wait for the current generation to be synced, so
+		// previous generations must be durable.
+		Self::sync_impl(&gens_mut)?;
 		let gen_num = gens_mut.current_gen_num + 1;
 		let file = folder.open_wal_file(gen_num)?;
 		gens_mut.push_generation(gen_num, file);
+		event!(DEBUG, generation = gen_num, "Started a new WAL generation");
+		// Recovery starts from the latest checkpoint, so it must be durable
+		// before the generations it replaces are deleted.
+		Self::log_checkpoint(&gens_mut, state)?;
+		Self::sync_impl(&gens_mut)?;
 		Self::cleanup_generations(&mut gens_mut, state, folder)?;
-		mem::drop(gens_mut);
-		Self::log_checkpoint(generations, state)?;
-		Ok(())
+		Ok(gen_num)
 	}
 
 	async fn checkpoint_ok(
@@ -441,7 +957,7 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 		state: &Mutex<State>,
 		folder: &DF,
 	) {
-		if let Err(err) = Self::checkpoint(generations, state, folder).await {
+		if let Err(err) = Self::checkpoint(generations, state, folder) {
 			error!("A WAL checkpoint failed: {err}");
 		}
 	}
@@ -455,7 +971,7 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 	}
 
 	async fn periodic_checkpoint_task(
-		timer: Timer,
+		mut timer: Timer,
 		generations: Arc<RwLock<GenerationQueue<DF>>>,
 		state: Arc<Mutex<State>>,
 		folder: Arc<DF>,
@@ -469,7 +985,16 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> Wal<DF> {
 #[cfg_attr(test, automock)]
 #[allow(clippy::needless_lifetimes)]
 pub(crate) trait WalApi {
-	fn log_write<'a>(&self, log: WriteLog<'a>) -> Result<WalIndex, StorageError>;
+	/// Logs all writes and custom records of a transaction followed by its
+	/// commit, and waits until the commit is durable. The items are appended
+	/// in one batch, so they are contiguous in the WAL. Returns the indices of
+	/// the writes.
+	fn log_transaction<'a>(
+		&self,
+		writes: &[WriteLog<'a>],
+		records: &[CustomLog<'a>],
+		commit: CommitLog,
+	) -> Result<Vec<WalIndex>, StorageError>;
 
 	fn log_commit(&self, log: CommitLog) -> Result<WalIndex, StorageError>;
 
@@ -483,21 +1008,118 @@ pub(crate) trait WalApi {
 	where
 		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>;
 
-	fn cache_did_flush(&self);
+	fn dry_run_recovery(&self) -> Result<RecoveryReport, StorageError>;
+
+	fn next_transaction_id(&self) -> u64;
+
+	/// The index that the next logged item will get. Items logged so far all
+	/// have smaller indices.
+	fn position(&self) -> Result<WalIndex, StorageError>;
+
+	/// Starts a new WAL generation, and returns its number. All writes logged
+	/// after this belong to the new generation or a later one.
+	fn start_checkpoint(&self) -> Result<u64, StorageError>;
+
+	/// Must be called once all pages that were modified before `generation`
+	/// was started have been written to storage. Deletes the WAL generations
+	/// that are no longer needed for recovery.
+	fn finish_checkpoint(&self, generation: u64) -> Result<(), StorageError>;
+
+	/// Whether there is WAL content that a checkpoint would allow to be
+	/// deleted.
+	fn needs_checkpoint(&self) -> bool;
+
+	/// The total size of all WAL generations in bytes.
+	fn size(&self) -> usize;
+
+	/// Deletes the generations before the current one that are no longer
+	/// needed, without starting a new generation. Checkpoints do this as well,
+	/// but only after writing back all dirty pages; generations that were only
+	/// kept for transactions or subscriptions that have ended since can be
+	/// deleted right away. Returns the number of bytes that were deleted.
+	///
+	/// Items are never rewritten within a generation, since their positions
+	/// are referenced by the pages they wrote and by the dirty page tracking.
+	fn compact(&self) -> Result<usize, StorageError>;
+
+	/// The memory allocated for items that are not written to the WAL files
+	/// yet, in bytes.
+	fn buffer_memory(&self) -> usize;
+
+	/// The number of bytes of items that were logged since the WAL was
+	/// opened, including those of generations that were deleted since.
+	fn bytes_written(&self) -> u64;
+}
+
+impl<DF: DatabaseFolderApi> Drop for Wal<DF> {
+	fn drop(&mut self) {
+		self.group_commit.state.lock().closed = true;
+		self.group_commit.synced.notify_all();
+	}
+}
+
+impl<DF: DatabaseFolderApi> BackgroundTasks for Wal<DF> {
+	fn task_panic(&self) -> Option<TaskPanic> {
+		self.tasks.panic()
+	}
 }
 
 impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
-	fn log_write(&self, log: WriteLog) -> Result<WalIndex, StorageError> {
-		let write_data = self.create_write_data(log);
+	fn log_transaction(
+		&self,
+		writes: &[WriteLog],
+		records: &[CustomLog],
+		commit: CommitLog,
+	) -> Result<Vec<WalIndex>, StorageError> {
 		let gens = self.generations.read();
-		self.push_raw_item(wal::Item::Write(write_data), &gens)
+		// The file stays locked for the whole batch, so that the items of
+		// other transactions can't end up in between.
+		let Some(mut wal_file) = gens.current_generation() else {
+			return Err(StorageError::WalNotInitialized);
+		};
+		let mut indices = Vec::with_capacity(writes.len());
+		for write in writes {
+			let write_data = self.create_write_data(write.clone());
+			indices.push(self.append_item(
+				wal::Item::Write(write_data),
+				gens.current_gen_num,
+				&mut wal_file,
+			)?);
+		}
+		for record in records {
+			let custom_data = wal::CustomData {
+				transaction_data: self.create_transaction_data(record.transaction_id),
+				record_type: record.record_type,
+				version: record.version,
+				data: Cow::Borrowed(record.data),
+				undo: false,
+			};
+			self.append_item(
+				wal::Item::Custom(custom_data),
+				gens.current_gen_num,
+				&mut wal_file,
+			)?;
+		}
+		let transaction_data = self.create_transaction_data(commit.transaction_id);
+		let commit_index = self.append_item(
+			wal::Item::Commit(transaction_data),
+			gens.current_gen_num,
+			&mut wal_file,
+		)?;
+		self.checkpoint_if_full(&wal_file);
+		mem::drop(wal_file);
+		mem::drop(gens);
+
+		self.sync_commit(commit_index)?;
+		Ok(indices)
 	}
 
 	fn log_commit(&self, log: CommitLog) -> Result<WalIndex, StorageError> {
 		let transaction_data = self.create_transaction_data(log.transaction_id);
 		let gens = self.generations.read();
 		let index = self.push_raw_item(wal::Item::Commit(transaction_data), &gens)?;
-		Self::flush_impl(&gens)?;
+		mem::drop(gens);
+		self.sync_commit(index)?;
 		Ok(index)
 	}
 
@@ -518,44 +1140,431 @@ impl<DF: DatabaseFolderApi + Send + Sync + 'static> WalApi for Wal<DF> {
 		// acquire exclusive gen lock to prevent conflicts
 		let mut gens = self.generations.write();
 
-		let Some(mut file) = gens.current_generation() else {
-			return Err(StorageError::WalNotInitialized);
-		};
-
-		self.read_initial_state(&mut file)?;
-		self.recover_state(&mut file, gens.current_gen_num)?;
-		#[allow(clippy::needless_borrows_for_generic_args)]
-		self.redo(&mut file, gens.current_gen_num, &mut handle)?;
-		mem::drop(file);
+		let state = Self::read_final_state(&gens)?;
+		*self.state.lock() = state;
+
+		// Custom records are redone from all generations that are still kept,
+		// so generations before the first dirty page can't be skipped.
+		for generation in &gens.generations {
+			event!(
+				INFO,
+				generation = generation.gen_num,
+				"Replaying WAL generation"
+			);
+			let mut file = generation.file.lock();
+			#[allow(clippy::needless_borrows_for_generic_args)]
+			self.redo(&mut file, generation.gen_num, &mut handle)?;
+		}
 
 		let state = self.state.lock();
-		let all_tids = state.transactions.keys().copied().collect::<Vec<_>>();
+		let mut all_tids = state.transactions.keys().copied().collect::<Vec<_>>();
 		mem::drop(state);
+		all_tids.sort_unstable();
+		event!(
+			INFO,
+			num_transactions = all_tids.len(),
+			"Rolling back incomplete transactions"
+		);
 
 		self.undo_all(&all_tids, &mut gens, handle)?;
 
 		Ok(())
 	}
 
-	fn cache_did_flush(&self) {
-		let mut state = self.state.lock();
-		state.cache_did_flush();
-	}
-}
+	fn dry_run_recovery(&self) -> Result<RecoveryReport, StorageError> {
+		// acquire exclusive gen lock to get a consistent view of the WAL
+		let gens = self.generations.write();
+		let mut report = RecoveryReport::default();
 
-struct WalGeneration<DF: DatabaseFolderApi> {
-	gen_num: u64,
-	file: Mutex<DF::WalFile>,
-}
+		let mut buf = Vec::new();
+		for generation in &gens.generations {
+			let mut wal_file = generation.file.lock();
+			let mut items = wal_file.iter_items()?;
+			while let Some((_, item)) = items.next_into(&mut buf)? {
+				report.num_items += 1;
+				if let wal::Item::Custom(..) = item {
+					report.num_redo_records += 1;
+				}
+			}
+		}
 
-impl<DF: DatabaseFolderApi> WalGeneration<DF> {
-	fn new(generation_num: u64, file: DF::WalFile) -> Self {
-		Self {
-			gen_num: generation_num,
-			file: Mutex::new(file),
+		let state = Self::read_final_state(&gens)?;
+		let first_dirty_gen = state.first_dirty_generation();
+		for generation in &gens.generations {
+			if generation.gen_num < first_dirty_gen {
+				continue;
+			}
+			let mut file = generation.file.lock();
+			let mut items = file.iter_items()?;
+			while let Some((offset, item)) = items.next_into(&mut buf)? {
+				let index = WalIndex::new(generation.gen_num, offset);
+				if let wal::Item::Write(data) = item {
+					if state.needs_redo(index, data.page_id) {
+						report.num_redo_writes += 1;
+						report.redo_pages.insert(data.page_id);
+					}
+				}
+			}
+		}
+
+		let mut transaction_ids: Vec<u64> = state.transactions.keys().copied().collect();
+		transaction_ids.sort_unstable();
+		if let Some(first_gen) = state.first_transaction_gen(&transaction_ids) {
+			for item in Self::collect_undo_logs(&transaction_ids, first_gen, &gens)? {
+				match item {
+					Compensation::Write(..) => report.num_undo_writes += 1,
+					Compensation::Custom(..) => report.num_undo_records += 1,
+				}
+			}
 		}
+		report.undo_transactions = transaction_ids;
+
+		Ok(report)
 	}
-}
+
+	fn next_transaction_id(&self) -> u64 {
+		self.state.lock().next_transaction_id
+	}
+
+	fn position(&self) -> Result<WalIndex, StorageError> {
+		let gens = self.generations.read();
+		let Some(wal_file) = gens.current_generation() else {
+			return Err(StorageError::WalNotInitialized);
+		};
+		Ok(WalIndex::new(gens.current_gen_num, wal_file.next_offset()))
+	}
+
+	fn start_checkpoint(&self) -> Result<u64, StorageError> {
+		Self::checkpoint(&self.generations, &self.state, &self.folder)
+	}
+
+	fn finish_checkpoint(&self, generation: u64) -> Result<(), StorageError> {
+		self.state.lock().pages_flushed_before(generation);
+		Self::checkpoint(&self.generations, &self.state, &self.folder)?;
+		Ok(())
+	}
+
+	fn needs_checkpoint(&self) -> bool {
+		self.generations.read().generations.len() > 1 || !self.state.lock().dirty_pages.is_empty()
+	}
+
+	fn size(&self) -> usize {
+		self.generations.read().size()
+	}
+
+	fn compact(&self) -> Result<usize, StorageError> {
+		let mut gens = self.generations.write();
+		let size_before = gens.size();
+		Self::cleanup_generations(&mut gens, &self.state, &*self.folder)?;
+		Ok(size_before - gens.size())
+	}
+
+	fn buffer_memory(&self) -> usize {
+		let gens = self.generations.read();
+		gens.generations
+			.iter()
+			.map(|gen| gen.file.lock().buffer_capacity())
+			.sum()
+	}
+
+	fn bytes_written(&self) -> u64 {
+		self.bytes_written.load(Ordering::Relaxed)
+	}
+}
+
+/// A stand-in for the WAL for storage that doesn't need to be durable.
+///
+/// Since transactions only apply their writes to the cache on commit, undoing
+/// them doesn't require a log.
+#[derive(Debug, Default)]
+pub(crate) struct NoWal {
+	next_offset: AtomicU64,
+}
+
+impl NoWal {
+	fn next_index(&self) -> WalIndex {
+		let offset = self.next_offset.fetch_add(1, Ordering::Relaxed);
+		WalIndex::new(0, NonZeroU64::new(offset + 1).unwrap())
+	}
+}
+
+impl BackgroundTasks for NoWal {
+	fn task_panic(&self) -> Option<TaskPanic> {
+		None
+	}
+}
+
+impl WalApi for NoWal {
+	fn log_transaction(
+		&self,
+		writes: &[WriteLog],
+		records: &[CustomLog],
+		_commit: CommitLog,
+	) -> Result<Vec<WalIndex>, StorageError> {
+		let indices = writes.iter().map(|_| self.next_index()).collect();
+		for _ in records {
+			self.next_index();
+		}
+		self.next_index();
+		Ok(indices)
+	}
+
+	fn log_commit(&self, _log: CommitLog) -> Result<WalIndex, StorageError> {
+		Ok(self.next_index())
+	}
+
+	fn undo<HFn>(&self, _transaction_id: u64, _handle: HFn) -> Result<(), StorageError>
+	where
+		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>,
+	{
+		Ok(())
+	}
+
+	fn recover<HFn>(&self, _handle: &mut HFn) -> Result<(), StorageError>
+	where
+		HFn: FnMut(PartialWriteOp) -> Result<(), StorageError>,
+	{
+		Ok(())
+	}
+
+	fn dry_run_recovery(&self) -> Result<RecoveryReport, StorageError> {
+		Ok(RecoveryReport::default())
+	}
+
+	fn next_transaction_id(&self) -> u64 {
+		0
+	}
+
+	fn position(&self) -> Result<WalIndex, StorageError> {
+		let offset = self.next_offset.load(Ordering::Relaxed);
+		Ok(WalIndex::new(0, NonZeroU64::new(offset + 1).unwrap()))
+	}
+
+	fn start_checkpoint(&self) -> Result<u64, StorageError> {
+		Ok(0)
+	}
+
+	fn finish_checkpoint(&self, _generation: u64) -> Result<(), StorageError> {
+		Ok(())
+	}
+
+	fn needs_checkpoint(&self) -> bool {
+		false
+	}
+
+	fn size(&self) -> usize {
+		0
+	}
+
+	fn compact(&self) -> Result<usize, StorageError> {
+		Ok(0)
+	}
+
+	fn buffer_memory(&self) -> usize {
+		0
+	}
+
+	fn bytes_written(&self) -> u64 {
+		0
+	}
+}
+
+#[derive(Default)]
+struct GroupCommit {
+	state: Mutex<GroupCommitState>,
+	synced: Condvar,
+}
+
+#[derive(Default)]
+struct GroupCommitState {
+	synced_until: Option<WalIndex>,
+	syncing: bool,
+	/// Whether the WAL was dropped, so nothing will be synced anymore.
+	closed: bool,
+}
+
+/// Reads committed transactions from the WAL for [`Wal::subscribe`].
+pub(crate) struct WalSubscription<DF: DatabaseFolderApi> {
+	id: u64,
+	generations: Arc<RwLock<GenerationQueue<DF>>>,
+	state: Arc<Mutex<State>>,
+	group_commit: Arc<GroupCommit>,
+	from: WalIndex,
+	/// All items before this index were read.
+	read_until: WalIndex,
+	/// The writes of the transactions that haven't committed yet.
+	pending: HashMap<u64, PendingTransaction>,
+	ready: VecDeque<WalRecord>,
+}
+
+struct PendingTransaction {
+	first_gen: u64,
+	/// Whether the first write of the transaction was read.
+	complete: bool,
+	/// Whether the transaction was rolled back, so that its writes cancel out.
+	rolled_back: bool,
+	/// The writes and custom records of the transaction.
+	writes: Vec<WalRecord>,
+}
+
+impl<DF: DatabaseFolderApi> WalSubscription<DF> {
+	/// Returns the next record, waiting until one is durable if `wait` is set.
+	/// Returns `None` if no record is available, or once the WAL is closed.
+	pub fn next_record(&mut self, wait: bool) -> Result<Option<WalRecord>, StorageError> {
+		loop {
+			if let Some(record) = self.ready.pop_front() {
+				return Ok(Some(record));
+			}
+
+			let mut group_commit = self.group_commit.state.lock();
+			let synced_until = loop {
+				if let Some(synced) = group_commit.synced_until {
+					if synced > self.read_until {
+						break synced;
+					}
+				}
+				if group_commit.closed || !wait {
+					return Ok(None);
+				}
+				self.group_commit.synced.wait(&mut group_commit);
+			};
+			mem::drop(group_commit);
+
+			self.read_items(synced_until)?;
+		}
+	}
+
+	fn read_items(&mut self, until: WalIndex) -> Result<(), StorageError> {
+		let generations = Arc::clone(&self.generations);
+		let gens = generations.read();
+		let mut buf = Vec::new();
+		for generation in &gens.generations {
+			if generation.gen_num < self.read_until.generation {
+				continue;
+			}
+			if generation.gen_num > until.generation {
+				break;
+			}
+			let mut file = generation.file.lock();
+			let mut items = if generation.gen_num == self.read_until.generation {
+				file.iter_items_from(self.read_until.offset)?
+			} else {
+				file.iter_items()?
+			};
+			while let Some((offset, item)) = items.next_into(&mut buf)? {
+				let index = WalIndex::new(generation.gen_num, offset);
+				if index >= until {
+					break;
+				}
+				self.handle_item(index, item)?;
+			}
+		}
+		mem::drop(gens);
+		self.read_until = until;
+
+		let first_needed = self
+			.pending
+			.values()
+			.map(|pending| pending.first_gen)
+			.fold(until.generation, u64::min);
+		self.state
+			.lock()
+			.subscriptions
+			.insert(self.id, first_needed);
+		Ok(())
+	}
+
+	fn handle_item(&mut self, index: WalIndex, item: wal::Item) -> Result<(), StorageError> {
+		match item {
+			wal::Item::Write(data) => {
+				let transaction_id = data.transaction_data.transaction_id;
+				let pending = self.pending_transaction(index, &data.transaction_data);
+				if data.from.is_none() {
+					// Compensation writes undo all writes of the transaction.
+					pending.rolled_back = true;
+					pending.writes = Vec::new();
+				} else if !pending.rolled_back {
+					pending.writes.push(WalRecord::Write {
+						transaction_id,
+						page_id: data.page_id,
+						offset: data.offset,
+						data: data.to.into_owned(),
+					});
+				}
+			}
+			wal::Item::Custom(data) => {
+				let transaction_id = data.transaction_data.transaction_id;
+				let pending = self.pending_transaction(index, &data.transaction_data);
+				if data.undo {
+					pending.rolled_back = true;
+					pending.writes = Vec::new();
+				} else if !pending.rolled_back {
+					pending.writes.push(WalRecord::Custom {
+						transaction_id,
+						record_type: data.record_type,
+						version: data.version,
+						data: data.data.into_owned(),
+					});
+				}
+			}
+			wal::Item::Commit(data) => {
+				let pending = self.pending.remove(&data.transaction_id);
+				if index < self.from {
+					return Ok(());
+				}
+				let writes = match pending {
+					Some(pending) if pending.rolled_back => return Ok(()),
+					Some(pending) if pending.complete => pending.writes,
+					None if data.prev_transaction_item.is_none() => Vec::new(),
+					_ => return Err(StorageError::WalPositionUnavailable(self.from.into())),
+				};
+				self.ready.extend(writes);
+				self.ready.push_back(WalRecord::Commit {
+					transaction_id: data.transaction_id,
+					position: WalIndex::new(index.generation, index.offset.saturating_add(1))
+						.into(),
+				});
+			}
+			wal::Item::Checkpoint(..) => (),
+		}
+		Ok(())
+	}
+
+	fn pending_transaction(
+		&mut self,
+		index: WalIndex,
+		data: &wal::TransactionData,
+	) -> &mut PendingTransaction {
+		self.pending
+			.entry(data.transaction_id)
+			.or_insert_with(|| PendingTransaction {
+				first_gen: index.generation,
+				complete: data.prev_transaction_item.is_none(),
+				rolled_back: false,
+				writes: Vec::new(),
+			})
+	}
+}
+
+impl<DF: DatabaseFolderApi> Drop for WalSubscription<DF> {
+	fn drop(&mut self) {
+		self.state.lock().subscriptions.remove(&self.id);
+	}
+}
+
+struct WalGeneration<DF: DatabaseFolderApi> {
+	gen_num: u64,
+	file: Mutex<DF::WalFile>,
+}
+
+impl<DF: DatabaseFolderApi> WalGeneration<DF> {
+	fn new(generation_num: u64, file: DF::WalFile) -> Self {
+		Self {
+			gen_num: generation_num,
+			file: Mutex::new(file),
+		}
+	}
+}
 
 struct GenerationQueue<DF: DatabaseFolderApi> {
 	generations: VecDeque<WalGeneration<DF>>,
@@ -576,31 +1585,60 @@ impl<DF: DatabaseFolderApi> GenerationQueue<DF> {
 			.push_back(WalGeneration::new(gen_num, file))
 	}
 
-	fn current_generation(&self) -> Option<MutexGuard<DF::WalFile>> {
+	fn current_generation(&self) -> Option<MutexGuard<'_, DF::WalFile>> {
 		let generation = self.generations.back()?;
 		assert_eq!(generation.gen_num, self.current_gen_num);
 		Some(generation.file.lock())
 	}
+
+	/// The total size of the generations' files, in bytes.
+	fn size(&self) -> usize {
+		self.generations
+			.iter()
+			.map(|gen| gen.file.lock().size())
+			.fold(0, usize::saturating_add)
+	}
 }
 
 #[derive(Debug, Clone, Default)]
 struct State {
+	/// The index of the first write to each page that may not have been written
+	/// to storage yet.
 	dirty_pages: HashMap<PageId, WalIndex>,
+	/// The index of the latest write to each page in `dirty_pages`. This isn't
+	/// part of checkpoints.
+	latest_writes: HashMap<PageId, WalIndex>,
 	transactions: HashMap<u64, TransactionState>,
+	next_transaction_id: u64,
+	/// The first generation that each subscription still has to read. This
+	/// isn't part of checkpoints.
+	subscriptions: HashMap<u64, u64>,
+	next_subscription_id: u64,
 }
 
 impl State {
 	fn new(
 		dirty_pages: HashMap<PageId, WalIndex>,
 		transactions: HashMap<u64, TransactionState>,
+		next_transaction_id: u64,
 	) -> Self {
 		Self {
+			latest_writes: dirty_pages.clone(),
 			dirty_pages,
 			transactions,
+			next_transaction_id,
+			subscriptions: HashMap::new(),
+			next_subscription_id: 0,
 		}
 	}
 
+	fn observe_transaction_id(&mut self, transaction_id: u64) {
+		self.next_transaction_id =
+			u64::max(self.next_transaction_id, transaction_id.saturating_add(1));
+	}
+
 	fn track_transaction(&mut self, index: WalIndex, transaction_id: u64) {
+		self.observe_transaction_id(transaction_id);
 		match self.transactions.entry(transaction_id) {
 			Entry::Vacant(entry) => {
 				entry.insert(TransactionState {
@@ -621,10 +1659,48 @@ impl State {
 	fn track_write(&mut self, index: WalIndex, data: &wal::WriteData) {
 		self.track_transaction(index, data.transaction_data.transaction_id);
 		self.dirty_pages.entry(data.page_id).or_insert(index);
+		self.latest_writes.insert(data.page_id, index);
+	}
+
+	fn pages_flushed_before(&mut self, generation: u64) {
+		let generation_start = WalIndex::new(generation, NonZeroU64::MIN);
+		let latest_writes = &self.latest_writes;
+		self.dirty_pages.retain(|page_id, first_index| {
+			if first_index.generation >= generation {
+				return true;
+			}
+			// Writes since the start of the generation may have happened after the
+			// page was flushed.
+			if latest_writes[page_id] >= generation_start {
+				*first_index = generation_start;
+				return true;
+			}
+			false
+		});
+		let dirty_pages = &self.dirty_pages;
+		self.latest_writes
+			.retain(|page_id, _| dirty_pages.contains_key(page_id));
+	}
+
+	fn first_dirty_generation(&self) -> u64 {
+		self.dirty_pages
+			.values()
+			.map(|index| index.generation)
+			.min()
+			.unwrap_or(u64::MAX)
 	}
 
-	fn cache_did_flush(&mut self) {
-		self.dirty_pages.clear();
+	fn needs_redo(&self, index: WalIndex, page_id: PageId) -> bool {
+		self.dirty_pages
+			.get(&page_id)
+			.is_some_and(|first_dirty_index| index >= *first_dirty_index)
+	}
+
+	fn first_transaction_gen(&self, transaction_ids: &[u64]) -> Option<u64> {
+		transaction_ids
+			.iter()
+			.filter_map(|tid| self.transactions.get(tid).map(|ts| ts.first_gen))
+			.min()
 	}
 
 	fn first_needed_generation(&self) -> u64 {
@@ -633,12 +1709,26 @@ impl State {
 			.map(|ts| ts.first_gen)
 			.min()
 			.unwrap_or(u64::MAX)
+			.min(self.first_dirty_generation())
+			.min(
+				self.subscriptions
+					.values()
+					.copied()
+					.min()
+					.unwrap_or(u64::MAX),
+			)
 	}
 
 	fn handle_item(&mut self, index: WalIndex, item: &wal::Item) {
 		match item {
 			wal::Item::Write(data) => self.track_write(index, data),
-			wal::Item::Commit(data) => self.complete_transaction(data.transaction_id),
+			wal::Item::Commit(data) => {
+				self.observe_transaction_id(data.transaction_id);
+				self.complete_transaction(data.transaction_id);
+			}
+			wal::Item::Custom(data) => {
+				self.track_transaction(index, data.transaction_data.transaction_id)
+			}
 			wal::Item::Checkpoint(..) => (),
 		}
 	}
@@ -646,13 +1736,16 @@ impl State {
 
 #[cfg(test)]
 mod tests {
+	use std::sync::Barrier;
+
 	use mockall::{predicate::*, Sequence};
 
 	use crate::{
 		files::MockDatabaseFolderApi,
 		page_store::{
-			test_helpers::{page_id, wal_index},
+			test_helpers::{page_id, wal_index, HandlerCall, RecordingHandler},
 			wal::tests::wal::test_helpers::mock_wal_file,
+			WalRecordHandler,
 		},
 		utils::test_helpers::{map, non_zero},
 	};
@@ -682,6 +1775,7 @@ mod tests {
 					.once()
 					.withf(|item| {
 						item == &wal::Item::Checkpoint(CheckpointData {
+							next_transaction_id: 0,
 							transactions: Cow::Owned(HashMap::new()),
 							dirty_pages: Cow::Owned(HashMap::new()),
 						})
@@ -706,16 +1800,18 @@ mod tests {
 		folder.expect_iter_wal_files().returning(|| {
 			//  WAL content
 
-			// An older generation; has already been flushed to disk.
+			// An older generation; the page it writes to may not have been flushed to disk
+			// yet.
 			let generation_2 = mock_wal_file! {
 				// The initial checkpoint. Not relevant to this test case.
 				10 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 0,
 					transactions: Cow::Owned(HashMap::new()),
 					dirty_pages: Cow::Owned(HashMap::new())
 				}),
 
 				// This write item was flushed to disk, but has no corresponding commit. It should
-				// be reverted.
+				// be reapplied, and then reverted.
 				20 => wal::Item::Write(wal::WriteData {
 					transaction_data: wal::TransactionData {
 						transaction_id: 1,
@@ -746,6 +1842,7 @@ mod tests {
 				// The checkpoint for gen 3. The preceding fuzzy write item should be handled
 				// properly.
 				20 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 3,
 					transactions: Cow::Owned(map! {
 						1 => TransactionState {
 							first_gen: 2,
@@ -794,7 +1891,14 @@ mod tests {
 				.in_sequence(&mut seq)
 				.returning(|_| Ok(non_zero!(40)));
 
-			// 3. check the WAL file size
+			// 3. get the offset after the item
+			generation_3
+				.expect_next_offset()
+				.once()
+				.in_sequence(&mut seq)
+				.returning(|| non_zero!(50));
+
+			// 4. check the WAL file size
 			generation_3
 				.expect_size()
 				.once()
@@ -823,7 +1927,14 @@ mod tests {
 				.in_sequence(&mut seq)
 				.returning(|_| Ok(non_zero!(50)));
 
-			// 3. check the WAL file size
+			// 3. get the offset after the item
+			generation_3
+				.expect_next_offset()
+				.once()
+				.in_sequence(&mut seq)
+				.returning(|| non_zero!(60));
+
+			// 4. check the WAL file size
 			generation_3
 				.expect_size()
 				.once()
@@ -835,6 +1946,13 @@ mod tests {
 
 		// when
 		let mut expected_ops = vec![
+			// This reapplies write (2, 20).
+			PartialWriteOp {
+				index: wal_index!(2, 20),
+				page_id: page_id!(100, 200),
+				offset: 25,
+				buf: &[1, 2, 3, 4],
+			},
 			// This reapplies write (3, 10).
 			PartialWriteOp {
 				index: wal_index!(3, 10),
@@ -865,4 +1983,405 @@ mod tests {
 		})
 		.unwrap();
 	}
+
+	#[test]
+	fn recover_custom_records() {
+		// expect
+		let pushed: Arc<Mutex<Vec<wal::Item<'static>>>> = Arc::default();
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_iter_wal_files().returning({
+			let pushed = Arc::clone(&pushed);
+			move || {
+				fn custom_item(transaction_id: u64, data: u8) -> wal::Item<'static> {
+					wal::Item::Custom(wal::CustomData {
+						transaction_data: wal::TransactionData {
+							transaction_id,
+							prev_transaction_item: None,
+						},
+						record_type: 7,
+						version: 1,
+						data: Cow::Owned(vec![data]),
+						undo: false,
+					})
+				}
+				let mut generation = mock_wal_file! {
+					10 => wal::Item::Checkpoint(wal::CheckpointData {
+						next_transaction_id: 0,
+						transactions: Cow::Owned(HashMap::new()),
+						dirty_pages: Cow::Owned(HashMap::new())
+					}),
+					// A committed record, which only has to be redone.
+					20 => custom_item(1, 1),
+					30 => wal::Item::Commit(wal::TransactionData {
+						transaction_id: 1,
+						prev_transaction_item: Some(wal_index!(0, 20))
+					}),
+					// A record without a commit, which has to be redone, and then
+					// undone.
+					40 => custom_item(2, 2)
+				};
+				let next_offset = Arc::new(AtomicU64::new(50));
+				let offset = Arc::clone(&next_offset);
+				generation
+					.expect_next_offset()
+					.returning(move || NonZeroU64::new(offset.load(Ordering::SeqCst)).unwrap());
+				let pushed = Arc::clone(&pushed);
+				generation.expect_push_item().returning(move |item| {
+					pushed.lock().push(item.into_owned());
+					Ok(NonZeroU64::new(next_offset.fetch_add(10, Ordering::SeqCst)).unwrap())
+				});
+				generation.expect_size().returning(|| 0);
+				Ok(vec![Ok((0, generation))].into_iter())
+			}
+		});
+
+		// given
+		let handler = Arc::new(RecordingHandler::default());
+		let mut record_handlers = WalRecordHandlers::default();
+		record_handlers.register(7, Arc::clone(&handler) as Arc<dyn WalRecordHandler>);
+		let wal = Wal::open(
+			Arc::new(folder),
+			Arc::new(ThreadPool::new().unwrap()),
+			&WalConfig {
+				record_handlers,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		// when
+		wal.recover(&mut |_| Ok(())).unwrap();
+
+		// then
+		let record = |transaction_id, data| CustomRecord {
+			transaction_id,
+			record_type: 7,
+			version: 1,
+			data: vec![data],
+		};
+		assert_eq!(
+			*handler.calls.lock(),
+			[
+				HandlerCall::Redo(record(1, 1)),
+				HandlerCall::Redo(record(2, 2)),
+				HandlerCall::Undo(record(2, 2)),
+			]
+		);
+		assert_eq!(
+			*pushed.lock(),
+			[
+				wal::Item::Custom(wal::CustomData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 2,
+						prev_transaction_item: Some(wal_index!(0, 40)),
+					},
+					record_type: 7,
+					version: 1,
+					data: Cow::Owned(vec![2]),
+					undo: true,
+				}),
+				wal::Item::Commit(wal::TransactionData {
+					transaction_id: 2,
+					prev_transaction_item: Some(wal_index!(0, 50)),
+				}),
+			]
+		);
+	}
+
+	#[test]
+	fn group_commit() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_clear_wal_files().returning(|| Ok(()));
+		folder.expect_open_wal_file().with(eq(0)).returning(|_| {
+			let next_offset = Arc::new(AtomicU64::new(1));
+			let mut file = MockWalFileApi::new();
+			let offset = Arc::clone(&next_offset);
+			file.expect_next_offset()
+				.returning(move || NonZeroU64::new(offset.load(Ordering::SeqCst)).unwrap());
+			file.expect_push_item().returning(move |_| {
+				Ok(NonZeroU64::new(next_offset.fetch_add(1, Ordering::SeqCst)).unwrap())
+			});
+			file.expect_size().returning(|| 0);
+			// All commits should share a single sync.
+			file.expect_sync().once().returning(|| Ok(()));
+			Ok(file)
+		});
+
+		// given
+		let wal = Wal::create(
+			Arc::new(folder),
+			Arc::new(ThreadPool::new().unwrap()),
+			&WalConfig {
+				group_commit_delay: Duration::from_millis(200),
+				..Default::default()
+			},
+		)
+		.unwrap();
+		let barrier = Barrier::new(4);
+
+		// when
+		thread::scope(|scope| {
+			for transaction_id in 0..4 {
+				let wal = &wal;
+				let barrier = &barrier;
+				scope.spawn(move || {
+					barrier.wait();
+					wal.log_commit(CommitLog { transaction_id }).unwrap();
+				});
+			}
+		});
+	}
+
+	#[test]
+	fn forget_flushed_dirty_pages() {
+		// given
+		let mut state = State {
+			dirty_pages: map! {
+				page_id!(1, 1) => wal_index!(1, 10),
+				page_id!(1, 2) => wal_index!(1, 20),
+				page_id!(1, 3) => wal_index!(2, 10)
+			},
+			latest_writes: map! {
+				page_id!(1, 1) => wal_index!(1, 10),
+				page_id!(1, 2) => wal_index!(2, 20),
+				page_id!(1, 3) => wal_index!(2, 10)
+			},
+			..Default::default()
+		};
+
+		// when
+		state.pages_flushed_before(2);
+
+		// then
+		assert_eq!(
+			state.dirty_pages,
+			map! {
+				page_id!(1, 2) => wal_index!(2, 1),
+				page_id!(1, 3) => wal_index!(2, 10)
+			}
+		);
+		assert_eq!(state.first_needed_generation(), 2);
+	}
+
+	#[test]
+	fn dry_run_recovery() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_iter_wal_files().returning(|| {
+			//  WAL content
+
+			// An older generation; the page it writes to may not have been flushed to disk
+			// yet.
+			let generation_2 = mock_wal_file! {
+				// The initial checkpoint. Not relevant to this test case.
+				10 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 0,
+					transactions: Cow::Owned(HashMap::new()),
+					dirty_pages: Cow::Owned(HashMap::new())
+				}),
+
+				// This write item was flushed to disk, but has no corresponding commit. It should
+				// be reapplied, and then reverted.
+				20 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 1,
+						prev_transaction_item: None
+					},
+					page_id: page_id!(100, 200),
+					offset: 25,
+					from: Some(vec![2, 2, 2, 2].into()),
+					to: vec![1, 2, 3, 4].into()
+				})
+			};
+
+			// The current generation; has likely not yet been flushed to disk.
+			let generation_3 = mock_wal_file! {
+				// This write item has a corresponding commit, but wasn't yet flushed to disk;
+				// It should be reapplied.
+				10 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 2,
+						prev_transaction_item: None
+					},
+					page_id: page_id!(25, 69),
+					offset: 100,
+					from: Some(vec![0, 0, 0, 0].into()),
+					to: vec![1, 2, 3, 4].into()
+				}),
+
+				// The checkpoint for gen 3. The preceding fuzzy write item should be handled
+				// properly.
+				20 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 3,
+					transactions: Cow::Owned(map! {
+						1 => TransactionState {
+							first_gen: 2,
+							last_index: wal_index!(2, 20)
+						}
+					}),
+					dirty_pages: Cow::Owned(map! {
+						page_id!(100, 200) => wal_index!(2, 20)
+					})
+				}),
+
+				// The commit item for the write item at offset 10.
+				30 => wal::Item::Commit(wal::TransactionData {
+					transaction_id: 2,
+					prev_transaction_item: Some(wal_index!(2, 30))
+				})
+			};
+
+			Ok(vec![Ok((2, generation_2)), Ok((3, generation_3))].into_iter())
+		});
+
+		// given
+		let wal = Wal::open(
+			Arc::new(folder),
+			Arc::new(ThreadPool::new().unwrap()),
+			&WalConfig::default(),
+		)
+		.unwrap();
+
+		// when
+		let report = wal.dry_run_recovery().unwrap();
+
+		// then
+		assert_eq!(
+			report,
+			RecoveryReport {
+				num_items: 5,
+				num_redo_writes: 2,
+				redo_pages: [page_id!(100, 200), page_id!(25, 69)].into(),
+				undo_transactions: vec![1],
+				num_undo_writes: 1,
+				num_redo_records: 0,
+				num_undo_records: 0,
+			}
+		);
+	}
+
+	#[test]
+	fn summarize_transactions() {
+		// expect
+		let mut folder = MockDatabaseFolderApi::new();
+		folder.expect_iter_wal_files().returning(|| {
+			//  WAL content
+
+			let generation_2 = mock_wal_file! {
+				10 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 0,
+					transactions: Cow::Owned(HashMap::new()),
+					dirty_pages: Cow::Owned(HashMap::new())
+				}),
+
+				// Transaction 1 writes, and is committed in the next generation.
+				20 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 1,
+						prev_transaction_item: None
+					},
+					page_id: page_id!(1, 1),
+					offset: 0,
+					from: Some(vec![0, 0, 0, 0].into()),
+					to: vec![1, 2, 3, 4].into()
+				}),
+
+				// Transaction 2 writes, and is rolled back.
+				30 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 2,
+						prev_transaction_item: None
+					},
+					page_id: page_id!(1, 2),
+					offset: 0,
+					from: Some(vec![0, 0].into()),
+					to: vec![1, 2].into()
+				}),
+				40 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 2,
+						prev_transaction_item: Some(wal_index!(2, 30))
+					},
+					page_id: page_id!(1, 2),
+					offset: 0,
+					from: None,
+					to: vec![0, 0].into()
+				}),
+				50 => wal::Item::Commit(wal::TransactionData {
+					transaction_id: 2,
+					prev_transaction_item: Some(wal_index!(2, 40))
+				})
+			};
+
+			let mut generation_3 = mock_wal_file! {
+				10 => wal::Item::Checkpoint(wal::CheckpointData {
+					next_transaction_id: 3,
+					transactions: Cow::Owned(HashMap::new()),
+					dirty_pages: Cow::Owned(HashMap::new())
+				}),
+				20 => wal::Item::Commit(wal::TransactionData {
+					transaction_id: 1,
+					prev_transaction_item: Some(wal_index!(2, 20))
+				}),
+
+				// Transaction 3 is still in progress.
+				30 => wal::Item::Write(wal::WriteData {
+					transaction_data: wal::TransactionData {
+						transaction_id: 3,
+						prev_transaction_item: None
+					},
+					page_id: page_id!(1, 3),
+					offset: 0,
+					from: Some(vec![0].into()),
+					to: vec![1].into()
+				})
+			};
+			generation_3.expect_flush().returning(|| Ok(()));
+
+			Ok(vec![Ok((2, generation_2)), Ok((3, generation_3))].into_iter())
+		});
+
+		// given
+		let wal = Wal::open(
+			Arc::new(folder),
+			Arc::new(ThreadPool::new().unwrap()),
+			&WalConfig::default(),
+		)
+		.unwrap();
+
+		// when
+		let transactions = wal.transactions().unwrap();
+
+		// then
+		assert_eq!(
+			transactions,
+			vec![
+				WalTransaction {
+					transaction_id: 1,
+					first: WalPosition::new(2, non_zero!(20)),
+					last: WalPosition::new(3, non_zero!(20)),
+					num_writes: 1,
+					num_bytes: 4,
+					status: WalTransactionStatus::Committed,
+				},
+				WalTransaction {
+					transaction_id: 2,
+					first: WalPosition::new(2, non_zero!(30)),
+					last: WalPosition::new(2, non_zero!(50)),
+					num_writes: 2,
+					num_bytes: 4,
+					status: WalTransactionStatus::RolledBack,
+				},
+				WalTransaction {
+					transaction_id: 3,
+					first: WalPosition::new(3, non_zero!(30)),
+					last: WalPosition::new(3, non_zero!(30)),
+					num_writes: 1,
+					num_bytes: 1,
+					status: WalTransactionStatus::Open,
+				},
+			]
+		);
+	}
 }
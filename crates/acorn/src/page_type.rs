@@ -0,0 +1,71 @@
+use std::{fmt, num::NonZeroU8};
+
+use crate::{
+	files::{segment::PAGE_BODY_SIZE, PageId},
+	page_store::StorageError,
+};
+
+/// A type that whole pages can be read as and written as, with
+/// [`Transaction::read_typed`](crate::Transaction::read_typed) and
+/// [`Transaction::write_typed`](crate::Transaction::write_typed).
+///
+/// The first byte of a typed page holds the tag of its type, followed by the
+/// encoded value. Reading a page as a type checks its tag first, so a page ID
+/// that points at the wrong kind of page is reported as an error, see
+/// [`Error::page_type_mismatch`](crate::Error::page_type_mismatch), instead
+/// of being decoded as garbage.
+pub trait PageType: Sized {
+	/// Identifies the pages of this type. Tag 0 is never used, since pages
+	/// that were never written start with it.
+	const TAG: NonZeroU8;
+
+	/// Decodes a value from the bytes that follow the tag, which are the
+	/// remaining [`Database::PAGE_SIZE`](crate::Database::PAGE_SIZE)` - 1`
+	/// bytes of the page.
+	fn decode(bytes: &[u8]) -> Self;
+
+	/// Encodes the value into the bytes that follow the tag, which are zeroed
+	/// beforehand.
+	fn encode(&self, bytes: &mut [u8]);
+}
+
+/// A page was read as a [`PageType`] with a different tag than the one it
+/// was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageTypeMismatch {
+	pub page_id: PageId,
+	pub expected: NonZeroU8,
+	/// The tag that the page starts with, which is 0 if it was never written.
+	pub found: u8,
+}
+
+impl fmt::Display for PageTypeMismatch {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"Expected page {} to have type tag {}, but found {}",
+			self.page_id, self.expected, self.found
+		)
+	}
+}
+
+/// Decodes a page that was read in full, after checking its tag.
+pub(crate) fn decode_page<T: PageType>(page_id: PageId, page: &[u8]) -> Result<T, StorageError> {
+	debug_assert_eq!(page.len(), PAGE_BODY_SIZE);
+	if page[0] != T::TAG.get() {
+		return Err(StorageError::PageTypeMismatch(PageTypeMismatch {
+			page_id,
+			expected: T::TAG,
+			found: page[0],
+		}));
+	}
+	Ok(T::decode(&page[1..]))
+}
+
+/// Encodes a value into a full page, starting with the tag of its type.
+pub(crate) fn encode_page<T: PageType>(value: &T) -> Vec<u8> {
+	let mut page = vec![0; PAGE_BODY_SIZE];
+	page[0] = T::TAG.get();
+	value.encode(&mut page[1..]);
+	page
+}
@@ -3,7 +3,20 @@ use std::{
 	mem,
 };
 
-use zerocopy::{AsBytes, FromBytes};
+use zerocopy::{byteorder, AsBytes, FromBytes};
+
+/// The byte order of the integers in the file formats. Files use the native
+/// byte order of the machine that wrote them, unless the `portable-format`
+/// feature fixes it to little-endian, so that they can be copied between
+/// machines with different byte orders.
+#[cfg(not(feature = "portable-format"))]
+pub(crate) type FileByteOrder = byteorder::NativeEndian;
+#[cfg(feature = "portable-format")]
+pub(crate) type FileByteOrder = byteorder::LittleEndian;
+
+pub(crate) type U16 = byteorder::U16<FileByteOrder>;
+pub(crate) type U32 = byteorder::U32<FileByteOrder>;
+pub(crate) type U64 = byteorder::U64<FileByteOrder>;
 
 pub(crate) trait Repr<T>: Sized + FromBytes + AsBytes
 where
@@ -1,12 +1,22 @@
 use std::{
+	any::Any,
+	fmt,
+	future::Future,
+	panic::AssertUnwindSafe,
 	sync::{
 		atomic::{AtomicBool, Ordering},
-		Arc,
+		Arc, OnceLock,
 	},
 	thread,
 	time::{Duration, SystemTime},
 };
 
+#[cfg(feature = "async")]
+use futures::channel::oneshot;
+use futures::{executor::ThreadPool, FutureExt};
+use log::error;
+use parking_lot::Mutex;
+
 #[derive(Clone)]
 pub(crate) struct FailureStrategy {
 	pub fatal: bool,
@@ -22,6 +32,197 @@ impl Default for FailureStrategy {
 	}
 }
 
+/// A thread pool for blocking work that finishes on its own, like reading
+/// pages in the background or the async variants of a database's operations.
+/// Each database has its own, with as many threads as the pool of its
+/// background tasks, so that its work doesn't queue up behind that of other
+/// databases. It is separate from the pool of the background tasks, because
+/// those are long-lived tasks that blocking work could end up waiting for.
+/// Its threads are only started once it is first used.
+#[derive(Debug, Default)]
+pub(crate) struct BlockingPool {
+	/// The number of threads, or `None` for one per CPU.
+	num_threads: Option<usize>,
+	pool: OnceLock<ThreadPool>,
+}
+
+impl BlockingPool {
+	pub fn new(num_threads: Option<usize>) -> Self {
+		Self {
+			num_threads,
+			pool: OnceLock::new(),
+		}
+	}
+
+	pub fn get(&self) -> &ThreadPool {
+		self.pool.get_or_init(|| {
+			let mut builder = ThreadPool::builder();
+			builder.name_prefix("acorn-blocking-");
+			if let Some(num_threads) = self.num_threads {
+				builder.pool_size(num_threads);
+			}
+			builder
+				.create()
+				.expect("Failed to create the thread pool for blocking work")
+		})
+	}
+
+	/// Runs blocking work on the pool right away, and returns a future that
+	/// completes with its result, so that awaiting it doesn't block the
+	/// caller's executor. If the work panics, the future completes with the
+	/// panic instead.
+	#[cfg(feature = "async")]
+	pub fn spawn_blocking<T, F>(
+		&self,
+		task: &'static str,
+		work: F,
+	) -> impl Future<Output = Result<T, TaskPanic>>
+	where
+		T: Send + 'static,
+		F: FnOnce() -> T + Send + 'static,
+	{
+		let (sender, receiver) = oneshot::channel();
+		self.get().spawn_ok(async move {
+			let result = std::panic::catch_unwind(AssertUnwindSafe(work))
+				.map_err(|payload| TaskPanic::new(task, payload.as_ref()));
+			let _ = sender.send(result);
+		});
+		receiver.map(move |result| {
+			result.unwrap_or_else(|oneshot::Canceled| {
+				Err(TaskPanic {
+					task,
+					message: "The task was dropped before it completed".to_string(),
+				})
+			})
+		})
+	}
+}
+
+/// A panic of one of the background tasks of a database. Once one panicked,
+/// pages may not be written back or checkpointed as they should anymore, so
+/// the database stops accepting transactions, but can still be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskPanic {
+	task: &'static str,
+	message: String,
+}
+
+impl TaskPanic {
+	fn new(task: &'static str, payload: &(dyn Any + Send)) -> Self {
+		let message = if let Some(message) = payload.downcast_ref::<&str>() {
+			(*message).to_string()
+		} else if let Some(message) = payload.downcast_ref::<String>() {
+			message.clone()
+		} else {
+			"Unknown panic".to_string()
+		};
+		Self { task, message }
+	}
+
+	/// The name of the task that panicked, like `"flush"`.
+	pub fn task(&self) -> &'static str {
+		self.task
+	}
+
+	/// The message the task panicked with.
+	pub fn message(&self) -> &str {
+		&self.message
+	}
+}
+
+impl fmt::Display for TaskPanic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"The background task '{}' panicked: {}",
+			self.task, self.message
+		)
+	}
+}
+
+/// Runs the background tasks of a component of a database, and records the
+/// first one that panicked, which would otherwise just end the thread it ran
+/// on.
+#[derive(Debug, Default)]
+pub(crate) struct TaskMonitor {
+	panic: Mutex<Option<TaskPanic>>,
+	restart: bool,
+}
+
+impl TaskMonitor {
+	const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+	const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+	/// Creates a monitor that restarts periodic tasks after they panicked if
+	/// `restart` is set, waiting twice as long after each panic.
+	pub fn new(restart: bool) -> Self {
+		Self {
+			panic: Mutex::new(None),
+			restart,
+		}
+	}
+
+	pub fn panic(&self) -> Option<TaskPanic> {
+		self.panic.lock().clone()
+	}
+
+	pub fn spawn<F>(self: &Arc<Self>, thread_pool: &ThreadPool, task: &'static str, future: F)
+	where
+		F: Future<Output = ()> + Send + 'static,
+	{
+		let monitor = Arc::clone(self);
+		thread_pool.spawn_ok(async move {
+			if let Err(payload) = AssertUnwindSafe(future).catch_unwind().await {
+				monitor.record(task, payload.as_ref());
+			}
+		});
+	}
+
+	/// Spawns a task that runs until `timer` is stopped, by calling `start`
+	/// with the timer.
+	pub fn spawn_periodic<S, F>(
+		self: &Arc<Self>,
+		thread_pool: &ThreadPool,
+		task: &'static str,
+		timer: Timer,
+		start: S,
+	) where
+		S: Fn(Timer) -> F + Send + 'static,
+		F: Future<Output = ()> + Send + 'static,
+	{
+		let monitor = Arc::clone(self);
+		thread_pool.spawn_ok(async move {
+			let mut backoff = Self::INITIAL_BACKOFF;
+			loop {
+				let result = AssertUnwindSafe(start(timer.clone())).catch_unwind().await;
+				let Err(payload) = result else {
+					return;
+				};
+				monitor.record(task, payload.as_ref());
+				if !monitor.restart || !timer.sleep(backoff) {
+					return;
+				}
+				error!("Restarting the background task '{task}'");
+				backoff = Duration::min(backoff * 2, Self::MAX_BACKOFF);
+			}
+		});
+	}
+
+	fn record(&self, task: &'static str, payload: &(dyn Any + Send)) {
+		let panic = TaskPanic::new(task, payload);
+		error!("{panic}. The database no longer accepts transactions");
+		self.panic.lock().get_or_insert(panic);
+	}
+}
+
+/// A component of a database that runs background tasks.
+pub(crate) trait BackgroundTasks {
+	/// The first panic of one of the component's background tasks, if any
+	/// panicked.
+	fn task_panic(&self) -> Option<TaskPanic>;
+}
+
+#[derive(Clone)]
 pub(crate) struct Timer {
 	last_run: SystemTime,
 	period: Duration,
@@ -39,7 +240,7 @@ impl Timer {
 		(timer, TimerHandle { active })
 	}
 
-	pub fn wait(&self) -> bool {
+	pub fn wait(&mut self) -> bool {
 		if !self.active.load(Ordering::Relaxed) {
 			return false;
 		}
@@ -49,7 +250,18 @@ impl Timer {
 				.unwrap_or(Duration::ZERO),
 		);
 		thread::sleep(duration);
-		true
+		self.reset();
+		self.active.load(Ordering::Relaxed)
+	}
+
+	/// Sleeps for `duration`, unless the timer was stopped, and returns
+	/// whether it is still active afterwards.
+	pub fn sleep(&self, duration: Duration) -> bool {
+		if !self.active.load(Ordering::Relaxed) {
+			return false;
+		}
+		thread::sleep(duration);
+		self.active.load(Ordering::Relaxed)
 	}
 
 	fn reset(&mut self) {
@@ -70,3 +282,91 @@ impl Drop for TimerHandle {
 		self.active.store(false, Ordering::Relaxed);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::AtomicUsize;
+
+	use super::*;
+
+	#[test]
+	fn restart_panicked_task() {
+		// given
+		let pool = ThreadPool::new().unwrap();
+		let monitor = Arc::new(TaskMonitor::new(true));
+		let runs = Arc::new(AtomicUsize::new(0));
+		let (timer, _timer_handle) = Timer::new(Duration::ZERO);
+
+		// when
+		monitor.spawn_periodic(&pool, "flush", timer, {
+			let runs = Arc::clone(&runs);
+			move |_| {
+				let runs = Arc::clone(&runs);
+				async move {
+					if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+						panic!("Injected panic");
+					}
+				}
+			}
+		});
+		while runs.load(Ordering::SeqCst) < 2 {
+			thread::yield_now();
+		}
+
+		// then
+		assert_eq!(
+			monitor.panic(),
+			Some(TaskPanic {
+				task: "flush",
+				message: "Injected panic".to_string()
+			})
+		);
+	}
+
+	#[test]
+	fn run_blocking_work_on_configured_number_of_threads() {
+		// given
+		let pool = BlockingPool::new(Some(1));
+		let threads = Arc::new(Mutex::new(Vec::new()));
+		let (sender, receiver) = std::sync::mpsc::channel();
+
+		// when
+		for _ in 0..8 {
+			let threads = Arc::clone(&threads);
+			let sender = sender.clone();
+			pool.get().spawn_ok(async move {
+				threads.lock().push(thread::current().id());
+				sender.send(()).unwrap();
+			});
+		}
+		for _ in 0..8 {
+			receiver.recv().unwrap();
+		}
+
+		// then
+		let mut threads = threads.lock().clone();
+		threads.dedup();
+		assert_eq!(threads.len(), 1);
+	}
+
+	#[cfg(feature = "async")]
+	#[test]
+	fn return_panic_of_blocking_work() {
+		// given
+		let pool = BlockingPool::default();
+
+		// when
+		let panicked = pool.spawn_blocking("read", || -> usize { panic!("Injected panic") });
+		let completed = pool.spawn_blocking("read", || 42);
+
+		// then
+		assert_eq!(
+			futures::executor::block_on(panicked),
+			Err(TaskPanic {
+				task: "read",
+				message: "Injected panic".to_string()
+			})
+		);
+		assert_eq!(futures::executor::block_on(completed), Ok(42));
+	}
+}
@@ -0,0 +1,416 @@
+//! Randomized model testing of the transaction layer.
+//!
+//! [`ModelTest`] runs interleaved transactions on a database, and the same
+//! operations on an in-memory model of its committed state. Reads are
+//! checked against the model as they happen, and the database is crashed at
+//! random points in between; after every recovery, it has to hold exactly
+//! the state of the model. A run is reproducible from its seed.
+
+use std::{
+	collections::BTreeMap,
+	io,
+	panic::{self, AssertUnwindSafe},
+	sync::Arc,
+	time::Instant,
+};
+
+use thiserror::Error;
+
+use crate::{
+	faults::{FaultyVfs, MemoryVfs},
+	files::FileError,
+	Database, DatabaseBuilder, Error, PageId, Transaction,
+};
+
+#[derive(Debug, Error)]
+pub enum ModelTestError {
+	#[error(transparent)]
+	Database(#[from] Error),
+
+	#[error("With seed {seed}, page {page_id} held {found} at offset {offset} in step {step}, but the model expected {expected}")]
+	Diverged {
+		seed: u64,
+		step: u64,
+		page_id: PageId,
+		offset: usize,
+		expected: u64,
+		found: u64,
+	},
+}
+
+impl From<io::Error> for ModelTestError {
+	fn from(value: io::Error) -> Self {
+		Self::Database(FileError::from(value).into())
+	}
+}
+
+/// What a run of a [`ModelTest`] did, to check that it covered the
+/// interesting cases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModelTestStats {
+	pub commits: u64,
+	pub aborts: u64,
+	/// The reads that were checked against the model.
+	pub reads: u64,
+	/// The reads and writes that failed because another open transaction
+	/// held the page.
+	pub conflicts: u64,
+	pub crashes: u64,
+}
+
+/// Checks the transaction layer against an in-memory model, by running a
+/// random sequence of operations on both.
+///
+/// Each step begins a transaction, writes to or reads from a page in one of
+/// the open transactions, commits or aborts one of them, or takes a
+/// checkpoint while none are open. Every write stores a unique value in one of
+/// a few slots of the pages, and every read has to see the value that the
+/// transaction wrote itself, or that was committed last. Open transactions
+/// never wait for each other; a read or write that would wait fails instead,
+/// and is not applied to the model.
+///
+/// The database is kept in memory, and opened with the options of the given
+/// builder.
+pub struct ModelTest {
+	builder: DatabaseBuilder,
+	seed: u64,
+	num_steps: u64,
+	num_pages: u16,
+	max_transactions: usize,
+	crash_interval: Option<u64>,
+	lose_unsynced_writes: bool,
+}
+
+impl ModelTest {
+	/// The offsets of the slots in each page. The first two are close enough
+	/// for their changes to be logged together.
+	const SLOT_OFFSETS: [usize; 4] = [0, 16, 512, 2048];
+	const SEGMENT_NUM: u32 = 1;
+
+	pub fn new(builder: DatabaseBuilder) -> Self {
+		Self {
+			builder,
+			seed: 0,
+			num_steps: 1000,
+			num_pages: 8,
+			max_transactions: 4,
+			crash_interval: Some(100),
+			lose_unsynced_writes: false,
+		}
+	}
+
+	pub fn seed(mut self, seed: u64) -> Self {
+		self.seed = seed;
+		self
+	}
+
+	pub fn num_steps(mut self, num_steps: u64) -> Self {
+		self.num_steps = num_steps;
+		self
+	}
+
+	/// Sets the number of pages that are written to. Fewer pages make
+	/// conflicts between the open transactions more likely.
+	pub fn num_pages(mut self, num_pages: u16) -> Self {
+		self.num_pages = num_pages;
+		self
+	}
+
+	/// Sets the number of transactions that can be open at the same time.
+	pub fn max_transactions(mut self, max_transactions: usize) -> Self {
+		self.max_transactions = max_transactions;
+		self
+	}
+
+	/// Crashes the database after every `interval` steps on average, or never
+	/// if `None`.
+	pub fn crash_interval(mut self, interval: Option<u64>) -> Self {
+		self.crash_interval = interval;
+		self
+	}
+
+	/// See [`FaultyVfs::lose_unsynced_writes`]. This can only succeed if the
+	/// builder sets a [`Durability`](crate::Durability) that syncs.
+	pub fn lose_unsynced_writes(mut self, lose: bool) -> Self {
+		self.lose_unsynced_writes = lose;
+		self
+	}
+
+	/// Runs the operations, and crashes and checks the database once more at
+	/// the end.
+	pub fn run(&self) -> Result<ModelTestStats, ModelTestError> {
+		let vfs = Arc::new(
+			FaultyVfs::new(Arc::new(MemoryVfs::new()))
+				.lose_unsynced_writes(self.lose_unsynced_writes),
+		);
+		let db = self.builder.clone().open_vfs(vfs.clone())?;
+		let mut run = Run {
+			test: self,
+			rng: Rng(self.seed),
+			vfs,
+			db: Some(db),
+			open: Vec::new(),
+			committed: BTreeMap::new(),
+			stats: ModelTestStats::default(),
+			step: 0,
+		};
+		for step in 0..self.num_steps {
+			run.step = step;
+			run.step()?;
+		}
+		run.crash()?;
+		Ok(run.stats)
+	}
+
+	fn page_id(page_num: u16) -> PageId {
+		PageId::new_unwrap(Self::SEGMENT_NUM, page_num)
+	}
+}
+
+/// A slot of a page, by page number and slot index.
+type Slot = (u16, usize);
+
+struct OpenTransaction {
+	transaction: Transaction,
+	writes: BTreeMap<Slot, u64>,
+}
+
+struct Run<'a> {
+	test: &'a ModelTest,
+	rng: Rng,
+	vfs: Arc<FaultyVfs>,
+	/// Only `None` while the database is restarted after a crash.
+	db: Option<Database>,
+	open: Vec<OpenTransaction>,
+	/// The value that was committed last to each slot. Slots that were never
+	/// written to hold zero.
+	committed: BTreeMap<Slot, u64>,
+	stats: ModelTestStats,
+	step: u64,
+}
+
+impl Run<'_> {
+	fn step(&mut self) -> Result<(), ModelTestError> {
+		if self
+			.test
+			.crash_interval
+			.is_some_and(|interval| self.rng.below(interval) == 0)
+		{
+			return self.crash();
+		}
+		let op = self.rng.below(16);
+		if self.open.is_empty() {
+			// A checkpoint waits for the open transactions, so one is only
+			// taken in between them.
+			if op == 15 {
+				self.db().checkpoint()?;
+				return Ok(());
+			}
+			return self.begin();
+		}
+		if op < 2 && self.open.len() < self.test.max_transactions {
+			return self.begin();
+		}
+
+		let index = self.rng.index(self.open.len());
+		match op {
+			0..=7 => self.write(index),
+			8..=11 => self.read(index),
+			12..=13 => {
+				let t = self.open.swap_remove(index);
+				t.transaction.commit()?;
+				self.committed.extend(t.writes);
+				self.stats.commits += 1;
+				Ok(())
+			}
+			_ => {
+				self.open.swap_remove(index).transaction.abort()?;
+				self.stats.aborts += 1;
+				Ok(())
+			}
+		}
+	}
+
+	fn begin(&mut self) -> Result<(), ModelTestError> {
+		// Any wait for a page fails right away, since the transaction that
+		// holds it could only continue in a later step.
+		let transaction = self.db().begin_transaction()?.with_deadline(Instant::now());
+		self.open.push(OpenTransaction {
+			transaction,
+			writes: BTreeMap::new(),
+		});
+		Ok(())
+	}
+
+	fn write(&mut self, index: usize) -> Result<(), ModelTestError> {
+		let slot = self.random_slot();
+		let value = self.step + 1;
+		let t = &mut self.open[index];
+		match t.transaction.write(
+			ModelTest::page_id(slot.0),
+			ModelTest::SLOT_OFFSETS[slot.1],
+			&value.to_ne_bytes(),
+		) {
+			Ok(()) => {
+				t.writes.insert(slot, value);
+				Ok(())
+			}
+			Err(err) if err.is_timed_out() => {
+				self.stats.conflicts += 1;
+				Ok(())
+			}
+			Err(err) => Err(err.into()),
+		}
+	}
+
+	fn read(&mut self, index: usize) -> Result<(), ModelTestError> {
+		let slot = self.random_slot();
+		let t = &self.open[index];
+		let mut buf = [0; 8];
+		match t.transaction.read(
+			ModelTest::page_id(slot.0),
+			ModelTest::SLOT_OFFSETS[slot.1],
+			&mut buf,
+		) {
+			Ok(()) => (),
+			Err(err) if err.is_timed_out() => {
+				self.stats.conflicts += 1;
+				return Ok(());
+			}
+			Err(err) => return Err(err.into()),
+		}
+		let expected = t
+			.writes
+			.get(&slot)
+			.or_else(|| self.committed.get(&slot))
+			.copied()
+			.unwrap_or(0);
+		self.stats.reads += 1;
+		self.check(slot, expected, u64::from_ne_bytes(buf))
+	}
+
+	/// Crashes the database with its transactions still open, and checks
+	/// that it recovers the committed state of the model.
+	fn crash(&mut self) -> Result<(), ModelTestError> {
+		self.vfs.crash();
+		// Whatever the transactions and the database try to write while they
+		// are dropped fails, like it would in a crashed process. Each is
+		// dropped on its own, so that one panic doesn't abort the others.
+		for t in self.open.drain(..) {
+			let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(t)));
+		}
+		let db = self.db.take();
+		let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(db)));
+		self.stats.crashes += 1;
+
+		self.vfs.restart()?;
+		self.db = Some(self.test.builder.clone().open_vfs(self.vfs.clone())?);
+		for page_num in 1..=self.test.num_pages {
+			for slot_index in 0..ModelTest::SLOT_OFFSETS.len() {
+				let slot = (page_num, slot_index);
+				let mut buf = [0; 8];
+				self.db().read(
+					ModelTest::page_id(page_num),
+					ModelTest::SLOT_OFFSETS[slot_index],
+					&mut buf,
+				)?;
+				let expected = self.committed.get(&slot).copied().unwrap_or(0);
+				self.check(slot, expected, u64::from_ne_bytes(buf))?;
+			}
+		}
+		Ok(())
+	}
+
+	fn check(&self, slot: Slot, expected: u64, found: u64) -> Result<(), ModelTestError> {
+		if found == expected {
+			return Ok(());
+		}
+		Err(ModelTestError::Diverged {
+			seed: self.test.seed,
+			step: self.step,
+			page_id: ModelTest::page_id(slot.0),
+			offset: ModelTest::SLOT_OFFSETS[slot.1],
+			expected,
+			found,
+		})
+	}
+
+	fn random_slot(&mut self) -> Slot {
+		let page_num = self.rng.below(self.test.num_pages.into()) + 1;
+		(
+			u16::try_from(page_num).unwrap(),
+			self.rng.index(ModelTest::SLOT_OFFSETS.len()),
+		)
+	}
+
+	fn db(&self) -> &Database {
+		self.db
+			.as_ref()
+			.expect("The database is only missing during a restart")
+	}
+}
+
+/// A small seeded pseudo-random number generator (SplitMix64), so that runs
+/// can be reproduced without depending on a crate for randomness.
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+
+	fn below(&mut self, bound: u64) -> u64 {
+		self.next() % bound
+	}
+
+	fn index(&mut self, len: usize) -> usize {
+		usize::try_from(self.below(len as u64)).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::Durability;
+
+	use super::*;
+
+	#[test]
+	fn match_model_across_crashes() {
+		for seed in 0..4 {
+			// given
+			let test = ModelTest::new(Database::builder())
+				.seed(seed)
+				.num_steps(400);
+
+			// when
+			let stats = test.run().unwrap();
+
+			// then
+			assert!(stats.commits > 0);
+			assert!(stats.aborts > 0);
+			assert!(stats.reads > 0);
+			assert!(stats.crashes > 1);
+		}
+	}
+
+	#[test]
+	fn match_model_when_losing_unsynced_writes() {
+		// given
+		let test = ModelTest::new(Database::builder().durability(Durability::Sync))
+			.seed(42)
+			.num_steps(400)
+			.num_pages(2)
+			.lose_unsynced_writes(true);
+
+		// when
+		let stats = test.run().unwrap();
+
+		// then
+		assert!(stats.conflicts > 0);
+		assert!(stats.crashes > 1);
+	}
+}
@@ -0,0 +1,269 @@
+//! Read-only access to the files of a database, for building audit and
+//! debugging tools. Nothing here opens the database, so its files are never
+//! recovered or otherwise modified, and it can be used while the database is
+//! open in another process.
+
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use crate::{
+	files::{
+		crypto::{Cipher, FileCipher},
+		list_wal_archive,
+		memory::MemoryFile,
+		read_cipher,
+		wal::{self, ItemStream, WalFile, WalFileApi},
+		DatabaseFolder, FileError,
+	},
+	EncryptionKey, Error, PageId, WalPosition,
+};
+
+/// An item of the WAL, as read by a [`WalReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalItem {
+	/// The bytes at `offset` in the page were changed from `from` to `to`.
+	/// Writes that roll a transaction back have no `from`.
+	Write {
+		transaction_id: u64,
+		page_id: PageId,
+		offset: u16,
+		from: Option<Vec<u8>>,
+		to: Vec<u8>,
+	},
+
+	/// All writes of the transaction were logged.
+	Commit { transaction_id: u64 },
+
+	/// A custom record was logged, see
+	/// [`Transaction::log_record`](crate::Transaction::log_record), or undone
+	/// if `undo` is set.
+	Custom {
+		transaction_id: u64,
+		record_type: u16,
+		version: u16,
+		data: Vec<u8>,
+		undo: bool,
+	},
+
+	/// A checkpoint started. The pages that were modified before it are
+	/// written to the segment files once it finishes.
+	Checkpoint { next_transaction_id: u64 },
+}
+
+impl WalItem {
+	/// The transaction that logged the item, unless it is a checkpoint.
+	pub fn transaction_id(&self) -> Option<u64> {
+		match self {
+			Self::Write { transaction_id, .. }
+			| Self::Commit { transaction_id }
+			| Self::Custom { transaction_id, .. } => Some(*transaction_id),
+			Self::Checkpoint { .. } => None,
+		}
+	}
+}
+
+impl From<wal::Item<'_>> for WalItem {
+	fn from(item: wal::Item<'_>) -> Self {
+		match item {
+			wal::Item::Write(data) => Self::Write {
+				transaction_id: data.transaction_data.transaction_id,
+				page_id: data.page_id,
+				offset: data.offset,
+				from: data.from.map(|from| from.into_owned()),
+				to: data.to.into_owned(),
+			},
+			wal::Item::Commit(data) => Self::Commit {
+				transaction_id: data.transaction_id,
+			},
+			wal::Item::Custom(data) => Self::Custom {
+				transaction_id: data.transaction_data.transaction_id,
+				record_type: data.record_type,
+				version: data.version,
+				data: data.data.into_owned(),
+				undo: data.undo,
+			},
+			wal::Item::Checkpoint(data) => Self::Checkpoint {
+				next_transaction_id: data.next_transaction_id,
+			},
+		}
+	}
+}
+
+/// Reads the items of the WAL of a database, or of a WAL archive, in the
+/// order they were logged.
+///
+/// Each WAL file is copied into memory before its items are read, so the
+/// files are never modified, even if their last write was interrupted.
+pub struct WalReader {
+	files: Vec<(u64, PathBuf)>,
+	cipher: Option<Arc<Cipher>>,
+}
+
+impl WalReader {
+	/// Reads the WAL in the `wal` folder of the database at `path`. An
+	/// encrypted database can only be read with its `key`.
+	pub fn open(path: impl AsRef<Path>, key: Option<&EncryptionKey>) -> Result<Self, Error> {
+		let path = path.as_ref();
+		Self::open_folder(&path.join(DatabaseFolder::WAL_DIR_NAME), path, key)
+	}
+
+	/// Reads the WAL files in the folder at `path`, e.g. an archive set with
+	/// [`DatabaseBuilder::wal_archive`](crate::DatabaseBuilder::wal_archive).
+	/// An archive of an encrypted database can only be read with its `key`.
+	pub fn open_archive(
+		path: impl AsRef<Path>,
+		key: Option<&EncryptionKey>,
+	) -> Result<Self, Error> {
+		let path = path.as_ref();
+		Self::open_folder(path, path, key)
+	}
+
+	fn open_folder(
+		path: &Path,
+		encryption_path: &Path,
+		key: Option<&EncryptionKey>,
+	) -> Result<Self, Error> {
+		Ok(Self {
+			files: list_wal_archive(path)?,
+			cipher: read_cipher(encryption_path, key)?,
+		})
+	}
+
+	/// The generations of the WAL files that are read, in ascending order.
+	pub fn generations(&self) -> impl Iterator<Item = u64> + '_ {
+		self.files.iter().map(|(generation, _)| *generation)
+	}
+
+	/// Iterates over the items of all WAL files, with their positions.
+	pub fn items(&self) -> WalItems<'_> {
+		WalItems {
+			reader: self,
+			next_file: 0,
+			items: Vec::new().into_iter(),
+		}
+	}
+
+	fn read_file(
+		&self,
+		generation: u64,
+		path: &Path,
+	) -> Result<Vec<(WalPosition, WalItem)>, FileError> {
+		let cipher = self
+			.cipher
+			.as_ref()
+			.map(|cipher| FileCipher::wal(Arc::clone(cipher), generation));
+		let mut file = WalFile::open_with_cipher(MemoryFile::from_bytes(fs::read(path)?), cipher)?;
+		let mut items = file.iter_items()?;
+		let mut buf = Vec::new();
+		let mut read = Vec::new();
+		while let Some((offset, item)) = items.next_into(&mut buf)? {
+			read.push((WalPosition::new(generation, offset), item.into()));
+		}
+		Ok(read)
+	}
+}
+
+/// The items of a [`WalReader`]. The items of one WAL file are read at a
+/// time.
+pub struct WalItems<'a> {
+	reader: &'a WalReader,
+	next_file: usize,
+	items: std::vec::IntoIter<(WalPosition, WalItem)>,
+}
+
+impl Iterator for WalItems<'_> {
+	type Item = Result<(WalPosition, WalItem), Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(item) = self.items.next() {
+				return Some(Ok(item));
+			}
+			let (generation, path) = self.reader.files.get(self.next_file)?;
+			self.next_file += 1;
+			match self.reader.read_file(*generation, path) {
+				Ok(items) => self.items = items.into_iter(),
+				Err(err) => return Some(Err(err.into())),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tempfile::tempdir;
+
+	use crate::{files::test_helpers::page_id, Database};
+
+	use super::*;
+
+	#[test]
+	fn read_wal_items() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let db = Database::open(tempdir.path()).unwrap();
+		let mut committed = db.begin_transaction().unwrap();
+		let committed_id = committed.id();
+		committed.write(page_id!(1, 1), 8, &[1, 2, 3]).unwrap();
+		committed.commit().unwrap();
+		// Aborted transactions have nothing logged.
+		let mut aborted = db.begin_transaction().unwrap();
+		aborted.write(page_id!(1, 2), 0, &[4]).unwrap();
+		aborted.abort().unwrap();
+		db.flush().unwrap();
+
+		// when
+		let reader = WalReader::open(tempdir.path(), None).unwrap();
+		let items: Vec<WalItem> = reader
+			.items()
+			.map(|item| item.unwrap().1)
+			.filter(|item| item.transaction_id().is_some())
+			.collect();
+
+		// then
+		assert_eq!(
+			items,
+			vec![
+				WalItem::Write {
+					transaction_id: committed_id,
+					page_id: page_id!(1, 1),
+					offset: 8,
+					from: Some(vec![0, 0, 0]),
+					to: vec![1, 2, 3],
+				},
+				WalItem::Commit {
+					transaction_id: committed_id
+				},
+			]
+		);
+	}
+
+	#[test]
+	fn read_encrypted_wal_only_with_key() {
+		// given
+		let tempdir = tempdir().unwrap();
+		let key = EncryptionKey::new([1; 32]);
+		let db = Database::builder()
+			.encryption_key(Some(key.clone()))
+			.open(tempdir.path())
+			.unwrap();
+		let mut t = db.begin_transaction().unwrap();
+		t.write(page_id!(1, 1), 0, &[1]).unwrap();
+		t.commit().unwrap();
+		db.flush().unwrap();
+
+		// when
+		let without_key = WalReader::open(tempdir.path(), None);
+		let with_key = WalReader::open(tempdir.path(), Some(&key)).unwrap();
+
+		// then
+		assert!(without_key.is_err());
+		assert!(with_key.items().any(|item| matches!(
+			item.unwrap().1,
+			WalItem::Write { to, .. } if to == [1]
+		)));
+	}
+}
@@ -0,0 +1,117 @@
+//! Instrumentation of the storage engine with the
+//! [`tracing`](https://docs.rs/tracing) crate.
+//!
+//! With the `tracing` feature enabled, transactions, checkpoints, WAL syncs
+//! and recovery are wrapped in spans, and cache evictions, flushes and page
+//! allocations are reported as events, so that a subscriber can show where
+//! time is spent. Without the feature, the instrumentation compiles to
+//! nothing.
+
+/// Emits a `tracing` event at the given level, like `tracing::event!`.
+macro_rules! event {
+	($level:ident, $($arg:tt)+) => {
+		#[cfg(feature = "tracing")]
+		tracing::event!(tracing::Level::$level, $($arg)+);
+	};
+}
+pub(crate) use event;
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+	use std::{
+		fmt,
+		sync::{
+			atomic::{AtomicU64, Ordering},
+			Arc,
+		},
+	};
+
+	use parking_lot::Mutex;
+	use tracing::{
+		field::{Field, Visit},
+		span, Event, Metadata, Subscriber,
+	};
+
+	use crate::page_store::{
+		test_helpers::{page_id, temp_storage},
+		PageStorageApi, TransactionApi, WritePage,
+	};
+
+	/// Records the names of the spans that are created, and the messages of
+	/// the events, on the thread it is the default subscriber of.
+	#[derive(Clone, Default)]
+	struct Recorder {
+		next_span_id: Arc<AtomicU64>,
+		spans: Arc<Mutex<Vec<String>>>,
+		events: Arc<Mutex<Vec<String>>>,
+	}
+
+	struct MessageVisitor(Option<String>);
+
+	impl Visit for MessageVisitor {
+		fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+			if field.name() == "message" {
+				self.0 = Some(format!("{value:?}"));
+			}
+		}
+	}
+
+	impl Subscriber for Recorder {
+		fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+			true
+		}
+
+		fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+			self.spans.lock().push(span.metadata().name().to_string());
+			span::Id::from_u64(self.next_span_id.fetch_add(1, Ordering::Relaxed) + 1)
+		}
+
+		fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+		fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+		fn event(&self, event: &Event<'_>) {
+			let mut visitor = MessageVisitor(None);
+			event.record(&mut visitor);
+			if let Some(message) = visitor.0 {
+				self.events.lock().push(message);
+			}
+		}
+
+		fn enter(&self, _span: &span::Id) {}
+
+		fn exit(&self, _span: &span::Id) {}
+	}
+
+	#[test]
+	fn trace_transactions() {
+		// given
+		let recorder = Recorder::default();
+		let (_tempdir, storage) = temp_storage(&Default::default());
+
+		// when
+		tracing::subscriber::with_default(recorder.clone(), || {
+			let mut t = storage.transaction().unwrap();
+			t.get_page_mut(page_id!(1, 1))
+				.unwrap()
+				.write(0, &[1, 2, 3])
+				.unwrap();
+			t.commit().unwrap();
+			storage.transaction().unwrap().undo().unwrap();
+		});
+
+		// then
+		assert!(recorder.spans.lock().contains(&"commit".to_string()));
+		let events = recorder.events.lock();
+		for message in [
+			"Transaction started",
+			"Transaction committed",
+			"Transaction aborted",
+		] {
+			assert!(
+				events.iter().any(|event| event == message),
+				"Missing event {message:?} in {events:?}"
+			);
+		}
+	}
+}
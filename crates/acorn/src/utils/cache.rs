@@ -1,17 +1,25 @@
 use std::{
 	collections::{HashSet, VecDeque},
 	hash::Hash,
-	sync::atomic::{AtomicBool, Ordering},
+	mem,
+	sync::atomic::{AtomicU32, AtomicU64, Ordering},
 };
 
+use super::memory::hash_table_size;
+
 struct ClockItem<T> {
 	value: T,
-	referenced: AtomicBool,
+	/// How often the item was accessed since it was inserted.
+	references: AtomicU32,
 }
 
 impl<T> ClockItem<T> {
 	fn was_referenced(&self) -> bool {
-		self.referenced.load(Ordering::Relaxed)
+		self.references() != 0
+	}
+
+	fn references(&self) -> u32 {
+		self.references.load(Ordering::Relaxed)
 	}
 }
 
@@ -26,6 +34,10 @@ impl<T: PartialEq> ClockList<T> {
 		}
 	}
 
+	fn heap_size(&self) -> usize {
+		self.items.capacity() * mem::size_of::<ClockItem<T>>()
+	}
+
 	#[inline]
 	fn size(&self) -> usize {
 		self.items.len()
@@ -43,7 +55,7 @@ impl<T: PartialEq> ClockList<T> {
 	fn insert(&mut self, value: T) {
 		self.items.push_back(ClockItem {
 			value,
-			referenced: AtomicBool::new(false),
+			references: AtomicU32::new(0),
 		});
 	}
 
@@ -59,7 +71,13 @@ impl<T: PartialEq> ClockList<T> {
 	fn access(&self, value: &T) -> bool {
 		for item in &self.items {
 			if item.value == *value {
-				item.referenced.store(true, Ordering::Relaxed);
+				// Saturates instead of wrapping around, which would make a
+				// frequently accessed item look unreferenced.
+				let _ = item.references.fetch_update(
+					Ordering::Relaxed,
+					Ordering::Relaxed,
+					|references| references.checked_add(1),
+				);
 				return true;
 			}
 		}
@@ -74,6 +92,14 @@ impl<T: PartialEq> ClockList<T> {
 		}
 		false
 	}
+
+	fn remove_value(&mut self, value: &T) -> bool {
+		let Some(position) = self.items.iter().position(|item| item.value == *value) else {
+			return false;
+		};
+		self.items.remove(position);
+		true
+	}
 }
 
 struct LruList<T> {
@@ -94,6 +120,11 @@ impl<T: Clone + Hash + Eq> LruList<T> {
 		self.items.len()
 	}
 
+	fn heap_size(&self) -> usize {
+		self.items.capacity() * mem::size_of::<T>()
+			+ hash_table_size::<T>(self.items_set.capacity())
+	}
+
 	fn enqueue(&mut self, value: T) {
 		self.items.push_back(value.clone());
 		self.items_set.insert(value);
@@ -110,9 +141,301 @@ impl<T: Clone + Hash + Eq> LruList<T> {
 	}
 }
 
+/// The algorithm that decides which pages are evicted from the page cache
+/// when it is full.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+	/// Clock with adaptive replacement (CAR), which balances between recently
+	/// and frequently used pages. Pages that are only accessed once, like
+	/// during a sequential scan, can't push out pages that are used
+	/// repeatedly.
+	#[default]
+	Adaptive,
+
+	/// A single clock that gives each page that was accessed since the hand
+	/// last passed it a second chance. Cheaper than `Adaptive`, but a long
+	/// scan can evict the whole working set.
+	Clock,
+
+	/// Evicts the least recently used page.
+	Lru,
+}
+
+/// Tuning of the [`EvictionPolicy::Adaptive`] policy. The other policies have
+/// nothing to tune.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct EvictionTuning {
+	/// The number of evicted values that are remembered, as a fraction of the
+	/// size of the cache. Values that are inserted again while they are
+	/// remembered are considered frequently used.
+	pub history_ratio: f32,
+	/// How often a recently added value has to be accessed before it is
+	/// considered frequently used, instead of being evicted.
+	pub promotion_threshold: u32,
+}
+
+impl Default for EvictionTuning {
+	fn default() -> Self {
+		Self {
+			history_ratio: 1.0,
+			promotion_threshold: 1,
+		}
+	}
+}
+
+/// Counters of how the eviction policy of a cache treated the values in it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvictionStats {
+	/// The number of accesses to values that are considered frequently used.
+	/// Only [`EvictionPolicy::Adaptive`] tells them apart from recently used
+	/// ones.
+	pub hot_hits: u64,
+	/// The number of accesses to values that are considered recently used.
+	/// The policies other than [`EvictionPolicy::Adaptive`] count all hits
+	/// here.
+	pub cold_hits: u64,
+	/// The number of values that were inserted again while the policy still
+	/// remembered evicting them.
+	pub history_hits: u64,
+	/// The number of values that were moved from the recently to the
+	/// frequently used ones.
+	pub promotions: u64,
+	/// The number of values that were looked at while searching for one to
+	/// evict.
+	pub reclaim_scans: u64,
+	/// The number of values that the policy currently aims to keep as
+	/// recently used, rather than frequently used.
+	pub recent_target: usize,
+}
+
+#[derive(Debug, Default)]
+struct EvictionCounters {
+	hot_hits: AtomicU64,
+	cold_hits: AtomicU64,
+	history_hits: AtomicU64,
+	promotions: AtomicU64,
+	reclaim_scans: AtomicU64,
+}
+
+impl EvictionCounters {
+	fn count(counter: &AtomicU64, hit: bool) -> bool {
+		if hit {
+			counter.fetch_add(1, Ordering::Relaxed);
+		}
+		hit
+	}
+
+	fn stats(&self, recent_target: usize) -> EvictionStats {
+		EvictionStats {
+			hot_hits: self.hot_hits.load(Ordering::Relaxed),
+			cold_hits: self.cold_hits.load(Ordering::Relaxed),
+			history_hits: self.history_hits.load(Ordering::Relaxed),
+			promotions: self.promotions.load(Ordering::Relaxed),
+			reclaim_scans: self.reclaim_scans.load(Ordering::Relaxed),
+			recent_target,
+		}
+	}
+}
+
+/// Decides which values to evict from a cache of a fixed size, according to
+/// an [`EvictionPolicy`].
+pub(crate) enum CacheReplacer<T> {
+	/// Boxed, since it is much larger than the other replacers.
+	Adaptive(Box<CarReplacer<T>>),
+	Clock(ClockReplacer<T>),
+	Lru(LruReplacer<T>),
+}
+
+impl<T: Clone + Hash + Eq> CacheReplacer<T> {
+	pub fn new(policy: EvictionPolicy, size: usize) -> Self {
+		match policy {
+			EvictionPolicy::Adaptive => Self::Adaptive(Box::new(CarReplacer::new(size))),
+			EvictionPolicy::Clock => Self::Clock(ClockReplacer::new(size)),
+			EvictionPolicy::Lru => Self::Lru(LruReplacer::new(size)),
+		}
+	}
+
+	/// Applies `tuning` to the policy, if it can be tuned.
+	pub fn with_tuning(mut self, tuning: &EvictionTuning) -> Self {
+		if let Self::Adaptive(replacer) = &mut self {
+			#[allow(clippy::cast_possible_truncation)]
+			let history_size = (replacer.size as f32 * tuning.history_ratio) as usize;
+			replacer.history_size = history_size;
+			replacer.promotion_threshold = tuning.promotion_threshold;
+		}
+		self
+	}
+
+	/// Track an access to the given value
+	pub fn access(&self, value: &T) -> bool {
+		match self {
+			Self::Adaptive(replacer) => replacer.access(value),
+			Self::Clock(replacer) => replacer.access(value),
+			Self::Lru(replacer) => replacer.access(value),
+		}
+	}
+
+	/// Remove a value from the cache without evicting it into the history.
+	pub fn remove(&mut self, value: &T) -> bool {
+		match self {
+			Self::Adaptive(replacer) => replacer.remove(value),
+			Self::Clock(replacer) => replacer.remove(value),
+			Self::Lru(replacer) => replacer.remove(value),
+		}
+	}
+
+	/// Insert a value into the cache, potentially evicting a value to make
+	/// space.
+	pub fn evict_replace(&mut self, value: T) -> Option<T> {
+		match self {
+			Self::Adaptive(replacer) => replacer.evict_replace(value),
+			Self::Clock(replacer) => replacer.evict_replace(value),
+			Self::Lru(replacer) => replacer.evict_replace(value),
+		}
+	}
+
+	/// The approximate heap memory used to track the values, in bytes.
+	pub fn heap_size(&self) -> usize {
+		match self {
+			Self::Adaptive(replacer) => mem::size_of::<CarReplacer<T>>() + replacer.heap_size(),
+			Self::Clock(replacer) => replacer.clock.heap_size(),
+			Self::Lru(replacer) => replacer.items.capacity() * mem::size_of::<LruItem<T>>(),
+		}
+	}
+
+	/// The counters of the policy since the replacer was created.
+	pub fn stats(&self) -> EvictionStats {
+		match self {
+			Self::Adaptive(replacer) => replacer.counters.stats(replacer.recent_target_size),
+			Self::Clock(replacer) => replacer.counters.stats(0),
+			Self::Lru(replacer) => replacer.counters.stats(0),
+		}
+	}
+}
+
+/// An implementation of the CLOCK algorithm.
+pub(crate) struct ClockReplacer<T> {
+	clock: ClockList<T>,
+	size: usize,
+	counters: EvictionCounters,
+}
+
+impl<T: PartialEq> ClockReplacer<T> {
+	fn new(size: usize) -> Self {
+		Self {
+			clock: ClockList::new(),
+			size,
+			counters: EvictionCounters::default(),
+		}
+	}
+
+	fn access(&self, value: &T) -> bool {
+		EvictionCounters::count(&self.counters.cold_hits, self.clock.access(value))
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		self.clock.remove_value(value)
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		debug_assert!(!self.clock.contains(&value));
+
+		let mut evicted: Option<T> = None;
+		while self.clock.size() >= self.size {
+			let Some(head) = self.clock.remove() else {
+				break;
+			};
+			self.counters.reclaim_scans.fetch_add(1, Ordering::Relaxed);
+			if head.was_referenced() {
+				// Re-inserting the value clears its reference bit.
+				self.clock.insert(head.value);
+			} else {
+				evicted = Some(head.value);
+				break;
+			}
+		}
+		self.clock.insert(value);
+		evicted
+	}
+}
+
+struct LruItem<T> {
+	value: T,
+	last_access: AtomicU64,
+}
+
+/// An implementation of least-recently-used eviction.
+///
+/// Accesses only update a timestamp, so that they don't need exclusive access
+/// to the replacer; the least recently used value is found when evicting.
+pub(crate) struct LruReplacer<T> {
+	items: Vec<LruItem<T>>,
+	clock: AtomicU64,
+	size: usize,
+	counters: EvictionCounters,
+}
+
+impl<T: PartialEq> LruReplacer<T> {
+	fn new(size: usize) -> Self {
+		Self {
+			items: Vec::new(),
+			clock: AtomicU64::new(0),
+			size,
+			counters: EvictionCounters::default(),
+		}
+	}
+
+	fn tick(&self) -> u64 {
+		self.clock.fetch_add(1, Ordering::Relaxed)
+	}
+
+	fn access(&self, value: &T) -> bool {
+		let Some(item) = self.items.iter().find(|item| item.value == *value) else {
+			return false;
+		};
+		item.last_access.store(self.tick(), Ordering::Relaxed);
+		self.counters.cold_hits.fetch_add(1, Ordering::Relaxed);
+		true
+	}
+
+	fn remove(&mut self, value: &T) -> bool {
+		let Some(position) = self.items.iter().position(|item| item.value == *value) else {
+			return false;
+		};
+		self.items.swap_remove(position);
+		true
+	}
+
+	fn evict_replace(&mut self, value: T) -> Option<T> {
+		debug_assert!(!self.items.iter().any(|item| item.value == value));
+
+		let mut evicted: Option<T> = None;
+		if self.items.len() >= self.size {
+			// Finding the least recently used value looks at all of them
+			self.counters
+				.reclaim_scans
+				.fetch_add(self.items.len() as u64, Ordering::Relaxed);
+			let oldest = self
+				.items
+				.iter()
+				.enumerate()
+				.min_by_key(|(_, item)| item.last_access.load(Ordering::Relaxed))
+				.map(|(position, _)| position);
+			if let Some(position) = oldest {
+				evicted = Some(self.items.swap_remove(position).value);
+			}
+		}
+		self.items.push(LruItem {
+			value,
+			last_access: AtomicU64::new(self.tick()),
+		});
+		evicted
+	}
+}
+
 /// This is an impelementation of the CAR algorithm.
 /// See [Bansal et. al. 2012](https://theory.stanford.edu/~sbansal/pubs/fast04.pdf).
-pub(crate) struct CacheReplacer<T> {
+pub(crate) struct CarReplacer<T> {
 	/// A clock containing recently added values
 	recent: ClockList<T>,
 
@@ -130,9 +453,20 @@ pub(crate) struct CacheReplacer<T> {
 
 	/// The total cache size
 	size: usize,
+
+	/// The number of values that `recent_history` and `frequent_history` can
+	/// remember beyond the cached ones. CAR remembers as many as the cache
+	/// holds.
+	history_size: usize,
+
+	/// How often a value in `recent` has to be referenced to be promoted to
+	/// `frequent` instead of being evicted.
+	promotion_threshold: u32,
+
+	counters: EvictionCounters,
 }
 
-impl<T: Clone + Hash + Eq> CacheReplacer<T> {
+impl<T: Clone + Hash + Eq> CarReplacer<T> {
 	pub fn new(size: usize) -> Self {
 		Self {
 			recent: ClockList::new(),
@@ -141,13 +475,29 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 			frequent_history: LruList::new(),
 			recent_target_size: 0,
 			size,
+			history_size: size,
+			promotion_threshold: 1,
+			counters: EvictionCounters::default(),
 		}
 	}
 
+	fn heap_size(&self) -> usize {
+		self.recent.heap_size()
+			+ self.recent_history.heap_size()
+			+ self.frequent.heap_size()
+			+ self.frequent_history.heap_size()
+	}
+
 	/// Track an access to the given value
 	pub fn access(&self, value: &T) -> bool {
 		// Mark the corresponding page as referenced.
-		self.recent.access(value) || self.frequent.access(value)
+		EvictionCounters::count(&self.counters.cold_hits, self.recent.access(value))
+			|| EvictionCounters::count(&self.counters.hot_hits, self.frequent.access(value))
+	}
+
+	/// Remove a value from the cache without evicting it into the history.
+	pub fn remove(&mut self, value: &T) -> bool {
+		self.recent.remove_value(value) || self.frequent.remove_value(value)
 	}
 
 	/// Insert a value into the cache, potentially evicting a value to make
@@ -191,20 +541,20 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 	}
 
 	/// Checks whether the recent history LRU can be extended without violating
-	/// its size requirement: `|recent| + |recent_history| <= size`
+	/// its size requirement: `|recent| + |recent_history| <= history_size`
 	fn recent_history_is_full(&self) -> bool {
-		self.recent.size() + self.recent_history.len() >= self.size
+		self.recent.size() + self.recent_history.len() >= self.history_size
 	}
 
 	/// Checks whether the frequent history LRU can be extended without
 	/// violating its size requirement: `|recent| + |frequent| +
-	/// |recent_history| + |frequent_history| <= 2 * size`
+	/// |recent_history| + |frequent_history| <= size + history_size`
 	fn frequent_history_is_full(&self) -> bool {
 		self.recent.size()
 			+ self.frequent.size()
 			+ self.recent_history.len()
 			+ self.frequent_history.len()
-			>= self.size * 2
+			>= self.size + self.history_size
 	}
 
 	/// Evicts a value from the cache, unless the cache is empty
@@ -215,14 +565,16 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 				// If the recent clock is full, we want to look at its head.
 
 				let recent_head = self.recent.remove().unwrap();
-				if !recent_head.was_referenced() {
-					// The recent head item was not recently referenced! We evict it, and add it to
-					// the history.
+				self.counters.reclaim_scans.fetch_add(1, Ordering::Relaxed);
+				if recent_head.references() < self.promotion_threshold {
+					// The recent head item was not referenced often enough! We evict it, and add it
+					// to the history.
 					self.frequent_history.enqueue(recent_head.value.clone());
 					return Some(recent_head.value);
 				} else {
 					// The recent head item was recently referenced. We promote it to the frequent
 					// clock, where it can get a second chance.
+					self.counters.promotions.fetch_add(1, Ordering::Relaxed);
 					self.frequent.insert(recent_head.value);
 				}
 			} else {
@@ -230,6 +582,7 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 				// maintained.
 
 				let frequent_head = self.frequent.remove()?;
+				self.counters.reclaim_scans.fetch_add(1, Ordering::Relaxed);
 				if !frequent_head.was_referenced() {
 					// The frequent head item was not recently referenced! We evict it, and add it
 					// to the history.
@@ -246,9 +599,12 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 
 	/// Evicts an item from one of the history LRUs if they are full
 	fn maybe_evict_history(&mut self) {
-		if self.recent_history_is_full() {
-			self.recent_history.dequeue();
-		} else if self.frequent_history_is_full() {
+		// A small history may be full while `recent_history` is empty; the
+		// frequent history is trimmed then, so that it doesn't grow unbounded.
+		if self.recent_history_is_full() && self.recent_history.dequeue().is_some() {
+			return;
+		}
+		if self.frequent_history_is_full() {
 			self.frequent_history.dequeue();
 		}
 	}
@@ -282,6 +638,9 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 
 	/// Inserts a value into the cache.
 	fn insert(&mut self, value: T) {
+		if self.value_in_history(&value) {
+			self.counters.history_hits.fetch_add(1, Ordering::Relaxed);
+		}
 		if self.recent_history.contains(&value) {
 			// The value was only recently evicted from `recent`, so we might want `recent`
 			// to be bigger. We increase the `recent` target size, and add it to `frequent`.
@@ -299,3 +658,109 @@ impl<T: Clone + Hash + Eq> CacheReplacer<T> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn scan_evictions(policy: EvictionPolicy) -> Vec<u32> {
+		let mut replacer = CacheReplacer::new(policy, 4);
+		let mut evicted = Vec::new();
+		for hot in [1, 2] {
+			evicted.extend(replacer.evict_replace(hot));
+			assert!(replacer.access(&hot));
+		}
+		for cold in 10..20 {
+			evicted.extend(replacer.evict_replace(cold));
+		}
+		evicted
+	}
+
+	#[test]
+	fn adaptive_policy_resists_scans() {
+		// when
+		let evicted = scan_evictions(EvictionPolicy::Adaptive);
+
+		// then
+		assert_eq!(evicted, (10..18).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn clock_policy_gives_second_chance() {
+		// when
+		let evicted = scan_evictions(EvictionPolicy::Clock);
+
+		// then
+		assert_eq!(evicted[..4], [10, 11, 1, 2]);
+	}
+
+	#[test]
+	fn adaptive_policy_counts_hits_and_promotions() {
+		// given
+		let mut replacer = CacheReplacer::new(EvictionPolicy::Adaptive, 2);
+		replacer.evict_replace(1);
+		replacer.evict_replace(2);
+
+		// when
+		replacer.access(&1);
+		let first = replacer.evict_replace(3);
+		replacer.access(&1);
+		let second = replacer.evict_replace(2);
+
+		// then
+		assert_eq!(first, Some(2));
+		assert_eq!(second, Some(3));
+		assert_eq!(
+			replacer.stats(),
+			EvictionStats {
+				hot_hits: 1,
+				cold_hits: 1,
+				history_hits: 1,
+				promotions: 1,
+				reclaim_scans: 3,
+				recent_target: 0,
+			}
+		);
+	}
+
+	#[test]
+	fn adaptive_policy_promotes_after_threshold() {
+		// given
+		let mut replacer =
+			CacheReplacer::new(EvictionPolicy::Adaptive, 2).with_tuning(&EvictionTuning {
+				promotion_threshold: 2,
+				..Default::default()
+			});
+		replacer.evict_replace(1);
+		replacer.evict_replace(2);
+
+		// when
+		replacer.access(&1);
+		let evicted = replacer.evict_replace(3);
+
+		// then
+		assert_eq!(evicted, Some(1));
+		assert_eq!(replacer.stats().promotions, 0);
+	}
+
+	#[test]
+	fn lru_policy_evicts_least_recently_used() {
+		// given
+		let mut replacer = CacheReplacer::new(EvictionPolicy::Lru, 3);
+		for value in [1, 2, 3] {
+			assert_eq!(replacer.evict_replace(value), None);
+		}
+
+		// when
+		replacer.access(&1);
+		let first = replacer.evict_replace(4);
+		replacer.remove(&3);
+		let second = replacer.evict_replace(5);
+		let third = replacer.evict_replace(6);
+
+		// then
+		assert_eq!(first, Some(2));
+		assert_eq!(second, None);
+		assert_eq!(third, Some(1));
+	}
+}
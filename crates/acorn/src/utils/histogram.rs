@@ -0,0 +1,104 @@
+use std::{
+	array,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+const NUM_BUCKETS: usize = 24;
+
+/// A histogram of latencies. Bucket `i` counts the latencies that are shorter
+/// than 2<sup>i</sup> microseconds, but not shorter than the bound of the
+/// previous bucket; the last bucket also counts everything longer than that.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+	buckets: [u64; NUM_BUCKETS],
+	total_micros: u64,
+}
+
+impl LatencyHistogram {
+	/// The number of recorded latencies.
+	pub fn count(&self) -> u64 {
+		self.buckets.iter().sum()
+	}
+
+	/// The average of the recorded latencies, or `None` if there are none.
+	pub fn mean(&self) -> Option<Duration> {
+		let count = self.count();
+		if count == 0 {
+			return None;
+		}
+		Some(Duration::from_micros(self.total_micros / count))
+	}
+
+	/// The upper bound of each bucket, with the number of latencies in it.
+	pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+		self.buckets
+			.iter()
+			.enumerate()
+			.map(|(i, count)| (Duration::from_micros(1 << i), *count))
+	}
+}
+
+#[derive(Debug)]
+pub(crate) struct AtomicLatencyHistogram {
+	buckets: [AtomicU64; NUM_BUCKETS],
+	total_micros: AtomicU64,
+}
+
+impl Default for AtomicLatencyHistogram {
+	fn default() -> Self {
+		Self {
+			buckets: array::from_fn(|_| AtomicU64::new(0)),
+			total_micros: AtomicU64::new(0),
+		}
+	}
+}
+
+impl AtomicLatencyHistogram {
+	pub fn record(&self, latency: Duration) {
+		let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+		let bucket = usize::min(
+			(u64::BITS - micros.leading_zeros()) as usize,
+			NUM_BUCKETS - 1,
+		);
+		self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+		self.total_micros.fetch_add(micros, Ordering::Relaxed);
+	}
+
+	pub fn load(&self) -> LatencyHistogram {
+		LatencyHistogram {
+			buckets: array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+			total_micros: self.total_micros.load(Ordering::Relaxed),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn record_latencies() {
+		// given
+		let histogram = AtomicLatencyHistogram::default();
+
+		// when
+		histogram.record(Duration::ZERO);
+		histogram.record(Duration::from_micros(3));
+		histogram.record(Duration::from_micros(5));
+		histogram.record(Duration::from_secs(3600));
+
+		// then
+		let histogram = histogram.load();
+		assert_eq!(histogram.count(), 4);
+		assert_eq!(
+			histogram.mean(),
+			Some(Duration::from_micros((3_600_000_000 + 8) / 4))
+		);
+		let buckets: Vec<u64> = histogram.buckets().map(|(_, count)| count).collect();
+		assert_eq!(buckets[0], 1);
+		assert_eq!(buckets[2], 1);
+		assert_eq!(buckets[3], 1);
+		assert_eq!(buckets[NUM_BUCKETS - 1], 1);
+	}
+}
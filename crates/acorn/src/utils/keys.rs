@@ -0,0 +1,36 @@
+/// Computes the smallest key that is greater than every key starting with
+/// `prefix`, so that a prefix scan can be expressed as the range
+/// `prefix..upper_bound`.
+///
+/// Returns `None` if no such key exists (i.e. the prefix is empty or consists
+/// only of `0xff` bytes), in which case the range is unbounded above.
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+	let last_incrementable = prefix.iter().rposition(|byte| *byte != u8::MAX)?;
+	let mut upper_bound = prefix[..=last_incrementable].to_vec();
+	upper_bound[last_incrementable] += 1;
+	Some(upper_bound)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn increment_last_byte() {
+		assert_eq!(prefix_upper_bound(&[1, 2, 3]), Some(vec![1, 2, 4]));
+	}
+
+	#[test]
+	fn carry_overflow() {
+		assert_eq!(
+			prefix_upper_bound(&[1, 0xfe, 0xff, 0xff]),
+			Some(vec![1, 0xff])
+		);
+	}
+
+	#[test]
+	fn unbounded_prefixes() {
+		assert_eq!(prefix_upper_bound(&[]), None);
+		assert_eq!(prefix_upper_bound(&[0xff, 0xff]), None);
+	}
+}
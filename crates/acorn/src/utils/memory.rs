@@ -0,0 +1,8 @@
+use std::mem;
+
+/// The approximate heap memory used by a hash map or set with the given
+/// capacity, where `T` is the type of its entries.
+pub(crate) fn hash_table_size<T>(capacity: usize) -> usize {
+	// Each slot has one control byte besides the entry itself.
+	capacity * (mem::size_of::<T>() + 1)
+}
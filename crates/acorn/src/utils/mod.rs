@@ -1,4 +1,9 @@
 pub(crate) mod cache;
+pub(crate) mod histogram;
+pub(crate) mod keys;
+pub(crate) mod memory;
+pub(crate) mod rate_limit;
+pub(crate) mod sharded;
 pub(crate) mod units;
 
 #[cfg(test)]
@@ -0,0 +1,111 @@
+use std::{
+	thread,
+	time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+/// The longest time a waiting caller sleeps before it checks the rate again,
+/// so that a changed rate takes effect quickly.
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+/// A token bucket that limits the number of bytes per second that background
+/// tasks may read or write.
+///
+/// The bucket holds up to one second worth of bytes. A caller may take more
+/// bytes than the bucket holds, as long as it isn't empty; the following
+/// callers then wait until the debt is paid off.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+	bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+	rate: Option<usize>,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl Bucket {
+	fn refill(&mut self, rate: usize) {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+		self.tokens = f64::min(self.tokens + elapsed * rate as f64, rate as f64);
+		self.last_refill = now;
+	}
+}
+
+impl RateLimiter {
+	/// Creates a limiter that allows `rate` bytes per second, or any number of
+	/// bytes if `rate` is `None`.
+	pub fn new(rate: Option<usize>) -> Self {
+		Self {
+			bucket: Mutex::new(Bucket {
+				rate,
+				tokens: rate.unwrap_or(0) as f64,
+				last_refill: Instant::now(),
+			}),
+		}
+	}
+
+	pub fn rate(&self) -> Option<usize> {
+		self.bucket.lock().rate
+	}
+
+	pub fn set_rate(&self, rate: Option<usize>) {
+		let mut bucket = self.bucket.lock();
+		if let Some(old_rate) = bucket.rate {
+			bucket.refill(old_rate);
+		}
+		if let Some(rate) = rate {
+			bucket.tokens = f64::min(bucket.tokens, rate as f64);
+		}
+		bucket.last_refill = Instant::now();
+		bucket.rate = rate;
+	}
+
+	/// Blocks until `bytes` may be transferred without exceeding the rate.
+	pub fn acquire(&self, bytes: usize) {
+		loop {
+			let mut bucket = self.bucket.lock();
+			let Some(rate) = bucket.rate else {
+				return;
+			};
+			bucket.refill(rate);
+			if bucket.tokens >= 0.0 {
+				bucket.tokens -= bytes as f64;
+				return;
+			}
+			let wait = Duration::from_secs_f64(-bucket.tokens / rate as f64);
+			drop(bucket);
+			thread::sleep(Duration::min(wait, MAX_SLEEP));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn limit_rate() {
+		// given
+		let limiter = RateLimiter::new(Some(10_000));
+
+		// when
+		let start = Instant::now();
+		limiter.acquire(10_000);
+		limiter.acquire(1_000);
+		let burst = start.elapsed();
+		limiter.acquire(1);
+		let limited = start.elapsed();
+		limiter.set_rate(None);
+		limiter.acquire(1_000_000);
+
+		// then
+		assert!(burst < Duration::from_millis(50));
+		assert!(limited >= Duration::from_millis(90));
+		assert_eq!(limiter.rate(), None);
+	}
+}
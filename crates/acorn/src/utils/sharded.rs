@@ -0,0 +1,158 @@
+use std::{
+	collections::HashMap,
+	hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+use parking_lot::RwLock;
+
+/// A hash map that is split into shards with a lock each, so that threads
+/// accessing unrelated keys don't contend for the same lock.
+pub(crate) struct ShardedMap<K, V> {
+	shards: Box<[RwLock<HashMap<K, V>>]>,
+	len: AtomicUsize,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+	const NUM_SHARDS: usize = 16;
+
+	pub fn new() -> Self {
+		Self {
+			shards: std::iter::repeat_with(|| RwLock::new(HashMap::new()))
+				.take(Self::NUM_SHARDS)
+				.collect(),
+			len: AtomicUsize::new(0),
+		}
+	}
+
+	#[allow(clippy::cast_possible_truncation)]
+	fn shard(&self, key: &K) -> &RwLock<HashMap<K, V>> {
+		let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(key);
+		&self.shards[hash as usize % self.shards.len()]
+	}
+
+	pub fn get(&self, key: &K) -> Option<V>
+	where
+		V: Copy,
+	{
+		self.shard(key).read().get(key).copied()
+	}
+
+	pub fn contains_key(&self, key: &K) -> bool {
+		self.shard(key).read().contains_key(key)
+	}
+
+	pub fn insert(&self, key: K, value: V) -> Option<V> {
+		let shard = self.shard(&key);
+		let mut shard = shard.write();
+		let previous = shard.insert(key, value);
+		if previous.is_none() {
+			self.len.fetch_add(1, Ordering::Relaxed);
+		}
+		previous
+	}
+
+	pub fn remove(&self, key: &K) -> Option<V> {
+		self.update(key, |shard| shard.remove(key))
+	}
+
+	/// Runs `f` with the shard that `key` belongs to, while holding its lock
+	/// exclusively. `f` must not insert any other key than `key`.
+	pub fn update<R>(&self, key: &K, f: impl FnOnce(&mut HashMap<K, V>) -> R) -> R {
+		let mut shard = self.shard(key).write();
+		let len_before = shard.len();
+		let result = f(&mut shard);
+		let len_after = shard.len();
+		if len_after > len_before {
+			self.len
+				.fetch_add(len_after - len_before, Ordering::Relaxed);
+		} else {
+			self.len
+				.fetch_sub(len_before - len_after, Ordering::Relaxed);
+		}
+		result
+	}
+
+	pub fn len(&self) -> usize {
+		self.len.load(Ordering::Relaxed)
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The number of entries the shards can hold without reallocating.
+	pub fn capacity(&self) -> usize {
+		self.shards
+			.iter()
+			.map(|shard| shard.read().capacity())
+			.sum()
+	}
+
+	/// Copies out the entries, in no particular order. Since the shards are
+	/// locked one after another, the entries don't necessarily reflect the
+	/// state of the map at a single point in time.
+	pub fn entries(&self) -> Vec<(K, V)>
+	where
+		K: Copy,
+		V: Copy,
+	{
+		let mut entries = Vec::with_capacity(self.len());
+		for shard in self.shards.iter() {
+			entries.extend(shard.read().iter().map(|(key, value)| (*key, *value)));
+		}
+		entries
+	}
+
+	/// Like [`Self::entries`], but only copies out the keys.
+	pub fn keys(&self) -> Vec<K>
+	where
+		K: Copy,
+	{
+		let mut keys = Vec::with_capacity(self.len());
+		for shard in self.shards.iter() {
+			keys.extend(shard.read().keys().copied());
+		}
+		keys
+	}
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn track_len_across_shards() {
+		// given
+		let map: ShardedMap<u32, u32> = ShardedMap::new();
+		for key in 0..100 {
+			map.insert(key, key * 2);
+		}
+
+		// when
+		map.insert(10, 1);
+		map.remove(&20);
+		map.update(&30, |shard| {
+			shard.remove(&30);
+			shard.insert(30, 3);
+		});
+		map.update(&40, |shard| shard.remove(&40));
+
+		// then
+		assert_eq!(map.len(), 98);
+		assert_eq!(map.get(&10), Some(1));
+		assert_eq!(map.get(&20), None);
+		assert_eq!(map.get(&30), Some(3));
+		assert!(!map.contains_key(&40));
+		let mut keys = map.keys();
+		keys.sort_unstable();
+		assert_eq!(keys.len(), 98);
+		assert_eq!(keys[..3], [0, 1, 2]);
+	}
+}
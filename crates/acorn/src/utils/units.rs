@@ -1,4 +1,133 @@
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
 pub(crate) const B: usize = 1;
 pub(crate) const KIB: usize = 1024 * B;
 pub(crate) const MIB: usize = 1024 * KIB;
 pub(crate) const GIB: usize = 1024 * MIB;
+pub(crate) const TIB: usize = 1024 * GIB;
+
+/// A number of bytes, which can be parsed from and formatted as a
+/// human-readable size like `64MiB`.
+///
+/// Binary units (`KiB`, `MiB`, `GiB`, `TiB`) are multiples of 1024, decimal
+/// units (`kB`, `MB`, `GB`, `TB`) are multiples of 1000. Units are case
+/// insensitive and may be separated from the number by whitespace; a number
+/// without a unit is a number of bytes. Sizes are formatted exactly, in the
+/// largest binary unit that divides them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub usize);
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ParseSizeError {
+	#[error("Expected a size like `64MiB`, but found `{0}`")]
+	InvalidNumber(String),
+
+	#[error("Unknown size unit `{0}`")]
+	UnknownUnit(String),
+
+	#[error("The size `{0}` is too large")]
+	Overflow(String),
+}
+
+impl ByteSize {
+	const BINARY_UNITS: [(&'static str, usize); 4] =
+		[("TiB", TIB), ("GiB", GIB), ("MiB", MIB), ("KiB", KIB)];
+
+	fn unit_factor(unit: &str) -> Option<usize> {
+		let factor = match unit.to_ascii_lowercase().as_str() {
+			"" | "b" => B,
+			"kib" => KIB,
+			"mib" => MIB,
+			"gib" => GIB,
+			"tib" => TIB,
+			"kb" => 1000,
+			"mb" => 1000 * 1000,
+			"gb" => 1000 * 1000 * 1000,
+			"tb" => 1000 * 1000 * 1000 * 1000,
+			_ => return None,
+		};
+		Some(factor)
+	}
+}
+
+impl FromStr for ByteSize {
+	type Err = ParseSizeError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+		let (number, unit) = s.split_at(unit_start);
+		let number: usize = number
+			.parse()
+			.map_err(|_| ParseSizeError::InvalidNumber(s.to_string()))?;
+		let unit = unit.trim_start();
+		let Some(factor) = Self::unit_factor(unit) else {
+			return Err(ParseSizeError::UnknownUnit(unit.to_string()));
+		};
+		number
+			.checked_mul(factor)
+			.map(Self)
+			.ok_or_else(|| ParseSizeError::Overflow(s.to_string()))
+	}
+}
+
+impl fmt::Display for ByteSize {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (unit, factor) in Self::BINARY_UNITS {
+			if self.0 != 0 && self.0.is_multiple_of(factor) {
+				return write!(f, "{}{unit}", self.0 / factor);
+			}
+		}
+		write!(f, "{}B", self.0)
+	}
+}
+
+impl From<usize> for ByteSize {
+	fn from(value: usize) -> Self {
+		Self(value)
+	}
+}
+
+impl From<ByteSize> for usize {
+	fn from(value: ByteSize) -> Self {
+		value.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse_sizes() {
+		// expect
+		assert_eq!("4096".parse(), Ok(ByteSize(4096)));
+		assert_eq!("64MiB".parse(), Ok(ByteSize(64 * MIB)));
+		assert_eq!(" 4 kib ".parse(), Ok(ByteSize(4 * KIB)));
+		assert_eq!("2GB".parse(), Ok(ByteSize(2_000_000_000)));
+		assert_eq!(
+			"12 parsecs".parse::<ByteSize>(),
+			Err(ParseSizeError::UnknownUnit("parsecs".to_string()))
+		);
+		assert_eq!(
+			"MiB".parse::<ByteSize>(),
+			Err(ParseSizeError::InvalidNumber("MiB".to_string()))
+		);
+		assert!(matches!(
+			"100000000000TiB".parse::<ByteSize>(),
+			Err(ParseSizeError::Overflow(..))
+		));
+	}
+
+	#[test]
+	fn format_sizes() {
+		// expect
+		assert_eq!(ByteSize(0).to_string(), "0B");
+		assert_eq!(ByteSize(1000).to_string(), "1000B");
+		assert_eq!(ByteSize(32 * KIB).to_string(), "32KiB");
+		assert_eq!(ByteSize(3 * GIB).to_string(), "3GiB");
+		assert_eq!(ByteSize(MIB + KIB).to_string(), "1025KiB");
+	}
+}
@@ -0,0 +1,180 @@
+use std::{
+	future::Future,
+	panic::{self, AssertUnwindSafe},
+	sync::{mpsc, Arc},
+	thread::{self, JoinHandle},
+};
+
+use futures::{channel::oneshot, FutureExt};
+use static_assertions::assert_impl_all;
+
+use crate::{files::FileError, Database, Error, Transaction};
+
+type Job = Box<dyn FnOnce(&Database) + Send>;
+
+/// Runs the writing transactions that are submitted to it one after the
+/// other on a dedicated thread, so that they never wait for each other's
+/// locks, or conflict with each other.
+///
+/// Each submitted closure runs in a transaction of its own, so closures can
+/// batch any number of writes. The transaction is committed if the closure
+/// succeeds, and aborted otherwise. Transactions can still be started on the
+/// database directly, alongside the ones of the writer, but those may wait
+/// for the locks of the writer's transactions as usual.
+///
+/// Dropping the writer waits for the closures that were already submitted
+/// to finish.
+pub struct SingleWriter {
+	sender: Option<mpsc::Sender<Job>>,
+	thread: Option<JoinHandle<()>>,
+}
+assert_impl_all!(SingleWriter: Send, Sync);
+
+impl SingleWriter {
+	/// Starts the writer thread of `database`.
+	pub fn new(database: Arc<Database>) -> Result<Self, Error> {
+		let (sender, receiver) = mpsc::channel::<Job>();
+		let thread = thread::Builder::new()
+			.name("acorn-writer".to_string())
+			.spawn(move || {
+				for job in receiver {
+					// A panic ends the transaction of the closure, and is
+					// passed on to the caller by dropping its result sender.
+					let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&database)));
+				}
+			})
+			.map_err(FileError::from)?;
+		Ok(Self {
+			sender: Some(sender),
+			thread: Some(thread),
+		})
+	}
+
+	/// Submits a closure that writes to the database in a transaction, and
+	/// returns a future that completes with its result once the transaction
+	/// committed or was aborted. The closure runs even if the future is
+	/// dropped.
+	///
+	/// If the closure panics, awaiting the future panics as well.
+	pub fn submit<T, F>(&self, work: F) -> impl Future<Output = Result<T, Error>>
+	where
+		T: Send + 'static,
+		F: FnOnce(&mut Transaction) -> Result<T, Error> + Send + 'static,
+	{
+		let (result_sender, result_receiver) = oneshot::channel();
+		let job: Job = Box::new(move |database| {
+			let _ = result_sender.send(Self::run(database, work));
+		});
+		self.sender
+			.as_ref()
+			.expect("The writer is only shut down when dropped")
+			.send(job)
+			.expect("The writer thread catches panics, so it outlives its sender");
+		result_receiver.map(|result| result.expect("The closure submitted to the writer panicked"))
+	}
+
+	fn run<T>(
+		database: &Database,
+		work: impl FnOnce(&mut Transaction) -> Result<T, Error>,
+	) -> Result<T, Error> {
+		let mut t = database.begin_transaction()?;
+		match work(&mut t) {
+			Ok(value) => {
+				t.commit()?;
+				Ok(value)
+			}
+			Err(err) => {
+				t.abort()?;
+				Err(err)
+			}
+		}
+	}
+}
+
+impl Drop for SingleWriter {
+	fn drop(&mut self) {
+		// Ends the loop of the writer thread once the submitted closures ran.
+		self.sender.take();
+		if let Some(thread) = self.thread.take() {
+			let _ = thread.join();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::block_on;
+
+	use crate::files::test_helpers::page_id;
+
+	use super::*;
+
+	#[test]
+	fn run_submitted_writes_in_order() {
+		// given
+		let db = Arc::new(Database::open_in_memory().unwrap());
+		let writer = SingleWriter::new(Arc::clone(&db)).unwrap();
+
+		// when
+		let results: Vec<_> = (0..10u8)
+			.map(|i| {
+				writer.submit(move |t| {
+					t.write(page_id!(1, 1), 0, &[i])?;
+					Ok(i)
+				})
+			})
+			.collect();
+		let results: Vec<u8> = results
+			.into_iter()
+			.map(|result| block_on(result).unwrap())
+			.collect();
+
+		// then
+		assert_eq!(results, (0..10).collect::<Vec<_>>());
+		let mut buf = [0; 1];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [9]);
+	}
+
+	#[test]
+	fn abort_failed_writes() {
+		// given
+		let db = Arc::new(Database::open_in_memory().unwrap());
+		let writer = SingleWriter::new(Arc::clone(&db)).unwrap();
+
+		// when
+		let result = block_on(writer.submit(|t| {
+			t.write(page_id!(1, 1), 0, &[1, 2, 3])?;
+			Err::<(), _>(FileError::UnexpectedEof.into())
+		}));
+
+		// then
+		assert!(result.is_err());
+		let mut buf = [0; 3];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0, 0, 0]);
+	}
+
+	#[test]
+	fn keep_running_after_panic() {
+		// given
+		let db = Arc::new(Database::open_in_memory().unwrap());
+		let writer = SingleWriter::new(Arc::clone(&db)).unwrap();
+		let panicked = writer.submit(|t| -> Result<(), Error> {
+			t.write(page_id!(1, 1), 0, &[1])?;
+			panic!("Oh no")
+		});
+
+		// when
+		let written = writer.submit(|t| t.write(page_id!(1, 2), 0, &[2]));
+
+		// then
+		assert!(panic::catch_unwind(AssertUnwindSafe(|| block_on(panicked))).is_err());
+		block_on(written).unwrap();
+		let mut buf = [0; 1];
+		db.read(page_id!(1, 1), 0, &mut buf).unwrap();
+		assert_eq!(buf, [0]);
+		db.read(page_id!(1, 2), 0, &mut buf).unwrap();
+		assert_eq!(buf, [2]);
+	}
+}
@@ -0,0 +1,59 @@
+//! Failpoints are configured for the whole process, so these tests live in
+//! their own test binary, where they can't fail the engine's unit tests.
+
+use std::error::Error;
+
+use acorn::{failpoints, Database, ErrorKind, PageId};
+use fail::FailScenario;
+use tempfile::tempdir;
+
+#[test]
+fn fail_commit_on_wal_fsync_failpoint() {
+	// given
+	let scenario = FailScenario::setup();
+	let tempdir = tempdir().unwrap();
+	let db = Database::open(tempdir.path()).unwrap();
+	fail::cfg(failpoints::WAL_FSYNC, "return(disk full)").unwrap();
+
+	// when
+	let mut t = db.begin_transaction().unwrap();
+	t.write(PageId::new_unwrap(1, 1), 0, &[1, 2, 3]).unwrap();
+	let result = t.commit();
+
+	// then
+	let error = result.unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::Io);
+	assert!(error.source().unwrap().to_string().contains("disk full"));
+	scenario.teardown();
+}
+
+#[test]
+fn fail_checkpoint_on_page_write_failpoint() {
+	// given
+	let scenario = FailScenario::setup();
+	let tempdir = tempdir().unwrap();
+	let db = Database::open(tempdir.path()).unwrap();
+	let mut t = db.begin_transaction().unwrap();
+	t.write(PageId::new_unwrap(1, 1), 0, &[1, 2, 3]).unwrap();
+	t.commit().unwrap();
+	fail::cfg(failpoints::PAGE_WRITE, "return").unwrap();
+
+	// when
+	let failed = db.checkpoint();
+	fail::remove(failpoints::PAGE_WRITE);
+	let retried = db.checkpoint();
+
+	// then
+	let error = failed.unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::Io);
+	assert!(error
+		.source()
+		.unwrap()
+		.to_string()
+		.contains(&format!("Failpoint {} triggered", failpoints::PAGE_WRITE)));
+	retried.unwrap();
+	let mut buf = [0; 3];
+	db.read(PageId::new_unwrap(1, 1), 0, &mut buf).unwrap();
+	assert_eq!(buf, [1, 2, 3]);
+	scenario.teardown();
+}
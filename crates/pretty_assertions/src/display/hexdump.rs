@@ -103,7 +103,7 @@ impl fmt::Display for HexdumpDiff<'_> {
 
 		let bytes_per_line = get_bytes_per_line(line_length);
 		let mut num_lines = diff_len / bytes_per_line;
-		if diff_len % bytes_per_line != 0 {
+		if !diff_len.is_multiple_of(bytes_per_line) {
 			num_lines += 1;
 		}
 